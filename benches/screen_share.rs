@@ -0,0 +1,123 @@
+// Screen share benchmarks
+//
+// Encoding a frame and handing it to every subscribed viewer, for 1, 4 and
+// 8 viewers. Frames go to a transport that drops them, so the numbers are
+// the host's own cost. Drawing the cursor into a frame is measured on its
+// own, at the capture size and scaled down.
+
+use std::sync::Arc;
+use async_trait::async_trait;
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tokio::runtime::Runtime;
+
+use desk_share_net::network::{
+    ControlAction, ControlMessage, Frame, FrameCodec, JoinRequest, JoinResponse, ScreenShare, SessionAnnouncement,
+    SessionEnded, SessionTransport, TokenGrant,
+};
+use desk_share_net::platform::cursor::{self, Cursor, CursorImage};
+use desk_share_net::platform::fallback::FallbackCapture;
+use desk_share_net::platform::{CaptureBackend, CaptureRegion};
+
+const RESOLUTION: (u32, u32) = (1280, 720);
+
+struct DroppingTransport;
+
+#[async_trait]
+impl SessionTransport for DroppingTransport {
+    async fn announce(&self, _announcement: SessionAnnouncement) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    async fn request_join(&self, _host_peer_id: &str, _request: JoinRequest) -> Result<JoinResponse, anyhow::Error> {
+        Ok(JoinResponse::Denied)
+    }
+
+    async fn send_control(&self, _host_peer_id: &str, _message: ControlMessage) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    async fn grant_token(&self, _peer_id: &str, _grant: TokenGrant) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    async fn end_session(&self, _peer_id: &str, _ended: SessionEnded) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    async fn send_frame(&self, _peer_id: &str, _frame: Frame) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+}
+
+/// A paused session, so only the benchmark produces frames, with `viewers` subscribed
+async fn session_with_viewers(viewers: usize) -> (ScreenShare, String) {
+    let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+        .with_transport("host".to_string(), Arc::new(DroppingTransport));
+    let session_id = screen_share.start_sharing("host".to_string(), 1, RESOLUTION, None, None, None).await.unwrap();
+    screen_share.set_paused(&session_id, true).await.unwrap();
+
+    for viewer in 0..viewers {
+        let peer_id = format!("10.0.0.{}", viewer + 10);
+        let request = JoinRequest {
+            request_id: format!("join-{}", viewer),
+            session_id: session_id.clone(),
+            peer_id: peer_id.clone(),
+            password: None,
+            codecs: FrameCodec::decodable(),
+            audio: false,
+            display_name: None,
+        };
+        let JoinResponse::Accepted { token, .. } = screen_share.handle_join_request(request).await else {
+            panic!("open session refused a viewer");
+        };
+        screen_share
+            .handle_control(ControlMessage {
+                session_id: session_id.clone(),
+                peer_id,
+                token,
+                action: ControlAction::Subscribe,
+            })
+            .await
+            .unwrap();
+    }
+    (screen_share, session_id)
+}
+
+fn frame_encode_and_distribute(c: &mut Criterion) {
+    let rt: Runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+    let capture = FallbackCapture;
+
+    let mut group = c.benchmark_group("frame_encode_distribute");
+    for viewers in [1, 4, 8] {
+        let (screen_share, session_id) = rt.block_on(session_with_viewers(viewers));
+        group.bench_with_input(BenchmarkId::from_parameter(viewers), &viewers, |b, _| {
+            b.to_async(&rt).iter(|| async {
+                let frame = capture.capture(None, RESOLUTION, 70).await.unwrap();
+                screen_share.broadcast_to_session(&session_id, Bytes::from(frame)).await.unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn cursor_composite(c: &mut Criterion) {
+    let monitor = FallbackCapture.list_monitors().unwrap().into_iter().find(|m| m.is_primary).unwrap();
+    let cursor = Cursor { x: monitor.width as i32 / 2, y: monitor.height as i32 / 2, image: CursorImage::arrow() };
+
+    let mut group = c.benchmark_group("cursor_composite");
+    for (name, size) in [("native", (monitor.width, monitor.height)), ("scaled", RESOLUTION)] {
+        let mut frame = image::RgbaImage::new(size.0, size.1);
+        group.bench_function(name, |b| {
+            b.iter(|| cursor::composite(&mut frame, &cursor, &monitor, CaptureRegion::whole(&monitor)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = frame_encode_and_distribute, cursor_composite
+}
+criterion_main!(benches);
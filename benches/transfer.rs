@@ -0,0 +1,106 @@
+// File transfer benchmarks
+//
+// Chunking a shared file, serving one chunk through to its wire envelope,
+// and the incremental blake3 hashing both sides run. Everything goes through
+// the in-memory chunk transport, so nothing needs a network.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::sync::Arc;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::runtime::Runtime;
+
+use desk_share_net::network::{FileTransfer, MemoryChunkTransport};
+use desk_share_net::security::{DeviceIdentity, SecureChannel};
+
+const FILE_SIZE: usize = 8 * 1024 * 1024;
+const CHUNK_SIZE: usize = 1024 * 1024;
+const REQUESTER: &str = "10.0.0.3";
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+}
+
+/// An 8 MiB file inside a shareable root
+fn shared_file(name: &str) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("dsn-bench-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("payload.bin");
+    let data: Vec<u8> = (0..FILE_SIZE).map(|i| (i * 31 % 251) as u8).collect();
+    std::fs::write(&path, data).unwrap();
+    (dir, path)
+}
+
+fn share_file_chunking(c: &mut Criterion) {
+    let rt = runtime();
+    let (dir, path) = shared_file("chunking");
+    let file_transfer = rt.block_on(FileTransfer::new());
+    file_transfer.set_shareable_roots(vec![dir.clone()]);
+
+    let mut group = c.benchmark_group("share_file");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.bench_function("8MiB", |b| {
+        b.to_async(&rt).iter(|| file_transfer.share_file(&path, "local".to_string()))
+    });
+    group.finish();
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+fn chunk_serve(c: &mut Criterion) {
+    let rt = runtime();
+    let (dir, path) = shared_file("serve");
+    let (transport, sent) = MemoryChunkTransport::new();
+    let sent = RefCell::new(sent);
+    let file_transfer = rt.block_on(FileTransfer::new())
+        .with_identity(Arc::new(DeviceIdentity::generate()))
+        .with_chunk_transport(Arc::new(transport));
+    file_transfer.set_shareable_roots(vec![dir.clone()]);
+
+    let chunk_hash = rt.block_on(async {
+        let file_hash = file_transfer.share_file(&path, "local".to_string()).await.unwrap();
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        let (ours, theirs) = (DeviceIdentity::generate(), DeviceIdentity::generate());
+        let (channel, _) = tokio::join!(SecureChannel::connect(client_io, &ours), SecureChannel::accept(server_io, &theirs));
+        file_transfer.set_transfer_key(&file_hash, REQUESTER, &channel.unwrap());
+        file_transfer.signed_announcement(&file_hash).unwrap().file.chunks[1].clone()
+    });
+
+    let mut group = c.benchmark_group("chunk_serve");
+    group.throughput(Throughput::Bytes(CHUNK_SIZE as u64));
+    group.bench_function("request_to_envelope", |b| {
+        b.to_async(&rt).iter(|| async {
+            file_transfer.handle_chunk_request(&chunk_hash, REQUESTER.to_string()).await.unwrap();
+            // The in-memory transport queues the envelope before the request returns
+            let (_, envelope) = sent.borrow_mut().try_recv().unwrap();
+            envelope.write_to(&mut tokio::io::sink()).await.unwrap();
+        })
+    });
+    group.finish();
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+fn blake3_incremental(c: &mut Criterion) {
+    let data: Vec<u8> = (0..FILE_SIZE).map(|i| i as u8).collect();
+
+    let mut group = c.benchmark_group("blake3_incremental");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    for update_size in [64 * 1024, CHUNK_SIZE] {
+        group.bench_with_input(BenchmarkId::from_parameter(update_size), &update_size, |b, &update_size| {
+            b.iter(|| {
+                let mut hasher = blake3::Hasher::new();
+                for chunk in data.chunks(update_size) {
+                    hasher.update(chunk);
+                }
+                hasher.finalize()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(20);
+    targets = share_file_chunking, chunk_serve, blake3_incremental
+}
+criterion_main!(benches);
@@ -1,5 +1,6 @@
 // Tauri API imports
 const { invoke } = window.__TAURI__.tauri;
+const { listen } = window.__TAURI__.event;
 
 // Application state
 let currentUser = '';
@@ -19,8 +20,8 @@ document.addEventListener('DOMContentLoaded', async () => {
     // Load initial data
     await refreshDevices();
 
-    // Start periodic refresh
-    setInterval(refreshDevices, 5000);
+    // Keep the device list current from discovery events
+    await listenForDeviceEvents();
 });
 
 // ============================================================================
@@ -46,6 +47,11 @@ function setupEventListeners() {
         fileTransferBtn.addEventListener('click', startFileTransfer);
     }
 
+    const browseFilesBtn = document.getElementById('browse-files-btn');
+    if (browseFilesBtn) {
+        browseFilesBtn.addEventListener('click', browseFilesToSend);
+    }
+
     // Screen share
     const screenShareBtn = document.getElementById('start-screen-share-btn');
     if (screenShareBtn) {
@@ -59,6 +65,24 @@ function setupEventListeners() {
     }
 }
 
+async function listenForDeviceEvents() {
+    const upsertDevice = (event) => {
+        devices = devices.filter(d => d.ip !== event.payload.ip).concat(event.payload);
+        updateDeviceList(devices);
+    };
+
+    await listen('device-added', upsertDevice);
+    await listen('device-updated', upsertDevice);
+    await listen('device-removed', (event) => {
+        devices = devices.filter(d => d.ip !== event.payload.ip);
+        updateDeviceList(devices);
+    });
+    await listen('devices-snapshot', (event) => {
+        devices = event.payload;
+        updateDeviceList(devices);
+    });
+}
+
 // ============================================================================
 // Tauri Command Handlers
 // ============================================================================
@@ -109,6 +133,21 @@ async function startFileTransfer() {
     }
 }
 
+async function browseFilesToSend() {
+    const filePathInput = document.getElementById('file-path-input');
+    if (!filePathInput) return;
+
+    try {
+        const paths = await invoke('pick_files_to_send');
+        if (paths.length > 0) {
+            filePathInput.value = paths[0];
+        }
+    } catch (error) {
+        console.error('Cannot send the picked file:', error);
+        showNotification('Error', error.message || 'Cannot send that file');
+    }
+}
+
 async function startScreenShare() {
     const frameRateInput = document.getElementById('frame-rate-input');
     const frameRate = frameRateInput ? parseInt(frameRateInput.value) : 30;
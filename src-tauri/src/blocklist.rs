@@ -0,0 +1,154 @@
+// Blocking devices
+//
+// A block is a trust store record, so discovery, chat, transfer offers and
+// screen share joins all refuse the device through the same check and lifting
+// the block takes effect everywhere without a restart. Attempts that were
+// turned away show up in `get_connection_info` as `blocked_peer_activity`.
+
+use desk_share_net::security::TrustStore;
+use desk_share_net::{DeskShareError, Device};
+
+use crate::chat::resolve_peer;
+use crate::error::UiError;
+
+/// Names resolve to the device's address; anything else is taken as an
+/// address or device id as given
+fn block_target(devices: &[Device], peer_id: &str) -> String {
+    resolve_peer(devices, peer_id).unwrap_or_else(|_| peer_id.to_string())
+}
+
+pub fn block_peer(trust_store: &TrustStore, devices: &[Device], peer_id: &str) {
+    trust_store.block(&block_target(devices, peer_id));
+}
+
+pub fn unblock_peer(trust_store: &TrustStore, devices: &[Device], peer_id: &str) -> Result<(), UiError> {
+    if trust_store.unblock(&block_target(devices, peer_id)) {
+        Ok(())
+    } else {
+        Err(DeskShareError::PeerNotFound(peer_id.to_string()).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use desk_share_net::network::{JoinRequest, JoinResponse, ChunkCodec, HashAlgorithm, ShareKind, SharedFile, MANIFEST_VERSION_FLAT};
+    use desk_share_net::p2p::DeviceInfo;
+    use desk_share_net::platform::fallback::FallbackCapture;
+    use desk_share_net::services::{ChatMessage, ChatPacket, DeliveryState};
+    use desk_share_net::{ChatService, FileTransfer, NetworkDiscovery, ScreenShare};
+
+    const MALLORY: &str = "10.0.0.66";
+
+    struct Subsystems {
+        discovery: NetworkDiscovery,
+        chat: ChatService,
+        file_transfer: FileTransfer,
+        screen_share: ScreenShare,
+        session_id: String,
+    }
+
+    impl Subsystems {
+        async fn new(trust_store: &Arc<TrustStore>) -> Self {
+            let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+                .with_trust_store(trust_store.clone());
+            let session_id = screen_share.start_sharing(5, (64, 48), None, None, None).await.unwrap();
+            Self {
+                discovery: NetworkDiscovery::new().await.with_trust_store(trust_store.clone()),
+                chat: ChatService::new().await.with_trust_store(trust_store.clone()),
+                file_transfer: FileTransfer::new().await.with_trust_store(trust_store.clone()),
+                screen_share,
+                session_id,
+            }
+        }
+
+        /// What each subsystem lets through from Mallory: (discovered, chat, offer, join)
+        async fn contact(&mut self, attempt: u64) -> (bool, bool, bool, bool) {
+            self.discovery.record_device(DeviceInfo {
+                name: "Mallory".to_string(),
+                ip: MALLORY.to_string(),
+                port: 8080,
+                services: Vec::new(),
+                last_seen: 0,
+                fingerprint: None,
+            });
+            let discovered = self.discovery.get_devices().iter().any(|device| device.ip == MALLORY);
+
+            let message = ChatMessage {
+                id: format!("msg-{}", attempt),
+                from: MALLORY.to_string(),
+                to: None,
+                content: "hello".to_string(),
+                timestamp: 0,
+                attachment: None,
+                state: DeliveryState::Sent,
+            };
+            self.chat.receive_packet(ChatPacket::Message(message)).await.unwrap();
+            let chatted = self.chat.get_messages().await.iter().any(|m| m.id == format!("msg-{}", attempt));
+
+            let file = SharedFile {
+                hash: format!("hash-{}", attempt),
+                name: "payload.bin".to_string(),
+                size: 16,
+                chunks: vec!["chunk".to_string()],
+                chunk_size: 16,
+                total_chunks: 1,
+                peer_id: MALLORY.to_string(),
+                timestamp: 0,
+                kind: ShareKind::File,
+                codec: ChunkCodec::Raw,
+                encrypted: true,
+                manifest_version: MANIFEST_VERSION_FLAT,
+                merkle_root: None,
+                hash_algorithm: HashAlgorithm::Blake3,
+            };
+            let offer_id = self
+                .file_transfer
+                .receive_offer(MALLORY.to_string(), "Mallory".to_string(), file)
+                .await;
+            let offered = self.file_transfer.get_pending_offers().iter().any(|offer| offer.offer_id == offer_id);
+
+            let response = self
+                .screen_share
+                .handle_join_request(JoinRequest {
+                    request_id: format!("join-{}", attempt),
+                    session_id: self.session_id.clone(),
+                    peer_id: MALLORY.to_string(),
+                    password: None,
+                    codecs: Vec::new(),
+                    audio: false,
+                    display_name: None,
+                })
+                .await;
+            let joined = matches!(response, JoinResponse::Accepted { .. });
+
+            (discovered, chatted, offered, joined)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blocked_peer_is_refused_everywhere() {
+        let trust_store = Arc::new(TrustStore::new());
+        let devices = vec![Device::new("Mallory".to_string(), MALLORY.to_string(), 8080)];
+
+        let mut blocked = Subsystems::new(&trust_store).await;
+        block_peer(&trust_store, &devices, "Mallory");
+        assert!(trust_store.is_blocked(MALLORY));
+        assert_eq!(blocked.contact(1).await, (false, false, false, false));
+        assert!(blocked.screen_share.get_session(&blocked.session_id).await.unwrap().participants.is_empty());
+
+        let activity = trust_store.blocked_activity();
+        for subsystem in ["discovery", "chat", "transfer", "screen_share"] {
+            assert_eq!(activity.get(subsystem), Some(&1), "{}", subsystem);
+        }
+
+        // Lifting the block applies to the running services straight away
+        unblock_peer(&trust_store, &devices, MALLORY).unwrap();
+        assert_eq!(blocked.contact(2).await, (true, true, true, true));
+        assert_eq!(trust_store.blocked_activity(), activity);
+
+        let err = unblock_peer(&trust_store, &devices, MALLORY).unwrap_err();
+        assert_eq!(err.code, "peer_not_found");
+    }
+}
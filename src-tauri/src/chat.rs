@@ -0,0 +1,260 @@
+// Chat commands and events
+//
+// Commands address peers the way the UI knows them (device name or IP) and
+// are resolved against the discovered device list before reaching the
+// ChatService. Everything the service reports is relayed as `chat-event`.
+// History can be encrypted at rest; encrypting reports its progress as
+// `chat-history-encryption-progress`, and a passphrase-locked history stays
+// closed until it is unlocked or reset.
+
+use std::path::Path;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use desk_share_net::security::{TrustLevel, TrustStore};
+use desk_share_net::services::{ChatAttachment, ChatEvent, ChatMessage, ChatStore, KeySource};
+use desk_share_net::{ChatService, DeskShareError, Device};
+
+use crate::error::UiError;
+use crate::events::EventSink;
+
+pub const CHAT_EVENT: &str = "chat-event";
+pub const CHAT_HISTORY_PROGRESS_EVENT: &str = "chat-history-encryption-progress";
+
+/// Who a conversation is with, for the header above it
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationInfo {
+    pub peer_id: String,
+    pub name: String,
+    pub fingerprint: Option<String>,
+    pub trust_level: TrustLevel,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EncryptionProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+/// Relay chat activity until the sending side is dropped
+pub async fn forward_chat_events<E: EventSink>(mut rx: broadcast::Receiver<ChatEvent>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(event) => sink.emit_event(CHAT_EVENT, event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Chat forwarder lagged, skipped {} events", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Map a device name or IP from the UI to the peer id chat uses
+pub fn resolve_peer(devices: &[Device], to: &str) -> Result<String, UiError> {
+    devices
+        .iter()
+        .find(|device| device.ip == to || device.name == to)
+        .map(|device| device.ip.clone())
+        .ok_or_else(|| DeskShareError::PeerNotFound(to.to_string()).into())
+}
+
+pub fn conversation_info(trust_store: &TrustStore, devices: &[Device], peer: &str) -> Result<ConversationInfo, UiError> {
+    let peer_id = resolve_peer(devices, peer)?;
+    let name = devices
+        .iter()
+        .find(|device| device.ip == peer_id)
+        .map(|device| device.name.clone())
+        .unwrap_or_else(|| peer_id.clone());
+    Ok(ConversationInfo {
+        fingerprint: crate::pairing::peer_fingerprint(trust_store, devices, &peer_id).ok(),
+        trust_level: trust_store.level_for_peer(&peer_id),
+        peer_id,
+        name,
+    })
+}
+
+pub async fn send_message(
+    chat: &ChatService,
+    devices: &[Device],
+    content: String,
+    to: Option<String>,
+) -> Result<ChatMessage, UiError> {
+    let to = to.map(|to| resolve_peer(devices, &to)).transpose()?;
+    Ok(chat.send_message(content, to).await?)
+}
+
+pub async fn send_attachment(
+    chat: &ChatService,
+    devices: &[Device],
+    attachment: ChatAttachment,
+    to: Option<String>,
+) -> Result<ChatMessage, UiError> {
+    let to = to.map(|to| resolve_peer(devices, &to)).transpose()?;
+    Ok(chat.send_attachment(attachment, to).await?)
+}
+
+/// Open a passphrase-locked history and bring its messages back
+pub fn unlock_history(chat: &ChatService, path: &Path, passphrase: String) -> Result<(), UiError> {
+    let store = ChatStore::open(path, Some(&KeySource::Passphrase(passphrase)))?;
+    Ok(chat.attach_store(store)?)
+}
+
+/// Encrypt history under `passphrase`, or a keychain secret when there is none
+pub fn encrypt_history<E: EventSink>(chat: &ChatService, passphrase: Option<String>, sink: &E) -> Result<(), UiError> {
+    let key = passphrase.map(KeySource::Passphrase).unwrap_or(KeySource::Keychain);
+    Ok(chat.encrypt_history(&key, |done, total| {
+        sink.emit_event(CHAT_HISTORY_PROGRESS_EVENT, EncryptionProgress { done, total })
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use async_trait::async_trait;
+    use desk_share_net::services::{ChatPacket, ChatTransport, DeliveryState, MessageFilter};
+    use crate::events::tests::RecordingSink;
+
+    /// Delivers packets straight into other services in the same process
+    #[derive(Default)]
+    struct InProcessHub {
+        peers: Mutex<HashMap<String, Arc<ChatService>>>,
+    }
+
+    struct HubTransport(Arc<InProcessHub>);
+
+    #[async_trait]
+    impl ChatTransport for HubTransport {
+        async fn send(&self, from: &str, to: Option<&str>, packet: ChatPacket) -> Result<(), anyhow::Error> {
+            let targets: Vec<Arc<ChatService>> = {
+                let peers = self.0.peers.lock().unwrap();
+                peers
+                    .iter()
+                    .filter(|(id, _)| id.as_str() != from && (to.is_none() || to == Some(id.as_str())))
+                    .map(|(_, service)| service.clone())
+                    .collect()
+            };
+            if targets.is_empty() {
+                anyhow::bail!("no route to {:?}", to);
+            }
+            for target in targets {
+                target.receive_packet(packet.clone()).await?;
+            }
+            Ok(())
+        }
+    }
+
+    fn join(hub: &Arc<InProcessHub>, id: &str) -> Arc<ChatService> {
+        let service = Arc::new(ChatService::with_transport(
+            id.to_string(),
+            Arc::new(HubTransport(hub.clone())),
+        ));
+        hub.peers.lock().unwrap().insert(id.to_string(), service.clone());
+        service
+    }
+
+    #[tokio::test]
+    async fn test_message_reaches_peer_history() {
+        let hub = Arc::new(InProcessHub::default());
+        let alice = join(&hub, "10.0.0.2");
+        let bob = join(&hub, "10.0.0.3");
+        let devices = vec![Device::new("Bob's PC".to_string(), "10.0.0.3".to_string(), 8080)];
+
+        let sink = RecordingSink::default();
+        let forwarder = tokio::spawn(forward_chat_events(alice.subscribe(), sink.clone()));
+
+        let sent = send_message(&alice, &devices, "hello bob".to_string(), Some("Bob's PC".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(sent.to.as_deref(), Some("10.0.0.3"));
+        assert_eq!(sent.state, DeliveryState::Delivered);
+
+        let history = bob.get_history(&MessageFilter {
+            peer_id: Some("10.0.0.2".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].id, sent.id);
+        assert_eq!(history[0].content, "hello bob");
+
+        // Reading on Bob's side flows back to Alice as a state change
+        bob.mark_read(&sent.id).await.unwrap();
+        assert_eq!(alice.get_history(&MessageFilter::default())[0].state, DeliveryState::Read);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let events = sink.named(CHAT_EVENT);
+        assert_eq!(events[0]["type"], "message_sent");
+        assert_eq!(events.last().unwrap()["type"], "state_changed");
+        assert_eq!(events.last().unwrap()["state"], "Read");
+
+        forwarder.abort();
+    }
+
+    #[tokio::test]
+    async fn test_history_paging_and_unknown_peer() {
+        let hub = Arc::new(InProcessHub::default());
+        let alice = join(&hub, "10.0.0.2");
+        let _bob = join(&hub, "10.0.0.3");
+        let devices = vec![Device::new("Bob's PC".to_string(), "10.0.0.3".to_string(), 8080)];
+
+        for i in 0..5 {
+            send_message(&alice, &devices, format!("message {}", i), Some("10.0.0.3".to_string()))
+                .await
+                .unwrap();
+        }
+
+        let latest = alice.get_history(&MessageFilter { limit: Some(2), ..Default::default() });
+        assert_eq!(latest.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), ["message 3", "message 4"]);
+
+        let older = alice.get_history(&MessageFilter {
+            before: Some(latest[0].id.clone()),
+            limit: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(older.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), ["message 1", "message 2"]);
+
+        let err = send_message(&alice, &devices, "hi".to_string(), Some("Carol".to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "peer_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_history_encryption_unlock_and_reset() {
+        let path = std::env::temp_dir().join(format!("desk-share-chat-ui-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let hub = Arc::new(InProcessHub::default());
+        let alice = join(&hub, "10.0.0.2");
+        let _bob = join(&hub, "10.0.0.3");
+        let devices = vec![Device::new("Bob's PC".to_string(), "10.0.0.3".to_string(), 8080)];
+        alice.attach_store(ChatStore::open(&path, None).unwrap()).unwrap();
+        for i in 0..3 {
+            send_message(&alice, &devices, format!("secret {}", i), None).await.unwrap();
+        }
+
+        let sink = RecordingSink::default();
+        encrypt_history(&alice, Some("hunter2".to_string()), &sink).unwrap();
+        assert_eq!(alice.history_encrypted(), Some(true));
+        let progress = sink.named(CHAT_HISTORY_PROGRESS_EVENT);
+        assert_eq!(progress.last().unwrap(), &serde_json::json!({ "done": 3, "total": 3 }));
+
+        // After a restart the history stays closed until unlocked
+        let restarted = ChatService::new().await;
+        let err = UiError::from(ChatStore::open(&path, None).err().unwrap());
+        assert_eq!(err.code, "chat_history_locked");
+        let err = unlock_history(&restarted, &path, "wrong".to_string()).unwrap_err();
+        assert_eq!(err.code, "chat_history_locked");
+        assert!(restarted.get_messages().await.is_empty());
+        unlock_history(&restarted, &path, "hunter2".to_string()).unwrap();
+        assert_eq!(restarted.get_messages().await.len(), 3);
+
+        // Reset starts over without the passphrase
+        let forgetful = ChatService::new().await;
+        forgetful.reset_history(&path).unwrap();
+        assert_eq!(forgetful.history_encrypted(), Some(false));
+        assert!(ChatStore::open(&path, None).unwrap().load().unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,223 @@
+// Device list events
+//
+// Discovery changes are pushed to the webview as `device-added`,
+// `device-updated` and `device-removed` events. Updates are coalesced per
+// device so a chatty network can't flood the webview; the latest state is
+// always delivered once the window closes.
+
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use desk_share_net::{DeviceEvent, Device};
+
+use crate::events::EventSink;
+
+pub const DEVICE_ADDED_EVENT: &str = "device-added";
+pub const DEVICE_UPDATED_EVENT: &str = "device-updated";
+pub const DEVICE_REMOVED_EVENT: &str = "device-removed";
+pub const DEVICES_SNAPSHOT_EVENT: &str = "devices-snapshot";
+
+/// Minimum spacing between two update events for the same device
+pub const DEVICE_UPDATE_WINDOW: Duration = Duration::from_millis(500);
+
+#[derive(Default)]
+struct UpdateWindow {
+    last_emitted: Option<Instant>,
+    pending: Option<Device>,
+}
+
+/// Relay discovery changes until the sending side is dropped
+pub async fn forward_device_events<E: EventSink>(
+    mut rx: broadcast::Receiver<DeviceEvent>,
+    sink: E,
+    window: Duration,
+) {
+    let mut windows: HashMap<String, UpdateWindow> = HashMap::new();
+
+    loop {
+        let next_flush = windows
+            .values()
+            .filter(|w| w.pending.is_some())
+            .filter_map(|w| w.last_emitted)
+            .min()
+            .map(|last| last + window);
+
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(event) => handle_event(event, &sink, &mut windows, window),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Device forwarder lagged, skipped {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = sleep_until(next_flush) => flush_due(&sink, &mut windows, window),
+        }
+    }
+
+    // Deliver whatever was still held back so the last state isn't lost
+    for state in windows.values_mut() {
+        if let Some(device) = state.pending.take() {
+            sink.emit_event(DEVICE_UPDATED_EVENT, device);
+        }
+    }
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+fn handle_event<E: EventSink>(
+    event: DeviceEvent,
+    sink: &E,
+    windows: &mut HashMap<String, UpdateWindow>,
+    window: Duration,
+) {
+    match event {
+        DeviceEvent::Added(device) => {
+            windows.insert(device.ip.clone(), UpdateWindow {
+                last_emitted: Some(Instant::now()),
+                pending: None,
+            });
+            sink.emit_event(DEVICE_ADDED_EVENT, device);
+        }
+        DeviceEvent::Updated(device) => {
+            let state = windows.entry(device.ip.clone()).or_default();
+            let now = Instant::now();
+            match state.last_emitted {
+                Some(last) if now.duration_since(last) < window => {
+                    state.pending = Some(device);
+                }
+                _ => {
+                    state.last_emitted = Some(now);
+                    state.pending = None;
+                    sink.emit_event(DEVICE_UPDATED_EVENT, device);
+                }
+            }
+        }
+        DeviceEvent::Removed(device) => {
+            windows.remove(&device.ip);
+            sink.emit_event(DEVICE_REMOVED_EVENT, device);
+        }
+    }
+}
+
+fn flush_due<E: EventSink>(sink: &E, windows: &mut HashMap<String, UpdateWindow>, window: Duration) {
+    let now = Instant::now();
+    for state in windows.values_mut() {
+        let due = matches!(state.last_emitted, Some(last) if now.duration_since(last) >= window);
+        if due {
+            if let Some(device) = state.pending.take() {
+                state.last_emitted = Some(now);
+                sink.emit_event(DEVICE_UPDATED_EVENT, device);
+            }
+        }
+    }
+}
+
+/// Send the full device list to every window, e.g. for windows opened late
+pub fn emit_snapshot<E: EventSink>(sink: &E, devices: Vec<Device>) -> Vec<Device> {
+    sink.emit_event(DEVICES_SNAPSHOT_EVENT, devices.clone());
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use desk_share_net::NetworkDiscovery;
+    use desk_share_net::p2p::DeviceInfo;
+    use crate::events::tests::RecordingSink;
+
+    fn info(ip: &str, name: &str) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_string(),
+            ip: ip.to_string(),
+            port: 8080,
+            services: vec!["file-transfer".to_string()],
+            last_seen: chrono::Utc::now().timestamp() as u64,
+            fingerprint: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_updates_are_coalesced_per_device() {
+        let mut discovery = NetworkDiscovery::new().await;
+        let sink = RecordingSink::default();
+        let window = Duration::from_millis(100);
+        let forwarder = tokio::spawn(forward_device_events(
+            discovery.subscribe_device_events(),
+            sink.clone(),
+            window,
+        ));
+
+        discovery.record_device(info("10.0.0.2", "laptop"));
+        for i in 0..10 {
+            discovery.record_device(info("10.0.0.2", &format!("laptop-{}", i)));
+        }
+        discovery.record_device(info("10.0.0.3", "desktop"));
+        discovery.record_device(info("10.0.0.3", "desktop-renamed"));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(sink.named(DEVICE_ADDED_EVENT).len(), 2);
+        assert!(sink.named(DEVICE_UPDATED_EVENT).is_empty());
+
+        // After the window closes each device gets exactly one update carrying its latest state
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let updates = sink.named(DEVICE_UPDATED_EVENT);
+        assert_eq!(updates.len(), 2);
+        assert!(updates.iter().any(|d| d["name"] == "laptop-9"));
+        assert!(updates.iter().any(|d| d["name"] == "desktop-renamed"));
+
+        forwarder.abort();
+    }
+
+    #[tokio::test]
+    async fn test_stream_replay_matches_snapshot() {
+        let mut discovery = NetworkDiscovery::new().await;
+        let sink = RecordingSink::default();
+        let forwarder = tokio::spawn(forward_device_events(
+            discovery.subscribe_device_events(),
+            sink.clone(),
+            Duration::from_millis(50),
+        ));
+
+        discovery.record_device(info("10.0.0.2", "laptop"));
+        discovery.record_device(info("10.0.0.3", "desktop"));
+        discovery.record_device(info("10.0.0.2", "laptop-renamed"));
+        let mut stale = info("10.0.0.4", "old-phone");
+        stale.last_seen = 0;
+        discovery.record_device(stale);
+        discovery.cleanup_old_devices(300);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        // Rebuild the list the way the frontend does from the event stream
+        let mut replayed: HashMap<String, String> = HashMap::new();
+        for (name, payload) in sink.events.lock().unwrap().iter() {
+            let ip = payload["ip"].as_str().unwrap().to_string();
+            match name.as_str() {
+                DEVICE_ADDED_EVENT | DEVICE_UPDATED_EVENT => {
+                    replayed.insert(ip, payload["name"].as_str().unwrap().to_string());
+                }
+                DEVICE_REMOVED_EVENT => {
+                    replayed.remove(&ip);
+                }
+                _ => {}
+            }
+        }
+
+        let snapshot = emit_snapshot(&sink, discovery.get_devices());
+        let expected: HashMap<String, String> = snapshot
+            .into_iter()
+            .map(|device| (device.ip, device.name))
+            .collect();
+        assert_eq!(replayed, expected);
+        assert_eq!(sink.named(DEVICES_SNAPSHOT_EVENT).len(), 1);
+
+        forwarder.abort();
+    }
+}
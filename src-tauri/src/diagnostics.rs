@@ -0,0 +1,319 @@
+// Network diagnostics for the settings panel
+//
+// `get_connection_info` gathers what the app knows about its own
+// reachability in one payload; `run_connectivity_test` actively probes it,
+// stage by stage, so the panel can show which step failed and how long each
+// one took.
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+use desk_share_net::network::{ExternalAddress, NatTraversal, NatType, StunHealth};
+use desk_share_net::security::{RateLimitEvent, RateLimiter, RateLimiterState, TrustStore};
+use desk_share_net::Device;
+
+use crate::events::EventSink;
+
+/// Port peers connect to for transfers and screen shares
+pub const DEFAULT_LISTEN_PORT: u16 = 8080;
+
+/// Raised once each time a device is muted for flooding us
+pub const PEER_RATE_LIMITED_EVENT: &str = "peer-rate-limited";
+
+/// Upper bound for each connectivity test stage
+pub const STAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub listen_addresses: Vec<String>,
+    pub listen_port: u16,
+    pub external_addresses: Vec<ExternalAddress>,
+    pub nat_type: NatType,
+    pub stun_servers: Vec<StunHealth>,
+    pub turn_servers: Vec<RelayServer>,
+    pub relay: RelayStatus,
+    pub peers: Vec<PeerConnection>,
+    pub discovery: DiscoveryStats,
+    /// Attempts from blocked devices each subsystem turned away
+    pub blocked_peer_activity: BTreeMap<String, u64>,
+    /// Limits, drops and muted devices
+    pub rate_limiter: RateLimiterState,
+}
+
+/// A configured TURN server; credentials stay out of the payload
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayServer {
+    pub address: String,
+    pub port: u16,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayStatus {
+    pub available: bool,
+    /// Direct connections are unlikely to work, so peers will need the relay
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PeerConnection {
+    pub peer_id: String,
+    pub name: String,
+    pub transport: String,
+    pub latency_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscoveryStats {
+    pub devices_known: usize,
+    pub devices_online: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageResult {
+    pub stage: String,
+    pub passed: bool,
+    pub duration_ms: u64,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectivityReport {
+    pub passed: bool,
+    pub stages: Vec<StageResult>,
+}
+
+/// Active checks run by the connectivity test; mocked in tests
+#[async_trait]
+pub trait ConnectivityProbe: Send + Sync {
+    async fn stun_query(&self) -> Result<ExternalAddress, anyhow::Error>;
+    async fn loopback_echo(&self) -> Result<(), anyhow::Error>;
+}
+
+/// Probes the real network
+pub struct NativeProbe<'a> {
+    pub nat: &'a NatTraversal,
+}
+
+#[async_trait]
+impl ConnectivityProbe for NativeProbe<'_> {
+    async fn stun_query(&self) -> Result<ExternalAddress, anyhow::Error> {
+        self.nat.query_stun().await
+    }
+
+    async fn loopback_echo(&self) -> Result<(), anyhow::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let echo = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut buf = [0u8; 32];
+            let len = stream.read(&mut buf).await?;
+            stream.write_all(&buf[..len]).await?;
+            Ok::<_, std::io::Error>(())
+        });
+
+        let payload = b"desk-share-net echo";
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(payload).await?;
+        let mut reply = [0u8; 19];
+        stream.read_exact(&mut reply).await?;
+        echo.await??;
+
+        if &reply != payload {
+            anyhow::bail!("echo returned different bytes");
+        }
+        Ok(())
+    }
+}
+
+pub fn connection_info(
+    nat: &NatTraversal,
+    devices: &[Device],
+    trust_store: &TrustStore,
+    rate_limiter: &RateLimiter,
+    listen_port: u16,
+) -> ConnectionInfo {
+    let nat_type = nat.nat_type();
+    let turn_servers: Vec<RelayServer> = nat
+        .turn_servers()
+        .iter()
+        .map(|server| RelayServer {
+            address: server.address.clone(),
+            port: server.port,
+            username: server.username.clone(),
+        })
+        .collect();
+
+    ConnectionInfo {
+        listen_addresses: vec![format!("{}:{}", nat.local_ip(), listen_port)],
+        listen_port,
+        external_addresses: nat.external_addresses(),
+        nat_type,
+        stun_servers: nat.stun_health(),
+        relay: RelayStatus {
+            available: !turn_servers.is_empty(),
+            required: nat_type == NatType::Symmetric,
+        },
+        turn_servers,
+        peers: devices
+            .iter()
+            .filter(|device| device.is_online)
+            .map(|device| PeerConnection {
+                peer_id: device.ip.clone(),
+                name: device.name.clone(),
+                transport: "lan".to_string(),
+                latency_ms: None,
+            })
+            .collect(),
+        discovery: DiscoveryStats {
+            devices_known: devices.len(),
+            devices_online: devices.iter().filter(|device| device.is_online).count(),
+        },
+        blocked_peer_activity: trust_store.blocked_activity(),
+        rate_limiter: rate_limiter.state(),
+    }
+}
+
+/// Relay rate limit warnings until the limiter is dropped
+pub async fn forward_rate_limit_events<E: EventSink>(mut rx: broadcast::Receiver<RateLimitEvent>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(event) => sink.emit_event(PEER_RATE_LIMITED_EVENT, event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Rate limit forwarder lagged, skipped {} warnings", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Run every stage even when an earlier one fails
+pub async fn run_connectivity_test<P: ConnectivityProbe + ?Sized>(probe: &P) -> ConnectivityReport {
+    let stages = vec![
+        run_stage("stun", async {
+            probe
+                .stun_query()
+                .await
+                .map(|addr| Some(format!("{}:{}", addr.address, addr.port)))
+        })
+        .await,
+        run_stage("loopback", async { probe.loopback_echo().await.map(|_| None) }).await,
+    ];
+
+    ConnectivityReport {
+        passed: stages.iter().all(|stage| stage.passed),
+        stages,
+    }
+}
+
+async fn run_stage<F>(stage: &str, check: F) -> StageResult
+where
+    F: Future<Output = Result<Option<String>, anyhow::Error>>,
+{
+    let started = Instant::now();
+    let (passed, detail) = match tokio::time::timeout(STAGE_TIMEOUT, check).await {
+        Ok(Ok(detail)) => (true, detail),
+        Ok(Err(e)) => (false, Some(e.to_string())),
+        Err(_) => (false, Some(format!("timed out after {}s", STAGE_TIMEOUT.as_secs()))),
+    };
+
+    StageResult {
+        stage: stage.to_string(),
+        passed,
+        duration_ms: started.elapsed().as_millis() as u64,
+        detail,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use desk_share_net::security::{ProtocolClass, RateLimit, RateLimitConfig};
+
+    struct MockProbe {
+        stun: Option<ExternalAddress>,
+    }
+
+    #[async_trait]
+    impl ConnectivityProbe for MockProbe {
+        async fn stun_query(&self) -> Result<ExternalAddress, anyhow::Error> {
+            self.stun.clone().ok_or_else(|| anyhow::anyhow!("no STUN server answered"))
+        }
+
+        async fn loopback_echo(&self) -> Result<(), anyhow::Error> {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_info_shape() {
+        let mut nat = NatTraversal::new().await.unwrap();
+        nat.add_turn_server("turn.example.com".to_string(), 3478, "desk".to_string(), "secret".to_string());
+        let mut offline = Device::new("Old Laptop".to_string(), "10.0.0.9".to_string(), 8080);
+        offline.mark_offline();
+        let devices = vec![Device::new("Bob's PC".to_string(), "10.0.0.3".to_string(), 8080), offline];
+
+        let trust_store = Arc::new(TrustStore::new());
+        trust_store.block("10.0.0.66");
+        assert!(trust_store.refuses("chat", "10.0.0.66"));
+        let config = RateLimitConfig { chat: RateLimit { per_second: 1, burst: 2 }, ..RateLimitConfig::default() };
+        let rate_limiter = RateLimiter::new(config, trust_store.clone());
+        let admitted = (0..4).filter(|_| rate_limiter.admit("10.0.0.7", ProtocolClass::Chat)).count();
+        assert_eq!(admitted, 2);
+
+        let info = connection_info(&nat, &devices, &trust_store, &rate_limiter, DEFAULT_LISTEN_PORT);
+        let info = serde_json::to_value(info).unwrap();
+
+        assert_eq!(info["listen_port"], 8080);
+        assert!(info["listen_addresses"][0].as_str().unwrap().ends_with(":8080"));
+        assert_eq!(info["nat_type"], "Unknown");
+        assert_eq!(info["external_addresses"].as_array().unwrap().len(), 0);
+        assert_eq!(info["stun_servers"].as_array().unwrap().len(), 3);
+        assert_eq!(info["stun_servers"][0]["successes"], 0);
+        assert_eq!(info["turn_servers"][0]["address"], "turn.example.com");
+        assert!(info["turn_servers"][0].get("password").is_none());
+        assert_eq!(info["relay"]["available"], true);
+        assert_eq!(info["peers"].as_array().unwrap().len(), 1);
+        assert_eq!(info["peers"][0]["name"], "Bob's PC");
+        assert_eq!(info["discovery"]["devices_known"], 2);
+        assert_eq!(info["discovery"]["devices_online"], 1);
+        assert_eq!(info["blocked_peer_activity"]["chat"], 1);
+        assert_eq!(info["rate_limiter"]["config"]["chat"]["burst"], 2);
+        assert_eq!(info["rate_limiter"]["dropped"]["chat"], 2);
+        assert_eq!(info["rate_limiter"]["muted"][0]["peer_id"], "10.0.0.7");
+        assert_eq!(info["rate_limiter"]["muted"][0]["strikes"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_connectivity_test_reports_each_stage() {
+        let probe = MockProbe {
+            stun: Some(ExternalAddress { address: "203.0.113.7".to_string(), port: 40000, confidence: 1.0 }),
+        };
+        let report = run_connectivity_test(&probe).await;
+        assert!(report.passed);
+        assert_eq!(report.stages.iter().map(|s| s.stage.as_str()).collect::<Vec<_>>(), ["stun", "loopback"]);
+        assert_eq!(report.stages[0].detail.as_deref(), Some("203.0.113.7:40000"));
+        assert!(report.stages[1].duration_ms >= 10);
+
+        // A failed STUN stage doesn't stop the loopback check
+        let report = run_connectivity_test(&MockProbe { stun: None }).await;
+        assert!(!report.passed);
+        assert!(!report.stages[0].passed);
+        assert_eq!(report.stages[0].detail.as_deref(), Some("no STUN server answered"));
+        assert!(report.stages[1].passed);
+    }
+
+    #[tokio::test]
+    async fn test_native_loopback_echo() {
+        let nat = NatTraversal::new().await.unwrap();
+        NativeProbe { nat: &nat }.loopback_echo().await.unwrap();
+    }
+}
@@ -0,0 +1,38 @@
+// Errors returned to the frontend from Tauri commands
+//
+// Commands return a `UiError` so the webview gets a stable `code` to switch on
+// alongside a message that is safe to show the user.
+
+use serde::Serialize;
+
+use desk_share_net::DeskShareError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UiError {
+    pub code: String,
+    pub message: String,
+}
+
+impl UiError {
+    pub fn new(code: &str, message: impl Into<String>) -> Self {
+        Self {
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl From<DeskShareError> for UiError {
+    fn from(error: DeskShareError) -> Self {
+        Self::new(error.code(), error.user_message())
+    }
+}
+
+impl From<anyhow::Error> for UiError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast_ref::<DeskShareError>() {
+            Some(error) => Self::new(error.code(), error.user_message()),
+            None => Self::new("internal", error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+// Event forwarding from the library's broadcast channels to the webview
+//
+// Each forwarder owns a receiver and relays every item as a window event so
+// the frontend can listen instead of polling commands on a timer.
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Runtime};
+use tokio::sync::broadcast;
+
+use desk_share_net::TransferProgress;
+
+pub const TRANSFER_PROGRESS_EVENT: &str = "transfer-progress";
+
+/// Destination for forwarded events; implemented by the Tauri app handle
+/// and by recording sinks in tests
+pub trait EventSink: Send + Sync + 'static {
+    fn emit_event<S: Serialize + Clone>(&self, event: &str, payload: S);
+}
+
+impl<R: Runtime> EventSink for AppHandle<R> {
+    fn emit_event<S: Serialize + Clone>(&self, event: &str, payload: S) {
+        if let Err(e) = self.emit(event, payload) {
+            tracing::warn!("Failed to emit {} event: {}", event, e);
+        }
+    }
+}
+
+/// Relay transfer progress updates as `transfer-progress` events until the
+/// sending side is dropped
+pub async fn forward_transfer_progress<E: EventSink>(
+    mut rx: broadcast::Receiver<TransferProgress>,
+    sink: E,
+) {
+    loop {
+        match rx.recv().await {
+            Ok(progress) => sink.emit_event(TRANSFER_PROGRESS_EVENT, progress),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::debug!("Transfer progress forwarder lagged, skipped {} updates", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use desk_share_net::{AppState, TransferStatus};
+
+    /// Sink that keeps every emitted event as JSON for later assertions
+    #[derive(Clone, Default)]
+    pub(crate) struct RecordingSink {
+        pub events: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    }
+
+    impl RecordingSink {
+        pub fn named(&self, event: &str) -> Vec<serde_json::Value> {
+            self.events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(name, _)| name == event)
+                .map(|(_, payload)| payload.clone())
+                .collect()
+        }
+    }
+
+    impl EventSink for RecordingSink {
+        fn emit_event<S: Serialize + Clone>(&self, event: &str, payload: S) {
+            let value = serde_json::to_value(payload).expect("event payload serializes");
+            self.events.lock().unwrap().push((event.to_string(), value));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_events_match_command_result() {
+        let app_state = AppState::new().await;
+        let sink = RecordingSink::default();
+        let rx = app_state.file_transfer.lock().await.subscribe_progress();
+        let forwarder = tokio::spawn(forward_transfer_progress(rx, sink.clone()));
+
+        let dir = std::env::temp_dir().join(format!("dsn-progress-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let source = dir.join("report.bin");
+        tokio::fs::write(&source, vec![7u8; 3 * 1024 * 1024 + 17]).await.unwrap();
+
+        let file_hash = {
+            let file_transfer = app_state.file_transfer.lock().await;
+            let hash = file_transfer.share_file(&source).await.unwrap();
+            file_transfer.download_file(&hash, &dir.join("report-copy.bin")).await.unwrap();
+            hash
+        };
+
+        // Same call the get_transfer_progress command makes
+        let snapshot = app_state.file_transfer.lock().await.get_transfer_progress().await;
+        let current = snapshot
+            .iter()
+            .find(|p| p.file_hash == file_hash)
+            .expect("transfer is tracked");
+        assert!(matches!(current.status, TransferStatus::InProgress));
+
+        let mut last_event = None;
+        for _ in 0..50 {
+            last_event = sink
+                .named(TRANSFER_PROGRESS_EVENT)
+                .into_iter()
+                .rev()
+                .find(|p| p["file_hash"] == file_hash.as_str());
+            if matches!(&last_event, Some(p) if p["status"] == "in_progress") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let event = last_event.expect("at least one transfer-progress event");
+        assert_eq!(event["total_bytes"], current.total_bytes);
+        assert_eq!(event["bytes_transferred"], current.bytes_transferred);
+        assert_eq!(event["status"], "in_progress");
+        assert!(event.get("bytes_per_second").is_some());
+        assert!(event.get("output_path").is_some());
+        assert_eq!(event["error_message"], serde_json::Value::Null);
+        assert_eq!(event["error_kind"], serde_json::Value::Null);
+
+        forwarder.abort();
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
@@ -0,0 +1,308 @@
+// Native file pickers and file manager integration
+//
+// Paths picked for sending are checked before they reach the frontend: each
+// must be a readable file inside the shareable directories, so a picked
+// symlink can't smuggle something else onto the network. New shareable
+// directories only come from the native folder dialog, never from a path the
+// webview supplies.
+
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use tauri::{AppHandle, Runtime};
+use tauri_plugin_dialog::DialogExt;
+use tauri_plugin_opener::OpenerExt;
+use tokio::sync::oneshot;
+
+use desk_share_net::{DeskShareError, FileTransfer};
+
+use crate::error::UiError;
+
+/// Native dialogs and the OS file manager; mocked in tests
+#[async_trait]
+pub trait DesktopShell: Send + Sync {
+    /// None when the user cancels
+    async fn pick_files(&self) -> Option<Vec<PathBuf>>;
+    async fn pick_directory(&self, title: &str, start: &Path) -> Option<PathBuf>;
+    fn reveal(&self, path: &Path) -> Result<(), UiError>;
+}
+
+#[async_trait]
+impl<R: Runtime> DesktopShell for AppHandle<R> {
+    async fn pick_files(&self) -> Option<Vec<PathBuf>> {
+        let (tx, rx) = oneshot::channel();
+        self.dialog()
+            .file()
+            .set_title("Choose files to send")
+            .pick_files(move |picked| {
+                let paths = picked.map(|files| {
+                    files.into_iter().filter_map(|file| file.into_path().ok()).collect()
+                });
+                let _ = tx.send(paths);
+            });
+        rx.await.ok().flatten()
+    }
+
+    async fn pick_directory(&self, title: &str, start: &Path) -> Option<PathBuf> {
+        let (tx, rx) = oneshot::channel();
+        self.dialog()
+            .file()
+            .set_title(title)
+            .set_directory(start)
+            .pick_folder(move |picked| {
+                let _ = tx.send(picked.and_then(|folder| folder.into_path().ok()));
+            });
+        rx.await.ok().flatten()
+    }
+
+    fn reveal(&self, path: &Path) -> Result<(), UiError> {
+        self.opener()
+            .reveal_item_in_dir(path)
+            .map_err(|e| UiError::new("internal", e.to_string()))
+    }
+}
+
+/// Ask for files to send; an empty list means the dialog was cancelled
+pub async fn pick_files_to_send<S: DesktopShell + ?Sized>(
+    shell: &S,
+    file_transfer: &FileTransfer,
+) -> Result<Vec<String>, UiError> {
+    let picked = shell.pick_files().await.unwrap_or_default();
+    validate_send_paths(file_transfer, &picked)
+}
+
+/// Reject the whole pick if any path can't be sent
+pub fn validate_send_paths(file_transfer: &FileTransfer, paths: &[PathBuf]) -> Result<Vec<String>, UiError> {
+    paths
+        .iter()
+        .map(|path| {
+            let resolved = file_transfer.check_shareable(path)?;
+            if !resolved.is_file() {
+                return Err(DeskShareError::FileNotFound(path.display().to_string()).into());
+            }
+            std::fs::File::open(&resolved).map_err(DeskShareError::from)?;
+            Ok(resolved.to_string_lossy().to_string())
+        })
+        .collect()
+}
+
+/// Ask where to save an incoming transfer, starting in the downloads directory
+pub async fn pick_save_directory<S: DesktopShell + ?Sized>(
+    shell: &S,
+    file_transfer: &FileTransfer,
+) -> Option<String> {
+    shell
+        .pick_directory("Save incoming files to", &file_transfer.downloads_dir())
+        .await
+        .map(|dir| dir.to_string_lossy().to_string())
+}
+
+pub fn get_downloads_dir(file_transfer: &FileTransfer) -> String {
+    file_transfer.downloads_dir().to_string_lossy().to_string()
+}
+
+/// Save incoming files to `path` from now on; it's created if missing and
+/// refused if it can't be written to
+pub fn set_downloads_dir(file_transfer: &FileTransfer, path: &str) -> Result<String, UiError> {
+    file_transfer.set_downloads_dir(PathBuf::from(path))?;
+    Ok(get_downloads_dir(file_transfer))
+}
+
+pub fn get_shareable_roots(file_transfer: &FileTransfer) -> Vec<String> {
+    file_transfer
+        .shareable_roots()
+        .iter()
+        .map(|root| root.to_string_lossy().to_string())
+        .collect()
+}
+
+/// Let the user choose another directory to share from; returns the roots
+/// afterwards, unchanged if the dialog was cancelled
+pub async fn add_shareable_root<S: DesktopShell + ?Sized>(
+    shell: &S,
+    file_transfer: &FileTransfer,
+) -> Result<Vec<String>, UiError> {
+    if let Some(root) = shell.pick_directory("Share files from", &file_transfer.downloads_dir()).await {
+        file_transfer.add_shareable_root(&root)?;
+    }
+    Ok(get_shareable_roots(file_transfer))
+}
+
+pub fn reveal_in_file_manager<S: DesktopShell + ?Sized>(shell: &S, path: &str) -> Result<(), UiError> {
+    let path = Path::new(path);
+    if !path.exists() {
+        return Err(DeskShareError::FileNotFound(path.display().to_string()).into());
+    }
+    shell.reveal(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MockShell {
+        files: Option<Vec<PathBuf>>,
+        directory: Option<PathBuf>,
+        revealed: Mutex<Vec<PathBuf>>,
+    }
+
+    #[async_trait]
+    impl DesktopShell for MockShell {
+        async fn pick_files(&self) -> Option<Vec<PathBuf>> {
+            self.files.clone()
+        }
+
+        async fn pick_directory(&self, _title: &str, _start: &Path) -> Option<PathBuf> {
+            self.directory.clone()
+        }
+
+        fn reveal(&self, path: &Path) -> Result<(), UiError> {
+            self.revealed.lock().unwrap().push(path.to_path_buf());
+            Ok(())
+        }
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dsn-files-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::create_dir_all(dir.join("private")).unwrap();
+        std::fs::write(dir.join("shared/report.pdf"), b"report").unwrap();
+        std::fs::write(dir.join("private/keys.txt"), b"secret").unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_picked_paths_are_validated() {
+        let dir = scratch_dir("validate");
+        let file_transfer = FileTransfer::new().await;
+
+        let shell = MockShell {
+            files: Some(vec![dir.join("shared/report.pdf")]),
+            ..Default::default()
+        };
+        let picked = pick_files_to_send(&shell, &file_transfer).await.unwrap();
+        assert_eq!(picked.len(), 1);
+        assert!(picked[0].ends_with("report.pdf"));
+
+        // Cancelling the dialog is not an error
+        let cancelled = pick_files_to_send(&MockShell::default(), &file_transfer).await.unwrap();
+        assert!(cancelled.is_empty());
+
+        let shell = MockShell {
+            files: Some(vec![dir.join("shared/missing.pdf")]),
+            ..Default::default()
+        };
+        let err = pick_files_to_send(&shell, &file_transfer).await.unwrap_err();
+        assert_eq!(err.code, "file_not_found");
+
+        let shell = MockShell {
+            files: Some(vec![dir.join("shared")]),
+            ..Default::default()
+        };
+        let err = pick_files_to_send(&shell, &file_transfer).await.unwrap_err();
+        assert_eq!(err.code, "file_not_found");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_whitelist_is_enforced() {
+        let dir = scratch_dir("whitelist");
+        let file_transfer = FileTransfer::new().await;
+        file_transfer.set_shareable_roots(vec![dir.join("shared")]);
+
+        let shell = MockShell {
+            files: Some(vec![dir.join("shared/report.pdf"), dir.join("private/keys.txt")]),
+            ..Default::default()
+        };
+        let err = pick_files_to_send(&shell, &file_transfer).await.unwrap_err();
+        assert_eq!(err.code, "path_not_allowed");
+
+        // Walking out of the root with `..` is caught after resolving
+        let shell = MockShell {
+            files: Some(vec![dir.join("shared/../private/keys.txt")]),
+            ..Default::default()
+        };
+        let err = pick_files_to_send(&shell, &file_transfer).await.unwrap_err();
+        assert_eq!(err.code, "path_not_allowed");
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("private/keys.txt"), dir.join("shared/link.txt")).unwrap();
+            let shell = MockShell {
+                files: Some(vec![dir.join("shared/link.txt")]),
+                ..Default::default()
+            };
+            let err = pick_files_to_send(&shell, &file_transfer).await.unwrap_err();
+            assert_eq!(err.code, "path_not_allowed");
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_save_directory_and_reveal() {
+        let dir = scratch_dir("reveal");
+        let file_transfer = FileTransfer::new().await;
+
+        let shell = MockShell {
+            directory: Some(dir.join("shared")),
+            ..Default::default()
+        };
+        let picked = pick_save_directory(&shell, &file_transfer).await;
+        assert_eq!(picked, Some(dir.join("shared").to_string_lossy().to_string()));
+        assert_eq!(pick_save_directory(&MockShell::default(), &file_transfer).await, None);
+
+        let report = dir.join("shared/report.pdf");
+        reveal_in_file_manager(&shell, &report.to_string_lossy()).unwrap();
+        assert_eq!(*shell.revealed.lock().unwrap(), vec![report]);
+
+        let err = reveal_in_file_manager(&shell, &dir.join("gone.bin").to_string_lossy()).unwrap_err();
+        assert_eq!(err.code, "file_not_found");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_downloads_dir_is_checked() {
+        let dir = scratch_dir("downloads");
+        let file_transfer = FileTransfer::new().await;
+
+        let incoming = dir.join("incoming").to_string_lossy().to_string();
+        assert_eq!(set_downloads_dir(&file_transfer, &incoming).unwrap(), incoming);
+        assert_eq!(get_downloads_dir(&file_transfer), incoming);
+        assert!(dir.join("incoming").is_dir());
+
+        let err = set_downloads_dir(&file_transfer, &dir.join("shared/report.pdf").to_string_lossy()).unwrap_err();
+        assert_eq!(err.code, "invalid_config");
+        assert_eq!(get_downloads_dir(&file_transfer), incoming);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_shareable_root_added_from_dialog() {
+        let dir = scratch_dir("roots");
+        let file_transfer = FileTransfer::new().await;
+        file_transfer.set_shareable_roots(vec![dir.join("shared")]);
+        assert_eq!(get_shareable_roots(&file_transfer), [dir.join("shared").to_string_lossy()]);
+
+        let shell = MockShell {
+            files: Some(vec![dir.join("private/keys.txt")]),
+            directory: Some(dir.join("private")),
+            ..Default::default()
+        };
+        let err = pick_files_to_send(&shell, &file_transfer).await.unwrap_err();
+        assert_eq!(err.code, "path_not_allowed");
+
+        // Cancelling leaves the roots alone
+        assert_eq!(add_shareable_root(&MockShell::default(), &file_transfer).await.unwrap().len(), 1);
+        let roots = add_shareable_root(&shell, &file_transfer).await.unwrap();
+        assert_eq!(roots.len(), 2);
+        assert!(roots[1].ends_with("private"));
+        assert_eq!(pick_files_to_send(&shell, &file_transfer).await.unwrap().len(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -3,22 +3,54 @@
     windows_subsystem = "windows"
 )]
 
+mod blocklist;
+mod chat;
+mod devices;
+mod diagnostics;
+mod error;
+mod events;
+mod files;
+mod offers;
+mod pairing;
+mod remote;
+mod screen;
+mod sends;
+mod shares;
+mod texts;
+mod transfers;
+
 use std::sync::Arc;
-use tauri::{Manager, State};
+use tauri::{AppHandle, Manager, State};
 use tokio::sync::Mutex;
-use serde::{Serialize, Deserialize};
 
 // Import from the main application
 use desk_share_net::{
-    network::{NetworkDiscovery, FileTransfer, ScreenShare},
-    AppState, Device,
+    network::{AccessMode, BufferUsage, IgnoreRules, InputEvent, MultiSendProgress, NatTraversal, Participant, PeerTransferStats, PeerWindowState, QualityPreset, ReceivedText, RemoteFile, SessionStats, SharedDirectory, SharedFileSummary, StreamOverrides, SymlinkPolicy, TransferRecord, UploadProgress},
+    platform::{CaptureRegion, MonitorInfo},
+    security::PairingHandle,
+    services::{ChatAttachment, ChatMessage, MessageFilter},
+    AppState, Device, FileTransfer, TransferProgress, TransferStatus,
 };
 
+use crate::error::UiError;
+use crate::screen::{FrameForwarders, ScreenFrame};
+use crate::transfers::TransferAction;
+
 // Tauri-specific state wrapper
 struct TauriAppState {
     app_state: Arc<Mutex<AppState>>,
 }
 
+impl TauriAppState {
+    /// A handle onto the file transfers, cloned out so no lock is held while
+    /// a command waits on the user or the network
+    async fn file_transfer(&self) -> FileTransfer {
+        let file_transfer = self.app_state.lock().await.file_transfer.clone();
+        let guard = file_transfer.lock().await;
+        guard.clone()
+    }
+}
+
 // ============================================================================
 // Command Handlers
 // ============================================================================
@@ -49,6 +81,17 @@ async fn get_devices(
     Ok(devices)
 }
 
+#[tauri::command]
+async fn resync_devices(
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<Device>, String> {
+    let app_state = state.app_state.lock().await;
+    let discovery = app_state.network_discovery.lock().await;
+    
+    Ok(devices::emit_snapshot(&app, discovery.get_devices()))
+}
+
 #[tauri::command]
 async fn refresh_devices(
     state: State<'_, TauriAppState>,
@@ -62,91 +105,810 @@ async fn refresh_devices(
     Ok("Devices refreshed".to_string())
 }
 
-#[derive(Serialize, Deserialize)]
-struct FileTransferRequest {
+#[tauri::command]
+async fn start_file_transfer(
     device_ip: String,
     file_path: String,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<String, UiError> {
+    let devices = state.app_state.lock().await.network_discovery.lock().await.get_devices();
+    // Connecting can take a while, so hold no locks
+    let file_transfer = state.file_transfer().await;
+    
+    tracing::info!("Starting file transfer to {} for file: {}", device_ip, file_path);
+    
+    sends::send_file_to_device(&file_transfer, &app, &devices, &device_ip, &file_path).await
 }
 
 #[tauri::command]
-async fn start_file_transfer(
-    device_ip: String,
+async fn list_shared_files(
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<SharedFileSummary>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(shares::list_shared_files(&file_transfer))
+}
+
+/// Files a device has announced, optionally only those whose names match `query`
+#[tauri::command]
+async fn list_remote_files(
+    peer_id: String,
+    query: Option<String>,
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<RemoteFile>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(match query {
+        Some(query) => file_transfer
+            .search_remote_files(&query)
+            .into_iter()
+            .filter(|file| file.peer_id == peer_id)
+            .collect(),
+        None => file_transfer.get_remote_files(&peer_id),
+    })
+}
+
+#[tauri::command]
+async fn share_file(
+    path: String,
+    ttl_seconds: Option<u64>,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<String, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    shares::share_file(&file_transfer, &app, &path, ttl_seconds).await
+}
+
+#[tauri::command]
+async fn share_directory(
+    path: String,
+    ignore_patterns: Option<Vec<String>>,
+    use_ignore_files: Option<bool>,
+    symlinks: Option<SymlinkPolicy>,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<SharedDirectory, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    let rules = IgnoreRules {
+        patterns: ignore_patterns.unwrap_or_default(),
+        use_ignore_files: use_ignore_files.unwrap_or(false),
+        symlinks: symlinks.unwrap_or_default(),
+    };
+    shares::share_directory(&file_transfer, &app, &path, rules).await
+}
+
+#[tauri::command]
+async fn send_text(
+    content: String,
+    title: Option<String>,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<String, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    texts::send_text(&file_transfer, &app, content, title).await
+}
+
+#[tauri::command]
+async fn send_file_to_devices(
+    device_ips: Vec<String>,
     file_path: String,
+    app: AppHandle,
     state: State<'_, TauriAppState>,
-) -> Result<String, String> {
+) -> Result<MultiSendProgress, UiError> {
     let app_state = state.app_state.lock().await;
     let file_transfer = app_state.file_transfer.lock().await;
     
-    tracing::info!("Starting file transfer to {} for file: {}", device_ip, file_path);
+    sends::send_file_to_devices(&file_transfer, &app, device_ips, &file_path).await
+}
+
+#[tauri::command]
+async fn get_received_texts(state: State<'_, TauriAppState>) -> Result<Vec<ReceivedText>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
     
-    // In a real implementation, this would initiate the transfer
-    // For now, we'll return a success message
-    Ok(format!("File transfer started to {}", device_ip))
+    Ok(texts::list_received_texts(&file_transfer))
 }
 
-#[derive(Serialize, Deserialize)]
-struct TransferProgress {
-    file_name: String,
-    percentage: f64,
-    bytes_transferred: u64,
-    total_bytes: u64,
+#[tauri::command]
+async fn dismiss_received_text(file_hash: String, state: State<'_, TauriAppState>) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    texts::dismiss_received_text(&file_transfer, &file_hash)
+}
+
+#[tauri::command]
+async fn unshare_file(
+    file_hash: String,
+    force: Option<bool>,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    shares::unshare_file(&file_transfer, &app, &file_hash, force.unwrap_or(false)).await
 }
 
 #[tauri::command]
 async fn get_transfer_progress(
     state: State<'_, TauriAppState>,
 ) -> Result<Vec<TransferProgress>, String> {
-    // In a real implementation, this would track actual progress
-    // For now, return empty array
-    Ok(vec![])
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(file_transfer.get_transfer_progress().await)
 }
 
-#[derive(Serialize, Deserialize)]
-struct ScreenShareRequest {
-    frame_rate: u32,
+/// Files being sent, per receiving device
+#[tauri::command]
+async fn get_upload_progress(
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<UploadProgress>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(file_transfer.get_upload_progress())
+}
+
+/// Per-peer request windows used by downloads
+#[tauri::command]
+async fn get_transfer_diagnostics(
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<PeerWindowState>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(file_transfer.chunk_windows())
+}
+
+/// Bytes, round trips and failures per device, across transfers
+#[tauri::command]
+async fn get_peer_stats(state: State<'_, TauriAppState>) -> Result<Vec<PeerTransferStats>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(file_transfer.get_peer_stats())
+}
+
+#[tauri::command]
+async fn reset_peer_stats(state: State<'_, TauriAppState>) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    file_transfer.reset_peer_stats();
+    Ok(())
+}
+
+/// Finished transfers, newest first, a page at a time
+#[tauri::command]
+async fn get_transfer_history(
+    limit: Option<usize>,
+    offset: Option<usize>,
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<TransferRecord>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(file_transfer.get_transfer_history(limit.unwrap_or(transfers::TRANSFER_HISTORY_PAGE), offset.unwrap_or(0))?)
+}
+
+#[tauri::command]
+async fn clear_transfer_history(state: State<'_, TauriAppState>) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(file_transfer.clear_history()?)
+}
+
+/// Move a queued download to `position` in line, 0 being next
+#[tauri::command]
+async fn reorder_transfer_queue(
+    transfer_id: String,
+    position: usize,
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<String>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    file_transfer.reorder_queue(&transfer_id, position)?;
+    Ok(file_transfer.queued_transfers())
+}
+
+async fn control_transfer(
+    transfer_id: String,
+    action: TransferAction,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<TransferStatus, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    transfers::apply_transfer_action(&*file_transfer, &app, &transfer_id, action).await
+}
+
+#[tauri::command]
+async fn pause_transfer(
+    transfer_id: String,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<TransferStatus, UiError> {
+    control_transfer(transfer_id, TransferAction::Pause, app, state).await
+}
+
+#[tauri::command]
+async fn resume_transfer(
+    transfer_id: String,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<TransferStatus, UiError> {
+    control_transfer(transfer_id, TransferAction::Resume, app, state).await
+}
+
+#[tauri::command]
+async fn cancel_transfer(
+    transfer_id: String,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<TransferStatus, UiError> {
+    control_transfer(transfer_id, TransferAction::Cancel, app, state).await
+}
+
+#[tauri::command]
+async fn dismiss_transfer(transfer_id: String, state: State<'_, TauriAppState>) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(file_transfer.dismiss_transfer(&transfer_id).await?)
+}
+
+#[tauri::command]
+async fn pick_files_to_send(
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<String>, UiError> {
+    // The dialog stays open until the user answers, so hold no locks
+    let file_transfer = state.file_transfer().await;
+    
+    files::pick_files_to_send(&app, &file_transfer).await
+}
+
+#[tauri::command]
+async fn pick_save_directory(
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<Option<String>, UiError> {
+    // The dialog stays open until the user answers, so hold no locks
+    let file_transfer = state.file_transfer().await;
+    
+    Ok(files::pick_save_directory(&app, &file_transfer).await)
+}
+
+#[tauri::command]
+async fn get_downloads_dir(state: State<'_, TauriAppState>) -> Result<String, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(files::get_downloads_dir(&file_transfer))
+}
+
+#[tauri::command]
+async fn set_downloads_dir(path: String, state: State<'_, TauriAppState>) -> Result<String, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    files::set_downloads_dir(&file_transfer, &path)
+}
+
+#[tauri::command]
+async fn get_shareable_roots(state: State<'_, TauriAppState>) -> Result<Vec<String>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let file_transfer = app_state.file_transfer.lock().await;
+    
+    Ok(files::get_shareable_roots(&file_transfer))
+}
+
+#[tauri::command]
+async fn add_shareable_root(
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<String>, UiError> {
+    // The dialog stays open until the user answers, so hold no locks
+    let file_transfer = state.file_transfer().await;
+    
+    files::add_shareable_root(&app, &file_transfer).await
+}
+
+#[tauri::command]
+async fn reveal_in_file_manager(path: String, app: AppHandle) -> Result<(), UiError> {
+    files::reveal_in_file_manager(&app, &path)
+}
+
+#[tauri::command]
+async fn respond_to_transfer(
+    offer_id: String,
+    accept: bool,
+    save_path: Option<String>,
+    reason: Option<String>,
+    state: State<'_, TauriAppState>,
+) -> Result<Option<String>, UiError> {
+    // Cloned out so nothing stays locked while the offer is answered
+    let file_transfer = state.file_transfer().await;
+    
+    offers::respond_to_offer(&file_transfer, &offer_id, accept, save_path, reason).await
+}
+
+#[tauri::command]
+async fn list_monitors(
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<MonitorInfo>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    screen::list_monitors(&screen_share)
 }
 
 #[tauri::command]
 async fn start_screen_share(
     frame_rate: u32,
+    monitor_id: Option<u32>,
+    quality: Option<u8>,
+    max_participants: Option<usize>,
     state: State<'_, TauriAppState>,
-) -> Result<String, String> {
+) -> Result<String, UiError> {
     let app_state = state.app_state.lock().await;
     let screen_share = app_state.screen_share.lock().await;
     
     tracing::info!("Starting screen share with frame rate: {}", frame_rate);
     
-    // Generate a session ID
-    let session_id = format!("session_{}", chrono::Utc::now().timestamp());
+    screen::start_share(&screen_share, frame_rate, monitor_id, quality, max_participants).await
+}
+
+#[tauri::command]
+async fn start_screen_share_preset(
+    preset: QualityPreset,
+    overrides: Option<StreamOverrides>,
+    monitor_id: Option<u32>,
+    max_participants: Option<usize>,
+    state: State<'_, TauriAppState>,
+) -> Result<String, UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    tracing::info!("Starting screen share at preset {:?}", preset);
+    
+    screen::start_preset_share(&screen_share, preset, overrides.unwrap_or_default(), monitor_id, max_participants).await
+}
+
+#[tauri::command]
+async fn switch_monitor(
+    session_id: String,
+    monitor_id: u32,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    screen::switch_monitor(&screen_share, &session_id, monitor_id).await
+}
+
+#[tauri::command]
+async fn set_capture_region(
+    session_id: String,
+    region: Option<CaptureRegion>,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    screen::set_capture_region(&screen_share, &session_id, region).await
+}
+
+#[tauri::command]
+async fn update_screen_share_settings(
+    session_id: String,
+    frame_rate: u32,
+    resolution: (u32, u32),
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    screen::update_settings(&screen_share, &session_id, frame_rate, resolution).await
+}
+
+#[tauri::command]
+async fn set_screen_quality_preset(
+    session_id: String,
+    preset: QualityPreset,
+    overrides: Option<StreamOverrides>,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
     
-    Ok(session_id)
+    screen::set_quality_preset(&screen_share, &session_id, preset, overrides.unwrap_or_default()).await
+}
+
+#[tauri::command]
+async fn set_show_cursor(
+    session_id: String,
+    show: bool,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    screen::set_show_cursor(&screen_share, &session_id, show).await
+}
+
+#[tauri::command]
+async fn set_screen_audio(
+    session_id: String,
+    enabled: bool,
+    state: State<'_, TauriAppState>,
+) -> Result<bool, UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    screen::set_audio(&screen_share, &session_id, enabled).await
+}
+
+#[tauri::command]
+async fn subscribe_screen_frames(
+    session_id: String,
+    max_fps: Option<u32>,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+    forwarders: State<'_, FrameForwarders>,
+) -> Result<(), UiError> {
+    let rx = {
+        let app_state = state.app_state.lock().await;
+        let screen_share = app_state.screen_share.lock().await;
+        screen_share.subscribe_frames(&session_id).await?
+    };
+    
+    forwarders.start(&session_id, rx, app, max_fps.unwrap_or(screen::DEFAULT_VIEWER_FPS));
+    Ok(())
+}
+
+#[tauri::command]
+async fn unsubscribe_screen_frames(
+    session_id: String,
+    forwarders: State<'_, FrameForwarders>,
+) -> Result<bool, UiError> {
+    Ok(forwarders.stop(&session_id))
+}
+
+#[tauri::command]
+async fn get_screen_frame(
+    session_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<Option<ScreenFrame>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    Ok(screen::latest_frame(&screen_share, &session_id).await)
+}
+
+#[tauri::command]
+async fn get_screen_share_stats(
+    session_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<SessionStats, UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    screen::session_stats(&screen_share, &session_id).await
+}
+
+#[tauri::command]
+async fn get_frame_buffer_usage(
+    state: State<'_, TauriAppState>,
+) -> Result<BufferUsage, UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    Ok(screen_share.buffer_usage().await)
+}
+
+#[tauri::command]
+async fn list_remote_sessions(
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<remote::RemoteSessionInfo>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    let screen_share = app_state.screen_share.lock().await;
+    
+    Ok(remote::list_remote_sessions(&screen_share, &devices).await)
+}
+
+#[tauri::command]
+async fn join_remote_session(
+    session_id: String,
+    password: Option<String>,
+    max_fps: Option<u32>,
+    app: AppHandle,
+    state: State<'_, TauriAppState>,
+    forwarders: State<'_, FrameForwarders>,
+) -> Result<remote::ViewerHandle, UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::join_remote_session(
+        &screen_share,
+        &forwarders,
+        app,
+        &session_id,
+        password,
+        max_fps.unwrap_or(screen::DEFAULT_VIEWER_FPS),
+    )
+    .await
+}
+
+#[tauri::command]
+async fn leave_remote_session(
+    session_id: String,
+    state: State<'_, TauriAppState>,
+    forwarders: State<'_, FrameForwarders>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::leave_remote_session(&screen_share, &forwarders, &session_id).await
+}
+
+#[tauri::command]
+async fn set_screen_share_access(
+    session_id: String,
+    access_mode: AccessMode,
+    password: Option<String>,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::set_session_access(&screen_share, &session_id, access_mode, password).await
+}
+
+#[tauri::command]
+async fn list_screen_share_participants(
+    session_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<Participant>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::list_participants(&screen_share, &devices, &session_id).await
+}
+
+#[tauri::command]
+async fn kick_screen_share_participant(
+    session_id: String,
+    peer_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::kick_participant(&screen_share, &session_id, &peer_id).await
+}
+
+#[tauri::command]
+async fn set_screen_share_paused(
+    session_id: String,
+    paused: bool,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::set_session_paused(&screen_share, &session_id, paused).await
+}
+
+#[tauri::command]
+async fn set_screen_remote_control(
+    session_id: String,
+    allowed: bool,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::set_remote_control(&screen_share, &session_id, allowed).await
+}
+
+#[tauri::command]
+async fn allow_screen_control(
+    session_id: String,
+    peer_id: String,
+    allowed: bool,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::allow_control(&screen_share, &session_id, &peer_id, allowed).await
+}
+
+#[tauri::command]
+async fn revoke_screen_control(state: State<'_, TauriAppState>) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::revoke_control(&screen_share).await;
+    Ok(())
+}
+
+#[tauri::command]
+async fn send_screen_input(
+    session_id: String,
+    event: InputEvent,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::send_input(&screen_share, &session_id, event).await
+}
+
+#[tauri::command]
+async fn respond_to_join_request(
+    request_id: String,
+    approve: bool,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    Ok(screen_share.respond_to_join(&request_id, approve)?)
 }
 
 #[tauri::command]
 async fn stop_screen_share(
     session_id: String,
     state: State<'_, TauriAppState>,
-) -> Result<String, String> {
-    tracing::info!("Stopping screen share session: {}", session_id);
-    Ok("Screen share stopped".to_string())
+    forwarders: State<'_, FrameForwarders>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    forwarders.stop(&session_id);
+    Ok(screen_share.stop_sharing(&session_id).await?)
 }
 
+/// Join whichever session the device at `host_ip` is announcing
 #[tauri::command]
 async fn join_screen_share(
     host_ip: String,
-    host_port: u16,
+    password: Option<String>,
+    app: AppHandle,
     state: State<'_, TauriAppState>,
-) -> Result<String, String> {
-    tracing::info!("Joining screen share at {}:{}", host_ip, host_port);
-    Ok(format!("Joined screen share at {}:{}", host_ip, host_port))
+    forwarders: State<'_, FrameForwarders>,
+) -> Result<remote::ViewerHandle, UiError> {
+    let app_state = state.app_state.lock().await;
+    let screen_share = app_state.screen_share.lock().await;
+    
+    remote::join_host_session(
+        &screen_share,
+        &forwarders,
+        app,
+        &host_ip,
+        password,
+        screen::DEFAULT_VIEWER_FPS,
+    )
+    .await
 }
 
-#[derive(Serialize, Deserialize)]
-struct ChatMessage {
-    from: String,
-    to: Option<String>,
-    message: String,
-    timestamp: i64,
+#[tauri::command]
+async fn get_connection_info(
+    state: State<'_, TauriAppState>,
+    nat: State<'_, NatTraversal>,
+) -> Result<diagnostics::ConnectionInfo, UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    
+    Ok(diagnostics::connection_info(
+        &nat,
+        &devices,
+        &app_state.trust_store,
+        &app_state.rate_limiter,
+        diagnostics::DEFAULT_LISTEN_PORT,
+    ))
+}
+
+#[tauri::command]
+async fn run_connectivity_test(
+    nat: State<'_, NatTraversal>,
+) -> Result<diagnostics::ConnectivityReport, UiError> {
+    let probe = diagnostics::NativeProbe { nat: &nat };
+    Ok(diagnostics::run_connectivity_test(&probe).await)
+}
+
+/// Refuse a device everywhere, by name, address or device id
+#[tauri::command]
+async fn block_peer(
+    peer_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    
+    blocklist::block_peer(&app_state.trust_store, &devices, &peer_id);
+    Ok(())
+}
+
+#[tauri::command]
+async fn unblock_peer(
+    peer_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    
+    blocklist::unblock_peer(&app_state.trust_store, &devices, &peer_id)
+}
+
+#[tauri::command]
+async fn request_pairing(
+    device_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<PairingHandle, UiError> {
+    let (pairing, devices) = {
+        let app_state = state.app_state.lock().await;
+        let devices = app_state.network_discovery.lock().await.get_devices();
+        (app_state.pairing.clone(), devices)
+    };
+    
+    pairing::request_pairing(&pairing, &devices, &device_id).await
+}
+
+#[tauri::command]
+async fn confirm_pairing(
+    request_id: String,
+    accept: bool,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let pairing = state.app_state.lock().await.pairing.clone();
+    
+    pairing::confirm_pairing(&pairing, &request_id, accept).await
+}
+
+#[tauri::command]
+async fn get_peer_fingerprint(
+    device_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<String, UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    
+    pairing::peer_fingerprint(&app_state.trust_store, &devices, &device_id)
+}
+
+#[tauri::command]
+async fn get_local_fingerprint(state: State<'_, TauriAppState>) -> Result<String, UiError> {
+    Ok(state.app_state.lock().await.identity.fingerprint())
+}
+
+#[tauri::command]
+async fn get_conversation_info(
+    peer_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<chat::ConversationInfo, UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    
+    chat::conversation_info(&app_state.trust_store, &devices, &peer_id)
 }
 
 #[tauri::command]
@@ -154,21 +916,105 @@ async fn send_chat_message(
     message: String,
     to: Option<String>,
     state: State<'_, TauriAppState>,
-) -> Result<String, String> {
+) -> Result<ChatMessage, UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    let chat_service = app_state.chat_service.lock().await;
+    
+    chat::send_message(&chat_service, &devices, message, to).await
+}
+
+#[tauri::command]
+async fn send_attachment(
+    file_path: String,
+    to: Option<String>,
+    state: State<'_, TauriAppState>,
+) -> Result<ChatMessage, UiError> {
     let app_state = state.app_state.lock().await;
-    let user_name = app_state.user_name.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
     
-    tracing::info!("Sending chat message from {}: {}", user_name, message);
+    let path = std::path::PathBuf::from(&file_path);
+    let file_hash = app_state.file_transfer.lock().await.share_file(&path).await?;
+    let attachment = ChatAttachment {
+        file_hash,
+        name: path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or(file_path),
+        size: tokio::fs::metadata(&path).await.map_err(desk_share_net::DeskShareError::from)?.len(),
+    };
     
-    Ok("Message sent".to_string())
+    let chat_service = app_state.chat_service.lock().await;
+    chat::send_attachment(&chat_service, &devices, attachment, to).await
 }
 
 #[tauri::command]
 async fn get_chat_history(
+    filter: Option<MessageFilter>,
+    state: State<'_, TauriAppState>,
+) -> Result<Vec<ChatMessage>, UiError> {
+    let app_state = state.app_state.lock().await;
+    let chat_service = app_state.chat_service.lock().await;
+    
+    Ok(chat_service.get_history(&filter.unwrap_or_default()))
+}
+
+#[tauri::command]
+async fn mark_read(
+    message_id: String,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let chat_service = app_state.chat_service.lock().await;
+    
+    Ok(chat_service.mark_read(&message_id).await?)
+}
+
+#[tauri::command]
+async fn set_typing(
+    to: Option<String>,
+    is_typing: bool,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let devices = app_state.network_discovery.lock().await.get_devices();
+    let to = to.map(|to| chat::resolve_peer(&devices, &to)).transpose()?;
+    let chat_service = app_state.chat_service.lock().await;
+    
+    Ok(chat_service.set_typing(to, is_typing).await?)
+}
+
+#[tauri::command]
+async fn unlock_chat_history(
+    passphrase: String,
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let chat_service = app_state.chat_service.lock().await;
+    
+    chat::unlock_history(&chat_service, &AppState::chat_history_path(), passphrase)
+}
+
+#[tauri::command]
+async fn encrypt_chat_history(
+    passphrase: Option<String>,
+    app: AppHandle,
     state: State<'_, TauriAppState>,
-) -> Result<Vec<ChatMessage>, String> {
-    // In a real implementation, this would retrieve chat history
-    Ok(vec![])
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let chat_service = app_state.chat_service.lock().await;
+    
+    chat::encrypt_history(&chat_service, passphrase, &app)
+}
+
+#[tauri::command]
+async fn reset_chat_history(
+    state: State<'_, TauriAppState>,
+) -> Result<(), UiError> {
+    let app_state = state.app_state.lock().await;
+    let chat_service = app_state.chat_service.lock().await;
+    
+    Ok(chat_service.reset_history(&AppState::chat_history_path())?)
 }
 
 // ============================================================================
@@ -184,24 +1030,16 @@ async fn main() {
 
     tracing::info!("Starting Desk Share Net application");
 
-    // Initialize application state
-    let app_state = AppState {
-        user_name: Arc::new(Mutex::new(String::new())),
-        network_discovery: Arc::new(Mutex::new(NetworkDiscovery::new().await)),
-        file_transfer: Arc::new(Mutex::new(FileTransfer::new().await)),
-        screen_share: Arc::new(Mutex::new(ScreenShare::new().await)),
-        connected_devices: Arc::new(Mutex::new(Vec::new())),
-    };
+    // Run Tauri's tasks on the same runtime as the library's
+    tauri::async_runtime::set(tokio::runtime::Handle::current());
 
-    // Start network discovery in background
-    {
-        let discovery = app_state.network_discovery.clone();
-        tokio::spawn(async move {
-            let mut discovery = discovery.lock().await;
-            discovery.start_discovery().await;
-            discovery.listen_for_devices().await;
-        });
-    }
+    // Initialize application state and start discovery
+    let app_state = AppState::new().await;
+    app_state.initialize().await;
+
+    let nat_traversal = NatTraversal::new()
+        .await
+        .expect("failed to initialize NAT traversal");
 
     // Wrap state for Tauri
     let tauri_state = TauriAppState {
@@ -210,23 +1048,166 @@ async fn main() {
 
     // Build and run Tauri application
     tauri::Builder::default()
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_opener::init())
         .manage(tauri_state)
+        .manage(FrameForwarders::default())
+        .manage(nat_traversal)
         .invoke_handler(tauri::generate_handler![
             set_user_name,
             get_devices,
+            resync_devices,
             refresh_devices,
             start_file_transfer,
+            list_shared_files,
+            list_remote_files,
+            share_file,
+            share_directory,
+            send_text,
+            send_file_to_devices,
+            get_received_texts,
+            dismiss_received_text,
+            unshare_file,
             get_transfer_progress,
+            get_upload_progress,
+            get_transfer_diagnostics,
+            get_peer_stats,
+            reset_peer_stats,
+            get_transfer_history,
+            clear_transfer_history,
+            reorder_transfer_queue,
+            pause_transfer,
+            resume_transfer,
+            cancel_transfer,
+            dismiss_transfer,
+            respond_to_transfer,
+            pick_files_to_send,
+            pick_save_directory,
+            get_downloads_dir,
+            set_downloads_dir,
+            get_shareable_roots,
+            add_shareable_root,
+            reveal_in_file_manager,
+            list_monitors,
             start_screen_share,
+            start_screen_share_preset,
+            switch_monitor,
+            set_capture_region,
+            update_screen_share_settings,
+            set_screen_quality_preset,
+            set_show_cursor,
+            set_screen_audio,
+            subscribe_screen_frames,
+            unsubscribe_screen_frames,
+            get_screen_frame,
+            get_screen_share_stats,
+            get_frame_buffer_usage,
             stop_screen_share,
             join_screen_share,
+            list_remote_sessions,
+            join_remote_session,
+            leave_remote_session,
+            set_screen_share_access,
+            list_screen_share_participants,
+            kick_screen_share_participant,
+            set_screen_share_paused,
+            set_screen_remote_control,
+            allow_screen_control,
+            revoke_screen_control,
+            send_screen_input,
+            respond_to_join_request,
+            get_connection_info,
+            run_connectivity_test,
+            request_pairing,
+            confirm_pairing,
+            get_peer_fingerprint,
+            get_local_fingerprint,
+            get_conversation_info,
+            block_peer,
+            unblock_peer,
             send_chat_message,
+            send_attachment,
             get_chat_history,
+            unlock_chat_history,
+            encrypt_chat_history,
+            reset_chat_history,
+            mark_read,
+            set_typing,
         ])
         .setup(|app| {
+            // Relay library event streams to the webview
+            let handle = app.handle().clone();
+            let app_state = app.state::<TauriAppState>().app_state.clone();
+            tauri::async_runtime::spawn(async move {
+                let (
+                    progress_rx,
+                    offers_rx,
+                    texts_rx,
+                    devices_rx,
+                    chat_rx,
+                    join_rx,
+                    monitor_lost_rx,
+                    status_rx,
+                    participants_rx,
+                    summaries_rx,
+                    pairing_rx,
+                    rate_limit_rx,
+                    identity_rx,
+                ) = {
+                    let app_state = app_state.lock().await;
+                    let file_transfer = app_state.file_transfer.lock().await;
+                    let discovery = app_state.network_discovery.lock().await;
+                    let chat_service = app_state.chat_service.lock().await;
+                    let screen_share = app_state.screen_share.lock().await;
+                    (
+                        file_transfer.subscribe_progress(),
+                        file_transfer.subscribe_offers(),
+                        file_transfer.subscribe_texts(),
+                        discovery.subscribe_device_events(),
+                        chat_service.subscribe(),
+                        screen_share.subscribe_join_requests(),
+                        screen_share.subscribe_monitor_lost(),
+                        screen_share.subscribe_session_status(),
+                        screen_share.subscribe_participants(),
+                        screen_share.subscribe_session_summaries(),
+                        app_state.pairing.subscribe(),
+                        app_state.rate_limiter.subscribe(),
+                        app_state.trust_store.subscribe_identity_changes(),
+                    )
+                };
+                tauri::async_runtime::spawn(offers::forward_offers(offers_rx, handle.clone()));
+                tauri::async_runtime::spawn(texts::forward_received_texts(texts_rx, handle.clone()));
+                tauri::async_runtime::spawn(chat::forward_chat_events(chat_rx, handle.clone()));
+                tauri::async_runtime::spawn(remote::forward_join_requests(join_rx, handle.clone()));
+                tauri::async_runtime::spawn(screen::forward_monitor_lost(monitor_lost_rx, handle.clone()));
+                tauri::async_runtime::spawn(screen::forward_session_status(status_rx, handle.clone()));
+                tauri::async_runtime::spawn(remote::forward_participant_changes(participants_rx, handle.clone()));
+                tauri::async_runtime::spawn(screen::forward_session_summaries(summaries_rx, handle.clone()));
+                tauri::async_runtime::spawn(pairing::forward_pairing_events(pairing_rx, handle.clone()));
+                tauri::async_runtime::spawn(pairing::forward_identity_changes(identity_rx, handle.clone()));
+                tauri::async_runtime::spawn(diagnostics::forward_rate_limit_events(rate_limit_rx, handle.clone()));
+                tauri::async_runtime::spawn(devices::forward_device_events(
+                    devices_rx,
+                    handle.clone(),
+                    devices::DEVICE_UPDATE_WINDOW,
+                ));
+                events::forward_transfer_progress(progress_rx, handle).await;
+            });
+            
             tracing::info!("Tauri application setup complete");
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Hosted sessions end properly, so their viewers are told
+            if let tauri::RunEvent::Exit = event {
+                let app_state = app.state::<TauriAppState>().app_state.clone();
+                tauri::async_runtime::block_on(async move {
+                    let screen_share = app_state.lock().await.screen_share.clone();
+                    let screen_share = screen_share.lock().await;
+                    screen_share.shutdown().await;
+                });
+            }
+        });
 }
@@ -0,0 +1,299 @@
+// Incoming transfer prompts
+//
+// Offers from peers surface as `incoming-transfer` events; the dialog answers
+// through `respond_to_transfer` and closes itself on `transfer-offer-expired`.
+// Small offers from paired devices skip the dialog and arrive as
+// `transfer-auto-accepted` instead.
+
+use std::path::PathBuf;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use desk_share_net::{FileTransfer, OfferEvent, PendingOffer};
+
+use crate::error::UiError;
+use crate::events::EventSink;
+
+pub const INCOMING_TRANSFER_EVENT: &str = "incoming-transfer";
+pub const TRANSFER_OFFER_EXPIRED_EVENT: &str = "transfer-offer-expired";
+pub const TRANSFER_AUTO_ACCEPTED_EVENT: &str = "transfer-auto-accepted";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IncomingTransfer {
+    pub offer_id: String,
+    pub sender_name: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub file_hash: String,
+}
+
+impl From<PendingOffer> for IncomingTransfer {
+    fn from(offer: PendingOffer) -> Self {
+        Self {
+            offer_id: offer.offer_id,
+            sender_name: offer.sender_name,
+            file_name: offer.file.name,
+            file_size: offer.file.size,
+            file_hash: offer.file.hash,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OfferExpired {
+    pub offer_id: String,
+}
+
+/// Relay offer arrivals and expiries until the sending side is dropped
+pub async fn forward_offers<E: EventSink>(mut rx: broadcast::Receiver<OfferEvent>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(OfferEvent::Received(offer)) => sink.emit_event(INCOMING_TRANSFER_EVENT, IncomingTransfer::from(offer)),
+            Ok(OfferEvent::AutoAccepted(offer)) => {
+                sink.emit_event(TRANSFER_AUTO_ACCEPTED_EVENT, IncomingTransfer::from(offer))
+            }
+            Ok(OfferEvent::Expired { offer_id }) => {
+                sink.emit_event(TRANSFER_OFFER_EXPIRED_EVENT, OfferExpired { offer_id })
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Offer forwarder lagged, skipped {} offers", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Reason the sender hears when the dialog gives none
+const DEFAULT_REJECT_REASON: &str = "declined";
+
+/// Accept or reject an offer; returns the file hash to track when accepted.
+/// A second answer for the same offer fails with `offer_not_found`.
+pub async fn respond_to_offer(
+    file_transfer: &FileTransfer,
+    offer_id: &str,
+    accept: bool,
+    save_path: Option<String>,
+    reason: Option<String>,
+) -> Result<Option<String>, UiError> {
+    if !accept {
+        let reason = reason.unwrap_or_else(|| DEFAULT_REJECT_REASON.to_string());
+        file_transfer.reject_offer(offer_id, &reason).await?;
+        return Ok(None);
+    }
+
+    // Fall back to the configured downloads directory when nothing was picked
+    let save_dir = save_path
+        .map(PathBuf::from)
+        .unwrap_or_else(|| file_transfer.downloads_dir());
+    let file_hash = file_transfer.accept_offer(offer_id, &save_dir).await?;
+
+    Ok(Some(file_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use desk_share_net::network::{ChunkCodec, HashAlgorithm, LanChunkTransport, ShareKind, SharedFile, MANIFEST_VERSION_FLAT};
+    use desk_share_net::p2p::TcpTransport;
+    use desk_share_net::security::{DeviceIdentity, PeerIdentity, SecurityConfig, TrustLevel, TrustPolicy, TrustStore};
+    use desk_share_net::{Device, TransferStatus};
+    use crate::events::tests::RecordingSink;
+
+    fn offered_file(name: &str) -> SharedFile {
+        SharedFile {
+            hash: format!("hash-{}", name),
+            name: name.to_string(),
+            size: 4096,
+            chunks: vec![format!("chunk-{}", name)],
+            chunk_size: 1024 * 1024,
+            total_chunks: 1,
+            peer_id: "peer-alice".to_string(),
+            timestamp: 0,
+            kind: ShareKind::File,
+            codec: ChunkCodec::Raw,
+            encrypted: true,
+            manifest_version: MANIFEST_VERSION_FLAT,
+            merkle_root: None,
+            hash_algorithm: HashAlgorithm::Blake3,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_offer_response_and_expiry_events() {
+        let file_transfer = FileTransfer::new().await;
+        file_transfer.set_offer_timeout(Duration::from_millis(100));
+        let sink = RecordingSink::default();
+        let forwarder = tokio::spawn(forward_offers(file_transfer.subscribe_offers(), sink.clone()));
+
+        let rejected = file_transfer
+            .receive_offer("peer-alice".to_string(), "Alice's MacBook".to_string(), offered_file("notes.txt"))
+            .await;
+        assert_eq!(respond_to_offer(&file_transfer, &rejected, false, None, None).await, Ok(None));
+
+        // Answering twice must not reach the library a second time
+        let err = respond_to_offer(&file_transfer, &rejected, true, None, None).await.unwrap_err();
+        assert_eq!(err.code, "offer_not_found");
+
+        let dir = std::env::temp_dir().join(format!("dsn-offers-{}", std::process::id()));
+        let accepted = file_transfer
+            .receive_offer("peer-alice".to_string(), "Alice's MacBook".to_string(), offered_file("deck.pdf"))
+            .await;
+        let hash = respond_to_offer(&file_transfer, &accepted, true, Some(dir.to_string_lossy().to_string()), None)
+            .await
+            .unwrap();
+        assert_eq!(hash.as_deref(), Some("hash-deck.pdf"));
+        let progress = file_transfer.get_transfer_progress().await;
+        assert_eq!(progress[0].output_path, Some(dir.join("deck.pdf")));
+
+        let ignored = file_transfer
+            .receive_offer("peer-alice".to_string(), "Alice's MacBook".to_string(), offered_file("movie.mkv"))
+            .await;
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let sequence: Vec<(String, String)> = sink
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, payload)| (name.clone(), payload["offer_id"].as_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            sequence,
+            vec![
+                (INCOMING_TRANSFER_EVENT.to_string(), rejected.clone()),
+                (INCOMING_TRANSFER_EVENT.to_string(), accepted.clone()),
+                (INCOMING_TRANSFER_EVENT.to_string(), ignored.clone()),
+                (TRANSFER_OFFER_EXPIRED_EVENT.to_string(), ignored.clone()),
+            ]
+        );
+
+        let incoming = sink.named(INCOMING_TRANSFER_EVENT);
+        assert_eq!(incoming[0]["sender_name"], "Alice's MacBook");
+        assert_eq!(incoming[0]["file_name"], "notes.txt");
+        assert_eq!(incoming[0]["file_size"], 4096);
+
+        // Answering after expiry is the same as answering twice
+        let err = respond_to_offer(&file_transfer, &ignored, true, None, None).await.unwrap_err();
+        assert_eq!(err.code, "offer_not_found");
+
+        forwarder.abort();
+    }
+
+    #[tokio::test]
+    async fn test_sent_files_are_accepted_or_declined_on_the_other_side() {
+        let dir = std::env::temp_dir().join(format!("dsn-offers-e2e-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("holiday.mov"), &data).unwrap();
+        std::fs::write(dir.join("spam.zip"), b"unwanted").unwrap();
+
+        // The receiving app takes every connection on its transfer listener
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let devices = vec![Device::new("Bob's laptop".to_string(), listener.local_addr().unwrap().to_string(), 0)];
+        let receiver = FileTransfer::new().await.with_lan_transport(Arc::new(LanChunkTransport::new()));
+        let sink = RecordingSink::default();
+        let forwarder = tokio::spawn(forward_offers(receiver.subscribe_offers(), sink.clone()));
+        let server = {
+            let receiver = receiver.clone();
+            tokio::spawn(async move {
+                let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+                loop {
+                    let (stream, addr) = transport.accept(&listener).await.unwrap();
+                    receiver.accept_transfer(stream, addr.ip().to_string()).await.unwrap();
+                }
+            })
+        };
+
+        let sender = FileTransfer::new().await.with_identity(Arc::new(DeviceIdentity::generate()));
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let send = |name: &str| {
+            let path = dir.join(name).to_string_lossy().to_string();
+            let (sender, devices) = (sender.clone(), devices.clone());
+            async move { crate::sends::send_file_to_device(&sender, &RecordingSink::default(), &devices, "Bob's laptop", &path).await }
+        };
+        let sent = send("holiday.mov").await.unwrap();
+        let incoming = loop {
+            if let Some(incoming) = sink.named(INCOMING_TRANSFER_EVENT).first().cloned() {
+                break incoming;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        };
+        assert_eq!((incoming["file_name"].as_str(), incoming["file_hash"].as_str()), (Some("holiday.mov"), Some(sent.as_str())));
+
+        let downloads = dir.join("downloads");
+        let offer_id = incoming["offer_id"].as_str().unwrap();
+        let hash = respond_to_offer(&receiver, offer_id, true, Some(downloads.to_string_lossy().to_string()), None)
+            .await
+            .unwrap();
+        assert_eq!(hash.as_deref(), Some(sent.as_str()));
+        assert_eq!(receiver.wait_for_transfer(&sent).await, Some(TransferStatus::Completed));
+        assert_eq!(std::fs::read(downloads.join("holiday.mov")).unwrap(), data);
+
+        // Declining leaves nothing behind on the receiving side
+        send("spam.zip").await.unwrap();
+        let declined = loop {
+            if let Some(incoming) = sink.named(INCOMING_TRANSFER_EVENT).get(1).cloned() {
+                break incoming;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        };
+        assert_eq!(declined["file_name"], "spam.zip");
+        let offer_id = declined["offer_id"].as_str().unwrap();
+        assert_eq!(respond_to_offer(&receiver, offer_id, false, None, Some("no thanks".to_string())).await, Ok(None));
+        assert!(receiver.get_pending_offers().is_empty());
+        assert!(!downloads.join("spam.zip").exists());
+        assert_eq!(receiver.get_transfer_progress().await.len(), 1);
+
+        server.abort();
+        forwarder.abort();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_small_offers_from_paired_devices_skip_the_prompt() {
+        let trust_store = Arc::new(TrustStore::new());
+        let bob = DeviceIdentity::generate();
+        trust_store.record_paired(&bob.device_id(), "peer-bob", &bob.public_key());
+        let file_transfer = FileTransfer::new().await.with_trust_store(trust_store.clone());
+        let downloads = std::env::temp_dir().join(format!("dsn-auto-accept-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&downloads);
+        file_transfer.set_downloads_dir(downloads.clone()).unwrap();
+        let sink = RecordingSink::default();
+        let forwarder = tokio::spawn(forward_offers(file_transfer.subscribe_offers(), sink.clone()));
+
+        let as_bob = || PeerIdentity { peer_id: "peer-bob".to_string(), device_id: bob.device_id() };
+        let auto = file_transfer
+            .receive_authenticated_offer(as_bob(), "Bob's PC".to_string(), offered_file("notes.txt"))
+            .await;
+        assert!(file_transfer.get_pending_offers().is_empty());
+        let progress = file_transfer.get_transfer_progress().await;
+        assert_eq!(progress[0].output_path, Some(file_transfer.downloads_dir().join("notes.txt")));
+
+        // Too big for the paired policy, and strangers always get asked
+        trust_store.set_policy(
+            TrustLevel::Paired,
+            TrustPolicy { auto_accept_max_bytes: Some(1024), skip_join_approval: true },
+        );
+        let big = file_transfer
+            .receive_authenticated_offer(as_bob(), "Bob's PC".to_string(), offered_file("deck.pdf"))
+            .await;
+        let stranger = file_transfer
+            .receive_offer("peer-eve".to_string(), "Eve".to_string(), offered_file("tiny.txt"))
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(sink.named(TRANSFER_AUTO_ACCEPTED_EVENT).len(), 1);
+        assert_eq!(sink.named(TRANSFER_AUTO_ACCEPTED_EVENT)[0]["offer_id"], auto.as_str());
+        let prompted: Vec<String> = sink
+            .named(INCOMING_TRANSFER_EVENT)
+            .iter()
+            .map(|payload| payload["offer_id"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(prompted, vec![big, stranger]);
+
+        forwarder.abort();
+        let _ = std::fs::remove_dir_all(&downloads);
+    }
+}
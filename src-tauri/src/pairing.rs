@@ -0,0 +1,282 @@
+// Pairing commands and events
+//
+// `request_pairing` returns the code the initiator shows; the other device
+// gets a `pairing-request` event with the code from its side of the key
+// exchange, which only matches when nobody sits in between. Once both users answer
+// `confirm_pairing`, each side emits `pairing-completed`, with `paired`
+// false when either rejected or the request timed out.
+//
+// Fingerprints let two users compare keys over a phone call. When a paired
+// device's address answers with a different key it loses its privileges
+// until paired again, and the UI gets a high-severity `identity-changed`.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use desk_share_net::security::{IdentityChange, PairingEvent, PairingHandle, PairingManager, TrustStore};
+use desk_share_net::{DeskShareError, Device};
+
+use crate::chat::resolve_peer;
+use crate::error::UiError;
+use crate::events::EventSink;
+
+pub const PAIRING_REQUEST_EVENT: &str = "pairing-request";
+pub const PAIRING_COMPLETED_EVENT: &str = "pairing-completed";
+pub const IDENTITY_CHANGED_EVENT: &str = "identity-changed";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IdentityChanged {
+    pub severity: &'static str,
+    #[serde(flatten)]
+    pub change: IdentityChange,
+}
+
+/// Relay pairing activity until the sending side is dropped
+pub async fn forward_pairing_events<E: EventSink>(mut rx: broadcast::Receiver<PairingEvent>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(event @ PairingEvent::Requested { .. }) => sink.emit_event(PAIRING_REQUEST_EVENT, event),
+            Ok(event @ PairingEvent::Completed { .. }) => sink.emit_event(PAIRING_COMPLETED_EVENT, event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Pairing forwarder lagged, skipped {} events", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Relay key changes at paired addresses until the trust store is dropped
+pub async fn forward_identity_changes<E: EventSink>(mut rx: broadcast::Receiver<IdentityChange>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(change) => sink.emit_event(IDENTITY_CHANGED_EVENT, IdentityChanged { severity: "high", change }),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Identity change forwarder lagged, skipped {} changes", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Fingerprint for a device by name or IP, as its discovery reply was
+/// signed, or for a device id or address the trust store has a key for
+pub fn peer_fingerprint(trust_store: &TrustStore, devices: &[Device], device_id: &str) -> Result<String, UiError> {
+    resolve_peer(devices, device_id)
+        .ok()
+        .and_then(|peer_id| devices.iter().find(|device| device.ip == peer_id)?.fingerprint.clone())
+        .or_else(|| trust_store.fingerprint(device_id))
+        .ok_or_else(|| DeskShareError::PeerNotFound(device_id.to_string()).into())
+}
+
+/// Start pairing with a device by name or IP
+pub async fn request_pairing(
+    pairing: &PairingManager,
+    devices: &[Device],
+    device_id: &str,
+) -> Result<PairingHandle, UiError> {
+    let peer_id = resolve_peer(devices, device_id)?;
+    Ok(pairing.request_pairing(&peer_id).await?)
+}
+
+pub async fn confirm_pairing(pairing: &PairingManager, request_id: &str, accept: bool) -> Result<(), UiError> {
+    Ok(pairing.confirm_pairing(request_id, accept).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use async_trait::async_trait;
+    use desk_share_net::security::{
+        DeviceIdentity, PairingReply, PairingRequest, PairingReveal, PairingTransport, PeerIdentity, TrustStore,
+    };
+    use crate::events::tests::RecordingSink;
+
+    /// Delivers pairing messages straight to managers in the same process
+    #[derive(Default)]
+    struct InProcessHub {
+        peers: Mutex<HashMap<String, Arc<PairingManager>>>,
+    }
+
+    impl InProcessHub {
+        fn peer(&self, peer_id: &str) -> Result<Arc<PairingManager>, anyhow::Error> {
+            self.peers
+                .lock()
+                .unwrap()
+                .get(peer_id)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("no route to {}", peer_id))
+        }
+    }
+
+    struct HubTransport(Arc<InProcessHub>);
+
+    #[async_trait]
+    impl PairingTransport for HubTransport {
+        async fn send_request(&self, peer_id: &str, request: PairingRequest) -> Result<PairingReply, anyhow::Error> {
+            self.0.peer(peer_id)?.handle_request(request)
+        }
+
+        async fn send_reveal(&self, peer_id: &str, reveal: PairingReveal) -> Result<(), anyhow::Error> {
+            self.0.peer(peer_id)?.handle_reveal(reveal)
+        }
+
+        async fn send_confirm(&self, peer_id: &str, from_peer_id: &str, request_id: &str, accept: bool) -> Result<(), anyhow::Error> {
+            self.0.peer(peer_id)?.handle_confirm(from_peer_id, request_id, accept);
+            Ok(())
+        }
+    }
+
+    struct Peer {
+        manager: Arc<PairingManager>,
+        identity: Arc<DeviceIdentity>,
+        trust: Arc<TrustStore>,
+        sink: RecordingSink,
+    }
+
+    fn join(hub: &Arc<InProcessHub>, peer_id: &str) -> Peer {
+        let identity = Arc::new(DeviceIdentity::generate());
+        let trust = Arc::new(TrustStore::new());
+        let manager = Arc::new(
+            PairingManager::new(identity.clone(), trust.clone())
+                .with_transport(peer_id.to_string(), Arc::new(HubTransport(hub.clone()))),
+        );
+        hub.peers.lock().unwrap().insert(peer_id.to_string(), manager.clone());
+
+        let sink = RecordingSink::default();
+        tokio::spawn(forward_pairing_events(manager.subscribe(), sink.clone()));
+        Peer { manager, identity, trust, sink }
+    }
+
+    async fn wait_for(sink: &RecordingSink, event: &str) -> serde_json::Value {
+        for _ in 0..50 {
+            if let Some(payload) = sink.named(event).first() {
+                return payload.clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no {} event", event);
+    }
+
+    fn devices() -> Vec<Device> {
+        vec![
+            Device::new("Alice's Mac".to_string(), "10.0.0.2".to_string(), 8080),
+            Device::new("Bob's PC".to_string(), "10.0.0.3".to_string(), 8080),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_both_sides_pair_with_matching_codes() {
+        let hub = Arc::new(InProcessHub::default());
+        let alice = join(&hub, "10.0.0.2");
+        let bob = join(&hub, "10.0.0.3");
+
+        let handle = request_pairing(&alice.manager, &devices(), "Bob's PC").await.unwrap();
+        assert_eq!(handle.code.len(), 6);
+        assert!(handle.code.chars().all(|c| c.is_ascii_digit()));
+        assert_eq!(handle.device_id, bob.identity.device_id());
+
+        let request = wait_for(&bob.sink, PAIRING_REQUEST_EVENT).await;
+        assert_eq!(request["code"], handle.code.as_str());
+        assert_eq!(request["device_id"], alice.identity.device_id().as_str());
+        assert_eq!(request["request_id"], handle.request_id.as_str());
+
+        // Nothing is trusted until both users confirm
+        confirm_pairing(&bob.manager, &handle.request_id, true).await.unwrap();
+        assert!(!bob.trust.is_paired(&alice.identity.device_id()));
+        confirm_pairing(&alice.manager, &handle.request_id, true).await.unwrap();
+
+        assert!(alice.trust.is_paired(&bob.identity.device_id()));
+        assert!(bob.trust.is_paired(&alice.identity.device_id()));
+        for peer in [&alice, &bob] {
+            let completed = wait_for(&peer.sink, PAIRING_COMPLETED_EVENT).await;
+            assert_eq!(completed["paired"], true);
+        }
+
+        let err = confirm_pairing(&alice.manager, &handle.request_id, true).await.unwrap_err();
+        assert_eq!(err.code, "pairing_request_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_rejection_leaves_no_trust_record() {
+        let hub = Arc::new(InProcessHub::default());
+        let alice = join(&hub, "10.0.0.2");
+        let bob = join(&hub, "10.0.0.3");
+
+        let handle = request_pairing(&alice.manager, &devices(), "10.0.0.3").await.unwrap();
+        confirm_pairing(&alice.manager, &handle.request_id, true).await.unwrap();
+        confirm_pairing(&bob.manager, &handle.request_id, false).await.unwrap();
+
+        for peer in [&alice, &bob] {
+            let completed = wait_for(&peer.sink, PAIRING_COMPLETED_EVENT).await;
+            assert_eq!(completed["paired"], false);
+            assert!(peer.trust.list().is_empty());
+        }
+        assert_eq!(wait_for(&alice.sink, PAIRING_COMPLETED_EVENT).await["reason"], "rejected by peer");
+    }
+
+    #[tokio::test]
+    async fn test_unanswered_pairing_times_out() {
+        let hub = Arc::new(InProcessHub::default());
+        let alice = join(&hub, "10.0.0.2");
+        let bob = join(&hub, "10.0.0.3");
+        alice.manager.set_timeout(Duration::from_millis(50));
+        bob.manager.set_timeout(Duration::from_millis(50));
+
+        let handle = request_pairing(&alice.manager, &devices(), "Bob's PC").await.unwrap();
+        confirm_pairing(&alice.manager, &handle.request_id, true).await.unwrap();
+
+        let completed = wait_for(&bob.sink, PAIRING_COMPLETED_EVENT).await;
+        assert_eq!(completed["reason"], "timed out");
+        let completed = wait_for(&alice.sink, PAIRING_COMPLETED_EVENT).await;
+        assert_eq!(completed["paired"], false);
+
+        // A late answer finds nothing to confirm
+        let err = confirm_pairing(&bob.manager, &handle.request_id, true).await.unwrap_err();
+        assert_eq!(err.code, "pairing_request_not_found");
+        assert!(alice.trust.list().is_empty() && bob.trust.list().is_empty());
+
+        let err = request_pairing(&alice.manager, &devices(), "Carol").await.unwrap_err();
+        assert_eq!(err.code, "peer_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_key_rotation_needs_pairing_again() {
+        let hub = Arc::new(InProcessHub::default());
+        let alice = join(&hub, "10.0.0.2");
+        let bob = join(&hub, "10.0.0.3");
+        tokio::spawn(forward_identity_changes(alice.trust.subscribe_identity_changes(), alice.sink.clone()));
+
+        let handle = request_pairing(&alice.manager, &devices(), "Bob's PC").await.unwrap();
+        confirm_pairing(&bob.manager, &handle.request_id, true).await.unwrap();
+        confirm_pairing(&alice.manager, &handle.request_id, true).await.unwrap();
+        let bob_at = PeerIdentity { peer_id: "10.0.0.3".to_string(), device_id: bob.identity.device_id() };
+        assert!(alice.trust.policy_for(&bob_at).skip_join_approval);
+        assert_eq!(peer_fingerprint(&alice.trust, &devices(), &bob.identity.device_id()).unwrap(), bob.identity.fingerprint());
+
+        // Bob's address connects with a new key
+        let rotated = DeviceIdentity::generate();
+        alice.trust.observe_key("10.0.0.3", &rotated.public_key());
+
+        let changed = wait_for(&alice.sink, IDENTITY_CHANGED_EVENT).await;
+        assert_eq!(changed["severity"], "high");
+        assert_eq!(changed["peer_id"], "10.0.0.3");
+        assert_eq!(changed["device_id"], bob.identity.device_id().as_str());
+        assert_eq!(changed["previous_fingerprint"], bob.identity.fingerprint().as_str());
+        assert_eq!(changed["new_fingerprint"], rotated.fingerprint().as_str());
+        assert!(!alice.trust.is_paired(&bob.identity.device_id()));
+        let rotated_at = PeerIdentity { peer_id: "10.0.0.3".to_string(), device_id: rotated.device_id() };
+        assert!(!alice.trust.policy_for(&bob_at).skip_join_approval);
+        assert!(!alice.trust.policy_for(&rotated_at).skip_join_approval);
+        assert!(!alice.trust.policy_for(&rotated_at).auto_accepts(1));
+
+        // Discovery shows the key the device now signs with
+        let mut listed = devices();
+        listed[1].fingerprint = Some(rotated.fingerprint());
+        assert_eq!(peer_fingerprint(&alice.trust, &listed, "Bob's PC").unwrap(), rotated.fingerprint());
+        let err = peer_fingerprint(&alice.trust, &listed, "Carol").unwrap_err();
+        assert_eq!(err.code, "peer_not_found");
+    }
+}
@@ -0,0 +1,625 @@
+// Joining screen shares hosted by other devices
+//
+// Hosts broadcast their sessions to the LAN every few seconds, and take
+// them back when they stop; ones not heard from for a while drop off the
+// list. The viewer lists them with the host's name, when they started and
+// whether they need a password, joins one (supplying the password or
+// waiting for the host's approval) and receives its frames as the usual
+// `screen-frame` events. Each way a join can fail keeps its own error code:
+// `wrong_password`, `join_denied`, `session_not_found` when the host has
+// stopped sharing, `session_full` past the host's participant limit and
+// `peer_connection_failed` when it can't be reached.
+//
+// Hosts see who is watching with `list_participants`, and viewers coming
+// and going as `screen-participant-joined` and `screen-participant-left`
+// events carrying the new count.
+//
+// Viewers may drive the host's mouse and keyboard only once the host has
+// turned remote control on for the session and for them; until then their
+// input fails with `remote_control_not_allowed`. `revoke_control` takes
+// control back from everyone at once.
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use desk_share_net::network::{AccessMode, InputEvent, Participant, ParticipantChange, PendingJoin, RemoteSession};
+use desk_share_net::{DeskShareError, Device, ScreenShare};
+
+use crate::error::UiError;
+use crate::events::EventSink;
+use crate::screen::FrameForwarders;
+
+/// Emitted on the host when a viewer asks to join an Approval session
+pub const JOIN_REQUEST_EVENT: &str = "screen-join-request";
+
+pub const PARTICIPANT_JOINED_EVENT: &str = "screen-participant-joined";
+pub const PARTICIPANT_LEFT_EVENT: &str = "screen-participant-left";
+
+/// A joinable session as shown in the device list
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteSessionInfo {
+    #[serde(flatten)]
+    pub session: RemoteSession,
+    /// What the host calls itself, else its device name, else the peer id
+    pub host_name: String,
+}
+
+/// What the viewer needs to render a joined session
+#[derive(Debug, Clone, Serialize)]
+pub struct ViewerHandle {
+    pub session_id: String,
+    pub host_peer_id: String,
+    pub resolution: (u32, u32),
+}
+
+pub async fn list_remote_sessions(screen_share: &ScreenShare, devices: &[Device]) -> Vec<RemoteSessionInfo> {
+    screen_share
+        .get_available_sessions()
+        .await
+        .into_iter()
+        .map(|mut session| {
+            let host_name = session.host_name.take().unwrap_or_else(|| {
+                devices
+                    .iter()
+                    .find(|device| device.ip == session.host_peer_id)
+                    .map(|device| device.name.clone())
+                    .unwrap_or_else(|| session.host_peer_id.clone())
+            });
+            RemoteSessionInfo { session, host_name }
+        })
+        .collect()
+}
+
+/// Join a remote session and start forwarding its frames to `sink`
+pub async fn join_remote_session<E: EventSink>(
+    screen_share: &ScreenShare,
+    forwarders: &FrameForwarders,
+    sink: E,
+    session_id: &str,
+    password: Option<String>,
+    max_fps: u32,
+) -> Result<ViewerHandle, UiError> {
+    let session = screen_share.join_remote_session(session_id, password).await?;
+    let rx = screen_share.subscribe_frames(session_id).await?;
+    forwarders.start(session_id, rx, sink, max_fps);
+
+    Ok(ViewerHandle {
+        session_id: session.session_id,
+        host_peer_id: session.host_peer_id,
+        resolution: session.resolution,
+    })
+}
+
+/// Join the session announced by the device at `host_peer_id`
+pub async fn join_host_session<E: EventSink>(
+    screen_share: &ScreenShare,
+    forwarders: &FrameForwarders,
+    sink: E,
+    host_peer_id: &str,
+    password: Option<String>,
+    max_fps: u32,
+) -> Result<ViewerHandle, UiError> {
+    let session_id = screen_share
+        .get_available_sessions()
+        .await
+        .into_iter()
+        .find(|session| session.host_peer_id == host_peer_id)
+        .map(|session| session.session_id)
+        .ok_or_else(|| DeskShareError::SessionNotFound(host_peer_id.to_string()))?;
+
+    join_remote_session(screen_share, forwarders, sink, &session_id, password, max_fps).await
+}
+
+pub async fn leave_remote_session(
+    screen_share: &ScreenShare,
+    forwarders: &FrameForwarders,
+    session_id: &str,
+) -> Result<(), UiError> {
+    forwarders.stop(session_id);
+    Ok(screen_share.leave_remote_session(session_id).await?)
+}
+
+/// Viewers of a session we host. Those that didn't name themselves go by
+/// their device name when discovery knows them.
+pub async fn list_participants(
+    screen_share: &ScreenShare,
+    devices: &[Device],
+    session_id: &str,
+) -> Result<Vec<Participant>, UiError> {
+    let mut participants = screen_share.get_participants(session_id).await?;
+    for participant in &mut participants {
+        if participant.display_name != participant.peer_id {
+            continue;
+        }
+        if let Some(device) = devices.iter().find(|device| device.ip == participant.peer_id) {
+            participant.display_name = device.name.clone();
+        }
+    }
+    Ok(participants)
+}
+
+/// Remove a viewer from a session we host
+pub async fn kick_participant(screen_share: &ScreenShare, session_id: &str, peer_id: &str) -> Result<(), UiError> {
+    Ok(screen_share.kick_participant(session_id, peer_id).await?)
+}
+
+/// Stop sending frames without ending the session
+pub async fn set_session_paused(screen_share: &ScreenShare, session_id: &str, paused: bool) -> Result<(), UiError> {
+    Ok(screen_share.set_paused(session_id, paused).await?)
+}
+
+/// Let viewers of a session we host drive the mouse and keyboard, those
+/// given control with `allow_control`
+pub async fn set_remote_control(screen_share: &ScreenShare, session_id: &str, allowed: bool) -> Result<(), UiError> {
+    Ok(screen_share.set_remote_control(session_id, allowed).await?)
+}
+
+pub async fn allow_control(
+    screen_share: &ScreenShare,
+    session_id: &str,
+    peer_id: &str,
+    allowed: bool,
+) -> Result<(), UiError> {
+    Ok(screen_share.allow_control(session_id, peer_id, allowed).await?)
+}
+
+/// Take control back from every viewer of every session we host
+pub async fn revoke_control(screen_share: &ScreenShare) {
+    screen_share.revoke_control().await
+}
+
+/// Mouse and keyboard input for the host of a session we're viewing
+pub async fn send_input(screen_share: &ScreenShare, session_id: &str, event: InputEvent) -> Result<(), UiError> {
+    Ok(screen_share.send_input(session_id, event).await?)
+}
+
+/// Restrict who may join a session we host
+pub async fn set_session_access(
+    screen_share: &ScreenShare,
+    session_id: &str,
+    access_mode: AccessMode,
+    password: Option<String>,
+) -> Result<(), UiError> {
+    Ok(screen_share.set_access(session_id, access_mode, password).await?)
+}
+
+/// Relay join requests awaiting approval until the sending side is dropped
+pub async fn forward_join_requests<E: EventSink>(mut rx: broadcast::Receiver<PendingJoin>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(request) => sink.emit_event(JOIN_REQUEST_EVENT, request),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Join request forwarder lagged, skipped {} requests", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Relay viewers joining and leaving until the sending side is dropped
+pub async fn forward_participant_changes<E: EventSink>(mut rx: broadcast::Receiver<ParticipantChange>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(change) if change.joined => sink.emit_event(PARTICIPANT_JOINED_EVENT, change),
+            Ok(change) => sink.emit_event(PARTICIPANT_LEFT_EVENT, change),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Participant forwarder lagged, skipped {} changes", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use async_trait::async_trait;
+    use desk_share_net::network::{
+        CaptureConfig, ControlAction, ControlMessage, Frame, JoinRequest, JoinResponse, SessionAnnouncement, SessionEnded,
+        SessionToken, SessionTransport, TokenGrant,
+    };
+    use desk_share_net::platform::fallback::FallbackCapture;
+    use desk_share_net::platform::input::{InputAction, InputBackend, MouseButton};
+    use desk_share_net::security::{DeviceIdentity, PeerIdentity, TrustStore};
+    use crate::events::tests::RecordingSink;
+    use crate::screen::SCREEN_FRAME_EVENT;
+
+    /// Routes session traffic between services in the same process
+    #[derive(Default)]
+    struct InProcessHub {
+        peers: Mutex<HashMap<String, Arc<ScreenShare>>>,
+        /// Every token a host handed out, as an eavesdropper would see them
+        tokens: Mutex<Vec<SessionToken>>,
+        /// The key each peer's connections authenticate with
+        keys: Mutex<HashMap<String, [u8; 32]>>,
+    }
+
+    impl InProcessHub {
+        fn peer(&self, peer_id: &str) -> Result<Arc<ScreenShare>, anyhow::Error> {
+            self.peers
+                .lock()
+                .unwrap()
+                .get(peer_id)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("no route to {}", peer_id))
+        }
+
+        fn key(&self, peer_id: &str) -> [u8; 32] {
+            self.keys.lock().unwrap()[peer_id]
+        }
+    }
+
+    /// One peer's end of the hub; whoever it reaches knows who sent what,
+    /// as a real connection's handshake would prove
+    struct HubTransport(Arc<InProcessHub>, PeerIdentity);
+
+    impl HubTransport {
+        fn new(hub: &Arc<InProcessHub>, peer_id: &str) -> Self {
+            let identity = DeviceIdentity::generate();
+            hub.keys.lock().unwrap().insert(peer_id.to_string(), identity.public_key());
+            Self(hub.clone(), PeerIdentity { peer_id: peer_id.to_string(), device_id: identity.device_id() })
+        }
+    }
+
+    #[async_trait]
+    impl SessionTransport for HubTransport {
+        async fn announce(&self, announcement: SessionAnnouncement) -> Result<(), anyhow::Error> {
+            let peers: Vec<Arc<ScreenShare>> = self.0.peers.lock().unwrap().values().cloned().collect();
+            for peer in peers {
+                peer.handle_announcement(announcement.clone()).await;
+            }
+            Ok(())
+        }
+
+        async fn request_join(&self, host_peer_id: &str, request: JoinRequest) -> Result<JoinResponse, anyhow::Error> {
+            let response = self.0.peer(host_peer_id)?.handle_authenticated_join(request, &self.1).await;
+            if let JoinResponse::Accepted { token, .. } = &response {
+                self.0.tokens.lock().unwrap().push(token.clone());
+            }
+            Ok(response)
+        }
+
+        async fn send_control(&self, host_peer_id: &str, message: ControlMessage) -> Result<(), anyhow::Error> {
+            self.0.peer(host_peer_id)?.handle_control(message).await
+        }
+
+        async fn grant_token(&self, peer_id: &str, grant: TokenGrant) -> Result<(), anyhow::Error> {
+            self.0.tokens.lock().unwrap().push(grant.token.clone());
+            self.0.peer(peer_id)?.handle_token_grant(grant).await
+        }
+
+        async fn end_session(&self, peer_id: &str, ended: SessionEnded) -> Result<(), anyhow::Error> {
+            self.0.peer(peer_id)?.handle_session_ended(ended).await;
+            Ok(())
+        }
+
+        async fn send_frame(&self, peer_id: &str, frame: Frame) -> Result<(), anyhow::Error> {
+            self.0.peer(peer_id)?.receive_frame(frame).await;
+            Ok(())
+        }
+    }
+
+    fn join_hub(hub: &Arc<InProcessHub>, peer_id: &str) -> Arc<ScreenShare> {
+        join_hub_trusting(hub, peer_id, Arc::new(TrustStore::new()))
+    }
+
+    fn join_hub_trusting(hub: &Arc<InProcessHub>, peer_id: &str, trust_store: Arc<TrustStore>) -> Arc<ScreenShare> {
+        join_hub_capturing(hub, peer_id, trust_store, CaptureConfig::default())
+    }
+
+    fn join_hub_capturing(
+        hub: &Arc<InProcessHub>,
+        peer_id: &str,
+        trust_store: Arc<TrustStore>,
+        capture: CaptureConfig,
+    ) -> Arc<ScreenShare> {
+        let screen_share = Arc::new(
+            ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+                .with_transport(peer_id.to_string(), Arc::new(HubTransport::new(hub, peer_id)))
+                .with_trust_store(trust_store)
+                .with_capture_config(capture),
+        );
+        hub.peers.lock().unwrap().insert(peer_id.to_string(), screen_share.clone());
+        screen_share
+    }
+
+    #[tokio::test]
+    async fn test_password_protected_join() {
+        let hub = Arc::new(InProcessHub::default());
+        let host = join_hub(&hub, "10.0.0.2");
+        let viewer = join_hub(&hub, "10.0.0.3");
+        let devices = vec![Device::new("Alice's Mac".to_string(), "10.0.0.2".to_string(), 8080)];
+
+        let session_id = host.start_sharing(30, (64, 48), None, None, None).await.unwrap();
+        set_session_access(&host, &session_id, AccessMode::Password, Some("hunter2".to_string()))
+            .await
+            .unwrap();
+
+        let listed = list_remote_sessions(&viewer, &devices).await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].host_name, "Alice's Mac");
+        assert_eq!(listed[0].session.access_mode, AccessMode::Password);
+        assert_eq!(listed[0].session.participant_count, 0);
+        // Hosts don't list their own sessions
+        assert!(list_remote_sessions(&host, &devices).await.is_empty());
+
+        let forwarders = FrameForwarders::default();
+        let sink = RecordingSink::default();
+
+        let err = join_remote_session(&viewer, &forwarders, sink.clone(), &session_id, Some("guess".to_string()), 30)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "wrong_password");
+        let err = join_remote_session(&viewer, &forwarders, sink.clone(), &session_id, None, 30)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "wrong_password");
+        assert!(!forwarders.is_active(&session_id));
+
+        let handle = join_remote_session(&viewer, &forwarders, sink.clone(), &session_id, Some("hunter2".to_string()), 30)
+            .await
+            .unwrap();
+        assert_eq!(handle.host_peer_id, "10.0.0.2");
+        assert_eq!(handle.resolution, (64, 48));
+        assert!(host.get_session(&session_id).await.unwrap().participants.contains("10.0.0.3"));
+        assert_eq!(list_remote_sessions(&viewer, &devices).await[0].session.participant_count, 1);
+
+        for _ in 0..50 {
+            if !sink.named(SCREEN_FRAME_EVENT).is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let frames = sink.named(SCREEN_FRAME_EVENT);
+        assert!(!frames.is_empty());
+        assert_eq!(frames[0]["session_id"], session_id.as_str());
+        assert_eq!(frames[0]["width"], 64);
+
+        leave_remote_session(&viewer, &forwarders, &session_id).await.unwrap();
+        assert!(!forwarders.is_active(&session_id));
+        assert!(host.get_session(&session_id).await.unwrap().participants.is_empty());
+
+        let err = leave_remote_session(&viewer, &forwarders, &session_id).await.unwrap_err();
+        assert_eq!(err.code, "session_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_approval_join_denied_and_host_gone() {
+        let hub = Arc::new(InProcessHub::default());
+        let host = join_hub(&hub, "10.0.0.2");
+        let viewer = join_hub(&hub, "10.0.0.3");
+
+        let session_id = host.start_sharing(5, (64, 48), None, None, None).await.unwrap();
+        set_session_access(&host, &session_id, AccessMode::Approval, None).await.unwrap();
+
+        let sink = RecordingSink::default();
+        let forwarder = tokio::spawn(forward_join_requests(host.subscribe_join_requests(), sink.clone()));
+
+        // Deny the request as soon as it shows up on the host
+        let approver = {
+            let host = host.clone();
+            let sink = sink.clone();
+            tokio::spawn(async move {
+                loop {
+                    if let Some(request) = sink.named(JOIN_REQUEST_EVENT).first() {
+                        assert_eq!(request["peer_id"], "10.0.0.3");
+                        host.respond_to_join(request["request_id"].as_str().unwrap(), false).unwrap();
+                        break;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+
+        let forwarders = FrameForwarders::default();
+        let err = join_remote_session(&viewer, &forwarders, sink.clone(), &session_id, None, 15)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "join_denied");
+        approver.await.unwrap();
+        assert!(host.get_session(&session_id).await.unwrap().participants.is_empty());
+
+        let err = host.respond_to_join("no-such-request", true).unwrap_err();
+        assert_eq!(UiError::from(err).code, "join_request_not_found");
+
+        // A host that has disappeared from the network can't be reached
+        hub.peers.lock().unwrap().remove("10.0.0.2");
+        let err = join_remote_session(&viewer, &forwarders, sink.clone(), &session_id, None, 15)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "peer_connection_failed");
+
+        let err = join_remote_session(&viewer, &forwarders, sink.clone(), "no-such-session", None, 15)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "session_not_found");
+        let err = join_host_session(&viewer, &forwarders, sink, "10.0.0.9", None, 15)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "session_not_found");
+
+        forwarder.abort();
+    }
+
+    #[tokio::test]
+    async fn test_paired_viewer_skips_approval() {
+        let hub = Arc::new(InProcessHub::default());
+        let trust_store = Arc::new(TrustStore::new());
+        let host = join_hub_trusting(&hub, "10.0.0.2", trust_store.clone());
+        let viewer = join_hub(&hub, "10.0.0.3");
+
+        let session_id = host.start_sharing(5, (64, 48), None, None, None).await.unwrap();
+        set_session_access(&host, &session_id, AccessMode::Approval, None).await.unwrap();
+        host.set_join_approval_timeout(Duration::from_millis(50));
+
+        // Unpaired viewers still wait for the host, and time out unanswered
+        let forwarders = FrameForwarders::default();
+        let sink = RecordingSink::default();
+        let err = join_remote_session(&viewer, &forwarders, sink.clone(), &session_id, None, 15)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "join_denied");
+
+        let viewer_key = hub.key("10.0.0.3");
+        trust_store.record_paired(&DeviceIdentity::device_id_for(&viewer_key), "10.0.0.3", &viewer_key);
+        let handle = join_remote_session(&viewer, &forwarders, sink, &session_id, None, 15)
+            .await
+            .unwrap();
+        assert_eq!(handle.host_peer_id, "10.0.0.2");
+        assert!(host.get_session(&session_id).await.unwrap().participants.contains("10.0.0.3"));
+        forwarders.stop(&session_id);
+    }
+
+    async fn next_frame(frames: &mut broadcast::Receiver<Frame>) -> bool {
+        loop {
+            match tokio::time::timeout(Duration::from_secs(1), frames.recv()).await {
+                Ok(Ok(_)) => return true,
+                Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+                _ => return false,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_frames_need_a_current_token() {
+        let hub = Arc::new(InProcessHub::default());
+        // The fallback pattern never changes, so only keyframes carry frames
+        let capture = CaptureConfig { keyframe_interval_ms: 20, ..CaptureConfig::default() };
+        let host = join_hub_capturing(&hub, "10.0.0.2", Arc::new(TrustStore::new()), capture);
+        let viewer = join_hub(&hub, "10.0.0.3");
+        let session_id = host.start_sharing(30, (64, 48), None, None, None).await.unwrap();
+
+        let forwarders = FrameForwarders::default();
+        join_remote_session(&viewer, &forwarders, RecordingSink::default(), &session_id, None, 30)
+            .await
+            .unwrap();
+        forwarders.stop(&session_id);
+        let mut frames = viewer.subscribe_frames(&session_id).await.unwrap();
+        assert!(next_frame(&mut frames).await);
+
+        let control = |peer_id: &str, token: SessionToken| ControlMessage {
+            session_id: session_id.clone(),
+            peer_id: peer_id.to_string(),
+            token,
+            action: ControlAction::Subscribe,
+        };
+        let refused = |result: Result<(), anyhow::Error>| UiError::from(result.unwrap_err()).code == "invalid_session_token";
+
+        // A made-up token, or someone else replaying the viewer's, gets nowhere
+        let original = hub.tokens.lock().unwrap()[0].clone();
+        assert!(format!("{:?}", original).ends_with("(..)"));
+        assert!(refused(host.handle_control(control("10.0.0.3", SessionToken::generate())).await));
+        assert!(refused(host.handle_control(control("10.0.0.9", original.clone())).await));
+
+        // Pausing rotates tokens; the viewer picks up its new one, the old one is dead
+        set_session_paused(&host, &session_id, true).await.unwrap();
+        assert!(refused(host.handle_control(control("10.0.0.3", original.clone())).await));
+        set_session_paused(&host, &session_id, false).await.unwrap();
+        while frames.try_recv().is_ok() {}
+        assert!(next_frame(&mut frames).await);
+
+        let current = hub.tokens.lock().unwrap().last().cloned().unwrap();
+        host.handle_control(control("10.0.0.3", current.clone())).await.unwrap();
+
+        // Kicked: the current token stops working and frames stop coming
+        kick_participant(&host, &session_id, "10.0.0.3").await.unwrap();
+        assert!(refused(host.handle_control(control("10.0.0.3", current)).await));
+        assert!(!host.get_session(&session_id).await.unwrap().participants.contains("10.0.0.3"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        while !matches!(frames.try_recv(), Err(broadcast::error::TryRecvError::Empty)) {}
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(matches!(frames.try_recv(), Err(broadcast::error::TryRecvError::Empty)));
+
+        let err = kick_participant(&host, &session_id, "10.0.0.3").await.unwrap_err();
+        assert_eq!(err.code, "peer_not_found");
+    }
+
+    #[derive(Default)]
+    struct RecordingInput(Mutex<Vec<InputAction>>);
+
+    impl InputBackend for RecordingInput {
+        fn inject(&self, action: InputAction) -> Result<(), anyhow::Error> {
+            self.0.lock().unwrap().push(action);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_viewers_control_the_host_only_while_let_in() {
+        let hub = Arc::new(InProcessHub::default());
+        let input = Arc::new(RecordingInput::default());
+        let host = Arc::new(
+            ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+                .with_transport("10.0.0.2".to_string(), Arc::new(HubTransport::new(&hub, "10.0.0.2")))
+                .with_input_backend(input.clone()),
+        );
+        hub.peers.lock().unwrap().insert("10.0.0.2".to_string(), host.clone());
+        let viewer = join_hub(&hub, "10.0.0.3");
+        let session_id = host.start_sharing(30, (64, 48), None, None, None).await.unwrap();
+        join_remote_session(&viewer, &FrameForwarders::default(), RecordingSink::default(), &session_id, None, 30)
+            .await
+            .unwrap();
+
+        let click = InputEvent::MouseButton { button: MouseButton::Left, pressed: true };
+        let err = send_input(&viewer, &session_id, click).await.unwrap_err();
+        assert_eq!(err.code, "remote_control_not_allowed");
+
+        set_remote_control(&host, &session_id, true).await.unwrap();
+        allow_control(&host, &session_id, "10.0.0.3", true).await.unwrap();
+        send_input(&viewer, &session_id, click).await.unwrap();
+        assert_eq!(input.0.lock().unwrap().len(), 1);
+
+        revoke_control(&host).await;
+        let err = send_input(&viewer, &session_id, click).await.unwrap_err();
+        assert_eq!(err.code, "remote_control_not_allowed");
+        assert_eq!(input.0.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_participants_are_listed_and_capped() {
+        let hub = Arc::new(InProcessHub::default());
+        let host = join_hub(&hub, "10.0.0.2");
+        let first = join_hub(&hub, "10.0.0.3");
+        let second = join_hub(&hub, "10.0.0.4");
+        let devices = vec![Device::new("Bob's Laptop".to_string(), "10.0.0.3".to_string(), 8080)];
+        let sink = RecordingSink::default();
+        tokio::spawn(forward_participant_changes(host.subscribe_participants(), sink.clone()));
+
+        let session_id = host.start_sharing(30, (64, 48), None, None, Some(1)).await.unwrap();
+        let forwarders = FrameForwarders::default();
+        join_remote_session(&first, &forwarders, RecordingSink::default(), &session_id, None, 30)
+            .await
+            .unwrap();
+        let err = join_remote_session(&second, &forwarders, RecordingSink::default(), &session_id, None, 30)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "session_full");
+
+        let listed = list_participants(&host, &devices, &session_id).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].peer_id, "10.0.0.3");
+        assert_eq!(listed[0].display_name, "Bob's Laptop");
+        assert!(listed[0].joined_at_ms > 0);
+
+        // Kicking makes room
+        kick_participant(&host, &session_id, "10.0.0.3").await.unwrap();
+        join_remote_session(&second, &forwarders, RecordingSink::default(), &session_id, None, 30)
+            .await
+            .unwrap();
+        let listed = list_participants(&host, &devices, &session_id).await.unwrap();
+        assert_eq!(listed.iter().map(|p| p.display_name.as_str()).collect::<Vec<_>>(), ["10.0.0.4"]);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let joined = sink.named(PARTICIPANT_JOINED_EVENT);
+        let left = sink.named(PARTICIPANT_LEFT_EVENT);
+        assert_eq!(joined.len(), 2);
+        assert_eq!(joined[0]["peer_id"], "10.0.0.3");
+        assert_eq!(joined[1]["participants"], 1);
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0]["participants"], 0);
+        assert!(list_participants(&host, &devices, "missing").await.is_err());
+    }
+}
@@ -0,0 +1,541 @@
+// Screen share commands
+//
+// The monitor picker lists displays with `list_monitors`, starts a share on
+// one of them and can move a running share with `switch_monitor`. Unknown
+// monitor ids are rejected with `monitor_not_found` instead of falling back
+// to the primary display. A monitor unplugged mid-share is the exception:
+// capture moves to the primary and `screen-monitor-lost` says so.
+// `update_screen_share_settings` changes a running share's frame rate and
+// size; frames say what size they are, so viewers follow along. Shares can
+// instead start from a quality preset (`low`, `balanced`, `high`,
+// `lossless`) and switch presets live, with explicit overrides winning over
+// the preset's own frame rate, scale and JPEG quality.
+// `set_capture_region` narrows a share to part of its monitor; regions off
+// the monitor are rejected with `invalid_capture_region`. The cursor is
+// drawn into frames unless `set_show_cursor` turns it off. `set_screen_audio`
+// sends system audio along with the frames, answering false where the
+// platform can't capture it and the share stays video-only.
+// `get_screen_share_stats` reports the frame rate and bandwidth a share
+// actually achieves over the last few seconds, frames dropped for slow
+// viewers and how far behind each viewer is. A stopped share's final stats
+// arrive as `screen-session-ended`. Capture that keeps failing, e.g. without
+// screen recording permission, sends viewers nothing and is reported as
+// `screen-session-status` with a `screen_capture_failed` error to show the
+// host; the same event says when capture works again.
+//
+// Frames are pushed to the webview as `screen-frame` events by one forwarder
+// task per subscribed session, throttled to the fps the viewer asked for.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use base64::Engine;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use desk_share_net::network::{
+    Frame, FrameHeader, MonitorLost, QualityPreset, SessionStats, SessionStatus, SessionStatusChange, SessionSummary, StreamOverrides,
+};
+use desk_share_net::platform::{CaptureRegion, MonitorInfo};
+use desk_share_net::{DeskShareError, ScreenShare};
+
+use crate::error::UiError;
+use crate::events::EventSink;
+
+pub const SCREEN_FRAME_EVENT: &str = "screen-frame";
+
+/// Emitted when a shared monitor goes away and capture falls back to the primary
+pub const MONITOR_LOST_EVENT: &str = "screen-monitor-lost";
+
+/// Emitted with a hosted session's final stats when it stops
+pub const SESSION_ENDED_EVENT: &str = "screen-session-ended";
+
+/// Emitted when a hosted session's capture starts failing or recovers
+pub const SESSION_STATUS_EVENT: &str = "screen-session-status";
+
+/// Capture size used when the monitor doesn't report one
+pub const DEFAULT_RESOLUTION: (u32, u32) = (1920, 1080);
+
+pub const MAX_FRAME_RATE: u32 = 60;
+
+/// Delivery rate when the viewer doesn't ask for one
+pub const DEFAULT_VIEWER_FPS: u32 = 15;
+
+/// Frame as delivered to the webview; `data` is the base64-encoded JPEG
+#[derive(Debug, Clone, Serialize)]
+pub struct ScreenFrame {
+    #[serde(flatten)]
+    pub header: FrameHeader,
+    pub data: String,
+}
+
+impl From<Frame> for ScreenFrame {
+    fn from(frame: Frame) -> Self {
+        Self {
+            header: FrameHeader::clone(&frame.header),
+            data: base64::engine::general_purpose::STANDARD.encode(&frame.data),
+        }
+    }
+}
+
+/// Running frame forwarders, one per subscribed session
+#[derive(Default)]
+pub struct FrameForwarders {
+    tasks: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl FrameForwarders {
+    /// Start forwarding a session's frames, replacing any earlier subscription
+    pub fn start<E: EventSink>(
+        &self,
+        session_id: &str,
+        rx: broadcast::Receiver<Frame>,
+        sink: E,
+        max_fps: u32,
+    ) {
+        let handle = tokio::spawn(forward_frames(rx, sink, max_fps));
+
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|_, task| !task.is_finished());
+        if let Some(previous) = tasks.insert(session_id.to_string(), handle) {
+            previous.abort();
+        }
+    }
+
+    /// Stop forwarding; returns false when the session wasn't subscribed
+    pub fn stop(&self, session_id: &str) -> bool {
+        match self.tasks.lock().unwrap().remove(session_id) {
+            Some(task) => {
+                task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_active(&self, session_id: &str) -> bool {
+        self.tasks
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .is_some_and(|task| !task.is_finished())
+    }
+}
+
+/// Relay frames as `screen-frame` events, dropping any that arrive faster
+/// than `max_fps`, until the session's channel closes
+pub async fn forward_frames<E: EventSink>(
+    mut rx: broadcast::Receiver<Frame>,
+    sink: E,
+    max_fps: u32,
+) {
+    let min_interval = Duration::from_secs(1) / max_fps.clamp(1, MAX_FRAME_RATE);
+    let mut last_emitted: Option<Instant> = None;
+
+    loop {
+        match rx.recv().await {
+            Ok(frame) => {
+                let now = Instant::now();
+                if matches!(last_emitted, Some(last) if now.duration_since(last) < min_interval) {
+                    continue;
+                }
+                last_emitted = Some(now);
+                sink.emit_event(SCREEN_FRAME_EVENT, ScreenFrame::from(frame));
+            }
+            // Skipped frames are stale anyway
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Relay lost monitors until the sending side is dropped
+pub async fn forward_monitor_lost<E: EventSink>(mut rx: broadcast::Receiver<MonitorLost>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(lost) => sink.emit_event(MONITOR_LOST_EVENT, lost),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Monitor lost forwarder lagged, skipped {} events", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// A hosted session's status as sent to the webview, with the error to
+/// show while capture is failing
+#[derive(Debug, Clone, Serialize)]
+pub struct ScreenSessionStatus {
+    pub session_id: String,
+    #[serde(flatten)]
+    pub status: SessionStatus,
+    pub error: Option<UiError>,
+}
+
+impl From<SessionStatusChange> for ScreenSessionStatus {
+    fn from(change: SessionStatusChange) -> Self {
+        let error = match &change.status {
+            SessionStatus::Active => None,
+            SessionStatus::Degraded { reason } | SessionStatus::Failed { reason } => {
+                Some(DeskShareError::ScreenCaptureFailed(reason.clone()).into())
+            }
+        };
+        Self { session_id: change.session_id, status: change.status, error }
+    }
+}
+
+/// Relay hosted sessions' status changes until the sending side is dropped
+pub async fn forward_session_status<E: EventSink>(mut rx: broadcast::Receiver<SessionStatusChange>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(change) => sink.emit_event(SESSION_STATUS_EVENT, ScreenSessionStatus::from(change)),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Session status forwarder lagged, skipped {} changes", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Relay ended sessions' summaries until the sending side is dropped
+pub async fn forward_session_summaries<E: EventSink>(mut rx: broadcast::Receiver<SessionSummary>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(summary) => sink.emit_event(SESSION_ENDED_EVENT, summary),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Session summary forwarder lagged, skipped {} summaries", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+pub fn list_monitors(screen_share: &ScreenShare) -> Result<Vec<MonitorInfo>, UiError> {
+    Ok(screen_share.list_monitors()?)
+}
+
+/// Start sharing `monitor_id` (primary when None) at its native resolution,
+/// turning viewers away past `max_participants`
+pub async fn start_share(
+    screen_share: &ScreenShare,
+    frame_rate: u32,
+    monitor_id: Option<u32>,
+    quality: Option<u8>,
+    max_participants: Option<usize>,
+) -> Result<String, UiError> {
+    check_frame_rate(frame_rate)?;
+    let resolution = native_resolution(screen_share, monitor_id)?;
+
+    Ok(screen_share
+        .start_sharing(frame_rate, resolution, monitor_id, quality, max_participants)
+        .await?)
+}
+
+/// Start sharing `monitor_id` (primary when None) at `preset`, with any of
+/// `overrides` in place of its settings
+pub async fn start_preset_share(
+    screen_share: &ScreenShare,
+    preset: QualityPreset,
+    overrides: StreamOverrides,
+    monitor_id: Option<u32>,
+    max_participants: Option<usize>,
+) -> Result<String, UiError> {
+    if let Some(frame_rate) = overrides.frame_rate {
+        check_frame_rate(frame_rate)?;
+    }
+    let resolution = native_resolution(screen_share, monitor_id)?;
+
+    Ok(screen_share
+        .start_sharing_preset(preset, overrides, resolution, monitor_id, max_participants)
+        .await?)
+}
+
+fn check_frame_rate(frame_rate: u32) -> Result<(), UiError> {
+    if frame_rate == 0 || frame_rate > MAX_FRAME_RATE {
+        return Err(DeskShareError::InvalidConfig(format!(
+            "frame rate must be between 1 and {}",
+            MAX_FRAME_RATE
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// The size of `monitor_id`, or of the primary when None
+fn native_resolution(screen_share: &ScreenShare, monitor_id: Option<u32>) -> Result<(u32, u32), UiError> {
+    let monitors = screen_share.list_monitors()?;
+    let monitor = match monitor_id {
+        Some(id) => Some(
+            monitors
+                .iter()
+                .find(|m| m.id == id)
+                .ok_or(DeskShareError::MonitorNotFound(id))?,
+        ),
+        None => monitors.iter().find(|m| m.is_primary).or(monitors.first()),
+    };
+    Ok(monitor
+        .map(|m| (m.width, m.height))
+        .unwrap_or(DEFAULT_RESOLUTION))
+}
+
+pub async fn switch_monitor(
+    screen_share: &ScreenShare,
+    session_id: &str,
+    monitor_id: u32,
+) -> Result<(), UiError> {
+    Ok(screen_share.switch_monitor(session_id, monitor_id).await?)
+}
+
+/// Change a running share's frame rate and capture size
+pub async fn update_settings(
+    screen_share: &ScreenShare,
+    session_id: &str,
+    frame_rate: u32,
+    resolution: (u32, u32),
+) -> Result<(), UiError> {
+    check_frame_rate(frame_rate)?;
+    Ok(screen_share.update_session_settings(session_id, frame_rate, resolution).await?)
+}
+
+/// Move a running share to `preset`, with any of `overrides` in place of
+/// its settings
+pub async fn set_quality_preset(
+    screen_share: &ScreenShare,
+    session_id: &str,
+    preset: QualityPreset,
+    overrides: StreamOverrides,
+) -> Result<(), UiError> {
+    if let Some(frame_rate) = overrides.frame_rate {
+        check_frame_rate(frame_rate)?;
+    }
+    Ok(screen_share.set_quality_preset(session_id, preset, overrides).await?)
+}
+
+/// Share only `region` of the session's monitor, or all of it when None
+pub async fn set_capture_region(
+    screen_share: &ScreenShare,
+    session_id: &str,
+    region: Option<CaptureRegion>,
+) -> Result<(), UiError> {
+    Ok(screen_share.set_capture_region(session_id, region).await?)
+}
+
+pub async fn set_show_cursor(screen_share: &ScreenShare, session_id: &str, show: bool) -> Result<(), UiError> {
+    Ok(screen_share.set_show_cursor(session_id, show).await?)
+}
+
+/// Whether audio is now going out
+pub async fn set_audio(screen_share: &ScreenShare, session_id: &str, enabled: bool) -> Result<bool, UiError> {
+    Ok(screen_share.set_audio(session_id, enabled).await?)
+}
+
+/// Latest frame of a session, for thumbnails
+pub async fn latest_frame(screen_share: &ScreenShare, session_id: &str) -> Option<ScreenFrame> {
+    screen_share.get_latest_frame(session_id).await.map(ScreenFrame::from)
+}
+
+pub async fn session_stats(screen_share: &ScreenShare, session_id: &str) -> Result<SessionStats, UiError> {
+    screen_share
+        .session_stats(session_id)
+        .await
+        .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use desk_share_net::network::CaptureConfig;
+    use desk_share_net::platform::fallback::FallbackCapture;
+    use crate::events::tests::RecordingSink;
+
+    fn fallback_share() -> ScreenShare {
+        ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+    }
+
+    #[tokio::test]
+    async fn test_monitor_selection_round_trip() {
+        let screen_share = fallback_share();
+
+        let monitors = list_monitors(&screen_share).unwrap();
+        assert_eq!(monitors.len(), 2);
+        assert_eq!(monitors.iter().filter(|m| m.is_primary).count(), 1);
+        let secondary = monitors.iter().find(|m| !m.is_primary).unwrap();
+
+        let session_id = start_share(&screen_share, 15, Some(secondary.id), Some(60), None)
+            .await
+            .unwrap();
+        let session = screen_share.get_session(&session_id).await.unwrap();
+        assert_eq!(session.monitor_id, Some(secondary.id));
+        assert_eq!(session.resolution, (secondary.width, secondary.height));
+        assert_eq!(session.quality, 60);
+
+        let primary = monitors.iter().find(|m| m.is_primary).unwrap();
+        switch_monitor(&screen_share, &session_id, primary.id).await.unwrap();
+        let session = screen_share.get_session(&session_id).await.unwrap();
+        assert_eq!(session.monitor_id, Some(primary.id));
+        assert_eq!(session.resolution, (primary.width, primary.height));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_monitor_is_rejected() {
+        let screen_share = fallback_share();
+
+        let err = start_share(&screen_share, 15, Some(99), None, None).await.unwrap_err();
+        assert_eq!(err.code, "monitor_not_found");
+
+        let session_id = start_share(&screen_share, 15, None, None, None).await.unwrap();
+        let err = switch_monitor(&screen_share, &session_id, 99).await.unwrap_err();
+        assert_eq!(err.code, "monitor_not_found");
+
+        // The running share keeps its original monitor
+        let session = screen_share.get_session(&session_id).await.unwrap();
+        assert_eq!(session.monitor_id, None);
+
+        let err = switch_monitor(&screen_share, "no-such-session", 1).await.unwrap_err();
+        assert_eq!(err.code, "session_not_found");
+
+        let off_screen = CaptureRegion { x: 1900, y: 0, width: 100, height: 100 };
+        let err = set_capture_region(&screen_share, &session_id, Some(off_screen)).await.unwrap_err();
+        assert_eq!(err.code, "invalid_capture_region");
+        assert_eq!(screen_share.get_session(&session_id).await.unwrap().region, None);
+    }
+
+    #[tokio::test]
+    async fn test_frames_stop_after_unsubscribe() {
+        // The fallback pattern never changes, so only keyframes carry frames
+        let screen_share = fallback_share().with_capture_config(CaptureConfig { keyframe_interval_ms: 20, ..CaptureConfig::default() });
+        let forwarders = FrameForwarders::default();
+        let sink = RecordingSink::default();
+
+        let session_id = screen_share
+            .start_sharing(30, (64, 48), None, None, None)
+            .await
+            .unwrap();
+        let rx = screen_share.subscribe_frames(&session_id).await.unwrap();
+        forwarders.start(&session_id, rx, sink.clone(), 30);
+        assert!(forwarders.is_active(&session_id));
+
+        for _ in 0..50 {
+            if sink.named(SCREEN_FRAME_EVENT).len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let frames = sink.named(SCREEN_FRAME_EVENT);
+        assert!(frames.len() >= 2);
+        assert_eq!(frames[0]["session_id"], session_id.as_str());
+        assert_eq!(frames[0]["width"], 64);
+        assert!(frames[1]["sequence"].as_u64() > frames[0]["sequence"].as_u64());
+        assert!(!frames[0]["data"].as_str().unwrap().is_empty());
+
+        assert!(forwarders.stop(&session_id));
+        assert!(!forwarders.is_active(&session_id));
+        assert!(!forwarders.stop(&session_id));
+
+        // Capture keeps running, but nothing reaches the webview any more
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let delivered = sink.named(SCREEN_FRAME_EVENT).len();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(sink.named(SCREEN_FRAME_EVENT).len(), delivered);
+
+        // The thumbnail path still sees the newest frame
+        let thumbnail = latest_frame(&screen_share, &session_id).await.unwrap();
+        assert!(thumbnail.header.sequence as usize > delivered);
+    }
+
+    #[tokio::test]
+    async fn test_frame_subscription_is_throttled() {
+        let screen_share = fallback_share();
+        let forwarders = FrameForwarders::default();
+        let sink = RecordingSink::default();
+
+        let session_id = screen_share
+            .start_sharing(30, (64, 48), None, None, None)
+            .await
+            .unwrap();
+        let rx = screen_share.subscribe_frames(&session_id).await.unwrap();
+        forwarders.start(&session_id, rx, sink.clone(), 2);
+
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        forwarders.stop(&session_id);
+
+        // ~18 frames captured, at most two per second delivered
+        let delivered = sink.named(SCREEN_FRAME_EVENT).len();
+        assert!((1..=2).contains(&delivered), "delivered {} frames", delivered);
+
+        let err = screen_share.subscribe_frames("no-such-session").await.unwrap_err();
+        assert_eq!(UiError::from(err).code, "session_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_stats_while_sharing_and_a_summary_when_stopped() {
+        let screen_share = fallback_share();
+        let sink = RecordingSink::default();
+        let forwarder = tokio::spawn(forward_session_summaries(screen_share.subscribe_session_summaries(), sink.clone()));
+        let session_id = screen_share.start_sharing(30, (64, 48), None, None, None).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let stats = session_stats(&screen_share, &session_id).await.unwrap();
+        assert!(stats.frames_per_second > 0.0);
+        assert_eq!(stats.dropped_frames, 0);
+
+        screen_share.stop_sharing(&session_id).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let ended = sink.named(SESSION_ENDED_EVENT);
+        assert_eq!(ended.len(), 1);
+        assert_eq!(ended[0]["stats"]["session_id"], session_id.as_str());
+        assert!(ended[0]["duration_ms"].as_u64().unwrap() >= 300);
+        let err = session_stats(&screen_share, &session_id).await.unwrap_err();
+        assert_eq!(err.code, "session_not_found");
+        forwarder.abort();
+    }
+
+    #[tokio::test]
+    async fn test_a_share_started_from_a_preset_reports_it() {
+        let screen_share = fallback_share();
+        let overrides = StreamOverrides { frame_rate: Some(20), ..StreamOverrides::default() };
+        let session_id = start_preset_share(&screen_share, QualityPreset::Low, overrides, Some(2), None).await.unwrap();
+        let stats = serde_json::to_value(session_stats(&screen_share, &session_id).await.unwrap()).unwrap();
+        assert_eq!(stats["preset"], "low");
+        assert_eq!(stats["stream"], serde_json::json!({ "quality": 50, "frame_rate": 20, "scale_percent": 50 }));
+
+        set_quality_preset(&screen_share, &session_id, QualityPreset::Lossless, StreamOverrides::default()).await.unwrap();
+        let stats = session_stats(&screen_share, &session_id).await.unwrap();
+        assert_eq!(stats.preset, Some(QualityPreset::Lossless));
+
+        let too_fast = StreamOverrides { frame_rate: Some(MAX_FRAME_RATE + 1), ..StreamOverrides::default() };
+        let err = set_quality_preset(&screen_share, &session_id, QualityPreset::High, too_fast).await.unwrap_err();
+        assert_eq!(err.code, "invalid_config");
+        let err = start_preset_share(&screen_share, QualityPreset::High, too_fast, None, None).await.unwrap_err();
+        assert_eq!(err.code, "invalid_config");
+        let err = set_quality_preset(&screen_share, "no-such-session", QualityPreset::Low, StreamOverrides::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "session_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_failing_capture_reaches_the_webview_as_an_error() {
+        let sink = RecordingSink::default();
+        let (tx, rx) = broadcast::channel(8);
+        let forwarder = tokio::spawn(forward_session_status(rx, sink.clone()));
+        let reason = "screen recording permission denied".to_string();
+        tx.send(SessionStatusChange { session_id: "s1".to_string(), status: SessionStatus::Failed { reason } }).unwrap();
+        tx.send(SessionStatusChange { session_id: "s1".to_string(), status: SessionStatus::Active }).unwrap();
+        drop(tx);
+        forwarder.await.unwrap();
+
+        let statuses = sink.named(SESSION_STATUS_EVENT);
+        assert_eq!(statuses.len(), 2);
+        assert_eq!(statuses[0]["session_id"], "s1");
+        assert_eq!(statuses[0]["state"], "failed");
+        assert_eq!(statuses[0]["reason"], "screen recording permission denied");
+        assert_eq!(statuses[0]["error"]["code"], "screen_capture_failed");
+        assert!(statuses[0]["error"]["message"].as_str().unwrap().contains("screen recording permission"));
+        assert_eq!(statuses[1]["state"], "active");
+        assert!(statuses[1]["error"].is_null());
+    }
+}
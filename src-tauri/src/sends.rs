@@ -0,0 +1,160 @@
+// Sending a file straight to picked devices
+//
+// A single device gets the offer and fetches the file in the background. For
+// several, the file is shared once and offered to every device at the same time.
+// While it's on its way the combined progress goes out as
+// `multi-send-progress` events; a device that can't be reached shows up as
+// failed in the final record without stopping the others.
+
+use std::path::Path;
+use std::time::Duration;
+
+use desk_share_net::network::MultiSendProgress;
+use desk_share_net::{DeskShareError, Device, FileTransfer};
+
+use crate::error::UiError;
+use crate::events::EventSink;
+use crate::shares::{list_shared_files, SHARES_CHANGED_EVENT};
+
+pub const MULTI_SEND_PROGRESS_EVENT: &str = "multi-send-progress";
+
+/// How often progress goes out while a send is under way
+pub const MULTI_SEND_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Offer `path` to one of the known `devices`, by name or address; returns
+/// the file's hash once the offer has arrived
+pub async fn send_file_to_device<E: EventSink>(
+    file_transfer: &FileTransfer,
+    sink: &E,
+    devices: &[Device],
+    device: &str,
+    path: &str,
+) -> Result<String, UiError> {
+    let device_ip = crate::chat::resolve_peer(devices, device)?;
+    let file_hash = file_transfer.send_offer(&device_ip, path).await?;
+
+    sink.emit_event(SHARES_CHANGED_EVENT, list_shared_files(file_transfer));
+    Ok(file_hash)
+}
+
+/// Send `path` to every device in `device_ips`; returns how each one went
+pub async fn send_file_to_devices<E: EventSink>(
+    file_transfer: &FileTransfer,
+    sink: &E,
+    device_ips: Vec<String>,
+    path: &str,
+) -> Result<MultiSendProgress, UiError> {
+    if device_ips.is_empty() {
+        return Err(DeskShareError::InvalidConfig("no devices to send to".to_string()).into());
+    }
+    let file_hash = file_transfer.share_file(Path::new(path)).await?;
+    sink.emit_event(SHARES_CHANGED_EVENT, list_shared_files(file_transfer));
+
+    let send = file_transfer.send_share_to_devices(device_ips, &file_hash);
+    tokio::pin!(send);
+    let mut ticker = tokio::time::interval(MULTI_SEND_PROGRESS_INTERVAL);
+    let sent = loop {
+        tokio::select! {
+            sent = &mut send => break sent?,
+            _ = ticker.tick() => {
+                if let Some(progress) = file_transfer.get_multi_send_progress(&file_hash) {
+                    sink.emit_event(MULTI_SEND_PROGRESS_EVENT, progress);
+                }
+            }
+        }
+    };
+
+    sink.emit_event(MULTI_SEND_PROGRESS_EVENT, sent.clone());
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use desk_share_net::network::LanChunkTransport;
+    use desk_share_net::p2p::TcpTransport;
+    use desk_share_net::security::{DeviceIdentity, SecurityConfig};
+    use desk_share_net::TransferStatus;
+    use crate::events::tests::RecordingSink;
+
+    #[tokio::test]
+    async fn test_offer_reaches_the_chosen_device() {
+        let dir = std::env::temp_dir().join(format!("dsn-send-one-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("deck.pdf"), vec![7u8; 4096]).unwrap();
+        let file_transfer = FileTransfer::new().await.with_identity(Arc::new(DeviceIdentity::generate()));
+        file_transfer.set_shareable_roots(vec![dir.clone()]);
+        let sink = RecordingSink::default();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let devices = vec![Device::new("Bob's laptop".to_string(), listener.local_addr().unwrap().to_string(), 0)];
+        let receiver = FileTransfer::new().await.with_lan_transport(Arc::new(LanChunkTransport::new()));
+        let server = {
+            let receiver = receiver.clone();
+            tokio::spawn(async move {
+                let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+                let (stream, addr) = transport.accept(&listener).await.unwrap();
+                receiver.accept_transfer(stream, addr.ip().to_string()).await.unwrap()
+            })
+        };
+
+        let file_hash = send_file_to_device(&file_transfer, &sink, &devices, "Bob's laptop", &dir.join("deck.pdf").to_string_lossy())
+            .await
+            .unwrap();
+        server.await.unwrap();
+        let offers = receiver.get_pending_offers();
+        assert_eq!(offers.len(), 1);
+        assert_eq!((offers[0].file.name.as_str(), offers[0].file.hash.as_str()), ("deck.pdf", file_hash.as_str()));
+        assert_eq!(sink.named(SHARES_CHANGED_EVENT).len(), 1);
+
+        // Unknown and unreachable devices are errors, not quiet successes
+        let err = send_file_to_device(&file_transfer, &sink, &devices, "Carol's phone", &dir.join("deck.pdf").to_string_lossy())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "peer_not_found");
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+        let devices = vec![Device::new("Bob's laptop".to_string(), closed, 0)];
+        let err = send_file_to_device(&file_transfer, &sink, &devices, "Bob's laptop", &dir.join("deck.pdf").to_string_lossy())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "peer_connection_failed");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_devices_are_reported_not_raised() {
+        let dir = std::env::temp_dir().join(format!("dsn-sends-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("deck.pdf"), vec![7u8; 4096]).unwrap();
+        let file_transfer = FileTransfer::new().await.with_identity(Arc::new(DeviceIdentity::generate()));
+        let sink = RecordingSink::default();
+
+        let mut devices = Vec::new();
+        for _ in 0..2 {
+            let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            devices.push(closed.local_addr().unwrap().to_string());
+        }
+        let sent = send_file_to_devices(&file_transfer, &sink, devices, &dir.join("deck.pdf").to_string_lossy())
+            .await
+            .unwrap();
+
+        assert_eq!(sent.destinations.len(), 2);
+        for destination in &sent.destinations {
+            assert_eq!(destination.progress.status, TransferStatus::Failed);
+            assert_eq!(destination.progress.error_kind.as_deref(), Some("peer_connection_failed"));
+            assert_eq!(destination.progress.error_message.as_deref(), Some("Failed to connect to peer. They may be offline."));
+        }
+        assert_eq!(sent.aggregate.status, TransferStatus::Failed);
+        assert_eq!(sink.named(SHARES_CHANGED_EVENT).len(), 1);
+        let progress = sink.named(MULTI_SEND_PROGRESS_EVENT);
+        assert_eq!(progress.last(), Some(&serde_json::to_value(&sent).unwrap()));
+        assert_eq!(progress.last().unwrap()["aggregate"]["status"], "failed");
+
+        let err = send_file_to_devices(&file_transfer, &sink, Vec::new(), &dir.join("deck.pdf").to_string_lossy())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "invalid_config");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,129 @@
+// Files offered to the network
+//
+// Every change to the shared set is followed by a `shares-changed` event
+// carrying the full listing, so the panel never has to re-query.
+
+use std::path::Path;
+use std::time::Duration;
+
+use desk_share_net::network::{IgnoreRules, SharedDirectory, SharedFileSummary};
+use desk_share_net::FileTransfer;
+
+use crate::error::UiError;
+use crate::events::EventSink;
+
+pub const SHARES_CHANGED_EVENT: &str = "shares-changed";
+
+pub fn list_shared_files(file_transfer: &FileTransfer) -> Vec<SharedFileSummary> {
+    file_transfer.get_shared_files()
+}
+
+/// Offer a file to every peer, for `ttl_seconds` if given; returns its hash
+pub async fn share_file<E: EventSink>(
+    file_transfer: &FileTransfer,
+    sink: &E,
+    path: &str,
+    ttl_seconds: Option<u64>,
+) -> Result<String, UiError> {
+    let ttl = ttl_seconds.map(Duration::from_secs);
+    let file_hash = file_transfer.share_file_with_ttl(Path::new(path), ttl).await?;
+
+    sink.emit_event(SHARES_CHANGED_EVENT, list_shared_files(file_transfer));
+    Ok(file_hash)
+}
+
+/// Offer a folder and everything in it that `rules` don't leave out
+pub async fn share_directory<E: EventSink>(
+    file_transfer: &FileTransfer,
+    sink: &E,
+    path: &str,
+    rules: IgnoreRules,
+) -> Result<SharedDirectory, UiError> {
+    let shared = file_transfer.share_directory_with_rules(Path::new(path), &rules).await?;
+
+    sink.emit_event(SHARES_CHANGED_EVENT, list_shared_files(file_transfer));
+    Ok(shared)
+}
+
+/// Stop offering a file; peers partway through it finish unless `force` is set
+pub async fn unshare_file<E: EventSink>(
+    file_transfer: &FileTransfer,
+    sink: &E,
+    file_hash: &str,
+    force: bool,
+) -> Result<(), UiError> {
+    file_transfer.unshare_file(file_hash, force).await?;
+
+    sink.emit_event(SHARES_CHANGED_EVENT, list_shared_files(file_transfer));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::tests::RecordingSink;
+
+    #[tokio::test]
+    async fn test_share_list_and_unshare() {
+        let dir = std::env::temp_dir().join(format!("dsn-shares-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), b"meeting notes").unwrap();
+        std::fs::write(dir.join("build.zip"), vec![1u8; 2048]).unwrap();
+
+        let file_transfer = FileTransfer::new().await;
+        let sink = RecordingSink::default();
+
+        let notes = share_file(&file_transfer, &sink, &dir.join("notes.txt").to_string_lossy(), None)
+            .await
+            .unwrap();
+        let build = share_file(&file_transfer, &sink, &dir.join("build.zip").to_string_lossy(), Some(600))
+            .await
+            .unwrap();
+
+        let listed = list_shared_files(&file_transfer);
+        assert_eq!(
+            listed.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(),
+            ["build.zip", "notes.txt"]
+        );
+        assert_eq!(listed[0].size, 2048);
+        assert_eq!(listed[0].bytes_served, 0);
+        assert!(listed[0].expires_in_secs.is_some_and(|left| (590..=600).contains(&left)));
+        assert_eq!(listed[1].expires_in_secs, None);
+
+        unshare_file(&file_transfer, &sink, &build, false).await.unwrap();
+        let listed = list_shared_files(&file_transfer);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].hash, notes);
+
+        // One event per mutation, the last one matching the listing
+        let events = sink.named(SHARES_CHANGED_EVENT);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2], serde_json::to_value(&listed).unwrap());
+
+        let err = unshare_file(&file_transfer, &sink, &build, false).await.unwrap_err();
+        assert_eq!(err.code, "share_not_found");
+        assert_eq!(sink.named(SHARES_CHANGED_EVENT).len(), 3);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_share_outside_whitelist_is_rejected() {
+        let dir = std::env::temp_dir().join(format!("dsn-shares-wl-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("public")).unwrap();
+        std::fs::write(dir.join("secret.txt"), b"secret").unwrap();
+
+        let file_transfer = FileTransfer::new().await;
+        file_transfer.set_shareable_roots(vec![dir.join("public")]);
+        let sink = RecordingSink::default();
+
+        let err = share_file(&file_transfer, &sink, &dir.join("secret.txt").to_string_lossy(), None)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "path_not_allowed");
+        assert!(list_shared_files(&file_transfer).is_empty());
+        assert!(sink.named(SHARES_CHANGED_EVENT).is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,104 @@
+// Text snippets sent between devices
+//
+// Short texts arrive whole as `text-received` events instead of files, and
+// stay listed until the panel dismisses them. Longer ones download like any
+// other file.
+
+use tokio::sync::broadcast;
+
+use desk_share_net::network::ReceivedText;
+use desk_share_net::FileTransfer;
+
+use crate::error::UiError;
+use crate::events::EventSink;
+use crate::shares::{list_shared_files, SHARES_CHANGED_EVENT};
+
+pub const TEXT_RECEIVED_EVENT: &str = "text-received";
+
+/// Longest title made up from a snippet's first line
+const TITLE_LEN: usize = 40;
+
+/// Offer a snippet to every peer; returns its hash. Untitled snippets are
+/// named after their first line.
+pub async fn send_text<E: EventSink>(
+    file_transfer: &FileTransfer,
+    sink: &E,
+    content: String,
+    title: Option<String>,
+) -> Result<String, UiError> {
+    let title = title
+        .filter(|title| !title.trim().is_empty())
+        .unwrap_or_else(|| default_title(&content));
+    let file_hash = file_transfer.share_text(content, title).await?;
+
+    sink.emit_event(SHARES_CHANGED_EVENT, list_shared_files(file_transfer));
+    Ok(file_hash)
+}
+
+fn default_title(content: &str) -> String {
+    let first_line = content.lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or("Text");
+    first_line.chars().take(TITLE_LEN).collect()
+}
+
+pub fn list_received_texts(file_transfer: &FileTransfer) -> Vec<ReceivedText> {
+    file_transfer.received_texts()
+}
+
+pub fn dismiss_received_text(file_transfer: &FileTransfer, file_hash: &str) -> Result<(), UiError> {
+    Ok(file_transfer.dismiss_text(file_hash)?)
+}
+
+/// Relay short texts as they arrive until the sending side is dropped
+pub async fn forward_received_texts<E: EventSink>(mut rx: broadcast::Receiver<ReceivedText>, sink: E) {
+    loop {
+        match rx.recv().await {
+            Ok(text) => sink.emit_event(TEXT_RECEIVED_EVENT, text),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Text forwarder lagged, skipped {} texts", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use desk_share_net::network::FileTransferMessage;
+    use crate::events::tests::RecordingSink;
+
+    #[tokio::test]
+    async fn test_snippet_reaches_the_panel() {
+        let sender = FileTransfer::new().await;
+        let receiver = FileTransfer::new().await;
+        let sink = RecordingSink::default();
+        let forwarder = tokio::spawn(forward_received_texts(receiver.subscribe_texts(), sink.clone()));
+
+        let content = "\n  Ünïcödé notes ✓ for the meeting that runs far too long\nsecond line".to_string();
+        let file_hash = send_text(&sender, &sink, content.clone(), None).await.unwrap();
+        assert_eq!(sink.named(SHARES_CHANGED_EVENT).len(), 1);
+        let file = sender.get_shared_file(&file_hash).unwrap();
+        assert_eq!(file.name, "Ünïcödé notes ✓ for the meeting that run");
+
+        let offer_id = receiver.receive_offer("peer-alice".to_string(), "Alice's MacBook".to_string(), file).await;
+        let dir = std::env::temp_dir().join(format!("dsn-texts-{}", std::process::id()));
+        receiver.accept_offer(&offer_id, &dir).await.unwrap();
+        let request = FileTransferMessage::ChunkRequest { file_hash: file_hash.clone(), chunk_index: 0 };
+        let reply = sender.handle_incoming_message("peer-bob", request.encode().unwrap()).await.unwrap().unwrap();
+        receiver.handle_incoming_message("peer-alice", reply).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let received = list_received_texts(&receiver);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].content, content);
+        assert_eq!(sink.named(TEXT_RECEIVED_EVENT), vec![serde_json::to_value(&received[0]).unwrap()]);
+        assert!(!dir.exists());
+
+        dismiss_received_text(&receiver, &file_hash).unwrap();
+        assert!(list_received_texts(&receiver).is_empty());
+        let err = dismiss_received_text(&receiver, &file_hash).unwrap_err();
+        assert_eq!(err.code, "share_not_found");
+        forwarder.abort();
+    }
+}
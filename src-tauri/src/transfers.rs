@@ -0,0 +1,214 @@
+// Transfer control commands: pause, resume and cancel
+//
+// The command handlers go through `TransferControl` so the argument plumbing
+// and error mapping can be tested without a running FileTransfer.
+
+use async_trait::async_trait;
+use serde::Serialize;
+
+use desk_share_net::{FileTransfer, TransferStatus};
+
+use crate::error::UiError;
+use crate::events::EventSink;
+
+pub const TRANSFER_STATE_CHANGED_EVENT: &str = "transfer-state-changed";
+/// Transfer history records returned when the UI doesn't ask for a page size
+pub const TRANSFER_HISTORY_PAGE: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferAction {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferStateChanged {
+    pub transfer_id: String,
+    pub status: TransferStatus,
+}
+
+#[async_trait]
+pub trait TransferControl: Send + Sync {
+    async fn pause_transfer(&self, transfer_id: &str) -> Result<TransferStatus, anyhow::Error>;
+    async fn resume_transfer(&self, transfer_id: &str) -> Result<TransferStatus, anyhow::Error>;
+    async fn cancel_transfer(&self, transfer_id: &str) -> Result<TransferStatus, anyhow::Error>;
+}
+
+#[async_trait]
+impl TransferControl for FileTransfer {
+    async fn pause_transfer(&self, transfer_id: &str) -> Result<TransferStatus, anyhow::Error> {
+        FileTransfer::pause_transfer(self, transfer_id).await
+    }
+
+    async fn resume_transfer(&self, transfer_id: &str) -> Result<TransferStatus, anyhow::Error> {
+        FileTransfer::resume_transfer(self, transfer_id).await
+    }
+
+    async fn cancel_transfer(&self, transfer_id: &str) -> Result<TransferStatus, anyhow::Error> {
+        FileTransfer::cancel_transfer(self, transfer_id).await
+    }
+}
+
+/// Run a control action and tell every window about the new status
+pub async fn apply_transfer_action<C, E>(
+    control: &C,
+    sink: &E,
+    transfer_id: &str,
+    action: TransferAction,
+) -> Result<TransferStatus, UiError>
+where
+    C: TransferControl + ?Sized,
+    E: EventSink,
+{
+    let status = match action {
+        TransferAction::Pause => control.pause_transfer(transfer_id).await,
+        TransferAction::Resume => control.resume_transfer(transfer_id).await,
+        TransferAction::Cancel => control.cancel_transfer(transfer_id).await,
+    }
+    .map_err(UiError::from)?;
+
+    tracing::info!("Transfer {} is now {:?}", transfer_id, status);
+    sink.emit_event(
+        TRANSFER_STATE_CHANGED_EVENT,
+        TransferStateChanged {
+            transfer_id: transfer_id.to_string(),
+            status,
+        },
+    );
+
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use desk_share_net::{AppState, DeskShareError};
+    use crate::events::tests::RecordingSink;
+
+    /// Records every call and answers with a canned result
+    struct MockControl {
+        calls: Mutex<Vec<(TransferAction, String)>>,
+        result: fn() -> Result<TransferStatus, anyhow::Error>,
+    }
+
+    impl MockControl {
+        fn new(result: fn() -> Result<TransferStatus, anyhow::Error>) -> Self {
+            Self {
+                calls: Mutex::new(Vec::new()),
+                result,
+            }
+        }
+
+        fn record(&self, action: TransferAction, id: &str) -> Result<TransferStatus, anyhow::Error> {
+            self.calls.lock().unwrap().push((action, id.to_string()));
+            (self.result)()
+        }
+    }
+
+    #[async_trait]
+    impl TransferControl for MockControl {
+        async fn pause_transfer(&self, id: &str) -> Result<TransferStatus, anyhow::Error> {
+            self.record(TransferAction::Pause, id)
+        }
+
+        async fn resume_transfer(&self, id: &str) -> Result<TransferStatus, anyhow::Error> {
+            self.record(TransferAction::Resume, id)
+        }
+
+        async fn cancel_transfer(&self, id: &str) -> Result<TransferStatus, anyhow::Error> {
+            self.record(TransferAction::Cancel, id)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_actions_reach_the_matching_method() {
+        let control = MockControl::new(|| Ok(TransferStatus::Paused));
+        let sink = RecordingSink::default();
+
+        for action in [TransferAction::Pause, TransferAction::Resume, TransferAction::Cancel] {
+            apply_transfer_action(&control, &sink, "hash-1", action).await.unwrap();
+        }
+
+        let calls = control.calls.lock().unwrap().clone();
+        assert_eq!(
+            calls,
+            vec![
+                (TransferAction::Pause, "hash-1".to_string()),
+                (TransferAction::Resume, "hash-1".to_string()),
+                (TransferAction::Cancel, "hash-1".to_string()),
+            ]
+        );
+
+        let events = sink.named(TRANSFER_STATE_CHANGED_EVENT);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["transfer_id"], "hash-1");
+        assert_eq!(events[0]["status"], "paused");
+    }
+
+    #[tokio::test]
+    async fn test_errors_map_to_ui_codes_without_events() {
+        let sink = RecordingSink::default();
+
+        let unknown = MockControl::new(|| {
+            Err(DeskShareError::TransferNotFound("missing".to_string()).into())
+        });
+        let err = apply_transfer_action(&unknown, &sink, "missing", TransferAction::Cancel)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "transfer_not_found");
+
+        let illegal = MockControl::new(|| {
+            Err(DeskShareError::InvalidTransition("Completed -> Paused".to_string()).into())
+        });
+        let err = apply_transfer_action(&illegal, &sink, "done", TransferAction::Pause)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "invalid_transition");
+
+        let opaque = MockControl::new(|| Err(anyhow::anyhow!("disk on fire")));
+        let err = apply_transfer_action(&opaque, &sink, "x", TransferAction::Resume)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "internal");
+
+        assert!(sink.named(TRANSFER_STATE_CHANGED_EVENT).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pause_resume_cancel_against_real_transfer() {
+        let app_state = AppState::new().await;
+        let sink = RecordingSink::default();
+
+        let dir = std::env::temp_dir().join(format!("dsn-control-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let source = dir.join("slides.pdf");
+        tokio::fs::write(&source, vec![1u8; 2 * 1024 * 1024]).await.unwrap();
+
+        let file_transfer = app_state.file_transfer.lock().await;
+        let hash = file_transfer.share_file(&source).await.unwrap();
+        // No peers are known yet, so the download stays in progress
+        file_transfer.download_file(&hash, &dir.join("slides-copy.pdf")).await.unwrap();
+
+        let status = apply_transfer_action(&*file_transfer, &sink, &hash, TransferAction::Pause).await;
+        assert_eq!(status, Ok(TransferStatus::Paused));
+        let status = apply_transfer_action(&*file_transfer, &sink, &hash, TransferAction::Resume).await;
+        assert_eq!(status, Ok(TransferStatus::InProgress));
+        let status = apply_transfer_action(&*file_transfer, &sink, &hash, TransferAction::Cancel).await;
+        assert_eq!(status, Ok(TransferStatus::Cancelled));
+
+        // Cancelled is terminal
+        let err = apply_transfer_action(&*file_transfer, &sink, &hash, TransferAction::Resume)
+            .await
+            .unwrap_err();
+        assert_eq!(err.code, "invalid_transition");
+
+        let progress = file_transfer.get_transfer_progress().await;
+        assert!(progress.iter().any(|p| p.file_hash == hash && p.status == TransferStatus::Cancelled));
+        assert_eq!(sink.named(TRANSFER_STATE_CHANGED_EVENT).len(), 3);
+
+        drop(file_transfer);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+}
@@ -1,9 +1,21 @@
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, Mutex};
 use serde::{Serialize, Deserialize};
 
-use crate::p2p::NetworkDiscovery;
-use crate::services::{FileTransfer, ScreenShare, ChatService};
+use crate::p2p::{DeviceEvent, NetworkDiscovery, TcpTransport};
+use crate::config::AppConfig;
+use crate::network::{
+    LanAnnouncer, LanChunkTransport, LanFrameLinks, LanSessionTransport, SessionMessage, ShareRegistry, TransferHistory, ANNOUNCE_INTERVAL,
+    DEFAULT_ANNOUNCE_PORT, DEFAULT_SCREEN_PORT, DEFAULT_TRANSFER_PORT,
+};
+use crate::security::{DeviceIdentity, PairingManager, RateLimiter, TrustStore};
+use crate::services::{ChatStore, FileTransfer, ScreenShare, ChatService};
+
+/// How often shares are checked for having outlived their time to live
+const SHARE_REAP_INTERVAL: Duration = Duration::from_secs(15);
 
 /// Main application state shared across the application
 #[derive(Clone)]
@@ -12,25 +24,228 @@ pub struct AppState {
     pub network_discovery: Arc<Mutex<NetworkDiscovery>>,
     pub file_transfer: Arc<Mutex<FileTransfer>>,
     pub screen_share: Arc<Mutex<ScreenShare>>,
+    /// Connections viewers opened to us for our sessions' frames
+    pub frame_links: Arc<LanFrameLinks>,
+    /// Joins, control messages and notices between us and other devices'
+    /// sessions, and theirs and ours
+    pub session_transport: Arc<LanSessionTransport>,
+    /// Session announcements to and from the LAN; None when the port is taken
+    pub announcer: Option<Arc<LanAnnouncer>>,
     pub chat_service: Arc<Mutex<ChatService>>,
     pub connected_devices: Arc<Mutex<Vec<Device>>>,
+    pub identity: Arc<DeviceIdentity>,
+    pub trust_store: Arc<TrustStore>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub pairing: Arc<PairingManager>,
+    pub config: AppConfig,
 }
 
 impl AppState {
     /// Create a new application state
     pub async fn new() -> Self {
+        let identity = Arc::new(Self::load_identity());
+        let trust_store = Arc::new(TrustStore::open(&Self::config_dir().join("trust.json")));
+        let pairing = Arc::new(PairingManager::new(identity.clone(), trust_store.clone()));
+        let config = AppConfig::default();
+        let rate_limiter = Arc::new(RateLimiter::new(config.rate_limits, trust_store.clone()));
+        let chat_service = ChatService::new()
+            .await
+            .with_trust_store(trust_store.clone())
+            .with_rate_limiter(rate_limiter.clone());
+        // A passphrase-locked history stays closed until the user unlocks it
+        if let Err(e) = ChatStore::open(&Self::chat_history_path(), None).and_then(|store| chat_service.attach_store(store)) {
+            tracing::warn!("Chat history not loaded: {}", e);
+        }
+        let file_transfer = FileTransfer::new()
+            .await
+            .with_trust_store(trust_store.clone())
+            .with_rate_limiter(rate_limiter.clone())
+            .with_identity(identity.clone())
+            .with_security_config(config.security)
+            .with_auto_accept(config.auto_accept)
+            .with_chunk_windows(config.chunk_windows)
+            .with_chunk_size(config.sharing.chunk_size)
+            .with_collision_policy(config.sharing.collision_policy)
+            .with_chunk_order(config.sharing.chunk_order)
+            .with_chunk_cache_limit(config.sharing.chunk_cache_bytes)
+            .with_hash_algorithm(config.sharing.hash_algorithm)
+            .with_lan_transport(Arc::new(LanChunkTransport::new()))
+            .with_transfer_history(Arc::new(TransferHistory::open(&Self::config_dir().join("transfer-history.jsonl"))))
+            .with_share_registry(Arc::new(ShareRegistry::open(&Self::config_dir().join("shares.json"))));
+        file_transfer.set_shareable_roots(config.sharing.shareable_roots.clone());
+        if let Err(e) = file_transfer.set_downloads_dir(config.sharing.downloads_dir.clone()) {
+            tracing::warn!("Keeping the default downloads folder: {}", e);
+        }
+        file_transfer.set_upload_limit(config.bandwidth.upload_bytes_per_second);
+        file_transfer.set_download_limit(config.bandwidth.download_bytes_per_second);
+        file_transfer.set_max_concurrent_transfers(config.sharing.max_concurrent_transfers);
+        let frame_links = Arc::new(LanFrameLinks::new());
+        let session_transport = Arc::new(LanSessionTransport::new(
+            TcpTransport::new(identity.clone(), config.security).with_trust_store(trust_store.clone()),
+        ));
+        // Other devices know us by our address
+        let local_peer_id = local_ip_address::local_ip()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|_| "127.0.0.1".to_string());
+        let announcer = match LanAnnouncer::bind(DEFAULT_ANNOUNCE_PORT).await {
+            Ok(announcer) => Some(Arc::new(announcer)),
+            Err(e) => {
+                tracing::warn!("Not announcing screen shares to other devices: {}", e);
+                None
+            }
+        };
+        let mut screen_share = ScreenShare::new()
+            .await
+            .with_trust_store(trust_store.clone())
+            .with_rate_limiter(rate_limiter.clone())
+            .with_frame_buffer(config.frame_buffer)
+            .with_capture_config(config.capture)
+            .with_lan_transport(local_peer_id, session_transport.clone())
+            .with_frame_links(frame_links.clone());
+        if let Some(announcer) = &announcer {
+            screen_share = screen_share.with_announcer(announcer.clone());
+        }
+        
         Self {
             user_name: Arc::new(Mutex::new(String::new())),
-            network_discovery: Arc::new(Mutex::new(NetworkDiscovery::new().await)),
-            file_transfer: Arc::new(Mutex::new(FileTransfer::new().await)),
-            screen_share: Arc::new(Mutex::new(ScreenShare::new().await)),
-            chat_service: Arc::new(Mutex::new(ChatService::new().await)),
+            network_discovery: Arc::new(Mutex::new(
+                NetworkDiscovery::new()
+                    .await
+                    .with_trust_store(trust_store.clone())
+                    .with_rate_limiter(rate_limiter.clone()),
+            )),
+            file_transfer: Arc::new(Mutex::new(file_transfer)),
+            screen_share: Arc::new(Mutex::new(screen_share)),
+            frame_links,
+            session_transport,
+            announcer,
+            chat_service: Arc::new(Mutex::new(chat_service)),
             connected_devices: Arc::new(Mutex::new(Vec::new())),
+            identity,
+            trust_store,
+            rate_limiter,
+            pairing,
+            config,
         }
     }
-
+    
+    fn config_dir() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("desk-share-net")
+    }
+    
+    pub fn chat_history_path() -> PathBuf {
+        Self::config_dir().join("chat-history.json")
+    }
+    
+    /// The identity kept in the config directory, or a throwaway one if it can't be stored
+    fn load_identity() -> DeviceIdentity {
+        let path = Self::config_dir().join("identity.key");
+        
+        DeviceIdentity::load_or_generate(&path).unwrap_or_else(|e| {
+            tracing::warn!("Using a temporary identity: {}", e);
+            DeviceIdentity::generate()
+        })
+    }
+    
     /// Initialize and start background services
     pub async fn initialize(&self) {
+        // Files a device announced go away with the device
+        let mut device_events = self.network_discovery.lock().await.subscribe_device_events();
+        let file_transfer = self.file_transfer.clone();
+        tokio::spawn(async move {
+            loop {
+                match device_events.recv().await {
+                    Ok(DeviceEvent::Removed(device)) => file_transfer.lock().await.forget_remote_peer(&device.ip).await,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        
+        // Files other devices send us directly
+        let transport = TcpTransport::new(self.identity.clone(), self.config.security).with_trust_store(self.trust_store.clone());
+        let file_transfer = self.file_transfer.clone();
+        tokio::spawn(async move {
+            let listener = match TcpListener::bind(("0.0.0.0", DEFAULT_TRANSFER_PORT)).await {
+                Ok(listener) => listener,
+                Err(e) => {
+                    tracing::warn!("Not accepting direct transfers: {}", e);
+                    return;
+                }
+            };
+            loop {
+                let (stream, addr) = match transport.accept(&listener).await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("Direct transfer connection failed: {}", e);
+                        continue;
+                    }
+                };
+                let file_transfer = file_transfer.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = file_transfer.lock().await.accept_transfer(stream, addr.ip().to_string()).await {
+                        tracing::warn!("Transfer from {} not taken: {}", addr, e);
+                    }
+                });
+            }
+        });
+        
+        // Viewers of our sessions, and the hosts of those we join
+        match TcpListener::bind(("0.0.0.0", DEFAULT_SCREEN_PORT)).await {
+            Ok(listener) => self.serve_sessions(listener).await,
+            Err(e) => tracing::warn!("Not sharing screens with other devices: {}", e),
+        }
+        self.follow_joined_sessions().await;
+        
+        // Sessions other devices are sharing
+        if let Some(announcer) = self.announcer.clone() {
+            let screen_share = self.screen_share.clone();
+            tokio::spawn(async move {
+                loop {
+                    match announcer.recv().await {
+                        Ok((beacon, sender)) => screen_share.lock().await.handle_beacon(beacon, &sender.to_string()).await,
+                        Err(e) => tracing::debug!("Session announcement not received: {}", e),
+                    }
+                }
+            });
+        }
+        
+        // Ours go out again before they expire, and others' that weren't
+        // repeated are dropped
+        let screen_share = self.screen_share.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(ANNOUNCE_INTERVAL);
+            loop {
+                ticker.tick().await;
+                screen_share.lock().await.refresh_announcements().await;
+            }
+        });
+        
+        // What the last run shared, and the downloads it didn't get to
+        // finish, listed as paused until they're resumed
+        let file_transfer = self.file_transfer.clone();
+        tokio::spawn(async move {
+            let file_transfer = file_transfer.lock().await;
+            let restored = file_transfer.restore_shares().await;
+            tracing::info!("Sharing {} files again", restored.len());
+            if let Err(e) = file_transfer.restore_pending_downloads().await {
+                tracing::warn!("Couldn't restore unfinished downloads: {}", e);
+            }
+        });
+        
+        // Shares given a time to live go once it's up
+        let file_transfer = self.file_transfer.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(SHARE_REAP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                file_transfer.lock().await.reap_expired_shares().await;
+            }
+        });
+        
         // Start network discovery
         let discovery = self.network_discovery.clone();
         tokio::spawn(async move {
@@ -38,9 +253,68 @@ impl AppState {
             discovery.start_discovery().await;
             discovery.listen_for_devices().await;
         });
-
+        
         tracing::info!("Application state initialized");
     }
+    
+    /// Take viewers' connections to our sessions on `listener`
+    async fn serve_sessions(&self, listener: TcpListener) {
+        let transport = TcpTransport::new(self.identity.clone(), self.config.security).with_trust_store(self.trust_store.clone());
+        // Served from a clone, so a join waiting for approval doesn't keep
+        // the user from giving it
+        let screen_share = self.screen_share.lock().await.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, addr) = match transport.accept(&listener).await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("Session connection failed: {}", e);
+                        continue;
+                    }
+                };
+                let screen_share = screen_share.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = screen_share.accept_connection(stream, addr.ip().to_string()).await {
+                        tracing::warn!("Session connection from {} dropped: {}", addr, e);
+                    }
+                });
+            }
+        });
+    }
+    
+    /// Hand frames and notices from the hosts of sessions we joined to the
+    /// screen share
+    async fn follow_joined_sessions(&self) {
+        let screen_share = self.screen_share.lock().await.clone();
+        let mut frames = self.session_transport.subscribe_frames();
+        let frames_to = screen_share.clone();
+        tokio::spawn(async move {
+            loop {
+                match frames.recv().await {
+                    Ok(frame) => frames_to.receive_frame(frame).await,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        let mut notices = self.session_transport.subscribe_notices();
+        tokio::spawn(async move {
+            loop {
+                match notices.recv().await {
+                    Ok(SessionMessage::Grant(grant)) => {
+                        let session_id = grant.session_id.clone();
+                        if let Err(e) = screen_share.handle_token_grant(grant).await {
+                            tracing::debug!("New token for {} not taken: {}", session_id, e);
+                        }
+                    }
+                    Ok(SessionMessage::Ended(ended)) => screen_share.handle_session_ended(ended).await,
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(missed)) => tracing::warn!("Missed {} notices from hosts", missed),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
 }
 
 /// Represents a discovered device on the network
@@ -51,6 +325,9 @@ pub struct Device {
     pub port: u16,
     pub is_online: bool,
     pub last_seen: String,
+    /// Identity key fingerprint from the device's signed discovery reply
+    #[serde(default)]
+    pub fingerprint: Option<String>,
 }
 
 impl Device {
@@ -61,15 +338,104 @@ impl Device {
             port,
             is_online: true,
             last_seen: chrono::Utc::now().to_rfc3339(),
+            fingerprint: None,
         }
     }
-
+    
     pub fn update_last_seen(&mut self) {
         self.last_seen = chrono::Utc::now().to_rfc3339();
         self.is_online = true;
     }
-
+    
     pub fn mark_offline(&mut self) {
         self.is_online = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DeskShareError;
+    use crate::network::{AccessMode, SessionAnnouncement, SessionBeacon};
+    
+    /// Another device on loopback, serving its sessions on a port of its
+    /// own; returns it and the address others reach those sessions at
+    async fn device() -> (AppState, String) {
+        let state = AppState::new().await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        state.serve_sessions(listener).await;
+        state.follow_joined_sessions().await;
+        (state, addr)
+    }
+    
+    /// What the viewer hears of `session_id` from the host at `host_addr`
+    async fn hear_of(viewer: &AppState, session_id: &str, host_addr: &str) {
+        let announcement = SessionAnnouncement {
+            session_id: session_id.to_string(),
+            host_peer_id: "anything".to_string(),
+            resolution: (64, 48),
+            access_mode: AccessMode::Password,
+            participant_count: 0,
+            timestamp: 0,
+            host_name: None,
+            started_at_ms: 0,
+            has_password: true,
+            preset: None,
+        };
+        viewer.screen_share.lock().await.handle_beacon(SessionBeacon::Announce(announcement), host_addr).await;
+    }
+    
+    #[tokio::test]
+    async fn test_joining_a_session_on_another_device() {
+        let (host, host_addr) = device().await;
+        let (viewer, _) = device().await;
+        let host_share = host.screen_share.lock().await.clone();
+        let session_id = host_share.start_sharing(5, (64, 48), None, None, None).await.unwrap();
+        host_share.set_access(&session_id, AccessMode::Password, Some("hunter2".to_string())).await.unwrap();
+        hear_of(&viewer, &session_id, &host_addr).await;
+        let viewer_share = viewer.screen_share.lock().await.clone();
+        
+        let err = viewer_share.join_remote_session(&session_id, Some("guess".to_string())).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DeskShareError>(), Some(DeskShareError::InvalidSessionPassword)), "{}", err);
+        assert!(!viewer.session_transport.is_watching(&host_addr));
+        
+        // Let in, the viewer is known by the address it connected from and
+        // has the host sending it frames
+        let joined = viewer_share.join_remote_session(&session_id, Some("hunter2".to_string())).await.unwrap();
+        assert_eq!(joined.host_peer_id, host_addr);
+        let participants = host_share.get_participants(&session_id).await.unwrap();
+        assert_eq!(participants.iter().map(|p| p.peer_id.as_str()).collect::<Vec<_>>(), ["127.0.0.1"]);
+        assert!(host.frame_links.is_attached("127.0.0.1"));
+        assert!(viewer.session_transport.is_watching(&host_addr));
+        
+        viewer_share.leave_remote_session(&session_id).await.unwrap();
+        assert!(host_share.get_participants(&session_id).await.unwrap().is_empty());
+        host_share.stop_sharing(&session_id).await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_ending_a_session_reaches_viewers_on_other_devices() {
+        let (host, host_addr) = device().await;
+        let (viewer, _) = device().await;
+        let host_share = host.screen_share.lock().await.clone();
+        let session_id = host_share.start_sharing(5, (64, 48), None, None, None).await.unwrap();
+        host_share.set_access(&session_id, AccessMode::Password, Some("hunter2".to_string())).await.unwrap();
+        hear_of(&viewer, &session_id, &host_addr).await;
+        let viewer_share = viewer.screen_share.lock().await.clone();
+        viewer_share.join_remote_session(&session_id, Some("hunter2".to_string())).await.unwrap();
+        assert!(viewer_share.session_stats(&session_id).await.is_some());
+        
+        // Told over the notice connection it opened, the viewer stops
+        // watching and forgets the session
+        host_share.stop_sharing(&session_id).await.unwrap();
+        let ended = async {
+            while viewer_share.session_stats(&session_id).await.is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), ended).await.unwrap();
+        assert!(viewer_share.get_available_sessions().await.is_empty());
+        assert!(host_share.get_session(&session_id).await.is_none());
+    }
+}
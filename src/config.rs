@@ -0,0 +1,83 @@
+// Application configuration
+// User-adjustable policy knobs, grouped by area
+
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize};
+
+use crate::network::{BandwidthConfig, CaptureConfig, ChunkOrder, DEFAULT_CHUNK_CACHE_BYTES, ChunkWindowConfig, CollisionPolicy, FrameBufferConfig, HashAlgorithm};
+use crate::security::{RateLimitConfig, SecurityConfig};
+
+/// Offers accepted without prompting. Anything over a limit, or past the
+/// daily quota, is put to the user instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AutoAcceptConfig {
+    pub enabled: bool,
+    /// Largest single offer accepted without asking, per peer
+    pub max_size: u64,
+    /// Only paired devices qualify
+    pub trusted_only: bool,
+    /// Bytes accepted without asking per day, across all peers
+    pub daily_quota: u64,
+}
+
+impl Default for AutoAcceptConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_size: 50 * 1024 * 1024,
+            trusted_only: true,
+            daily_quota: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Where files may be shared or browsed from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharingConfig {
+    pub shareable_roots: Vec<PathBuf>,
+    /// Where incoming files go unless another folder is picked
+    pub downloads_dir: PathBuf,
+    /// Bytes per chunk of files we share; receivers follow the sender's
+    pub chunk_size: usize,
+    /// Downloads running at once; the rest wait in line
+    pub max_concurrent_transfers: usize,
+    /// What a download does when its file name is already taken
+    pub collision_policy: CollisionPolicy,
+    /// Which chunks downloads ask for first
+    pub chunk_order: ChunkOrder,
+    /// Chunk data of shared files kept in memory for serving; the rest is
+    /// read from disk when asked for
+    pub chunk_cache_bytes: u64,
+    /// What new shares are hashed with
+    pub hash_algorithm: HashAlgorithm,
+}
+
+impl Default for SharingConfig {
+    fn default() -> Self {
+        Self {
+            shareable_roots: [dirs::download_dir(), dirs::document_dir(), dirs::desktop_dir()]
+                .into_iter()
+                .flatten()
+                .collect(),
+            downloads_dir: dirs::download_dir().unwrap_or_else(std::env::temp_dir),
+            chunk_size: 1024 * 1024,
+            max_concurrent_transfers: 3,
+            collision_policy: CollisionPolicy::RenameWithSuffix,
+            chunk_order: ChunkOrder::RarestFirst,
+            chunk_cache_bytes: DEFAULT_CHUNK_CACHE_BYTES,
+            hash_algorithm: HashAlgorithm::Blake3,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppConfig {
+    pub security: SecurityConfig,
+    pub auto_accept: AutoAcceptConfig,
+    pub rate_limits: RateLimitConfig,
+    pub sharing: SharingConfig,
+    pub frame_buffer: FrameBufferConfig,
+    pub capture: CaptureConfig,
+    pub chunk_windows: ChunkWindowConfig,
+    pub bandwidth: BandwidthConfig,
+}
@@ -17,6 +17,12 @@ pub enum DeskShareError {
     #[error("Peer connection failed: {0}")]
     PeerConnectionFailed(String),
     
+    #[error("Unknown peer: {0}")]
+    PeerNotFound(String),
+    
+    #[error("Unsupported protocol version: {0}")]
+    UnsupportedProtocolVersion(u8),
+    
     // File transfer errors
     #[error("File transfer failed: {0}")]
     FileTransferFailed(String),
@@ -24,6 +30,9 @@ pub enum DeskShareError {
     #[error("File not found: {0}")]
     FileNotFound(String),
     
+    #[error("Path is outside the shareable directories: {0}")]
+    PathNotAllowed(String),
+    
     #[error("File read error: {0}")]
     FileReadError(#[from] io::Error),
     
@@ -33,6 +42,36 @@ pub enum DeskShareError {
     #[error("File integrity check failed")]
     IntegrityCheckFailed,
     
+    #[error("Chunk failed authentication: {0}")]
+    ChunkAuthenticationFailed(String),
+    
+    #[error("File is not shared: {0}")]
+    ShareNotFound(String),
+    
+    #[error("Share has expired: {0}")]
+    ShareExpired(String),
+    
+    #[error("Transfer not found: {0}")]
+    TransferNotFound(String),
+    
+    #[error("Invalid transfer state transition: {0}")]
+    InvalidTransition(String),
+    
+    #[error("Transfer offer not found: {0}")]
+    OfferNotFound(String),
+    
+    #[error("File announcement rejected: {0}")]
+    AnnouncementRejected(String),
+    
+    #[error("Unsafe file name from remote device: {0}")]
+    UnsafeRemotePath(String),
+    
+    #[error("Output file already exists: {0}")]
+    OutputFileExists(String),
+    
+    #[error("Not enough disk space: {needed} bytes needed, {available} available")]
+    InsufficientDiskSpace { needed: u64, available: u64 },
+    
     // Screen sharing errors
     #[error("Screen capture failed: {0}")]
     ScreenCaptureFailed(String),
@@ -40,6 +79,33 @@ pub enum DeskShareError {
     #[error("Screen share session not found: {0}")]
     SessionNotFound(String),
     
+    #[error("Wrong session password")]
+    InvalidSessionPassword,
+    
+    #[error("Host declined the join request: {0}")]
+    JoinDenied(String),
+    
+    #[error("Session {0} is full")]
+    SessionFull(String),
+    
+    #[error("Remote control not allowed in session {0}")]
+    RemoteControlNotAllowed(String),
+    
+    #[error("Join request not found: {0}")]
+    JoinRequestNotFound(String),
+    
+    #[error("Session token rejected for session {0}")]
+    InvalidSessionToken(String),
+    
+    #[error("Only the host can end session {0}")]
+    NotSessionHost(String),
+    
+    #[error("Monitor not found: {0}")]
+    MonitorNotFound(u32),
+    
+    #[error("Invalid capture region: {0}")]
+    InvalidCaptureRegion(String),
+    
     #[error("Video encoding failed: {0}")]
     EncodingFailed(String),
     
@@ -57,9 +123,31 @@ pub enum DeskShareError {
     #[error("Message send failed: {0}")]
     MessageSendFailed(String),
     
+    #[error("Message not found: {0}")]
+    MessageNotFound(String),
+    
     #[error("Invalid message format")]
     InvalidMessageFormat,
     
+    #[error("Chat history is locked: {0}")]
+    ChatHistoryLocked(String),
+    
+    // Security errors
+    #[error("Pairing failed: {0}")]
+    PairingFailed(String),
+    
+    #[error("Pairing request not found: {0}")]
+    PairingRequestNotFound(String),
+    
+    #[error("Secure channel handshake failed: {0}")]
+    HandshakeFailed(String),
+    
+    #[error("Encrypted message rejected: {0}")]
+    DecryptionFailed(String),
+    
+    #[error("Encryption is required but {0} would run in plaintext")]
+    EncryptionRequired(String),
+    
     // General errors
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
@@ -106,7 +194,8 @@ impl DeskShareError {
             
             // File errors - fail immediately
             DeskShareError::FileNotFound(_) 
-            | DeskShareError::IntegrityCheckFailed => RecoveryStrategy::Fail,
+            | DeskShareError::IntegrityCheckFailed
+            | DeskShareError::ChunkAuthenticationFailed(_) => RecoveryStrategy::Fail,
             
             // Screen capture - retry once
             DeskShareError::ScreenCaptureFailed(_) => {
@@ -131,17 +220,155 @@ impl DeskShareError {
                 format!("File not found: {}", path)
             }
             DeskShareError::ScreenCaptureFailed(_) => {
-                "Failed to capture screen. Please check permissions.".to_string()
+                "Failed to capture the screen. Check that screen recording permission is granted.".to_string()
             }
             DeskShareError::PeerConnectionFailed(_) => {
                 "Failed to connect to peer. They may be offline.".to_string()
             }
+            DeskShareError::PeerNotFound(_) => {
+                "That device is no longer on the network.".to_string()
+            }
+            DeskShareError::UnsupportedProtocolVersion(_) => {
+                "That device runs a newer version of Desk Share Net. Update to share files with it.".to_string()
+            }
             DeskShareError::Timeout => {
                 "Operation timed out. Please try again.".to_string()
             }
+            DeskShareError::PathNotAllowed(_) => {
+                "That location isn't in your shared folders.".to_string()
+            }
+            DeskShareError::TransferNotFound(_) => {
+                "That transfer no longer exists.".to_string()
+            }
+            DeskShareError::OfferNotFound(_) => {
+                "This transfer offer was already answered or has expired.".to_string()
+            }
+            DeskShareError::ShareExpired(_) => {
+                "The sender stopped sharing this file when its time ran out.".to_string()
+            }
+            DeskShareError::UnsafeRemotePath(_) => {
+                "The other device sent a file name that isn't safe to save.".to_string()
+            }
+            DeskShareError::OutputFileExists(_) => {
+                "A file with that name is already there.".to_string()
+            }
+            DeskShareError::FileReadError(e) if e.kind() == io::ErrorKind::StorageFull => {
+                "The disk is full. Free up some space and try again.".to_string()
+            }
+            DeskShareError::ChunkTransferFailed(_) => {
+                "Couldn't get part of the file from the devices sharing it. Try again once they're back online.".to_string()
+            }
+            DeskShareError::IntegrityCheckFailed => {
+                "The file arrived damaged and was thrown away. Try downloading it again.".to_string()
+            }
+            DeskShareError::InsufficientDiskSpace { needed, available } => {
+                format!("Not enough disk space. Free up {} and try again.", format_bytes(needed.saturating_sub(*available)))
+            }
+            DeskShareError::InvalidSessionPassword => {
+                "That password isn't right for this session.".to_string()
+            }
+            DeskShareError::JoinDenied(_) => {
+                "The host declined your request to join.".to_string()
+            }
+            DeskShareError::SessionFull(_) => {
+                "That session already has as many viewers as the host allows.".to_string()
+            }
+            DeskShareError::RemoteControlNotAllowed(_) => {
+                "The host hasn't let you control their screen.".to_string()
+            }
+            DeskShareError::MonitorNotFound(_) => {
+                "That monitor is no longer connected.".to_string()
+            }
+            DeskShareError::InvalidCaptureRegion(_) => {
+                "That area doesn't fit on the shared monitor.".to_string()
+            }
+            DeskShareError::ChatHistoryLocked(_) => {
+                "Chat history is encrypted. Unlock it with your passphrase, or reset it to start over.".to_string()
+            }
+            DeskShareError::PairingRequestNotFound(_) => {
+                "This pairing request was already answered or has expired.".to_string()
+            }
+            DeskShareError::HandshakeFailed(_) => {
+                "Couldn't set up a secure connection to that device.".to_string()
+            }
+            DeskShareError::EncryptionRequired(_) => {
+                "That device can't connect securely, and plaintext connections are turned off.".to_string()
+            }
             _ => self.to_string(),
         }
     }
+    
+    /// Stable machine-readable code for the frontend
+    pub fn code(&self) -> &'static str {
+        match self {
+            DeskShareError::NetworkConnection(_) => "network_connection",
+            DeskShareError::DiscoveryFailed(_) => "discovery_failed",
+            DeskShareError::NatTraversalFailed(_) => "nat_traversal_failed",
+            DeskShareError::PeerConnectionFailed(_) => "peer_connection_failed",
+            DeskShareError::PeerNotFound(_) => "peer_not_found",
+            DeskShareError::UnsupportedProtocolVersion(_) => "unsupported_protocol_version",
+            DeskShareError::FileTransferFailed(_) => "file_transfer_failed",
+            DeskShareError::FileNotFound(_) => "file_not_found",
+            DeskShareError::PathNotAllowed(_) => "path_not_allowed",
+            DeskShareError::FileReadError(_) => "file_read_error",
+            DeskShareError::ChunkTransferFailed(_) => "chunk_transfer_failed",
+            DeskShareError::IntegrityCheckFailed => "integrity_check_failed",
+            DeskShareError::ChunkAuthenticationFailed(_) => "chunk_authentication_failed",
+            DeskShareError::ShareNotFound(_) => "share_not_found",
+            DeskShareError::ShareExpired(_) => "share_expired",
+            DeskShareError::AnnouncementRejected(_) => "announcement_rejected",
+            DeskShareError::UnsafeRemotePath(_) => "unsafe_remote_path",
+            DeskShareError::OutputFileExists(_) => "output_file_exists",
+            DeskShareError::InsufficientDiskSpace { .. } => "insufficient_disk_space",
+            DeskShareError::TransferNotFound(_) => "transfer_not_found",
+            DeskShareError::InvalidTransition(_) => "invalid_transition",
+            DeskShareError::OfferNotFound(_) => "offer_not_found",
+            DeskShareError::ScreenCaptureFailed(_) => "screen_capture_failed",
+            DeskShareError::SessionNotFound(_) => "session_not_found",
+            DeskShareError::InvalidSessionPassword => "wrong_password",
+            DeskShareError::JoinDenied(_) => "join_denied",
+            DeskShareError::SessionFull(_) => "session_full",
+            DeskShareError::RemoteControlNotAllowed(_) => "remote_control_not_allowed",
+            DeskShareError::JoinRequestNotFound(_) => "join_request_not_found",
+            DeskShareError::InvalidSessionToken(_) => "invalid_session_token",
+            DeskShareError::NotSessionHost(_) => "not_session_host",
+            DeskShareError::MonitorNotFound(_) => "monitor_not_found",
+            DeskShareError::InvalidCaptureRegion(_) => "invalid_capture_region",
+            DeskShareError::EncodingFailed(_) => "encoding_failed",
+            DeskShareError::SignalingFailed(_) => "signaling_failed",
+            DeskShareError::SdpExchangeFailed(_) => "sdp_exchange_failed",
+            DeskShareError::IceCandidateFailed(_) => "ice_candidate_failed",
+            DeskShareError::MessageSendFailed(_) => "message_send_failed",
+            DeskShareError::MessageNotFound(_) => "message_not_found",
+            DeskShareError::InvalidMessageFormat => "invalid_message_format",
+            DeskShareError::ChatHistoryLocked(_) => "chat_history_locked",
+            DeskShareError::PairingFailed(_) => "pairing_failed",
+            DeskShareError::PairingRequestNotFound(_) => "pairing_request_not_found",
+            DeskShareError::HandshakeFailed(_) => "handshake_failed",
+            DeskShareError::DecryptionFailed(_) => "decryption_failed",
+            DeskShareError::EncryptionRequired(_) => "encryption_required",
+            DeskShareError::SerializationError(_) => "serialization_error",
+            DeskShareError::InvalidConfig(_) => "invalid_config",
+            DeskShareError::Timeout => "timeout",
+            DeskShareError::Internal(_) => "internal",
+        }
+    }
+}
+
+/// A byte count in the largest unit that keeps it at or above one
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["bytes", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
 }
 
 /// Retry helper with exponential backoff
@@ -154,7 +381,6 @@ where
     F: FnMut() -> std::result::Result<T, E>,
 {
     let mut attempts = 0;
-    let mut backoff = initial_backoff_ms;
     
     loop {
         attempts += 1;
@@ -162,18 +388,29 @@ where
         match operation() {
             Ok(result) => return Ok(result),
             Err(e) if attempts >= max_attempts => return Err(e),
-            Err(_) => {
-                tokio::time::sleep(tokio::time::Duration::from_millis(backoff)).await;
-                backoff *= 2; // Exponential backoff
-            }
+            Err(_) => tokio::time::sleep(backoff_delay(initial_backoff_ms, attempts)).await,
         }
     }
 }
 
+/// How long to wait after failed attempt number `attempt`, counting from 1;
+/// doubles each time
+pub fn backoff_delay(initial_backoff_ms: u64, attempt: u32) -> std::time::Duration {
+    let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+    std::time::Duration::from_millis(initial_backoff_ms.saturating_mul(factor))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     
+    #[test]
+    fn test_backoff_doubles_without_overflowing() {
+        let delays: Vec<u64> = (1..=4).map(|attempt| backoff_delay(50, attempt).as_millis() as u64).collect();
+        assert_eq!(delays, vec![50, 100, 200, 400]);
+        assert_eq!(backoff_delay(50, 200), std::time::Duration::from_millis(u64::MAX));
+    }
+    
     #[test]
     fn test_error_recovery_strategy() {
         let error = DeskShareError::NetworkConnection("test".to_string());
@@ -190,5 +427,21 @@ mod tests {
         let error = DeskShareError::FileNotFound("/test/file.txt".to_string());
         let message = error.user_message();
         assert!(message.contains("File not found"));
+        
+        let error = DeskShareError::InsufficientDiskSpace { needed: 5 * 1024 * 1024 * 1024, available: 2 * 1024 * 1024 * 1024 };
+        assert_eq!(error.user_message(), "Not enough disk space. Free up 3.0 GB and try again.");
+        assert_eq!(error.code(), "insufficient_disk_space");
+        
+        let error = DeskShareError::FileReadError(io::Error::from(io::ErrorKind::StorageFull));
+        assert_eq!(error.user_message(), "The disk is full. Free up some space and try again.");
+        assert_eq!(error.code(), "file_read_error");
+    }
+    
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(DeskShareError::TransferNotFound("abc".to_string()).code(), "transfer_not_found");
+        assert_eq!(DeskShareError::InvalidTransition("x".to_string()).code(), "invalid_transition");
+        assert_eq!(DeskShareError::Timeout.code(), "timeout");
+        assert_eq!(DeskShareError::ShareExpired("abc".to_string()).code(), "share_expired");
     }
 }
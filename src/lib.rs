@@ -4,15 +4,21 @@
 // file sharing, screen sharing, and chat services.
 
 pub mod p2p;
+pub mod network;
 pub mod services;
 pub mod ui;
 pub mod error;
 pub mod app;
+pub mod config;
+pub mod platform;
+pub mod security;
 
 // Re-export commonly used types
 pub use app::{AppState, Device};
+pub use config::AppConfig;
 pub use error::DeskShareError;
 
 // Re-export network types for convenience
-pub use p2p::{NetworkDiscovery, P2PNetwork};
+pub use p2p::{DeviceEvent, NetworkDiscovery, P2PNetwork};
 pub use services::{FileTransfer, ScreenShare, ChatService};
+pub use network::{OfferEvent, PendingOffer, TransferProgress, TransferStatus};
@@ -0,0 +1,274 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+/// A frame taking longer than this to reach a viewer means it's behind
+const LATENCY_LIMIT: Duration = Duration::from_millis(250);
+
+/// Share of a window's frames a viewer may lose before it counts as behind
+const MAX_DROP_PERCENT: u64 = 10;
+
+/// Windows every viewer has to keep up through before stepping back up
+const RECOVERY_WINDOWS: u32 = 3;
+
+const QUALITY_STEP: u8 = 15;
+const SCALE_STEP: u32 = 25;
+
+/// How far a session may fall when viewers can't keep up. The session's
+/// own quality, frame rate and resolution are the ceiling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdaptiveConfig {
+    pub enabled: bool,
+    pub min_quality: u8,
+    pub min_frame_rate: u32,
+    /// Smallest share of the session's resolution to capture at
+    pub min_scale_percent: u32,
+    /// How much delivery each decision looks back over
+    pub window_ms: u64,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_quality: 30,
+            min_frame_rate: 5,
+            min_scale_percent: 50,
+            window_ms: 1_000,
+        }
+    }
+}
+
+impl AdaptiveConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_millis(self.window_ms)
+    }
+}
+
+/// What the capture loop is producing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamSettings {
+    pub quality: u8,
+    pub frame_rate: u32,
+    pub scale_percent: u32,
+}
+
+impl StreamSettings {
+    /// `full` scaled down, kept even for the encoders' sake
+    pub fn resolution(&self, full: (u32, u32)) -> (u32, u32) {
+        let scale = |side: u32| (side * self.scale_percent / 100).max(2) & !1;
+        (scale(full.0), scale(full.1))
+    }
+    
+    pub fn frame_interval(&self) -> Duration {
+        Duration::from_millis(1000 / self.frame_rate.max(1) as u64)
+    }
+}
+
+/// One change of stream settings and what prompted it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Adaptation {
+    pub from: StreamSettings,
+    pub to: StreamSettings,
+    pub reason: String,
+    pub at_ms: u64,
+}
+
+/// How frames reached one viewer over a window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeliveryReport {
+    pub delivered: u64,
+    pub dropped: u64,
+    pub worst_latency: Duration,
+}
+
+impl DeliveryReport {
+    pub fn delivered_in(&mut self, latency: Duration) {
+        self.delivered += 1;
+        self.worst_latency = self.worst_latency.max(latency);
+    }
+    
+    pub fn merge(&mut self, other: DeliveryReport) {
+        self.delivered += other.delivered;
+        self.dropped += other.dropped;
+        self.worst_latency = self.worst_latency.max(other.worst_latency);
+    }
+    
+    fn is_behind(&self) -> bool {
+        let frames = self.delivered + self.dropped;
+        self.dropped * 100 > frames * MAX_DROP_PERCENT || self.worst_latency > LATENCY_LIMIT
+    }
+}
+
+/// Steps a session's stream down when any viewer falls behind: JPEG
+/// quality first, then frame rate, then resolution. Once every viewer has
+/// kept up for a while it steps back up the same way in reverse.
+pub struct QualityController {
+    ceiling: StreamSettings,
+    config: AdaptiveConfig,
+    current: StreamSettings,
+    healthy_windows: u32,
+}
+
+impl QualityController {
+    pub fn new(ceiling: StreamSettings, config: AdaptiveConfig) -> Self {
+        Self {
+            ceiling,
+            config,
+            current: ceiling,
+            healthy_windows: 0,
+        }
+    }
+    
+    pub fn settings(&self) -> StreamSettings {
+        self.current
+    }
+    
+    /// Take a new ceiling and start over from it
+    pub fn set_ceiling(&mut self, ceiling: StreamSettings) {
+        self.ceiling = ceiling;
+        self.current = ceiling;
+        self.healthy_windows = 0;
+    }
+    
+    /// Take new bounds; turning adaptation off goes straight back to the ceiling
+    pub fn set_config(&mut self, config: AdaptiveConfig) {
+        self.config = config;
+        self.current = if config.enabled {
+            StreamSettings {
+                quality: self.current.quality.max(config.min_quality.min(self.ceiling.quality)),
+                frame_rate: self.current.frame_rate.max(config.min_frame_rate.min(self.ceiling.frame_rate)),
+                scale_percent: self.current.scale_percent.max(config.min_scale_percent.min(self.ceiling.scale_percent)),
+            }
+        } else {
+            self.ceiling
+        };
+    }
+    
+    /// Judge a window of delivery, one report per viewer. Returns the
+    /// change made, if any.
+    pub fn observe<'a>(
+        &mut self,
+        reports: impl IntoIterator<Item = (&'a str, DeliveryReport)>,
+        at_ms: u64,
+    ) -> Option<Adaptation> {
+        if !self.config.enabled {
+            return None;
+        }
+        let mut heard = false;
+        let mut behind = None;
+        for (peer_id, report) in reports {
+            if report.delivered + report.dropped == 0 {
+                continue;
+            }
+            heard = true;
+            if report.is_behind() && behind.is_none() {
+                behind = Some((peer_id, report));
+            }
+        }
+        
+        let from = self.current;
+        let reason = match behind {
+            Some((peer_id, report)) => {
+                self.healthy_windows = 0;
+                self.current = self.step_down()?;
+                format!(
+                    "{} behind: {} of {} frames dropped, slowest took {}ms",
+                    peer_id,
+                    report.dropped,
+                    report.delivered + report.dropped,
+                    report.worst_latency.as_millis()
+                )
+            }
+            None if heard => {
+                self.healthy_windows += 1;
+                if self.healthy_windows < RECOVERY_WINDOWS {
+                    return None;
+                }
+                self.healthy_windows = 0;
+                self.current = self.step_up()?;
+                "every viewer keeping up".to_string()
+            }
+            None => return None,
+        };
+        Some(Adaptation { from, to: self.current, reason, at_ms })
+    }
+    
+    fn step_down(&self) -> Option<StreamSettings> {
+        let mut next = self.current;
+        let min_quality = self.config.min_quality.min(self.ceiling.quality);
+        let min_frame_rate = self.config.min_frame_rate.min(self.ceiling.frame_rate);
+        let min_scale_percent = self.config.min_scale_percent.min(self.ceiling.scale_percent);
+        if next.quality > min_quality {
+            next.quality = next.quality.saturating_sub(QUALITY_STEP).max(min_quality);
+        } else if next.frame_rate > min_frame_rate {
+            next.frame_rate = (next.frame_rate * 2 / 3).max(min_frame_rate);
+        } else if next.scale_percent > min_scale_percent {
+            next.scale_percent = next.scale_percent.saturating_sub(SCALE_STEP).max(min_scale_percent);
+        } else {
+            return None;
+        }
+        Some(next)
+    }
+    
+    fn step_up(&self) -> Option<StreamSettings> {
+        let mut next = self.current;
+        if next.scale_percent < self.ceiling.scale_percent {
+            next.scale_percent = (next.scale_percent + SCALE_STEP).min(self.ceiling.scale_percent);
+        } else if next.frame_rate < self.ceiling.frame_rate {
+            next.frame_rate = (next.frame_rate * 3 / 2 + 1).min(self.ceiling.frame_rate);
+        } else if next.quality < self.ceiling.quality {
+            next.quality = next.quality.saturating_add(QUALITY_STEP).min(self.ceiling.quality);
+        } else {
+            return None;
+        }
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_steps_down_in_order_and_back_up_in_reverse() {
+        let ceiling = StreamSettings { quality: 80, frame_rate: 30, scale_percent: 100 };
+        let mut controller = QualityController::new(ceiling, AdaptiveConfig::default());
+        let slow = DeliveryReport { delivered: 10, dropped: 0, worst_latency: Duration::from_millis(400) };
+        let lossy = DeliveryReport { delivered: 8, dropped: 2, worst_latency: Duration::ZERO };
+        let fine = DeliveryReport { delivered: 10, dropped: 0, worst_latency: Duration::from_millis(20) };
+        
+        let mut seen = vec![ceiling];
+        while let Some(change) = controller.observe([("a", fine), ("b", slow)], 0) {
+            assert!(change.reason.starts_with("b behind"), "{}", change.reason);
+            seen.push(change.to);
+        }
+        let qualities: Vec<_> = seen.iter().map(|s| s.quality).collect();
+        assert_eq!(qualities[..5], [80, 65, 50, 35, 30]);
+        assert!(seen[5..].iter().all(|s| s.quality == 30));
+        // Resolution only goes once the frame rate is at its floor
+        let first_scaled = seen.iter().position(|s| s.scale_percent < 100).unwrap();
+        assert_eq!(seen[first_scaled - 1].frame_rate, 5);
+        assert_eq!(controller.settings(), StreamSettings { quality: 30, frame_rate: 5, scale_percent: 50 });
+        assert!(controller.observe([("b", lossy)], 0).is_none());
+        
+        // Nothing heard is no reason to move either way
+        for _ in 0..10 {
+            assert!(controller.observe([("a", DeliveryReport::default())], 0).is_none());
+        }
+        let mut steps = 0;
+        for _ in 0..200 {
+            if let Some(change) = controller.observe([("a", fine)], 0) {
+                steps += 1;
+                assert_eq!(change.reason, "every viewer keeping up");
+            }
+        }
+        assert_eq!(controller.settings(), ceiling);
+        assert_eq!(steps, seen.len() - 1);
+        assert_eq!(StreamSettings { scale_percent: 75, ..ceiling }.resolution((1366, 768)), (1024, 576));
+        
+        controller.observe([("b", slow)], 0).unwrap();
+        controller.set_config(AdaptiveConfig { enabled: false, ..AdaptiveConfig::default() });
+        assert_eq!(controller.settings(), ceiling);
+        assert!(controller.observe([("b", slow)], 0).is_none());
+    }
+}
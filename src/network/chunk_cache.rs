@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap};
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+/// Chunk data kept in memory unless configured otherwise
+pub const DEFAULT_CHUNK_CACHE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Snapshot of what the chunk cache holds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkCacheUsage {
+    pub bytes: u64,
+    pub chunks: usize,
+    pub limit_bytes: u64,
+    /// Most bytes ever held at once
+    pub peak_bytes: u64,
+    pub evicted_chunks: u64,
+}
+
+struct CachedChunk {
+    data: Bytes,
+    /// Tick of the last hit, for least-recently-used eviction
+    touched: u64,
+}
+
+/// Data of chunks read from shared files, under one byte budget, so a chunk
+/// several peers want is read from disk once. Only the data is held; what
+/// it takes to read a chunk again lives with the share, so a chunk that's
+/// been evicted is just read back when it's next asked for.
+pub struct ChunkCache {
+    chunks: HashMap<String, CachedChunk>,
+    /// Chunk hashes by the tick they were last touched
+    order: BTreeMap<u64, String>,
+    limit: u64,
+    used: u64,
+    peak: u64,
+    evicted: u64,
+    tick: u64,
+}
+
+impl ChunkCache {
+    pub fn new(limit_bytes: u64) -> Self {
+        Self {
+            chunks: HashMap::new(),
+            order: BTreeMap::new(),
+            limit: limit_bytes,
+            used: 0,
+            peak: 0,
+            evicted: 0,
+            tick: 0,
+        }
+    }
+    
+    pub fn get(&mut self, chunk_hash: &str) -> Option<Bytes> {
+        let chunk = self.chunks.get_mut(chunk_hash)?;
+        self.order.remove(&chunk.touched);
+        self.tick += 1;
+        chunk.touched = self.tick;
+        self.order.insert(self.tick, chunk_hash.to_string());
+        Some(chunk.data.clone())
+    }
+    
+    /// Keep a chunk's data, evicting the least recently used to make room.
+    /// Chunks larger than the whole cache aren't kept.
+    pub fn insert(&mut self, chunk_hash: &str, data: Bytes) {
+        let bytes = data.len() as u64;
+        if bytes > self.limit || self.chunks.contains_key(chunk_hash) {
+            return;
+        }
+        while self.used + bytes > self.limit && self.evict_one() {}
+        
+        self.tick += 1;
+        self.order.insert(self.tick, chunk_hash.to_string());
+        self.chunks.insert(chunk_hash.to_string(), CachedChunk { data, touched: self.tick });
+        self.used += bytes;
+        self.peak = self.peak.max(self.used);
+    }
+    
+    /// Keep only the chunks `keep` says yes to
+    pub fn retain(&mut self, mut keep: impl FnMut(&str) -> bool) {
+        let dropped: Vec<String> = self.chunks.keys().filter(|chunk_hash| !keep(chunk_hash)).cloned().collect();
+        for chunk_hash in dropped {
+            self.remove(&chunk_hash);
+        }
+    }
+    
+    /// Change the cap, evicting down to it straight away
+    pub fn set_limit(&mut self, limit_bytes: u64) {
+        self.limit = limit_bytes;
+        while self.used > self.limit && self.evict_one() {}
+    }
+    
+    pub fn usage(&self) -> ChunkCacheUsage {
+        ChunkCacheUsage {
+            bytes: self.used,
+            chunks: self.chunks.len(),
+            limit_bytes: self.limit,
+            peak_bytes: self.peak,
+            evicted_chunks: self.evicted,
+        }
+    }
+    
+    fn evict_one(&mut self) -> bool {
+        let Some((_, chunk_hash)) = self.order.pop_first() else {
+            return false;
+        };
+        if let Some(chunk) = self.chunks.remove(&chunk_hash) {
+            self.used -= chunk.data.len() as u64;
+            self.evicted += 1;
+        }
+        true
+    }
+    
+    fn remove(&mut self, chunk_hash: &str) {
+        if let Some(chunk) = self.chunks.remove(chunk_hash) {
+            self.order.remove(&chunk.touched);
+            self.used -= chunk.data.len() as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_least_recently_used_go_first() {
+        let mut cache = ChunkCache::new(300);
+        cache.insert("a", Bytes::from(vec![1u8; 100]));
+        cache.insert("b", Bytes::from(vec![2u8; 100]));
+        cache.insert("c", Bytes::from(vec![3u8; 100]));
+        assert!(cache.get("a").is_some());
+        
+        cache.insert("d", Bytes::from(vec![4u8; 100]));
+        assert!(cache.get("b").is_none());
+        assert_eq!(cache.get("a").unwrap(), vec![1u8; 100]);
+        assert_eq!(cache.usage().bytes, 300);
+        assert_eq!(cache.usage().evicted_chunks, 1);
+        
+        // Too big to keep at all
+        cache.insert("e", Bytes::from(vec![5u8; 301]));
+        assert!(cache.get("e").is_none());
+        assert_eq!(cache.usage().chunks, 3);
+        
+        cache.retain(|chunk_hash| chunk_hash != "c");
+        cache.set_limit(100);
+        let usage = cache.usage();
+        assert_eq!((usage.bytes, usage.chunks, usage.peak_bytes), (100, 1, 300));
+        assert!(cache.get("a").is_some());
+    }
+}
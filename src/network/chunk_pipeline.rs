@@ -0,0 +1,234 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::Error;
+use async_trait::async_trait;
+use bytes::Bytes;
+use dashmap::DashMap;
+use serde::{Serialize, Deserialize};
+use tokio::sync::mpsc;
+
+use crate::error::DeskShareError;
+use super::file_transfer::ChunkEnvelope;
+
+/// Moves chunks between this device and the peers sharing a file
+#[async_trait]
+pub trait ChunkTransport: Send + Sync {
+    async fn fetch_chunk(&self, peer_id: &str, file_hash: &str, index: usize) -> Result<Bytes, Error>;
+    
+    /// Answer a peer's chunk request
+    async fn send_chunk(&self, peer_id: &str, envelope: ChunkEnvelope) -> Result<(), Error>;
+    
+    /// Tell a peer we turned down the file it offered. Transports with no
+    /// way back to the sender leave it to time out.
+    async fn decline_offer(&self, _peer_id: &str, _file_hash: &str, _reason: &str) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// In-process transport: fetches come from chunks offered to it and sent
+/// envelopes land in a channel. Lets tests and benchmarks run anywhere.
+pub struct MemoryChunkTransport {
+    chunks: DashMap<(String, usize), Bytes>,
+    sent: mpsc::UnboundedSender<(String, ChunkEnvelope)>,
+    /// (peer, file hash, reason) of every offer declined
+    declined: Mutex<Vec<(String, String, String)>>,
+}
+
+impl MemoryChunkTransport {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<(String, ChunkEnvelope)>) {
+        let (sent, rx) = mpsc::unbounded_channel();
+        (Self { chunks: DashMap::new(), sent, declined: Mutex::new(Vec::new()) }, rx)
+    }
+    
+    /// Make `chunks` of `file_hash` available to fetch
+    pub fn offer(&self, file_hash: &str, chunks: impl IntoIterator<Item = Bytes>) {
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            self.chunks.insert((file_hash.to_string(), index), chunk);
+        }
+    }
+    
+    pub fn declined(&self) -> Vec<(String, String, String)> {
+        self.declined.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl ChunkTransport for MemoryChunkTransport {
+    async fn fetch_chunk(&self, _peer_id: &str, file_hash: &str, index: usize) -> Result<Bytes, Error> {
+        self.chunks
+            .get(&(file_hash.to_string(), index))
+            .map(|chunk| chunk.clone())
+            .ok_or_else(|| DeskShareError::ChunkTransferFailed(format!("chunk {} of {} not offered", index, file_hash)).into())
+    }
+    
+    async fn send_chunk(&self, peer_id: &str, envelope: ChunkEnvelope) -> Result<(), Error> {
+        self.sent
+            .send((peer_id.to_string(), envelope))
+            .map_err(|_| DeskShareError::PeerConnectionFailed(peer_id.to_string()).into())
+    }
+    
+    async fn decline_offer(&self, peer_id: &str, file_hash: &str, reason: &str) -> Result<(), Error> {
+        self.declined
+            .lock()
+            .unwrap()
+            .push((peer_id.to_string(), file_hash.to_string(), reason.to_string()));
+        Ok(())
+    }
+}
+
+/// Bounds for how many chunk requests may be outstanding per peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkWindowConfig {
+    pub initial_window: usize,
+    pub min_window: usize,
+    pub max_window: usize,
+    /// A request not answered within this counts as timed out
+    pub request_timeout_ms: u64,
+}
+
+impl Default for ChunkWindowConfig {
+    fn default() -> Self {
+        Self {
+            initial_window: 4,
+            min_window: 1,
+            max_window: 32,
+            request_timeout_ms: 10_000,
+        }
+    }
+}
+
+impl ChunkWindowConfig {
+    pub fn request_timeout(&self) -> Duration {
+        Duration::from_millis(self.request_timeout_ms)
+    }
+}
+
+/// One peer's window, for transfer diagnostics
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerWindowState {
+    pub peer_id: String,
+    pub window: usize,
+    pub in_flight: usize,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PeerWindow {
+    size: usize,
+    in_flight: usize,
+    completed: u64,
+    failed: u64,
+}
+
+/// Per-peer request windows, shared by every download from that peer. A
+/// window grows by one with each chunk that arrives in time and halves when
+/// a request times out or fails.
+pub struct ChunkWindows {
+    config: ChunkWindowConfig,
+    peers: DashMap<String, PeerWindow>,
+}
+
+impl ChunkWindows {
+    pub fn new(config: ChunkWindowConfig) -> Self {
+        Self {
+            config,
+            peers: DashMap::new(),
+        }
+    }
+    
+    pub fn config(&self) -> ChunkWindowConfig {
+        self.config
+    }
+    
+    /// Take a request slot with `peer_id` if its window has room
+    pub fn try_acquire(self: &Arc<Self>, peer_id: &str) -> Option<WindowSlot> {
+        let mut window = self.entry(peer_id);
+        if window.in_flight >= window.size {
+            return None;
+        }
+        window.in_flight += 1;
+        Some(WindowSlot {
+            windows: self.clone(),
+            peer_id: peer_id.to_string(),
+            finished: false,
+        })
+    }
+    
+    /// Give a slot back, widening the window when the chunk came in time
+    fn release(&self, peer_id: &str, outcome: Option<bool>) {
+        let (min, max) = self.bounds();
+        let mut window = self.entry(peer_id);
+        window.in_flight = window.in_flight.saturating_sub(1);
+        let Some(timely) = outcome else {
+            return;
+        };
+        if timely {
+            window.completed += 1;
+            window.size = (window.size + 1).min(max);
+        } else {
+            window.failed += 1;
+            window.size = (window.size / 2).max(min);
+        }
+    }
+    
+    pub fn states(&self) -> Vec<PeerWindowState> {
+        let mut states: Vec<PeerWindowState> = self
+            .peers
+            .iter()
+            .map(|window| PeerWindowState {
+                peer_id: window.key().clone(),
+                window: window.size,
+                in_flight: window.in_flight,
+                completed: window.completed,
+                failed: window.failed,
+            })
+            .collect();
+        states.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        states
+    }
+    
+    /// (min, max) window, never below one request
+    fn bounds(&self) -> (usize, usize) {
+        let min = self.config.min_window.max(1);
+        (min, self.config.max_window.max(min))
+    }
+    
+    fn entry(&self, peer_id: &str) -> dashmap::mapref::one::RefMut<'_, String, PeerWindow> {
+        let (min, max) = self.bounds();
+        let initial = self.config.initial_window.clamp(min, max);
+        self.peers.entry(peer_id.to_string()).or_insert_with(|| PeerWindow {
+            size: initial,
+            in_flight: 0,
+            completed: 0,
+            failed: 0,
+        })
+    }
+}
+
+/// One outstanding chunk request. Dropped unfinished, e.g. when the
+/// download gives up, it frees its slot without counting against the peer.
+pub struct WindowSlot {
+    windows: Arc<ChunkWindows>,
+    peer_id: String,
+    finished: bool,
+}
+
+impl WindowSlot {
+    pub fn peer_id(&self) -> &str {
+        &self.peer_id
+    }
+    
+    pub fn finish(mut self, timely: bool) {
+        self.finished = true;
+        self.windows.release(&self.peer_id, Some(timely));
+    }
+}
+
+impl Drop for WindowSlot {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.windows.release(&self.peer_id, None);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Error;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+
+/// zstd level chunks are compressed at; fast enough not to hold up a LAN
+const ZSTD_LEVEL: i32 = 3;
+
+/// A sample has to shrink below this share of its size (9 in 10) for a
+/// file to be worth compressing
+const WORTHWHILE_NUMERATOR: usize = 9;
+const WORTHWHILE_DENOMINATOR: usize = 10;
+
+/// Only the start of a sample is tried, which keeps choosing cheap
+const SAMPLE_LEN: usize = 64 * 1024;
+
+/// How a share's chunks travel. Chunk and file hashes are always over the
+/// uncompressed data, so integrity checks don't depend on the codec.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkCodec {
+    #[default]
+    Raw,
+    Zstd,
+}
+
+impl ChunkCodec {
+    /// Zstd if `sample`, usually a file's first chunk, compresses well;
+    /// media and archives that are already compressed go raw
+    pub fn choose(sample: &[u8]) -> Self {
+        if sample.is_empty() {
+            return ChunkCodec::Raw;
+        }
+        let sample = &sample[..sample.len().min(SAMPLE_LEN)];
+        match zstd::bulk::compress(sample, ZSTD_LEVEL) {
+            Ok(compressed) if compressed.len() * WORTHWHILE_DENOMINATOR < sample.len() * WORTHWHILE_NUMERATOR => ChunkCodec::Zstd,
+            _ => ChunkCodec::Raw,
+        }
+    }
+    
+    pub fn encode(self, data: Bytes) -> Result<Bytes, Error> {
+        match self {
+            ChunkCodec::Raw => Ok(data),
+            ChunkCodec::Zstd => Ok(Bytes::from(zstd::bulk::compress(&data, ZSTD_LEVEL)?)),
+        }
+    }
+    
+    /// Undo `encode`, refusing anything that would come out longer than
+    /// `max_len`
+    pub fn decode(self, data: Bytes, max_len: usize) -> Result<Bytes, Error> {
+        let decoded = match self {
+            ChunkCodec::Raw => data,
+            ChunkCodec::Zstd => zstd::bulk::decompress(&data, max_len)
+                .map(Bytes::from)
+                .map_err(|e| DeskShareError::ChunkTransferFailed(format!("chunk didn't decompress: {}", e)))?,
+        };
+        if decoded.len() > max_len {
+            return Err(DeskShareError::ChunkTransferFailed(format!("chunk is over {} bytes", max_len)).into());
+        }
+        Ok(decoded)
+    }
+    
+    /// Most bytes an encoded chunk of `len` bytes can take on the wire
+    pub fn max_encoded_len(len: usize) -> usize {
+        zstd::zstd_safe::compress_bound(len).max(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_zstd_round_trips_and_refuses_oversized_chunks() {
+        let text = b"the quick brown fox jumps over the lazy dog\n".repeat(2000);
+        assert_eq!(ChunkCodec::choose(&text), ChunkCodec::Zstd);
+        assert_eq!(ChunkCodec::choose(&[]), ChunkCodec::Raw);
+        
+        let encoded = ChunkCodec::Zstd.encode(Bytes::from(text.clone())).unwrap();
+        assert!(encoded.len() < text.len() / 10);
+        assert_eq!(ChunkCodec::Zstd.decode(encoded.clone(), text.len()).unwrap(), text);
+        // A chunk that inflates past the limit is refused, not allocated
+        assert!(ChunkCodec::Zstd.decode(encoded, text.len() - 1).is_err());
+        assert!(ChunkCodec::Zstd.decode(Bytes::from_static(b"not zstd"), 1024).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    static LIVE: Cell<isize> = const { Cell::new(0) };
+    static PEAK: Cell<isize> = const { Cell::new(0) };
+}
+
+/// Adds up bytes allocated on a thread while it has counting switched on,
+/// so tests running alongside don't throw the figure off. Also tracks the
+/// most those allocations held at once, net of what the thread freed.
+struct CountingAlloc;
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.try_with(Cell::get).unwrap_or(false) {
+            let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get() + layout.size()));
+            let _ = LIVE.try_with(|live| {
+                live.set(live.get() + layout.size() as isize);
+                let _ = PEAK.try_with(|peak| peak.set(peak.get().max(live.get())));
+            });
+        }
+        System.alloc(layout)
+    }
+    
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if COUNTING.try_with(Cell::get).unwrap_or(false) {
+            let _ = LIVE.try_with(|live| live.set(live.get() - layout.size() as isize));
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+/// Start counting this thread's allocations from zero
+pub fn start() {
+    ALLOCATED.with(|allocated| allocated.set(0));
+    LIVE.with(|live| live.set(0));
+    PEAK.with(|peak| peak.set(0));
+    COUNTING.with(|counting| counting.set(true));
+}
+
+/// Stop counting; returns the bytes allocated since `start`
+pub fn stop() -> usize {
+    COUNTING.with(|counting| counting.set(false));
+    ALLOCATED.with(Cell::get)
+}
+
+/// Most bytes held at once between `start` and `stop`
+pub fn peak() -> usize {
+    PEAK.with(Cell::get) as usize
+}
@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 use std::net::{IpAddr, SocketAddr};
 use std::time::{Duration, SystemTime};
 use local_ip_address::local_ip;
 // use webrtc::mdns::{Record, RecordKind}; // TODO: Re-enable when implementing mDNS
 use tokio::net::UdpSocket;
 use tokio::sync::broadcast;
+use dashmap::DashMap;
 use serde::{Serialize, Deserialize};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -17,7 +18,7 @@ pub struct DeviceInfo {
 }
 
 pub struct NetworkDiscovery {
-    devices: HashMap<String, DeviceInfo>,
+    devices: Arc<DashMap<String, DeviceInfo>>,
     broadcast_sender: broadcast::Sender<DeviceInfo>,
     local_ip: IpAddr,
 }
@@ -28,7 +29,7 @@ impl NetworkDiscovery {
         let (tx, _) = broadcast::channel(100);
         
         NetworkDiscovery {
-            devices: HashMap::new(),
+            devices: Arc::new(DashMap::new()),
             broadcast_sender: tx,
             local_ip,
         }
@@ -67,6 +68,7 @@ impl NetworkDiscovery {
     pub async fn listen_for_devices(&mut self) {
         let mut rx = self.broadcast_sender.subscribe();
         let socket = UdpSocket::bind("0.0.0.0:5353").await.unwrap();
+        let devices = self.devices.clone();
         
         tokio::spawn(async move {
             let mut buf = [0; 1024];
@@ -86,22 +88,23 @@ impl NetworkDiscovery {
                                     .unwrap()
                                     .as_secs(),
                             };
-                            self.devices.insert(device.ip.clone(), device.clone());
+                            devices.insert(device.ip.clone(), device.clone());
                         }
                     }
                 }
             }
         });
         
+        let devices = self.devices.clone();
         tokio::spawn(async move {
             while let Ok(device) = rx.recv().await {
-                self.devices.insert(device.ip.clone(), device);
+                devices.insert(device.ip.clone(), device);
             }
         });
     }
     
     pub fn get_devices(&self) -> Vec<DeviceInfo> {
-        self.devices.values().cloned().collect()
+        self.devices.iter().map(|entry| entry.value().clone()).collect()
     }
     
     pub fn cleanup_old_devices(&mut self, max_age_seconds: u64) {
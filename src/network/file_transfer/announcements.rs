@@ -0,0 +1,555 @@
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::security::{DeviceIdentity, ProtocolClass, TrustLevel};
+
+use super::{FileTransfer, SharedFile};
+
+/// A SharedFile (or DHT record) signed by the device that announced it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedAnnouncement {
+    pub file: SharedFile,
+    /// Chunks the announcer has so far, when it doesn't have them all
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held_chunks: Option<Vec<usize>>,
+    pub public_key: [u8; 32],
+    /// Device id of `public_key`
+    pub fingerprint: String,
+    pub signature: Vec<u8>,
+}
+
+impl SignedAnnouncement {
+    pub fn sign(file: SharedFile, identity: &DeviceIdentity) -> Result<Self, Error> {
+        Self::sign_partial(file, None, identity)
+    }
+    
+    /// Announce a file still being downloaded, of which only `held_chunks`
+    /// can be fetched from us
+    pub fn sign_partial(file: SharedFile, held_chunks: Option<Vec<usize>>, identity: &DeviceIdentity) -> Result<Self, Error> {
+        let signature = identity.sign(&Self::signed_bytes(&file, held_chunks.as_deref())?).to_vec();
+        Ok(Self {
+            file,
+            held_chunks,
+            public_key: identity.public_key(),
+            fingerprint: identity.device_id(),
+            signature,
+        })
+    }
+    
+    /// Check the signature and fingerprint; returns the announcer's device id
+    pub fn verify(&self) -> Result<String, Error> {
+        let device_id = DeviceIdentity::device_id_for(&self.public_key);
+        if device_id != self.fingerprint {
+            return Err(DeskShareError::AnnouncementRejected("fingerprint doesn't match key".to_string()).into());
+        }
+        
+        let signed = Self::signed_bytes(&self.file, self.held_chunks.as_deref())?;
+        let valid = <[u8; 64]>::try_from(self.signature.as_slice())
+            .map(|signature| DeviceIdentity::verify(&self.public_key, &signed, &signature))
+            .unwrap_or(false);
+        if !valid {
+            return Err(DeskShareError::AnnouncementRejected(format!("bad signature on {}", self.file.hash)).into());
+        }
+        Ok(device_id)
+    }
+    
+    /// Whole-file announcements sign just the file, as they always have
+    fn signed_bytes(file: &SharedFile, held_chunks: Option<&[usize]>) -> Result<Vec<u8>, Error> {
+        let mut bytes = b"desk-share-net shared file v1:".to_vec();
+        bytes.extend(serde_json::to_vec(file)?);
+        if let Some(held) = held_chunks {
+            bytes.extend(b"\nheld chunks:");
+            bytes.extend(serde_json::to_vec(held)?);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Word that a device has stopped sharing a file, signed by that device
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedRetraction {
+    pub file_hash: String,
+    pub public_key: [u8; 32],
+    /// Device id of `public_key`
+    pub fingerprint: String,
+    pub signature: Vec<u8>,
+}
+
+impl SignedRetraction {
+    pub fn sign(file_hash: &str, identity: &DeviceIdentity) -> Self {
+        Self {
+            file_hash: file_hash.to_string(),
+            public_key: identity.public_key(),
+            fingerprint: identity.device_id(),
+            signature: identity.sign(&Self::signed_bytes(file_hash)).to_vec(),
+        }
+    }
+    
+    /// Check the signature and fingerprint; returns the retracting device's id
+    pub fn verify(&self) -> Result<String, Error> {
+        let device_id = DeviceIdentity::device_id_for(&self.public_key);
+        if device_id != self.fingerprint {
+            return Err(DeskShareError::AnnouncementRejected("fingerprint doesn't match key".to_string()).into());
+        }
+        let valid = <[u8; 64]>::try_from(self.signature.as_slice())
+            .map(|signature| DeviceIdentity::verify(&self.public_key, &Self::signed_bytes(&self.file_hash), &signature))
+            .unwrap_or(false);
+        if !valid {
+            return Err(DeskShareError::AnnouncementRejected(format!("bad signature on retraction of {}", self.file_hash)).into());
+        }
+        Ok(device_id)
+    }
+    
+    fn signed_bytes(file_hash: &str) -> Vec<u8> {
+        let mut bytes = b"desk-share-net retraction v1:".to_vec();
+        bytes.extend(file_hash.as_bytes());
+        bytes
+    }
+}
+
+/// A file a peer announced, with the device that signed it
+#[derive(Clone, Debug)]
+pub(super) struct AnnouncedFile {
+    file: SharedFile,
+    device_id: String,
+}
+
+/// One version of a file's metadata; demoted once its chunk hashes fail to
+/// match data that verifies against another version
+#[derive(Clone, Debug)]
+pub(super) struct AnnouncedMetadata {
+    file: SharedFile,
+    announcers: HashSet<String>,
+    demoted: bool,
+}
+
+/// A file another device is offering
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RemoteFile {
+    pub peer_id: String,
+    pub hash: String,
+    pub name: String,
+    pub size: u64,
+}
+
+fn remote_file(peer_id: &str, file: &SharedFile) -> RemoteFile {
+    RemoteFile {
+        peer_id: peer_id.to_string(),
+        hash: file.hash.clone(),
+        name: file.name.clone(),
+        size: file.size,
+    }
+}
+
+impl FileTransfer {
+    /// What `peer_id` has announced, by name
+    pub fn get_remote_files(&self, peer_id: &str) -> Vec<RemoteFile> {
+        let mut files: Vec<RemoteFile> = self
+            .remote_files
+            .get(peer_id)
+            .map(|files| files.values().map(|announced| remote_file(peer_id, &announced.file)).collect())
+            .unwrap_or_default();
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        files
+    }
+    
+    /// Announced files from any peer whose name contains `query`, ignoring case
+    pub fn search_remote_files(&self, query: &str) -> Vec<RemoteFile> {
+        let query = query.to_lowercase();
+        let mut files: Vec<RemoteFile> = self
+            .remote_files
+            .iter()
+            .flat_map(|peer| {
+                peer.values()
+                    .filter(|announced| announced.file.name.to_lowercase().contains(&query))
+                    .map(|announced| remote_file(peer.key(), &announced.file))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        files.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.peer_id.cmp(&b.peer_id)));
+        files
+    }
+    
+    /// Drop what `peer_id` announced, once discovery no longer sees it
+    /// `peer_id` left the network: it stops being a source for anything,
+    /// and files only it offered are forgotten
+    pub async fn forget_remote_peer(&self, peer_id: &str) {
+        if self.remote_files.remove(peer_id).is_some() {
+            tracing::debug!("Forgot the files announced by {}", peer_id);
+        }
+        for mut versions in self.announcements.iter_mut() {
+            for version in versions.iter_mut() {
+                version.announcers.remove(peer_id);
+            }
+        }
+        for mut holders in self.chunk_holders.iter_mut() {
+            holders.remove(peer_id);
+        }
+        
+        let downloading: HashSet<String> = self.downloading_files.read().await.keys().cloned().collect();
+        let mut peers_with_files = self.peers_with_files.write().await;
+        let unheld: Vec<String> = peers_with_files
+            .iter_mut()
+            .filter_map(|(file_hash, holders)| (holders.remove(peer_id) && holders.is_empty()).then(|| file_hash.clone()))
+            .collect();
+        for file_hash in unheld {
+            if !downloading.contains(&file_hash) {
+                self.drop_unheld(&mut peers_with_files, &file_hash);
+            }
+        }
+    }
+    
+    /// Peers we can fetch `file_hash` from
+    pub async fn get_peers_for_file(&self, file_hash: &str) -> Vec<String> {
+        let mut peers: Vec<String> = self
+            .peers_with_files
+            .read()
+            .await
+            .get(file_hash)
+            .map(|holders| holders.iter().cloned().collect())
+            .unwrap_or_default();
+        peers.sort();
+        peers
+    }
+    
+    /// Nobody is left offering `file_hash`, so it can't be downloaded any
+    /// more; forget it unless we share it ourselves
+    fn drop_unheld(&self, peers_with_files: &mut HashMap<String, HashSet<String>>, file_hash: &str) {
+        if self.share_stats.contains_key(file_hash) {
+            return;
+        }
+        peers_with_files.remove(file_hash);
+        self.chunk_holders.remove(file_hash);
+        self.shared_files.remove(file_hash);
+        self.announcements.remove(file_hash);
+    }
+    
+    /// Our signed announcement for a file we share or are still downloading,
+    /// ready to send to peers
+    pub fn signed_announcement(&self, file_hash: &str) -> Result<SignedAnnouncement, Error> {
+        let identity = self
+            .identity
+            .as_ref()
+            .ok_or_else(|| DeskShareError::InvalidConfig("no identity to sign announcements".to_string()))?;
+        let file = self
+            .shared_files
+            .get(file_hash)
+            .map(|file| file.clone())
+            .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.to_string()))?;
+        // A file still downloading offers just the chunks it has
+        let held: Vec<usize> = file
+            .chunks
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk_hash)| self.shared_chunks.contains_key(*chunk_hash) || self.file_chunks.contains_key(*chunk_hash))
+            .map(|(index, _)| index)
+            .collect();
+        let held = (held.len() < file.total_chunks).then_some(held);
+        SignedAnnouncement::sign_partial(file, held, identity)
+    }
+    
+    /// Record that `from_peer` has a file. Nothing is trusted until the
+    /// signature verifies; when peers disagree about a file's chunks, the
+    /// first version stays in use until downloaded data says otherwise.
+    pub async fn handle_announcement(&self, from_peer: String, announcement: SignedAnnouncement) -> Result<(), Error> {
+        // Dropped before the signature check, which is the expensive part
+        if !self.admits(&from_peer, ProtocolClass::Signaling) {
+            return Ok(());
+        }
+        let device_id = announcement.verify().map_err(|e| {
+            tracing::warn!("Rejected announcement from {}: {}", from_peer, e);
+            e
+        })?;
+        if announcement.file.check_manifest().is_err() {
+            return Err(DeskShareError::AnnouncementRejected(format!("{}'s chunk hashes don't match its merkle root", announcement.file.hash)).into());
+        }
+        
+        if self.refuses(&device_id) || self.refuses(&from_peer) {
+            return Err(DeskShareError::AnnouncementRejected(format!("{} is blocked", device_id)).into());
+        }
+        let level = self
+            .trust_store
+            .as_ref()
+            .map(|trust_store| trust_store.level(&device_id))
+            .unwrap_or(TrustLevel::Unknown);
+        match level {
+            TrustLevel::Unknown if self.security_config.strict_announcements => {
+                return Err(DeskShareError::AnnouncementRejected(format!("{} isn't paired", device_id)).into());
+            }
+            _ => {}
+        }
+        
+        let (file, held_chunks) = (announcement.file, announcement.held_chunks);
+        let file_hash = file.hash.clone();
+        self.remote_files.entry(from_peer.clone()).or_default().insert(
+            file_hash.clone(),
+            AnnouncedFile {
+                file: file.clone(),
+                device_id: device_id.clone(),
+            },
+        );
+        let demoted = {
+            let mut versions = self.announcements.entry(file_hash.clone()).or_default();
+            let index = match versions.iter().position(|version| version.file.same_metadata(&file)) {
+                Some(index) => index,
+                None => {
+                    versions.push(AnnouncedMetadata {
+                        file: file.clone(),
+                        announcers: HashSet::new(),
+                        demoted: false,
+                    });
+                    versions.len() - 1
+                }
+            };
+            versions[index].announcers.insert(from_peer.clone());
+            versions[index].demoted
+        };
+        if demoted {
+            tracing::warn!("Ignoring {} from {}: its chunk hashes already failed", file_hash, from_peer);
+            return Ok(());
+        }
+        
+        let matches_active = match self.shared_files.get(&file_hash) {
+            Some(active) => active.same_metadata(&file),
+            None => {
+                self.shared_files.insert(file_hash.clone(), file);
+                true
+            }
+        };
+        if matches_active {
+            let total_chunks = self.shared_files.get(&file_hash).map_or(0, |file| file.total_chunks);
+            match held_chunks {
+                Some(held) => {
+                    let held = held.into_iter().filter(|&index| index < total_chunks).collect();
+                    self.chunk_holders.entry(file_hash.clone()).or_default().insert(from_peer.clone(), held);
+                }
+                None => {
+                    if let Some(mut holders) = self.chunk_holders.get_mut(&file_hash) {
+                        holders.remove(&from_peer);
+                    }
+                }
+            }
+            self.peers_with_files
+                .write()
+                .await
+                .entry(file_hash)
+                .or_insert_with(HashSet::new)
+                .insert(from_peer);
+        } else {
+            tracing::info!("{} announced conflicting metadata for {}", from_peer, file_hash);
+        }
+        
+        Ok(())
+    }
+    
+    /// `from_peer` no longer has a file. Only the device that announced it
+    /// can retract it; a download already under way keeps its source.
+    pub async fn handle_retraction(&self, from_peer: String, retraction: SignedRetraction) -> Result<(), Error> {
+        if !self.admits(&from_peer, ProtocolClass::Signaling) {
+            return Ok(());
+        }
+        let device_id = retraction.verify()?;
+        let file_hash = retraction.file_hash;
+        let announced_by = self
+            .remote_files
+            .get(&from_peer)
+            .and_then(|files| files.get(&file_hash).map(|announced| announced.device_id.clone()));
+        if announced_by.as_deref() != Some(device_id.as_str()) {
+            return Err(DeskShareError::AnnouncementRejected(format!("{} didn't announce {}", device_id, file_hash)).into());
+        }
+        if let Some(mut files) = self.remote_files.get_mut(&from_peer) {
+            files.remove(&file_hash);
+        }
+        if let Some(mut versions) = self.announcements.get_mut(&file_hash) {
+            for version in versions.iter_mut() {
+                version.announcers.remove(&from_peer);
+            }
+        }
+        
+        if self.downloading_files.read().await.contains_key(&file_hash) {
+            return Ok(());
+        }
+        let mut peers_with_files = self.peers_with_files.write().await;
+        let unheld = peers_with_files.get_mut(&file_hash).is_some_and(|holders| {
+            holders.remove(&from_peer);
+            holders.is_empty()
+        });
+        if let Some(mut holders) = self.chunk_holders.get_mut(&file_hash) {
+            holders.remove(&from_peer);
+        }
+        if unheld {
+            self.drop_unheld(&mut peers_with_files, &file_hash);
+        }
+        
+        Ok(())
+    }
+    
+    /// Prefer the metadata versions whose hash for `chunk_index` matches real
+    /// data and demote the rest, along with the peers that announced them
+    pub(super) async fn settle_metadata(&self, file_hash: &str, chunk_index: usize, chunk_hash: &str) {
+        let (preferred, announcers) = {
+            let Some(mut versions) = self.announcements.get_mut(file_hash) else {
+                return;
+            };
+            let verifies = |version: &AnnouncedMetadata| {
+                version.file.chunks.get(chunk_index).map(String::as_str) == Some(chunk_hash)
+            };
+            // Data that matches no version says nothing about which is right
+            if !versions.iter().any(verifies) {
+                return;
+            }
+            
+            let mut preferred = None;
+            let mut announcers = HashSet::new();
+            for version in versions.iter_mut() {
+                if verifies(version) {
+                    preferred.get_or_insert_with(|| version.file.clone());
+                    announcers.extend(version.announcers.iter().cloned());
+                } else if !version.demoted {
+                    tracing::warn!("Demoting metadata for {} announced by {:?}", file_hash, version.announcers);
+                    version.demoted = true;
+                }
+            }
+            match preferred {
+                Some(preferred) => (preferred, announcers),
+                None => return,
+            }
+        };
+        
+        let switch = self
+            .shared_files
+            .get(file_hash)
+            .map(|active| !active.same_metadata(&preferred))
+            .unwrap_or(true);
+        if switch {
+            tracing::info!("Switching {} to the metadata its chunks verify against", file_hash);
+            if let Some(downloading) = self.downloading_files.write().await.get_mut(file_hash) {
+                downloading.chunks_expected = preferred.total_chunks;
+                downloading.chunks_received.clear();
+                downloading.progress.reset();
+                if let Some(output) = &downloading.output {
+                    if let Err(e) = output.lock().unwrap().set_len(preferred.size) {
+                        tracing::warn!("Couldn't resize the download of {}: {}", file_hash, e);
+                    }
+                }
+            }
+            self.shared_files.insert(file_hash.to_string(), preferred);
+        }
+        self.peers_with_files.write().await.insert(file_hash.to_string(), announcers);
+    }
+    
+    pub(super) async fn announce_file(&self, file: &SharedFile) -> Result<(), Error> {
+        // Store in local registry
+        self.shared_files.insert(file.hash.clone(), file.clone());
+        
+        // Announce to connected peers
+        // This would use our P2P network broadcast
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::network::file_transfer::TransferStatus;
+    use crate::network::file_transfer::tests::{describe, holders};
+    use crate::security::{SecurityConfig, TrustStore};
+    
+    #[tokio::test]
+    async fn test_forged_announcement_is_rejected() {
+        let trust_store = Arc::new(TrustStore::new());
+        let file_transfer = FileTransfer::new()
+            .await
+            .with_trust_store(trust_store.clone())
+            .with_security_config(SecurityConfig { strict_announcements: true, ..SecurityConfig::default() });
+        let bob = DeviceIdentity::generate();
+        let file = describe(b"quarterly numbers", 8);
+        
+        let mut forged = SignedAnnouncement::sign(file.clone(), &bob).unwrap();
+        forged.file.chunks[0] = "0".repeat(64);
+        let err = file_transfer.handle_announcement("10.0.0.3".to_string(), forged).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "announcement_rejected");
+        assert!(file_transfer.shared_files.get(&file.hash).is_none());
+        
+        // Validly signed, but strict mode wants a paired device
+        let genuine = SignedAnnouncement::sign(file.clone(), &bob).unwrap();
+        assert!(file_transfer.handle_announcement("10.0.0.3".to_string(), genuine.clone()).await.is_err());
+        assert!(holders(&file_transfer, &file.hash).await.is_empty());
+        
+        trust_store.record_paired(&bob.device_id(), "10.0.0.3", &bob.public_key());
+        file_transfer.handle_announcement("10.0.0.3".to_string(), genuine).await.unwrap();
+        assert_eq!(holders(&file_transfer, &file.hash).await, vec!["10.0.0.3"]);
+    }
+    
+    #[tokio::test]
+    async fn test_announced_files_are_browsable_until_the_peer_leaves() {
+        let file_transfer = FileTransfer::new().await;
+        let bob = DeviceIdentity::generate();
+        let carol = DeviceIdentity::generate();
+        let mut report = describe(b"quarterly numbers", 8);
+        report.name = "Q3 Report.pdf".to_string();
+        let mut notes = describe(b"meeting notes", 8);
+        notes.name = "notes.txt".to_string();
+        
+        for (peer, identity, file) in [("10.0.0.3", &bob, &report), ("10.0.0.3", &bob, &notes), ("10.0.0.4", &carol, &report)] {
+            file_transfer
+                .handle_announcement(peer.to_string(), SignedAnnouncement::sign(file.clone(), identity).unwrap())
+                .await
+                .unwrap();
+        }
+        let names: Vec<String> = file_transfer.get_remote_files("10.0.0.3").into_iter().map(|file| file.name).collect();
+        assert_eq!(names, vec!["Q3 Report.pdf", "notes.txt"]);
+        let found = file_transfer.search_remote_files("REPORT");
+        assert_eq!(found.iter().map(|file| file.peer_id.as_str()).collect::<Vec<_>>(), vec!["10.0.0.3", "10.0.0.4"]);
+        assert_eq!(found[0].hash, report.hash);
+        assert_eq!(found[0].size, report.size);
+        
+        file_transfer.forget_remote_peer("10.0.0.3").await;
+        assert!(file_transfer.get_remote_files("10.0.0.3").is_empty());
+        assert_eq!(file_transfer.search_remote_files("report").len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_conflicting_metadata_resolved_by_verified_chunks() {
+        let file_transfer = FileTransfer::new().await;
+        let data = b"the real contents of the report";
+        let genuine = describe(data, 16);
+        let mut poisoned = describe(b"something else entirely, honest", 16);
+        poisoned.hash = genuine.hash.clone();
+        
+        // The poisoned copy arrives first and becomes the working metadata
+        let mallory = DeviceIdentity::generate();
+        let bob = DeviceIdentity::generate();
+        file_transfer
+            .handle_announcement("10.0.0.6".to_string(), SignedAnnouncement::sign(poisoned.clone(), &mallory).unwrap())
+            .await
+            .unwrap();
+        file_transfer
+            .handle_announcement("10.0.0.3".to_string(), SignedAnnouncement::sign(genuine.clone(), &bob).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(holders(&file_transfer, &genuine.hash).await, vec!["10.0.0.6"]);
+        
+        let output = std::env::temp_dir().join(format!("dsn-tiebreak-{}", std::process::id()));
+        file_transfer.download_file(&genuine.hash, &output).await.unwrap();
+        for (index, chunk) in data.chunks(16).enumerate() {
+            file_transfer.receive_chunk(&genuine.hash, index, chunk.to_vec()).await.unwrap();
+        }
+        
+        assert_eq!(file_transfer.shared_files.get(&genuine.hash).unwrap().chunks, genuine.chunks);
+        assert_eq!(holders(&file_transfer, &genuine.hash).await, vec!["10.0.0.3"]);
+        let progress = file_transfer.get_transfer_progress().await;
+        assert_eq!(progress[0].status, TransferStatus::Completed);
+        
+        // Re-announcing the demoted version doesn't bring it back
+        file_transfer
+            .handle_announcement("10.0.0.6".to_string(), SignedAnnouncement::sign(poisoned, &mallory).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(holders(&file_transfer, &genuine.hash).await, vec!["10.0.0.3"]);
+        let _ = std::fs::remove_file(output);
+    }
+}
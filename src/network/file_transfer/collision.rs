@@ -0,0 +1,217 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+
+use super::{FileTransfer, SharedFile, TransferProgress, TransferStatus, CHUNK_SIZE};
+
+/// What a download does when its output path is already taken
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    /// Replace what's there
+    Overwrite,
+    /// Save as "name (1).ext", or the first number that's free
+    #[default]
+    RenameWithSuffix,
+    /// Leave it and count the download as done, marked skipped. A file
+    /// that's the same as the download counts as done under every policy.
+    Skip,
+    /// Leave it and fail the download
+    Fail,
+}
+
+/// `path` with " (n)" added to its name, before the extension
+fn numbered_path(path: &Path, n: usize) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{} ({}).{}", stem, n, extension.to_string_lossy()),
+        None => format!("{} ({})", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+impl FileTransfer {
+    /// Where to download `file` given what's at `output_path`, or None if
+    /// the file is there already and has been marked complete
+    pub(super) async fn resolve_collision(&self, file: &SharedFile, output_path: &Path, policy: CollisionPolicy) -> Result<Option<PathBuf>, Error> {
+        // Paths other downloads will write to count as taken too
+        let targets: HashSet<PathBuf> = self
+            .downloading_files
+            .read()
+            .await
+            .values()
+            .map(|downloading| downloading.output_path.clone())
+            .collect();
+        let taken = |path: &Path| path.exists() || targets.contains(path);
+        if !taken(output_path) {
+            return Ok(Some(output_path.to_path_buf()));
+        }
+        
+        // Nothing to fetch if what's there is the file already
+        if !targets.contains(output_path) && self.already_have(file, output_path).await? {
+            self.skip_download(file, output_path, true).await;
+            return Ok(None);
+        }
+        
+        match policy {
+            CollisionPolicy::Overwrite => Ok(Some(output_path.to_path_buf())),
+            CollisionPolicy::RenameWithSuffix => Ok((1..).map(|n| numbered_path(output_path, n)).find(|path| !taken(path))),
+            CollisionPolicy::Skip => {
+                self.skip_download(file, output_path, false).await;
+                Ok(None)
+            }
+            CollisionPolicy::Fail => Err(DeskShareError::OutputFileExists(output_path.display().to_string()).into()),
+        }
+    }
+    
+    /// Whether `path` already holds `file`, read back a chunk at a time
+    pub(super) async fn already_have(&self, file: &SharedFile, path: &Path) -> Result<bool, Error> {
+        let Ok(existing) = std::fs::File::open(path) else {
+            return Ok(false);
+        };
+        // Hashing is only worth it at the right size
+        if !existing.metadata()?.is_file() || existing.metadata()?.len() != file.size {
+            return Ok(false);
+        }
+        let algorithm = file.hash_algorithm;
+        let hash = self
+            .off_runtime(move |cancel| Self::hash_chunks(existing, CHUNK_SIZE, algorithm, cancel, |_, _| {}))
+            .await?;
+        Ok(hash == file.hash)
+    }
+    
+    /// List `file` as done without downloading it, since `output_path` has
+    /// it or, unless `verified`, something else the user chose to keep
+    pub(super) async fn skip_download(&self, file: &SharedFile, output_path: &Path, verified: bool) {
+        if verified {
+            tracing::info!("{} is already at {}, skipping the download", file.name, output_path.display());
+        } else {
+            tracing::info!("Keeping what's at {} and skipping {}", output_path.display(), file.name);
+        }
+        self.publish_progress(TransferProgress {
+            file_name: file.name.clone(),
+            file_hash: file.hash.clone(),
+            bytes_transferred: file.size,
+            total_bytes: file.size,
+            percentage: 100.0,
+            status: TransferStatus::Completed,
+            bytes_per_second: 0.0,
+            eta_seconds: None,
+            output_path: Some(output_path.to_path_buf()),
+            verified_hash: verified.then(|| file.hash.clone()),
+            skipped: true,
+            error_message: None,
+            error_kind: None,
+        })
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use bytes::Bytes;
+    use crate::network::chunk_pipeline::MemoryChunkTransport;
+    use crate::network::file_transfer::SignedAnnouncement;
+    use crate::network::file_transfer::tests::{describe, scratch_dir, RecordingPeers};
+    use crate::security::DeviceIdentity;
+    
+    #[tokio::test]
+    async fn test_files_already_in_place_are_not_fetched_again() {
+        let data: Vec<u8> = (0..8 * 32).map(|i| (i * 7) as u8).collect();
+        let file = describe(&data, 32);
+        let transport = Arc::new(RecordingPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            fetches: Mutex::new(Vec::new()),
+        });
+        let receiver = FileTransfer::new().await.with_chunk_transport(transport.clone());
+        receiver.handle_announcement("10.0.0.3".to_string(), SignedAnnouncement::sign(file.clone(), &DeviceIdentity::generate()).unwrap()).await.unwrap();
+        let dir = scratch_dir("in-place");
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("report.pdf");
+        
+        // The same bytes: done at once, under the policy that would rename
+        std::fs::write(&output, &data).unwrap();
+        receiver.download_file(&file.hash, &output).await.unwrap();
+        assert!(transport.fetches.lock().unwrap().is_empty());
+        let progress = receiver.get_transfer_progress().await;
+        assert_eq!(progress[0].status, TransferStatus::Completed);
+        assert_eq!(progress[0].bytes_transferred, file.size);
+        assert!(progress[0].skipped);
+        assert!(!dir.join("report (1).pdf").exists());
+        
+        // Same size, different bytes: the policy decides
+        receiver.active_transfers.remove(&file.hash);
+        let mut stale = data.clone();
+        stale[100] ^= 1;
+        std::fs::write(&output, &stale).unwrap();
+        receiver.download_file(&file.hash, &output).await.unwrap();
+        assert_eq!(transport.fetches.lock().unwrap().len(), file.total_chunks);
+        assert_eq!(std::fs::read(&output).unwrap(), stale);
+        assert_eq!(std::fs::read(dir.join("report (1).pdf")).unwrap(), data);
+        let progress = receiver.get_transfer_progress().await;
+        assert!(progress[0].status == TransferStatus::Completed && !progress[0].skipped);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_existing_output_files_follow_the_collision_policy() {
+        let data: Vec<u8> = (0..4 * 1024).map(|i| (i % 193) as u8).collect();
+        let file = describe(&data, 1024);
+        let (transport, _) = MemoryChunkTransport::new();
+        transport.offer(&file.hash, data.chunks(1024).map(Bytes::copy_from_slice));
+        let dir = scratch_dir("collisions");
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("report.pdf");
+        let receiver = FileTransfer::new().await.with_chunk_transport(Arc::new(transport));
+        let download = |policy| {
+            let (receiver, file, output) = (&receiver, &file, &output);
+            async move {
+                std::fs::write(output, b"an older report").unwrap();
+                receiver.shared_files.insert(file.hash.clone(), file.clone());
+                receiver.peers_with_files.write().await.insert(file.hash.clone(), HashSet::from(["10.0.0.2".to_string()]));
+                let result = receiver.download_file_with_policy(&file.hash, output, policy).await;
+                receiver.downloading_files.write().await.remove(&file.hash);
+                receiver.active_transfers.remove(&file.hash);
+                result
+            }
+        };
+        
+        download(CollisionPolicy::RenameWithSuffix).await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), b"an older report");
+        assert_eq!(std::fs::read(dir.join("report (1).pdf")).unwrap(), data);
+        download(CollisionPolicy::RenameWithSuffix).await.unwrap();
+        assert_eq!(std::fs::read(dir.join("report (2).pdf")).unwrap(), data);
+        
+        let err = download(CollisionPolicy::Fail).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "output_file_exists");
+        assert_eq!(std::fs::read(&output).unwrap(), b"an older report");
+        
+        // Skipping keeps what's there too, but as a finished download
+        receiver.download_file_with_policy(&file.hash, &output, CollisionPolicy::Skip).await.unwrap();
+        let progress = receiver.get_transfer_progress().await;
+        assert_eq!((progress[0].status, progress[0].skipped), (TransferStatus::Completed, true));
+        assert_eq!(progress[0].verified_hash, None);
+        assert_eq!(std::fs::read(&output).unwrap(), b"an older report");
+        receiver.active_transfers.remove(&file.hash);
+        
+        download(CollisionPolicy::Overwrite).await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        
+        // The same file already there is complete without fetching anything
+        std::fs::write(&output, &data).unwrap();
+        receiver.shared_files.insert(file.hash.clone(), file.clone());
+        receiver.download_file_with_policy(&file.hash, &output, CollisionPolicy::Skip).await.unwrap();
+        assert!(receiver.downloading_files.read().await.is_empty());
+        let progress = receiver.get_transfer_progress().await;
+        assert_eq!(progress[0].status, TransferStatus::Completed);
+        assert_eq!(progress[0].verified_hash.as_deref(), Some(file.hash.as_str()));
+        assert!(progress[0].skipped);
+        assert!(!dir.join("report (3).pdf").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,510 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::watch;
+use bytes::Bytes;
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::network::codec::ChunkCodec;
+use crate::network::merkle::MerkleTree;
+use crate::network::progress::ProgressAccumulator;
+
+use super::{
+    check_disk_space, chunk_len, partial_path, preallocate, write_at, CollisionPolicy, DownloadingFile, FileChunk,
+    FileTransfer, ShareKind, SharedFile, TransferProgress, TransferStatus, CHUNK_SIZE, MAX_CHUNK_SIZE,
+};
+
+impl FileTransfer {
+    pub async fn download_file(&self, file_hash: &str, output_path: &Path) -> Result<(), Error> {
+        self.download_file_with_policy(file_hash, output_path, self.collision_policy).await
+    }
+    
+    /// Download, handling an existing file at `output_path` by `policy`
+    /// rather than the default
+    pub async fn download_file_with_policy(&self, file_hash: &str, output_path: &Path, policy: CollisionPolicy) -> Result<(), Error> {
+        if let Some(status) = self.start_download(file_hash, output_path, policy).await? {
+            self.fetch_download(file_hash, status).await?;
+        }
+        Ok(())
+    }
+    
+    /// List a download, running or queued behind the others; None if there's
+    /// nothing to fetch
+    pub(super) async fn start_download(&self, file_hash: &str, output_path: &Path, policy: CollisionPolicy) -> Result<Option<TransferStatus>, Error> {
+        // Get file info from DHT or direct from peers. Copied out so the map
+        // isn't held across the awaits below.
+        let Some(file) = self.shared_files.get(file_hash).map(|file| file.clone()) else {
+            return Err(DeskShareError::FileNotFound(file_hash.to_string()).into());
+        };
+        // Folders are rebuilt into whatever is there, as before, and short
+        // texts aren't written at all
+        let output_path = match file.kind {
+            ShareKind::File | ShareKind::Text if !file.is_inline_text() => match self.resolve_collision(&file, output_path, policy).await? {
+                Some(output_path) => output_path,
+                None => return Ok(None),
+            },
+            _ => output_path.to_path_buf(),
+        };
+        let output_path = output_path.as_path();
+        check_disk_space(output_path, file.size)?;
+        self.download_queue.lock().unwrap().waiting.push_back(file_hash.to_string());
+        let status = if self.take_slot(file_hash) { TransferStatus::InProgress } else { TransferStatus::Queued };
+        self.begin_download_as(file, output_path, status).await?;
+        Ok(Some(status))
+    }
+    
+    /// Wait out a download's turn if it was queued, then fetch its chunks
+    pub(super) async fn fetch_download(&self, file_hash: &str, status: TransferStatus) -> Result<(), Error> {
+        if status == TransferStatus::Queued && !self.wait_for_slot(file_hash).await? {
+            return Ok(());
+        }
+        
+        // Request chunks from multiple peers
+        self.request_chunks(file_hash).await
+    }
+    
+    /// Track a download and list it as in progress; nothing is requested yet
+    pub(super) async fn begin_download(&self, file: SharedFile, output_path: &Path) -> Result<(), Error> {
+        self.begin_download_as(file, output_path, TransferStatus::InProgress).await
+    }
+    
+    async fn begin_download_as(&self, file: SharedFile, output_path: &Path, status: TransferStatus) -> Result<(), Error> {
+        let output = match file.kind {
+            ShareKind::File | ShareKind::Text if !file.is_inline_text() => {
+                let (path, size) = (output_path.to_path_buf(), file.size);
+                let output = tokio::task::spawn_blocking(move || preallocate(&path, size))
+                    .await
+                    .map_err(|e| DeskShareError::Internal(e.to_string()))??;
+                Some(output)
+            }
+            _ => None,
+        };
+        self.track_download(file, output_path, status, output, HashSet::new()).await;
+        Ok(())
+    }
+    
+    /// List a download as `status`, already holding the chunks in `received`
+    pub(super) async fn track_download(
+        &self,
+        file: SharedFile,
+        output_path: &Path,
+        status: TransferStatus,
+        output: Option<std::fs::File>,
+        received: HashSet<usize>,
+    ) {
+        let file_hash = file.hash.clone();
+        let done: u64 = received.iter().map(|&index| chunk_len(&file, index)).sum();
+        let saved_to = (!file.is_inline_text()).then(|| output_path.to_path_buf());
+        if output.is_some() {
+            for &index in &received {
+                self.serve_downloaded(&file_hash, &file.chunks[index], index, file.chunk_size, chunk_len(&file, index), output_path);
+            }
+        }
+        let downloading = DownloadingFile {
+            file_hash: file.hash.clone(),
+            chunks_received: received,
+            chunks_expected: file.total_chunks,
+            peers: HashSet::new(),
+            chunk_attempts: HashMap::new(),
+            output_path: output_path.to_path_buf(),
+            progress: Arc::new(ProgressAccumulator::starting_at(done)),
+            output: output.map(|output| Arc::new(Mutex::new(output))),
+            alive: watch::channel(()).0,
+        };
+        
+        self.downloading_files.write().await.insert(file.hash.clone(), downloading);
+        self.download_started.insert(file.hash.clone(), Instant::now());
+        
+        // Create progress entry
+        let progress = TransferProgress {
+            file_name: file.name,
+            file_hash: file.hash,
+            bytes_transferred: done,
+            total_bytes: file.size,
+            percentage: if file.size > 0 { done as f64 / file.size as f64 * 100.0 } else { 0.0 },
+            status,
+            bytes_per_second: 0.0,
+            eta_seconds: None,
+            output_path: saved_to,
+            verified_hash: None,
+            skipped: false,
+            error_message: None,
+            error_kind: None,
+        };
+        
+        self.publish_progress(progress).await;
+        self.save_partial(&file_hash).await;
+    }
+    
+    /// Take a chunk of a file we're downloading; the data is checked against
+    /// the announced chunk hashes before it counts. Chunks for anything the
+    /// user (or an auto-accept policy) hasn't accepted are dropped unread.
+    pub async fn receive_chunk(&self, file_hash: &str, chunk_index: usize, data: impl Into<Bytes>) -> Result<(), Error> {
+        let data = data.into();
+        if !self.downloading_files.read().await.contains_key(file_hash) {
+            tracing::warn!("Dropping chunk {} of {}: not accepted", chunk_index, file_hash);
+            return Err(DeskShareError::ChunkTransferFailed(format!("{} hasn't been accepted", file_hash)).into());
+        }
+        // Chunks are as long as the sender's chunk size, the last one shorter,
+        // and are hashed as they were before compression
+        let (codec, chunk_size) = self
+            .shared_files
+            .get(file_hash)
+            .map(|file| (file.codec, file.chunk_size as usize))
+            .unwrap_or((ChunkCodec::Raw, MAX_CHUNK_SIZE));
+        let data = match codec {
+            ChunkCodec::Raw => codec.decode(data, chunk_size)?,
+            ChunkCodec::Zstd => tokio::task::spawn_blocking(move || codec.decode(data, chunk_size))
+                .await
+                .map_err(|e| DeskShareError::Internal(e.to_string()))??,
+        };
+        
+        let chunk_hash = Self::calculate_chunk_hash(self.algorithm_of(file_hash), chunk_index, &data);
+        self.settle_metadata(file_hash, chunk_index, &chunk_hash).await;
+        self.handle_chunk_received(file_hash, &chunk_hash, chunk_index, data).await
+    }
+    
+    /// Whether the disk a download is going to still has room for the
+    /// chunks it hasn't written yet
+    pub(super) async fn check_download_space(&self, file_hash: &str) -> Result<(), DeskShareError> {
+        let written = self
+            .downloading_files
+            .read()
+            .await
+            .get(file_hash)
+            .map(|downloading| (downloading.output_path.clone(), downloading.chunks_received.len() as u64));
+        let sizes = self.shared_files.get(file_hash).map(|file| (file.size, file.chunk_size));
+        match (written, sizes) {
+            (Some((output_path, chunks)), Some((size, chunk_size))) => check_disk_space(&output_path, size.saturating_sub(chunks * chunk_size)),
+            _ => Ok(()),
+        }
+    }
+    
+    pub(super) async fn fail_download(&self, file_hash: &str, error: DeskShareError) -> Result<(), Error> {
+        tracing::warn!("Download of {} failed: {}", file_hash, error);
+        self.transition_because(file_hash, TransferStatus::Failed, Some(&error)).await?;
+        let partial = self
+            .downloading_files
+            .read()
+            .await
+            .get(file_hash)
+            .filter(|downloading| downloading.output.is_some())
+            .map(|downloading| downloading.output_path.clone());
+        if let Some(partial) = partial {
+            self.stop_serving_download(file_hash, &partial);
+            let _ = tokio::fs::remove_file(partial_path(&partial)).await;
+            let _ = tokio::fs::remove_file(partial).await;
+        }
+        if let Some(folder_hash) = self.folder_of(file_hash) {
+            self.refresh_folder(&folder_hash).await;
+        }
+        Err(error.into())
+    }
+    
+    /// Record a chunk of the download for `file_hash`, writing it straight
+    /// to its place in the output file. The download's lock is released
+    /// before progress is touched, so the two maps are never held together.
+    pub(super) async fn handle_chunk_received(&self, file_hash: &str, chunk_hash: &str, chunk_index: usize, data: Bytes) -> Result<(), Error> {
+        let chunk_size = self.shared_files.get(file_hash).and_then(|file| {
+            let expected = file.chunks.get(chunk_index).map(String::as_str) == Some(chunk_hash);
+            expected.then_some(file.chunk_size)
+        });
+        let Some(chunk_size) = chunk_size else {
+            return Ok(());
+        };
+        
+        let output = match self.downloading_files.read().await.get(file_hash) {
+            Some(downloading) if !downloading.chunks_received.contains(&chunk_index) => downloading.output.clone(),
+            _ => return Ok(()),
+        };
+        let len = data.len() as u64;
+        match output {
+            // Chunks land where they belong whatever order they come in, and
+            // nothing waits in memory
+            Some(output) => {
+                let offset = chunk_index as u64 * chunk_size;
+                tokio::task::spawn_blocking(move || write_at(&output, offset, &data))
+                    .await
+                    .map_err(|e| DeskShareError::Internal(e.to_string()))??;
+            }
+            // A folder's manifest is read as a whole once it's all in
+            None => {
+                self.file_chunks.entry(chunk_hash.to_string()).or_insert_with(|| FileChunk {
+                    chunk_hash: chunk_hash.to_string(),
+                    data,
+                    index: chunk_index,
+                    file_hash: file_hash.to_string(),
+                });
+            }
+        }
+        
+        let (accumulator, completed) = {
+            let mut downloading_files = self.downloading_files.write().await;
+            let Some(downloading) = downloading_files.get_mut(file_hash) else {
+                return Ok(());
+            };
+            if !downloading.chunks_received.insert(chunk_index) {
+                return Ok(());
+            }
+            if downloading.output.is_some() {
+                self.serve_downloaded(file_hash, chunk_hash, chunk_index, chunk_size, len, &downloading.output_path);
+            }
+            downloading.progress.add(len);
+            let completed = downloading.chunks_received.len() == downloading.chunks_expected;
+            (downloading.progress.clone(), completed.then(|| downloading.output_path.clone()))
+        };
+        
+        if let Some(output_path) = &completed {
+            let is_folder = self.shared_files.get(file_hash).is_some_and(|file| file.kind == ShareKind::Directory);
+            let is_text = self.shared_files.get(file_hash).is_some_and(|file| file.is_inline_text());
+            if is_folder {
+                let chunk_hashes = self.shared_files.get(file_hash).map(|file| file.chunks.clone()).unwrap_or_default();
+                return self.open_folder(file_hash, output_path, &chunk_hashes).await;
+            }
+            // Cancelled while the last chunk was being written: don't leave half of it
+            if !self.downloading_files.read().await.contains_key(file_hash) {
+                if !is_text {
+                    self.stop_serving_download(file_hash, output_path);
+                    let _ = tokio::fs::remove_file(output_path).await;
+                }
+                return Ok(());
+            }
+            // Every chunk checked out, but the whole has to match the file hash
+            // too, read back from the disk a chunk at a time
+            self.transition(file_hash, TransferStatus::Verifying).await?;
+            if is_text {
+                if let Err(e) = self.open_text(file_hash).await {
+                    return self.fail_download(file_hash, e).await;
+                }
+            } else {
+                let written = std::fs::File::open(output_path)?;
+                let algorithm = self.algorithm_of(file_hash);
+                let merkle = self
+                    .shared_files
+                    .get(file_hash)
+                    .and_then(|file| file.merkle_root.clone().map(|root| (root, file.chunk_size as usize)));
+                let intact = match merkle {
+                    // The chunks read back have to make the announced root
+                    Some((root, chunk_size)) => {
+                        let on_disk = self
+                            .off_runtime(move |cancel| {
+                                let mut chunks = Vec::new();
+                                Self::hash_chunks(written, chunk_size, algorithm, cancel, |i, chunk| chunks.push(Self::calculate_chunk_hash(algorithm, i, &chunk)))?;
+                                Ok(chunks)
+                            })
+                            .await?;
+                        MerkleTree::from_chunk_hashes(&on_disk).is_ok_and(|tree| tree.root() == root)
+                    }
+                    None => self.off_runtime(move |cancel| Self::hash_chunks(written, CHUNK_SIZE, algorithm, cancel, |_, _| {})).await? == file_hash,
+                };
+                if !intact {
+                    return self.fail_download(file_hash, DeskShareError::IntegrityCheckFailed).await;
+                }
+                let _ = tokio::fs::remove_file(partial_path(output_path)).await;
+            }
+        }
+        
+        // Completion always goes out; otherwise only when a batch is due
+        let flush = match completed {
+            Some(_) => Some(accumulator.flush()),
+            None => accumulator.try_flush(),
+        };
+        let Some(flush) = flush else {
+            return Ok(());
+        };
+        if completed.is_none() {
+            self.save_partial(file_hash).await;
+        }
+        let updated = self.active_transfers.get_mut(file_hash).and_then(|mut progress| {
+            Self::apply_flush(&mut progress, &flush);
+            let previous = progress.status;
+            if completed.is_some() {
+                // Cancelled while it was being verified
+                if !progress.status.can_transition_to(TransferStatus::Completed) {
+                    return None;
+                }
+                progress.status = TransferStatus::Completed;
+                progress.verified_hash = Some(file_hash.to_string());
+            }
+            Some((previous, progress.clone()))
+        });
+        
+        let finished = completed.as_ref().filter(|_| updated.is_some());
+        if let Some((previous, progress)) = updated {
+            if completed.is_some() {
+                self.record_download(&progress);
+                self.leave_queue(file_hash);
+            }
+            self.notify_progress(Some(previous), progress, None);
+        }
+        if let Some(folder_hash) = self.folder_of(file_hash) {
+            if let Some(output_path) = finished {
+                self.copy_duplicates(&folder_hash, file_hash, output_path).await?;
+            }
+            self.refresh_folder(&folder_hash).await;
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom};
+    use crate::network::chunk_pipeline::MemoryChunkTransport;
+    use crate::network::file_transfer::tests::{describe, scratch_dir};
+    
+    #[tokio::test]
+    async fn test_downloads_are_verified_before_completing() {
+        let file_transfer = FileTransfer::new().await;
+        let dir = scratch_dir("verifying");
+        let data = b"checked as a whole once every chunk is in";
+        let file = describe(data, 8);
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        let mut progress = file_transfer.subscribe_progress();
+        file_transfer.begin_download(file.clone(), &dir.join("report.pdf")).await.unwrap();
+        for (index, chunk) in data.chunks(8).enumerate() {
+            file_transfer.receive_chunk(&file.hash, index, chunk.to_vec()).await.unwrap();
+        }
+        
+        let mut statuses = Vec::new();
+        while let Ok(update) = progress.try_recv() {
+            if statuses.last() != Some(&update.status) {
+                statuses.push(update.status);
+            }
+        }
+        assert_eq!(statuses, [TransferStatus::InProgress, TransferStatus::Verifying, TransferStatus::Completed]);
+        
+        // Nothing moves a finished transfer again
+        let err = file_transfer.resume_transfer(&file.hash).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "invalid_transition");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_downloads_too_big_for_the_disk_fail_before_starting() {
+        let file_transfer = FileTransfer::new().await;
+        let mut file = describe(b"pretends to be enormous", 8);
+        file.size = u64::MAX / 2;
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        file_transfer.peers_with_files.write().await.insert(file.hash.clone(), HashSet::from(["10.0.0.3".to_string()]));
+        
+        let output = scratch_dir("disk-space").join("huge.bin");
+        let err = file_transfer.download_file(&file.hash, &output).await.unwrap_err();
+        match err.downcast_ref::<DeskShareError>().unwrap() {
+            DeskShareError::InsufficientDiskSpace { needed, available } => assert!(needed > available),
+            other => panic!("unexpected error: {}", other),
+        }
+        assert!(file_transfer.downloading_files.read().await.is_empty());
+        assert!(file_transfer.get_transfer_progress().await.is_empty());
+        assert!(file_transfer.queued_transfers().is_empty());
+        assert!(!output.exists());
+    }
+    
+    #[tokio::test]
+    async fn test_downloaded_file_matches_the_shared_one() {
+        let dir = scratch_dir("round-trip");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + CHUNK_SIZE / 2).map(|i| (i % 241) as u8).collect();
+        std::fs::write(dir.join("album.zip"), &data).unwrap();
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = sender.share_file(&dir.join("album.zip"), "10.0.0.2".to_string()).await.unwrap();
+        let file = sender.shared_files.get(&file_hash).unwrap().clone();
+        assert_eq!(file.total_chunks, 4);
+        
+        // The receiver fetches exactly what the sender serves
+        let (transport, _) = MemoryChunkTransport::new();
+        let mut served = Vec::new();
+        for chunk_hash in &file.chunks {
+            let chunk = sender.load_chunk(chunk_hash).await.unwrap().unwrap();
+            served.push(sender.encode_chunk(chunk).await.unwrap().data);
+        }
+        transport.offer(&file_hash, served);
+        let receiver = FileTransfer::new().await.with_chunk_transport(Arc::new(transport));
+        receiver.shared_files.insert(file_hash.clone(), file);
+        receiver.peers_with_files.write().await.insert(file_hash.clone(), HashSet::from(["10.0.0.2".to_string()]));
+        let output = dir.join("downloads").join("album.zip");
+        receiver.download_file(&file_hash, &output).await.unwrap();
+        
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        let progress = &receiver.get_transfer_progress().await[0];
+        assert_eq!(progress.status, TransferStatus::Completed);
+        assert_eq!(progress.verified_hash.as_deref(), Some(file_hash.as_str()));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_corrupt_output_fails_the_transfer() {
+        let data: Vec<u8> = (0..4 * 1024).map(|i| (i % 199) as u8).collect();
+        let file = describe(&data, 1024);
+        let file_transfer = FileTransfer::new().await;
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        let output = scratch_dir("corrupt").join("report.pdf");
+        file_transfer.begin_download(file.clone(), &output).await.unwrap();
+        for (index, chunk) in data.chunks(1024).enumerate().take(3) {
+            file_transfer.receive_chunk(&file.hash, index, chunk.to_vec()).await.unwrap();
+        }
+        
+        // Chunk 1 goes bad on disk after it was checked and written
+        use std::io::Write;
+        let mut written = std::fs::OpenOptions::new().write(true).open(&output).unwrap();
+        written.seek(SeekFrom::Start(1024)).unwrap();
+        written.write_all(&[0u8; 1024]).unwrap();
+        drop(written);
+        let err = file_transfer.receive_chunk(&file.hash, 3, data[3 * 1024..].to_vec()).await.unwrap_err();
+        
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "integrity_check_failed");
+        assert!(!output.exists());
+        let progress = &file_transfer.get_transfer_progress().await[0];
+        assert_eq!(progress.status, TransferStatus::Failed);
+        assert_eq!(progress.verified_hash, None);
+        let _ = std::fs::remove_dir_all(output.parent().unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_files_with_different_chunk_sizes_download_side_by_side() {
+        let dir = scratch_dir("chunk-sizes");
+        std::fs::create_dir_all(&dir).unwrap();
+        let small: Vec<u8> = (0..CHUNK_SIZE).map(|i| (i % 239) as u8).collect();
+        let large: Vec<u8> = (0..3 * CHUNK_SIZE).map(|i| (i % 233) as u8).collect();
+        std::fs::write(dir.join("small.bin"), &small).unwrap();
+        std::fs::write(dir.join("large.bin"), &large).unwrap();
+        let sender = FileTransfer::new().await.with_chunk_size(256 * 1024);
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let small_hash = sender.share_file(&dir.join("small.bin"), "10.0.0.2".to_string()).await.unwrap();
+        let large_hash = sender
+            .share_file_with_chunk_size(&dir.join("large.bin"), "10.0.0.2".to_string(), 2 * CHUNK_SIZE)
+            .await
+            .unwrap();
+        
+        // The file hash is the same whatever the chunk size, the chunks aren't
+        let small_file = sender.shared_files.get(&small_hash).unwrap().clone();
+        let large_file = sender.shared_files.get(&large_hash).unwrap().clone();
+        assert_eq!((small_file.chunk_size, small_file.total_chunks), (256 * 1024, 4));
+        assert_eq!((large_file.chunk_size, large_file.total_chunks), (2 * CHUNK_SIZE as u64, 2));
+        assert_eq!(small_hash, describe(&small, CHUNK_SIZE).hash);
+        
+        let (transport, _) = MemoryChunkTransport::new();
+        for file in [&small_file, &large_file] {
+            let mut served = Vec::new();
+            for chunk_hash in &file.chunks {
+                let chunk = sender.load_chunk(chunk_hash).await.unwrap().unwrap();
+                served.push(sender.encode_chunk(chunk).await.unwrap().data);
+            }
+            transport.offer(&file.hash, served);
+        }
+        let receiver = FileTransfer::new().await.with_chunk_transport(Arc::new(transport));
+        for (file, data) in [(small_file, &small), (large_file, &large)] {
+            let output = dir.join("downloads").join(&file.name);
+            receiver.shared_files.insert(file.hash.clone(), file.clone());
+            receiver.peers_with_files.write().await.insert(file.hash.clone(), HashSet::from(["10.0.0.2".to_string()]));
+            receiver.download_file(&file.hash, &output).await.unwrap();
+            assert_eq!(&std::fs::read(&output).unwrap(), data);
+        }
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,320 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use bytes::Bytes;
+use blake3::Hasher;
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use rand::RngCore;
+
+use crate::error::DeskShareError;
+use crate::security::SecureChannel;
+use crate::network::codec::ChunkCodec;
+
+use super::{FileTransfer, MAX_CHUNK_SIZE};
+
+/// Sealed chunks are the ciphertext, then the AEAD tag, then the nonce, so
+/// sealing and opening happen in place
+const NONCE_LEN: usize = 12;
+const AEAD_TAG_LEN: usize = 16;
+pub(super) const SEAL_OVERHEAD: usize = AEAD_TAG_LEN + NONCE_LEN;
+const CHUNK_CIPHER_CONTEXT: &str = "desk-share-net chunk encryption v1";
+
+/// A chunk on the wire, tagged with the key of the transfer session it
+/// belongs to so only that session's sender can produce one we accept
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaggedChunk {
+    pub file_hash: String,
+    pub index: usize,
+    pub data: Bytes,
+    pub tag: [u8; 32],
+}
+
+/// A tagged chunk ready for the socket: a length-prefixed header, then the
+/// chunk bytes exactly as they sit in the share
+#[derive(Clone, Debug)]
+pub struct ChunkEnvelope {
+    pub header: Bytes,
+    pub body: Bytes,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChunkHeader {
+    file_hash: String,
+    index: usize,
+    tag: [u8; 32],
+    len: usize,
+}
+
+impl TaggedChunk {
+    pub fn into_envelope(self) -> Result<ChunkEnvelope, Error> {
+        let header = serde_json::to_vec(&ChunkHeader {
+            file_hash: self.file_hash,
+            index: self.index,
+            tag: self.tag,
+            len: self.data.len(),
+        })?;
+        Ok(ChunkEnvelope {
+            header: Bytes::from(header),
+            body: self.data,
+        })
+    }
+    
+    pub fn from_envelope(envelope: ChunkEnvelope) -> Result<Self, Error> {
+        let header: ChunkHeader = serde_json::from_slice(&envelope.header)?;
+        if header.len != envelope.body.len() {
+            return Err(DeskShareError::ChunkTransferFailed(format!("chunk {} is truncated", header.index)).into());
+        }
+        Ok(Self {
+            file_hash: header.file_hash,
+            index: header.index,
+            data: envelope.body,
+            tag: header.tag,
+        })
+    }
+}
+
+impl ChunkEnvelope {
+    /// Header length, header, body; the body goes out without being copied
+    pub async fn write_to<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&(self.header.len() as u32).to_be_bytes()).await?;
+        writer.write_all(&self.header).await?;
+        writer.write_all(&self.body).await?;
+        Ok(())
+    }
+    
+    pub async fn read_from<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Self, Error> {
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len).await?;
+        let mut header = vec![0u8; u32::from_be_bytes(len) as usize];
+        reader.read_exact(&mut header).await?;
+        let body_len = serde_json::from_slice::<ChunkHeader>(&header)?.len;
+        if body_len > ChunkCodec::max_encoded_len(MAX_CHUNK_SIZE) + SEAL_OVERHEAD {
+            return Err(DeskShareError::ChunkTransferFailed("chunk larger than the largest chunk size".to_string()).into());
+        }
+        let mut body = vec![0u8; body_len];
+        reader.read_exact(&mut body).await?;
+        Ok(Self {
+            header: Bytes::from(header),
+            body: Bytes::from(body),
+        })
+    }
+}
+
+impl FileTransfer {
+    /// Set the chunk tag key for transferring `file_hash` with `peer_id`,
+    /// derived from the encrypted channel both sides share
+    pub fn set_transfer_key<S>(&self, file_hash: &str, peer_id: &str, channel: &SecureChannel<S>) {
+        let key = channel.derive_key(format!("chunk tags {}", file_hash).as_bytes());
+        self.transfer_keys.insert((file_hash.to_string(), peer_id.to_string()), key);
+    }
+    
+    /// Seal and tag a chunk we're sending to `peer_id`
+    pub fn tag_chunk(&self, peer_id: &str, file_hash: &str, index: usize, data: impl Into<Bytes>) -> Result<TaggedChunk, Error> {
+        let key = self.transfer_key(file_hash, peer_id)?;
+        let mut data = data.into();
+        if self.is_encrypted(file_hash) {
+            data = Self::seal_chunk(&key, file_hash, index, data)?;
+        }
+        Ok(TaggedChunk {
+            file_hash: file_hash.to_string(),
+            index,
+            tag: *Self::chunk_tag(&key, index, &data).as_bytes(),
+            data,
+        })
+    }
+    
+    /// Take a chunk that arrived from `from_peer`. The tag is checked before
+    /// the content hash; a bad one counts against the peer it came from.
+    pub async fn receive_tagged_chunk(&self, from_peer: &str, chunk: TaggedChunk) -> Result<(), Error> {
+        let key = self.transfer_key(&chunk.file_hash, from_peer)?;
+        // blake3::Hash compares in constant time
+        if Self::chunk_tag(&key, chunk.index, &chunk.data) != blake3::Hash::from(chunk.tag) {
+            if let Some(trust_store) = &self.trust_store {
+                trust_store.record_integrity_violation(from_peer);
+            }
+            return Err(DeskShareError::ChunkAuthenticationFailed(format!(
+                "chunk {} of {} from {}",
+                chunk.index, chunk.file_hash, from_peer
+            ))
+            .into());
+        }
+        
+        let received = chunk.data.len() as u64;
+        let data = if self.is_encrypted(&chunk.file_hash) {
+            Self::open_chunk(&key, &chunk.file_hash, chunk.index, chunk.data)?
+        } else {
+            self.security_config.allow_plaintext(&format!("chunks of {}", chunk.file_hash))?;
+            chunk.data
+        };
+        self.receive_chunk(&chunk.file_hash, chunk.index, data).await?;
+        self.record_peer(from_peer, |stats| stats.record_received(received, None));
+        Ok(())
+    }
+    
+    /// Shares we don't know the announcement of are treated as encrypted
+    fn is_encrypted(&self, file_hash: &str) -> bool {
+        self.shared_files.get(file_hash).is_none_or(|file| file.encrypted)
+    }
+    
+    fn chunk_cipher(key: &[u8; 32]) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&blake3::derive_key(CHUNK_CIPHER_CONTEXT, key)))
+    }
+    
+    /// The file and index go in the associated data, so a sealed chunk
+    /// can't stand in for another
+    fn chunk_aad(file_hash: &str, index: usize) -> Vec<u8> {
+        [file_hash.as_bytes(), &(index as u64).to_le_bytes()].concat()
+    }
+    
+    /// Seal a chunk. The buffer is reused when nothing else holds it, and
+    /// chunks read from disk leave room for the overhead; a cached one is
+    /// copied once, with room.
+    fn seal_chunk(key: &[u8; 32], file_hash: &str, index: usize, data: Bytes) -> Result<Bytes, Error> {
+        let mut buf = if data.is_unique() {
+            Vec::from(data)
+        } else {
+            let mut buf = Vec::with_capacity(data.len() + SEAL_OVERHEAD);
+            buf.extend_from_slice(&data);
+            buf
+        };
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let tag = Self::chunk_cipher(key)
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), &Self::chunk_aad(file_hash, index), &mut buf)
+            .map_err(|e| DeskShareError::Internal(e.to_string()))?;
+        buf.extend_from_slice(&tag);
+        buf.extend_from_slice(&nonce);
+        Ok(Bytes::from(buf))
+    }
+    
+    pub(super) fn open_chunk(key: &[u8; 32], file_hash: &str, index: usize, sealed: Bytes) -> Result<Bytes, Error> {
+        let failed = || DeskShareError::DecryptionFailed(format!("chunk {} of {}", index, file_hash));
+        let Some(len) = sealed.len().checked_sub(SEAL_OVERHEAD) else {
+            return Err(failed().into());
+        };
+        let mut buf = Vec::from(sealed);
+        let nonce = *Nonce::from_slice(&buf[len + AEAD_TAG_LEN..]);
+        let tag = *Tag::from_slice(&buf[len..len + AEAD_TAG_LEN]);
+        buf.truncate(len);
+        Self::chunk_cipher(key)
+            .decrypt_in_place_detached(&nonce, &Self::chunk_aad(file_hash, index), &mut buf, &tag)
+            .map_err(|_| failed())?;
+        Ok(Bytes::from(buf))
+    }
+    
+    pub(super) fn transfer_key(&self, file_hash: &str, peer_id: &str) -> Result<[u8; 32], Error> {
+        self.transfer_keys
+            .get(&(file_hash.to_string(), peer_id.to_string()))
+            .map(|key| *key)
+            .ok_or_else(|| {
+                DeskShareError::ChunkAuthenticationFailed(format!("no transfer session for {} with {}", file_hash, peer_id))
+                    .into()
+            })
+    }
+    
+    fn chunk_tag(key: &[u8; 32], index: usize, data: &[u8]) -> blake3::Hash {
+        let mut hasher = Hasher::new_keyed(key);
+        hasher.update(&(index as u64).to_le_bytes());
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::network::file_transfer::SharedFile;
+    use crate::network::file_transfer::tests::{channel_pair, describe, scratch_dir};
+    use crate::security::{DeviceIdentity, PeerIdentity, TrustPolicy, TrustStore};
+    
+    #[tokio::test]
+    async fn test_chunk_tags_bind_session_and_index() {
+        let (alice, bob, mallory) = (DeviceIdentity::generate(), DeviceIdentity::generate(), DeviceIdentity::generate());
+        let trust_store = Arc::new(TrustStore::new());
+        let receiver = FileTransfer::new().await.with_trust_store(trust_store.clone());
+        let sender = FileTransfer::new().await;
+        let forger = FileTransfer::new().await;
+        
+        let data = b"only bob's session can vouch for these bytes";
+        let file = describe(data, 16);
+        let dir = scratch_dir("tags");
+        let offer_id = receiver.receive_offer("10.0.0.3".to_string(), "Bob".to_string(), file.clone()).await;
+        receiver.accept_offer(&offer_id, &dir).await.unwrap();
+        
+        let (alice_channel, bob_channel) = channel_pair(&alice, &bob).await;
+        receiver.set_transfer_key(&file.hash, "10.0.0.3", &alice_channel);
+        sender.set_transfer_key(&file.hash, "10.0.0.2", &bob_channel);
+        // Mallory has her own session with Alice, and Bob's public chunk hashes
+        let (_, mallory_channel) = channel_pair(&alice, &mallory).await;
+        forger.set_transfer_key(&file.hash, "10.0.0.2", &mallory_channel);
+        
+        let chunks: Vec<Vec<u8>> = data.chunks(16).map(|chunk| chunk.to_vec()).collect();
+        let tagged = |from: &FileTransfer, index: usize| from.tag_chunk("10.0.0.2", &file.hash, index, chunks[index].clone()).unwrap();
+        let code = |err: Error| err.downcast_ref::<DeskShareError>().unwrap().code();
+        
+        // Right bytes, wrong session key
+        let err = receiver.receive_tagged_chunk("10.0.0.3", tagged(&forger, 0)).await.unwrap_err();
+        assert_eq!(code(err), "chunk_authentication_failed");
+        
+        // Bob's genuine tag moved to another index
+        let mut swapped = tagged(&sender, 0);
+        swapped.index = 1;
+        let err = receiver.receive_tagged_chunk("10.0.0.3", swapped).await.unwrap_err();
+        assert_eq!(code(err), "chunk_authentication_failed");
+        assert_eq!(trust_store.integrity_violations("10.0.0.3"), 2);
+        // Even pairing doesn't bring back the privileges of that address
+        trust_store.record_paired(&bob.device_id(), "10.0.0.3", &bob.public_key());
+        let bob_at = PeerIdentity { peer_id: "10.0.0.3".to_string(), device_id: bob.device_id() };
+        assert_eq!(trust_store.policy_for(&bob_at), TrustPolicy::default());
+        
+        // No session with that peer at all
+        let err = receiver.receive_tagged_chunk("10.0.0.9", tagged(&sender, 0)).await.unwrap_err();
+        assert_eq!(code(err), "chunk_authentication_failed");
+        
+        for index in 0..chunks.len() {
+            receiver.receive_tagged_chunk("10.0.0.3", tagged(&sender, index)).await.unwrap();
+        }
+        assert_eq!(std::fs::read(dir.join("report.pdf")).unwrap(), data);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_sealed_chunks_reject_tampering_and_plaintext_offers() {
+        let (alice, bob) = (DeviceIdentity::generate(), DeviceIdentity::generate());
+        let receiver = FileTransfer::new().await;
+        let sender = FileTransfer::new().await;
+        let data = b"quarterly numbers nobody on the wifi should read";
+        let file = describe(data, 16);
+        let dir = scratch_dir("sealed");
+        let offer_id = receiver.receive_offer("10.0.0.3".to_string(), "Bob".to_string(), file.clone()).await;
+        receiver.accept_offer(&offer_id, &dir).await.unwrap();
+        let (alice_channel, bob_channel) = channel_pair(&alice, &bob).await;
+        receiver.set_transfer_key(&file.hash, "10.0.0.3", &alice_channel);
+        sender.set_transfer_key(&file.hash, "10.0.0.2", &bob_channel);
+        
+        let sealed = sender.tag_chunk("10.0.0.2", &file.hash, 0, &data[..16]).unwrap();
+        assert_eq!(sealed.data.len(), 16 + SEAL_OVERHEAD);
+        assert!(!sealed.data.windows(16).any(|window| window == &data[..16]));
+        
+        // Flipped ciphertext under a valid tag still fails to open
+        let key = sender.transfer_key(&file.hash, "10.0.0.2").unwrap();
+        let mut tampered = sealed.data.to_vec();
+        tampered[3] ^= 1;
+        let forged = FileTransfer::new().await;
+        forged.transfer_keys.insert((file.hash.clone(), "10.0.0.2".to_string()), key);
+        forged.shared_files.insert(file.hash.clone(), SharedFile { encrypted: false, ..file.clone() });
+        let retagged = forged.tag_chunk("10.0.0.2", &file.hash, 0, tampered).unwrap();
+        let err = receiver.receive_tagged_chunk("10.0.0.3", retagged).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "decryption_failed");
+        receiver.receive_tagged_chunk("10.0.0.3", sealed).await.unwrap();
+        
+        // An announcement from a peer that sends in the clear is refused
+        let plain = SharedFile { encrypted: false, hash: "plain".to_string(), ..file };
+        let offer_id = receiver.receive_offer("10.0.0.3".to_string(), "Bob".to_string(), plain).await;
+        let err = receiver.accept_offer(&offer_id, &dir).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "encryption_required");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::time::Instant;
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::security::resolve_remote_path;
+use crate::network::codec::ChunkCodec;
+use crate::network::manifest::{self, DirectoryManifest, IgnoreRules, ManifestFile, ManifestLink};
+
+use super::{
+    check_disk_space, FileTransfer, ShareKind, SharedFile, TransferProgress, TransferStatus, CHUNK_SIZE,
+    MANIFEST_VERSION_FLAT,
+};
+
+/// A folder just shared, and what its ignore rules left out of it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SharedDirectory {
+    pub hash: String,
+    pub excluded_files: usize,
+    pub excluded_bytes: u64,
+}
+
+/// A folder being rebuilt from its manifest. The folder's progress entry,
+/// under the manifest hash, sums the downloads of its files.
+#[derive(Debug)]
+pub(super) struct FolderDownload {
+    name: String,
+    root: PathBuf,
+    /// Every path each file is written to; the first is downloaded, the
+    /// rest are copies of it
+    pub(super) files: HashMap<String, Vec<PathBuf>>,
+}
+
+impl FileTransfer {
+    /// Offer a folder: every file in it, and a manifest of its layout that
+    /// the receiver rebuilds it from. Returns the manifest's hash, which
+    /// stands for the folder from then on.
+    pub async fn share_directory(&self, path: &Path, peer_id: String) -> Result<String, Error> {
+        Ok(self.share_directory_with_rules(path, peer_id, &IgnoreRules::default()).await?.hash)
+    }
+    
+    /// Share a folder without the entries `rules` match, such as build
+    /// output or dependencies
+    pub async fn share_directory_with_rules(&self, path: &Path, peer_id: String, rules: &IgnoreRules) -> Result<SharedDirectory, Error> {
+        let root = self.check_shareable(path)?;
+        if !root.is_dir() {
+            return Err(DeskShareError::FileTransferFailed(format!("{} is not a folder", root.display())).into());
+        }
+        let (walked_root, walked_rules) = (root.clone(), rules.clone());
+        let walked = tokio::task::spawn_blocking(move || manifest::walk_with_rules(&walked_root, &walked_rules))
+            .await
+            .map_err(|e| DeskShareError::Internal(e.to_string()))??;
+        if walked.excluded_files > 0 {
+            tracing::info!("Left {} files ({} bytes) out of {:?}", walked.excluded_files, walked.excluded_bytes, root);
+        }
+        
+        let mut files = Vec::with_capacity(walked.files.len());
+        for relative in walked.files {
+            let file_hash = self.share_file(&root.join(&relative), peer_id.clone()).await?;
+            let file = self
+                .shared_files
+                .get(&file_hash)
+                .map(|file| file.clone())
+                .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.clone()))?;
+            files.push(ManifestFile { path: relative, file });
+        }
+        let manifest = DirectoryManifest {
+            name: root.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            directories: walked.directories,
+            files,
+            links: walked.links,
+        };
+        
+        // The manifest lives only in memory; its chunks are served from there
+        let data = serde_json::to_vec(&manifest)?;
+        let size = data.len() as u64;
+        let (hash, chunk_hashes) = self.hold_in_memory(&data, CHUNK_SIZE)?;
+        
+        self.publish_share(SharedFile {
+            hash: hash.clone(),
+            name: manifest.name,
+            size,
+            total_chunks: chunk_hashes.len(),
+            chunks: chunk_hashes,
+            chunk_size: CHUNK_SIZE as u64,
+            peer_id,
+            timestamp: Self::now_secs(),
+            kind: ShareKind::Directory,
+            codec: ChunkCodec::choose(&data[..data.len().min(CHUNK_SIZE)]),
+            encrypted: true,
+            manifest_version: MANIFEST_VERSION_FLAT,
+            merkle_root: None,
+            hash_algorithm: self.hash_algorithm,
+        })
+        .await?;
+        
+        Ok(SharedDirectory {
+            hash,
+            excluded_files: walked.excluded_files,
+            excluded_bytes: walked.excluded_bytes,
+        })
+    }
+    
+    /// Rebuild a folder's layout from its downloaded manifest and register a
+    /// download for each file in it. Empty files are created right away.
+    pub(super) async fn open_folder(&self, manifest_hash: &str, root: &Path, chunk_hashes: &[String]) -> Result<(), Error> {
+        let mut data = Vec::new();
+        for chunk_hash in chunk_hashes {
+            let chunk = self
+                .file_chunks
+                .get(chunk_hash)
+                .map(|chunk| chunk.data.clone())
+                .ok_or_else(|| DeskShareError::ChunkTransferFailed(format!("missing chunk {}", chunk_hash)))?;
+            data.extend_from_slice(&chunk);
+        }
+        let algorithm = self.algorithm_of(manifest_hash);
+        if Self::hash_chunks(data.as_slice(), CHUNK_SIZE, algorithm, &AtomicBool::new(false), |_, _| {})? != manifest_hash {
+            return self.fail_download(manifest_hash, DeskShareError::IntegrityCheckFailed).await;
+        }
+        if let Err(e) = self.build_folder(manifest_hash, root, &data).await {
+            // Running out of room isn't the manifest's fault
+            let error = match e.downcast::<DeskShareError>() {
+                Ok(full @ DeskShareError::InsufficientDiskSpace { .. }) => full,
+                Ok(e) => DeskShareError::ChunkTransferFailed(format!("bad folder manifest: {}", e)),
+                Err(e) => DeskShareError::ChunkTransferFailed(format!("bad folder manifest: {}", e)),
+            };
+            return self.fail_download(manifest_hash, error).await;
+        }
+        Ok(())
+    }
+    
+    async fn build_folder(&self, manifest_hash: &str, root: &Path, data: &[u8]) -> Result<(), Error> {
+        let manifest: DirectoryManifest = serde_json::from_slice(data)?;
+        check_disk_space(root, manifest.total_size())?;
+        tokio::fs::create_dir_all(root).await?;
+        // Paths came from the sender; each is checked like a file name would be
+        for directory in &manifest.directories {
+            tokio::fs::create_dir_all(resolve_remote_path(root, directory)?).await?;
+        }
+        
+        let mut files = HashMap::new();
+        let mut downloads = Vec::new();
+        for (file, paths) in manifest.distinct_files() {
+            let paths = paths
+                .into_iter()
+                .map(|path| resolve_remote_path(root, path))
+                .collect::<Result<Vec<_>, _>>()?;
+            if file.total_chunks == 0 {
+                for path in &paths {
+                    tokio::fs::File::create(path).await?;
+                }
+                continue;
+            }
+            downloads.push((file.clone(), paths[0].clone()));
+            files.insert(file.hash.clone(), paths);
+        }
+        Self::make_links(root, &manifest.links).await?;
+        self.folders.insert(manifest_hash.to_string(), FolderDownload {
+            name: manifest.name.clone(),
+            root: root.to_path_buf(),
+            files,
+        });
+        
+        // Whoever has the manifest has the files
+        let peers = self.peers_with_files.read().await.get(manifest_hash).cloned().unwrap_or_default();
+        for (file, output_path) in downloads {
+            // A re-sync doesn't fetch files that are already in place
+            if self.already_have(&file, &output_path).await? {
+                self.skip_download(&file, &output_path, true).await;
+                self.copy_duplicates(manifest_hash, &file.hash, &output_path).await?;
+                continue;
+            }
+            self.shared_files.entry(file.hash.clone()).or_insert_with(|| file.clone());
+            self.peers_with_files
+                .write()
+                .await
+                .entry(file.hash.clone())
+                .or_default()
+                .extend(peers.iter().cloned());
+            self.begin_download(file, &output_path).await?;
+        }
+        self.refresh_folder(manifest_hash).await;
+        
+        Ok(())
+    }
+    
+    /// Recreate the links a folder was shared with, where that's safe
+    async fn make_links(root: &Path, links: &[ManifestLink]) -> Result<(), Error> {
+        for link in links {
+            if !link.stays_inside() {
+                tracing::warn!("Not recreating {}, it leads out of the folder to {}", link.path, link.target);
+                continue;
+            }
+            let path = resolve_remote_path(root, &link.path)?;
+            #[cfg(unix)]
+            if let Err(e) = tokio::fs::symlink(&link.target, &path).await {
+                tracing::warn!("Couldn't recreate link {:?}: {}", path, e);
+            }
+            #[cfg(not(unix))]
+            tracing::warn!("Not recreating link {:?}, links are only kept on Unix", path);
+        }
+        Ok(())
+    }
+    
+    /// The folder download a file belongs to, if any
+    pub(super) fn folder_of(&self, file_hash: &str) -> Option<String> {
+        self.folders
+            .iter()
+            .find(|folder| folder.files.contains_key(file_hash))
+            .map(|folder| folder.key().clone())
+    }
+    
+    /// Write the other paths of a file that appears more than once in a folder
+    pub(super) async fn copy_duplicates(&self, folder_hash: &str, file_hash: &str, downloaded: &Path) -> Result<(), Error> {
+        let copies: Vec<PathBuf> = self
+            .folders
+            .get(folder_hash)
+            .and_then(|folder| folder.files.get(file_hash).map(|paths| paths.iter().skip(1).cloned().collect()))
+            .unwrap_or_default();
+        for copy in copies {
+            tokio::fs::copy(downloaded, &copy).await?;
+        }
+        Ok(())
+    }
+    
+    /// Sum a folder's file downloads into its own progress entry. It fails
+    /// or is cancelled with any of its files, and completes with all of them.
+    pub(super) async fn refresh_folder(&self, folder_hash: &str) {
+        let Some((name, root, files)) = self
+            .folders
+            .get(folder_hash)
+            .map(|folder| (folder.name.clone(), folder.root.clone(), folder.files.keys().cloned().collect::<Vec<_>>()))
+        else {
+            return;
+        };
+        let children: Vec<TransferProgress> = files
+            .iter()
+            .filter_map(|file_hash| self.active_transfers.get(file_hash).map(|progress| progress.clone()))
+            .collect();
+        let status = [TransferStatus::Failed, TransferStatus::Cancelled]
+            .into_iter()
+            .find(|status| children.iter().any(|progress| progress.status == *status))
+            .unwrap_or(if children.iter().all(|progress| progress.status == TransferStatus::Completed) {
+                TransferStatus::Completed
+            } else {
+                TransferStatus::InProgress
+            });
+        
+        let bytes_transferred = children.iter().map(|progress| progress.bytes_transferred).sum();
+        let total_bytes: u64 = children.iter().map(|progress| progress.total_bytes).sum();
+        let bytes_per_second = children.iter().map(|progress| progress.bytes_per_second).sum();
+        // A failed folder says why its file failed
+        let failed = children.iter().find(|progress| status == TransferStatus::Failed && progress.status == status);
+        let progress = TransferProgress {
+            file_name: name,
+            file_hash: folder_hash.to_string(),
+            bytes_transferred,
+            total_bytes,
+            percentage: if total_bytes == 0 { 100.0 } else { (bytes_transferred as f64 / total_bytes as f64) * 100.0 },
+            status,
+            bytes_per_second,
+            eta_seconds: Self::estimate_eta(total_bytes.saturating_sub(bytes_transferred), bytes_per_second),
+            output_path: Some(root),
+            // Every file in it passed its own check
+            verified_hash: (status == TransferStatus::Completed).then(|| folder_hash.to_string()),
+            skipped: false,
+            error_message: failed.and_then(|progress| progress.error_message.clone()),
+            error_kind: failed.and_then(|progress| progress.error_kind.clone()),
+        };
+        if matches!(status, TransferStatus::Failed | TransferStatus::Cancelled) {
+            self.ended_transfers.entry(folder_hash.to_string()).or_insert_with(Instant::now);
+        }
+        if status.is_terminal() {
+            self.record_download(&progress);
+            self.leave_queue(folder_hash);
+        }
+        self.publish_progress(progress).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::network::chunk_pipeline::MemoryChunkTransport;
+    use crate::network::file_transfer::tests::scratch_dir;
+    use crate::network::manifest::SymlinkPolicy;
+    
+    #[tokio::test]
+    async fn test_ignored_folders_stay_out_of_the_manifest() {
+        let dir = scratch_dir("ignored-folder");
+        let source = dir.join("site");
+        std::fs::create_dir_all(source.join("node_modules/left-pad")).unwrap();
+        std::fs::create_dir_all(source.join("src")).unwrap();
+        std::fs::write(source.join("node_modules/left-pad/index.js"), vec![b' '; 64]).unwrap();
+        std::fs::write(source.join("src/app.js"), b"start()").unwrap();
+        
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let rules = IgnoreRules { patterns: vec!["node_modules".to_string()], ..Default::default() };
+        let shared = sender.share_directory_with_rules(&source, "local".to_string(), &rules).await.unwrap();
+        assert_eq!((shared.excluded_files, shared.excluded_bytes), (1, 64));
+        
+        let folder = sender.get_shared_file(&shared.hash).unwrap();
+        let mut data = Vec::new();
+        for chunk_hash in &folder.chunks {
+            data.extend_from_slice(&sender.load_chunk(chunk_hash).await.unwrap().unwrap().data);
+        }
+        let manifest: DirectoryManifest = serde_json::from_slice(&data).unwrap();
+        assert_eq!(manifest.directories, vec!["src"]);
+        assert_eq!(manifest.files.iter().map(|entry| entry.path.as_str()).collect::<Vec<_>>(), vec!["src/app.js"]);
+        // Only the folder and the one file it kept are offered
+        assert_eq!(sender.list_shared_files().len(), 2);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_kept_links_are_recreated_only_inside_the_folder() {
+        let dir = scratch_dir("kept-links");
+        let source = dir.join("tree");
+        std::fs::create_dir_all(source.join("docs")).unwrap();
+        std::fs::write(source.join("docs/readme.txt"), b"read me").unwrap();
+        std::fs::write(dir.join("secret.txt"), b"not for sharing").unwrap();
+        std::os::unix::fs::symlink(dir.join("secret.txt"), source.join("docs/outside.txt")).unwrap();
+        
+        // Following a link out of the shareable directories is refused
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![source.clone()]);
+        let follow = IgnoreRules { symlinks: SymlinkPolicy::Follow, ..Default::default() };
+        let err = sender.share_directory_with_rules(&source, "local".to_string(), &follow).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "path_not_allowed");
+        
+        // Whatever a sender claims, a link out of the folder isn't made
+        let manifest = DirectoryManifest {
+            name: "tree".to_string(),
+            directories: vec!["docs".to_string()],
+            files: Vec::new(),
+            links: [("docs/same.txt", "readme.txt"), ("docs/up", "../../.."), ("docs/passwd", "/etc/passwd")]
+                .into_iter()
+                .map(|(path, target)| ManifestLink { path: path.to_string(), target: target.to_string() })
+                .collect(),
+        };
+        let receiver = FileTransfer::new().await;
+        let copy = dir.join("downloads/tree");
+        receiver.build_folder("manifest", &copy, &serde_json::to_vec(&manifest).unwrap()).await.unwrap();
+        assert_eq!(std::fs::read_link(copy.join("docs/same.txt")).unwrap(), Path::new("readme.txt"));
+        assert!(std::fs::symlink_metadata(copy.join("docs/up")).is_err());
+        assert!(std::fs::symlink_metadata(copy.join("docs/passwd")).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_shared_folder_is_rebuilt_with_its_layout() {
+        let dir = scratch_dir("folder");
+        let source = dir.join("project");
+        let deep = (0..8).fold(source.clone(), |path, depth| path.join(format!("level{}", depth)));
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::create_dir_all(source.join("empty/also-empty")).unwrap();
+        let big: Vec<u8> = (0..CHUNK_SIZE + 4096).map(|i| (i % 251) as u8).collect();
+        std::fs::write(source.join("big.bin"), &big).unwrap();
+        std::fs::write(source.join("notes.txt"), b"same").unwrap();
+        std::fs::write(source.join("level0/notes-copy.txt"), b"same").unwrap();
+        std::fs::write(source.join("level0/blank"), b"").unwrap();
+        std::fs::write(deep.join("bottom.txt"), b"deepest").unwrap();
+        
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let folder_hash = sender.share_directory(&source, "10.0.0.2".to_string()).await.unwrap();
+        let folder = sender.shared_files.get(&folder_hash).unwrap().clone();
+        assert_eq!(folder.kind, ShareKind::Directory);
+        
+        // Serve the manifest and every file the way the sender would
+        let (transport, _) = MemoryChunkTransport::new();
+        let shares: Vec<SharedFile> = sender.shared_files.iter().map(|file| file.clone()).collect();
+        for file in shares {
+            let mut served = Vec::new();
+            for chunk_hash in &file.chunks {
+                let chunk = sender.load_chunk(chunk_hash).await.unwrap().unwrap();
+                served.push(sender.encode_chunk(chunk).await.unwrap().data);
+            }
+            transport.offer(&file.hash, served);
+        }
+        let receiver = FileTransfer::new().await.with_chunk_transport(Arc::new(transport));
+        let downloads = dir.join("downloads");
+        std::fs::create_dir_all(&downloads).unwrap();
+        let offer_id = receiver.receive_offer("10.0.0.2".to_string(), "Alice".to_string(), folder).await;
+        receiver.accept_offer(&offer_id, &downloads).await.unwrap();
+        assert_eq!(receiver.wait_for_transfer(&folder_hash).await, Some(TransferStatus::Completed));
+        
+        let copy = downloads.join("project");
+        assert_eq!(std::fs::read(copy.join("big.bin")).unwrap(), big);
+        assert_eq!(std::fs::read(copy.join("notes.txt")).unwrap(), b"same");
+        assert_eq!(std::fs::read(copy.join("level0/notes-copy.txt")).unwrap(), b"same");
+        assert_eq!(std::fs::read(copy.join("level0/blank")).unwrap(), b"");
+        let bottom = deep.strip_prefix(&source).unwrap().join("bottom.txt");
+        assert_eq!(std::fs::read(copy.join(bottom)).unwrap(), b"deepest");
+        assert!(copy.join("empty/also-empty").is_dir());
+        
+        let progress = receiver.get_transfer_progress().await;
+        let folder = progress.iter().find(|progress| progress.file_hash == folder_hash).unwrap();
+        assert_eq!(folder.status, TransferStatus::Completed);
+        assert_eq!(folder.file_name, "project");
+        assert_eq!(folder.total_bytes, (big.len() + 4 + 7) as u64);
+        assert_eq!(folder.bytes_transferred, folder.total_bytes);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,2064 @@
+// Sharing files with peers and downloading theirs, a chunk at a time
+//
+// `FileTransfer` and the types every part of it uses live here; its methods
+// are spread over the submodules by what they do, from sharing and offering
+// files to requesting chunks from peers and serving them.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, Notify, broadcast, watch};
+use bytes::Bytes;
+use dashmap::{DashMap, DashSet};
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::config::AutoAcceptConfig;
+use crate::security::{DeviceIdentity, RateLimiter, SecurityConfig, TrustStore};
+use super::chunk_cache::{ChunkCache, ChunkCacheUsage, DEFAULT_CHUNK_CACHE_BYTES};
+use super::codec::ChunkCodec;
+use super::hashing::HashAlgorithm;
+use super::history::{TransferDirection, TransferHistory, TransferRecord};
+use super::lan_transfer::LanChunkTransport;
+use super::chunk_pipeline::{ChunkTransport, ChunkWindowConfig, ChunkWindows};
+use super::merkle::{MerkleProof, MerkleTree};
+use super::observer::TransferObserver;
+use super::peer_stats::PeerTransferStats;
+use super::share_registry::ShareRegistry;
+use super::progress::{Flush, ProgressAccumulator};
+use super::throttle::Throttle;
+use super::timings::StageTimings;
+
+mod announcements;
+mod collision;
+mod downloads;
+mod encryption;
+mod folders;
+mod offers;
+mod pipeline;
+mod queue;
+mod resume;
+mod sends;
+mod shares;
+mod swarm;
+mod texts;
+
+pub use announcements::{RemoteFile, SignedAnnouncement, SignedRetraction};
+pub use collision::CollisionPolicy;
+pub use encryption::{ChunkEnvelope, TaggedChunk};
+pub use folders::SharedDirectory;
+pub use offers::{AutoAcceptDecision, OfferEvent, PendingOffer, TransferHistoryEntry};
+pub use sends::{DestinationProgress, MultiSendProgress};
+pub use swarm::UploadProgress;
+pub use texts::ReceivedText;
+use announcements::{AnnouncedFile, AnnouncedMetadata};
+use folders::FolderDownload;
+use queue::DownloadQueue;
+use resume::partial_path;
+use sends::DeviceSend;
+use swarm::UploadTally;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransferProgress {
+    pub file_name: String,
+    pub file_hash: String,
+    pub bytes_transferred: u64,
+    pub total_bytes: u64,
+    pub percentage: f64,
+    pub status: TransferStatus,
+    pub bytes_per_second: f64,
+    pub eta_seconds: Option<u64>,
+    pub output_path: Option<PathBuf>,
+    /// Set once a download's output has been read back and matched its hash
+    #[serde(default)]
+    pub verified_hash: Option<String>,
+    /// Completed without a download, because the output already held the file
+    #[serde(default)]
+    pub skipped: bool,
+    /// Why a failed transfer failed, fit to show the user
+    #[serde(default)]
+    pub error_message: Option<String>,
+    /// The failure's error code, such as `peer_connection_failed` or
+    /// `insufficient_disk_space`
+    #[serde(default)]
+    pub error_kind: Option<String>,
+}
+
+/// Which missing chunk a download asks for next
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkOrder {
+    /// Those the fewest peers hold, so they're spread around before anyone
+    /// leaves; chunks equally rare go in random order
+    #[default]
+    RarestFirst,
+    /// From the start of the file, for playing it while it downloads
+    Sequential,
+}
+
+/// Sent to the frontend as snake_case names. History written before they
+/// were lowercase still reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferStatus {
+    Pending,
+    /// Waiting for one of the downloads running to finish
+    Queued,
+    InProgress,
+    Paused,
+    /// Every chunk is in; the whole file is being checked against its hash
+    Verifying,
+    #[serde(alias = "Completed")]
+    Completed,
+    #[serde(alias = "Failed")]
+    Failed,
+    #[serde(alias = "Cancelled")]
+    Cancelled,
+}
+
+impl TransferStatus {
+    /// Whether a transfer in this state may move to `next`
+    pub fn can_transition_to(self, next: TransferStatus) -> bool {
+        use TransferStatus::*;
+        
+        matches!(
+            (self, next),
+            (Pending, Queued | InProgress | Paused | Failed | Cancelled)
+                | (Queued, InProgress | Paused | Cancelled)
+                | (InProgress, Paused | Verifying | Completed | Failed | Cancelled)
+                // Chunks already in flight when it was paused can finish it
+                | (Paused, InProgress | Verifying | Cancelled)
+                | (Verifying, Completed | Failed | Cancelled)
+        )
+    }
+    
+    pub fn is_terminal(self) -> bool {
+        matches!(self, TransferStatus::Completed | TransferStatus::Failed | TransferStatus::Cancelled)
+    }
+}
+
+/// Clones are handles onto the same transfers, so work can carry on in
+/// the background
+#[derive(Clone)]
+pub struct FileTransfer {
+    shared_files: Arc<DashMap<String, SharedFile>>,
+    /// Chunks of folder manifests, those we share and those being downloaded
+    file_chunks: Arc<DashMap<String, FileChunk>>,
+    /// Chunks of files we share, read from disk when a peer asks
+    shared_chunks: Arc<DashMap<String, SharedChunk>>,
+    /// Data of recently read shared chunks, up to a byte cap
+    chunk_cache: Arc<Mutex<ChunkCache>>,
+    /// Every path a shared file's content was shared from; its chunks are
+    /// read from the first
+    share_sources: Arc<DashMap<String, Vec<Arc<Path>>>>,
+    downloading_files: Arc<RwLock<HashMap<String, DownloadingFile>>>,
+    /// Folders being downloaded, by manifest hash
+    folders: Arc<DashMap<String, FolderDownload>>,
+    peers_with_files: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    /// Holders of a file that only have some of its chunks, and which ones.
+    /// Peers in `peers_with_files` not listed here have all of them.
+    chunk_holders: Arc<DashMap<String, HashMap<String, HashSet<usize>>>>,
+    share_stats: Arc<DashMap<String, ShareStats>>,
+    /// Never locked while `downloading_files` is held
+    active_transfers: Arc<DashMap<String, TransferProgress>>,
+    /// When failed and cancelled transfers ended. Cancelled ones are forgotten
+    /// once they've been on show for the retention period, failed ones when
+    /// they're dismissed.
+    ended_transfers: Arc<DashMap<String, Instant>>,
+    ended_retention_ms: Arc<AtomicU64>,
+    progress_tx: broadcast::Sender<TransferProgress>,
+    /// Told of every change `progress_tx` carries, and of chunk failures
+    observers: Arc<Mutex<Vec<Arc<dyn TransferObserver>>>>,
+    pending_offers: Arc<DashMap<String, PendingOffer>>,
+    offer_tx: broadcast::Sender<OfferEvent>,
+    offer_timeout_ms: Arc<AtomicU64>,
+    downloads_dir: Arc<Mutex<PathBuf>>,
+    shareable_roots: Arc<Mutex<Vec<PathBuf>>>,
+    trust_store: Option<Arc<TrustStore>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    identity: Option<Arc<DeviceIdentity>>,
+    security_config: SecurityConfig,
+    /// Every distinct metadata announced for a file hash, with who announced it
+    announcements: Arc<DashMap<String, Vec<AnnouncedMetadata>>>,
+    /// Files each peer has announced, by peer then file hash, until the
+    /// peer drops out of discovery
+    remote_files: Arc<DashMap<String, HashMap<String, AnnouncedFile>>>,
+    /// Unshared files still being sent to the peers listed, dropped once
+    /// they've had every chunk
+    retiring_shares: Arc<DashMap<String, HashSet<String>>>,
+    /// Shares whose time ran out, so late requests hear why
+    expired_shares: Arc<DashSet<String>>,
+    retraction_tx: broadcast::Sender<SignedRetraction>,
+    /// Short texts received, until they're dismissed
+    received_texts: Arc<DashMap<String, ReceivedText>>,
+    text_tx: broadcast::Sender<ReceivedText>,
+    auto_accept: AutoAcceptConfig,
+    collision_policy: CollisionPolicy,
+    chunk_order: ChunkOrder,
+    /// Day number and bytes auto-accepted on it
+    auto_accept_usage: Arc<Mutex<(u64, u64)>>,
+    history: Arc<RwLock<Vec<TransferHistoryEntry>>>,
+    /// Chunk tag keys per (file hash, peer) transfer session
+    transfer_keys: Arc<DashMap<(String, String), [u8; 32]>>,
+    hash_timings: Arc<Mutex<StageTimings>>,
+    /// Kept across transfers until reset
+    peer_stats: Arc<DashMap<String, PeerTransferStats>>,
+    chunk_transport: Option<Arc<dyn ChunkTransport>>,
+    /// Connections that files were offered to us on, when we take them
+    lan_transport: Option<Arc<LanChunkTransport>>,
+    chunk_windows: Arc<ChunkWindows>,
+    /// Chunk size for new shares
+    chunk_size: usize,
+    /// Hash algorithm for new shares
+    hash_algorithm: HashAlgorithm,
+    upload_throttle: Arc<Throttle>,
+    download_throttle: Arc<Throttle>,
+    /// Where finished transfers are recorded, if anywhere
+    transfer_history: Option<Arc<TransferHistory>>,
+    /// Where the files we share are recorded, if anywhere
+    share_registry: Option<Arc<ShareRegistry>>,
+    /// When each download still to be recorded started
+    download_started: Arc<DashMap<String, Instant>>,
+    /// Chunks each peer has had of a share, by (file hash, peer)
+    uploads: Arc<DashMap<(String, String), UploadTally>>,
+    /// Keyed by (file hash, device address as given)
+    device_sends: Arc<DashMap<(String, String), DeviceSend>>,
+    download_queue: Arc<Mutex<DownloadQueue>>,
+    /// Woken whenever a download leaves the queue or a slot
+    queue_changed: Arc<Notify>,
+    max_concurrent_transfers: Arc<AtomicUsize>,
+}
+
+/// How long an incoming offer waits for an answer before it expires
+const DEFAULT_OFFER_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How long failed and cancelled transfers stay listed
+const DEFAULT_ENDED_RETENTION: Duration = Duration::from_secs(10 * 60);
+
+/// Chunk size for shares that don't pick one; hashes of files shared with
+/// it before it was configurable stay the same
+const CHUNK_SIZE: usize = 1024 * 1024;
+/// Bounds on a share's chunk size; the largest is also the most a peer may
+/// send in one envelope
+const MIN_CHUNK_SIZE: usize = 16 * 1024;
+const MAX_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Downloads running at once unless configured otherwise
+const DEFAULT_MAX_CONCURRENT_TRANSFERS: usize = 3;
+
+/// Room left over on the disk after a download, for everything else using it
+const DISK_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+/// How often a running download checks the disk still has room for it
+const DISK_SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Raises its flag when dropped, so blocking work stops between chunks once
+/// whoever was waiting for it has gone away
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A file hashed a chunk at a time: its hash, each chunk's hash and length,
+/// and the codec its first chunk suggests
+struct HashedFile {
+    hash: String,
+    chunks: Vec<(String, usize)>,
+    codec: ChunkCodec,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SharedFile {
+    pub hash: String,
+    pub name: String,
+    pub size: u64,
+    pub chunks: Vec<String>,
+    pub chunk_size: u64,
+    pub total_chunks: usize,
+    pub peer_id: String,
+    pub timestamp: u64,
+    #[serde(default)]
+    pub kind: ShareKind,
+    #[serde(default)]
+    pub codec: ChunkCodec,
+    /// Chunks are sealed with the transfer key. Announcements from before
+    /// this existed read as unencrypted, which receivers requiring
+    /// encryption refuse up front.
+    #[serde(default)]
+    pub encrypted: bool,
+    /// How the chunks are described; announcements from before this existed
+    /// are flat
+    #[serde(default = "flat_manifest")]
+    pub manifest_version: u32,
+    /// Root of the merkle tree over `chunks`, from `MANIFEST_VERSION_MERKLE`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merkle_root: Option<String>,
+    /// What `hash` and `chunks` were taken with; blake3 for announcements
+    /// from before this existed
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
+}
+
+/// Chunks described only by the list of their hashes
+pub const MANIFEST_VERSION_FLAT: u32 = 1;
+/// The list, and a merkle root over it that chunks can be proven against
+/// one at a time. Peers that don't know it still read the list.
+pub const MANIFEST_VERSION_MERKLE: u32 = 2;
+
+/// Files with this many chunks or more get a merkle root
+const MERKLE_MIN_CHUNKS: usize = 64;
+
+fn flat_manifest() -> u32 {
+    MANIFEST_VERSION_FLAT
+}
+
+/// What a share's bytes are: the file itself, the manifest of a folder, or
+/// a snippet of UTF-8 text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShareKind {
+    #[default]
+    File,
+    Directory,
+    Text,
+}
+
+/// Text shares up to this size are handed to the UI rather than saved
+pub const TEXT_INLINE_LIMIT: u64 = 256 * 1024;
+
+impl SharedFile {
+    /// Whether two announcements describe the same chunks
+    fn same_metadata(&self, other: &SharedFile) -> bool {
+        self.size == other.size && self.chunk_size == other.chunk_size && self.chunks == other.chunks && self.merkle_root == other.merkle_root
+    }
+    
+    /// The manifest version and merkle root to share chunks under
+    fn manifest_for(chunks: &[String]) -> (u32, Option<String>) {
+        match MerkleTree::from_chunk_hashes(chunks) {
+            Ok(tree) if chunks.len() >= MERKLE_MIN_CHUNKS => (MANIFEST_VERSION_MERKLE, Some(tree.root())),
+            _ => (MANIFEST_VERSION_FLAT, None),
+        }
+    }
+    
+    /// A merkle manifest's root has to be the one its chunk hashes make
+    fn check_manifest(&self) -> Result<(), DeskShareError> {
+        if self.manifest_version < MANIFEST_VERSION_MERKLE {
+            return Ok(());
+        }
+        let root = MerkleTree::from_chunk_hashes(&self.chunks)?.root();
+        match &self.merkle_root {
+            Some(announced) if *announced == root => Ok(()),
+            _ => Err(DeskShareError::IntegrityCheckFailed),
+        }
+    }
+    
+    /// Check one chunk's data against the merkle root alone, with its proof
+    /// from the sender
+    pub fn verify_chunk(&self, index: usize, data: &[u8], proof: &MerkleProof) -> bool {
+        let Some(root) = &self.merkle_root else {
+            return false;
+        };
+        proof.index == index && proof.verify(root, &FileTransfer::calculate_chunk_hash(self.hash_algorithm, index, data), self.total_chunks)
+    }
+    
+    /// A text share small enough to be kept in memory on arrival
+    pub fn is_inline_text(&self) -> bool {
+        self.kind == ShareKind::Text && self.size <= TEXT_INLINE_LIMIT
+    }
+}
+
+/// A file this machine is offering, as shown in the shared files list
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SharedFileSummary {
+    pub hash: String,
+    pub name: String,
+    pub size: u64,
+    pub bytes_served: u64,
+    pub peers_requesting: Vec<String>,
+    pub shared_at: u64,
+    /// Where the content is shared from; more than one when identical
+    /// files were shared
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// Seconds until the share lapses, for shares given a time to live
+    #[serde(default)]
+    pub expires_in_secs: Option<u64>,
+}
+
+/// One entry of a local folder, for browsing what can be shared
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Links are listed as themselves, neither file nor folder, and not
+    /// followed
+    pub is_symlink: bool,
+    /// Zero for folders and links
+    pub size: u64,
+    /// Seconds since the epoch, when the platform reports it
+    pub modified: Option<u64>,
+}
+
+/// Dot files, and on Windows anything marked hidden
+fn is_hidden(name: &str, metadata: &std::fs::Metadata) -> bool {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+        if metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0 {
+            return true;
+        }
+    }
+    #[cfg(not(windows))]
+    let _ = metadata;
+    name.starts_with('.')
+}
+
+/// Bytes in chunk `index` of `file`; the last one may be short
+fn chunk_len(file: &SharedFile, index: usize) -> u64 {
+    file.size.saturating_sub(index as u64 * file.chunk_size).min(file.chunk_size)
+}
+
+/// An output file of `size` bytes to write a download into, sparse where
+/// the filesystem allows
+fn preallocate(path: &Path, size: u64) -> std::io::Result<std::fs::File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let file = std::fs::File::create(path)?;
+    file.set_len(size)?;
+    Ok(file)
+}
+
+/// Write a verified chunk into a download's output at `offset`
+fn write_at(output: &Mutex<std::fs::File>, offset: u64, data: &[u8]) -> std::io::Result<()> {
+    let mut file = output.lock().unwrap();
+    file.seek(SeekFrom::Start(offset))?;
+    std::io::Write::write_all(&mut *file, data)
+}
+
+/// Fail unless the disk `path` is on has room for `size` bytes and the margin
+fn check_disk_space(path: &Path, size: u64) -> Result<(), DeskShareError> {
+    // The output directory may not exist yet; its nearest existing parent is on the same disk
+    let Some(existing) = path.ancestors().find(|dir| dir.exists()) else {
+        return Ok(());
+    };
+    let available = match fs2::available_space(existing) {
+        Ok(available) => available,
+        Err(e) => {
+            tracing::warn!("Couldn't check free space on {}: {}", existing.display(), e);
+            return Ok(());
+        }
+    };
+    let needed = size.saturating_add(DISK_SPACE_MARGIN);
+    if available < needed {
+        return Err(DeskShareError::InsufficientDiskSpace { needed, available });
+    }
+    Ok(())
+}
+
+/// Create `dir` if it's missing and make sure files can be written in it
+fn check_writable(dir: &Path) -> Result<(), DeskShareError> {
+    let unwritable = |e: std::io::Error| DeskShareError::InvalidConfig(format!("can't save files to {}: {}", dir.display(), e));
+    std::fs::create_dir_all(dir).map_err(unwritable)?;
+    let probe = dir.join(format!(".dsn-write-check-{}", std::process::id()));
+    std::fs::File::create(&probe).map_err(unwritable)?;
+    let _ = std::fs::remove_file(probe);
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct ShareStats {
+    bytes_served: u64,
+    requesters: HashSet<String>,
+    /// When the share lapses, if it was given a time to live
+    expires_at: Option<Instant>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FileChunk {
+    pub chunk_hash: String,
+    pub data: Bytes,
+    pub index: usize,
+    pub file_hash: String,
+}
+
+/// Where a chunk of a shared file sits on disk
+#[derive(Clone, Debug)]
+struct SharedChunk {
+    path: Arc<Path>,
+    offset: u64,
+    len: usize,
+    index: usize,
+    file_hash: String,
+}
+
+#[derive(Debug)]
+pub struct DownloadingFile {
+    pub file_hash: String,
+    /// Chunks written so far; chunk `i` covers the bytes from
+    /// `i * chunk_size` up to the next chunk or the end of the file
+    pub chunks_received: HashSet<usize>,
+    pub chunks_expected: usize,
+    pub peers: HashSet<String>,
+    /// Failed requests per chunk index, across all peers
+    pub chunk_attempts: HashMap<usize, u32>,
+    pub output_path: PathBuf,
+    pub progress: Arc<ProgressAccumulator>,
+    /// The output file, sized up front and written as chunks arrive. Folder
+    /// manifests have none; they're held in memory until they're read.
+    output: Option<Arc<Mutex<std::fs::File>>>,
+    /// Dropped with the download, which abandons its requests in flight
+    alive: watch::Sender<()>,
+}
+
+impl FileTransfer {
+    pub async fn new() -> Self {
+        let (progress_tx, _) = broadcast::channel(256);
+        let (offer_tx, _) = broadcast::channel(32);
+        let (retraction_tx, _) = broadcast::channel(32);
+        let (text_tx, _) = broadcast::channel(32);
+        
+        FileTransfer {
+            shared_files: Arc::new(DashMap::new()),
+            file_chunks: Arc::new(DashMap::new()),
+            shared_chunks: Arc::new(DashMap::new()),
+            chunk_cache: Arc::new(Mutex::new(ChunkCache::new(DEFAULT_CHUNK_CACHE_BYTES))),
+            share_sources: Arc::new(DashMap::new()),
+            downloading_files: Arc::new(RwLock::new(HashMap::new())),
+            folders: Arc::new(DashMap::new()),
+            peers_with_files: Arc::new(RwLock::new(HashMap::new())),
+            chunk_holders: Arc::new(DashMap::new()),
+            share_stats: Arc::new(DashMap::new()),
+            active_transfers: Arc::new(DashMap::new()),
+            ended_transfers: Arc::new(DashMap::new()),
+            ended_retention_ms: Arc::new(AtomicU64::new(DEFAULT_ENDED_RETENTION.as_millis() as u64)),
+            progress_tx,
+            observers: Arc::new(Mutex::new(Vec::new())),
+            pending_offers: Arc::new(DashMap::new()),
+            offer_tx,
+            offer_timeout_ms: Arc::new(AtomicU64::new(DEFAULT_OFFER_TIMEOUT.as_millis() as u64)),
+            downloads_dir: Arc::new(Mutex::new(
+                dirs::download_dir().unwrap_or_else(std::env::temp_dir),
+            )),
+            shareable_roots: Arc::new(Mutex::new(Vec::new())),
+            trust_store: None,
+            rate_limiter: None,
+            identity: None,
+            security_config: SecurityConfig::default(),
+            announcements: Arc::new(DashMap::new()),
+            remote_files: Arc::new(DashMap::new()),
+            retiring_shares: Arc::new(DashMap::new()),
+            expired_shares: Arc::new(DashSet::new()),
+            retraction_tx,
+            received_texts: Arc::new(DashMap::new()),
+            text_tx,
+            auto_accept: AutoAcceptConfig::default(),
+            collision_policy: CollisionPolicy::default(),
+            chunk_order: ChunkOrder::default(),
+            auto_accept_usage: Arc::new(Mutex::new((0, 0))),
+            history: Arc::new(RwLock::new(Vec::new())),
+            transfer_keys: Arc::new(DashMap::new()),
+            hash_timings: Arc::new(Mutex::new(StageTimings::default())),
+            peer_stats: Arc::new(DashMap::new()),
+            chunk_transport: None,
+            lan_transport: None,
+            chunk_windows: Arc::new(ChunkWindows::new(ChunkWindowConfig::default())),
+            chunk_size: CHUNK_SIZE,
+            hash_algorithm: HashAlgorithm::default(),
+            upload_throttle: Arc::new(Throttle::new(0)),
+            download_throttle: Arc::new(Throttle::new(0)),
+            transfer_history: None,
+            share_registry: None,
+            download_started: Arc::new(DashMap::new()),
+            uploads: Arc::new(DashMap::new()),
+            device_sends: Arc::new(DashMap::new()),
+            download_queue: Arc::default(),
+            queue_changed: Arc::new(Notify::new()),
+            max_concurrent_transfers: Arc::new(AtomicUsize::new(DEFAULT_MAX_CONCURRENT_TRANSFERS)),
+        }
+    }
+    
+    /// Apply trust levels to offers and announcements from other devices
+    pub fn with_trust_store(mut self, trust_store: Arc<TrustStore>) -> Self {
+        self.trust_store = Some(trust_store);
+        self
+    }
+    
+    /// Drop offers and chunk requests from devices sending too many
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+    
+    /// Key our own announcements are signed with
+    pub fn with_identity(mut self, identity: Arc<DeviceIdentity>) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+    
+    pub fn with_security_config(mut self, config: SecurityConfig) -> Self {
+        self.security_config = config;
+        self
+    }
+    
+    pub fn with_auto_accept(mut self, config: AutoAcceptConfig) -> Self {
+        self.auto_accept = config;
+        self
+    }
+    
+    /// What downloads do about existing files, unless one says otherwise
+    pub fn with_collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collision_policy = policy;
+        self
+    }
+    
+    pub fn with_chunk_order(mut self, order: ChunkOrder) -> Self {
+        self.chunk_order = order;
+        self
+    }
+    
+    /// Fetch chunks from peers through `transport`, pipelined per peer
+    pub fn with_chunk_transport(mut self, transport: Arc<dyn ChunkTransport>) -> Self {
+        self.chunk_transport = Some(transport);
+        self
+    }
+    
+    /// Take files sent straight to us, fetching their chunks over the
+    /// connection each was offered on
+    pub fn with_lan_transport(mut self, transport: Arc<LanChunkTransport>) -> Self {
+        self.chunk_transport = Some(transport.clone());
+        self.lan_transport = Some(transport);
+        self
+    }
+    
+    pub fn with_chunk_windows(mut self, config: ChunkWindowConfig) -> Self {
+        self.chunk_windows = Arc::new(ChunkWindows::new(config));
+        self
+    }
+    
+    /// Chunk size for files shared from now on, kept within 16 KiB to 4 MiB.
+    /// Smaller chunks suit flaky links, larger ones fast LANs.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        self
+    }
+    
+    /// Hash algorithm for shares made from now on, unless one picks its own
+    pub fn with_hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = algorithm;
+        self
+    }
+    
+    pub fn with_max_concurrent_transfers(self, max: usize) -> Self {
+        self.set_max_concurrent_transfers(max);
+        self
+    }
+    
+    /// Downloads past this many wait, Queued, for one to end; at least one runs
+    pub fn set_max_concurrent_transfers(&self, max: usize) {
+        self.max_concurrent_transfers.store(max.max(1), Ordering::Relaxed);
+        self.queue_changed.notify_waiters();
+    }
+    
+    pub fn with_chunk_cache_limit(self, bytes: u64) -> Self {
+        self.set_chunk_cache_limit(bytes);
+        self
+    }
+    
+    /// Most chunk data kept in memory for serving, 256 MiB by default; 0
+    /// reads every chunk from disk as it's asked for
+    pub fn set_chunk_cache_limit(&self, bytes: u64) {
+        self.chunk_cache.lock().unwrap().set_limit(bytes);
+    }
+    
+    pub fn chunk_cache_usage(&self) -> ChunkCacheUsage {
+        self.chunk_cache.lock().unwrap().usage()
+    }
+    
+    /// Record every transfer that completes, fails or is cancelled
+    pub fn with_transfer_history(mut self, history: Arc<TransferHistory>) -> Self {
+        self.transfer_history = Some(history);
+        self
+    }
+    
+    /// Record the files we share, to share them again after a restart
+    pub fn with_share_registry(mut self, registry: Arc<ShareRegistry>) -> Self {
+        self.share_registry = Some(registry);
+        self
+    }
+    
+    /// How long cancelled transfers stay in the progress list. Failed ones
+    /// stay until they're dismissed.
+    pub fn set_ended_retention(&self, retention: Duration) {
+        self.ended_retention_ms.store(retention.as_millis() as u64, Ordering::Relaxed);
+    }
+    
+    /// Cap on bytes per second sent to peers, zero for none. Applies to
+    /// transfers already running.
+    pub fn set_upload_limit(&self, bytes_per_second: u64) {
+        self.upload_throttle.set_limit(bytes_per_second);
+    }
+    
+    /// Cap on bytes per second fetched from peers, zero for none. Applies
+    /// to transfers already running.
+    pub fn set_download_limit(&self, bytes_per_second: u64) {
+        self.download_throttle.set_limit(bytes_per_second);
+    }
+    
+    /// Where accepted files go when the user doesn't pick a location
+    pub fn downloads_dir(&self) -> PathBuf {
+        self.downloads_dir.lock().unwrap().clone()
+    }
+    
+    /// Where incoming files go when no other folder is picked. It's created
+    /// if need be, and refused if files can't be written there.
+    pub fn set_downloads_dir(&self, dir: PathBuf) -> Result<(), Error> {
+        check_writable(&dir)?;
+        *self.downloads_dir.lock().unwrap() = dir;
+        Ok(())
+    }
+    
+    pub fn shareable_roots(&self) -> Vec<PathBuf> {
+        self.shareable_roots.lock().unwrap().clone()
+    }
+    
+    /// Restrict sharing to these directories; an empty list allows any path
+    pub fn set_shareable_roots(&self, roots: Vec<PathBuf>) {
+        *self.shareable_roots.lock().unwrap() = roots;
+    }
+    
+    /// Allow sharing from one more directory; false if it was already covered
+    pub fn add_shareable_root(&self, root: &Path) -> Result<bool, Error> {
+        let root = root
+            .canonicalize()
+            .map_err(|_| DeskShareError::FileNotFound(root.display().to_string()))?;
+        if !root.is_dir() {
+            return Err(DeskShareError::FileNotFound(root.display().to_string()).into());
+        }
+        
+        let mut roots = self.shareable_roots.lock().unwrap();
+        if roots.iter().filter_map(|r| r.canonicalize().ok()).any(|r| root.starts_with(r)) {
+            return Ok(false);
+        }
+        roots.push(root);
+        Ok(true)
+    }
+    
+    /// Resolve `path` and make sure it lies inside a shareable root
+    pub fn check_shareable(&self, path: &Path) -> Result<PathBuf, Error> {
+        let resolved = path
+            .canonicalize()
+            .map_err(|_| DeskShareError::FileNotFound(path.display().to_string()))?;
+        
+        let roots = self.shareable_roots();
+        if roots.is_empty() {
+            return Ok(resolved);
+        }
+        
+        let allowed = roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| resolved.starts_with(root));
+        if !allowed {
+            tracing::warn!("Refusing to share {:?}: outside the shareable directories", path);
+            return Err(DeskShareError::PathNotAllowed(path.display().to_string()).into());
+        }
+        
+        Ok(resolved)
+    }
+    
+    pub async fn get_transfer_progress(&self) -> Vec<TransferProgress> {
+        self.forget_ended_transfers().await;
+        self.active_transfers.iter().map(|progress| progress.value().clone()).collect()
+    }
+    
+    /// Wait for a transfer to complete, fail or be cancelled and return
+    /// which; None if it isn't listed
+    pub async fn wait_for_transfer(&self, file_hash: &str) -> Option<TransferStatus> {
+        // Subscribed before looking, so a change in between isn't missed
+        let mut changes = self.progress_tx.subscribe();
+        loop {
+            let status = self.active_transfers.get(file_hash)?.status;
+            if status.is_terminal() {
+                return Some(status);
+            }
+            if let Err(broadcast::error::RecvError::Closed) = changes.recv().await {
+                return None;
+            }
+        }
+    }
+    
+    /// Drop cancelled transfers that ended longer ago than the retention
+    /// period, along with whatever they still held
+    async fn forget_ended_transfers(&self) {
+        let retention = Duration::from_millis(self.ended_retention_ms.load(Ordering::Relaxed));
+        let failed = |file_hash: &String| {
+            self.active_transfers.get(file_hash).is_some_and(|progress| progress.status == TransferStatus::Failed)
+        };
+        let expired: Vec<String> = self
+            .ended_transfers
+            .iter()
+            .filter(|ended| ended.value().elapsed() >= retention && !failed(ended.key()))
+            .map(|ended| ended.key().clone())
+            .collect();
+        self.forget_transfers(&expired).await;
+    }
+    
+    /// Take a failed or cancelled transfer off the progress list, with the
+    /// files of a folder that ended with it
+    pub async fn dismiss_transfer(&self, file_hash: &str) -> Result<(), Error> {
+        let status = self
+            .active_transfers
+            .get(file_hash)
+            .map(|progress| progress.status)
+            .ok_or_else(|| DeskShareError::TransferNotFound(file_hash.to_string()))?;
+        if !matches!(status, TransferStatus::Failed | TransferStatus::Cancelled) {
+            return Err(DeskShareError::InvalidTransition(format!("cannot dismiss transfer {} while it's {:?}", file_hash, status)).into());
+        }
+        
+        let mut dismissed = vec![file_hash.to_string()];
+        let files: Vec<String> = self.folders.get(file_hash).map(|folder| folder.files.keys().cloned().collect()).unwrap_or_default();
+        dismissed.extend(files.into_iter().filter(|file| {
+            self.active_transfers.get(file).is_some_and(|progress| matches!(progress.status, TransferStatus::Failed | TransferStatus::Cancelled))
+        }));
+        self.forget_transfers(&dismissed).await;
+        Ok(())
+    }
+    
+    async fn forget_transfers(&self, file_hashes: &[String]) {
+        if file_hashes.is_empty() {
+            return;
+        }
+        
+        for file_hash in file_hashes {
+            self.ended_transfers.remove(file_hash);
+            self.active_transfers.remove(file_hash);
+            self.folders.remove(file_hash);
+        }
+        let mut downloading_files = self.downloading_files.write().await;
+        for file_hash in file_hashes {
+            downloading_files.remove(file_hash);
+        }
+        drop(downloading_files);
+        self.file_chunks.retain(|_, chunk| !file_hashes.contains(&chunk.file_hash));
+    }
+    
+    /// Subscribe to progress updates for every transfer tracked by this instance
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<TransferProgress> {
+        self.progress_tx.subscribe()
+    }
+    
+    /// Call `observer` back as transfers start, move along and end
+    pub fn add_observer(&self, observer: Box<dyn TransferObserver>) {
+        self.observers.lock().unwrap().push(Arc::from(observer));
+    }
+    
+    /// Copied out, so observers run without the list locked
+    fn observers(&self) -> Vec<Arc<dyn TransferObserver>> {
+        self.observers.lock().unwrap().clone()
+    }
+    
+    /// Stop requesting chunks for an in-progress download. A queued one
+    /// gives up its place in line and starts straight away when resumed.
+    pub async fn pause_transfer(&self, file_hash: &str) -> Result<TransferStatus, Error> {
+        let status = self.transition(file_hash, TransferStatus::Paused).await?;
+        self.download_queue.lock().unwrap().waiting.retain(|queued| queued != file_hash);
+        self.queue_changed.notify_waiters();
+        self.save_partial(file_hash).await;
+        Ok(status)
+    }
+    
+    /// Continue a paused download from the chunks already received
+    pub async fn resume_transfer(&self, file_hash: &str) -> Result<TransferStatus, Error> {
+        let status = self.transition(file_hash, TransferStatus::InProgress).await?;
+        {
+            // One restored paused holds no slot until now
+            let mut queue = self.download_queue.lock().unwrap();
+            if !queue.waiting.iter().any(|queued| queued == file_hash) {
+                queue.running.insert(file_hash.to_string());
+            }
+        }
+        self.request_chunks(file_hash).await?;
+        
+        Ok(status)
+    }
+    
+    /// Abort a download, dropping its requests in flight and the chunks it
+    /// held, or stop offering a file we share. The progress entry stays
+    /// visible as Cancelled for the retention period.
+    pub async fn cancel_transfer(&self, file_hash: &str) -> Result<TransferStatus, Error> {
+        let folder_files = self.folders.get(file_hash).map(|folder| folder.files.keys().cloned().collect::<Vec<_>>());
+        if let Some(files) = folder_files {
+            for file in files {
+                let ended = self.active_transfers.get(&file).is_none_or(|progress| progress.status.is_terminal());
+                if !ended {
+                    self.cancel_download(&file).await?;
+                }
+            }
+            self.refresh_folder(file_hash).await;
+            return Ok(TransferStatus::Cancelled);
+        }
+        
+        let downloading = self.downloading_files.read().await.contains_key(file_hash);
+        if !downloading && self.share_stats.contains_key(file_hash) {
+            self.unshare_file(file_hash, true).await?;
+            // A share's entry is always Completed, so this skips the download states
+            let updated = self.active_transfers.get_mut(file_hash).map(|mut progress| {
+                let previous = progress.status;
+                progress.status = TransferStatus::Cancelled;
+                (previous, progress.clone())
+            });
+            if let Some((previous, progress)) = updated {
+                self.ended_transfers.insert(file_hash.to_string(), Instant::now());
+                self.notify_progress(Some(previous), progress, None);
+            }
+            return Ok(TransferStatus::Cancelled);
+        }
+        
+        let status = self.cancel_download(file_hash).await?;
+        if let Some(folder_hash) = self.folder_of(file_hash) {
+            self.refresh_folder(&folder_hash).await;
+        }
+        
+        Ok(status)
+    }
+    
+    async fn cancel_download(&self, file_hash: &str) -> Result<TransferStatus, Error> {
+        let status = self.transition(file_hash, TransferStatus::Cancelled).await?;
+        let cancelled = self.downloading_files.write().await.remove(file_hash);
+        self.file_chunks.retain(|_, chunk| chunk.file_hash != file_hash);
+        // Nothing half-written is left behind
+        if let Some(downloading) = cancelled.filter(|downloading| downloading.output.is_some()) {
+            self.stop_serving_download(file_hash, &downloading.output_path);
+            let _ = tokio::fs::remove_file(partial_path(&downloading.output_path)).await;
+            let _ = tokio::fs::remove_file(&downloading.output_path).await;
+        }
+        
+        Ok(status)
+    }
+    
+    async fn transition(&self, file_hash: &str, next: TransferStatus) -> Result<TransferStatus, Error> {
+        self.transition_because(file_hash, next, None).await
+    }
+    
+    /// Move to `next`, keeping `error` as the reason if it's a failure
+    async fn transition_because(&self, file_hash: &str, next: TransferStatus, error: Option<&DeskShareError>) -> Result<TransferStatus, Error> {
+        // Bytes still batched go out with the state change
+        let accumulator = self.downloading_files.read().await.get(file_hash).map(|downloading| downloading.progress.clone());
+        let flush = accumulator.as_ref().map(|accumulator| accumulator.flush());
+        let updated = {
+            let mut progress = self
+                .active_transfers
+                .get_mut(file_hash)
+                .ok_or_else(|| DeskShareError::TransferNotFound(file_hash.to_string()))?;
+            if let Some(flush) = &flush {
+                Self::apply_flush(&mut progress, flush);
+            }
+            
+            if !progress.status.can_transition_to(next) {
+                return Err(DeskShareError::InvalidTransition(format!(
+                    "cannot move transfer {} from {:?} to {:?}",
+                    file_hash, progress.status, next
+                )).into());
+            }
+            
+            let previous = progress.status;
+            progress.status = next;
+            if let Some(error) = error {
+                progress.error_message = Some(error.user_message());
+                progress.error_kind = Some(error.code().to_string());
+            }
+            (previous, progress.clone())
+        };
+        let (previous, updated) = updated;
+        if matches!(next, TransferStatus::Failed | TransferStatus::Cancelled) {
+            self.ended_transfers.insert(file_hash.to_string(), Instant::now());
+            self.record_download(&updated);
+            self.leave_queue(file_hash);
+        }
+        
+        let reason = error.map(|error| error.to_string());
+        self.notify_progress(Some(previous), updated, reason.as_deref());
+        
+        Ok(next)
+    }
+    
+    async fn publish_progress(&self, progress: TransferProgress) {
+        let previous = self
+            .active_transfers
+            .insert(progress.file_hash.clone(), progress.clone())
+            .map(|previous| previous.status);
+        self.notify_progress(previous, progress, None);
+    }
+    
+    /// Send out a change to a transfer's progress, `previous` being its
+    /// status before, if it was listed
+    fn notify_progress(&self, previous: Option<TransferStatus>, progress: TransferProgress, reason: Option<&str>) {
+        for observer in self.observers() {
+            match progress.status {
+                TransferStatus::Completed if previous != Some(TransferStatus::Completed) => observer.on_completed(&progress),
+                TransferStatus::Failed if previous != Some(TransferStatus::Failed) => observer.on_failed(&progress, reason),
+                status if !status.is_terminal() && previous.is_none_or(TransferStatus::is_terminal) => observer.on_started(&progress),
+                _ => observer.on_progress(&progress),
+            }
+        }
+        // Nobody listening is fine, the snapshot in `active_transfers` is still up to date
+        let _ = self.progress_tx.send(progress);
+    }
+    
+    /// What's in a shareable folder, folders first, then by name. Hidden
+    /// entries are left out unless `show_hidden` is set.
+    pub async fn list_files_in_directory(&self, path: &str, show_hidden: bool) -> Result<Vec<DirEntryInfo>, Error> {
+        let mut listing = Vec::new();
+        let mut entries = tokio::fs::read_dir(self.check_shareable(Path::new(path))?).await?;
+        
+        while let Some(entry) = entries.next_entry().await? {
+            // The entry itself, not what a link points at
+            let metadata = entry.metadata().await?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !show_hidden && is_hidden(&name, &metadata) {
+                continue;
+            }
+            let (is_dir, is_symlink) = (metadata.is_dir(), metadata.file_type().is_symlink());
+            listing.push(DirEntryInfo {
+                name,
+                path: entry.path(),
+                is_dir,
+                is_symlink,
+                size: if metadata.is_file() { metadata.len() } else { 0 },
+                modified: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|since| since.as_secs()),
+            });
+        }
+        
+        listing.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())));
+        Ok(listing)
+    }
+    
+    fn apply_flush(progress: &mut TransferProgress, flush: &Flush) {
+        progress.bytes_transferred = flush.bytes;
+        progress.percentage = match progress.total_bytes {
+            0 => 100.0,
+            total => (flush.bytes as f64 / total as f64) * 100.0,
+        };
+        if let Some(bytes_per_second) = flush.bytes_per_second {
+            progress.bytes_per_second = bytes_per_second;
+        }
+        progress.eta_seconds = Self::estimate_eta(progress.total_bytes.saturating_sub(flush.bytes), progress.bytes_per_second);
+    }
+    
+    fn estimate_eta(remaining_bytes: u64, bytes_per_second: f64) -> Option<u64> {
+        if remaining_bytes == 0 {
+            Some(0)
+        } else if bytes_per_second > 0.0 {
+            Some((remaining_bytes as f64 / bytes_per_second).ceil() as u64)
+        } else {
+            None
+        }
+    }
+    
+    /// Add a download that just ended to the history, once. A folder's
+    /// files are covered by the folder's own record.
+    fn record_download(&self, progress: &TransferProgress) {
+        let Some((_, started)) = self.download_started.remove(&progress.file_hash) else {
+            return;
+        };
+        if self.folder_of(&progress.file_hash).is_some() {
+            return;
+        }
+        let peer_id = self.shared_files.get(&progress.file_hash).map(|file| file.peer_id.clone()).unwrap_or_default();
+        self.record_transfer(TransferRecord {
+            file_name: progress.file_name.clone(),
+            file_hash: progress.file_hash.clone(),
+            size: progress.total_bytes,
+            peer_id,
+            direction: TransferDirection::Download,
+            status: progress.status,
+            duration_ms: started.elapsed().as_millis() as u64,
+            timestamp: Self::now_secs(),
+        });
+    }
+    
+    fn record_transfer(&self, record: TransferRecord) {
+        let Some(history) = &self.transfer_history else {
+            return;
+        };
+        if let Err(e) = history.append(&record) {
+            tracing::warn!("Couldn't record transfer of {}: {}", record.file_name, e);
+        }
+    }
+    
+    /// Finished transfers, newest first
+    pub fn get_transfer_history(&self, limit: usize, offset: usize) -> Result<Vec<TransferRecord>, Error> {
+        match &self.transfer_history {
+            Some(history) => history.list(limit, offset),
+            None => Ok(Vec::new()),
+        }
+    }
+    
+    pub fn clear_history(&self) -> Result<(), Error> {
+        match &self.transfer_history {
+            Some(history) => history.clear(),
+            None => Ok(()),
+        }
+    }
+    
+    fn now_secs() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+    
+    /// Time spent hashing shared and downloaded files
+    pub fn hash_timings(&self) -> StageTimings {
+        *self.hash_timings.lock().unwrap()
+    }
+    
+    /// What's been sent to and fetched from each peer, by peer id
+    pub fn get_peer_stats(&self) -> Vec<PeerTransferStats> {
+        let mut stats: Vec<PeerTransferStats> = self.peer_stats.iter().map(|stats| stats.value().clone()).collect();
+        stats.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        stats
+    }
+    
+    pub fn reset_peer_stats(&self) {
+        self.peer_stats.clear();
+    }
+    
+    fn record_peer(&self, peer_id: &str, update: impl FnOnce(&mut PeerTransferStats)) {
+        update(&mut self.peer_stats.entry(peer_id.to_string()).or_insert_with(|| PeerTransferStats::new(peer_id)));
+    }
+    
+    /// Run hashing on a blocking thread so timers and network IO on the
+    /// runtime keep going; the async side only waits for the result
+    async fn off_runtime<T: Send + 'static>(
+        &self,
+        work: impl FnOnce(&AtomicBool) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let _cancel_on_drop = CancelOnDrop(cancel.clone());
+        let started = Instant::now();
+        
+        let result = tokio::task::spawn_blocking(move || work(&cancel))
+            .await
+            .map_err(|e| DeskShareError::Internal(e.to_string()))?;
+        self.hash_timings.lock().unwrap().record(started.elapsed());
+        result
+    }
+    
+    /// Hash a file to share in one pass, keeping nothing of it but hashes
+    fn index_file(file: std::fs::File, chunk_size: usize, algorithm: HashAlgorithm, cancel: &AtomicBool) -> Result<HashedFile, Error> {
+        let size = file.metadata()?.len();
+        let mut chunks = Vec::with_capacity((size as usize).div_ceil(chunk_size));
+        let mut codec = ChunkCodec::Raw;
+        let hash = Self::hash_chunks(file, chunk_size, algorithm, cancel, |i, chunk| {
+            if i == 0 {
+                codec = ChunkCodec::choose(&chunk);
+            }
+            chunks.push((Self::calculate_chunk_hash(algorithm, i, &chunk), chunk.len()));
+        })?;
+        Ok(HashedFile { hash, chunks, codec })
+    }
+    
+    /// File hash of everything `reader` yields, read and hashed a chunk at a
+    /// time in one pass. The file hash doesn't depend on `chunk_size`, only
+    /// the chunks `each` gets do; a raised `cancel` stops the work before
+    /// the next one.
+    fn hash_chunks(
+        mut reader: impl Read,
+        chunk_size: usize,
+        algorithm: HashAlgorithm,
+        cancel: &AtomicBool,
+        mut each: impl FnMut(usize, Bytes),
+    ) -> Result<String, Error> {
+        let mut hasher = algorithm.hasher();
+        for i in 0.. {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(DeskShareError::FileTransferFailed("hashing cancelled".to_string()).into());
+            }
+            let mut chunk = Vec::with_capacity(chunk_size);
+            (&mut reader).take(chunk_size as u64).read_to_end(&mut chunk)?;
+            if chunk.is_empty() {
+                break;
+            }
+            hasher.update(&chunk);
+            each(i, Bytes::from(chunk));
+        }
+        
+        Ok(hasher.finalize_hex())
+    }
+    
+    /// A chunk's hash covers its place in the file as well as its data
+    fn calculate_chunk_hash(algorithm: HashAlgorithm, index: usize, data: &[u8]) -> String {
+        let mut hasher = algorithm.hasher();
+        hasher.update(&index.to_le_bytes());
+        hasher.update(data);
+        hasher.finalize_hex()
+    }
+    
+    /// The algorithm a share's hashes were taken with, ours or announced
+    fn algorithm_of(&self, file_hash: &str) -> HashAlgorithm {
+        self.shared_files.get(file_hash).map(|file| file.hash_algorithm).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::transfer_protocol::FileTransferMessage;
+    use crate::security::SecureChannel;
+    use super::pipeline::{MAX_CHUNK_ATTEMPTS, MAX_PEER_FAILURES};
+    use tokio::io::DuplexStream;
+    use tokio::sync::mpsc;
+    use crate::network::chunk_pipeline::MemoryChunkTransport;
+    use crate::network::observer::{ChannelObserver, TransferEvent};
+    
+    pub(super) fn describe(data: &[u8], chunk_size: usize) -> SharedFile {
+        let chunks: Vec<String> = data
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(i, chunk)| FileTransfer::calculate_chunk_hash(HashAlgorithm::Blake3, i, chunk))
+            .collect();
+        SharedFile {
+            hash: FileTransfer::hash_chunks(data, CHUNK_SIZE, HashAlgorithm::Blake3, &AtomicBool::new(false), |_, _| {}).unwrap(),
+            name: "report.pdf".to_string(),
+            size: data.len() as u64,
+            total_chunks: chunks.len(),
+            chunks,
+            chunk_size: chunk_size as u64,
+            peer_id: "10.0.0.3".to_string(),
+            timestamp: 0,
+            kind: ShareKind::File,
+            codec: ChunkCodec::Raw,
+            encrypted: true,
+            manifest_version: MANIFEST_VERSION_FLAT,
+            merkle_root: None,
+            hash_algorithm: HashAlgorithm::Blake3,
+        }
+    }
+    
+    pub(super) async fn holders(file_transfer: &FileTransfer, file_hash: &str) -> Vec<String> {
+        let mut peers: Vec<String> = file_transfer
+            .peers_with_files
+            .read()
+            .await
+            .get(file_hash)
+            .map(|peers| peers.iter().cloned().collect())
+            .unwrap_or_default();
+        peers.sort();
+        peers
+    }
+    
+    pub(super) fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dsn-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+    
+    #[test]
+    fn test_transfer_status_transitions() {
+        use TransferStatus::*;
+        let all = [Pending, Queued, InProgress, Paused, Verifying, Completed, Failed, Cancelled];
+        let allowed = [
+            (Pending, Queued), (Pending, InProgress), (Pending, Paused), (Pending, Failed), (Pending, Cancelled),
+            (Queued, InProgress), (Queued, Paused), (Queued, Cancelled),
+            (InProgress, Paused), (InProgress, Verifying), (InProgress, Completed), (InProgress, Failed), (InProgress, Cancelled),
+            (Paused, InProgress), (Paused, Verifying), (Paused, Cancelled),
+            (Verifying, Completed), (Verifying, Failed), (Verifying, Cancelled),
+        ];
+        for from in all {
+            for to in all {
+                assert_eq!(from.can_transition_to(to), allowed.contains(&(from, to)), "{:?} -> {:?}", from, to);
+            }
+            assert_eq!(from.is_terminal(), !all.iter().any(|&to| from.can_transition_to(to)));
+        }
+        
+        assert_eq!(serde_json::to_string(&InProgress).unwrap(), r#""in_progress""#);
+        assert_eq!(serde_json::to_string(&Verifying).unwrap(), r#""verifying""#);
+        assert_eq!(serde_json::from_str::<TransferStatus>(r#""Completed""#).unwrap(), Completed);
+        assert_eq!(serde_json::from_str::<TransferStatus>(r#""cancelled""#).unwrap(), Cancelled);
+    }
+    
+    #[tokio::test]
+    async fn test_downloads_dir_must_be_writable() {
+        let file_transfer = FileTransfer::new().await;
+        let dir = scratch_dir("downloads-dir");
+        let default = file_transfer.downloads_dir();
+        
+        // Created when it's missing
+        file_transfer.set_downloads_dir(dir.join("incoming/new")).unwrap();
+        assert!(dir.join("incoming/new").is_dir());
+        assert_eq!(file_transfer.downloads_dir(), dir.join("incoming/new"));
+        assert_eq!(std::fs::read_dir(dir.join("incoming/new")).unwrap().count(), 0);
+        
+        // Somewhere nothing can be written is refused and changes nothing
+        std::fs::write(dir.join("notes.txt"), b"a file, not a folder").unwrap();
+        let err = file_transfer.set_downloads_dir(dir.join("notes.txt/inside")).unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "invalid_config");
+        assert_eq!(file_transfer.downloads_dir(), dir.join("incoming/new"));
+        assert_ne!(default, dir.join("incoming/new"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_large_files_are_checked_against_a_merkle_root() {
+        let dir = scratch_dir("merkle");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..MERKLE_MIN_CHUNKS * MIN_CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("disk.img"), &data).unwrap();
+        std::fs::write(dir.join("small.txt"), b"small").unwrap();
+        let sender = FileTransfer::new().await.with_chunk_size(MIN_CHUNK_SIZE);
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = sender.share_file(&dir.join("disk.img"), "local".to_string()).await.unwrap();
+        let file = sender.shared_files.get(&file_hash).unwrap().clone();
+        assert_eq!(file.manifest_version, MANIFEST_VERSION_MERKLE);
+        assert_eq!(file.merkle_root, Some(MerkleTree::from_chunk_hashes(&file.chunks).unwrap().root()));
+        
+        // Small files stay flat, and old announcements read as flat
+        let small = sender.share_file(&dir.join("small.txt"), "local".to_string()).await.unwrap();
+        let small = sender.shared_files.get(&small).unwrap().clone();
+        assert_eq!((small.manifest_version, small.merkle_root.as_deref()), (MANIFEST_VERSION_FLAT, None));
+        assert!(!serde_json::to_string(&small).unwrap().contains("merkle_root"));
+        let mut old = serde_json::to_value(&small).unwrap();
+        old.as_object_mut().unwrap().remove("manifest_version");
+        assert_eq!(serde_json::from_value::<SharedFile>(old).unwrap().manifest_version, MANIFEST_VERSION_FLAT);
+        assert!(sender.chunk_proof(&small.hash, 0).is_err());
+        
+        // Any chunk checks out against the root with its proof alone
+        let proof = sender.chunk_proof(&file_hash, 40).unwrap();
+        let chunk = &data[40 * MIN_CHUNK_SIZE..41 * MIN_CHUNK_SIZE];
+        assert!(file.verify_chunk(40, chunk, &proof));
+        assert!(!file.verify_chunk(41, chunk, &proof));
+        assert!(!file.verify_chunk(40, &chunk[1..], &proof));
+        
+        // A root that doesn't match the chunk hashes is refused
+        let receiver = FileTransfer::new().await;
+        let forged = SharedFile { merkle_root: Some(small.hash.clone()), ..file.clone() };
+        let offer_id = receiver.receive_offer("10.0.0.2".to_string(), "Alice".to_string(), forged).await;
+        let err = receiver.accept_offer(&offer_id, &dir.join("downloads")).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "integrity_check_failed");
+        
+        let offer_id = receiver.receive_offer("10.0.0.2".to_string(), "Alice".to_string(), file.clone()).await;
+        receiver.accept_offer(&offer_id, &dir.join("downloads")).await.unwrap();
+        for chunk_index in 0..file.total_chunks {
+            let request = FileTransferMessage::ChunkRequest { file_hash: file_hash.clone(), chunk_index };
+            let reply = sender.handle_incoming_message("10.0.0.3", request.encode().unwrap()).await.unwrap().unwrap();
+            receiver.handle_incoming_message("10.0.0.2", reply).await.unwrap();
+        }
+        let progress = receiver.get_transfer_progress().await;
+        assert_eq!(progress[0].status, TransferStatus::Completed);
+        assert_eq!(std::fs::read(dir.join("downloads").join("disk.img")).unwrap(), data);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_files_move_under_either_hash_algorithm() {
+        let dir = scratch_dir("hash-algorithms");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("audit.log"), &data).unwrap();
+        
+        for algorithm in [HashAlgorithm::Blake3, HashAlgorithm::Sha256] {
+            let sender = FileTransfer::new().await;
+            sender.set_shareable_roots(vec![dir.clone()]);
+            let file_hash = sender
+                .share_file_with_hash_algorithm(&dir.join("audit.log"), "local".to_string(), algorithm)
+                .await
+                .unwrap();
+            let file = sender.shared_files.get(&file_hash).unwrap().clone();
+            assert_eq!((file_hash.as_str(), file.hash_algorithm), (algorithm.hash(&data).as_str(), algorithm));
+            assert_eq!(file.chunks[1], FileTransfer::calculate_chunk_hash(algorithm, 1, &data[CHUNK_SIZE..2 * CHUNK_SIZE]));
+            
+            // The receiver goes by what the offer names
+            let receiver = FileTransfer::new().await;
+            let offer = FileTransferMessage::Offer { sender_name: "Alice".to_string(), file: file.clone() };
+            receiver.handle_incoming_message("10.0.0.2", offer.encode().unwrap()).await.unwrap();
+            let offer_id = receiver.get_pending_offers()[0].offer_id.clone();
+            let downloads = dir.join(format!("{:?}", algorithm));
+            receiver.accept_offer(&offer_id, &downloads).await.unwrap();
+            for chunk_index in 0..file.total_chunks {
+                let request = FileTransferMessage::ChunkRequest { file_hash: file_hash.clone(), chunk_index };
+                let reply = sender.handle_incoming_message("10.0.0.3", request.encode().unwrap()).await.unwrap().unwrap();
+                receiver.handle_incoming_message("10.0.0.2", reply).await.unwrap();
+            }
+            let progress = receiver.get_transfer_progress().await;
+            assert_eq!(progress[0].status, TransferStatus::Completed, "{:?}", algorithm);
+            assert_eq!(progress[0].verified_hash.as_deref(), Some(file_hash.as_str()));
+            assert_eq!(std::fs::read(downloads.join("audit.log")).unwrap(), data);
+        }
+        
+        // An algorithm we don't know makes the offer unreadable
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = sender.share_file(&dir.join("audit.log"), "local".to_string()).await.unwrap();
+        let file = sender.shared_files.get(&file_hash).unwrap().clone();
+        let manifest = serde_json::to_vec(&file).unwrap();
+        let offer = FileTransferMessage::Offer { sender_name: "Alice".to_string(), file }.encode().unwrap();
+        assert!(offer.ends_with(&manifest));
+        let mut changed: serde_json::Value = serde_json::from_slice(&manifest).unwrap();
+        assert_eq!(changed["hash_algorithm"], "blake3");
+        changed["hash_algorithm"] = "md5".into();
+        let mut message = offer[..offer.len() - manifest.len()].to_vec();
+        message.extend_from_slice(&serde_json::to_vec(&changed).unwrap());
+        let receiver = FileTransfer::new().await;
+        let err = receiver.handle_incoming_message("10.0.0.2", Bytes::from(message)).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "invalid_message_format");
+        assert!(receiver.get_pending_offers().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    pub(super) async fn channel_pair(a: &DeviceIdentity, b: &DeviceIdentity) -> (SecureChannel<DuplexStream>, SecureChannel<DuplexStream>) {
+        let (a_io, b_io) = tokio::io::duplex(4096);
+        let (a_channel, b_channel) = tokio::join!(SecureChannel::connect(a_io, a), SecureChannel::accept(b_io, b));
+        (a_channel.unwrap(), b_channel.unwrap())
+    }
+    
+    #[tokio::test]
+    async fn test_finished_transfers_are_recorded_in_the_history() {
+        let dir = scratch_dir("history");
+        std::fs::create_dir_all(&dir).unwrap();
+        let history = Arc::new(TransferHistory::open(&dir.join("history.jsonl")));
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE).map(|i| (i % 211) as u8).collect();
+        std::fs::write(dir.join("notes.txt"), &data).unwrap();
+        let sender = FileTransfer::new().await.with_transfer_history(history.clone());
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = sender.share_file(&dir.join("notes.txt"), "local".to_string()).await.unwrap();
+        let file = sender.shared_files.get(&file_hash).unwrap().clone();
+        
+        // Every chunk served to a peer makes an upload; a repeat doesn't count twice
+        let (alice, bob) = (DeviceIdentity::generate(), DeviceIdentity::generate());
+        let (_, bob_channel) = channel_pair(&alice, &bob).await;
+        sender.set_transfer_key(&file_hash, "10.0.0.2", &bob_channel);
+        for chunk_hash in [&file.chunks[0], &file.chunks[0], &file.chunks[1]] {
+            sender.handle_chunk_request(chunk_hash, "10.0.0.2".to_string()).await.unwrap();
+        }
+        
+        // A download that completes, and one that's cancelled
+        let (transport, _) = MemoryChunkTransport::new();
+        let mut served = Vec::new();
+        for chunk_hash in &file.chunks {
+            let chunk = sender.load_chunk(chunk_hash).await.unwrap().unwrap();
+            served.push(sender.encode_chunk(chunk).await.unwrap().data);
+        }
+        transport.offer(&file_hash, served);
+        let receiver = FileTransfer::new().await.with_transfer_history(history.clone()).with_chunk_transport(Arc::new(transport));
+        receiver.shared_files.insert(file_hash.clone(), file);
+        receiver.peers_with_files.write().await.insert(file_hash.clone(), HashSet::from(["10.0.0.3".to_string()]));
+        receiver.download_file(&file_hash, &dir.join("downloads").join("notes.txt")).await.unwrap();
+        let stalled = describe(b"never arrives", 4);
+        receiver.shared_files.insert(stalled.hash.clone(), stalled.clone());
+        receiver.begin_download(stalled.clone(), &dir.join("downloads").join("stalled.bin")).await.unwrap();
+        receiver.cancel_transfer(&stalled.hash).await.unwrap();
+        
+        let records = receiver.get_transfer_history(10, 0).unwrap();
+        let summary: Vec<_> = records.iter().map(|record| (record.file_hash.as_str(), record.direction, record.status)).collect();
+        assert_eq!(summary, vec![
+            (stalled.hash.as_str(), TransferDirection::Download, TransferStatus::Cancelled),
+            (file_hash.as_str(), TransferDirection::Download, TransferStatus::Completed),
+            (file_hash.as_str(), TransferDirection::Upload, TransferStatus::Completed),
+        ]);
+        assert_eq!((records[1].size, records[1].peer_id.as_str()), (data.len() as u64, "local"));
+        assert_eq!((records[2].file_name.as_str(), records[2].peer_id.as_str()), ("notes.txt", "10.0.0.2"));
+        
+        receiver.clear_history().unwrap();
+        assert!(sender.get_transfer_history(10, 0).unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_listing_describes_each_entry() {
+        let dir = scratch_dir("listing");
+        std::fs::create_dir_all(dir.join("photos")).unwrap();
+        std::fs::create_dir_all(dir.join("Archive")).unwrap();
+        std::fs::create_dir_all(dir.join(".cache")).unwrap();
+        std::fs::write(dir.join("notes.txt"), b"notes").unwrap();
+        std::fs::write(dir.join("Budget.xlsx"), vec![0u8; 1500]).unwrap();
+        std::fs::write(dir.join(".profile"), b"hidden").unwrap();
+        std::fs::write(dir.join("photos/beach.jpg"), b"jpeg").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("photos"), dir.join("latest")).unwrap();
+        
+        let file_transfer = FileTransfer::new().await;
+        file_transfer.set_shareable_roots(vec![dir.clone()]);
+        let listing = file_transfer.list_files_in_directory(&dir.to_string_lossy(), false).await.unwrap();
+        let names: Vec<&str> = listing.iter().map(|entry| entry.name.as_str()).collect();
+        #[cfg(unix)]
+        assert_eq!(names, ["Archive", "photos", "Budget.xlsx", "latest", "notes.txt"]);
+        #[cfg(not(unix))]
+        assert_eq!(names, ["Archive", "photos", "Budget.xlsx", "notes.txt"]);
+        
+        let entry = |name: &str| listing.iter().find(|entry| entry.name == name).unwrap();
+        assert!(entry("photos").is_dir && !entry("photos").is_symlink);
+        assert_eq!(entry("photos").size, 0);
+        assert_eq!(entry("photos").path, dir.join("photos"));
+        assert!(!entry("Budget.xlsx").is_dir);
+        assert_eq!(entry("Budget.xlsx").size, 1500);
+        assert!(entry("notes.txt").modified.is_some_and(|modified| modified > 1_600_000_000));
+        #[cfg(unix)]
+        assert!(entry("latest").is_symlink && !entry("latest").is_dir);
+        
+        // Hidden entries only when asked for
+        let listing = file_transfer.list_files_in_directory(&dir.to_string_lossy(), true).await.unwrap();
+        assert_eq!(listing[0].name, ".cache");
+        assert!(listing.iter().any(|entry| entry.name == ".profile"));
+        let listing = file_transfer.list_files_in_directory(&dir.join("photos").to_string_lossy(), false).await.unwrap();
+        assert_eq!(listing.len(), 1);
+        assert_eq!(listing[0].name, "beach.jpg");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_timer_keeps_schedule_while_hashing() {
+        let data: Vec<u8> = (0..64 * 1024 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let expected = FileTransfer::hash_chunks(data.as_slice(), CHUNK_SIZE, HashAlgorithm::Blake3, &AtomicBool::new(false), |_, _| {}).unwrap();
+        
+        // One runtime thread: hashing inline would stall the ticker outright
+        let file_transfer = FileTransfer::new().await;
+        let hashing = file_transfer.off_runtime(move |cancel| FileTransfer::hash_chunks(data.as_slice(), CHUNK_SIZE, HashAlgorithm::Blake3, cancel, |_, _| {}));
+        tokio::pin!(hashing);
+        let mut ticker = tokio::time::interval(Duration::from_millis(1));
+        ticker.tick().await;
+        let mut last_tick = Instant::now();
+        let mut worst_gap = Duration::ZERO;
+        let hash = loop {
+            tokio::select! {
+                hash = &mut hashing => break hash.unwrap(),
+                _ = ticker.tick() => {
+                    worst_gap = worst_gap.max(last_tick.elapsed());
+                    last_tick = Instant::now();
+                }
+            }
+        };
+        worst_gap = worst_gap.max(last_tick.elapsed());
+        
+        // Inline, the ticker would wait out the whole hash
+        let timings = file_transfer.hash_timings();
+        assert_eq!(timings.runs, 1);
+        assert!(worst_gap.as_secs_f64() * 1000.0 < timings.last_ms / 4.0, "ticker stalled for {:?} of {:?}", worst_gap, timings);
+        assert_eq!(hash, expected);
+        
+        // A raised flag stops the work before the next chunk
+        let cancelled = FileTransfer::hash_chunks(&[0u8; 16][..], CHUNK_SIZE, HashAlgorithm::Blake3, &AtomicBool::new(true), |_, _| panic!("hashed a chunk"));
+        assert!(cancelled.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_streamed_hashes_match_naive_hashing() {
+        use rand::seq::SliceRandom;
+        use rand::{Rng, RngCore};
+        let mut rng = rand::thread_rng();
+        let naive = |data: &[u8]| hex::encode(blake3::hash(data).as_bytes());
+        
+        // Sharing reads and hashes in the same pass
+        let dir = scratch_dir("streamed");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut data = vec![0u8; 2 * CHUNK_SIZE + rng.gen_range(1..CHUNK_SIZE)];
+        rng.fill_bytes(&mut data);
+        std::fs::write(dir.join("random.bin"), &data).unwrap();
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = sender.share_file(&dir.join("random.bin"), "local".to_string()).await.unwrap();
+        assert_eq!(file_hash, naive(&data));
+        let shared = sender.shared_files.get(&file_hash).unwrap().clone();
+        let naive_chunks: Vec<String> = data
+            .chunks(CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| FileTransfer::calculate_chunk_hash(HashAlgorithm::Blake3, i, chunk))
+            .collect();
+        assert_eq!((shared.size, shared.chunks), (data.len() as u64, naive_chunks));
+        
+        // Downloads write chunks in place as they come, the short last one included
+        let mut data = vec![0u8; 40 * 1024 + rng.gen_range(1..4096)];
+        rng.fill_bytes(&mut data);
+        let file = describe(&data, 4096);
+        assert_eq!(file.hash, naive(&data));
+        let receiver = FileTransfer::new().await;
+        let offer_id = receiver.receive_offer("10.0.0.3".to_string(), "Bob".to_string(), file.clone()).await;
+        receiver.accept_offer(&offer_id, &dir).await.unwrap();
+        
+        let chunks: Vec<&[u8]> = data.chunks(4096).collect();
+        let last = chunks.len() - 1;
+        let mut order: Vec<usize> = (0..last).collect();
+        order.shuffle(&mut rng);
+        for index in order {
+            receiver.receive_chunk(&file.hash, index, chunks[index].to_vec()).await.unwrap();
+        }
+        let partial = std::fs::read(dir.join(&file.name)).unwrap();
+        assert_eq!(partial.len(), data.len());
+        assert_eq!(&partial[..last * 4096], &data[..last * 4096]);
+        assert!(receiver.file_chunks.is_empty());
+        
+        receiver.receive_chunk(&file.hash, last, chunks[last].to_vec()).await.unwrap();
+        assert_eq!(std::fs::read(dir.join(&file.name)).unwrap(), data);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    /// Register `count` downloads of distinct files, each `chunks` chunks long
+    async fn start_downloads(file_transfer: &FileTransfer, dir: &Path, count: usize, chunks: usize) -> Vec<(SharedFile, Vec<u8>)> {
+        // All of them run at once, none queued
+        file_transfer.set_max_concurrent_transfers(count);
+        let mut downloads = Vec::new();
+        for n in 0..count {
+            let data: Vec<u8> = (0..chunks * 32).map(|i| (i * 7 + n) as u8).collect();
+            let file = describe(&data, 32);
+            file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+            file_transfer.download_file(&file.hash, &dir.join(format!("{}.bin", n))).await.unwrap();
+            downloads.push((file, data));
+        }
+        downloads
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_downloads_batch_progress() {
+        let file_transfer = Arc::new(FileTransfer::new().await);
+        let dir = scratch_dir("parallel");
+        let mut progress_rx = file_transfer.subscribe_progress();
+        let started = Instant::now();
+        let downloads = start_downloads(&file_transfer, &dir, 16, 512).await;
+        
+        let tasks: Vec<_> = downloads
+            .iter()
+            .cloned()
+            .map(|(file, data)| {
+                let file_transfer = file_transfer.clone();
+                tokio::spawn(async move {
+                    for (index, chunk) in data.chunks(32).enumerate() {
+                        file_transfer.receive_chunk(&file.hash, index, chunk.to_vec()).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        
+        for (n, (_, data)) in downloads.iter().enumerate() {
+            assert_eq!(&std::fs::read(dir.join(format!("{}.bin", n))).unwrap(), data);
+        }
+        let progress = file_transfer.get_transfer_progress().await;
+        assert!(progress.iter().all(|p| p.status == TransferStatus::Completed && p.bytes_transferred == p.total_bytes));
+        
+        // Per file: the initial update, one per 100 ms, verification and completion
+        let mut updates = 0;
+        loop {
+            match progress_rx.try_recv() {
+                Ok(_) => updates += 1,
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => updates += missed,
+                Err(_) => break,
+            }
+        }
+        let ticks = started.elapsed().as_millis() as u64 / 100;
+        assert!(updates <= 16 * (3 + ticks), "{} progress updates", updates);
+        assert!(updates < 16 * 512 / 4, "{} progress updates", updates);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_state_changes_flush_batched_progress() {
+        let file_transfer = FileTransfer::new().await;
+        let dir = scratch_dir("flush");
+        let mut progress_rx = file_transfer.subscribe_progress();
+        let (file, data) = start_downloads(&file_transfer, &dir, 1, 1000).await.remove(0);
+        progress_rx.recv().await.unwrap();
+        
+        // Four chunks are under 1% of the file, so they stay batched...
+        for (index, chunk) in data.chunks(32).take(4).enumerate() {
+            file_transfer.receive_chunk(&file.hash, index, chunk.to_vec()).await.unwrap();
+        }
+        assert!(progress_rx.try_recv().is_err());
+        assert_eq!(file_transfer.get_transfer_progress().await[0].bytes_transferred, 0);
+        
+        // ...until pausing publishes them along with the new state
+        file_transfer.pause_transfer(&file.hash).await.unwrap();
+        let paused = progress_rx.try_recv().unwrap();
+        assert_eq!((paused.status, paused.bytes_transferred), (TransferStatus::Paused, 4 * 32));
+        
+        file_transfer.resume_transfer(&file.hash).await.unwrap();
+        for (index, chunk) in data.chunks(32).enumerate().skip(4) {
+            file_transfer.receive_chunk(&file.hash, index, chunk.to_vec()).await.unwrap();
+        }
+        let mut last = None;
+        while let Ok(progress) = progress_rx.try_recv() {
+            last = Some(progress);
+        }
+        let last = last.unwrap();
+        assert_eq!((last.status, last.bytes_transferred), (TransferStatus::Completed, data.len() as u64));
+        assert_eq!(last.percentage, 100.0);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_chunks_pauses_and_progress_reads_dont_deadlock() {
+        let file_transfer = Arc::new(FileTransfer::new().await);
+        let dir = scratch_dir("lock-order");
+        let downloads = start_downloads(&file_transfer, &dir, 4, 256).await;
+        
+        let mut tasks = Vec::new();
+        for (file, data) in downloads.iter().cloned() {
+            let receiver = file_transfer.clone();
+            let file_hash = file.hash.clone();
+            tasks.push(tokio::spawn(async move {
+                for (index, chunk) in data.chunks(32).enumerate() {
+                    receiver.receive_chunk(&file_hash, index, chunk.to_vec()).await.unwrap();
+                }
+            }));
+            let toggler = file_transfer.clone();
+            tasks.push(tokio::spawn(async move {
+                for _ in 0..200 {
+                    // Finishing mid-toggle makes the transition invalid, which is fine
+                    let _ = toggler.pause_transfer(&file.hash).await;
+                    let _ = toggler.resume_transfer(&file.hash).await;
+                }
+            }));
+        }
+        let reader = file_transfer.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..500 {
+                assert_eq!(reader.get_transfer_progress().await.len(), 4);
+                tokio::task::yield_now().await;
+            }
+        }));
+        
+        let all = async {
+            for task in tasks {
+                task.await.unwrap();
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(30), all).await.expect("lock ordering deadlocked");
+        
+        let progress = file_transfer.get_transfer_progress().await;
+        assert!(progress.iter().all(|p| p.bytes_transferred == p.total_bytes));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_compressible_files_travel_compressed() {
+        let dir = scratch_dir("codec");
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = b"2026-10-16 12:00:00 INFO transfer finished\n".repeat(CHUNK_SIZE / 16);
+        let mut noise = vec![0u8; CHUNK_SIZE];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut noise);
+        std::fs::write(dir.join("server.log"), &text).unwrap();
+        std::fs::write(dir.join("photo.jpg"), &noise).unwrap();
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let log_hash = sender.share_file(&dir.join("server.log"), "10.0.0.2".to_string()).await.unwrap();
+        let photo_hash = sender.share_file(&dir.join("photo.jpg"), "10.0.0.2".to_string()).await.unwrap();
+        let log = sender.shared_files.get(&log_hash).unwrap().clone();
+        assert_eq!(log.codec, ChunkCodec::Zstd);
+        assert_eq!(sender.shared_files.get(&photo_hash).unwrap().codec, ChunkCodec::Raw);
+        
+        // Hashes stay over the plain data; only the wire bytes shrink
+        assert_eq!(log_hash, describe(&text, CHUNK_SIZE).hash);
+        let (transport, _) = MemoryChunkTransport::new();
+        let mut served = Vec::new();
+        for chunk_hash in &log.chunks {
+            let chunk = sender.encode_chunk(sender.load_chunk(chunk_hash).await.unwrap().unwrap()).await.unwrap();
+            assert!(chunk.data.len() < CHUNK_SIZE / 10);
+            served.push(chunk.data);
+        }
+        transport.offer(&log_hash, served);
+        let receiver = FileTransfer::new().await.with_chunk_transport(Arc::new(transport));
+        receiver.shared_files.insert(log_hash.clone(), log);
+        receiver.peers_with_files.write().await.insert(log_hash.clone(), HashSet::from(["10.0.0.2".to_string()]));
+        let output = dir.join("downloads").join("server.log");
+        receiver.download_file(&log_hash, &output).await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), text);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    /// Serves chunks of one file after `delay`; stalled peers stop answering
+    /// once they've been asked `stalls_after` times
+    pub(super) struct SlowPeers {
+        pub(super) chunks: Vec<Vec<u8>>,
+        pub(super) delay: Duration,
+        pub(super) stalled: HashSet<String>,
+        pub(super) stalls_after: usize,
+        pub(super) asked: AtomicU64,
+    }
+    
+    #[async_trait::async_trait]
+    impl ChunkTransport for SlowPeers {
+        async fn fetch_chunk(&self, peer_id: &str, _file_hash: &str, index: usize) -> Result<Bytes, Error> {
+            if self.stalled.contains(peer_id) && self.asked.fetch_add(1, Ordering::Relaxed) as usize >= self.stalls_after {
+                std::future::pending::<()>().await;
+            }
+            tokio::time::sleep(self.delay).await;
+            Ok(Bytes::copy_from_slice(&self.chunks[index]))
+        }
+        
+        async fn send_chunk(&self, _peer_id: &str, _envelope: ChunkEnvelope) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+    
+    /// Download a 48-chunk file over 20 ms links from `peers`, returning how long it took
+    pub(super) async fn pipelined_download(name: &str, config: ChunkWindowConfig, peers: &[&str], stalled: &[&str]) -> (Duration, FileTransfer) {
+        let data: Vec<u8> = (0..48 * 32).map(|i| i as u8).collect();
+        let file = describe(&data, 32);
+        let transport = SlowPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            delay: Duration::from_millis(20),
+            stalled: stalled.iter().map(|peer| peer.to_string()).collect(),
+            stalls_after: 0,
+            asked: AtomicU64::new(0),
+        };
+        let file_transfer = FileTransfer::new()
+            .await
+            .with_chunk_transport(Arc::new(transport))
+            .with_chunk_windows(config);
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        file_transfer
+            .peers_with_files
+            .write()
+            .await
+            .insert(file.hash.clone(), peers.iter().map(|peer| peer.to_string()).collect());
+        
+        let output = scratch_dir(name).join("report.pdf");
+        let started = Instant::now();
+        file_transfer.download_file(&file.hash, &output).await.unwrap();
+        let elapsed = started.elapsed();
+        
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        assert_eq!(file_transfer.get_transfer_progress().await[0].status, TransferStatus::Completed);
+        let _ = std::fs::remove_dir_all(output.parent().unwrap());
+        (elapsed, file_transfer)
+    }
+    
+    pub(super) fn fixed_window(window: usize) -> ChunkWindowConfig {
+        ChunkWindowConfig { initial_window: window, min_window: 1, max_window: window, ..ChunkWindowConfig::default() }
+    }
+    
+    /// Serves every chunk after a short delay, noting who was asked for what
+    pub(super) struct RecordingPeers {
+        pub(super) chunks: Vec<Vec<u8>>,
+        pub(super) fetches: Mutex<Vec<(String, usize)>>,
+    }
+    
+    #[async_trait::async_trait]
+    impl ChunkTransport for RecordingPeers {
+        async fn fetch_chunk(&self, peer_id: &str, _file_hash: &str, index: usize) -> Result<Bytes, Error> {
+            self.fetches.lock().unwrap().push((peer_id.to_string(), index));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            Ok(Bytes::copy_from_slice(&self.chunks[index]))
+        }
+        
+        async fn send_chunk(&self, _peer_id: &str, _envelope: ChunkEnvelope) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+    
+    /// Never has `broken` to give, from anyone
+    pub(super) struct BrokenChunkPeers {
+        pub(super) chunks: Vec<Vec<u8>>,
+        pub(super) broken: usize,
+        pub(super) asked_for_broken: Mutex<Vec<(String, Instant)>>,
+    }
+    
+    #[async_trait::async_trait]
+    impl ChunkTransport for BrokenChunkPeers {
+        async fn fetch_chunk(&self, peer_id: &str, _file_hash: &str, index: usize) -> Result<Bytes, Error> {
+            if index == self.broken {
+                self.asked_for_broken.lock().unwrap().push((peer_id.to_string(), Instant::now()));
+                return Err(DeskShareError::PeerNotFound(peer_id.to_string()).into());
+            }
+            Ok(Bytes::copy_from_slice(&self.chunks[index]))
+        }
+        
+        async fn send_chunk(&self, _peer_id: &str, _envelope: ChunkEnvelope) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+    
+    fn observed(rx: &mut mpsc::UnboundedReceiver<TransferEvent>) -> Vec<TransferEvent> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+    
+    #[tokio::test]
+    async fn test_observers_see_a_transfer_start_to_end() {
+        let data: Vec<u8> = (0..8 * 32).map(|i| (i * 3) as u8).collect();
+        let file = describe(&data, 32);
+        let holders = HashSet::from(["10.0.0.2".to_string()]);
+        
+        let transport = Arc::new(RecordingPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            fetches: Mutex::new(Vec::new()),
+        });
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(transport);
+        let (observer, mut rx) = ChannelObserver::new();
+        file_transfer.add_observer(Box::new(observer));
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        file_transfer.peers_with_files.write().await.insert(file.hash.clone(), holders.clone());
+        file_transfer.download_file(&file.hash, &scratch_dir("observed").join("report.pdf")).await.unwrap();
+        
+        let events = observed(&mut rx);
+        assert!(matches!(&events[0], TransferEvent::Started(progress) if progress.file_hash == file.hash), "{:?}", events);
+        assert!(matches!(events.last(), Some(TransferEvent::Completed(progress)) if progress.status == TransferStatus::Completed));
+        assert_eq!(events.iter().filter(|event| matches!(event, TransferEvent::Started(_))).count(), 1);
+        assert_eq!(events.iter().filter(|event| matches!(event, TransferEvent::Completed(_))).count(), 1);
+        let middle = &events[1..events.len() - 1];
+        assert!(middle.iter().all(|event| matches!(event, TransferEvent::Progress(_))), "{:?}", events);
+        
+        // A chunk nobody has: each attempt is reported, then the failure and why
+        let transport = Arc::new(BrokenChunkPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            broken: 5,
+            asked_for_broken: Mutex::new(Vec::new()),
+        });
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(transport);
+        let (observer, mut rx) = ChannelObserver::new();
+        file_transfer.add_observer(Box::new(observer));
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        file_transfer.peers_with_files.write().await.insert(file.hash.clone(), holders);
+        file_transfer.download_file(&file.hash, &scratch_dir("observed-broken").join("report.pdf")).await.unwrap_err();
+        
+        let events = observed(&mut rx);
+        assert!(matches!(&events[0], TransferEvent::Started(_)), "{:?}", events);
+        let failed_chunks: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                TransferEvent::ChunkFailed { chunk_index, peer_id, .. } => Some((*chunk_index, peer_id.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(failed_chunks, vec![(5, "10.0.0.2"); MAX_CHUNK_ATTEMPTS as usize]);
+        match events.last() {
+            Some(TransferEvent::Failed { progress, reason }) => {
+                assert_eq!(progress.status, TransferStatus::Failed);
+                assert!(reason.as_deref().is_some_and(|reason| reason.contains("chunk 5")), "{:?}", reason);
+            }
+            other => panic!("ended with {:?}", other),
+        }
+        assert!(!events.iter().any(|event| matches!(event, TransferEvent::Completed(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_failed_transfers_say_why_until_dismissed() {
+        let data: Vec<u8> = (0..4 * 32).map(|i| (i * 11) as u8).collect();
+        let file = describe(&data, 32);
+        let transport = Arc::new(BrokenChunkPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            broken: 1,
+            asked_for_broken: Mutex::new(Vec::new()),
+        });
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(transport);
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        file_transfer.peers_with_files.write().await.insert(file.hash.clone(), HashSet::from(["10.0.0.2".to_string()]));
+        file_transfer.download_file(&file.hash, &scratch_dir("explained").join("report.pdf")).await.unwrap_err();
+        
+        // Retention only clears out cancelled transfers
+        file_transfer.set_ended_retention(Duration::ZERO);
+        let progress = file_transfer.get_transfer_progress().await;
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0].status, TransferStatus::Failed);
+        assert_eq!(progress[0].error_kind.as_deref(), Some("chunk_transfer_failed"));
+        let json = serde_json::to_value(&progress[0]).unwrap();
+        assert_eq!(json["error_message"], DeskShareError::ChunkTransferFailed(String::new()).user_message());
+        
+        let err = file_transfer.dismiss_transfer("unknown").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "transfer_not_found");
+        file_transfer.dismiss_transfer(&file.hash).await.unwrap();
+        assert!(file_transfer.get_transfer_progress().await.is_empty());
+        assert!(file_transfer.ended_transfers.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_cancel_drops_requests_chunks_and_shares() {
+        let data: Vec<u8> = (0..48 * 32).map(|i| (i * 3) as u8).collect();
+        let file = describe(&data, 32);
+        let transport = Arc::new(RecordingPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            fetches: Mutex::new(Vec::new()),
+        });
+        let file_transfer = Arc::new(
+            FileTransfer::new()
+                .await
+                .with_chunk_transport(transport.clone())
+                .with_chunk_windows(fixed_window(4)),
+        );
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        file_transfer.peers_with_files.write().await.insert(file.hash.clone(), HashSet::from(["10.0.0.2".to_string()]));
+        let dir = scratch_dir("cancel");
+        
+        let downloader = file_transfer.clone();
+        let (file_hash, output) = (file.hash.clone(), dir.join("report.pdf"));
+        let download = tokio::spawn(async move { downloader.download_file(&file_hash, &output).await });
+        let received = || async {
+            let downloading = file_transfer.downloading_files.read().await;
+            downloading.get(&file.hash).map_or(0, |downloading| downloading.chunks_received.len())
+        };
+        while received().await < 8 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(dir.join("report.pdf").exists());
+        assert_eq!(file_transfer.cancel_transfer(&file.hash).await.unwrap(), TransferStatus::Cancelled);
+        
+        // Requests in flight are dropped at once and don't count against the peer
+        tokio::time::timeout(Duration::from_secs(1), download).await.unwrap().unwrap().unwrap();
+        let windows = file_transfer.chunk_windows();
+        assert_eq!((windows[0].in_flight, windows[0].failed), (0, 0));
+        assert!(transport.fetches.lock().unwrap().len() < 48);
+        assert!(file_transfer.downloading_files.read().await.is_empty());
+        assert!(file_transfer.file_chunks.is_empty());
+        assert!(!dir.join("report.pdf").exists());
+        
+        // Cancelling a share stops offering it
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), b"shared for a moment").unwrap();
+        file_transfer.set_shareable_roots(vec![dir.clone()]);
+        let shared = file_transfer.share_file(&dir.join("notes.txt"), "local".to_string()).await.unwrap();
+        assert_eq!(file_transfer.cancel_transfer(&shared).await.unwrap(), TransferStatus::Cancelled);
+        assert!(file_transfer.list_shared_files().is_empty());
+        assert!(file_transfer.shared_chunks.is_empty());
+        
+        // Both stay listed as Cancelled for a while, then go
+        let progress = file_transfer.get_transfer_progress().await;
+        assert_eq!(progress.len(), 2);
+        assert!(progress.iter().all(|p| p.status == TransferStatus::Cancelled));
+        file_transfer.set_ended_retention(Duration::ZERO);
+        assert!(file_transfer.get_transfer_progress().await.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_peer_stats_outlast_transfers() {
+        let config = ChunkWindowConfig { request_timeout_ms: 50, ..fixed_window(4) };
+        let (_, file_transfer) = pipelined_download("peer-stats", config, &["10.0.0.3", "10.0.0.6"], &["10.0.0.6"]).await;
+        
+        // The download is over, its numbers stay
+        let stats = file_transfer.get_peer_stats();
+        let (healthy, stalled) = (&stats[0], &stats[1]);
+        assert_eq!((healthy.peer_id.as_str(), healthy.bytes_received, healthy.chunks_received, healthy.failed_requests), ("10.0.0.3", 48 * 32, 48, 0));
+        assert!(healthy.average_rtt_ms >= 20.0 && healthy.average_rtt_ms < 500.0, "{}", healthy.average_rtt_ms);
+        assert_eq!((stalled.peer_id.as_str(), stalled.bytes_received), ("10.0.0.6", 0));
+        assert!(stalled.failed_requests >= MAX_PEER_FAILURES as u64);
+        
+        // Chunks served count as sent
+        let dir = scratch_dir("peer-stats-share");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), b"served to whoever asks").unwrap();
+        let shared = file_transfer.share_file(&dir.join("notes.txt"), "local".to_string()).await.unwrap();
+        let request = FileTransferMessage::ChunkRequest { file_hash: shared, chunk_index: 0 }.encode().unwrap();
+        let reply = file_transfer.handle_incoming_message("10.0.0.9", request).await.unwrap().unwrap();
+        let FileTransferMessage::ChunkData { data, .. } = FileTransferMessage::decode(reply).unwrap() else {
+            panic!("expected chunk data");
+        };
+        let stats = file_transfer.get_peer_stats();
+        assert_eq!((stats[2].peer_id.as_str(), stats[2].bytes_sent), ("10.0.0.9", data.len() as u64));
+        
+        file_transfer.reset_peer_stats();
+        assert!(file_transfer.get_peer_stats().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::security::{resolve_remote_path, PeerIdentity, ProtocolClass, TrustLevel};
+
+use super::{FileTransfer, SharedFile, TransferStatus, MAX_CHUNK_SIZE};
+
+/// A file a peer wants to send us, waiting for the user to accept or reject it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingOffer {
+    pub offer_id: String,
+    pub from_peer: String,
+    /// Device id the sender authenticated as; None when the offer came in
+    /// some other way, and then it earns no trusted-device privileges
+    #[serde(default)]
+    pub from_device: Option<String>,
+    pub sender_name: String,
+    pub file: SharedFile,
+    pub received_at: u64,
+}
+
+/// Whether an offer was let through without asking, and why
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum AutoAcceptDecision {
+    /// `policy` describes the limits that allowed it
+    Accepted { policy: String },
+    /// Eligible, but over a limit; the user was asked instead
+    Prompted { reason: String },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransferHistoryEntry {
+    pub offer_id: String,
+    pub file_hash: String,
+    pub file_name: String,
+    pub size: u64,
+    pub from_peer: String,
+    pub decision: AutoAcceptDecision,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum OfferEvent {
+    Received(PendingOffer),
+    /// Accepted into the downloads directory without prompting
+    AutoAccepted(PendingOffer),
+    Expired { offer_id: String },
+}
+
+impl FileTransfer {
+    /// Register a file a peer is offering us; nothing is requested until the
+    /// offer is accepted, and unanswered offers expire after the offer timeout
+    pub async fn receive_offer(&self, from_peer: String, sender_name: String, file: SharedFile) -> String {
+        self.register_offer(from_peer, None, sender_name, file).await
+    }
+    
+    /// Register an offer from a peer whose handshake proved who it is;
+    /// only these can be auto-accepted as coming from a paired device
+    pub async fn receive_authenticated_offer(&self, from: PeerIdentity, sender_name: String, file: SharedFile) -> String {
+        self.register_offer(from.peer_id, Some(from.device_id), sender_name, file).await
+    }
+    
+    async fn register_offer(&self, from_peer: String, from_device: Option<String>, sender_name: String, file: SharedFile) -> String {
+        let offer_id = Self::generate_offer_id();
+        if self.refuses(&from_peer) || from_device.as_deref().is_some_and(|device_id| self.refuses(device_id)) {
+            tracing::info!("Rejected transfer offer {} from blocked {}", offer_id, from_peer);
+            return offer_id;
+        }
+        if !self.admits(&from_peer, ProtocolClass::Signaling) {
+            return offer_id;
+        }
+        
+        let offer = PendingOffer {
+            offer_id: offer_id.clone(),
+            from_peer,
+            from_device,
+            sender_name,
+            file,
+            received_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        };
+        
+        tracing::info!("Transfer offer {} for {} from {}", offer_id, offer.file.name, offer.sender_name);
+        self.pending_offers.insert(offer_id.clone(), offer.clone());
+        
+        match self.auto_accept_decision(&offer) {
+            Some(decision @ AutoAcceptDecision::Accepted { .. }) => {
+                self.record_decision(&offer, decision).await;
+                let downloads_dir = self.downloads_dir();
+                match self.accept_offer(&offer_id, &downloads_dir).await {
+                    Ok(_) => {
+                        let _ = self.offer_tx.send(OfferEvent::AutoAccepted(offer));
+                        return offer_id;
+                    }
+                    Err(e) => {
+                        // Fall back to asking
+                        tracing::warn!("Couldn't auto-accept offer {}: {}", offer_id, e);
+                        self.refund_quota(offer.file.size);
+                        self.pending_offers.insert(offer_id.clone(), offer.clone());
+                    }
+                }
+            }
+            Some(decision) => self.record_decision(&offer, decision).await,
+            None => {}
+        }
+        
+        let _ = self.offer_tx.send(OfferEvent::Received(offer));
+        
+        // Expire the offer if nobody answers in time
+        let pending_offers = self.pending_offers.clone();
+        let offer_tx = self.offer_tx.clone();
+        let timeout = Duration::from_millis(self.offer_timeout_ms.load(Ordering::Relaxed));
+        let expiring_id = offer_id.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            if pending_offers.remove(&expiring_id).is_some() {
+                tracing::info!("Transfer offer {} expired", expiring_id);
+                let _ = offer_tx.send(OfferEvent::Expired { offer_id: expiring_id });
+            }
+        });
+        
+        offer_id
+    }
+    
+    pub(super) fn refuses(&self, peer_or_device_id: &str) -> bool {
+        self.trust_store
+            .as_ref()
+            .is_some_and(|trust_store| trust_store.refuses("transfer", peer_or_device_id))
+    }
+    
+    pub(super) fn admits(&self, peer_id: &str, class: ProtocolClass) -> bool {
+        self.rate_limiter
+            .as_ref()
+            .map(|rate_limiter| rate_limiter.admit(peer_id, class))
+            .unwrap_or(true)
+    }
+    
+    /// None when the sender isn't eligible at all, otherwise whether this
+    /// offer fits the size limit and what's left of today's quota
+    fn auto_accept_decision(&self, offer: &PendingOffer) -> Option<AutoAcceptDecision> {
+        let config = self.auto_accept;
+        if !config.enabled {
+            return None;
+        }
+        
+        // Paired only counts for the key the sender authenticated with
+        let sender = offer.from_device.clone().map(|device_id| PeerIdentity { peer_id: offer.from_peer.clone(), device_id });
+        let level = match (&self.trust_store, &sender) {
+            (Some(trust_store), Some(sender)) => trust_store.level_for(sender),
+            _ => TrustLevel::Unknown,
+        };
+        let limit = match level {
+            TrustLevel::Blocked => return None,
+            TrustLevel::Unknown if config.trusted_only => return None,
+            TrustLevel::Unknown => config.max_size,
+            TrustLevel::Paired => {
+                let policy = self.trust_store.as_ref()?.policy_for(sender.as_ref()?);
+                config.max_size.min(policy.auto_accept_max_bytes?)
+            }
+        };
+        
+        let size = offer.file.size;
+        if size > limit {
+            return Some(AutoAcceptDecision::Prompted {
+                reason: format!("{} bytes is over the {} byte limit", size, limit),
+            });
+        }
+        
+        let today = Self::now_secs() / 86_400;
+        let mut usage = self.auto_accept_usage.lock().unwrap();
+        if usage.0 != today {
+            *usage = (today, 0);
+        }
+        if usage.1 + size > config.daily_quota {
+            return Some(AutoAcceptDecision::Prompted {
+                reason: format!("daily quota of {} bytes used up", config.daily_quota),
+            });
+        }
+        usage.1 += size;
+        
+        Some(AutoAcceptDecision::Accepted {
+            policy: format!(
+                "{:?} device, up to {} bytes per file and {} bytes per day",
+                level, limit, config.daily_quota
+            ),
+        })
+    }
+    
+    fn refund_quota(&self, size: u64) {
+        let mut usage = self.auto_accept_usage.lock().unwrap();
+        usage.1 = usage.1.saturating_sub(size);
+    }
+    
+    async fn record_decision(&self, offer: &PendingOffer, decision: AutoAcceptDecision) {
+        tracing::info!("Auto-accept decision for offer {} from {}: {:?}", offer.offer_id, offer.from_peer, decision);
+        self.history.write().await.push(TransferHistoryEntry {
+            offer_id: offer.offer_id.clone(),
+            file_hash: offer.file.hash.clone(),
+            file_name: offer.file.name.clone(),
+            size: offer.file.size,
+            from_peer: offer.from_peer.clone(),
+            decision,
+            timestamp: Self::now_secs(),
+        });
+    }
+    
+    /// Auto-accept decisions, oldest first
+    pub async fn transfer_history(&self) -> Vec<TransferHistoryEntry> {
+        self.history.read().await.clone()
+    }
+    
+    /// Accept a pending offer and start downloading it into `output_dir`;
+    /// the chunks keep arriving after this returns
+    pub async fn accept_offer(&self, offer_id: &str, output_dir: &Path) -> Result<String, Error> {
+        let (_, offer) = self
+            .pending_offers
+            .remove(offer_id)
+            .ok_or_else(|| DeskShareError::OfferNotFound(offer_id.to_string()))?;
+        
+        let file_hash = offer.file.hash.clone();
+        if offer.file.chunk_size > MAX_CHUNK_SIZE as u64 {
+            return Err(DeskShareError::FileTransferFailed(format!("chunks of {} bytes are too large", offer.file.chunk_size)).into());
+        }
+        if !offer.file.encrypted {
+            self.security_config.allow_plaintext(&format!("transfer of {}", offer.file.name))?;
+        }
+        offer.file.check_manifest()?;
+        // The name came from the sender; a bad one fails the transfer
+        let output_path = resolve_remote_path(output_dir, &offer.file.name)?;
+        
+        self.shared_files.entry(file_hash.clone()).or_insert(offer.file);
+        self.peers_with_files
+            .write()
+            .await
+            .entry(file_hash.clone())
+            .or_insert_with(HashSet::new)
+            .insert(offer.from_peer);
+        
+        let Some(status) = self.start_download(&file_hash, &output_path, self.collision_policy).await? else {
+            return Ok(file_hash);
+        };
+        
+        // The chunks are fetched in the background, so whoever accepted
+        // isn't held up for as long as the transfer takes
+        let downloader = self.clone();
+        let fetching = file_hash.clone();
+        tokio::spawn(async move {
+            if let Err(e) = downloader.fetch_offered(&fetching, status).await {
+                tracing::warn!("Download of {} failed: {}", fetching, e);
+            }
+        });
+        
+        Ok(file_hash)
+    }
+    
+    async fn fetch_offered(&self, file_hash: &str, status: TransferStatus) -> Result<(), Error> {
+        self.fetch_download(file_hash, status).await?;
+        
+        // A folder's manifest is in by now, and its files are registered
+        let files: Vec<String> = self
+            .folders
+            .get(file_hash)
+            .map(|folder| folder.files.keys().cloned().collect())
+            .unwrap_or_default();
+        for file in files {
+            self.request_chunks(&file).await?;
+        }
+        Ok(())
+    }
+    
+    /// Decline a pending offer and let the sender know why
+    pub async fn reject_offer(&self, offer_id: &str, reason: &str) -> Result<(), Error> {
+        let (_, offer) = self
+            .pending_offers
+            .remove(offer_id)
+            .ok_or_else(|| DeskShareError::OfferNotFound(offer_id.to_string()))?;
+        
+        tracing::info!("Rejected transfer offer {} for {}: {}", offer_id, offer.file.name, reason);
+        // The offer is gone either way; a sender we can't reach just times out
+        if let Some(transport) = &self.chunk_transport {
+            if let Err(e) = transport.decline_offer(&offer.from_peer, &offer.file.hash, reason).await {
+                tracing::warn!("Couldn't tell {} offer {} was declined: {}", offer.from_peer, offer_id, e);
+            }
+        }
+        Ok(())
+    }
+    
+    pub fn get_pending_offers(&self) -> Vec<PendingOffer> {
+        self.pending_offers.iter().map(|entry| entry.value().clone()).collect()
+    }
+    
+    pub fn subscribe_offers(&self) -> broadcast::Receiver<OfferEvent> {
+        self.offer_tx.subscribe()
+    }
+    
+    pub fn set_offer_timeout(&self, timeout: Duration) {
+        self.offer_timeout_ms.store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+    
+    fn generate_offer_id() -> String {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        format!("{:x}", rng.gen::<u128>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicU64;
+    use crate::config::AutoAcceptConfig;
+    use crate::network::chunk_pipeline::MemoryChunkTransport;
+    use crate::network::file_transfer::tests::{describe, scratch_dir, SlowPeers};
+    use crate::security::{DeviceIdentity, TrustStore};
+    
+    #[tokio::test]
+    async fn test_chunks_before_acceptance_never_touch_disk() {
+        let file_transfer = FileTransfer::new().await;
+        let dir = scratch_dir("consent");
+        file_transfer.set_downloads_dir(dir.clone()).unwrap();
+        
+        let data = b"nothing lands on disk before a yes";
+        let file = describe(data, 8);
+        let offer_id = file_transfer
+            .receive_offer("10.0.0.9".to_string(), "Stranger".to_string(), file.clone())
+            .await;
+        
+        // Unknown sender: prompted, and pushing chunks anyway gets nowhere
+        for (index, chunk) in data.chunks(8).enumerate() {
+            assert!(file_transfer.receive_chunk(&file.hash, index, chunk.to_vec()).await.is_err());
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        assert!(file_transfer.transfer_history().await.is_empty());
+        
+        file_transfer.accept_offer(&offer_id, &dir).await.unwrap();
+        for (index, chunk) in data.chunks(8).enumerate() {
+            file_transfer.receive_chunk(&file.hash, index, chunk.to_vec()).await.unwrap();
+        }
+        assert_eq!(std::fs::read(dir.join("report.pdf")).unwrap(), data);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_rejected_offer_tells_the_sender() {
+        let transport = Arc::new(MemoryChunkTransport::new().0);
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(transport.clone());
+        let file = describe(b"not today", 4);
+        let offer_id = file_transfer
+            .receive_offer("10.0.0.9".to_string(), "Stranger".to_string(), file.clone())
+            .await;
+        
+        file_transfer.reject_offer(&offer_id, "no room").await.unwrap();
+        
+        assert_eq!(transport.declined(), vec![("10.0.0.9".to_string(), file.hash.clone(), "no room".to_string())]);
+        assert!(file_transfer.get_pending_offers().is_empty());
+        assert!(file_transfer.reject_offer(&offer_id, "no room").await.is_err());
+        assert!(file_transfer.get_transfer_progress().await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_hostile_offer_name_fails_the_transfer() {
+        let file_transfer = FileTransfer::new().await;
+        let dir = scratch_dir("hostile");
+        
+        let mut file = describe(b"payload", 8);
+        file.name = "invoice.pdf\u{202E}exe.".to_string();
+        let offer_id = file_transfer
+            .receive_offer("10.0.0.9".to_string(), "Stranger".to_string(), file.clone())
+            .await;
+        let err = file_transfer.accept_offer(&offer_id, &dir).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "unsafe_remote_path");
+        assert!(file_transfer.get_pending_offers().is_empty());
+        
+        // Traversal is stripped rather than followed
+        file.name = "../../outside.txt".to_string();
+        let offer_id = file_transfer
+            .receive_offer("10.0.0.9".to_string(), "Stranger".to_string(), file.clone())
+            .await;
+        file_transfer.accept_offer(&offer_id, &dir).await.unwrap();
+        file_transfer.receive_chunk(&file.hash, 0, b"payload".to_vec()).await.unwrap();
+        assert_eq!(std::fs::read(dir.join("outside.txt")).unwrap(), b"payload");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_auto_accept_size_limit_and_daily_quota() {
+        let trust_store = Arc::new(TrustStore::new());
+        let bob = DeviceIdentity::generate();
+        trust_store.record_paired(&bob.device_id(), "10.0.0.3", &bob.public_key());
+        let file_transfer = FileTransfer::new()
+            .await
+            .with_trust_store(trust_store)
+            .with_auto_accept(AutoAcceptConfig { max_size: 100, daily_quota: 150, ..AutoAcceptConfig::default() });
+        let dir = scratch_dir("quota");
+        file_transfer.set_downloads_dir(dir.clone()).unwrap();
+        
+        let offer = |seed: &str, size: usize| describe(seed.repeat(size).as_bytes(), 64);
+        let as_bob = || PeerIdentity { peer_id: "10.0.0.3".to_string(), device_id: bob.device_id() };
+        let small = file_transfer.receive_authenticated_offer(as_bob(), "Bob".to_string(), offer("a", 80)).await;
+        let large = file_transfer.receive_authenticated_offer(as_bob(), "Bob".to_string(), offer("b", 200)).await;
+        let over_quota = file_transfer.receive_authenticated_offer(as_bob(), "Bob".to_string(), offer("c", 80)).await;
+        let stranger = file_transfer.receive_offer("10.0.0.9".to_string(), "Eve".to_string(), offer("d", 10)).await;
+        // Bob's address is no credential: not unauthenticated, nor with another key
+        let claimed = file_transfer.receive_offer("10.0.0.3".to_string(), "Bob".to_string(), offer("e", 10)).await;
+        let mallory = PeerIdentity { peer_id: "10.0.0.3".to_string(), device_id: DeviceIdentity::generate().device_id() };
+        let spoofed = file_transfer.receive_authenticated_offer(mallory, "Bob".to_string(), offer("f", 10)).await;
+        
+        let mut pending: Vec<String> = file_transfer.get_pending_offers().into_iter().map(|offer| offer.offer_id).collect();
+        pending.sort();
+        let mut expected = vec![large.clone(), over_quota.clone(), stranger, claimed, spoofed];
+        expected.sort();
+        assert_eq!(pending, expected);
+        
+        // Strangers aren't eligible, so only Bob's offers are logged
+        let history = file_transfer.transfer_history().await;
+        let decisions: Vec<(&str, &AutoAcceptDecision)> =
+            history.iter().map(|entry| (entry.offer_id.as_str(), &entry.decision)).collect();
+        assert_eq!(decisions.len(), 3);
+        assert_eq!(decisions[0].0, small);
+        assert!(matches!(decisions[0].1, AutoAcceptDecision::Accepted { policy } if policy.contains("Paired")));
+        assert_eq!(decisions[1].0, large);
+        assert!(matches!(decisions[1].1, AutoAcceptDecision::Prompted { reason } if reason.contains("limit")));
+        assert_eq!(decisions[2].0, over_quota);
+        assert!(matches!(decisions[2].1, AutoAcceptDecision::Prompted { reason } if reason.contains("quota")));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_accepting_returns_while_the_download_runs() {
+        let data = b"a peer that never answers";
+        let file = describe(data, 8);
+        let transport = SlowPeers {
+            chunks: data.chunks(8).map(<[u8]>::to_vec).collect(),
+            delay: Duration::ZERO,
+            stalled: HashSet::from(["10.0.0.3".to_string()]),
+            stalls_after: 0,
+            asked: AtomicU64::new(0),
+        };
+        let receiver = FileTransfer::new().await.with_chunk_transport(Arc::new(transport));
+        let dir = scratch_dir("accept-returns");
+        let offer_id = receiver.receive_offer("10.0.0.3".to_string(), "Bob".to_string(), file.clone()).await;
+        
+        // The only peer never answers, so waiting on the download would hang
+        let accepted = tokio::time::timeout(Duration::from_secs(5), receiver.accept_offer(&offer_id, &dir)).await;
+        assert_eq!(accepted.unwrap().unwrap(), file.hash);
+        assert_eq!(receiver.get_transfer_progress().await[0].status, TransferStatus::InProgress);
+        
+        receiver.cancel_transfer(&file.hash).await.unwrap();
+        assert_eq!(receiver.wait_for_transfer(&file.hash).await, Some(TransferStatus::Cancelled));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
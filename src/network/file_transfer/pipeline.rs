@@ -0,0 +1,503 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use anyhow::Error;
+use rand::seq::SliceRandom;
+
+use crate::error::{backoff_delay, DeskShareError};
+use crate::network::chunk_pipeline::{ChunkTransport, PeerWindowState};
+
+use super::{ChunkOrder, FileTransfer, TransferStatus, DISK_SPACE_CHECK_INTERVAL};
+
+/// Requests for one chunk, across all peers, before the download fails
+pub(super) const MAX_CHUNK_ATTEMPTS: u32 = 5;
+/// Failures in a row before a peer is left out of the rest of a download
+pub(super) const MAX_PEER_FAILURES: u32 = 3;
+/// Wait before a failed chunk's first retry; it doubles with each failure
+const CHUNK_RETRY_BACKOFF_MS: u64 = 50;
+/// How often a download waiting on request windows other downloads hold
+/// looks for a free one
+const WINDOW_WAIT: Duration = Duration::from_millis(10);
+
+impl FileTransfer {
+    pub(super) async fn request_chunks(&self, file_hash: &str) -> Result<(), Error> {
+        // Paused and cancelled transfers don't issue new requests
+        if !self.is_in_progress(file_hash) {
+            return Ok(());
+        }
+        
+        // Copy out what's needed so no map stays locked across the requests
+        let Some(chunks) = self.shared_files.get(file_hash).map(|file| file.chunks.clone()) else {
+            return Ok(());
+        };
+        let mut peers: Vec<String> = self
+            .peers_with_files
+            .read()
+            .await
+            .get(file_hash)
+            .map(|peers| peers.iter().cloned().collect())
+            .unwrap_or_default();
+        peers.sort();
+        
+        if let Some(transport) = self.chunk_transport.clone() {
+            return self.pipeline_chunks(transport, file_hash, peers).await;
+        }
+        if let Some(peer_id) = peers.first() {
+            for chunk_index in self.missing_chunks(file_hash).await {
+                self.request_chunk_from_peer(peer_id, file_hash, &chunks[chunk_index], chunk_index).await?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Keep every peer's window of chunk requests full, issuing the next
+    /// request as soon as one completes. A request that fails or times out
+    /// shrinks that peer's window and goes back in the queue for another
+    /// peer to pick up; a peer that keeps failing is dropped.
+    async fn pipeline_chunks(&self, transport: Arc<dyn ChunkTransport>, file_hash: &str, mut peers: Vec<String>) -> Result<(), Error> {
+        let timeout = self.chunk_windows.config().request_timeout();
+        let alive = self.downloading_files.read().await.get(file_hash).map(|downloading| downloading.alive.subscribe());
+        let Some(mut alive) = alive else {
+            return Ok(());
+        };
+        let holdings = self.chunk_holders.get(file_hash).map(|holders| holders.clone()).unwrap_or_default();
+        let holds = |peer_id: &str, index: usize| holdings.get(peer_id).is_none_or(|held| held.contains(&index));
+        let mut queue = self.order_chunks(self.missing_chunks(file_hash).await, &peers, holds);
+        // Failed chunks wait out their backoff here before going back in the queue
+        let mut retries: Vec<(Instant, usize)> = Vec::new();
+        let mut failed_on: HashMap<usize, HashSet<String>> = HashMap::new();
+        let mut peer_failures: HashMap<String, u32> = HashMap::new();
+        let mut requests = JoinSet::new();
+        let mut space_checked = Instant::now();
+        
+        loop {
+            let now = Instant::now();
+            retries.retain(|&(due, index)| {
+                if due <= now {
+                    queue.push_front(index);
+                }
+                due > now
+            });
+            
+            // Paused and cancelled downloads stop issuing, in-flight requests still land
+            if self.is_in_progress(file_hash) {
+                for peer_id in &peers {
+                    loop {
+                        // Chunks this peer already failed are left for the others
+                        let next = queue.iter().position(|&index| {
+                            holds(peer_id, index) && !failed_on.get(&index).is_some_and(|peers| peers.contains(peer_id))
+                        });
+                        let Some(position) = next else {
+                            break;
+                        };
+                        let Some(slot) = self.chunk_windows.try_acquire(peer_id) else {
+                            break;
+                        };
+                        let Some(index) = queue.remove(position) else {
+                            break;
+                        };
+                        let (transport, file_hash) = (transport.clone(), file_hash.to_string());
+                        let throttle = self.download_throttle.clone();
+                        requests.spawn(async move {
+                            let asked = Instant::now();
+                            let fetch = transport.fetch_chunk(slot.peer_id(), &file_hash, index);
+                            let result = tokio::time::timeout(timeout, fetch).await;
+                            let rtt = asked.elapsed();
+                            // Holding the slot while throttled paces the requests that follow
+                            if let Ok(Ok(data)) = &result {
+                                throttle.acquire(data.len() as u64).await;
+                            }
+                            (slot, index, result, rtt)
+                        });
+                    }
+                }
+            }
+            
+            if requests.is_empty() && retries.is_empty() {
+                if queue.is_empty() || !self.is_in_progress(file_hash) {
+                    break;
+                }
+                // Chunks are left over; either another download has the
+                // windows they need, or no peer that's left can send them
+                let servable = queue.iter().any(|&index| {
+                    peers.iter().any(|peer| holds(peer, index) && !failed_on.get(&index).is_some_and(|tried| tried.contains(peer)))
+                });
+                if !servable {
+                    let name = self.shared_files.get(file_hash).map(|file| file.name.clone()).unwrap_or_else(|| file_hash.to_string());
+                    let reason = if peers.is_empty() {
+                        format!("no peers have {}", name)
+                    } else {
+                        format!("no peer left can send the last {} chunks of {}", queue.len(), name)
+                    };
+                    return self.fail_download(file_hash, DeskShareError::ChunkTransferFailed(reason)).await;
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(WINDOW_WAIT) => continue,
+                    _ = alive.changed() => return Ok(()),
+                }
+            }
+            let next_retry = retries.iter().map(|&(due, _)| due).min().unwrap_or(now);
+            let joined = tokio::select! {
+                Some(joined) = requests.join_next() => joined,
+                _ = tokio::time::sleep_until(next_retry.into()), if !retries.is_empty() => continue,
+                // Cancelled: dropping the set abandons the requests in flight
+                // without holding them against the peers
+                _ = alive.changed() => return Ok(()),
+            };
+            let (slot, index, result, rtt) = joined?;
+            let peer_id = slot.peer_id().to_string();
+            let fetched = match &result {
+                Ok(Ok(data)) => data.len() as u64,
+                _ => 0,
+            };
+            let outcome = match result {
+                Ok(Ok(data)) => match self.receive_chunk(file_hash, index, data).await {
+                    Ok(()) if self.has_chunk(file_hash, index).await => Ok(()),
+                    Ok(()) => Err(DeskShareError::ChunkTransferFailed(format!("chunk {} didn't verify", index)).into()),
+                    Err(e) => Err(e),
+                },
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(DeskShareError::Timeout.into()),
+            };
+            slot.finish(outcome.is_ok());
+            self.record_peer(&peer_id, |stats| match outcome {
+                Ok(()) => stats.record_received(fetched, Some(rtt)),
+                Err(_) => stats.record_failure(),
+            });
+            
+            let Err(e) = outcome else {
+                peer_failures.remove(&peer_id);
+                // Something else may have filled the disk since the download started
+                if space_checked.elapsed() >= DISK_SPACE_CHECK_INTERVAL {
+                    space_checked = Instant::now();
+                    if let Err(e) = self.check_download_space(file_hash).await {
+                        return self.fail_download(file_hash, e).await;
+                    }
+                }
+                continue;
+            };
+            tracing::debug!("Chunk {} of {} from {} failed: {}", index, file_hash, peer_id, e);
+            let reason = e.to_string();
+            for observer in self.observers() {
+                observer.on_chunk_failed(file_hash, index, &peer_id, &reason);
+            }
+            
+            let tries = self.downloading_files.write().await.get_mut(file_hash).map(|downloading| {
+                let tries = downloading.chunk_attempts.entry(index).or_default();
+                *tries += 1;
+                *tries
+            });
+            let Some(tries) = tries else {
+                return Ok(());
+            };
+            if tries >= MAX_CHUNK_ATTEMPTS {
+                let reason = format!("chunk {} failed {} times", index, tries);
+                return self.fail_download(file_hash, DeskShareError::ChunkTransferFailed(reason)).await;
+            }
+            let failures = peer_failures.entry(peer_id.clone()).or_default();
+            *failures += 1;
+            if *failures >= MAX_PEER_FAILURES && peers.len() > 1 {
+                tracing::warn!("Leaving {} out of {} after {} failures", peer_id, file_hash, failures);
+                peers.retain(|peer| peer != &peer_id);
+            }
+            
+            // Once every remaining peer with the chunk has failed it, they all get another go
+            let tried = failed_on.entry(index).or_default();
+            tried.insert(peer_id);
+            if peers.iter().filter(|peer| holds(peer, index)).all(|peer| tried.contains(peer)) {
+                tried.clear();
+            }
+            retries.push((Instant::now() + backoff_delay(CHUNK_RETRY_BACKOFF_MS, tries), index));
+        }
+        
+        Ok(())
+    }
+    
+    /// Failed requests per chunk of a download, for diagnostics
+    pub async fn chunk_attempts(&self, file_hash: &str) -> HashMap<usize, u32> {
+        self.downloading_files
+            .read()
+            .await
+            .get(file_hash)
+            .map(|downloading| downloading.chunk_attempts.clone())
+            .unwrap_or_default()
+    }
+    
+    /// Put missing chunks in the order they'll be asked for
+    fn order_chunks(&self, mut missing: Vec<usize>, peers: &[String], holds: impl Fn(&str, usize) -> bool) -> VecDeque<usize> {
+        if self.chunk_order == ChunkOrder::RarestFirst {
+            missing.shuffle(&mut rand::thread_rng());
+            missing.sort_by_cached_key(|&index| peers.iter().filter(|peer| holds(peer, index)).count());
+        }
+        missing.into()
+    }
+    
+    /// Chunk indices of a download that haven't arrived yet
+    async fn missing_chunks(&self, file_hash: &str) -> Vec<usize> {
+        self.downloading_files
+            .read()
+            .await
+            .get(file_hash)
+            .map(|downloading| {
+                (0..downloading.chunks_expected)
+                    .filter(|index| !downloading.chunks_received.contains(index))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    async fn has_chunk(&self, file_hash: &str, index: usize) -> bool {
+        self.downloading_files
+            .read()
+            .await
+            .get(file_hash)
+            .is_some_and(|downloading| downloading.chunks_received.contains(&index))
+    }
+    
+    fn is_in_progress(&self, file_hash: &str) -> bool {
+        matches!(
+            self.active_transfers.get(file_hash).map(|p| p.status),
+            Some(TransferStatus::InProgress)
+        )
+    }
+    
+    /// Request windows per peer, for transfer diagnostics
+    pub fn chunk_windows(&self) -> Vec<PeerWindowState> {
+        self.chunk_windows.states()
+    }
+    
+    async fn request_chunk_from_peer(&self, _peer_id: &str, file_hash: &str, chunk_hash: &str, chunk_index: usize) -> Result<(), Error> {
+        // This would use our P2P transport
+        // For now, we'll simulate receiving the chunk
+        if let Some(chunk) = self.load_chunk(chunk_hash).await? {
+            let chunk = self.encode_chunk(chunk).await?;
+            self.download_throttle.acquire(chunk.data.len() as u64).await;
+            self.handle_chunk_received(file_hash, chunk_hash, chunk_index, chunk.data).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicU64;
+    use crate::network::chunk_pipeline::ChunkWindowConfig;
+    use crate::network::file_transfer::SignedAnnouncement;
+    use crate::network::file_transfer::tests::{
+        describe, fixed_window, pipelined_download, scratch_dir, BrokenChunkPeers, RecordingPeers, SlowPeers,
+    };
+    use crate::security::DeviceIdentity;
+    
+    #[tokio::test]
+    async fn test_rare_chunks_are_fetched_first() {
+        let data: Vec<u8> = (0..16 * 32).map(|i| (i * 5) as u8).collect();
+        let file = describe(&data, 32);
+        let download = |order: ChunkOrder, name: &'static str| {
+            let (file, data) = (file.clone(), data.clone());
+            async move {
+                let transport = Arc::new(RecordingPeers {
+                    chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+                    fetches: Mutex::new(Vec::new()),
+                });
+                let file_transfer = FileTransfer::new()
+                    .await
+                    .with_chunk_transport(transport.clone())
+                    .with_chunk_windows(fixed_window(1))
+                    .with_chunk_order(order);
+                // Only the first has the last four chunks
+                let whole = SignedAnnouncement::sign(file.clone(), &DeviceIdentity::generate()).unwrap();
+                file_transfer.handle_announcement("10.0.0.3".to_string(), whole).await.unwrap();
+                for peer in ["10.0.0.4", "10.0.0.5"] {
+                    let partial = SignedAnnouncement::sign_partial(file.clone(), Some((0..12).collect()), &DeviceIdentity::generate()).unwrap();
+                    file_transfer.handle_announcement(peer.to_string(), partial).await.unwrap();
+                }
+                
+                let dir = scratch_dir(name);
+                file_transfer.download_file(&file.hash, &dir.join("report.pdf")).await.unwrap();
+                assert_eq!(std::fs::read(dir.join("report.pdf")).unwrap(), data);
+                let _ = std::fs::remove_dir_all(dir);
+                let fetches = transport.fetches.lock().unwrap().clone();
+                fetches
+            }
+        };
+        
+        let fetches = download(ChunkOrder::RarestFirst, "rarest-first").await;
+        let from = |fetches: &[(String, usize)], peer: &str| -> Vec<usize> {
+            fetches.iter().filter(|(asked, _)| asked == peer).map(|&(_, index)| index).collect()
+        };
+        let mut first = from(&fetches, "10.0.0.3")[..4].to_vec();
+        first.sort();
+        assert_eq!(first, [12, 13, 14, 15]);
+        for peer in ["10.0.0.4", "10.0.0.5"] {
+            assert!(from(&fetches, peer).iter().all(|&index| index < 12), "{} asked for a chunk it lacks", peer);
+        }
+        
+        // Sequential downloads keep to file order
+        let fetches = download(ChunkOrder::Sequential, "sequential").await;
+        assert_eq!(from(&fetches, "10.0.0.3")[0], 0);
+        
+        // What a peer claims to hold is signed along with the file
+        let mut forged = SignedAnnouncement::sign_partial(file.clone(), Some(vec![0]), &DeviceIdentity::generate()).unwrap();
+        forged.held_chunks = None;
+        let file_transfer = FileTransfer::new().await;
+        assert!(file_transfer.handle_announcement("10.0.0.6".to_string(), forged).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_failed_chunks_back_off_across_peers_then_fail_the_transfer() {
+        let data: Vec<u8> = (0..8 * 32).map(|i| (i * 5) as u8).collect();
+        let file = describe(&data, 32);
+        let transport = Arc::new(BrokenChunkPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            broken: 2,
+            asked_for_broken: Mutex::new(Vec::new()),
+        });
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(transport.clone());
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        let holders = HashSet::from(["10.0.0.2".to_string(), "10.0.0.3".to_string()]);
+        file_transfer.peers_with_files.write().await.insert(file.hash.clone(), holders);
+        
+        let err = file_transfer.download_file(&file.hash, &scratch_dir("broken").join("report.pdf")).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "chunk_transfer_failed");
+        assert_eq!(file_transfer.get_transfer_progress().await[0].status, TransferStatus::Failed);
+        assert_eq!(file_transfer.chunk_attempts(&file.hash).await, HashMap::from([(2, MAX_CHUNK_ATTEMPTS)]));
+        
+        // Both peers were tried, each retry waiting twice as long as the last
+        let asked = transport.asked_for_broken.lock().unwrap().clone();
+        assert_eq!(asked.len(), MAX_CHUNK_ATTEMPTS as usize);
+        assert_eq!(asked.iter().map(|(peer, _)| peer.as_str()).collect::<HashSet<_>>().len(), 2);
+        for (attempt, pair) in asked.windows(2).enumerate() {
+            let waited = pair[1].1 - pair[0].1;
+            assert!(waited >= backoff_delay(CHUNK_RETRY_BACKOFF_MS, attempt as u32 + 1), "retry {} after {:?}", attempt + 1, waited);
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_downloads_nobody_can_finish_fail() {
+        let data: Vec<u8> = (0..8 * 32).map(|i| (i * 11) as u8).collect();
+        let file = describe(&data, 32);
+        let transport = Arc::new(RecordingPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            fetches: Mutex::new(Vec::new()),
+        });
+        
+        // Nobody to ask at all
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(transport.clone());
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        let err = file_transfer.download_file(&file.hash, &scratch_dir("no-peers").join("report.pdf")).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "chunk_transfer_failed");
+        let progress = &file_transfer.get_transfer_progress().await[0];
+        assert_eq!(progress.status, TransferStatus::Failed);
+        assert!(progress.error_message.is_some());
+        assert!(transport.fetches.lock().unwrap().is_empty());
+        
+        // The only peer runs out halfway
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(transport.clone());
+        let partial = SignedAnnouncement::sign_partial(file.clone(), Some((0..4).collect()), &DeviceIdentity::generate()).unwrap();
+        file_transfer.handle_announcement("10.0.0.4".to_string(), partial).await.unwrap();
+        let err = file_transfer.download_file(&file.hash, &scratch_dir("peers-run-out").join("report.pdf")).await.unwrap_err();
+        assert!(err.to_string().contains("last 4 chunks"), "{}", err);
+        assert_eq!(file_transfer.get_transfer_progress().await[0].status, TransferStatus::Failed);
+        assert_eq!(transport.fetches.lock().unwrap().len(), 4);
+    }
+    
+    #[tokio::test]
+    async fn test_window_pipelines_requests_over_latency() {
+        let (sequential, _) = pipelined_download("window-1", fixed_window(1), &["10.0.0.3"], &[]).await;
+        let (windowed, file_transfer) = pipelined_download("window-8", fixed_window(8), &["10.0.0.3"], &[]).await;
+        
+        // 48 round trips one at a time, six with eight in flight
+        assert!(sequential > windowed * 4, "sequential {:?}, windowed {:?}", sequential, windowed);
+        let windows = file_transfer.chunk_windows();
+        assert_eq!((windows[0].window, windows[0].in_flight, windows[0].completed), (8, 0, 48));
+        
+        // Starting from one request, the window opens up on its own
+        let adaptive = ChunkWindowConfig { initial_window: 1, max_window: 8, ..ChunkWindowConfig::default() };
+        let (grown, file_transfer) = pipelined_download("window-adaptive", adaptive, &["10.0.0.3"], &[]).await;
+        assert!(sequential > grown * 3, "sequential {:?}, adaptive {:?}", sequential, grown);
+        assert_eq!(file_transfer.chunk_windows()[0].window, 8);
+    }
+    
+    #[tokio::test]
+    async fn test_timed_out_requests_fail_over_to_another_peer() {
+        let config = ChunkWindowConfig { request_timeout_ms: 50, ..fixed_window(4) };
+        let (_, file_transfer) = pipelined_download("failover", config, &["10.0.0.3", "10.0.0.6"], &["10.0.0.6"]).await;
+        
+        let windows = file_transfer.chunk_windows();
+        let (healthy, stalled) = (&windows[0], &windows[1]);
+        assert_eq!((healthy.peer_id.as_str(), healthy.completed, healthy.failed), ("10.0.0.3", 48, 0));
+        assert_eq!((stalled.peer_id.as_str(), stalled.completed, stalled.window, stalled.in_flight), ("10.0.0.6", 0, 1, 0));
+        assert!(stalled.failed >= MAX_PEER_FAILURES as u64);
+    }
+    
+    #[tokio::test]
+    async fn test_download_completes_when_a_peer_stops_answering_halfway() {
+        let data: Vec<u8> = (0..48 * 32).map(|i| (i * 3) as u8).collect();
+        let file = describe(&data, 32);
+        let transport = SlowPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            delay: Duration::from_millis(5),
+            stalled: HashSet::from(["10.0.0.6".to_string()]),
+            stalls_after: 12,
+            asked: AtomicU64::new(0),
+        };
+        let config = ChunkWindowConfig { request_timeout_ms: 100, ..fixed_window(8) };
+        let file_transfer = FileTransfer::new()
+            .await
+            .with_chunk_transport(Arc::new(transport))
+            .with_chunk_windows(config);
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        file_transfer
+            .peers_with_files
+            .write()
+            .await
+            .insert(file.hash.clone(), HashSet::from(["10.0.0.3".to_string(), "10.0.0.6".to_string()]));
+        
+        let output = scratch_dir("halfway").join("report.pdf");
+        file_transfer.download_file(&file.hash, &output).await.unwrap();
+        
+        // Chunks landed out of order across both peers, yet the file and its progress add up
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        let progress = &file_transfer.get_transfer_progress().await[0];
+        assert_eq!(progress.status, TransferStatus::Completed);
+        assert_eq!(progress.bytes_transferred, data.len() as u64);
+        let windows = file_transfer.chunk_windows();
+        assert_eq!(windows[1].completed, 12);
+        assert!(windows[1].failed > 0);
+        assert_eq!(windows[0].completed, 36);
+        let _ = std::fs::remove_dir_all(output.parent().unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_download_limit_paces_the_transfer() {
+        let data: Vec<u8> = (0..48 * 1024).map(|i| (i % 253) as u8).collect();
+        let file = describe(&data, 1024);
+        let transport = Arc::new(RecordingPeers {
+            chunks: data.chunks(1024).map(<[u8]>::to_vec).collect(),
+            fetches: Mutex::new(Vec::new()),
+        });
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(transport);
+        file_transfer.set_download_limit(64 * 1024);
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        file_transfer
+            .peers_with_files
+            .write()
+            .await
+            .insert(file.hash.clone(), HashSet::from(["10.0.0.3".to_string(), "10.0.0.6".to_string()]));
+        
+        let output = scratch_dir("throttled").join("report.pdf");
+        let started = Instant::now();
+        file_transfer.download_file(&file.hash, &output).await.unwrap();
+        let elapsed = started.elapsed();
+        
+        // The first chunk is free, the other 47 KB take about 0.73 s at 64 KB/s
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        assert!(elapsed >= Duration::from_millis(650), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(2000), "{:?}", elapsed);
+        let _ = std::fs::remove_dir_all(output.parent().unwrap());
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::Ordering;
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+
+use super::{FileTransfer, TransferStatus};
+
+/// Downloads holding a slot, and those waiting for one in the order they'll start
+#[derive(Default)]
+pub(super) struct DownloadQueue {
+    pub(super) running: HashSet<String>,
+    pub(super) waiting: VecDeque<String>,
+}
+
+impl FileTransfer {
+    /// Start `file_hash` if it's first in line and a slot is free
+    pub(super) fn take_slot(&self, file_hash: &str) -> bool {
+        let mut queue = self.download_queue.lock().unwrap();
+        let free = queue.running.len() < self.max_concurrent_transfers.load(Ordering::Relaxed);
+        if !free || queue.waiting.front().map(String::as_str) != Some(file_hash) {
+            return false;
+        }
+        queue.waiting.pop_front();
+        queue.running.insert(file_hash.to_string());
+        true
+    }
+    
+    /// Wait out a queued download's turn; false if it was cancelled first
+    pub(super) async fn wait_for_slot(&self, file_hash: &str) -> Result<bool, Error> {
+        loop {
+            // Created before checking, so a change in between still wakes it
+            let changed = self.queue_changed.notified();
+            if self.take_slot(file_hash) {
+                self.transition(file_hash, TransferStatus::InProgress).await?;
+                return Ok(true);
+            }
+            if !self.download_queue.lock().unwrap().waiting.iter().any(|queued| queued == file_hash) {
+                return Ok(false);
+            }
+            changed.await;
+        }
+    }
+    
+    /// Give up a download's slot or place in line once it has ended
+    pub(super) fn leave_queue(&self, file_hash: &str) {
+        {
+            let mut queue = self.download_queue.lock().unwrap();
+            queue.running.remove(file_hash);
+            queue.waiting.retain(|queued| queued != file_hash);
+        }
+        self.queue_changed.notify_waiters();
+    }
+    
+    /// Move a queued download to `position` in line, 0 being next to start
+    pub fn reorder_queue(&self, file_hash: &str, position: usize) -> Result<(), Error> {
+        {
+            let mut queue = self.download_queue.lock().unwrap();
+            let current = queue
+                .waiting
+                .iter()
+                .position(|queued| queued == file_hash)
+                .ok_or_else(|| DeskShareError::InvalidTransition(format!("transfer {} isn't queued", file_hash)))?;
+            let queued = queue.waiting.remove(current).unwrap();
+            let position = position.min(queue.waiting.len());
+            queue.waiting.insert(position, queued);
+        }
+        self.queue_changed.notify_waiters();
+        Ok(())
+    }
+    
+    /// Queued downloads, next to start first
+    pub fn queued_transfers(&self) -> Vec<String> {
+        self.download_queue.lock().unwrap().waiting.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use bytes::Bytes;
+    use crate::network::chunk_pipeline::ChunkTransport;
+    use crate::network::file_transfer::{ChunkEnvelope, SharedFile};
+    use crate::network::file_transfer::tests::{describe, scratch_dir};
+    
+    /// Answers a fetch only once the test lets one through
+    struct GatedPeers {
+        files: HashMap<String, Vec<u8>>,
+        gate: tokio::sync::Semaphore,
+    }
+    
+    #[async_trait::async_trait]
+    impl ChunkTransport for GatedPeers {
+        async fn fetch_chunk(&self, _peer_id: &str, file_hash: &str, _index: usize) -> Result<Bytes, Error> {
+            self.gate.acquire().await.unwrap().forget();
+            Ok(Bytes::copy_from_slice(&self.files[file_hash]))
+        }
+        
+        async fn send_chunk(&self, _peer_id: &str, _envelope: ChunkEnvelope) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_downloads_past_the_limit_wait_their_turn() {
+        let contents = [b"first".as_slice(), b"second", b"third", b"fourth"];
+        let files: Vec<SharedFile> = contents.iter().map(|data| describe(data, 16)).collect();
+        let transport = Arc::new(GatedPeers {
+            files: files.iter().zip(contents).map(|(file, data)| (file.hash.clone(), data.to_vec())).collect(),
+            gate: tokio::sync::Semaphore::new(0),
+        });
+        let file_transfer = Arc::new(FileTransfer::new().await.with_chunk_transport(transport.clone()).with_max_concurrent_transfers(1));
+        let dir = scratch_dir("queue");
+        let mut downloads = Vec::new();
+        for (i, file) in files.iter().enumerate() {
+            file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+            file_transfer.peers_with_files.write().await.insert(file.hash.clone(), HashSet::from(["10.0.0.2".to_string()]));
+            let downloader = file_transfer.clone();
+            let (file_hash, output) = (file.hash.clone(), dir.join(i.to_string()));
+            downloads.push(tokio::spawn(async move { downloader.download_file(&file_hash, &output).await }));
+            while file_transfer.active_transfers.len() <= i {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+        let status = |file: &SharedFile| file_transfer.active_transfers.get(&file.hash).unwrap().status;
+        let [first, second, third, fourth] = [&files[0], &files[1], &files[2], &files[3]];
+        assert_eq!(
+            [status(first), status(second), status(third), status(fourth)],
+            [TransferStatus::InProgress, TransferStatus::Queued, TransferStatus::Queued, TransferStatus::Queued]
+        );
+        
+        // Bumped to the front, the third starts as soon as the first is done
+        file_transfer.reorder_queue(&third.hash, 0).unwrap();
+        assert_eq!(file_transfer.queued_transfers(), vec![third.hash.clone(), second.hash.clone(), fourth.hash.clone()]);
+        assert!(file_transfer.reorder_queue(&first.hash, 0).is_err());
+        transport.gate.add_permits(1);
+        tokio::time::timeout(Duration::from_secs(5), &mut downloads[0]).await.unwrap().unwrap().unwrap();
+        while status(third) != TransferStatus::InProgress {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!((status(first), status(second)), (TransferStatus::Completed, TransferStatus::Queued));
+        
+        // Cancelled while queued, it never starts
+        assert_eq!(file_transfer.cancel_transfer(&second.hash).await.unwrap(), TransferStatus::Cancelled);
+        tokio::time::timeout(Duration::from_secs(5), &mut downloads[1]).await.unwrap().unwrap().unwrap();
+        
+        // Paused while queued, it leaves the line until resumed
+        assert_eq!(file_transfer.pause_transfer(&fourth.hash).await.unwrap(), TransferStatus::Paused);
+        tokio::time::timeout(Duration::from_secs(5), &mut downloads[3]).await.unwrap().unwrap().unwrap();
+        assert!(file_transfer.queued_transfers().is_empty());
+        transport.gate.add_permits(1);
+        tokio::time::timeout(Duration::from_secs(5), &mut downloads[2]).await.unwrap().unwrap().unwrap();
+        assert_eq!((status(second), status(third), status(fourth)), (TransferStatus::Cancelled, TransferStatus::Completed, TransferStatus::Paused));
+        transport.gate.add_permits(1);
+        file_transfer.resume_transfer(&fourth.hash).await.unwrap();
+        assert_eq!(status(fourth), TransferStatus::Completed);
+        assert!(file_transfer.queued_transfers().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::network::manifest;
+
+use super::{chunk_len, FileTransfer, SharedFile, TransferStatus};
+
+/// Added to an output file's name for the record of an unfinished download
+const PARTIAL_SUFFIX: &str = ".dsnpart.json";
+
+/// Where the progress of a download to `output_path` is kept
+pub(super) fn partial_path(output_path: &Path) -> PathBuf {
+    let mut name = output_path.file_name().unwrap_or_default().to_os_string();
+    name.push(PARTIAL_SUFFIX);
+    output_path.with_file_name(name)
+}
+
+fn encode_bitmap(indices: &HashSet<usize>) -> String {
+    let mut bits = vec![0u8; indices.iter().max().map_or(0, |max| max / 8 + 1)];
+    for index in indices {
+        bits[index / 8] |= 1 << (index % 8);
+    }
+    hex::encode(bits)
+}
+
+/// Indices set in an encoded bitmap, below `total`
+fn decode_bitmap(bitmap: &str, total: usize) -> Vec<usize> {
+    let bits = hex::decode(bitmap).unwrap_or_default();
+    (0..total.min(bits.len() * 8)).filter(|index| bits[index / 8] & (1 << (index % 8)) != 0).collect()
+}
+
+/// What's kept beside a download's output, `<output>.dsnpart.json`, so it
+/// can pick up where it left off after a restart
+#[derive(Serialize, Deserialize)]
+struct PartialDownload {
+    file: SharedFile,
+    output_path: PathBuf,
+    /// Peers that had the file, to ask again
+    peers: Vec<String>,
+    /// Bit `i` is set once chunk `i` is written, hex encoded
+    received: String,
+}
+
+impl FileTransfer {
+    /// Record how far a download has got beside its output, so a restart
+    /// can pick it up from there. Folder manifests aren't recorded.
+    pub(super) async fn save_partial(&self, file_hash: &str) {
+        let saved = self.downloading_files.read().await.get(file_hash).and_then(|downloading| {
+            // Finished ones are cleaned up, not recorded
+            downloading.output.as_ref()?;
+            if downloading.chunks_received.len() == downloading.chunks_expected {
+                return None;
+            }
+            Some((downloading.output_path.clone(), encode_bitmap(&downloading.chunks_received)))
+        });
+        let (Some((output_path, received)), Some(file)) = (saved, self.shared_files.get(file_hash).map(|file| file.clone())) else {
+            return;
+        };
+        let mut peers: Vec<String> = self
+            .peers_with_files
+            .read()
+            .await
+            .get(file_hash)
+            .map(|holders| holders.iter().cloned().collect())
+            .unwrap_or_default();
+        peers.sort();
+        let partial = PartialDownload { file, output_path: output_path.clone(), peers, received };
+        
+        // Written aside and moved into place, so a crash leaves the old record whole
+        let sidecar = partial_path(&output_path);
+        let staged = sidecar.with_extension("json.tmp");
+        let saved = async {
+            tokio::fs::write(&staged, serde_json::to_vec(&partial)?).await?;
+            tokio::fs::rename(&staged, &sidecar).await?;
+            Ok::<_, Error>(())
+        };
+        if let Err(e) = saved.await {
+            tracing::warn!("Couldn't record the progress of {}: {}", file_hash, e);
+        }
+    }
+    
+    /// Pick up the downloads a previous run left unfinished in the downloads
+    /// folder. Chunks already written are checked against their hashes again
+    /// and only the rest are requested. Returns the hashes of the downloads
+    /// resumed, once they've run.
+    pub async fn resume_pending_downloads(&self) -> Result<Vec<String>, Error> {
+        let resumed = self.restore_partials(false).await?;
+        let runs = resumed.iter().map(|(file_hash, status)| async move {
+            if *status == TransferStatus::Queued && !self.wait_for_slot(file_hash).await? {
+                return Ok(());
+            }
+            self.request_chunks(file_hash).await
+        });
+        for ((file_hash, _), result) in resumed.iter().zip(futures::future::join_all(runs).await) {
+            if let Err(e) = result {
+                tracing::warn!("Resumed download of {} failed: {}", file_hash, e);
+            }
+        }
+        Ok(resumed.into_iter().map(|(file_hash, _)| file_hash).collect())
+    }
+    
+    /// List the downloads a previous run left unfinished as Paused, to go on
+    /// when each is resumed. Chunks already written are checked again as
+    /// they would be on resuming. Returns their hashes.
+    pub async fn restore_pending_downloads(&self) -> Result<Vec<String>, Error> {
+        let restored = self.restore_partials(true).await?;
+        Ok(restored.into_iter().map(|(file_hash, _)| file_hash).collect())
+    }
+    
+    /// Register every download a sidecar in the downloads folder describes
+    async fn restore_partials(&self, paused: bool) -> Result<Vec<(String, TransferStatus)>, Error> {
+        let root = self.downloads_dir();
+        if !root.is_dir() {
+            return Ok(Vec::new());
+        }
+        let walked = root.clone();
+        let (_, paths) = tokio::task::spawn_blocking(move || manifest::walk(&walked))
+            .await
+            .map_err(|e| DeskShareError::Internal(e.to_string()))??;
+        
+        let mut restored = Vec::new();
+        for sidecar in paths.iter().filter(|path| path.ends_with(PARTIAL_SUFFIX)).map(|path| root.join(path)) {
+            match self.restore_partial(&sidecar, paused).await {
+                Ok(Some(download)) => restored.push(download),
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("Not resuming {:?}: {}", sidecar, e);
+                    let _ = tokio::fs::remove_file(&sidecar).await;
+                }
+            }
+        }
+        Ok(restored)
+    }
+    
+    /// Register the download a sidecar describes, with the chunks on disk
+    /// that still check out; None if it's under way already
+    async fn restore_partial(&self, sidecar: &Path, paused: bool) -> Result<Option<(String, TransferStatus)>, Error> {
+        let partial: PartialDownload = serde_json::from_slice(&tokio::fs::read(sidecar).await?)?;
+        let file = partial.file;
+        if self.downloading_files.read().await.contains_key(&file.hash) {
+            return Ok(None);
+        }
+        let claimed = decode_bitmap(&partial.received, file.total_chunks);
+        let (output_path, checked) = (partial.output_path.clone(), file.clone());
+        let (output, verified) = self.off_runtime(move |cancel| Self::verify_written(&output_path, &checked, claimed, cancel)).await?;
+        tracing::info!("Restored {} with {} of {} chunks", file.name, verified.len(), file.total_chunks);
+        
+        let file_hash = file.hash.clone();
+        self.shared_files.entry(file_hash.clone()).or_insert_with(|| file.clone());
+        self.peers_with_files.write().await.entry(file_hash.clone()).or_default().extend(partial.peers);
+        let status = if paused {
+            TransferStatus::Paused
+        } else {
+            self.download_queue.lock().unwrap().waiting.push_back(file_hash.clone());
+            if self.take_slot(&file_hash) { TransferStatus::InProgress } else { TransferStatus::Queued }
+        };
+        self.track_download(file, &partial.output_path, status, Some(output), verified).await;
+        Ok(Some((file_hash, status)))
+    }
+    
+    /// Reopen a partial download and keep the chunks in `claimed` whose data
+    /// still matches its hash
+    fn verify_written(path: &Path, file: &SharedFile, claimed: Vec<usize>, cancel: &AtomicBool) -> Result<(std::fs::File, HashSet<usize>), Error> {
+        let mut output = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+        if output.metadata()?.len() != file.size {
+            output.set_len(file.size)?;
+        }
+        let mut verified = HashSet::new();
+        let mut buffer = Vec::new();
+        for index in claimed {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(DeskShareError::Internal("verification cancelled".to_string()).into());
+            }
+            buffer.resize(chunk_len(file, index) as usize, 0);
+            output.seek(SeekFrom::Start(index as u64 * file.chunk_size))?;
+            output.read_exact(&mut buffer)?;
+            if file.chunks.get(index) == Some(&Self::calculate_chunk_hash(file.hash_algorithm, index, &buffer)) {
+                verified.insert(index);
+            }
+        }
+        Ok((output, verified))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::network::file_transfer::tests::{describe, fixed_window, scratch_dir, RecordingPeers};
+    
+    #[tokio::test]
+    async fn test_resume_picks_up_where_it_left_off_with_current_peers() {
+        let data: Vec<u8> = (0..48 * 32).map(|i| (i * 7) as u8).collect();
+        let file = describe(&data, 32);
+        let transport = Arc::new(RecordingPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            fetches: Mutex::new(Vec::new()),
+        });
+        let file_transfer = Arc::new(
+            FileTransfer::new()
+                .await
+                .with_chunk_transport(transport.clone())
+                .with_chunk_windows(fixed_window(1)),
+        );
+        file_transfer.shared_files.insert(file.hash.clone(), file.clone());
+        let holders = |peer: &str| HashSet::from([peer.to_string()]);
+        file_transfer.peers_with_files.write().await.insert(file.hash.clone(), holders("10.0.0.2"));
+        let output = scratch_dir("resume").join("report.pdf");
+        
+        let downloader = file_transfer.clone();
+        let (file_hash, path) = (file.hash.clone(), output.clone());
+        let download = tokio::spawn(async move { downloader.download_file(&file_hash, &path).await });
+        let received = || async {
+            let downloading = file_transfer.downloading_files.read().await;
+            downloading.get(&file.hash).map_or(0, |downloading| downloading.chunks_received.len())
+        };
+        while received().await < 8 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        file_transfer.pause_transfer(&file.hash).await.unwrap();
+        // Requests in flight land, then the download stops asking
+        download.await.unwrap().unwrap();
+        let before_resume = transport.fetches.lock().unwrap().len();
+        assert!(before_resume < 48);
+        assert_eq!(file_transfer.get_transfer_progress().await[0].status, TransferStatus::Paused);
+        
+        // The first peer went away while we were paused
+        file_transfer.peers_with_files.write().await.insert(file.hash.clone(), holders("10.0.0.4"));
+        file_transfer.resume_transfer(&file.hash).await.unwrap();
+        
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        assert_eq!(file_transfer.get_transfer_progress().await[0].status, TransferStatus::Completed);
+        let fetches = transport.fetches.lock().unwrap().clone();
+        assert!(fetches[before_resume..].iter().all(|(peer, _)| peer == "10.0.0.4"));
+        let fetched: HashSet<usize> = fetches.iter().map(|(_, index)| *index).collect();
+        assert_eq!((fetches.len(), fetched.len()), (48, 48), "a chunk was fetched twice");
+        
+        // Finished transfers can't be paused
+        let err = file_transfer.pause_transfer(&file.hash).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DeskShareError>(), Some(DeskShareError::InvalidTransition(_))));
+        let _ = std::fs::remove_dir_all(output.parent().unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_downloads_pick_up_after_a_restart() {
+        let data: Vec<u8> = (0..48 * 32 + 5).map(|i| (i * 7) as u8).collect();
+        let file = describe(&data, 32);
+        let chunks: Vec<Vec<u8>> = data.chunks(32).map(<[u8]>::to_vec).collect();
+        let dir = scratch_dir("resume");
+        let (output, sidecar) = (dir.join("report.pdf"), dir.join("report.pdf.dsnpart.json"));
+        
+        // Stopped partway through, as if the app had been closed
+        let interrupted = Arc::new(
+            FileTransfer::new()
+                .await
+                .with_chunk_transport(Arc::new(RecordingPeers { chunks: chunks.clone(), fetches: Mutex::new(Vec::new()) }))
+                .with_chunk_windows(fixed_window(1)),
+        );
+        interrupted.set_downloads_dir(dir.clone()).unwrap();
+        interrupted.shared_files.insert(file.hash.clone(), file.clone());
+        interrupted.peers_with_files.write().await.insert(file.hash.clone(), HashSet::from(["10.0.0.2".to_string()]));
+        let downloader = interrupted.clone();
+        let (file_hash, target) = (file.hash.clone(), output.clone());
+        let download = tokio::spawn(async move { downloader.download_file(&file_hash, &target).await });
+        let saved = loop {
+            if let Ok(partial) = std::fs::read(&sidecar).map(|json| serde_json::from_slice::<PartialDownload>(&json).unwrap()) {
+                let saved = decode_bitmap(&partial.received, file.total_chunks);
+                if saved.len() >= 8 {
+                    break saved;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        };
+        download.abort();
+        let _ = download.await;
+        drop(interrupted);
+        let corrupted = saved[0];
+        
+        // A chunk that changed on disk since is fetched again
+        {
+            use std::io::Write;
+            let mut written = std::fs::OpenOptions::new().write(true).open(&output).unwrap();
+            written.seek(SeekFrom::Start(corrupted as u64 * 32)).unwrap();
+            written.write_all(&[0xff; 32]).unwrap();
+        }
+        
+        let transport = Arc::new(RecordingPeers { chunks, fetches: Mutex::new(Vec::new()) });
+        let restarted = FileTransfer::new().await.with_chunk_transport(transport.clone()).with_chunk_windows(fixed_window(1));
+        restarted.set_downloads_dir(dir.clone()).unwrap();
+        assert_eq!(restarted.resume_pending_downloads().await.unwrap(), vec![file.hash.clone()]);
+        
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        assert!(!sidecar.exists());
+        let fetched: HashSet<usize> = transport.fetches.lock().unwrap().iter().map(|(peer, index)| {
+            assert_eq!(peer, "10.0.0.2");
+            *index
+        }).collect();
+        assert!(fetched.contains(&corrupted));
+        assert!(saved.iter().filter(|&&index| index != corrupted).all(|index| !fetched.contains(index)));
+        let progress = restarted.get_transfer_progress().await;
+        assert_eq!(progress[0].status, TransferStatus::Completed);
+        
+        // Nothing left to pick up the next time
+        assert!(restarted.resume_pending_downloads().await.unwrap().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_restored_downloads_wait_paused() {
+        let data: Vec<u8> = (0..16 * 32 + 5).map(|i| (i * 5) as u8).collect();
+        let file = describe(&data, 32);
+        let chunks: Vec<Vec<u8>> = data.chunks(32).map(<[u8]>::to_vec).collect();
+        let dir = scratch_dir("restore-paused");
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("report.pdf");
+        let mut written = vec![0u8; data.len()];
+        written[..6 * 32].copy_from_slice(&data[..6 * 32]);
+        std::fs::write(&output, &written).unwrap();
+        let partial = PartialDownload {
+            file: file.clone(),
+            output_path: output.clone(),
+            peers: vec!["10.0.0.2".to_string()],
+            received: encode_bitmap(&(0..6).collect()),
+        };
+        std::fs::write(partial_path(&output), serde_json::to_vec(&partial).unwrap()).unwrap();
+        
+        let transport = Arc::new(RecordingPeers { chunks, fetches: Mutex::new(Vec::new()) });
+        let restarted = FileTransfer::new().await.with_chunk_transport(transport.clone());
+        restarted.set_downloads_dir(dir.clone()).unwrap();
+        assert_eq!(restarted.restore_pending_downloads().await.unwrap(), vec![file.hash.clone()]);
+        let progress = restarted.get_transfer_progress().await;
+        assert_eq!((progress[0].status, progress[0].bytes_transferred), (TransferStatus::Paused, 6 * 32));
+        assert!(transport.fetches.lock().unwrap().is_empty());
+        
+        restarted.resume_transfer(&file.hash).await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        assert_eq!(restarted.get_transfer_progress().await[0].status, TransferStatus::Completed);
+        assert!(transport.fetches.lock().unwrap().iter().all(|(_, index)| *index >= 6));
+        assert!(restarted.queued_transfers().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
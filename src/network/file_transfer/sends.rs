@@ -0,0 +1,472 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::p2p::{LanStream, TcpTransport};
+use crate::security::PeerIdentity;
+use crate::network::lan_transfer::DEFAULT_TRANSFER_PORT;
+use crate::network::transfer_protocol::FileTransferMessage;
+
+use super::{FileTransfer, SharedFile, TransferProgress, TransferStatus};
+
+/// How long a direct connection has to deliver its offer once it's open
+const OFFER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to reach a device given as "ip" or "ip:port"
+fn device_addr(device: &str) -> Result<SocketAddr, DeskShareError> {
+    device
+        .parse::<SocketAddr>()
+        .or_else(|_| device.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DEFAULT_TRANSFER_PORT)))
+        .map_err(|_| DeskShareError::PeerConnectionFailed(format!("{} isn't a device address", device)))
+}
+
+/// How sending a share straight to one device went
+pub(super) struct DeviceSend {
+    status: TransferStatus,
+    error_message: Option<String>,
+    error_kind: Option<String>,
+}
+
+/// A file sent straight to one of several devices
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DestinationProgress {
+    pub device_ip: String,
+    #[serde(flatten)]
+    pub progress: TransferProgress,
+}
+
+/// A file sent to several devices at once
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MultiSendProgress {
+    /// Summed over every destination. Completed once they all are, Failed
+    /// once they're all done and any of them failed.
+    pub aggregate: TransferProgress,
+    pub destinations: Vec<DestinationProgress>,
+}
+
+impl FileTransfer {
+    /// Offer a file to the device at `device_ip` (on the default port unless
+    /// one is given) over a direct connection, then serve its chunk requests
+    /// until it has the whole file
+    pub async fn send_file_to_device(&self, device_ip: &str, file_path: &str) -> Result<(), Error> {
+        let file_hash = self.share_file(Path::new(file_path), "local".to_string()).await?;
+        self.send_share_to_device(device_ip, &file_hash).await
+    }
+    
+    /// Share a file once and send it to every device in `device_ips` at the
+    /// same time. A device that can't be reached or drops out is marked
+    /// failed without holding up the others, so this only fails if the file
+    /// can't be shared.
+    pub async fn send_file_to_devices(&self, device_ips: Vec<String>, file_path: &str) -> Result<MultiSendProgress, Error> {
+        if device_ips.is_empty() {
+            return Err(DeskShareError::InvalidConfig("no devices to send to".to_string()).into());
+        }
+        let file_hash = self.share_file(Path::new(file_path), "local".to_string()).await?;
+        self.send_share_to_devices(device_ips, &file_hash).await
+    }
+    
+    /// Like `send_file_to_devices`, for something already shared
+    pub async fn send_share_to_devices(&self, device_ips: Vec<String>, file_hash: &str) -> Result<MultiSendProgress, Error> {
+        if device_ips.is_empty() {
+            return Err(DeskShareError::InvalidConfig("no devices to send to".to_string()).into());
+        }
+        if !self.shared_files.contains_key(file_hash) {
+            return Err(DeskShareError::ShareNotFound(file_hash.to_string()).into());
+        }
+        
+        let mut seen = HashSet::new();
+        let device_ips: Vec<String> = device_ips.into_iter().filter(|device_ip| seen.insert(device_ip.clone())).collect();
+        for device_ip in &device_ips {
+            let send = DeviceSend { status: TransferStatus::InProgress, error_message: None, error_kind: None };
+            self.device_sends.insert((file_hash.to_string(), device_ip.clone()), send);
+        }
+        
+        let sends = device_ips.iter().map(|device_ip| async move {
+            let result = self.send_share_to_device(device_ip, file_hash).await;
+            if let Err(e) = &result {
+                tracing::warn!("Sending {} to {} failed: {}", file_hash, device_ip, e);
+            }
+            if let Some(mut send) = self.device_sends.get_mut(&(file_hash.to_string(), device_ip.clone())) {
+                match result {
+                    Ok(()) => send.status = TransferStatus::Completed,
+                    Err(e) => {
+                        send.status = TransferStatus::Failed;
+                        let error = e.downcast_ref::<DeskShareError>();
+                        send.error_message = Some(error.map_or_else(|| e.to_string(), DeskShareError::user_message));
+                        send.error_kind = error.map(|error| error.code().to_string());
+                    }
+                }
+            }
+        });
+        futures::future::join_all(sends).await;
+        
+        self.get_multi_send_progress(file_hash)
+            .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.to_string()).into())
+    }
+    
+    /// Where a file sent with `send_file_to_devices` has got to, per device
+    /// and overall
+    pub fn get_multi_send_progress(&self, file_hash: &str) -> Option<MultiSendProgress> {
+        let file = self.shared_files.get(file_hash)?.clone();
+        let mut destinations: Vec<DestinationProgress> = self
+            .device_sends
+            .iter()
+            .filter(|send| send.key().0 == file_hash)
+            .map(|send| {
+                let device_ip = send.key().1.clone();
+                let mut progress = TransferProgress {
+                    file_name: file.name.clone(),
+                    file_hash: file.hash.clone(),
+                    bytes_transferred: 0,
+                    total_bytes: file.size,
+                    percentage: 0.0,
+                    status: send.status,
+                    bytes_per_second: 0.0,
+                    eta_seconds: None,
+                    output_path: None,
+                    verified_hash: None,
+                    skipped: false,
+                    error_message: None,
+                    error_kind: None,
+                };
+                if send.status == TransferStatus::Completed {
+                    progress.bytes_transferred = file.size;
+                    progress.percentage = 100.0;
+                    progress.eta_seconds = Some(0);
+                } else if let Some(addr) = device_addr(&device_ip).ok().filter(|_| send.status == TransferStatus::InProgress) {
+                    // Uploads know the peer by its address alone
+                    if let Some(upload) = self.uploads.get(&(file.hash.clone(), addr.ip().to_string())) {
+                        Self::apply_flush(&mut progress, &upload.sent.flush());
+                    }
+                }
+                progress.error_message = send.error_message.clone();
+                progress.error_kind = send.error_kind.clone();
+                DestinationProgress { device_ip, progress }
+            })
+            .collect();
+        if destinations.is_empty() {
+            return None;
+        }
+        destinations.sort_by(|a, b| a.device_ip.cmp(&b.device_ip));
+        
+        let total_bytes = file.size * destinations.len() as u64;
+        let bytes_transferred: u64 = destinations.iter().map(|destination| destination.progress.bytes_transferred).sum();
+        let bytes_per_second = destinations.iter().map(|destination| destination.progress.bytes_per_second).sum();
+        let statuses = || destinations.iter().map(|destination| destination.progress.status);
+        let status = if statuses().any(|status| !status.is_terminal()) {
+            TransferStatus::InProgress
+        } else if statuses().all(|status| status == TransferStatus::Completed) {
+            TransferStatus::Completed
+        } else {
+            TransferStatus::Failed
+        };
+        let aggregate = TransferProgress {
+            file_name: file.name.clone(),
+            file_hash: file.hash.clone(),
+            bytes_transferred,
+            total_bytes,
+            percentage: match total_bytes {
+                0 => 100.0,
+                total => (bytes_transferred as f64 / total as f64) * 100.0,
+            },
+            status,
+            bytes_per_second,
+            eta_seconds: Self::estimate_eta(total_bytes - bytes_transferred, bytes_per_second),
+            output_path: None,
+            verified_hash: None,
+            skipped: false,
+            error_message: None,
+            error_kind: None,
+        };
+        Some(MultiSendProgress { aggregate, destinations })
+    }
+    
+    /// Offer a snippet of text to the device at `device_ip`, like a file
+    pub async fn send_text_to_device(&self, device_ip: &str, content: String, title: String) -> Result<(), Error> {
+        let file_hash = self.share_text(content, title, "local".to_string()).await?;
+        self.send_share_to_device(device_ip, &file_hash).await
+    }
+    
+    /// Offer a file to the device at `device_ip` and return its hash once the
+    /// offer has arrived; its chunk requests are served in the background
+    pub async fn send_offer(&self, device_ip: &str, file_path: &str) -> Result<String, Error> {
+        let file_hash = self.share_file(Path::new(file_path), "local".to_string()).await?;
+        let (mut stream, file, peer_id) = self.deliver_offer(device_ip, &file_hash).await?;
+        
+        let sender = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = sender.serve_transfer(&mut stream, &file, &peer_id).await {
+                tracing::warn!("Sending {} to {} failed: {}", file.name, peer_id, e);
+            }
+        });
+        Ok(file_hash)
+    }
+    
+    async fn send_share_to_device(&self, device_ip: &str, file_hash: &str) -> Result<(), Error> {
+        let (mut stream, file, peer_id) = self.deliver_offer(device_ip, file_hash).await?;
+        self.serve_transfer(&mut stream, &file, &peer_id).await
+    }
+    
+    /// Connect to `device_ip` and offer it a shared file, returning the open
+    /// connection along with the file and the peer's address
+    async fn deliver_offer(&self, device_ip: &str, file_hash: &str) -> Result<(LanStream, SharedFile, String), Error> {
+        let identity = self
+            .identity
+            .clone()
+            .ok_or_else(|| DeskShareError::InvalidConfig("no identity to connect with".to_string()))?;
+        let addr = device_addr(device_ip)?;
+        let file = self
+            .shared_files
+            .get(file_hash)
+            .map(|file| file.clone())
+            .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.to_string()))?;
+        
+        let mut transport = TcpTransport::new(identity.clone(), self.security_config);
+        if let Some(trust_store) = &self.trust_store {
+            transport = transport.with_trust_store(trust_store.clone());
+        }
+        let mut stream = transport.connect(addr).await?;
+        let offer = FileTransferMessage::Offer {
+            sender_name: identity.device_id(),
+            file: file.clone(),
+        };
+        offer.send(&mut stream).await?;
+        Ok((stream, file, addr.ip().to_string()))
+    }
+    
+    /// Answer `peer_id`'s requests for `file` until it's had every chunk
+    async fn serve_transfer(&self, stream: &mut LanStream, file: &SharedFile, peer_id: &str) -> Result<(), Error> {
+        let mut served = HashSet::new();
+        while served.len() < file.total_chunks {
+            // The first request waits on the user, for as long as an offer lasts
+            let wait = Duration::from_millis(self.offer_timeout_ms.load(Ordering::Relaxed));
+            let message = tokio::time::timeout(wait, FileTransferMessage::recv(stream))
+                .await
+                .map_err(|_| DeskShareError::Timeout)??;
+            if matches!(&message, FileTransferMessage::TransferComplete { file_hash } if *file_hash == file.hash) {
+                break;
+            }
+            if let Some(reply) = self.handle_message(peer_id, message).await? {
+                if let FileTransferMessage::ChunkData { file_hash, chunk_index, .. } = &reply {
+                    if *file_hash == file.hash {
+                        served.insert(*chunk_index);
+                    }
+                }
+                reply.send(stream).await?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// The single entry point for file transfer messages from `peer_id`;
+    /// returns the encoded reply, if the message needs one
+    pub async fn handle_incoming_message(&self, peer_id: &str, message: impl Into<Bytes>) -> Result<Option<Bytes>, Error> {
+        let message = FileTransferMessage::decode(message.into())?;
+        match self.handle_message(peer_id, message).await? {
+            Some(reply) => Ok(Some(reply.encode()?)),
+            None => Ok(None),
+        }
+    }
+    
+    async fn handle_message(&self, peer_id: &str, message: FileTransferMessage) -> Result<Option<FileTransferMessage>, Error> {
+        match message {
+            FileTransferMessage::Offer { sender_name, file } => {
+                self.receive_offer(peer_id.to_string(), sender_name, file).await;
+                Ok(None)
+            }
+            FileTransferMessage::ChunkRequest { file_hash, chunk_index } => {
+                let reply = match self.serve_chunk(&file_hash, chunk_index, peer_id).await {
+                    Ok(data) => FileTransferMessage::ChunkData { file_hash, chunk_index, data },
+                    Err(e) => FileTransferMessage::Error { file_hash, chunk_index: Some(chunk_index), reason: e.to_string() },
+                };
+                Ok(Some(reply))
+            }
+            FileTransferMessage::ChunkData { file_hash, chunk_index, data } => {
+                self.receive_chunk(&file_hash, chunk_index, data).await?;
+                Ok(None)
+            }
+            FileTransferMessage::TransferComplete { file_hash } => {
+                tracing::debug!("{} has all of {}", peer_id, file_hash);
+                Ok(None)
+            }
+            FileTransferMessage::Error { file_hash, chunk_index: Some(chunk_index), reason } => {
+                tracing::warn!("{} couldn't send chunk {} of {}: {}", peer_id, chunk_index, file_hash, reason);
+                Ok(None)
+            }
+            // Without a chunk, the peer turned the whole transfer down
+            FileTransferMessage::Error { file_hash, chunk_index: None, reason } => {
+                let name = self.shared_files.get(&file_hash).map(|file| file.name.clone()).unwrap_or(file_hash);
+                Err(DeskShareError::FileTransferFailed(format!("{} declined {}: {}", peer_id, name, reason)).into())
+            }
+        }
+    }
+    
+    /// Take an offer arriving on a direct connection from `peer_id`; once
+    /// it's accepted, the file's chunks are fetched over the same connection
+    pub async fn accept_transfer(&self, mut stream: LanStream, peer_id: String) -> Result<String, Error> {
+        let transport = self
+            .lan_transport
+            .clone()
+            .ok_or_else(|| DeskShareError::InvalidConfig("direct transfers aren't enabled".to_string()))?;
+        let message = tokio::time::timeout(OFFER_READ_TIMEOUT, FileTransferMessage::recv(&mut stream))
+            .await
+            .map_err(|_| DeskShareError::Timeout)??;
+        let FileTransferMessage::Offer { sender_name, file } = message else {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        };
+        let sender = stream.remote_device_id().map(|device_id| PeerIdentity { peer_id: peer_id.clone(), device_id });
+        // Attached first: an auto-accepted offer starts fetching straight away
+        transport.attach(&peer_id, stream);
+        Ok(match sender {
+            Some(sender) => self.receive_authenticated_offer(sender, sender_name, file).await,
+            None => self.receive_offer(peer_id, sender_name, file).await,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::network::file_transfer::CHUNK_SIZE;
+    use crate::network::file_transfer::tests::scratch_dir;
+    use crate::network::lan_transfer::LanChunkTransport;
+    use crate::security::{DeviceIdentity, SecurityConfig};
+    
+    #[tokio::test]
+    async fn test_files_sent_to_a_device_cross_the_network() {
+        let dir = scratch_dir("lan-send");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("holiday.mov"), &data).unwrap();
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let receiver_identity = Arc::new(DeviceIdentity::generate());
+        let receiver = Arc::new(FileTransfer::new().await.with_lan_transport(Arc::new(LanChunkTransport::new())));
+        let downloads = dir.join("downloads");
+        let server = {
+            let (receiver, downloads) = (receiver.clone(), downloads.clone());
+            tokio::spawn(async move {
+                let transport = TcpTransport::new(receiver_identity, SecurityConfig::default());
+                let (stream, addr) = transport.accept(&listener).await.unwrap();
+                let offer_id = receiver.accept_transfer(stream, addr.ip().to_string()).await.unwrap();
+                assert_eq!(receiver.get_pending_offers()[0].file.name, "holiday.mov");
+                let file_hash = receiver.accept_offer(&offer_id, &downloads).await.unwrap();
+                assert_eq!(receiver.wait_for_transfer(&file_hash).await, Some(TransferStatus::Completed));
+            })
+        };
+        
+        let sender = FileTransfer::new().await.with_identity(Arc::new(DeviceIdentity::generate()));
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let path = dir.join("holiday.mov");
+        sender.send_file_to_device(&format!("127.0.0.1:{}", port), path.to_str().unwrap()).await.unwrap();
+        server.await.unwrap();
+        assert_eq!(std::fs::read(downloads.join("holiday.mov")).unwrap(), data);
+        
+        // Nobody listening is a failure, not a quiet success
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let err = sender.send_file_to_device(&closed.to_string(), path.to_str().unwrap()).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "peer_connection_failed");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_one_file_fans_out_to_several_devices() {
+        let dir = scratch_dir("lan-fan-out");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 100).map(|i| (i % 241) as u8).collect();
+        std::fs::write(dir.join("deck.pdf"), &data).unwrap();
+        
+        let mut devices = Vec::new();
+        let mut servers = Vec::new();
+        for name in ["first", "second"] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            devices.push(listener.local_addr().unwrap().to_string());
+            let receiver = FileTransfer::new().await.with_lan_transport(Arc::new(LanChunkTransport::new()));
+            let downloads = dir.join(name);
+            servers.push(tokio::spawn(async move {
+                let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+                let (stream, addr) = transport.accept(&listener).await.unwrap();
+                let offer_id = receiver.accept_transfer(stream, addr.ip().to_string()).await.unwrap();
+                let file_hash = receiver.accept_offer(&offer_id, &downloads).await.unwrap();
+                assert_eq!(receiver.wait_for_transfer(&file_hash).await, Some(TransferStatus::Completed));
+            }));
+        }
+        // Nobody answers here, which mustn't stop the others
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().to_string();
+        devices.insert(1, closed.clone());
+        
+        let sender = FileTransfer::new().await.with_identity(Arc::new(DeviceIdentity::generate()));
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let path = dir.join("deck.pdf");
+        let mut targets = devices.clone();
+        targets.push(devices[0].clone());
+        let sent = sender.send_file_to_devices(targets, path.to_str().unwrap()).await.unwrap();
+        for server in servers {
+            server.await.unwrap();
+        }
+        for name in ["first", "second"] {
+            assert_eq!(std::fs::read(dir.join(name).join("deck.pdf")).unwrap(), data);
+        }
+        
+        assert_eq!(sender.list_shared_files().len(), 1);
+        assert_eq!(sent.destinations.len(), 3);
+        for destination in &sent.destinations {
+            if destination.device_ip == closed {
+                assert_eq!(destination.progress.status, TransferStatus::Failed);
+                assert_eq!(destination.progress.error_kind.as_deref(), Some("peer_connection_failed"));
+            } else {
+                assert_eq!(destination.progress.status, TransferStatus::Completed);
+                assert_eq!(destination.progress.bytes_transferred, data.len() as u64);
+                assert_eq!(destination.progress.error_message, None);
+            }
+        }
+        assert_eq!(sent.aggregate.status, TransferStatus::Failed);
+        assert_eq!((sent.aggregate.bytes_transferred, sent.aggregate.total_bytes), (2 * data.len() as u64, 3 * data.len() as u64));
+        assert_eq!(sender.get_multi_send_progress(&sent.aggregate.file_hash).unwrap().destinations.len(), 3);
+        
+        let err = sender.send_file_to_devices(Vec::new(), path.to_str().unwrap()).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "invalid_config");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_wire_messages_move_a_file_between_instances() {
+        let dir = scratch_dir("wire-messages");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("notes.txt"), &data).unwrap();
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = sender.share_file(&dir.join("notes.txt"), "local".to_string()).await.unwrap();
+        let file = sender.shared_files.get(&file_hash).unwrap().clone();
+        
+        let receiver = FileTransfer::new().await;
+        let offer = FileTransferMessage::Offer { sender_name: "Alice".to_string(), file: file.clone() };
+        assert!(receiver.handle_incoming_message("10.0.0.3", offer.encode().unwrap()).await.unwrap().is_none());
+        let offer_id = receiver.get_pending_offers()[0].offer_id.clone();
+        receiver.accept_offer(&offer_id, &dir.join("downloads")).await.unwrap();
+        for chunk_index in 0..file.total_chunks {
+            let request = FileTransferMessage::ChunkRequest { file_hash: file_hash.clone(), chunk_index };
+            let reply = sender.handle_incoming_message("10.0.0.2", request.encode().unwrap()).await.unwrap().unwrap();
+            assert!(receiver.handle_incoming_message("10.0.0.3", reply).await.unwrap().is_none());
+        }
+        assert_eq!(std::fs::read(dir.join("downloads").join("notes.txt")).unwrap(), data);
+        
+        // A chunk past the end comes back as an error, and a refusal fails the send
+        let request = FileTransferMessage::ChunkRequest { file_hash: file_hash.clone(), chunk_index: 9 };
+        let reply = sender.handle_incoming_message("10.0.0.2", request.encode().unwrap()).await.unwrap().unwrap();
+        assert!(matches!(FileTransferMessage::decode(reply).unwrap(), FileTransferMessage::Error { chunk_index: Some(9), .. }));
+        let decline = FileTransferMessage::Error { file_hash, chunk_index: None, reason: "no room".to_string() };
+        let err = sender.handle_incoming_message("10.0.0.2", decline.encode().unwrap()).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "file_transfer_failed");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
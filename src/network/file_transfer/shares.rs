@@ -0,0 +1,705 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::network::hashing::HashAlgorithm;
+use crate::network::share_registry::{PersistedShare, ShareSource};
+
+use super::{
+    chunk_len, FileChunk, FileTransfer, HashedFile, ShareKind, ShareStats, SharedChunk, SharedFile, SharedFileSummary,
+    SignedRetraction, TransferProgress, TransferStatus, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE,
+};
+
+impl FileTransfer {
+    /// Offer a file to peers. Only its hashes are kept; chunks are read
+    /// back from disk as peers ask for them.
+    pub async fn share_file(&self, path: &Path, peer_id: String) -> Result<String, Error> {
+        self.share_file_with_chunk_size(path, peer_id, self.chunk_size).await
+    }
+    
+    /// Share a file cut into chunks of `chunk_size` rather than the default.
+    /// Receivers take whatever size the share names.
+    pub async fn share_file_with_chunk_size(&self, path: &Path, peer_id: String, chunk_size: usize) -> Result<String, Error> {
+        self.share_file_with(path, peer_id, chunk_size, self.hash_algorithm).await
+    }
+    
+    /// Share a file hashed with `algorithm` rather than the default, for
+    /// receivers whose tools expect those digests. Receivers check it with
+    /// whatever the share names.
+    pub async fn share_file_with_hash_algorithm(&self, path: &Path, peer_id: String, algorithm: HashAlgorithm) -> Result<String, Error> {
+        self.share_file_with(path, peer_id, self.chunk_size, algorithm).await
+    }
+    
+    async fn share_file_with(&self, path: &Path, peer_id: String, chunk_size: usize, algorithm: HashAlgorithm) -> Result<String, Error> {
+        let chunk_size = chunk_size.clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        let path: Arc<Path> = self.check_shareable(path)?.into();
+        let file = std::fs::File::open(&path)?;
+        let HashedFile { hash, chunks, codec } = self.off_runtime(move |cancel| Self::index_file(file, chunk_size, algorithm, cancel)).await?;
+        // The same content shared again keeps the chunk records it has
+        if self.add_share_source(&hash, &path) {
+            self.persist_shares();
+            return Ok(hash);
+        }
+        let size = chunks.iter().map(|(_, len)| *len as u64).sum();
+        let chunk_hashes: Vec<String> = chunks.iter().map(|(chunk_hash, _)| chunk_hash.clone()).collect();
+        let (manifest_version, merkle_root) = SharedFile::manifest_for(&chunk_hashes);
+        
+        // Create shared file record
+        let shared_file = SharedFile {
+            hash: hash.clone(),
+            name: path.file_name().unwrap().to_string_lossy().to_string(),
+            size,
+            chunks: chunk_hashes,
+            chunk_size: chunk_size as u64,
+            total_chunks: chunks.len(),
+            peer_id,
+            timestamp: Self::now_secs(),
+            kind: ShareKind::File,
+            codec,
+            encrypted: true,
+            manifest_version,
+            merkle_root,
+            hash_algorithm: algorithm,
+        };
+        
+        // Remember where each chunk is
+        self.share_sources.insert(hash.clone(), vec![path.clone()]);
+        for (i, (chunk_hash, len)) in chunks.into_iter().enumerate() {
+            self.shared_chunks.insert(chunk_hash, SharedChunk {
+                path: path.clone(),
+                offset: (i * chunk_size) as u64,
+                len,
+                index: i,
+                file_hash: hash.clone(),
+            });
+        }
+        
+        self.publish_share(shared_file).await?;
+        self.persist_shares();
+        
+        Ok(hash)
+    }
+    
+    /// Note another path `file_hash` is shared from, if we share it already
+    fn add_share_source(&self, file_hash: &str, path: &Arc<Path>) -> bool {
+        if !self.share_stats.contains_key(file_hash) {
+            return false;
+        }
+        let Some(mut sources) = self.share_sources.get_mut(file_hash) else {
+            return false;
+        };
+        if !sources.contains(path) {
+            sources.push(path.clone());
+        }
+        if let Some(mut file) = self.shared_files.get_mut(file_hash) {
+            file.timestamp = Self::now_secs();
+        }
+        tracing::debug!("{:?} has the same content as {}; reusing its chunks", path, file_hash);
+        true
+    }
+    
+    /// Cut `data` into chunks kept in memory and served from there; returns
+    /// its hash and the chunks' hashes
+    pub(super) fn hold_in_memory(&self, data: &[u8], chunk_size: usize) -> Result<(String, Vec<String>), Error> {
+        let mut chunks = Vec::new();
+        let hash = Self::hash_chunks(data, chunk_size, self.hash_algorithm, &AtomicBool::new(false), |i, chunk| chunks.push((i, chunk)))?;
+        let mut chunk_hashes = Vec::with_capacity(chunks.len());
+        for (index, data) in chunks {
+            let chunk_hash = Self::calculate_chunk_hash(self.hash_algorithm, index, &data);
+            self.file_chunks.insert(chunk_hash.clone(), FileChunk {
+                chunk_hash: chunk_hash.clone(),
+                data,
+                index,
+                file_hash: hash.clone(),
+            });
+            chunk_hashes.push(chunk_hash);
+        }
+        Ok((hash, chunk_hashes))
+    }
+    
+    /// Start offering a share whose chunks are in place, and list it
+    pub(super) async fn publish_share(&self, shared_file: SharedFile) -> Result<(), Error> {
+        let hash = shared_file.hash.clone();
+        self.shared_files.insert(hash.clone(), shared_file.clone());
+        self.share_stats.insert(hash.clone(), ShareStats::default());
+        self.expired_shares.remove(&hash);
+        
+        // Announce file to network
+        self.announce_file(&shared_file).await?;
+        
+        // Create transfer progress entry
+        let progress = TransferProgress {
+            file_name: shared_file.name.clone(),
+            file_hash: hash,
+            bytes_transferred: 0,
+            total_bytes: shared_file.size,
+            percentage: 0.0,
+            status: TransferStatus::Completed,
+            bytes_per_second: 0.0,
+            eta_seconds: None,
+            output_path: None,
+            verified_hash: None,
+            skipped: false,
+            error_message: None,
+            error_kind: None,
+        };
+        
+        self.publish_progress(progress).await;
+        
+        Ok(())
+    }
+    
+    /// Stop offering the file at `path`. Its chunks stay shared while the
+    /// same content is still shared from somewhere else.
+    pub async fn unshare_path(&self, path: &Path, force: bool) -> Result<(), Error> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let file_hash = self
+            .share_sources
+            .iter()
+            .find(|sources| sources.iter().any(|source| **source == *path))
+            .map(|sources| sources.key().clone())
+            .ok_or_else(|| DeskShareError::ShareNotFound(path.display().to_string()))?;
+        let remaining = self.share_sources.get_mut(&file_hash).and_then(|mut sources| {
+            sources.retain(|source| **source != *path);
+            sources.first().cloned()
+        });
+        let Some(source) = remaining else {
+            return self.unshare_file(&file_hash, force).await;
+        };
+        for mut chunk in self.shared_chunks.iter_mut() {
+            if chunk.file_hash == file_hash && *chunk.path == *path {
+                chunk.path = source.clone();
+            }
+        }
+        self.persist_shares();
+        Ok(())
+    }
+    
+    /// Stop offering a file and tell peers it's gone. Peers partway through
+    /// downloading it can finish unless `force` is set.
+    pub async fn unshare_file(&self, file_hash: &str, force: bool) -> Result<(), Error> {
+        if self.share_stats.remove(file_hash).is_none() {
+            return Err(DeskShareError::ShareNotFound(file_hash.to_string()).into());
+        }
+        match &self.identity {
+            Some(identity) => {
+                let _ = self.retraction_tx.send(SignedRetraction::sign(file_hash, identity));
+            }
+            None => tracing::debug!("No identity to sign the retraction of {}", file_hash),
+        }
+        
+        let finishing: HashSet<String> = self
+            .uploads
+            .iter()
+            .filter(|upload| upload.key().0 == file_hash)
+            .map(|upload| upload.key().1.clone())
+            .collect();
+        if force || finishing.is_empty() {
+            self.evict_share(file_hash);
+        } else {
+            tracing::info!("Unshared {}; still sending it to {} peer(s)", file_hash, finishing.len());
+            self.retiring_shares.insert(file_hash.to_string(), finishing);
+        }
+        
+        Ok(())
+    }
+    
+    /// Share a file until `ttl` has passed, or for good without one. Sharing
+    /// content that's shared already resets its time to live.
+    pub async fn share_file_with_ttl(&self, path: &Path, peer_id: String, ttl: Option<Duration>) -> Result<String, Error> {
+        let file_hash = self.share_file(path, peer_id).await?;
+        self.set_share_ttl(&file_hash, ttl)?;
+        Ok(file_hash)
+    }
+    
+    /// Unshare `file_hash` once `ttl` from now has passed, or never
+    pub fn set_share_ttl(&self, file_hash: &str, ttl: Option<Duration>) -> Result<(), Error> {
+        let mut stats = self
+            .share_stats
+            .get_mut(file_hash)
+            .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.to_string()))?;
+        stats.expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        drop(stats);
+        self.persist_shares();
+        Ok(())
+    }
+    
+    /// Unshare every share whose time to live has passed, retracting it from
+    /// peers. Peers partway through one still get the rest of it. Returns
+    /// the hashes unshared.
+    pub async fn reap_expired_shares(&self) -> Vec<String> {
+        let now = Instant::now();
+        let expired: Vec<String> = self
+            .share_stats
+            .iter()
+            .filter(|stats| stats.expires_at.is_some_and(|expires_at| expires_at <= now))
+            .map(|stats| stats.key().clone())
+            .collect();
+        for file_hash in &expired {
+            tracing::info!("Share {} expired", file_hash);
+            self.expired_shares.insert(file_hash.clone());
+            if let Err(e) = self.unshare_file(file_hash, false).await {
+                tracing::debug!("Expired share {} was already gone: {}", file_hash, e);
+            }
+        }
+        expired
+    }
+    
+    pub(super) fn evict_share(&self, file_hash: &str) {
+        self.retiring_shares.remove(file_hash);
+        self.shared_files.remove(file_hash);
+        self.shared_chunks.retain(|_, chunk| chunk.file_hash != file_hash);
+        self.chunk_cache.lock().unwrap().retain(|chunk_hash| self.shared_chunks.contains_key(chunk_hash));
+        self.share_sources.remove(file_hash);
+        self.file_chunks.retain(|_, chunk| chunk.file_hash != file_hash);
+        self.uploads.retain(|(hash, _), _| hash != file_hash);
+        self.device_sends.retain(|(hash, _), _| hash != file_hash);
+        self.persist_shares();
+    }
+    
+    /// Write the files we share from disk to the registry. Folder manifests
+    /// and texts live only in memory and aren't kept.
+    fn persist_shares(&self) {
+        let Some(registry) = &self.share_registry else {
+            return;
+        };
+        let now = Instant::now();
+        let shares = self
+            .share_sources
+            .iter()
+            .filter_map(|sources| {
+                let file = self.shared_files.get(sources.key())?.clone();
+                let expires_at = self.share_stats.get(sources.key())?.expires_at;
+                Some(PersistedShare {
+                    file,
+                    sources: sources.iter().filter_map(|path| ShareSource::stamp(path).ok()).collect(),
+                    expires_at: expires_at.map(|at| Self::now_secs() + at.saturating_duration_since(now).as_secs()),
+                })
+            })
+            .collect();
+        if let Err(e) = registry.save(shares) {
+            tracing::warn!("Couldn't record what's shared: {}", e);
+        }
+    }
+    
+    /// Share again the files the registry recorded, announcing each anew.
+    /// Sources still as they were are taken as they are; ones that changed
+    /// are hashed again, and ones that are gone are dropped. Returns the
+    /// hashes shared.
+    pub async fn restore_shares(&self) -> Vec<String> {
+        let Some(registry) = &self.share_registry else {
+            return Vec::new();
+        };
+        let now = Self::now_secs();
+        let mut restored = Vec::new();
+        for share in registry.load() {
+            let ttl = match share.expires_at {
+                Some(expires_at) if expires_at <= now => continue,
+                expires_at => expires_at.map(|expires_at| Duration::from_secs(expires_at - now)),
+            };
+            let (unchanged, changed): (Vec<ShareSource>, Vec<ShareSource>) = share.sources.into_iter().partition(ShareSource::unchanged);
+            let (peer_id, chunk_size, algorithm) = (share.file.peer_id.clone(), share.file.chunk_size as usize, share.file.hash_algorithm);
+            let mut hashes = Vec::new();
+            match self.reshare(share.file, &unchanged).await {
+                Ok(Some(file_hash)) => hashes.push(file_hash),
+                Ok(None) => {}
+                Err(e) => tracing::warn!("Not sharing {:?} again: {}", unchanged.first().map(|source| &source.path), e),
+            }
+            for source in changed.iter().filter(|source| source.path.is_file()) {
+                tracing::info!("{:?} changed since it was shared, hashing it again", source.path);
+                match self.share_file_with(&source.path, peer_id.clone(), chunk_size, algorithm).await {
+                    Ok(file_hash) => hashes.push(file_hash),
+                    Err(e) => tracing::warn!("Not sharing {:?} again: {}", source.path, e),
+                }
+            }
+            for file_hash in &hashes {
+                let _ = self.set_share_ttl(file_hash, ttl);
+            }
+            restored.extend(hashes);
+        }
+        // Whatever was dropped goes from the record too
+        self.persist_shares();
+        restored.sort();
+        restored.dedup();
+        restored
+    }
+    
+    /// Serve `file` from `sources`, which still hold it, without hashing it
+    /// again; None when there's nothing left to serve it from
+    async fn reshare(&self, file: SharedFile, sources: &[ShareSource]) -> Result<Option<String>, Error> {
+        let mut paths: Vec<Arc<Path>> = Vec::new();
+        for source in sources {
+            match self.check_shareable(&source.path) {
+                Ok(path) => paths.push(path.into()),
+                Err(e) => tracing::info!("Not sharing {:?} again: {}", source.path, e),
+            }
+        }
+        let Some(first) = paths.first().cloned() else {
+            return Ok(None);
+        };
+        let file_hash = file.hash.clone();
+        if self.shared_files.contains_key(&file_hash) {
+            for path in &paths {
+                self.add_share_source(&file_hash, path);
+            }
+            return Ok(Some(file_hash));
+        }
+        
+        for (index, chunk_hash) in file.chunks.iter().enumerate() {
+            self.shared_chunks.insert(chunk_hash.clone(), SharedChunk {
+                path: first.clone(),
+                offset: index as u64 * file.chunk_size,
+                len: chunk_len(&file, index) as usize,
+                index,
+                file_hash: file_hash.clone(),
+            });
+        }
+        self.share_sources.insert(file_hash.clone(), paths);
+        self.publish_share(file).await?;
+        Ok(Some(file_hash))
+    }
+    
+    /// Retractions of files we've unshared, for the network layer to send on
+    pub fn subscribe_retractions(&self) -> broadcast::Receiver<SignedRetraction> {
+        self.retraction_tx.subscribe()
+    }
+    
+    /// Files this machine is offering, by name
+    pub fn list_shared_files(&self) -> Vec<SharedFileSummary> {
+        let mut summaries: Vec<SharedFileSummary> = self
+            .share_stats
+            .iter()
+            .filter_map(|stats| {
+                let file = self.shared_files.get(stats.key())?;
+                let mut peers_requesting: Vec<String> = stats.requesters.iter().cloned().collect();
+                peers_requesting.sort();
+                Some(SharedFileSummary {
+                    hash: file.hash.clone(),
+                    name: file.name.clone(),
+                    size: file.size,
+                    bytes_served: stats.bytes_served,
+                    peers_requesting,
+                    shared_at: file.timestamp,
+                    paths: self
+                        .share_sources
+                        .get(stats.key())
+                        .map(|sources| sources.iter().map(|source| source.to_path_buf()).collect())
+                        .unwrap_or_default(),
+                    expires_in_secs: stats
+                        .expires_at
+                        .map(|expires_at| expires_at.saturating_duration_since(Instant::now()).as_secs()),
+                })
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+        summaries
+    }
+    
+    /// The full record of a file we share or know of from a peer
+    pub fn get_shared_file(&self, file_hash: &str) -> Option<SharedFile> {
+        self.shared_files.get(file_hash).map(|file| file.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{Seek, SeekFrom};
+    use crate::network::counting_alloc;
+    use crate::network::file_transfer::CHUNK_SIZE;
+    use crate::network::file_transfer::tests::{channel_pair, scratch_dir};
+    use crate::network::share_registry::ShareRegistry;
+    use crate::network::transfer_protocol::FileTransferMessage;
+    use crate::security::DeviceIdentity;
+    
+    #[tokio::test]
+    async fn test_unshared_files_are_retracted_once_uploads_finish() {
+        let dir = scratch_dir("retract");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE).map(|i| (i % 199) as u8).collect();
+        std::fs::write(dir.join("notes.txt"), &data).unwrap();
+        let (alice, bob) = (Arc::new(DeviceIdentity::generate()), DeviceIdentity::generate());
+        let sender = FileTransfer::new().await.with_identity(alice.clone());
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = sender.share_file(&dir.join("notes.txt"), "local".to_string()).await.unwrap();
+        let file = sender.shared_files.get(&file_hash).unwrap().clone();
+        let receiver = FileTransfer::new().await;
+        receiver.handle_announcement("10.0.0.3".to_string(), sender.signed_announcement(&file_hash).unwrap()).await.unwrap();
+        
+        // A peer partway through keeps getting chunks; nobody new does
+        let (_, bob_channel) = channel_pair(&alice, &bob).await;
+        sender.set_transfer_key(&file_hash, "10.0.0.2", &bob_channel);
+        sender.handle_chunk_request(&file.chunks[0], "10.0.0.2".to_string()).await.unwrap();
+        let mut retractions = sender.subscribe_retractions();
+        sender.unshare_file(&file_hash, false).await.unwrap();
+        assert!(sender.list_shared_files().is_empty());
+        sender.handle_chunk_request(&file.chunks[1], "10.0.0.4".to_string()).await.unwrap();
+        assert!(!sender.uploads.contains_key(&(file_hash.clone(), "10.0.0.4".to_string())));
+        sender.handle_chunk_request(&file.chunks[1], "10.0.0.2".to_string()).await.unwrap();
+        assert!(sender.shared_chunks.is_empty());
+        assert!(sender.shared_files.get(&file_hash).is_none());
+        
+        // Only the announcing device can take the listing down
+        let retraction = retractions.try_recv().unwrap();
+        let forged = SignedRetraction::sign(&file_hash, &bob);
+        assert!(receiver.handle_retraction("10.0.0.3".to_string(), forged).await.is_err());
+        assert_eq!(receiver.get_remote_files("10.0.0.3").len(), 1);
+        receiver.handle_retraction("10.0.0.3".to_string(), retraction).await.unwrap();
+        assert!(receiver.get_remote_files("10.0.0.3").is_empty());
+        let err = receiver.download_file(&file_hash, &dir.join("downloads").join("notes.txt")).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "file_not_found");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_expired_shares_are_retracted() {
+        let dir = scratch_dir("ttl");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..2 * MIN_CHUNK_SIZE + 5).map(|i| (i % 193) as u8).collect();
+        std::fs::write(dir.join("setup.exe"), &data).unwrap();
+        std::fs::write(dir.join("notes.txt"), b"kept for good").unwrap();
+        let sender = FileTransfer::new()
+            .await
+            .with_identity(Arc::new(DeviceIdentity::generate()))
+            .with_chunk_size(MIN_CHUNK_SIZE);
+        let file_hash = sender
+            .share_file_with_ttl(&dir.join("setup.exe"), "local".to_string(), Some(Duration::from_secs(3600)))
+            .await
+            .unwrap();
+        let kept = sender.share_file(&dir.join("notes.txt"), "local".to_string()).await.unwrap();
+        let listed = sender.list_shared_files();
+        assert_eq!(listed.iter().find(|share| share.hash == kept).unwrap().expires_in_secs, None);
+        let remaining = listed.iter().find(|share| share.hash == file_hash).unwrap().expires_in_secs.unwrap();
+        assert!((3590..=3600).contains(&remaining), "{} seconds left", remaining);
+        assert!(sender.reap_expired_shares().await.is_empty());
+        
+        let request = |chunk_index: usize| FileTransferMessage::ChunkRequest { file_hash: file_hash.clone(), chunk_index }.encode().unwrap();
+        let reply = sender.handle_incoming_message("10.0.0.2", request(0)).await.unwrap().unwrap();
+        assert!(matches!(FileTransferMessage::decode(reply).unwrap(), FileTransferMessage::ChunkData { .. }));
+        
+        // Time's up: peers hear of it, but the one partway through finishes
+        let mut retractions = sender.subscribe_retractions();
+        sender.set_share_ttl(&file_hash, Some(Duration::ZERO)).unwrap();
+        assert_eq!(sender.reap_expired_shares().await, vec![file_hash.clone()]);
+        assert_eq!(retractions.try_recv().unwrap().file_hash, file_hash);
+        assert_eq!(sender.list_shared_files().iter().map(|share| &share.hash).collect::<Vec<_>>(), [&kept]);
+        let reply = sender.handle_incoming_message("10.0.0.4", request(1)).await.unwrap().unwrap();
+        match FileTransferMessage::decode(reply).unwrap() {
+            FileTransferMessage::Error { reason, .. } => assert!(reason.contains("expired"), "{}", reason),
+            other => panic!("served an expired share: {:?}", other),
+        }
+        for chunk_index in 1..3 {
+            let reply = sender.handle_incoming_message("10.0.0.2", request(chunk_index)).await.unwrap().unwrap();
+            assert!(matches!(FileTransferMessage::decode(reply).unwrap(), FileTransferMessage::ChunkData { .. }));
+        }
+        assert!(sender.shared_files.get(&file_hash).is_none());
+        assert!(sender.reap_expired_shares().await.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_identical_files_share_one_set_of_chunks() {
+        let dir = scratch_dir("dedupe");
+        std::fs::create_dir_all(&dir).unwrap();
+        let dir = dir.canonicalize().unwrap();
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE).map(|i| (i % 241) as u8).collect();
+        std::fs::write(dir.join("disk.iso"), &data).unwrap();
+        std::fs::write(dir.join("copy.iso"), &data).unwrap();
+        let file_transfer = FileTransfer::new().await;
+        let file_hash = file_transfer.share_file(&dir.join("disk.iso"), "local".to_string()).await.unwrap();
+        let total_chunks = file_transfer.shared_files.get(&file_hash).unwrap().total_chunks;
+        
+        for path in ["disk.iso", "copy.iso"] {
+            assert_eq!(file_transfer.share_file(&dir.join(path), "local".to_string()).await.unwrap(), file_hash);
+        }
+        assert_eq!(file_transfer.shared_chunks.len(), total_chunks);
+        assert!(file_transfer.shared_chunks.iter().all(|chunk| *chunk.path == *dir.join("disk.iso")));
+        let shared = file_transfer.list_shared_files();
+        assert_eq!(shared.len(), 1);
+        assert_eq!(shared[0].paths, vec![dir.join("disk.iso"), dir.join("copy.iso")]);
+        
+        // The chunks outlive the first path, read from the copy instead
+        file_transfer.unshare_path(&dir.join("disk.iso"), false).await.unwrap();
+        std::fs::remove_file(dir.join("disk.iso")).unwrap();
+        assert_eq!(file_transfer.shared_chunks.len(), total_chunks);
+        let first_chunk = file_transfer.shared_files.get(&file_hash).unwrap().chunks[0].clone();
+        let chunk = file_transfer.load_chunk(&first_chunk).await.unwrap().unwrap();
+        assert_eq!(&chunk.data[..], &data[..chunk.data.len()]);
+        
+        file_transfer.unshare_path(&dir.join("copy.iso"), false).await.unwrap();
+        assert!(file_transfer.shared_chunks.is_empty());
+        assert!(file_transfer.list_shared_files().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_sharing_a_large_file_keeps_memory_bounded() {
+        use std::io::Write;
+        const SIZE: u64 = 256 * 1024 * 1024;
+        let dir = scratch_dir("sparse");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("disk.img");
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.set_len(SIZE).unwrap();
+        file.write_all(b"boot sector").unwrap();
+        file.seek(SeekFrom::Start(SIZE - 7)).unwrap();
+        file.write_all(b"trailer").unwrap();
+        drop(file);
+        
+        // The pass share_file runs off the runtime, here on the test thread
+        counting_alloc::start();
+        let indexed = FileTransfer::index_file(std::fs::File::open(&path).unwrap(), CHUNK_SIZE, HashAlgorithm::Blake3, &AtomicBool::new(false)).unwrap();
+        counting_alloc::stop();
+        assert!(counting_alloc::peak() < 2 * CHUNK_SIZE, "peak of {} bytes", counting_alloc::peak());
+        assert_eq!(indexed.chunks.len(), (SIZE as usize).div_ceil(CHUNK_SIZE));
+        
+        // Nothing cached, so the change below is read
+        let file_transfer = FileTransfer::new().await.with_chunk_cache_limit(0);
+        file_transfer.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = file_transfer.share_file(&path, "local".to_string()).await.unwrap();
+        let shared = file_transfer.shared_files.get(&file_hash).unwrap().clone();
+        let last_chunk = shared.chunks.last().unwrap().clone();
+        assert!(file_transfer.file_chunks.is_empty());
+        
+        // Same record the whole file in memory used to produce
+        let data = std::fs::read(&path).unwrap();
+        let chunks: Vec<String> = data
+            .chunks(CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| FileTransfer::calculate_chunk_hash(HashAlgorithm::Blake3, i, chunk))
+            .collect();
+        assert_eq!(file_hash, hex::encode(blake3::hash(&data).as_bytes()));
+        assert_eq!((shared.size, shared.chunks), (SIZE, chunks));
+        assert_eq!(indexed.hash, file_hash);
+        
+        // Chunks come back from disk on request, unless the file changed since
+        let last = file_transfer.load_chunk(&last_chunk).await.unwrap().unwrap();
+        assert_eq!(last.data, &data[data.len() - CHUNK_SIZE..]);
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(SIZE - 7)).unwrap();
+        file.write_all(b"tampered").unwrap();
+        assert!(file_transfer.load_chunk(&last_chunk).await.is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_sharing_stays_inside_roots() {
+        let dir = std::env::temp_dir().join(format!("dsn-roots-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("documents")).unwrap();
+        std::fs::create_dir_all(dir.join("projects")).unwrap();
+        std::fs::write(dir.join("documents/notes.txt"), b"notes").unwrap();
+        std::fs::write(dir.join("projects/plan.txt"), b"plan").unwrap();
+        std::fs::write(dir.join("shadow"), b"secret").unwrap();
+        
+        let file_transfer = FileTransfer::new().await;
+        file_transfer.set_shareable_roots(vec![dir.join("documents")]);
+        let refused = |result: Result<String, Error>| {
+            matches!(result.unwrap_err().downcast_ref(), Some(DeskShareError::PathNotAllowed(_)))
+        };
+        
+        assert!(file_transfer.share_file(&dir.join("documents/notes.txt"), "local".to_string()).await.is_ok());
+        assert!(refused(file_transfer.share_file(&dir.join("shadow"), "local".to_string()).await));
+        assert!(refused(file_transfer.share_file(&dir.join("documents/../shadow"), "local".to_string()).await));
+        let listing = file_transfer.list_files_in_directory(&dir.join("documents/..").to_string_lossy(), false).await;
+        assert!(matches!(listing.unwrap_err().downcast_ref(), Some(DeskShareError::PathNotAllowed(_))));
+        let listing = file_transfer.list_files_in_directory(&dir.join("documents").to_string_lossy(), false).await.unwrap();
+        assert_eq!(listing.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(), ["notes.txt"]);
+        
+        // Links are judged by where they lead
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("shadow"), dir.join("documents/shadow.txt")).unwrap();
+            std::os::unix::fs::symlink(&dir, dir.join("documents/up")).unwrap();
+            assert!(refused(file_transfer.share_file(&dir.join("documents/shadow.txt"), "local".to_string()).await));
+            assert!(refused(file_transfer.share_file(&dir.join("documents/up/projects/plan.txt"), "local".to_string()).await));
+        }
+        
+        // The same file reached through the administrative share
+        #[cfg(windows)]
+        {
+            let local = dir.join("shadow").to_string_lossy().to_string();
+            let unc = format!(r"\\localhost\{}${}", &local[..1], &local[2..]);
+            assert!(file_transfer.share_file(Path::new(&unc), "local".to_string()).await.is_err());
+        }
+        
+        // A root added while running applies to the next request
+        assert!(refused(file_transfer.share_file(&dir.join("projects/plan.txt"), "local".to_string()).await));
+        assert!(file_transfer.add_shareable_root(&dir.join("projects")).unwrap());
+        assert!(!file_transfer.add_shareable_root(&dir.join("projects")).unwrap());
+        assert!(file_transfer.share_file(&dir.join("projects/plan.txt"), "local".to_string()).await.is_ok());
+        assert!(file_transfer.add_shareable_root(&dir.join("missing")).is_err());
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+    
+    #[tokio::test]
+    async fn test_shares_come_back_after_a_restart() {
+        let dir = scratch_dir("share-registry");
+        std::fs::create_dir_all(dir.join("moved")).unwrap();
+        let registry_path = dir.join("state").join("shares.json");
+        for (name, fill) in [("kept.bin", 1u8), ("moved.bin", 2), ("edited.bin", 3), ("lapsed.bin", 4)] {
+            std::fs::write(dir.join(name), vec![fill; 3 * MIN_CHUNK_SIZE + 9]).unwrap();
+        }
+        let before = FileTransfer::new()
+            .await
+            .with_chunk_size(MIN_CHUNK_SIZE)
+            .with_share_registry(Arc::new(ShareRegistry::open(&registry_path)));
+        before.set_shareable_roots(vec![dir.clone()]);
+        let mut hashes = HashMap::new();
+        for name in ["kept.bin", "moved.bin", "edited.bin", "lapsed.bin"] {
+            hashes.insert(name, before.share_file(&dir.join(name), "local".to_string()).await.unwrap());
+        }
+        before.set_share_ttl(&hashes["kept.bin"], Some(Duration::from_secs(3600))).unwrap();
+        before.set_share_ttl(&hashes["lapsed.bin"], Some(Duration::ZERO)).unwrap();
+        drop(before);
+        
+        // While the app was closed
+        std::fs::rename(dir.join("moved.bin"), dir.join("moved").join("elsewhere.bin")).unwrap();
+        std::fs::write(dir.join("edited.bin"), vec![9u8; 2 * MIN_CHUNK_SIZE]).unwrap();
+        
+        let after = FileTransfer::new()
+            .await
+            .with_share_registry(Arc::new(ShareRegistry::open(&registry_path)));
+        after.set_shareable_roots(vec![dir.clone()]);
+        let mut progress = after.subscribe_progress();
+        let restored = after.restore_shares().await;
+        let edited = after.shared_files.iter().find(|file| file.name == "edited.bin").unwrap().hash.clone();
+        let mut expected = vec![hashes["kept.bin"].clone(), edited.clone()];
+        expected.sort();
+        assert_eq!(restored, expected);
+        assert_ne!(edited, hashes["edited.bin"]);
+        assert_eq!(after.shared_files.get(&edited).unwrap().chunk_size, MIN_CHUNK_SIZE as u64);
+        
+        // Served from disk without hashing it again, and announced anew
+        let kept = after.shared_files.get(&hashes["kept.bin"]).unwrap().clone();
+        assert_eq!(after.load_chunk(&kept.chunks[3]).await.unwrap().unwrap().data, vec![1u8; 9]);
+        assert_eq!(after.hash_timings().runs, 1);
+        let mut announced = Vec::new();
+        while let Ok(event) = progress.try_recv() {
+            announced.push(event.file_hash);
+        }
+        assert!(announced.contains(&kept.hash));
+        let expires_in = after.list_shared_files().into_iter().find(|share| share.hash == kept.hash).unwrap().expires_in_secs;
+        assert!(expires_in.is_some_and(|secs| secs > 3500));
+        
+        let recorded: Vec<String> = ShareRegistry::open(&registry_path).load().into_iter().map(|share| share.file.hash).collect();
+        assert_eq!(recorded.len(), 2);
+        assert!(recorded.contains(&kept.hash) && recorded.contains(&edited));
+        
+        // A record that can't be read is started over
+        std::fs::write(&registry_path, b"{\"shares\": [{\"file\": 7").unwrap();
+        let fresh = FileTransfer::new()
+            .await
+            .with_share_registry(Arc::new(ShareRegistry::open(&registry_path)));
+        assert!(fresh.restore_shares().await.is_empty());
+        fresh.share_file(&dir.join("kept.bin"), "local".to_string()).await.unwrap();
+        assert_eq!(ShareRegistry::open(&registry_path).load().len(), 1);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
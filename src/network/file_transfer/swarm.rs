@@ -0,0 +1,541 @@
+use std::collections::HashSet;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::security::ProtocolClass;
+use crate::network::codec::ChunkCodec;
+use crate::network::history::{TransferDirection, TransferRecord};
+use crate::network::merkle::{MerkleProof, MerkleTree};
+use crate::network::progress::ProgressAccumulator;
+
+use super::encryption::SEAL_OVERHEAD;
+use super::{FileChunk, FileTransfer, SharedChunk, TransferProgress, TransferStatus};
+
+/// An upload counts as done once the peer has had every chunk
+pub(super) struct UploadTally {
+    started: Instant,
+    chunks: HashSet<usize>,
+    /// Bytes of distinct chunks sent
+    pub(super) sent: ProgressAccumulator,
+}
+
+/// A file being sent to one peer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UploadProgress {
+    pub peer_id: String,
+    #[serde(flatten)]
+    pub progress: TransferProgress,
+}
+
+impl FileTransfer {
+    /// Proof of chunk `index` of a share with a merkle root, for a peer to
+    /// check that chunk on its own
+    pub fn chunk_proof(&self, file_hash: &str, index: usize) -> Result<MerkleProof, Error> {
+        let file = self
+            .shared_files
+            .get(file_hash)
+            .map(|file| file.clone())
+            .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.to_string()))?;
+        if file.merkle_root.is_none() {
+            return Err(DeskShareError::FileTransferFailed(format!("{} has no merkle root", file_hash)).into());
+        }
+        let proof = MerkleTree::from_chunk_hashes(&file.chunks)?
+            .proof(index)
+            .ok_or_else(|| DeskShareError::ChunkTransferFailed(format!("{} has no chunk {}", file_hash, index)))?;
+        Ok(proof)
+    }
+    
+    pub async fn handle_chunk_request(&self, chunk_hash: &str, from: String) -> Result<(), Error> {
+        if !self.admits(&from, ProtocolClass::ChunkRequest) {
+            return Ok(());
+        }
+        // Read once; the chunk goes out without being copied again
+        if let Some(chunk) = self.take_upload(chunk_hash, &from).await? {
+            self.send_chunk_to_peer(from, chunk).await?;
+        }
+        Ok(())
+    }
+    
+    /// Chunk `index` of a file we share, encoded for `from` to pull
+    pub(super) async fn serve_chunk(&self, file_hash: &str, index: usize, from: &str) -> Result<Bytes, Error> {
+        if !self.admits(from, ProtocolClass::ChunkRequest) {
+            return Err(DeskShareError::ChunkTransferFailed("too many requests".to_string()).into());
+        }
+        // Only peers it was already being sent to get the rest of an expired share
+        let finishing = self.retiring_shares.get(file_hash).is_some_and(|finishing| finishing.contains(from));
+        if self.expired_shares.contains(file_hash) && !finishing {
+            return Err(DeskShareError::ShareExpired(file_hash.to_string()).into());
+        }
+        let chunk_hash = self
+            .shared_files
+            .get(file_hash)
+            .and_then(|file| file.chunks.get(index).cloned())
+            .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.to_string()))?;
+        let chunk = self
+            .take_upload(&chunk_hash, from)
+            .await?
+            .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.to_string()))?;
+        let chunk = self.encode_chunk(chunk).await?;
+        self.upload_throttle.acquire(chunk.data.len() as u64).await;
+        self.record_peer(from, |stats| stats.record_sent(chunk.data.len() as u64));
+        Ok(chunk.data)
+    }
+    
+    /// A chunk we hold that `from` may have, counted towards its upload
+    async fn take_upload(&self, chunk_hash: &str, from: &str) -> Result<Option<FileChunk>, Error> {
+        let Some(chunk) = self.load_chunk(chunk_hash).await? else {
+            return Ok(None);
+        };
+        let retired = self
+            .retiring_shares
+            .get(&chunk.file_hash)
+            .is_some_and(|finishing| !finishing.contains(from));
+        if retired {
+            tracing::debug!("{} asked for {} after it was unshared", from, chunk.file_hash);
+            return Ok(None);
+        }
+        if let Some(mut stats) = self.share_stats.get_mut(&chunk.file_hash) {
+            stats.bytes_served += chunk.data.len() as u64;
+            stats.requesters.insert(from.to_string());
+        }
+        self.tally_upload(&chunk, from);
+        Ok(Some(chunk))
+    }
+    
+    /// A chunk we hold: a download's from memory, a shared file's from the
+    /// cache or else from disk
+    pub(super) async fn load_chunk(&self, chunk_hash: &str) -> Result<Option<FileChunk>, Error> {
+        if let Some(chunk) = self.file_chunks.get(chunk_hash) {
+            return Ok(Some(chunk.value().clone()));
+        }
+        let Some(shared) = self.shared_chunks.get(chunk_hash).map(|chunk| chunk.value().clone()) else {
+            return Ok(None);
+        };
+        if let Some(data) = self.chunk_cache.lock().unwrap().get(chunk_hash) {
+            return Ok(Some(FileChunk {
+                chunk_hash: chunk_hash.to_string(),
+                data,
+                index: shared.index,
+                file_hash: shared.file_hash,
+            }));
+        }
+        
+        // Room to seal the chunk without moving it
+        let mut data = Vec::with_capacity(shared.len + SEAL_OVERHEAD);
+        data.resize(shared.len, 0);
+        let path = shared.path.clone();
+        let data = tokio::task::spawn_blocking(move || -> std::io::Result<Vec<u8>> {
+            let mut file = std::fs::File::open(&path)?;
+            file.seek(SeekFrom::Start(shared.offset))?;
+            file.read_exact(&mut data)?;
+            Ok(data)
+        })
+        .await
+        .map_err(|e| DeskShareError::Internal(e.to_string()))?
+        .map_err(|e| match e.kind() {
+            // Deleted since it was shared
+            std::io::ErrorKind::NotFound => DeskShareError::FileNotFound(shared.path.display().to_string()),
+            _ => DeskShareError::FileReadError(e),
+        })?;
+        // Peers would reject it anyway, but don't send a chunk that's changed
+        if Self::calculate_chunk_hash(self.algorithm_of(&shared.file_hash), shared.index, &data) != chunk_hash {
+            return Err(DeskShareError::FileTransferFailed(format!("{} changed since it was shared", shared.path.display())).into());
+        }
+        let data = Bytes::from(data);
+        self.chunk_cache.lock().unwrap().insert(chunk_hash, data.clone());
+        
+        Ok(Some(FileChunk {
+            chunk_hash: chunk_hash.to_string(),
+            data,
+            index: shared.index,
+            file_hash: shared.file_hash,
+        }))
+    }
+    
+    async fn send_chunk_to_peer(&self, peer_id: String, chunk: FileChunk) -> Result<(), Error> {
+        let chunk = self.encode_chunk(chunk).await?;
+        self.upload_throttle.acquire(chunk.data.len() as u64).await;
+        let tagged = self.tag_chunk(&peer_id, &chunk.file_hash, chunk.index, chunk.data)?;
+        let Some(transport) = &self.chunk_transport else {
+            tracing::trace!("Chunk {} of {} ready for {}", tagged.index, tagged.file_hash, peer_id);
+            return Ok(());
+        };
+        let sent = tagged.data.len() as u64;
+        transport.send_chunk(&peer_id, tagged.into_envelope()?).await?;
+        self.record_peer(&peer_id, |stats| stats.record_sent(sent));
+        Ok(())
+    }
+    
+    /// A chunk as it goes on the wire, compressed if its share says so
+    pub(super) async fn encode_chunk(&self, mut chunk: FileChunk) -> Result<FileChunk, Error> {
+        let codec = self.shared_files.get(&chunk.file_hash).map(|file| file.codec).unwrap_or_default();
+        if codec != ChunkCodec::Raw {
+            let data = chunk.data.clone();
+            chunk.data = tokio::task::spawn_blocking(move || codec.encode(data))
+                .await
+                .map_err(|e| DeskShareError::Internal(e.to_string()))??;
+        }
+        Ok(chunk)
+    }
+    
+    /// Let other peers fetch a chunk we've verified and written, from the
+    /// output file, while our own download carries on
+    pub(super) fn serve_downloaded(&self, file_hash: &str, chunk_hash: &str, index: usize, chunk_size: u64, len: u64, output_path: &Path) {
+        // A copy we share ourselves is served from there instead
+        self.shared_chunks.entry(chunk_hash.to_string()).or_insert_with(|| SharedChunk {
+            path: Arc::from(output_path),
+            offset: index as u64 * chunk_size,
+            len: len as usize,
+            index,
+            file_hash: file_hash.to_string(),
+        });
+    }
+    
+    /// Stop serving the chunks of a download whose output is going away
+    pub(super) fn stop_serving_download(&self, file_hash: &str, output_path: &Path) {
+        self.shared_chunks.retain(|_, chunk| chunk.file_hash != file_hash || &*chunk.path != output_path);
+        self.chunk_cache.lock().unwrap().retain(|chunk_hash| self.shared_chunks.contains_key(chunk_hash));
+    }
+    
+    /// Note a chunk served to `peer_id`, recording the upload once they've had them all
+    fn tally_upload(&self, chunk: &FileChunk, peer_id: &str) {
+        let Some(file) = self.shared_files.get(&chunk.file_hash).map(|file| file.clone()) else {
+            return;
+        };
+        let key = (chunk.file_hash.clone(), peer_id.to_string());
+        let done = {
+            let mut tally = self.uploads.entry(key.clone()).or_insert_with(|| UploadTally {
+                started: Instant::now(),
+                chunks: HashSet::new(),
+                sent: ProgressAccumulator::new(),
+            });
+            if tally.chunks.insert(chunk.index) {
+                tally.sent.add(chunk.data.len() as u64);
+            }
+            tally.chunks.len() == file.total_chunks
+        };
+        let Some((_, tally)) = done.then(|| self.uploads.remove(&key)).flatten() else {
+            return;
+        };
+        let last = self
+            .retiring_shares
+            .get_mut(&file.hash)
+            .is_some_and(|mut finishing| {
+                finishing.remove(peer_id);
+                finishing.is_empty()
+            });
+        if last {
+            self.evict_share(&file.hash);
+        }
+        self.record_transfer(TransferRecord {
+            file_name: file.name,
+            file_hash: file.hash,
+            size: file.size,
+            peer_id: peer_id.to_string(),
+            direction: TransferDirection::Upload,
+            status: TransferStatus::Completed,
+            duration_ms: tally.started.elapsed().as_millis() as u64,
+            timestamp: Self::now_secs(),
+        });
+    }
+    
+    /// Files being sent to peers, with their speed over the last few seconds
+    pub fn get_upload_progress(&self) -> Vec<UploadProgress> {
+        let mut uploads: Vec<UploadProgress> = self
+            .uploads
+            .iter()
+            .filter_map(|upload| {
+                let (file_hash, peer_id) = upload.key();
+                let file = self.shared_files.get(file_hash)?;
+                let mut progress = TransferProgress {
+                    file_name: file.name.clone(),
+                    file_hash: file_hash.clone(),
+                    bytes_transferred: 0,
+                    total_bytes: file.size,
+                    percentage: 0.0,
+                    status: TransferStatus::InProgress,
+                    bytes_per_second: 0.0,
+                    eta_seconds: None,
+                    output_path: None,
+                    verified_hash: None,
+                    skipped: false,
+                    error_message: None,
+                    error_kind: None,
+                };
+                Self::apply_flush(&mut progress, &upload.sent.flush());
+                Some(UploadProgress {
+                    peer_id: peer_id.clone(),
+                    progress,
+                })
+            })
+            .collect();
+        uploads.sort_by(|a, b| (&a.progress.file_name, &a.peer_id).cmp(&(&b.progress.file_name, &b.peer_id)));
+        uploads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use dashmap::DashMap;
+    use crate::network::counting_alloc;
+    use crate::network::chunk_pipeline::{ChunkTransport, MemoryChunkTransport};
+    use crate::network::file_transfer::{ChunkEnvelope, SignedAnnouncement, TaggedChunk, CHUNK_SIZE, MIN_CHUNK_SIZE};
+    use crate::network::file_transfer::tests::{channel_pair, describe, fixed_window, scratch_dir, RecordingPeers};
+    use crate::network::transfer_protocol::FileTransferMessage;
+    use crate::security::DeviceIdentity;
+    
+    #[tokio::test]
+    async fn test_uploads_report_speed_per_peer() {
+        let dir = scratch_dir("upload-speed");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.join("slides.pdf"), &data).unwrap();
+        let sender = FileTransfer::new().await;
+        sender.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = sender.share_file(&dir.join("slides.pdf"), "local".to_string()).await.unwrap();
+        let file = sender.shared_files.get(&file_hash).unwrap().clone();
+        let (alice, bob) = (DeviceIdentity::generate(), DeviceIdentity::generate());
+        let (_, bob_channel) = channel_pair(&alice, &bob).await;
+        sender.set_transfer_key(&file_hash, "10.0.0.2", &bob_channel);
+        
+        // A chunk sent twice counts once
+        for _ in 0..2 {
+            sender.handle_chunk_request(&file.chunks[0], "10.0.0.2".to_string()).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let uploads = sender.get_upload_progress();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].peer_id, "10.0.0.2");
+        assert_eq!((uploads[0].progress.bytes_transferred, uploads[0].progress.percentage), (CHUNK_SIZE as u64, 50.0));
+        assert!(uploads[0].progress.bytes_per_second > 0.0);
+        assert!(uploads[0].progress.eta_seconds.is_some());
+        let json = serde_json::to_value(&uploads[0]).unwrap();
+        assert_eq!(json["peer_id"], "10.0.0.2");
+        assert!(json["bytes_per_second"].is_number());
+        
+        sender.handle_chunk_request(&file.chunks[1], "10.0.0.2".to_string()).await.unwrap();
+        assert!(sender.get_upload_progress().is_empty());
+        
+        // Before a download's first chunk there's no speed and no estimate
+        let receiver = FileTransfer::new().await;
+        receiver.begin_download(file.clone(), &dir.join("downloads").join("slides.pdf")).await.unwrap();
+        let json = serde_json::to_value(&receiver.get_transfer_progress().await[0]).unwrap();
+        assert_eq!((json["bytes_per_second"].as_f64(), &json["eta_seconds"]), (Some(0.0), &serde_json::Value::Null));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_serving_a_chunk_copies_no_data() {
+        let (alice, bob) = (DeviceIdentity::generate(), DeviceIdentity::generate());
+        let (transport, mut sent) = MemoryChunkTransport::new();
+        let file_transfer = FileTransfer::new().await.with_chunk_transport(Arc::new(transport));
+        let dir = scratch_dir("zero-copy");
+        std::fs::create_dir_all(&dir).unwrap();
+        // Incompressible, so it goes out raw
+        let mut data = vec![0u8; 3 * CHUNK_SIZE];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut data);
+        std::fs::write(dir.join("payload.bin"), &data).unwrap();
+        file_transfer.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = file_transfer.share_file(&dir.join("payload.bin"), "local".to_string()).await.unwrap();
+        let (_, bob_channel) = channel_pair(&alice, &bob).await;
+        file_transfer.set_transfer_key(&file_hash, "10.0.0.2", &bob_channel);
+        
+        let chunk_hash = file_transfer.shared_files.get(&file_hash).unwrap().chunks[1].clone();
+        // The first request sets up the per-requester stats
+        file_transfer.handle_chunk_request(&chunk_hash, "10.0.0.2".to_string()).await.unwrap();
+        sent.recv().await.unwrap();
+        
+        counting_alloc::start();
+        file_transfer.handle_chunk_request(&chunk_hash, "10.0.0.2".to_string()).await.unwrap();
+        let allocated = counting_alloc::stop();
+        // The one buffer sealed, copied out of the cache, and nothing more
+        assert!(allocated >= CHUNK_SIZE);
+        assert!(allocated < CHUNK_SIZE + CHUNK_SIZE / 64, "serving a chunk allocated {} bytes", allocated);
+        
+        let (peer_id, envelope) = sent.recv().await.unwrap();
+        assert_eq!(peer_id, "10.0.0.2");
+        
+        let mut wire = Vec::new();
+        envelope.write_to(&mut wire).await.unwrap();
+        let chunk = TaggedChunk::from_envelope(ChunkEnvelope::read_from(&mut wire.as_slice()).await.unwrap()).unwrap();
+        assert_eq!((chunk.file_hash.as_str(), chunk.index), (file_hash.as_str(), 1));
+        assert_ne!(&chunk.data[..CHUNK_SIZE], &data[CHUNK_SIZE..2 * CHUNK_SIZE]);
+        let key = file_transfer.transfer_key(&file_hash, "10.0.0.2").unwrap();
+        let opened = FileTransfer::open_chunk(&key, &file_hash, 1, chunk.data).unwrap();
+        assert_eq!(opened, &data[CHUNK_SIZE..2 * CHUNK_SIZE]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_chunk_cache_stays_under_its_cap() {
+        let dir = scratch_dir("chunk-cache");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_transfer = FileTransfer::new()
+            .await
+            .with_chunk_size(MIN_CHUNK_SIZE)
+            .with_chunk_cache_limit(4 * MIN_CHUNK_SIZE as u64);
+        file_transfer.set_shareable_roots(vec![dir.clone()]);
+        
+        // Three times what the cache holds, incompressible so it's served raw
+        let mut shares = Vec::new();
+        for name in ["one.bin", "two.bin", "three.bin"] {
+            let mut data = vec![0u8; 4 * MIN_CHUNK_SIZE];
+            rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut data);
+            std::fs::write(dir.join(name), &data).unwrap();
+            let file_hash = file_transfer.share_file(&dir.join(name), "local".to_string()).await.unwrap();
+            shares.push((file_hash, data));
+        }
+        for _ in 0..2 {
+            for (file_hash, data) in &shares {
+                for (index, expected) in data.chunks(MIN_CHUNK_SIZE).enumerate() {
+                    let served = file_transfer.serve_chunk(file_hash, index, "10.0.0.2").await.unwrap();
+                    assert_eq!(served, expected);
+                    assert!(file_transfer.chunk_cache_usage().bytes <= 4 * MIN_CHUNK_SIZE as u64);
+                }
+            }
+        }
+        let usage = file_transfer.chunk_cache_usage();
+        assert_eq!((usage.chunks, usage.peak_bytes), (4, 4 * MIN_CHUNK_SIZE as u64));
+        assert!(usage.evicted_chunks >= 20);
+        assert_eq!(file_transfer.shared_chunks.len(), 12);
+        
+        // An evicted chunk whose file has gone fails the request, and no more
+        std::fs::remove_file(dir.join("one.bin")).unwrap();
+        let err = file_transfer.serve_chunk(&shares[0].0, 0, "10.0.0.2").await.unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(DeskShareError::FileNotFound(_))));
+        assert!(file_transfer.serve_chunk(&shares[2].0, 3, "10.0.0.2").await.is_ok());
+        
+        file_transfer.unshare_file(&shares[2].0, true).await.unwrap();
+        assert_eq!(file_transfer.chunk_cache_usage().bytes, 0);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    #[tokio::test]
+    async fn test_announcing_peers_share_the_download() {
+        let data: Vec<u8> = (0..16 * 32).map(|i| (i * 3) as u8).collect();
+        let file = describe(&data, 32);
+        let transport = Arc::new(RecordingPeers {
+            chunks: data.chunks(32).map(<[u8]>::to_vec).collect(),
+            fetches: Mutex::new(Vec::new()),
+        });
+        let file_transfer = FileTransfer::new()
+            .await
+            .with_chunk_transport(transport.clone())
+            .with_chunk_windows(fixed_window(2));
+        for (peer, identity) in [("10.0.0.3", DeviceIdentity::generate()), ("10.0.0.4", DeviceIdentity::generate())] {
+            let announcement = SignedAnnouncement::sign(file.clone(), &identity).unwrap();
+            file_transfer.handle_announcement(peer.to_string(), announcement).await.unwrap();
+        }
+        assert_eq!(file_transfer.get_peers_for_file(&file.hash).await, vec!["10.0.0.3", "10.0.0.4"]);
+        
+        let dir = scratch_dir("two-sources");
+        file_transfer.download_file(&file.hash, &dir.join("report.pdf")).await.unwrap();
+        assert_eq!(std::fs::read(dir.join("report.pdf")).unwrap(), data);
+        let fetches = transport.fetches.lock().unwrap().clone();
+        for peer in ["10.0.0.3", "10.0.0.4"] {
+            assert!(fetches.iter().filter(|(asked, _)| asked == peer).count() >= 4, "{} barely asked: {:?}", peer, fetches);
+        }
+        
+        // Sources go with their device, and the file with the last of them
+        file_transfer.forget_remote_peer("10.0.0.3").await;
+        assert_eq!(file_transfer.get_peers_for_file(&file.hash).await, vec!["10.0.0.4"]);
+        file_transfer.forget_remote_peer("10.0.0.4").await;
+        assert!(file_transfer.get_peers_for_file(&file.hash).await.is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+    
+    /// Routes chunk requests to other instances as wire messages, each peer
+    /// answering after its own delay
+    struct Mesh {
+        local: String,
+        nodes: Arc<DashMap<String, Arc<FileTransfer>>>,
+        delays: HashMap<String, Duration>,
+        fetches: Arc<Mutex<Vec<(String, String, usize)>>>,
+    }
+    
+    #[async_trait::async_trait]
+    impl ChunkTransport for Mesh {
+        async fn fetch_chunk(&self, peer_id: &str, file_hash: &str, index: usize) -> Result<Bytes, Error> {
+            tokio::time::sleep(self.delays.get(peer_id).copied().unwrap_or_default()).await;
+            let node = self.nodes.get(peer_id).map(|node| node.clone()).ok_or_else(|| DeskShareError::PeerNotFound(peer_id.to_string()))?;
+            self.fetches.lock().unwrap().push((self.local.clone(), peer_id.to_string(), index));
+            let request = FileTransferMessage::ChunkRequest { file_hash: file_hash.to_string(), chunk_index: index };
+            let reply = node.handle_incoming_message(&self.local, request.encode()?).await?.ok_or(DeskShareError::InvalidMessageFormat)?;
+            match FileTransferMessage::decode(reply)? {
+                FileTransferMessage::ChunkData { data, .. } => Ok(data),
+                FileTransferMessage::Error { reason, .. } => Err(DeskShareError::ChunkTransferFailed(reason).into()),
+                _ => Err(DeskShareError::InvalidMessageFormat.into()),
+            }
+        }
+        
+        async fn send_chunk(&self, _peer_id: &str, _envelope: ChunkEnvelope) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_downloading_peers_pass_chunks_on() {
+        let dir = scratch_dir("swarm");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..48 * MIN_CHUNK_SIZE).map(|i| ((i % 251) ^ (i / 4093)) as u8).collect();
+        std::fs::write(dir.join("video.mp4"), &data).unwrap();
+        
+        // The source is slow; the other downloader is close by
+        let nodes = Arc::new(DashMap::new());
+        let fetches = Arc::new(Mutex::new(Vec::new()));
+        let delays = HashMap::from([("10.0.0.1".to_string(), Duration::from_millis(25)), ("10.0.0.2".to_string(), Duration::from_millis(1))]);
+        for (peer, window) in [("10.0.0.1", 1), ("10.0.0.2", 1), ("10.0.0.3", 4)] {
+            let mesh = Mesh { local: peer.to_string(), nodes: nodes.clone(), delays: delays.clone(), fetches: fetches.clone() };
+            let node = FileTransfer::new()
+                .await
+                .with_identity(Arc::new(DeviceIdentity::generate()))
+                .with_chunk_size(MIN_CHUNK_SIZE)
+                .with_chunk_transport(Arc::new(mesh))
+                .with_chunk_windows(fixed_window(window));
+            nodes.insert(peer.to_string(), Arc::new(node));
+        }
+        let node = |peer: &str| nodes.get(peer).unwrap().clone();
+        let (a, b, c) = (node("10.0.0.1"), node("10.0.0.2"), node("10.0.0.3"));
+        a.set_shareable_roots(vec![dir.clone()]);
+        let file_hash = a.share_file(&dir.join("video.mp4"), "10.0.0.1".to_string()).await.unwrap();
+        let whole = a.signed_announcement(&file_hash).unwrap();
+        assert_eq!(whole.held_chunks, None);
+        b.handle_announcement("10.0.0.1".to_string(), whole.clone()).await.unwrap();
+        c.handle_announcement("10.0.0.1".to_string(), whole).await.unwrap();
+        
+        let first = {
+            let (b, file_hash, output) = (b.clone(), file_hash.clone(), dir.join("b").join("video.mp4"));
+            tokio::spawn(async move { b.download_file(&file_hash, &output).await })
+        };
+        let partial = loop {
+            let announcement = b.signed_announcement(&file_hash).unwrap();
+            if announcement.held_chunks.as_ref().is_some_and(|held| held.len() >= 16) {
+                break announcement;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        };
+        assert!(partial.held_chunks.as_ref().unwrap().len() < 48);
+        c.handle_announcement("10.0.0.2".to_string(), partial).await.unwrap();
+        c.download_file(&file_hash, &dir.join("c").join("video.mp4")).await.unwrap();
+        
+        // Done with help from a peer that's still downloading itself
+        assert_eq!(std::fs::read(dir.join("c").join("video.mp4")).unwrap(), data);
+        assert!(!first.is_finished());
+        let fetches = fetches.lock().unwrap().clone();
+        assert!(fetches.iter().any(|(from, to, _)| from == "10.0.0.3" && to == "10.0.0.2"), "{:?}", fetches);
+        first.await.unwrap().unwrap();
+        assert_eq!(std::fs::read(dir.join("b").join("video.mp4")).unwrap(), data);
+        assert_eq!(b.signed_announcement(&file_hash).unwrap().held_chunks, None);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,173 @@
+use std::sync::atomic::AtomicBool;
+use tokio::sync::broadcast;
+use serde::{Serialize, Deserialize};
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::network::codec::ChunkCodec;
+
+use super::{FileTransfer, ShareKind, SharedFile, CHUNK_SIZE, MANIFEST_VERSION_FLAT};
+
+/// A short text a peer shared, delivered whole instead of saved to a file
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReceivedText {
+    pub file_hash: String,
+    pub title: String,
+    pub content: String,
+    /// One of the peers it was fetched from
+    pub from_peer: Option<String>,
+    pub received_at: u64,
+}
+
+impl FileTransfer {
+    /// Offer a snippet of text, titled `title`, without saving it anywhere.
+    /// Receivers get short ones as text rather than as a file.
+    pub async fn share_text(&self, content: String, title: String, peer_id: String) -> Result<String, Error> {
+        if content.is_empty() {
+            return Err(DeskShareError::FileTransferFailed("there's no text to share".to_string()).into());
+        }
+        let data = content.into_bytes();
+        let (hash, chunk_hashes) = self.hold_in_memory(&data, self.chunk_size)?;
+        if self.share_stats.contains_key(&hash) {
+            return Ok(hash);
+        }
+        
+        self.publish_share(SharedFile {
+            hash: hash.clone(),
+            name: title,
+            size: data.len() as u64,
+            total_chunks: chunk_hashes.len(),
+            chunks: chunk_hashes,
+            chunk_size: self.chunk_size as u64,
+            peer_id,
+            timestamp: Self::now_secs(),
+            kind: ShareKind::Text,
+            codec: ChunkCodec::choose(&data[..data.len().min(self.chunk_size)]),
+            encrypted: true,
+            manifest_version: MANIFEST_VERSION_FLAT,
+            merkle_root: None,
+            hash_algorithm: self.hash_algorithm,
+        })
+        .await?;
+        
+        Ok(hash)
+    }
+    
+    /// Put a short text together from its chunks and hand it over
+    pub(super) async fn open_text(&self, file_hash: &str) -> Result<(), DeskShareError> {
+        let Some(file) = self.shared_files.get(file_hash).map(|file| file.clone()) else {
+            return Err(DeskShareError::ShareNotFound(file_hash.to_string()));
+        };
+        let mut data = Vec::with_capacity(file.size as usize);
+        for chunk_hash in &file.chunks {
+            let chunk = self
+                .file_chunks
+                .get(chunk_hash)
+                .map(|chunk| chunk.data.clone())
+                .ok_or_else(|| DeskShareError::ChunkTransferFailed(format!("missing chunk {}", chunk_hash)))?;
+            data.extend_from_slice(&chunk);
+        }
+        let whole = Self::hash_chunks(data.as_slice(), CHUNK_SIZE, file.hash_algorithm, &AtomicBool::new(false), |_, _| {});
+        if whole.ok().as_deref() != Some(file_hash) {
+            return Err(DeskShareError::IntegrityCheckFailed);
+        }
+        // Characters can straddle chunks, so only the whole is decoded
+        let content = String::from_utf8(data)
+            .map_err(|_| DeskShareError::ChunkTransferFailed(format!("{} isn't UTF-8 text", file.name)))?;
+        let from_peer = self
+            .peers_with_files
+            .read()
+            .await
+            .get(file_hash)
+            .and_then(|holders| holders.iter().min().cloned());
+        let text = ReceivedText {
+            file_hash: file_hash.to_string(),
+            title: file.name,
+            content,
+            from_peer,
+            received_at: Self::now_secs(),
+        };
+        self.received_texts.insert(file_hash.to_string(), text.clone());
+        let _ = self.text_tx.send(text);
+        Ok(())
+    }
+    
+    /// Short texts received so far, oldest first
+    pub fn received_texts(&self) -> Vec<ReceivedText> {
+        let mut texts: Vec<ReceivedText> = self.received_texts.iter().map(|text| text.value().clone()).collect();
+        texts.sort_by(|a, b| a.received_at.cmp(&b.received_at).then_with(|| a.title.cmp(&b.title)));
+        texts
+    }
+    
+    /// Forget a received text once the user is done with it
+    pub fn dismiss_text(&self, file_hash: &str) -> Result<(), Error> {
+        self.received_texts
+            .remove(file_hash)
+            .map(|_| ())
+            .ok_or_else(|| DeskShareError::ShareNotFound(file_hash.to_string()).into())
+    }
+    
+    /// Short texts as they arrive
+    pub fn subscribe_texts(&self) -> broadcast::Receiver<ReceivedText> {
+        self.text_tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::file_transfer::{TransferStatus, MIN_CHUNK_SIZE, TEXT_INLINE_LIMIT};
+    use crate::network::file_transfer::tests::scratch_dir;
+    use crate::network::transfer_protocol::FileTransferMessage;
+    
+    #[tokio::test]
+    async fn test_shared_text_arrives_as_text() {
+        let dir = scratch_dir("text");
+        let sender = FileTransfer::new().await.with_chunk_size(MIN_CHUNK_SIZE);
+        let receiver = FileTransfer::new().await;
+        let mut texts = receiver.subscribe_texts();
+        // Two- to four-byte characters, some split across chunks
+        let content = format!("x{}", "Grüße, 東京 🎉 ".repeat(3 * MIN_CHUNK_SIZE / 20));
+        assert!(!content.is_char_boundary(MIN_CHUNK_SIZE));
+        
+        let send = |content: String, title: &str| {
+            let (sender, receiver, dir, title) = (&sender, &receiver, &dir, title.to_string());
+            async move {
+                let file_hash = sender.share_text(content, title, "local".to_string()).await.unwrap();
+                let file = sender.shared_files.get(&file_hash).unwrap().clone();
+                assert_eq!(file.kind, ShareKind::Text);
+                let offer = FileTransferMessage::Offer { sender_name: "Alice".to_string(), file: file.clone() };
+                receiver.handle_incoming_message("10.0.0.3", offer.encode().unwrap()).await.unwrap();
+                let offer_id = receiver.get_pending_offers()[0].offer_id.clone();
+                receiver.accept_offer(&offer_id, dir).await.unwrap();
+                for chunk_index in 0..file.total_chunks {
+                    let request = FileTransferMessage::ChunkRequest { file_hash: file_hash.clone(), chunk_index };
+                    let reply = sender.handle_incoming_message("10.0.0.2", request.encode().unwrap()).await.unwrap().unwrap();
+                    receiver.handle_incoming_message("10.0.0.3", reply).await.unwrap();
+                }
+                file
+            }
+        };
+        
+        let file = send(content.clone(), "Snippet").await;
+        assert!(file.total_chunks >= 3);
+        let received = receiver.received_texts();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].content, content);
+        assert_eq!(received[0].title, "Snippet");
+        assert_eq!(texts.try_recv().unwrap(), received[0]);
+        // Nothing was written for it
+        assert!(!dir.join("Snippet").exists());
+        let progress = receiver.get_transfer_progress().await;
+        assert!(progress.iter().any(|p| p.file_hash == file.hash && p.status == TransferStatus::Completed && p.output_path.is_none()));
+        receiver.dismiss_text(&file.hash).unwrap();
+        assert!(receiver.received_texts().is_empty());
+        
+        // Longer ones are saved like any file
+        let long = "é".repeat(TEXT_INLINE_LIMIT as usize / 2 + 1);
+        send(long.clone(), "Long.txt").await;
+        assert_eq!(std::fs::read_to_string(dir.join("Long.txt")).unwrap(), long);
+        assert!(receiver.received_texts().is_empty());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::{HashMap, VecDeque};
+use serde::{Serialize, Deserialize};
+
+use super::screen_share::{Frame, SessionId};
+
+/// How much frame data screen sharing may keep in memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameBufferConfig {
+    /// Bytes buffered across every hosted and viewed session
+    pub limit_bytes: u64,
+    /// Frames kept per session, the latest included
+    pub frames_per_session: usize,
+}
+
+impl Default for FrameBufferConfig {
+    fn default() -> Self {
+        Self {
+            limit_bytes: 64 * 1024 * 1024,
+            frames_per_session: 4,
+        }
+    }
+}
+
+/// Snapshot of what the frame buffer holds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BufferUsage {
+    pub bytes: u64,
+    pub frames: usize,
+    pub sessions: usize,
+    pub limit_bytes: u64,
+    /// Most bytes ever held at once
+    pub peak_bytes: u64,
+    pub evicted_frames: u64,
+}
+
+/// Byte accounting shared by every session's buffer
+#[derive(Debug)]
+pub struct BufferBudget {
+    limit: u64,
+    used: u64,
+    peak: u64,
+    evicted: u64,
+}
+
+impl BufferBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: 0,
+            peak: 0,
+            evicted: 0,
+        }
+    }
+    
+    /// Whether `bytes` more would still fit
+    pub fn fits(&self, bytes: u64) -> bool {
+        self.used + bytes <= self.limit
+    }
+    
+    pub fn charge(&mut self, bytes: u64) {
+        self.used += bytes;
+        self.peak = self.peak.max(self.used);
+    }
+    
+    pub fn release(&mut self, bytes: u64) {
+        self.used = self.used.saturating_sub(bytes);
+    }
+}
+
+struct SessionFrames {
+    frames: VecDeque<Frame>,
+    /// Tick of the last insert, for least-recently-used eviction
+    touched: u64,
+}
+
+/// Recent frames per session, kept under one byte budget. When a new frame
+/// doesn't fit, superseded frames go first, oldest session first, and then
+/// the buffers of whichever sessions have gone longest without a frame.
+pub struct FrameBuffer {
+    sessions: HashMap<SessionId, SessionFrames>,
+    budget: BufferBudget,
+    frames_per_session: usize,
+    tick: u64,
+}
+
+impl FrameBuffer {
+    pub fn new(config: FrameBufferConfig) -> Self {
+        Self {
+            sessions: HashMap::new(),
+            budget: BufferBudget::new(config.limit_bytes),
+            frames_per_session: config.frames_per_session.max(1),
+            tick: 0,
+        }
+    }
+    
+    /// Buffer a frame under the session named in its header
+    pub fn insert(&mut self, frame: Frame) {
+        let session_id = frame.header.session_id.clone();
+        let bytes = frame.data.len() as u64;
+        if bytes > self.budget.limit {
+            tracing::warn!("Frame for {} is larger than the whole buffer, not keeping it", session_id);
+            self.remove_session(session_id.as_str());
+            return;
+        }
+        
+        // Make room first, so the budget holds even for a moment
+        self.trim(session_id.as_str(), self.frames_per_session - 1);
+        while !self.budget.fits(bytes) && self.evict_one(session_id.as_str()) {}
+        if !self.budget.fits(bytes) {
+            self.trim(session_id.as_str(), 0);
+        }
+        
+        self.tick += 1;
+        let session = self.sessions.entry(session_id).or_insert_with(|| SessionFrames {
+            frames: VecDeque::new(),
+            touched: 0,
+        });
+        session.frames.push_back(frame);
+        session.touched = self.tick;
+        self.budget.charge(bytes);
+    }
+    
+    pub fn latest(&self, session_id: &str) -> Option<&Frame> {
+        self.sessions.get(session_id).and_then(|session| session.frames.back())
+    }
+    
+    /// Buffered frames for a session, oldest first
+    pub fn recent(&self, session_id: &str) -> Vec<Frame> {
+        self.sessions
+            .get(session_id)
+            .map(|session| session.frames.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    
+    /// Drop everything held for a session; returns the bytes released
+    pub fn remove_session(&mut self, session_id: &str) -> u64 {
+        let Some(session) = self.sessions.remove(session_id) else {
+            return 0;
+        };
+        let bytes = Self::size_of(&session.frames);
+        self.budget.release(bytes);
+        bytes
+    }
+    
+    /// (frames, bytes) held for a session
+    pub fn session_usage(&self, session_id: &str) -> (usize, u64) {
+        self.sessions
+            .get(session_id)
+            .map(|session| (session.frames.len(), Self::size_of(&session.frames)))
+            .unwrap_or_default()
+    }
+    
+    pub fn usage(&self) -> BufferUsage {
+        BufferUsage {
+            bytes: self.budget.used,
+            frames: self.sessions.values().map(|session| session.frames.len()).sum(),
+            sessions: self.sessions.len(),
+            limit_bytes: self.budget.limit,
+            peak_bytes: self.budget.peak,
+            evicted_frames: self.budget.evicted,
+        }
+    }
+    
+    /// Keep at most `keep` of a session's newest frames
+    fn trim(&mut self, session_id: &str, keep: usize) {
+        let Some(session) = self.sessions.get_mut(session_id) else {
+            return;
+        };
+        while session.frames.len() > keep {
+            if let Some(frame) = session.frames.pop_front() {
+                self.budget.release(frame.data.len() as u64);
+            }
+        }
+    }
+    
+    /// Free one frame: the oldest superseded frame of the least recently
+    /// updated session, or failing that another session's whole buffer
+    fn evict_one(&mut self, inserting: &str) -> bool {
+        let superseded = self
+            .sessions
+            .iter_mut()
+            .filter(|(_, session)| session.frames.len() > 1)
+            .min_by_key(|(_, session)| session.touched);
+        if let Some((_, session)) = superseded {
+            if let Some(frame) = session.frames.pop_front() {
+                self.budget.release(frame.data.len() as u64);
+                self.budget.evicted += 1;
+                return true;
+            }
+        }
+        
+        let idle = self
+            .sessions
+            .iter()
+            .filter(|(session_id, _)| session_id.as_str() != inserting)
+            .min_by_key(|(_, session)| session.touched)
+            .map(|(session_id, session)| (session_id.clone(), session.frames.len()));
+        match idle {
+            Some((session_id, frames)) => {
+                tracing::debug!("Frame buffer full, dropping idle session {}", session_id);
+                self.remove_session(session_id.as_str());
+                self.budget.evicted += frames as u64;
+                true
+            }
+            None => false,
+        }
+    }
+    
+    fn size_of(frames: &VecDeque<Frame>) -> u64 {
+        frames.iter().map(|frame| frame.data.len() as u64).sum()
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::Error;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use image::RgbaImage;
+use openh264::decoder::Decoder;
+use openh264::encoder::{Encoder, EncoderConfig, FrameType, RateControlMode};
+use openh264::formats::{RgbaSliceU8, YUVBuffer, YUVSource};
+use openh264::OpenH264API;
+
+use crate::error::DeskShareError;
+use super::video::{FrameCodec, VideoPacket};
+
+/// Keyframe flag (u8), then the packet number, width and height (u32s).
+/// H.264 pictures have even sides, so the size to crop back to goes along.
+const PACKET_HEADER_LEN: usize = 1 + 3 * 4;
+
+/// Largest picture a packet may decode to, 8K
+const MAX_SIDE: u32 = 7680;
+
+/// Screen codec on OpenH264: motion-compensated, so a still or scrolling
+/// screen costs a few hundred bytes a frame. Keyframes are IDR pictures,
+/// which a viewer can start, or recover from a missed packet, at.
+pub struct H264Encoder {
+    encoder: Encoder,
+    size: Option<(u32, u32)>,
+    packets: u32,
+}
+
+impl H264Encoder {
+    pub fn new() -> Result<Self, Error> {
+        // Every capture is sent, so none may be skipped; that leaves the
+        // encoder at a fixed quality rather than chasing a bitrate
+        let config = EncoderConfig::new()
+            .rate_control_mode(RateControlMode::Off)
+            .enable_skip_frame(false);
+        let encoder = Encoder::with_api_config(OpenH264API::from_source(), config)
+            .map_err(|e| DeskShareError::EncodingFailed(format!("H.264 encoder didn't start: {}", e)))?;
+        Ok(Self { encoder, size: None, packets: 0 })
+    }
+
+    /// Encode `image`; a keyframe if asked for, or if it's the first or a
+    /// new size
+    pub fn encode(&mut self, image: &RgbaImage, keyframe: bool) -> Result<VideoPacket, Error> {
+        let (width, height) = image.dimensions();
+        if keyframe || self.size != Some((width, height)) {
+            self.encoder.force_intra_frame();
+        }
+        let padded = pad_to_even(image);
+        let yuv = YUVBuffer::from_rgb_source(RgbaSliceU8::new(padded.as_raw(), (padded.width() as usize, padded.height() as usize)));
+        let stream = self
+            .encoder
+            .encode(&yuv)
+            .map_err(|e| DeskShareError::EncodingFailed(format!("H.264 encoding failed: {}", e)))?;
+        let keyframe = match stream.frame_type() {
+            FrameType::IDR => true,
+            FrameType::I | FrameType::P | FrameType::IPMixed => false,
+            frame_type => return Err(DeskShareError::EncodingFailed(format!("H.264 encoder gave a {:?} frame", frame_type)).into()),
+        };
+        self.size = Some((width, height));
+        self.packets = if keyframe { 0 } else { self.packets.wrapping_add(1) };
+
+        let mut body = BytesMut::with_capacity(PACKET_HEADER_LEN);
+        body.put_u8(keyframe as u8);
+        body.put_u32(self.packets);
+        body.put_u32(width);
+        body.put_u32(height);
+        let mut data = body.to_vec();
+        stream.write_vec(&mut data);
+        Ok(VideoPacket { data: Bytes::from(data), keyframe, codec: FrameCodec::H264 })
+    }
+}
+
+/// Rebuilds frames from `H264Encoder` packets. After a missed or broken
+/// packet it refuses everything up to the next keyframe.
+#[derive(Default)]
+pub struct H264Decoder {
+    decoder: Option<Decoder>,
+    packets: Option<u32>,
+}
+
+impl H264Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decode(&mut self, data: &[u8]) -> Result<RgbaImage, Error> {
+        let decoded = self.apply(data);
+        if decoded.is_err() {
+            self.decoder = None;
+            self.packets = None;
+        }
+        decoded
+    }
+
+    fn apply(&mut self, data: &[u8]) -> Result<RgbaImage, Error> {
+        let mut header = data.get(..PACKET_HEADER_LEN).ok_or(DeskShareError::InvalidMessageFormat)?;
+        let keyframe = header.get_u8() != 0;
+        let packet = header.get_u32();
+        let (width, height) = (header.get_u32(), header.get_u32());
+        if width == 0 || height == 0 || width > MAX_SIDE || height > MAX_SIDE {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        }
+        // A delta only makes sense on top of the packet just before it
+        if !keyframe && self.packets.map(|last| last.wrapping_add(1)) != Some(packet) {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        }
+
+        let decoder = match &mut self.decoder {
+            Some(decoder) => decoder,
+            decoder => decoder.insert(
+                Decoder::new().map_err(|e| DeskShareError::EncodingFailed(format!("H.264 decoder didn't start: {}", e)))?,
+            ),
+        };
+        let picture = decoder
+            .decode(&data[PACKET_HEADER_LEN..])
+            .map_err(|_| DeskShareError::InvalidMessageFormat)?
+            .ok_or(DeskShareError::InvalidMessageFormat)?;
+        let (padded_width, padded_height) = picture.dimensions();
+        if padded_width < width as usize || padded_height < height as usize {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        }
+        let mut rgba = vec![0u8; padded_width * padded_height * 4];
+        picture.write_rgba8(&mut rgba);
+        let padded = RgbaImage::from_raw(padded_width as u32, padded_height as u32, rgba).ok_or(DeskShareError::InvalidMessageFormat)?;
+
+        self.packets = Some(packet);
+        Ok(image::imageops::crop_imm(&padded, 0, 0, width, height).to_image())
+    }
+}
+
+/// `image` grown to even sides, the edge pixels repeated into the margin
+fn pad_to_even(image: &RgbaImage) -> std::borrow::Cow<'_, RgbaImage> {
+    let (width, height) = image.dimensions();
+    if width % 2 == 0 && height % 2 == 0 {
+        return std::borrow::Cow::Borrowed(image);
+    }
+    let padded = RgbaImage::from_fn(width.next_multiple_of(2), height.next_multiple_of(2), |x, y| {
+        *image.get_pixel(x.min(width - 1), y.min(height - 1))
+    });
+    std::borrow::Cow::Owned(padded)
+}
@@ -0,0 +1,76 @@
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+/// Digest a share's file and chunk hashes are taken with. The sharer picks
+/// it and names it in the announcement; receivers check with the same one.
+/// Merkle trees and chunk tags stay blake3 whatever it is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    /// For tooling that expects SHA-256 digests
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn hasher(self) -> ContentHasher {
+        match self {
+            HashAlgorithm::Blake3 => ContentHasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Sha256 => ContentHasher::Sha256(Sha256::new()),
+        }
+    }
+    
+    /// Hex digest of `data`
+    pub fn hash(self, data: &[u8]) -> String {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finalize_hex()
+    }
+}
+
+/// A running hash under either algorithm
+pub enum ContentHasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+}
+
+impl ContentHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            ContentHasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            ContentHasher::Sha256(hasher) => hasher.update(data),
+        }
+    }
+    
+    pub fn finalize_hex(self) -> String {
+        match self {
+            ContentHasher::Blake3(hasher) => hex::encode(hasher.finalize().as_bytes()),
+            ContentHasher::Sha256(hasher) => hex::encode(hasher.finalize()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_digests_match_the_standard_ones() {
+        assert_eq!(HashAlgorithm::Blake3.hash(b"abc"), blake3::hash(b"abc").to_hex().to_string());
+        assert_eq!(
+            HashAlgorithm::Sha256.hash(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        
+        let mut hasher = HashAlgorithm::Sha256.hasher();
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        assert_eq!(hasher.finalize_hex(), HashAlgorithm::Sha256.hash(b"abc"));
+        
+        assert_eq!(serde_json::to_string(&HashAlgorithm::Sha256).unwrap(), r#""sha256""#);
+        assert!(serde_json::from_str::<HashAlgorithm>(r#""md5""#).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use anyhow::Error;
+use serde::{Serialize, Deserialize};
+
+use super::file_transfer::TransferStatus;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+/// A transfer that reached a terminal state
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransferRecord {
+    pub file_name: String,
+    pub file_hash: String,
+    pub size: u64,
+    pub peer_id: String,
+    pub direction: TransferDirection,
+    pub status: TransferStatus,
+    pub duration_ms: u64,
+    /// When it ended, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+/// Finished transfers, one JSON line each, so recording one is an append.
+/// Appends are serialized and each line goes out in one write; a line torn
+/// by a crash is skipped when reading.
+pub struct TransferHistory {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl TransferHistory {
+    pub fn open(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            lock: Mutex::new(()),
+        }
+    }
+    
+    pub fn append(&self, record: &TransferRecord) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let _guard = self.lock.lock().unwrap();
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new().create(true).read(true).append(true).open(&self.path)?;
+        // Finish off a line torn by a crash so this one stays readable
+        if file.metadata()?.len() > 0 {
+            let mut last = [0u8];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                line.insert(0, b'\n');
+            }
+        }
+        file.write_all(&line)?;
+        Ok(())
+    }
+    
+    /// Up to `limit` records, newest first, after skipping `offset` of them
+    pub fn list(&self, limit: usize, offset: usize) -> Result<Vec<TransferRecord>, Error> {
+        let _guard = self.lock.lock().unwrap();
+        let file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str::<TransferRecord>(&line?) {
+                Ok(record) => records.push(record),
+                Err(e) => tracing::warn!("Skipping unreadable transfer record in {}: {}", self.path.display(), e),
+            }
+        }
+        Ok(records.into_iter().rev().skip(offset).take(limit).collect())
+    }
+    
+    pub fn clear(&self) -> Result<(), Error> {
+        let _guard = self.lock.lock().unwrap();
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    
+    fn record(i: usize) -> TransferRecord {
+        TransferRecord {
+            file_name: format!("file-{}.bin", i),
+            file_hash: format!("{:064x}", i),
+            size: i as u64,
+            peer_id: "10.0.0.2".to_string(),
+            direction: TransferDirection::Download,
+            status: TransferStatus::Completed,
+            duration_ms: 5,
+            timestamp: i as u64,
+        }
+    }
+    
+    #[test]
+    fn test_concurrent_appends_stay_whole_lines() {
+        let path = std::env::temp_dir().join(format!("desk-share-history-{}.jsonl", rand::random::<u64>()));
+        let history = Arc::new(TransferHistory::open(&path));
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let history = history.clone();
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        history.append(&record(writer * 25 + i)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(history.list(usize::MAX, 0).unwrap().len(), 200);
+        
+        // A torn line from a crash doesn't hide the rest
+        std::fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"file_na").unwrap();
+        history.append(&record(1000)).unwrap();
+        let page = history.list(2, 0).unwrap();
+        assert_eq!(page[0], record(1000));
+        assert_eq!(history.list(10, 195).unwrap().len(), 6);
+        
+        history.clear().unwrap();
+        assert!(history.list(10, 0).unwrap().is_empty());
+        history.clear().unwrap();
+    }
+}
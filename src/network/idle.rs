@@ -0,0 +1,162 @@
+use std::time::{Duration, Instant};
+use image::RgbaImage;
+use serde::{Serialize, Deserialize};
+use xxhash_rust::xxh3::Xxh3;
+
+use super::adaptive::AdaptiveConfig;
+use super::video::FrameCodec;
+
+/// Fingerprint every GRID_STEP-th pixel of every GRID_STEP-th row
+const GRID_STEP: usize = 4;
+
+/// How screen capture treats an unchanged screen
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureConfig {
+    /// A full frame goes out this often, changed or not, so viewers that
+    /// joined or dropped a frame catch up
+    pub keyframe_interval_ms: u64,
+    /// Sent to viewers that can decode it; the rest of a session falls
+    /// back, see `FrameCodec::fallback_for`, once a viewer that can't joins
+    #[serde(default = "preferred_codec")]
+    pub codec: FrameCodec,
+    /// What new sessions do when viewers fall behind
+    #[serde(default)]
+    pub adaptive: AdaptiveConfig,
+    /// Send viewers a test pattern while capture fails rather than
+    /// nothing; for debugging
+    #[serde(default)]
+    pub test_pattern_on_failure: bool,
+}
+
+fn preferred_codec() -> FrameCodec {
+    if cfg!(feature = "h264") {
+        FrameCodec::H264
+    } else {
+        FrameCodec::Tiled
+    }
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            keyframe_interval_ms: 5_000,
+            codec: preferred_codec(),
+            adaptive: AdaptiveConfig::default(),
+            test_pattern_on_failure: false,
+        }
+    }
+}
+
+impl CaptureConfig {
+    pub fn keyframe_interval(&self) -> Duration {
+        Duration::from_millis(self.keyframe_interval_ms)
+    }
+}
+
+/// What to do with a freshly captured frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameChange {
+    /// Encode and send it
+    Changed,
+    /// Send it in full, as one viewers can start from
+    Keyframe,
+    /// Same as the last frame sent; a heartbeat will do
+    Unchanged,
+}
+
+/// Spots captures identical to the last one sent, so an idle screen
+/// costs a hash per tick rather than an encode and a send
+pub struct IdleDetector {
+    keyframe_interval: Duration,
+    last: Option<(u64, (u32, u32))>,
+    last_keyframe: Option<Instant>,
+    samples: Vec<u8>,
+}
+
+impl IdleDetector {
+    pub fn new(config: CaptureConfig) -> Self {
+        Self {
+            keyframe_interval: config.keyframe_interval(),
+            last: None,
+            last_keyframe: None,
+            samples: Vec::new(),
+        }
+    }
+    
+    pub fn check(&mut self, image: &RgbaImage) -> FrameChange {
+        let current = (self.fingerprint(image), image.dimensions());
+        let keyframe_due = self
+            .last_keyframe
+            .is_none_or(|sent| sent.elapsed() >= self.keyframe_interval);
+        if self.last == Some(current) && !keyframe_due {
+            return FrameChange::Unchanged;
+        }
+        
+        self.last = Some(current);
+        if keyframe_due {
+            self.last_keyframe = Some(Instant::now());
+            FrameChange::Keyframe
+        } else {
+            FrameChange::Changed
+        }
+    }
+    
+    /// Send the next frame in full whether or not it changed
+    pub fn force_keyframe(&mut self) {
+        self.last_keyframe = None;
+    }
+    
+    /// xxh3 over a subsampled grid of the frame
+    fn fingerprint(&mut self, image: &RgbaImage) -> u64 {
+        let row_bytes = image.width() as usize * 4;
+        let mut hasher = Xxh3::new();
+        if row_bytes == 0 {
+            return hasher.digest();
+        }
+        for row in image.as_raw().chunks_exact(row_bytes).step_by(GRID_STEP) {
+            self.samples.clear();
+            for pixel in row.chunks_exact(4).step_by(GRID_STEP) {
+                self.samples.extend_from_slice(pixel);
+            }
+            hasher.update(&self.samples);
+        }
+        hasher.digest()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_only_changes_and_keyframes_are_sent() {
+        let mut detector = IdleDetector::new(CaptureConfig { keyframe_interval_ms: 50, ..CaptureConfig::default() });
+        let mut image = RgbaImage::new(64, 48);
+        assert_eq!(detector.check(&image), FrameChange::Keyframe);
+        assert_eq!(detector.check(&image), FrameChange::Unchanged);
+        
+        image.put_pixel(8, 8, image::Rgba([255, 0, 0, 255]));
+        assert_eq!(detector.check(&image), FrameChange::Changed);
+        assert_eq!(detector.check(&image), FrameChange::Unchanged);
+        
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(detector.check(&image), FrameChange::Keyframe);
+        assert_eq!(detector.check(&image), FrameChange::Unchanged);
+        
+        // A screen that never stops changing still gets keyframes
+        let mut changed = 0;
+        for shade in 1..=20 {
+            image.put_pixel(0, 0, image::Rgba([0, 0, shade, 255]));
+            match detector.check(&image) {
+                FrameChange::Changed => changed += 1,
+                FrameChange::Keyframe => break,
+                FrameChange::Unchanged => panic!("change {} missed", shade),
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!((1..20).contains(&changed), "{} changes", changed);
+        
+        detector.force_keyframe();
+        assert_eq!(detector.check(&image), FrameChange::Keyframe);
+    }
+}
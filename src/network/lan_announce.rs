@@ -0,0 +1,112 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use anyhow::Error;
+use serde::{Serialize, Deserialize};
+use tokio::net::UdpSocket;
+
+use super::session_protocol::{SessionAnnouncement, SessionEnded};
+
+/// Port session announcements are broadcast on
+pub const DEFAULT_ANNOUNCE_PORT: u16 = 47812;
+
+/// How often hosts repeat their announcements
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// An announcement not repeated for this long is of a session that's gone
+pub const ANNOUNCEMENT_TTL: Duration = Duration::from_secs(15);
+
+/// Largest datagram read; announcements are a few hundred bytes
+const MAX_BEACON_LEN: usize = 4096;
+
+/// One datagram on the announcement port
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SessionBeacon {
+    Announce(SessionAnnouncement),
+    /// The host stopped the session; no need to wait for it to expire
+    Retract(SessionEnded),
+}
+
+/// Session announcements over UDP broadcast: hosts tell the whole LAN, and
+/// every peer hears every host
+pub struct LanAnnouncer {
+    socket: UdpSocket,
+    target: SocketAddr,
+}
+
+impl LanAnnouncer {
+    /// Listen on `port` of every interface and broadcast to it
+    pub async fn bind(port: u16) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(("0.0.0.0", port)).await?;
+        socket.set_broadcast(true)?;
+        Ok(Self { socket, target: SocketAddr::from(([255, 255, 255, 255], port)) })
+    }
+    
+    /// Listen on `addr` and send to `target` alone, e.g. between loopback
+    /// sockets in tests
+    pub async fn bind_to(addr: SocketAddr, target: SocketAddr) -> Result<Self, Error> {
+        Ok(Self { socket: UdpSocket::bind(addr).await?, target })
+    }
+    
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.socket.local_addr()?)
+    }
+    
+    pub async fn send(&self, beacon: &SessionBeacon) -> Result<(), Error> {
+        self.socket.send_to(&serde_json::to_vec(beacon)?, self.target).await?;
+        Ok(())
+    }
+    
+    /// Next beacon and the address it came from. Datagrams that aren't
+    /// beacons are skipped.
+    pub async fn recv(&self) -> Result<(SessionBeacon, IpAddr), Error> {
+        let mut buf = [0u8; MAX_BEACON_LEN];
+        loop {
+            let (len, addr) = self.socket.recv_from(&mut buf).await?;
+            match serde_json::from_slice(&buf[..len]) {
+                Ok(beacon) => return Ok((beacon, addr.ip())),
+                Err(e) => tracing::debug!("Ignoring datagram from {} on the announcement port: {}", addr, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::quality_preset::QualityPreset;
+    use crate::network::session_protocol::AccessMode;
+    
+    #[tokio::test]
+    async fn test_beacons_cross_the_socket_and_noise_is_skipped() {
+        let any = SocketAddr::from(([127, 0, 0, 1], 0));
+        let viewer = LanAnnouncer::bind_to(any, any).await.unwrap();
+        let host = LanAnnouncer::bind_to(any, viewer.local_addr().unwrap()).await.unwrap();
+        
+        host.socket.send_to(b"DISCOVER_DESKTOPSHARE:10.0.0.9", viewer.local_addr().unwrap()).await.unwrap();
+        let announcement = SessionAnnouncement {
+            session_id: "s1".to_string(),
+            host_peer_id: "local".to_string(),
+            resolution: (1920, 1080),
+            access_mode: AccessMode::Password,
+            participant_count: 2,
+            timestamp: 1_700_000_000,
+            host_name: Some("Alice".to_string()),
+            started_at_ms: 1_700_000_000_000,
+            has_password: true,
+            preset: Some(QualityPreset::High),
+        };
+        host.send(&SessionBeacon::Announce(announcement)).await.unwrap();
+        host.send(&SessionBeacon::Retract(SessionEnded { session_id: "s1".to_string(), host_peer_id: "local".to_string() }))
+            .await
+            .unwrap();
+        
+        let (beacon, from) = viewer.recv().await.unwrap();
+        assert_eq!(from, IpAddr::from([127, 0, 0, 1]));
+        let SessionBeacon::Announce(heard) = beacon else {
+            panic!("{:?}", beacon);
+        };
+        assert_eq!((heard.host_name.as_deref(), heard.has_password, heard.resolution), (Some("Alice"), true, (1920, 1080)));
+        assert_eq!(heard.preset, Some(QualityPreset::High));
+        assert!(matches!(viewer.recv().await.unwrap().0, SessionBeacon::Retract(ended) if ended.session_id == "s1"));
+    }
+}
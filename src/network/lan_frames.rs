@@ -0,0 +1,257 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use anyhow::Error;
+use bytes::Bytes;
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+
+use crate::error::DeskShareError;
+use crate::p2p::LanStream;
+use super::adaptive::DeliveryReport;
+use super::screen_share::Frame;
+use super::video::FrameCodec;
+
+/// Port hosts listen on for viewers connecting to receive frames
+pub const DEFAULT_SCREEN_PORT: u16 = 47811;
+
+/// Frames waiting for a viewer's connection before newer ones are dropped
+const VIEWER_QUEUE_DEPTH: usize = 2;
+
+/// A frame connection carries one encoded frame per stream message, host
+/// to viewer
+impl Frame {
+    pub(crate) async fn send(&self, stream: &mut LanStream) -> Result<(), Error> {
+        stream.send(&self.encode()?).await
+    }
+    
+    pub(crate) async fn recv(stream: &mut LanStream) -> Result<Self, Error> {
+        Self::decode(Bytes::from(stream.recv().await?))
+    }
+}
+
+struct ViewerLink {
+    queue: mpsc::Sender<(Frame, Instant)>,
+    stats: Arc<LinkStats>,
+}
+
+#[derive(Default)]
+struct LinkStats {
+    /// Over the link's life
+    dropped: AtomicU64,
+    /// Since the last report
+    delivered: AtomicU64,
+    dropped_since: AtomicU64,
+    worst_latency_us: AtomicU64,
+    /// A dropped frame left the viewer unable to decode the ones after it
+    keyframe_wanted: AtomicBool,
+}
+
+/// Sends frames to viewers over the connections they opened to us, each
+/// from its own task. A viewer that can't keep up loses frames rather than
+/// holding up capture or anyone else.
+#[derive(Default)]
+pub struct LanFrameLinks {
+    viewers: DashMap<String, ViewerLink>,
+}
+
+impl LanFrameLinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Send `peer_id`'s frames over `stream` from now on
+    pub fn attach(&self, peer_id: &str, mut stream: LanStream) {
+        let (queue, mut frames) = mpsc::channel::<(Frame, Instant)>(VIEWER_QUEUE_DEPTH);
+        let stats = Arc::new(LinkStats::default());
+        let writer_stats = stats.clone();
+        let peer = peer_id.to_string();
+        // Ends when the link is detached or replaced, or the viewer goes away
+        tokio::spawn(async move {
+            while let Some((frame, queued)) = frames.recv().await {
+                if let Err(e) = frame.send(&mut stream).await {
+                    tracing::debug!("Frame connection to {} closed: {}", peer, e);
+                    break;
+                }
+                writer_stats.delivered.fetch_add(1, Ordering::Relaxed);
+                writer_stats.worst_latency_us.fetch_max(queued.elapsed().as_micros() as u64, Ordering::Relaxed);
+            }
+        });
+        self.viewers.insert(peer_id.to_string(), ViewerLink { queue, stats });
+    }
+    
+    pub fn detach(&self, peer_id: &str) {
+        self.viewers.remove(peer_id);
+    }
+    
+    pub fn is_attached(&self, peer_id: &str) -> bool {
+        self.viewers.contains_key(peer_id)
+    }
+    
+    /// Frames `peer_id` missed for being behind
+    pub fn dropped_frames(&self, peer_id: &str) -> u64 {
+        self.viewers.get(peer_id).map_or(0, |link| link.stats.dropped.load(Ordering::Relaxed))
+    }
+    
+    /// How frames reached `peer_id`, queueing included, since last asked
+    pub fn take_report(&self, peer_id: &str) -> DeliveryReport {
+        let Some(link) = self.viewers.get(peer_id) else {
+            return DeliveryReport::default();
+        };
+        DeliveryReport {
+            delivered: link.stats.delivered.swap(0, Ordering::Relaxed),
+            dropped: link.stats.dropped_since.swap(0, Ordering::Relaxed),
+            worst_latency: Duration::from_micros(link.stats.worst_latency_us.swap(0, Ordering::Relaxed)),
+        }
+    }
+    
+    /// Whether `peer_id` has needed a keyframe since last asked
+    pub fn take_keyframe_request(&self, peer_id: &str) -> bool {
+        self.viewers.get(peer_id).is_some_and(|link| link.stats.keyframe_wanted.swap(false, Ordering::Relaxed))
+    }
+    
+    /// Queue a frame without waiting; a full queue drops it, and a dropped
+    /// tiled frame asks for a keyframe, as later tiles build on it
+    pub fn send_frame(&self, peer_id: &str, frame: Frame) -> Result<(), Error> {
+        let closed = {
+            let link = self
+                .viewers
+                .get(peer_id)
+                .ok_or_else(|| DeskShareError::PeerConnectionFailed(format!("no frame connection to {}", peer_id)))?;
+            match link.queue.try_send((frame, Instant::now())) {
+                Ok(()) => false,
+                Err(mpsc::error::TrySendError::Full((frame, _))) => {
+                    link.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                    link.stats.dropped_since.fetch_add(1, Ordering::Relaxed);
+                    if frame.header.codec == FrameCodec::Tiled && !frame.header.unchanged {
+                        link.stats.keyframe_wanted.store(true, Ordering::Relaxed);
+                    }
+                    false
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => true,
+            }
+        };
+        if closed {
+            self.detach(peer_id);
+            return Err(DeskShareError::PeerConnectionFailed(format!("frame connection to {} closed", peer_id)).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::screen_share::{FrameHeader, Media};
+    use crate::network::session_protocol::FRAME_FORMAT_VERSION;
+    use crate::p2p::TcpTransport;
+    use crate::security::{DeviceIdentity, SecurityConfig};
+    
+    /// Both ends of a loopback frame connection: (host's, viewer's)
+    async fn connection() -> (LanStream, LanStream) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+            transport.accept(&listener).await.unwrap().0
+        });
+        let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+        let viewer = transport.connect(addr).await.unwrap();
+        (accept.await.unwrap(), viewer)
+    }
+    
+    fn frame(sequence: u64, len: usize) -> Frame {
+        Frame {
+            header: Arc::new(FrameHeader {
+                session_id: "s1".into(),
+                sequence,
+                timestamp_ms: 0,
+                width: 64,
+                height: 48,
+                unchanged: false,
+                codec: FrameCodec::Jpeg,
+                keyframe: true,
+                media: Media::Video,
+            }),
+            data: Bytes::from(vec![sequence as u8; len]),
+        }
+    }
+    
+    #[test]
+    fn test_frames_round_trip_without_copying() {
+        let encoded = frame(7, 1000).encode().unwrap();
+        let decoded = Frame::decode(encoded.clone()).unwrap();
+        assert_eq!(*decoded.header, *frame(7, 1000).header);
+        assert_eq!(decoded.data, vec![7u8; 1000]);
+        assert_eq!(decoded.data.as_ptr(), encoded[encoded.len() - 1000..].as_ptr());
+        
+        let mut newer = encoded.to_vec();
+        newer[0] = FRAME_FORMAT_VERSION + 1;
+        assert!(Frame::decode(Bytes::from(newer)).is_err());
+        assert!(Frame::decode(encoded.slice(..8)).is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_a_viewer_that_falls_behind_loses_frames_not_the_host() {
+        const FRAME_LEN: usize = 1024 * 1024;
+        let (host_end, mut viewer) = connection().await;
+        let links = LanFrameLinks::new();
+        links.attach("10.0.0.2", host_end);
+        
+        // Nothing is read, so the connection backs up almost at once
+        let started = Instant::now();
+        for sequence in 0..64 {
+            links.send_frame("10.0.0.2", frame(sequence, FRAME_LEN)).unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+        assert!(links.dropped_frames("10.0.0.2") > 32);
+        let report = links.take_report("10.0.0.2");
+        assert_eq!(report.dropped, links.dropped_frames("10.0.0.2"));
+        assert_eq!(links.take_report("10.0.0.2").dropped, 0);
+        
+        // What does arrive is in order
+        let mut last = None;
+        for _ in 0..2 {
+            let received = Frame::recv(&mut viewer).await.unwrap();
+            assert!(last < Some(received.header.sequence));
+            assert_eq!(received.data.len(), FRAME_LEN);
+            last = Some(received.header.sequence);
+        }
+        
+        // Once the viewer is gone the link goes too
+        drop(viewer);
+        let detached = async {
+            while links.send_frame("10.0.0.2", frame(99, 16)).is_ok() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), detached).await.unwrap();
+        assert!(!links.is_attached("10.0.0.2"));
+    }
+    
+    #[tokio::test]
+    async fn test_dropped_tiles_ask_for_a_keyframe() {
+        const FRAME_LEN: usize = 1024 * 1024;
+        let (host_end, _viewer) = connection().await;
+        let links = LanFrameLinks::new();
+        links.attach("10.0.0.2", host_end);
+        let tiled = |sequence| Frame {
+            header: Arc::new(FrameHeader { codec: FrameCodec::Tiled, keyframe: false, ..(*frame(sequence, 0).header).clone() }),
+            data: Bytes::from(vec![0u8; FRAME_LEN]),
+        };
+        
+        // Every JPEG stands alone, so losing some costs nothing more
+        for sequence in 0..16 {
+            links.send_frame("10.0.0.2", frame(sequence, FRAME_LEN)).unwrap();
+        }
+        assert!(links.dropped_frames("10.0.0.2") > 0);
+        assert!(!links.take_keyframe_request("10.0.0.2"));
+        
+        for sequence in 16..32 {
+            links.send_frame("10.0.0.2", tiled(sequence)).unwrap();
+        }
+        assert!(links.take_keyframe_request("10.0.0.2"));
+        assert!(!links.take_keyframe_request("10.0.0.2"));
+        assert!(!links.take_keyframe_request("10.0.0.9"));
+    }
+}
@@ -0,0 +1,391 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use anyhow::Error;
+use async_trait::async_trait;
+use dashmap::{DashMap, DashSet};
+use serde::{Serialize, Deserialize};
+use tokio::sync::{broadcast, Mutex};
+
+use crate::error::DeskShareError;
+use crate::p2p::{LanStream, TcpTransport};
+use super::lan_frames::DEFAULT_SCREEN_PORT;
+use super::screen_share::Frame;
+use super::session_protocol::{ControlMessage, JoinRequest, JoinResponse, SessionAnnouncement, SessionEnded, SessionTransport, TokenGrant};
+
+/// Frames from hosts not yet taken before the oldest are dropped
+const FRAME_QUEUE_DEPTH: usize = 8;
+
+/// Notices from hosts not yet taken before the oldest are dropped
+const NOTICE_QUEUE_DEPTH: usize = 32;
+
+/// One message on a connection to a host's screen port. The viewer's first
+/// says what the connection is for: joins and control messages, each
+/// answered by the host, or frames or notices from the host from then on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SessionMessage {
+    Join(JoinRequest),
+    Joined(JoinResponse),
+    Control(ControlMessage),
+    /// The host's answer to a control message or a watch: the code of the
+    /// error it refused with, if it did
+    Done(Result<(), String>),
+    /// Send us frames over this connection
+    WatchFrames,
+    /// Send us token grants and the end of sessions over this connection
+    WatchNotices,
+    Grant(TokenGrant),
+    Ended(SessionEnded),
+}
+
+impl SessionMessage {
+    pub(crate) async fn send(&self, stream: &mut LanStream) -> Result<(), Error> {
+        stream.send(&serde_json::to_vec(self)?).await
+    }
+    
+    pub(crate) async fn recv(stream: &mut LanStream) -> Result<Self, Error> {
+        Ok(serde_json::from_slice(&stream.recv().await?).map_err(|_| DeskShareError::InvalidMessageFormat)?)
+    }
+}
+
+/// Session traffic over direct connections to hosts' screen ports. As a
+/// viewer we open them: one for joins and control messages, one we're sent
+/// frames on and one for the host's notices. As a host we keep the notice
+/// connections viewers opened to us.
+pub struct LanSessionTransport {
+    transport: TcpTransport,
+    /// Our connections to hosts, for joins and control messages
+    hosts: DashMap<String, Arc<Mutex<LanStream>>>,
+    /// Hosts we have frame and notice connections to
+    watching: Arc<DashSet<String>>,
+    /// Connections viewers opened to us for notices
+    viewers: DashMap<String, Arc<Mutex<LanStream>>>,
+    frame_tx: broadcast::Sender<Frame>,
+    notice_tx: broadcast::Sender<SessionMessage>,
+}
+
+impl LanSessionTransport {
+    pub fn new(transport: TcpTransport) -> Self {
+        Self {
+            transport,
+            hosts: DashMap::new(),
+            watching: Arc::new(DashSet::new()),
+            viewers: DashMap::new(),
+            frame_tx: broadcast::channel(FRAME_QUEUE_DEPTH).0,
+            notice_tx: broadcast::channel(NOTICE_QUEUE_DEPTH).0,
+        }
+    }
+    
+    /// Frames from the hosts of sessions we've joined
+    pub fn subscribe_frames(&self) -> broadcast::Receiver<Frame> {
+        self.frame_tx.subscribe()
+    }
+    
+    /// `Grant`s and `Ended`s from the hosts of sessions we've joined, the
+    /// host named as we reached it whatever the message says
+    pub fn subscribe_notices(&self) -> broadcast::Receiver<SessionMessage> {
+        self.notice_tx.subscribe()
+    }
+    
+    /// Send `peer_id` our notices over `stream` from now on
+    pub fn attach_viewer(&self, peer_id: &str, stream: LanStream) {
+        self.viewers.insert(peer_id.to_string(), Arc::new(Mutex::new(stream)));
+    }
+    
+    pub fn detach_viewer(&self, peer_id: &str) {
+        self.viewers.remove(peer_id);
+    }
+    
+    pub fn is_watching(&self, host_peer_id: &str) -> bool {
+        self.watching.contains(host_peer_id)
+    }
+    
+    async fn connection(&self, host_peer_id: &str) -> Result<Arc<Mutex<LanStream>>, Error> {
+        if let Some(stream) = self.hosts.get(host_peer_id) {
+            return Ok(stream.clone());
+        }
+        let stream = Arc::new(Mutex::new(self.transport.connect(host_addr(host_peer_id)?).await?));
+        Ok(self.hosts.entry(host_peer_id.to_string()).or_insert(stream).clone())
+    }
+    
+    /// Send `message` to a host and wait for its answer. A connection that
+    /// fails is dropped, so the next exchange opens another.
+    async fn exchange(&self, host_peer_id: &str, message: SessionMessage) -> Result<SessionMessage, Error> {
+        let stream = self.connection(host_peer_id).await?;
+        // Runs to the end even if the caller gives up, so the stream is never
+        // left halfway through an exchange
+        let answer = tokio::spawn(async move {
+            let mut stream = stream.lock().await;
+            message.send(&mut stream).await?;
+            SessionMessage::recv(&mut stream).await
+        })
+        .await
+        .map_err(|e| DeskShareError::Internal(e.to_string()))?;
+        if answer.is_err() {
+            self.hosts.remove(host_peer_id);
+        }
+        answer
+    }
+    
+    /// Open a connection to a host's screen port for `purpose`
+    async fn open_watch(&self, host_peer_id: &str, purpose: SessionMessage) -> Result<LanStream, Error> {
+        let mut stream = self.transport.connect(host_addr(host_peer_id)?).await?;
+        purpose.send(&mut stream).await?;
+        match SessionMessage::recv(&mut stream).await? {
+            SessionMessage::Done(Ok(())) => Ok(stream),
+            SessionMessage::Done(Err(code)) => Err(DeskShareError::PeerConnectionFailed(format!("{} refused with {}", host_peer_id, code)).into()),
+            _ => Err(DeskShareError::InvalidMessageFormat.into()),
+        }
+    }
+    
+    /// Have a host send us frames and notices, unless it already does
+    async fn watch(&self, host_peer_id: &str) -> Result<(), Error> {
+        if !self.watching.insert(host_peer_id.to_string()) {
+            return Ok(());
+        }
+        let opened = async {
+            let frames = self.open_watch(host_peer_id, SessionMessage::WatchFrames).await?;
+            let notices = self.open_watch(host_peer_id, SessionMessage::WatchNotices).await?;
+            Ok::<_, Error>((frames, notices))
+        };
+        let (mut frames, mut notices) = match opened.await {
+            Ok(streams) => streams,
+            Err(e) => {
+                self.watching.remove(host_peer_id);
+                return Err(e);
+            }
+        };
+        
+        // Either connection closing means the host went away; the next join
+        // opens both again
+        let host = host_peer_id.to_string();
+        let (frame_tx, watching) = (self.frame_tx.clone(), self.watching.clone());
+        tokio::spawn(async move {
+            loop {
+                match Frame::recv(&mut frames).await {
+                    Ok(frame) => {
+                        let _ = frame_tx.send(frame);
+                    }
+                    Err(e) => {
+                        tracing::debug!("Frame connection to {} closed: {}", host, e);
+                        break;
+                    }
+                }
+            }
+            watching.remove(&host);
+        });
+        let host = host_peer_id.to_string();
+        let (notice_tx, watching) = (self.notice_tx.clone(), self.watching.clone());
+        tokio::spawn(async move {
+            loop {
+                let notice = match SessionMessage::recv(&mut notices).await {
+                    Ok(SessionMessage::Grant(grant)) => SessionMessage::Grant(TokenGrant { host_peer_id: host.clone(), ..grant }),
+                    Ok(SessionMessage::Ended(ended)) => SessionMessage::Ended(SessionEnded { host_peer_id: host.clone(), ..ended }),
+                    Ok(other) => {
+                        tracing::debug!("Ignoring {:?} from {} on its notice connection", other, host);
+                        continue;
+                    }
+                    Err(e) => {
+                        tracing::debug!("Notice connection to {} closed: {}", host, e);
+                        break;
+                    }
+                };
+                let _ = notice_tx.send(notice);
+            }
+            watching.remove(&host);
+        });
+        Ok(())
+    }
+    
+    /// Send a notice over the connection `peer_id` opened for them
+    async fn notify(&self, peer_id: &str, notice: SessionMessage) -> Result<(), Error> {
+        let stream = self
+            .viewers
+            .get(peer_id)
+            .map(|stream| stream.clone())
+            .ok_or_else(|| DeskShareError::PeerConnectionFailed(format!("no notice connection from {}", peer_id)))?;
+        let sent = notice.send(&mut *stream.lock().await).await;
+        if sent.is_err() {
+            self.detach_viewer(peer_id);
+        }
+        sent
+    }
+}
+
+#[async_trait]
+impl SessionTransport for LanSessionTransport {
+    /// Sessions reach the LAN through the `LanAnnouncer` instead
+    async fn announce(&self, _announcement: SessionAnnouncement) -> Result<(), Error> {
+        Ok(())
+    }
+    
+    /// Once let in, the host is asked for frames and notices as well
+    async fn request_join(&self, host_peer_id: &str, request: JoinRequest) -> Result<JoinResponse, Error> {
+        let SessionMessage::Joined(response) = self.exchange(host_peer_id, SessionMessage::Join(request)).await? else {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        };
+        if let JoinResponse::Accepted { .. } = response {
+            self.watch(host_peer_id).await?;
+        }
+        Ok(response)
+    }
+    
+    async fn send_control(&self, host_peer_id: &str, message: ControlMessage) -> Result<(), Error> {
+        let session_id = message.session_id.clone();
+        match self.exchange(host_peer_id, SessionMessage::Control(message)).await? {
+            SessionMessage::Done(Ok(())) => Ok(()),
+            SessionMessage::Done(Err(code)) => Err(refusal(&code, &session_id).into()),
+            _ => Err(DeskShareError::InvalidMessageFormat.into()),
+        }
+    }
+    
+    async fn grant_token(&self, peer_id: &str, grant: TokenGrant) -> Result<(), Error> {
+        self.notify(peer_id, SessionMessage::Grant(grant)).await
+    }
+    
+    async fn end_session(&self, peer_id: &str, ended: SessionEnded) -> Result<(), Error> {
+        self.notify(peer_id, SessionMessage::Ended(ended)).await
+    }
+    
+    /// Frames go over the frame connections viewers open, in `LanFrameLinks`
+    async fn send_frame(&self, peer_id: &str, _frame: Frame) -> Result<(), Error> {
+        Err(DeskShareError::PeerConnectionFailed(format!("no frame connection from {}", peer_id)).into())
+    }
+}
+
+/// Where to reach a host given as "ip" or "ip:port"
+fn host_addr(host_peer_id: &str) -> Result<SocketAddr, DeskShareError> {
+    host_peer_id
+        .parse::<SocketAddr>()
+        .or_else(|_| host_peer_id.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DEFAULT_SCREEN_PORT)))
+        .map_err(|_| DeskShareError::PeerConnectionFailed(format!("{} isn't a device address", host_peer_id)))
+}
+
+/// The error a host refused a control message with, from its code
+fn refusal(code: &str, session_id: &str) -> DeskShareError {
+    match code {
+        "session_not_found" => DeskShareError::SessionNotFound(session_id.to_string()),
+        "invalid_session_token" => DeskShareError::InvalidSessionToken(session_id.to_string()),
+        "remote_control_not_allowed" => DeskShareError::RemoteControlNotAllowed(session_id.to_string()),
+        code => DeskShareError::PeerConnectionFailed(format!("host refused {} with {}", session_id, code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::screen_share::ScreenShare;
+    use crate::network::session_protocol::{AccessMode, InputEvent};
+    use crate::platform::fallback::FallbackCapture;
+    use crate::security::{DeviceIdentity, SecurityConfig};
+    
+    fn lan_transport() -> Arc<LanSessionTransport> {
+        Arc::new(LanSessionTransport::new(TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default())))
+    }
+    
+    /// A host serving its screen port on loopback, as the app does; returns
+    /// the address viewers reach it at
+    async fn serve(host: ScreenShare) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+            while let Ok((stream, from)) = transport.accept(&listener).await {
+                let host = host.clone();
+                tokio::spawn(async move { host.accept_connection(stream, from.ip().to_string()).await });
+            }
+        });
+        addr
+    }
+    
+    #[tokio::test]
+    async fn test_viewers_are_known_by_their_connection() {
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_lan_transport("host".to_string(), lan_transport())
+            .with_frame_links(Arc::new(super::super::LanFrameLinks::new()));
+        let session_id = host.start_sharing("host".to_string(), 5, (64, 48), None, None, None).await.unwrap();
+        let host_addr = serve(host.clone()).await;
+        
+        let transport = lan_transport();
+        let viewer = ScreenShare::with_capture_backend(Arc::new(FallbackCapture)).with_lan_transport("10.0.0.9".to_string(), transport.clone());
+        viewer.handle_announcement(SessionAnnouncement {
+            session_id: session_id.clone(),
+            host_peer_id: host_addr.clone(),
+            resolution: (64, 48),
+            access_mode: AccessMode::Open,
+            participant_count: 0,
+            timestamp: 0,
+            host_name: None,
+            started_at_ms: 0,
+            has_password: false,
+            preset: None,
+        }).await;
+        viewer.join_remote_session(&session_id, None).await.unwrap();
+        
+        // Whatever the viewer calls itself, it's where it connected from
+        let participants = host.get_participants(&session_id).await.unwrap();
+        assert_eq!(participants.iter().map(|p| p.peer_id.as_str()).collect::<Vec<_>>(), ["127.0.0.1"]);
+        assert!(transport.is_watching(&host_addr));
+        
+        // A refusal comes back as the host's own error
+        let err = viewer.send_input(&session_id, InputEvent::Scroll { dx: 0, dy: 1 }).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<DeskShareError>(), Some(DeskShareError::RemoteControlNotAllowed(id)) if *id == session_id), "{}", err);
+        host.stop_sharing(&session_id, "host").await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_frames_reach_a_viewer_on_another_device() {
+        const FRAMES: usize = 24;
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_lan_transport("host".to_string(), lan_transport())
+            .with_frame_links(Arc::new(super::super::LanFrameLinks::new()));
+        let session_id = host.start_sharing("host".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        // Only the frames sent below, not the capture loop's
+        host.set_paused(&session_id, true).await.unwrap();
+        let host_addr = serve(host.clone()).await;
+        
+        // Frames from the host are handed to the viewer as the app does
+        let transport = lan_transport();
+        let viewer = ScreenShare::with_capture_backend(Arc::new(FallbackCapture)).with_lan_transport("10.0.0.9".to_string(), transport.clone());
+        let mut incoming = transport.subscribe_frames();
+        let receiver = viewer.clone();
+        tokio::spawn(async move {
+            while let Ok(frame) = incoming.recv().await {
+                receiver.receive_frame(frame).await;
+            }
+        });
+        viewer.handle_announcement(SessionAnnouncement {
+            session_id: session_id.clone(),
+            host_peer_id: host_addr.clone(),
+            resolution: (64, 48),
+            access_mode: AccessMode::Open,
+            participant_count: 0,
+            timestamp: 0,
+            host_name: None,
+            started_at_ms: 0,
+            has_password: false,
+            preset: None,
+        }).await;
+        viewer.join_remote_session(&session_id, None).await.unwrap();
+        let mut shown = viewer.subscribe_frames(&session_id).await.unwrap();
+        
+        let mut sequences = std::collections::HashSet::new();
+        for n in 0..FRAMES {
+            let data = vec![n as u8; 4096];
+            host.broadcast_to_session(&session_id, bytes::Bytes::from(data.clone())).await.unwrap();
+            // Skipping a keyframe the capture loop had sent before the pause
+            let frame = async {
+                loop {
+                    let frame = shown.recv().await.unwrap();
+                    if frame.data == data {
+                        break frame;
+                    }
+                }
+            };
+            let frame = tokio::time::timeout(std::time::Duration::from_secs(5), frame).await.unwrap();
+            sequences.insert(frame.header.sequence);
+            assert_eq!(viewer.get_frame(&session_id).await.unwrap(), data);
+        }
+        assert_eq!(sequences.len(), FRAMES);
+        host.stop_sharing(&session_id, "host").await.unwrap();
+    }
+}
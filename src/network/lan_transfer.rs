@@ -0,0 +1,100 @@
+use std::sync::Arc;
+use anyhow::Error;
+use async_trait::async_trait;
+use bytes::Bytes;
+use dashmap::DashMap;
+use tokio::sync::Mutex;
+
+use crate::error::DeskShareError;
+use crate::p2p::LanStream;
+use super::chunk_pipeline::ChunkTransport;
+use super::file_transfer::ChunkEnvelope;
+use super::transfer_protocol::FileTransferMessage;
+
+/// Port devices listen on for files sent to them directly
+pub const DEFAULT_TRANSFER_PORT: u16 = 47810;
+
+/// A direct transfer connection carries one file transfer message per
+/// stream message. The sender opens it with an offer, then answers the
+/// receiver's chunk requests.
+impl FileTransferMessage {
+    pub(crate) async fn send(&self, stream: &mut LanStream) -> Result<(), Error> {
+        stream.send(&self.encode()?).await
+    }
+    
+    pub(crate) async fn recv(stream: &mut LanStream) -> Result<Self, Error> {
+        Self::decode(Bytes::from(stream.recv().await?))
+    }
+}
+
+/// Fetches chunks over the connections senders opened to offer us files,
+/// one request at a time per connection
+#[derive(Default)]
+pub struct LanChunkTransport {
+    connections: DashMap<String, Arc<Mutex<LanStream>>>,
+}
+
+impl LanChunkTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Fetch from `peer_id` over `stream` from now on
+    pub fn attach(&self, peer_id: &str, stream: LanStream) {
+        self.connections.insert(peer_id.to_string(), Arc::new(Mutex::new(stream)));
+    }
+    
+    pub fn detach(&self, peer_id: &str) {
+        self.connections.remove(peer_id);
+    }
+    
+    pub fn is_attached(&self, peer_id: &str) -> bool {
+        self.connections.contains_key(peer_id)
+    }
+    
+    fn connection(&self, peer_id: &str) -> Result<Arc<Mutex<LanStream>>, Error> {
+        self.connections
+            .get(peer_id)
+            .map(|stream| stream.clone())
+            .ok_or_else(|| DeskShareError::PeerConnectionFailed(format!("no connection to {}", peer_id)).into())
+    }
+}
+
+#[async_trait]
+impl ChunkTransport for LanChunkTransport {
+    async fn fetch_chunk(&self, peer_id: &str, file_hash: &str, index: usize) -> Result<Bytes, Error> {
+        let stream = self.connection(peer_id)?;
+        let file_hash = file_hash.to_string();
+        // Runs to the end even if the caller gives up, so the stream is never
+        // left halfway through a reply
+        tokio::spawn(async move {
+            let mut stream = stream.lock().await;
+            let request = FileTransferMessage::ChunkRequest { file_hash: file_hash.clone(), chunk_index: index };
+            request.send(&mut stream).await?;
+            match FileTransferMessage::recv(&mut stream).await? {
+                FileTransferMessage::ChunkData { file_hash: sent, chunk_index, data } if sent == file_hash && chunk_index == index => Ok(data),
+                FileTransferMessage::Error { reason, .. } => Err(DeskShareError::ChunkTransferFailed(reason).into()),
+                _ => Err(DeskShareError::InvalidMessageFormat.into()),
+            }
+        })
+        .await
+        .map_err(|e| DeskShareError::Internal(e.to_string()))?
+    }
+    
+    /// Chunks are only pulled over these connections
+    async fn send_chunk(&self, peer_id: &str, _envelope: ChunkEnvelope) -> Result<(), Error> {
+        Err(DeskShareError::ChunkTransferFailed(format!("{} pulls chunks rather than being sent them", peer_id)).into())
+    }
+    
+    async fn decline_offer(&self, peer_id: &str, file_hash: &str, reason: &str) -> Result<(), Error> {
+        let stream = self.connection(peer_id)?;
+        self.detach(peer_id);
+        let decline = FileTransferMessage::Error {
+            file_hash: file_hash.to_string(),
+            chunk_index: None,
+            reason: reason.to_string(),
+        };
+        let mut stream = stream.lock().await;
+        decline.send(&mut stream).await
+    }
+}
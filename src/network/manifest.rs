@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::path::{Component, Path};
+use std::sync::Arc;
+use anyhow::Error;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use serde::{Serialize, Deserialize};
+
+use super::file_transfer::SharedFile;
+use crate::error::DeskShareError;
+
+/// One file of a shared folder
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestFile {
+    /// Relative to the folder, `/`-separated
+    pub path: String,
+    pub file: SharedFile,
+}
+
+/// A symlink in a shared folder, kept as a link
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestLink {
+    /// Relative to the folder, `/`-separated
+    pub path: String,
+    /// What the link points at, as it was written
+    pub target: String,
+}
+
+impl ManifestLink {
+    /// Whether following the link stays inside the folder. Anything else is
+    /// never recreated.
+    pub fn stays_inside(&self) -> bool {
+        let mut depth = self.path.split('/').count() as isize - 1;
+        for component in Path::new(&self.target).components() {
+            match component {
+                Component::Normal(_) => depth += 1,
+                Component::CurDir => {}
+                Component::ParentDir => depth -= 1,
+                Component::RootDir | Component::Prefix(_) => return false,
+            }
+            if depth < 0 {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The layout of a shared folder. It is shared like a file of its own, so
+/// the receiver can rebuild the tree, empty directories included.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DirectoryManifest {
+    pub name: String,
+    /// Every directory under the folder, parents before children
+    pub directories: Vec<String>,
+    pub files: Vec<ManifestFile>,
+    #[serde(default)]
+    pub links: Vec<ManifestLink>,
+}
+
+impl DirectoryManifest {
+    /// Files by content, each with every path it appears at
+    pub fn distinct_files(&self) -> Vec<(&SharedFile, Vec<&str>)> {
+        let mut order = Vec::new();
+        let mut paths: HashMap<&str, (&SharedFile, Vec<&str>)> = HashMap::new();
+        for entry in &self.files {
+            paths
+                .entry(entry.file.hash.as_str())
+                .or_insert_with(|| {
+                    order.push(entry.file.hash.as_str());
+                    (&entry.file, Vec::new())
+                })
+                .1
+                .push(entry.path.as_str());
+        }
+        order.into_iter().filter_map(|hash| paths.remove(hash)).collect()
+    }
+    
+    /// Bytes to download, identical files counted once
+    pub fn total_size(&self) -> u64 {
+        self.distinct_files().iter().map(|(file, _)| file.size).sum()
+    }
+}
+
+/// Ignore files followed in a shared tree when asked to
+pub const IGNORE_FILES: [&str; 2] = [".gitignore", ".dsnignore"];
+
+/// What a folder share does with the symlinks in it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// Leave them out, so nothing they lead to is sent
+    #[default]
+    Skip,
+    /// Share what they lead to as if it were in the folder. Links that loop
+    /// back on themselves are left out.
+    Follow,
+    /// Send the links themselves, for receivers to recreate. Only Unix
+    /// receivers can, and only links that stay inside the folder.
+    PreserveAsLink,
+}
+
+/// What to leave out of a shared folder
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct IgnoreRules {
+    /// In `.gitignore` syntax, relative to the shared folder
+    pub patterns: Vec<String>,
+    /// Also follow the `.gitignore` and `.dsnignore` files found in the tree
+    pub use_ignore_files: bool,
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+}
+
+/// A walked folder, and what its ignore rules left out
+#[derive(Clone, Debug, Default)]
+pub struct WalkedTree {
+    pub directories: Vec<String>,
+    pub files: Vec<String>,
+    /// Kept under `SymlinkPolicy::PreserveAsLink`
+    pub links: Vec<ManifestLink>,
+    /// Files under ignored directories included
+    pub excluded_files: usize,
+    pub excluded_bytes: u64,
+}
+
+/// Relative paths of the directories and files under `root`, sorted with
+/// parents first. Symlinks are left out rather than followed.
+pub fn walk(root: &Path) -> Result<(Vec<String>, Vec<String>), Error> {
+    let walked = walk_with_rules(root, &IgnoreRules::default())?;
+    Ok((walked.directories, walked.files))
+}
+
+/// Like `walk`, without whatever `rules` match and with symlinks dealt with
+/// as they say; an ignored directory is left out whole. Ignore files deeper
+/// in the tree win over shallower ones and over the patterns given. Names
+/// match case-insensitively on Windows, as the file system does there.
+pub fn walk_with_rules(root: &Path, rules: &IgnoreRules) -> Result<WalkedTree, Error> {
+    let mut walked = WalkedTree::default();
+    let mut given = Vec::new();
+    if !rules.patterns.is_empty() {
+        let mut builder = ignore_builder(root)?;
+        for pattern in &rules.patterns {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| DeskShareError::InvalidConfig(format!("bad ignore pattern {:?}: {}", pattern, e)))?;
+        }
+        given.push(Arc::new(builder.build()?));
+    }
+    
+    // Where each directory really is, from the root down, to catch links
+    // that lead back to a directory they're inside
+    let ancestors = vec![root.canonicalize()?];
+    let mut pending = vec![(root.to_path_buf(), String::new(), given, ancestors)];
+    while let Some((dir, relative, mut matchers, ancestors)) = pending.pop() {
+        if rules.use_ignore_files {
+            matchers.extend(ignore_files_in(&dir)?.map(Arc::new));
+        }
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let path = if relative.is_empty() {
+                name.to_string_lossy().to_string()
+            } else {
+                format!("{}/{}", relative, name.to_string_lossy())
+            };
+            let mut file_type = entry.file_type()?;
+            let linked = file_type.is_symlink();
+            if linked {
+                match rules.symlinks {
+                    SymlinkPolicy::Skip => continue,
+                    SymlinkPolicy::PreserveAsLink => {
+                        if !is_ignored(&matchers, &entry.path(), false) {
+                            let target = std::fs::read_link(entry.path())?.to_string_lossy().to_string();
+                            walked.links.push(ManifestLink { path, target });
+                        }
+                        continue;
+                    }
+                    SymlinkPolicy::Follow => match std::fs::metadata(entry.path()) {
+                        Ok(metadata) => file_type = metadata.file_type(),
+                        Err(e) => {
+                            tracing::warn!("Leaving out {:?}, it leads nowhere: {}", entry.path(), e);
+                            continue;
+                        }
+                    },
+                }
+            }
+            if !file_type.is_dir() && !file_type.is_file() {
+                continue;
+            }
+            
+            if is_ignored(&matchers, &entry.path(), file_type.is_dir()) {
+                let (files, bytes) = tally(&entry.path())?;
+                walked.excluded_files += files;
+                walked.excluded_bytes += bytes;
+            } else if file_type.is_dir() {
+                let real = if linked { entry.path().canonicalize()? } else { ancestors[ancestors.len() - 1].join(&name) };
+                if ancestors.contains(&real) {
+                    tracing::warn!("Not following {:?}, it loops back to {:?}", entry.path(), real);
+                    continue;
+                }
+                let mut ancestors = ancestors.clone();
+                ancestors.push(real);
+                walked.directories.push(path.clone());
+                pending.push((entry.path(), path, matchers.clone(), ancestors));
+            } else {
+                walked.files.push(path);
+            }
+        }
+    }
+    
+    walked.directories.sort();
+    walked.files.sort();
+    walked.links.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(walked)
+}
+
+fn ignore_builder(root: &Path) -> Result<GitignoreBuilder, Error> {
+    let mut builder = GitignoreBuilder::new(root);
+    builder.case_insensitive(cfg!(windows))?;
+    Ok(builder)
+}
+
+/// The ignore files in `dir`, if it has any. Lines that don't parse are
+/// skipped with a warning rather than failing the share.
+fn ignore_files_in(dir: &Path) -> Result<Option<Gitignore>, Error> {
+    let found: Vec<_> = IGNORE_FILES.iter().map(|name| dir.join(name)).filter(|path| path.is_file()).collect();
+    if found.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = ignore_builder(dir)?;
+    for path in found {
+        if let Some(e) = builder.add(&path) {
+            tracing::warn!("Skipping part of {:?}: {}", path, e);
+        }
+    }
+    Ok(Some(builder.build()?))
+}
+
+/// The deepest rule that matches decides
+fn is_ignored(matchers: &[Arc<Gitignore>], path: &Path, is_dir: bool) -> bool {
+    for matcher in matchers.iter().rev() {
+        match matcher.matched(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+    }
+    false
+}
+
+/// Files and bytes at `path`, counting everything under it if it's a directory
+fn tally(path: &Path) -> Result<(usize, u64), Error> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    if metadata.is_file() {
+        return Ok((1, metadata.len()));
+    }
+    let mut totals = (0, 0);
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            let (files, bytes) = tally(&entry?.path())?;
+            totals = (totals.0 + files, totals.1 + bytes);
+        }
+    }
+    Ok(totals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_ignored_entries_are_left_out_at_any_depth() {
+        let root = std::env::temp_dir().join(format!("dsn-ignore-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for dir in ["src/node_modules/pkg", "web/node_modules", "target/debug", "docs"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        std::fs::write(root.join("src/main.rs"), b"fn main() {}").unwrap();
+        std::fs::write(root.join("src/node_modules/pkg/index.js"), vec![b'x'; 100]).unwrap();
+        std::fs::write(root.join("web/node_modules/left.js"), vec![b'x'; 20]).unwrap();
+        std::fs::write(root.join("target/debug/app"), vec![0u8; 300]).unwrap();
+        std::fs::write(root.join("docs/notes.log"), b"log").unwrap();
+        std::fs::write(root.join("docs/keep.log"), b"kept").unwrap();
+        std::fs::write(root.join(".gitignore"), b"/target/\n*.log\n").unwrap();
+        std::fs::write(root.join("docs/.dsnignore"), b"!keep.log\n").unwrap();
+        
+        let rules = IgnoreRules { patterns: vec!["node_modules/".to_string()], ..Default::default() };
+        let walked = walk_with_rules(&root, &rules).unwrap();
+        assert_eq!(walked.directories, vec!["docs", "src", "target", "target/debug", "web"]);
+        assert_eq!((walked.excluded_files, walked.excluded_bytes), (2, 120));
+        
+        // Ignore files in the tree add to the patterns, the deepest deciding
+        let walked = walk_with_rules(&root, &IgnoreRules { use_ignore_files: true, ..rules }).unwrap();
+        assert_eq!(walked.directories, vec!["docs", "src", "web"]);
+        assert_eq!(walked.files, vec![".gitignore", "docs/.dsnignore", "docs/keep.log", "src/main.rs"]);
+        assert_eq!((walked.excluded_files, walked.excluded_bytes), (4, 423));
+        
+        let bad = IgnoreRules { patterns: vec!["src/{a,b".to_string()], ..Default::default() };
+        assert!(walk_with_rules(&root, &bad).is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_follow_the_policy_without_looping() {
+        use std::os::unix::fs::symlink;
+        let base = std::env::temp_dir().join(format!("dsn-links-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&base);
+        let root = base.join("tree");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/readme.txt"), b"read me").unwrap();
+        std::fs::write(base.join("secret.txt"), b"not for sharing").unwrap();
+        symlink(base.join("secret.txt"), root.join("docs/outside.txt")).unwrap();
+        symlink("readme.txt", root.join("docs/same.txt")).unwrap();
+        symlink("..", root.join("docs/loop")).unwrap();
+        symlink("../docs", root.join("docs/inner")).unwrap();
+        
+        let walked = walk_with_rules(&root, &IgnoreRules::default()).unwrap();
+        assert_eq!(walked.directories, vec!["docs"]);
+        assert_eq!(walked.files, vec!["docs/readme.txt"]);
+        assert!(walked.links.is_empty());
+        
+        // Both links back up the tree are caught rather than walked forever
+        let follow = IgnoreRules { symlinks: SymlinkPolicy::Follow, ..Default::default() };
+        let walked = walk_with_rules(&root, &follow).unwrap();
+        assert_eq!(walked.directories, vec!["docs"]);
+        assert_eq!(walked.files, vec!["docs/outside.txt", "docs/readme.txt", "docs/same.txt"]);
+        
+        let preserve = IgnoreRules { symlinks: SymlinkPolicy::PreserveAsLink, ..Default::default() };
+        let walked = walk_with_rules(&root, &preserve).unwrap();
+        assert_eq!(walked.files, vec!["docs/readme.txt"]);
+        let links: Vec<_> = walked.links.iter().map(|link| (link.path.as_str(), link.stays_inside())).collect();
+        assert_eq!(links, vec![("docs/inner", true), ("docs/loop", true), ("docs/outside.txt", false), ("docs/same.txt", true)]);
+        let _ = std::fs::remove_dir_all(&base);
+    }
+}
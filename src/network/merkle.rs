@@ -0,0 +1,176 @@
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+
+/// Leaves and inner nodes are hashed apart, so neither can pass for the other
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// Merkle tree over a share's chunk hashes. A level with an odd node out
+/// carries it up unhashed.
+#[derive(Clone, Debug)]
+pub struct MerkleTree {
+    /// Leaves first, the root alone last
+    levels: Vec<Vec<[u8; 32]>>,
+}
+
+/// The sibling hashes from a chunk's leaf up to the root, enough to check
+/// that chunk against the root alone
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    /// Hex, nearest the leaf first; levels where the node is carried up
+    /// have none
+    pub siblings: Vec<String>,
+}
+
+impl MerkleTree {
+    pub fn from_chunk_hashes(chunks: &[String]) -> Result<Self, DeskShareError> {
+        if chunks.is_empty() {
+            return Err(DeskShareError::IntegrityCheckFailed);
+        }
+        let leaves = chunks
+            .iter()
+            .map(|chunk_hash| decode(chunk_hash).map(|chunk_hash| leaf(&chunk_hash)))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(DeskShareError::IntegrityCheckFailed)?;
+        
+        let mut levels = vec![leaves];
+        while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+            let parents = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => node(left, right),
+                    [carried] => *carried,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(parents);
+        }
+        Ok(Self { levels })
+    }
+    
+    pub fn root(&self) -> String {
+        hex::encode(self.levels[self.levels.len() - 1][0])
+    }
+    
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.levels[0].len() {
+            return None;
+        }
+        let mut siblings = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                siblings.push(hex::encode(sibling));
+            }
+            position /= 2;
+        }
+        Some(MerkleProof { index, siblings })
+    }
+}
+
+impl MerkleProof {
+    /// Whether `chunk_hash` is chunk `index` of the `total_chunks` under `root`
+    pub fn verify(&self, root: &str, chunk_hash: &str, total_chunks: usize) -> bool {
+        if self.index >= total_chunks {
+            return false;
+        }
+        let Some(mut hash) = decode(chunk_hash).map(|chunk_hash| leaf(&chunk_hash)) else {
+            return false;
+        };
+        let mut siblings = self.siblings.iter();
+        let (mut position, mut width) = (self.index, total_chunks);
+        while width > 1 {
+            let carried = position == width - 1 && width % 2 == 1;
+            if !carried {
+                let Some(sibling) = siblings.next().and_then(|sibling| decode(sibling)) else {
+                    return false;
+                };
+                hash = if position % 2 == 0 { node(&hash, &sibling) } else { node(&sibling, &hash) };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none() && decode(root) == Some(hash)
+    }
+}
+
+fn decode(hash: &str) -> Option<[u8; 32]> {
+    hex::decode(hash).ok()?.try_into().ok()
+}
+
+fn leaf(chunk_hash: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(chunk_hash);
+    *hasher.finalize().as_bytes()
+}
+
+fn node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn chunk_hashes(count: usize) -> Vec<String> {
+        (0..count).map(|i| blake3::hash(&i.to_le_bytes()).to_hex().to_string()).collect()
+    }
+    
+    #[test]
+    fn test_every_chunk_proves_against_the_root() {
+        for count in [1, 2, 3, 7, 8, 13] {
+            let chunks = chunk_hashes(count);
+            let tree = MerkleTree::from_chunk_hashes(&chunks).unwrap();
+            let root = tree.root();
+            for (index, chunk_hash) in chunks.iter().enumerate() {
+                let proof = tree.proof(index).unwrap();
+                assert!(proof.verify(&root, chunk_hash, count), "chunk {} of {}", index, count);
+                // Only in its own place
+                let other = &chunks[(index + 1) % count];
+                assert_eq!(proof.verify(&root, other, count), other == chunk_hash);
+            }
+            assert!(tree.proof(count).is_none());
+        }
+        
+        let single = chunk_hashes(1);
+        assert_ne!(MerkleTree::from_chunk_hashes(&single).unwrap().root(), single[0]);
+        assert!(MerkleTree::from_chunk_hashes(&[]).is_err());
+        assert!(MerkleTree::from_chunk_hashes(&["not hex".to_string()]).is_err());
+    }
+    
+    #[test]
+    fn test_tampered_proofs_are_rejected() {
+        let chunks = chunk_hashes(13);
+        let tree = MerkleTree::from_chunk_hashes(&chunks).unwrap();
+        let root = tree.root();
+        let proof = tree.proof(5).unwrap();
+        assert!(proof.verify(&root, &chunks[5], 13));
+        
+        let mut flipped = proof.clone();
+        let mut sibling = hex::decode(&flipped.siblings[1]).unwrap();
+        sibling[0] ^= 1;
+        flipped.siblings[1] = hex::encode(sibling);
+        assert!(!flipped.verify(&root, &chunks[5], 13));
+        
+        let mut short = proof.clone();
+        short.siblings.pop();
+        assert!(!short.verify(&root, &chunks[5], 13));
+        let mut long = proof.clone();
+        long.siblings.push(chunks[0].clone());
+        assert!(!long.verify(&root, &chunks[5], 13));
+        
+        let moved = MerkleProof { index: 4, ..proof.clone() };
+        assert!(!moved.verify(&root, &chunks[5], 13));
+        // The last chunk is carried up, so how many there are matters
+        assert!(!tree.proof(12).unwrap().verify(&root, &chunks[12], 14));
+        assert!(!proof.verify(&chunks[0], &chunks[5], 13));
+        assert!(!proof.verify(&root, "not hex", 13));
+    }
+}
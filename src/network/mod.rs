@@ -1,9 +1,65 @@
+pub mod adaptive;
+pub mod chunk_cache;
+pub mod chunk_pipeline;
+pub mod codec;
+#[cfg(test)]
+mod counting_alloc;
 pub mod discovery;
 pub mod file_transfer;
+pub mod frame_buffer;
+#[cfg(feature = "h264")]
+pub mod h264;
+pub mod hashing;
+pub mod history;
+pub mod idle;
+pub mod lan_announce;
+pub mod lan_frames;
+pub mod lan_session;
+pub mod lan_transfer;
+pub mod manifest;
+pub mod merkle;
 pub mod nat_traversal;
+pub mod observer;
+pub mod peer_stats;
+pub mod progress;
+pub mod quality_preset;
 pub mod screen_share;
+pub mod session_protocol;
+pub mod share_registry;
+pub mod stream_meter;
+pub mod throttle;
+pub mod timings;
+pub mod transfer_protocol;
+pub mod video;
 
+pub use adaptive::{Adaptation, AdaptiveConfig, DeliveryReport, QualityController, StreamSettings};
+pub use chunk_cache::{ChunkCache, ChunkCacheUsage, DEFAULT_CHUNK_CACHE_BYTES};
+pub use chunk_pipeline::{ChunkTransport, ChunkWindowConfig, ChunkWindows, MemoryChunkTransport, PeerWindowState, WindowSlot};
+pub use codec::ChunkCodec;
 pub use discovery::NetworkDiscovery;
-pub use file_transfer::FileTransfer;
-pub use nat_traversal::NatTraversal;
-pub use screen_share::ScreenShare;
\ No newline at end of file
+pub use file_transfer::{AutoAcceptDecision, ChunkEnvelope, ChunkOrder, CollisionPolicy, DestinationProgress, DirEntryInfo, FileTransfer, MultiSendProgress, OfferEvent, PendingOffer, ReceivedText, RemoteFile, ShareKind, SharedDirectory, SharedFile, SharedFileSummary, SignedAnnouncement, SignedRetraction, TaggedChunk, TransferHistoryEntry, TransferProgress, TransferStatus, UploadProgress, MANIFEST_VERSION_FLAT, MANIFEST_VERSION_MERKLE, TEXT_INLINE_LIMIT};
+pub use frame_buffer::{BufferBudget, BufferUsage, FrameBuffer, FrameBufferConfig};
+#[cfg(feature = "h264")]
+pub use h264::{H264Decoder, H264Encoder};
+pub use hashing::{ContentHasher, HashAlgorithm};
+pub use history::{TransferDirection, TransferHistory, TransferRecord};
+pub use idle::{CaptureConfig, FrameChange, IdleDetector};
+pub use lan_announce::{LanAnnouncer, SessionBeacon, ANNOUNCEMENT_TTL, ANNOUNCE_INTERVAL, DEFAULT_ANNOUNCE_PORT};
+pub use lan_frames::{LanFrameLinks, DEFAULT_SCREEN_PORT};
+pub use lan_session::{LanSessionTransport, SessionMessage};
+pub use lan_transfer::{LanChunkTransport, DEFAULT_TRANSFER_PORT};
+pub use manifest::{DirectoryManifest, IgnoreRules, ManifestFile, ManifestLink, SymlinkPolicy};
+pub use merkle::{MerkleProof, MerkleTree};
+pub use nat_traversal::{ExternalAddress, NatTraversal, NatType, StunHealth, StunServer, TurnServer};
+pub use observer::{ChannelObserver, TransferEvent, TransferObserver};
+pub use peer_stats::PeerTransferStats;
+pub use progress::{Flush, ProgressAccumulator};
+pub use quality_preset::{QualityPreset, StreamOverrides};
+pub use screen_share::{Frame, FrameHeader, FrameTraffic, Media, MonitorLost, Participant, ParticipantChange, PendingJoin, RemoteSession, ScreenShare, SessionId, SessionStats, SessionStatus, SessionStatusChange, SessionSummary, SharingSession, ViewerStats};
+pub use session_protocol::{AccessMode, ControlAction, ControlMessage, InputEvent, JoinRequest, JoinResponse, SessionAnnouncement, SessionEnded, SessionToken, SessionTransport, TokenGrant, FRAME_FORMAT_VERSION};
+pub use share_registry::{PersistedShare, ShareRegistry, ShareSource};
+pub use stream_meter::{StreamMeter, METER_WINDOW};
+pub use throttle::{BandwidthConfig, Throttle};
+pub use timings::StageTimings;
+pub use transfer_protocol::{FileTransferMessage, PROTOCOL_VERSION};
+pub use video::{FrameCodec, TileDecoder, TileEncoder, VideoDecoder, VideoEncoder, VideoPacket};
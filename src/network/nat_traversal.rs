@@ -1,10 +1,14 @@
 use anyhow::Error;
+use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use std::net::{IpAddr, SocketAddr};
-use std::time::{Duration, SystemTime};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 
+use crate::error::DeskShareError;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IceCandidate {
     pub candidate_type: CandidateType,
@@ -41,11 +45,51 @@ pub struct TurnServer {
     pub password: String,
 }
 
+impl StunServer {
+    pub fn endpoint(&self) -> String {
+        format!("{}:{}", self.address, self.port)
+    }
+}
+
+/// NAT behaviour inferred from the addresses STUN servers report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NatType {
+    /// No STUN server has answered yet
+    Unknown,
+    /// The mapped address is our own; no NAT in the way
+    Open,
+    /// Every server sees the same mapping
+    Cone,
+    /// Mappings differ per destination; direct connections will mostly fail
+    Symmetric,
+}
+
+/// Running results for one STUN server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StunHealth {
+    pub server: String,
+    pub successes: u32,
+    pub failures: u32,
+    pub last_rtt_ms: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+/// Our address as seen from outside; `confidence` is the share of
+/// answering STUN servers that reported it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExternalAddress {
+    pub address: String,
+    pub port: u16,
+    pub confidence: f32,
+}
+
 pub struct NatTraversal {
     stun_servers: Vec<StunServer>,
     turn_servers: Vec<TurnServer>,
     local_ip: IpAddr,
     socket: Option<UdpSocket>,
+    stun_health: Arc<DashMap<String, StunHealth>>,
+    mappings: Arc<DashMap<String, (IpAddr, u16)>>,
 }
 
 impl NatTraversal {
@@ -61,9 +105,84 @@ impl NatTraversal {
             turn_servers: vec![], // Can be configured
             local_ip,
             socket: None,
+            stun_health: Arc::new(DashMap::new()),
+            mappings: Arc::new(DashMap::new()),
         })
     }
     
+    pub fn local_ip(&self) -> IpAddr {
+        self.local_ip
+    }
+    
+    pub fn stun_servers(&self) -> &[StunServer] {
+        &self.stun_servers
+    }
+    
+    pub fn turn_servers(&self) -> &[TurnServer] {
+        &self.turn_servers
+    }
+    
+    /// Health of every configured STUN server, in configuration order
+    pub fn stun_health(&self) -> Vec<StunHealth> {
+        self.stun_servers
+            .iter()
+            .map(|server| {
+                let endpoint = server.endpoint();
+                self.stun_health
+                    .get(&endpoint)
+                    .map(|health| health.clone())
+                    .unwrap_or(StunHealth { server: endpoint, ..Default::default() })
+            })
+            .collect()
+    }
+    
+    /// Distinct mapped addresses, most widely reported first
+    pub fn external_addresses(&self) -> Vec<ExternalAddress> {
+        let total = self.mappings.len();
+        let mut counts: Vec<((IpAddr, u16), usize)> = Vec::new();
+        for mapping in self.mappings.iter() {
+            match counts.iter_mut().find(|(addr, _)| addr == mapping.value()) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((*mapping.value(), 1)),
+            }
+        }
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        
+        counts
+            .into_iter()
+            .map(|((ip, port), count)| ExternalAddress {
+                address: ip.to_string(),
+                port,
+                confidence: count as f32 / total as f32,
+            })
+            .collect()
+    }
+    
+    pub fn nat_type(&self) -> NatType {
+        let addresses = self.external_addresses();
+        match addresses.as_slice() {
+            [] => NatType::Unknown,
+            [only] if only.address == self.local_ip.to_string() => NatType::Open,
+            [_] => NatType::Cone,
+            _ => NatType::Symmetric,
+        }
+    }
+    
+    /// Ask each STUN server in turn for our external address; the first answer wins
+    pub async fn query_stun(&self) -> Result<ExternalAddress, Error> {
+        for stun_server in &self.stun_servers {
+            if let Ok(candidate) = self.get_stun_candidate(stun_server).await {
+                return Ok(ExternalAddress {
+                    address: candidate.address,
+                    port: candidate.port,
+                    confidence: 1.0,
+                });
+            }
+        }
+        
+        Err(DeskShareError::NatTraversalFailed("no STUN server answered".to_string()).into())
+    }
+    
     /// Add custom STUN servers
     pub fn add_stun_server(&mut self, address: String, port: u16) {
         self.stun_servers.push(StunServer { address, port });
@@ -104,8 +223,43 @@ impl NatTraversal {
         Ok(candidates)
     }
     
-    /// Get server reflexive candidate using STUN
+    /// Get server reflexive candidate using STUN, recording the server's health
     async fn get_stun_candidate(&self, stun_server: &StunServer) -> Result<IceCandidate, Error> {
+        let started = Instant::now();
+        let result = self.request_stun_candidate(stun_server).await;
+        let mapped = result
+            .as_ref()
+            .ok()
+            .and_then(|candidate| Some((candidate.address.parse().ok()?, candidate.port)));
+        let outcome = match (&result, mapped) {
+            (Ok(_), Some(mapped)) => Ok((mapped, started.elapsed())),
+            (Err(e), _) => Err(e.to_string()),
+            (Ok(_), None) => Err("unparseable mapped address".to_string()),
+        };
+        self.record_stun_result(&stun_server.endpoint(), outcome);
+        result
+    }
+    
+    fn record_stun_result(&self, endpoint: &str, outcome: Result<((IpAddr, u16), Duration), String>) {
+        let mut health = self
+            .stun_health
+            .entry(endpoint.to_string())
+            .or_insert_with(|| StunHealth { server: endpoint.to_string(), ..Default::default() });
+        match outcome {
+            Ok((mapped, rtt)) => {
+                health.successes += 1;
+                health.last_rtt_ms = Some(rtt.as_millis() as u64);
+                health.last_error = None;
+                self.mappings.insert(endpoint.to_string(), mapped);
+            }
+            Err(error) => {
+                health.failures += 1;
+                health.last_error = Some(error);
+            }
+        }
+    }
+    
+    async fn request_stun_candidate(&self, stun_server: &StunServer) -> Result<IceCandidate, Error> {
         let socket = UdpSocket::bind("0.0.0.0:0").await?;
         socket.set_broadcast(true)?;
         
@@ -113,15 +267,13 @@ impl NatTraversal {
         let stun_request = self.create_stun_binding_request();
         
         // Send to STUN server
-        let addr = format!("{}:{}", stun_server.address, stun_server.port);
-        socket.send_to(&stun_request, addr).await?;
+        socket.send_to(&stun_request, stun_server.endpoint()).await?;
         
         // Receive response
         let mut buf = [0u8; 1024];
-        socket.set_read_timeout(Some(Duration::from_secs(5)))?;
         
-        match socket.recv_from(&mut buf).await {
-            Ok((len, _)) => {
+        match tokio::time::timeout(Duration::from_secs(5), socket.recv_from(&mut buf)).await {
+            Ok(Ok((len, _))) => {
                 if let Some((mapped_ip, mapped_port)) = self.parse_stun_response(&buf[..len]) {
                     return Ok(IceCandidate {
                         candidate_type: CandidateType::Srflx,
@@ -132,8 +284,8 @@ impl NatTraversal {
                     });
                 }
             }
-            Err(_) => {
-                // STUN failed, return error
+            _ => {
+                // STUN failed or timed out, return error
                 return Err(anyhow::anyhow!("STUN request failed"));
             }
         }
@@ -250,11 +402,10 @@ impl NatTraversal {
         
         // Wait for response
         let mut buf = [0u8; 1024];
-        socket.set_read_timeout(Some(Duration::from_secs(3)))?;
         
-        match socket.recv_from(&mut buf).await {
-            Ok(_) => Ok(true),
-            Err(_) => Ok(false),
+        match tokio::time::timeout(Duration::from_secs(3), socket.recv_from(&mut buf)).await {
+            Ok(Ok(_)) => Ok(true),
+            _ => Ok(false),
         }
     }
     
@@ -267,4 +418,41 @@ impl NatTraversal {
             turn_server.port,
         ))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn mapped(ip: &str, port: u16) -> Result<((IpAddr, u16), Duration), String> {
+        Ok(((ip.parse().unwrap(), port), Duration::from_millis(20)))
+    }
+    
+    #[tokio::test]
+    async fn test_nat_type_from_stun_mappings() {
+        let nat = NatTraversal::new().await.unwrap();
+        assert_eq!(nat.nat_type(), NatType::Unknown);
+        
+        nat.record_stun_result("stun.l.google.com:19302", mapped("203.0.113.7", 40000));
+        nat.record_stun_result("stun1.l.google.com:19302", mapped("203.0.113.7", 40000));
+        nat.record_stun_result("stun2.l.google.com:19302", Err("timed out".to_string()));
+        assert_eq!(nat.nat_type(), NatType::Cone);
+        assert_eq!(nat.external_addresses(), vec![ExternalAddress {
+            address: "203.0.113.7".to_string(),
+            port: 40000,
+            confidence: 1.0,
+        }]);
+        
+        let health = nat.stun_health();
+        assert_eq!(health.len(), 3);
+        assert_eq!((health[0].successes, health[0].last_rtt_ms), (1, Some(20)));
+        assert_eq!(health[2].failures, 1);
+        assert_eq!(health[2].last_error.as_deref(), Some("timed out"));
+        
+        // A different port towards another server means per-destination mappings
+        nat.record_stun_result("stun2.l.google.com:19302", mapped("203.0.113.7", 40001));
+        assert_eq!(nat.nat_type(), NatType::Symmetric);
+        assert_eq!(nat.external_addresses()[0].port, 40000);
+        assert!((nat.external_addresses()[0].confidence - 2.0 / 3.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,94 @@
+use tokio::sync::mpsc;
+
+use super::file_transfer::TransferProgress;
+
+/// Callbacks for an application embedding the library, as transfers move
+/// along. They're called after the transfer's own state is updated and no
+/// lock is held, so they see the same snapshot the progress stream does;
+/// still, a slow one delays the transfer that called it, so hand heavy work
+/// off elsewhere.
+///
+/// Shares show up as completed the moment they're offered, as they do in
+/// the progress stream. Pauses and cancellations come through `on_progress`
+/// with the new status.
+pub trait TransferObserver: Send + Sync {
+    /// A transfer appeared, queued or under way
+    fn on_started(&self, _progress: &TransferProgress) {}
+    
+    fn on_progress(&self, _progress: &TransferProgress) {}
+    
+    /// Fetching a chunk from a peer failed; it's retried unless the whole
+    /// transfer fails
+    fn on_chunk_failed(&self, _file_hash: &str, _chunk_index: usize, _peer_id: &str, _reason: &str) {}
+    
+    fn on_completed(&self, _progress: &TransferProgress) {}
+    
+    /// `reason` is missing when a folder failed because one of its files did
+    fn on_failed(&self, _progress: &TransferProgress, _reason: Option<&str>) {}
+}
+
+/// One observer callback, as sent by a `ChannelObserver`
+#[derive(Clone, Debug)]
+pub enum TransferEvent {
+    Started(TransferProgress),
+    Progress(TransferProgress),
+    ChunkFailed {
+        file_hash: String,
+        chunk_index: usize,
+        peer_id: String,
+        reason: String,
+    },
+    Completed(TransferProgress),
+    Failed {
+        progress: TransferProgress,
+        reason: Option<String>,
+    },
+}
+
+/// Turns callbacks into events on an unbounded channel, for callers that
+/// would rather receive than implement the trait
+pub struct ChannelObserver {
+    tx: mpsc::UnboundedSender<TransferEvent>,
+}
+
+impl ChannelObserver {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<TransferEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self { tx }, rx)
+    }
+    
+    fn send(&self, event: TransferEvent) {
+        // Nobody receiving any more is fine
+        let _ = self.tx.send(event);
+    }
+}
+
+impl TransferObserver for ChannelObserver {
+    fn on_started(&self, progress: &TransferProgress) {
+        self.send(TransferEvent::Started(progress.clone()));
+    }
+    
+    fn on_progress(&self, progress: &TransferProgress) {
+        self.send(TransferEvent::Progress(progress.clone()));
+    }
+    
+    fn on_chunk_failed(&self, file_hash: &str, chunk_index: usize, peer_id: &str, reason: &str) {
+        self.send(TransferEvent::ChunkFailed {
+            file_hash: file_hash.to_string(),
+            chunk_index,
+            peer_id: peer_id.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+    
+    fn on_completed(&self, progress: &TransferProgress) {
+        self.send(TransferEvent::Completed(progress.clone()));
+    }
+    
+    fn on_failed(&self, progress: &TransferProgress, reason: Option<&str>) {
+        self.send(TransferEvent::Failed {
+            progress: progress.clone(),
+            reason: reason.map(str::to_string),
+        });
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+/// Everything transferred with one peer since the stats were last reset,
+/// across all transfers
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeerTransferStats {
+    pub peer_id: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub chunks_received: u64,
+    /// Mean time from asking the peer for a chunk to having it
+    pub average_rtt_ms: f64,
+    /// Chunk requests that failed or timed out
+    pub failed_requests: u64,
+    /// Chunks the round-trip mean is over; pushed chunks have no round trip
+    #[serde(skip)]
+    rtt_samples: u64,
+}
+
+impl PeerTransferStats {
+    pub fn new(peer_id: &str) -> Self {
+        Self {
+            peer_id: peer_id.to_string(),
+            ..Self::default()
+        }
+    }
+    
+    pub fn record_sent(&mut self, bytes: u64) {
+        self.bytes_sent += bytes;
+    }
+    
+    pub fn record_received(&mut self, bytes: u64, rtt: Option<Duration>) {
+        self.bytes_received += bytes;
+        self.chunks_received += 1;
+        if let Some(rtt) = rtt {
+            self.rtt_samples += 1;
+            let ms = rtt.as_secs_f64() * 1000.0;
+            self.average_rtt_ms += (ms - self.average_rtt_ms) / self.rtt_samples as f64;
+        }
+    }
+    
+    pub fn record_failure(&mut self) {
+        self.failed_requests += 1;
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A transfer's progress is flushed at most this often, however fast its
+/// chunks arrive; state changes flush straight away
+const FLUSH_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Rates are measured over about this long, so they follow the transfer's
+/// current speed rather than its average since it started
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Bytes received by one transfer. Chunk arrivals add to it atomically; the
+/// shared progress and its event only catch up every 100 ms, so a fast
+/// transfer sends ten updates a second rather than one per chunk, and one
+/// flusher at a time does that.
+#[derive(Debug)]
+pub struct ProgressAccumulator {
+    received: AtomicU64,
+    /// Bytes as of the last flush, and when that was (ms after `started_at`)
+    flushed: AtomicU64,
+    flushed_at_ms: AtomicU64,
+    flushing: AtomicBool,
+    started_at: Instant,
+    /// Bytes as of each recent flush, by µs after `started_at`; only the
+    /// flusher touches it
+    samples: Mutex<VecDeque<(u64, u64)>>,
+}
+
+impl Default for ProgressAccumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressAccumulator {
+    pub fn new() -> Self {
+        Self {
+            received: AtomicU64::new(0),
+            flushed: AtomicU64::new(0),
+            flushed_at_ms: AtomicU64::new(0),
+            flushing: AtomicBool::new(false),
+            started_at: Instant::now(),
+            samples: Mutex::new(VecDeque::from([(0, 0)])),
+        }
+    }
+    
+    /// For a transfer picking up where it left off; what it already had
+    /// doesn't count toward its rate
+    pub fn starting_at(bytes: u64) -> Self {
+        let accumulator = Self::new();
+        accumulator.received.store(bytes, Ordering::Release);
+        accumulator.flushed.store(bytes, Ordering::Release);
+        *accumulator.samples.lock().unwrap() = VecDeque::from([(0, bytes)]);
+        accumulator
+    }
+    
+    pub fn add(&self, bytes: u64) {
+        self.received.fetch_add(bytes, Ordering::AcqRel);
+    }
+    
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Acquire)
+    }
+    
+    /// Start the count over, e.g. when the chunks being downloaded change
+    pub fn reset(&self) {
+        self.received.store(0, Ordering::Release);
+        self.flushed.store(0, Ordering::Release);
+        *self.samples.lock().unwrap() = VecDeque::from([(self.elapsed_us(), 0)]);
+    }
+    
+    /// A flush, if one is due and nobody else is flushing
+    pub fn try_flush(&self) -> Option<Flush<'_>> {
+        if !self.due() {
+            return None;
+        }
+        self.flushing.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).ok()?;
+        Some(self.begin_flush())
+    }
+    
+    /// Flush whatever is pending, for state changes the UI mustn't miss. A
+    /// flush under way is waited out; none is ever held across an await.
+    pub fn flush(&self) -> Flush<'_> {
+        while self.flushing.compare_exchange_weak(false, true, Ordering::AcqRel, Ordering::Acquire).is_err() {
+            std::thread::yield_now();
+        }
+        self.begin_flush()
+    }
+    
+    fn due(&self) -> bool {
+        let since_flush = self.elapsed_ms().saturating_sub(self.flushed_at_ms.load(Ordering::Acquire));
+        since_flush >= FLUSH_INTERVAL.as_millis() as u64
+    }
+    
+    fn begin_flush(&self) -> Flush<'_> {
+        let bytes = self.received();
+        Flush {
+            accumulator: self,
+            bytes,
+            bytes_per_second: self.rate_at(self.elapsed_us(), bytes),
+        }
+    }
+    
+    /// Bytes per second from the oldest sample still inside the window to
+    /// `bytes` at `now_us`, recording that as a sample too; None until time
+    /// has passed since the first
+    fn rate_at(&self, now_us: u64, bytes: u64) -> Option<f64> {
+        let window_us = RATE_WINDOW.as_micros() as u64;
+        let mut samples = self.samples.lock().unwrap();
+        // The newest sample at least a window old stays as the baseline
+        while samples.len() > 1 && now_us.saturating_sub(samples[1].0) >= window_us {
+            samples.pop_front();
+        }
+        let (since_us, since_bytes) = samples.front().copied().unwrap_or((0, 0));
+        samples.push_back((now_us, bytes));
+        let span_us = now_us.saturating_sub(since_us);
+        (span_us > 0).then(|| bytes.saturating_sub(since_bytes) as f64 * 1_000_000.0 / span_us as f64)
+    }
+    
+    fn elapsed_ms(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+    
+    fn elapsed_us(&self) -> u64 {
+        self.started_at.elapsed().as_micros() as u64
+    }
+}
+
+/// The right to publish a transfer's progress; given up when dropped
+pub struct Flush<'a> {
+    accumulator: &'a ProgressAccumulator,
+    /// Bytes received as of this flush
+    pub bytes: u64,
+    /// Over the last few seconds; None if no time has passed to measure
+    pub bytes_per_second: Option<f64>,
+}
+
+impl Drop for Flush<'_> {
+    fn drop(&mut self) {
+        self.accumulator.flushed.store(self.bytes, Ordering::Release);
+        self.accumulator.flushed_at_ms.store(self.accumulator.elapsed_ms(), Ordering::Release);
+        self.accumulator.flushing.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    
+    #[test]
+    fn test_flushes_come_once_per_interval() {
+        let accumulator = ProgressAccumulator::new();
+        let started = Instant::now();
+        let (mut flushes, mut last_at) = (0u64, Instant::now());
+        while started.elapsed() < Duration::from_millis(550) {
+            accumulator.add(1);
+            if accumulator.try_flush().is_some() {
+                // Never early, however many chunks piled up
+                assert!(last_at.elapsed() >= FLUSH_INTERVAL - Duration::from_millis(1));
+                flushes += 1;
+                last_at = Instant::now();
+            }
+            std::thread::sleep(Duration::from_micros(50));
+        }
+        assert!((4..=5).contains(&flushes), "{} flushes", flushes);
+    }
+    
+    #[test]
+    fn test_rate_follows_the_last_few_seconds() {
+        let accumulator = ProgressAccumulator::new();
+        let second = 1_000_000;
+        // Fast for ten seconds, then stalled
+        for s in 1..=10 {
+            assert_eq!(accumulator.rate_at(s * second, s * 4_000_000), Some(4_000_000.0));
+        }
+        let rates: Vec<f64> = (11..=15).map(|s| accumulator.rate_at(s * second, 40_000_000).unwrap()).collect();
+        assert_eq!(rates, vec![3_200_000.0, 2_400_000.0, 1_600_000.0, 800_000.0, 0.0]);
+        assert!(accumulator.samples.lock().unwrap().len() <= 6);
+        // Nothing to measure before any time has passed
+        assert_eq!(ProgressAccumulator::new().rate_at(0, 0), None);
+    }
+    
+    #[test]
+    fn test_concurrent_adds_total_exactly() {
+        let accumulator = Arc::new(ProgressAccumulator::new());
+        let flushed = Arc::new(AtomicU64::new(0));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (accumulator, flushed) = (accumulator.clone(), flushed.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        accumulator.add(3);
+                        if let Some(flush) = accumulator.try_flush() {
+                            // One flusher at a time, so counts never go backwards
+                            assert!(flushed.fetch_max(flush.bytes, Ordering::AcqRel) <= flush.bytes);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        
+        assert_eq!(accumulator.flush().bytes, 8 * 10_000 * 3);
+        assert!(accumulator.try_flush().is_none());
+    }
+}
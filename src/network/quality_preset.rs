@@ -0,0 +1,84 @@
+use serde::{Serialize, Deserialize};
+
+use crate::platform::DEFAULT_JPEG_QUALITY;
+use super::adaptive::StreamSettings;
+
+/// Named trade-offs between picture and bandwidth, for hosts who'd rather
+/// not pick a frame rate, size and JPEG quality themselves. Tiled video is
+/// lossless whatever the preset, so quality only touches JPEG frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QualityPreset {
+    /// Half size at 10fps, for slow links
+    Low,
+    #[default]
+    Balanced,
+    High,
+    /// JPEG at full quality, as close to the screen itself as it gets
+    Lossless,
+}
+
+impl QualityPreset {
+    pub fn settings(self) -> StreamSettings {
+        let (quality, frame_rate, scale_percent) = match self {
+            QualityPreset::Low => (50, 10, 50),
+            QualityPreset::Balanced => (DEFAULT_JPEG_QUALITY, 15, 100),
+            QualityPreset::High => (90, 30, 100),
+            QualityPreset::Lossless => (100, 30, 100),
+        };
+        StreamSettings { quality, frame_rate, scale_percent }
+    }
+    
+    /// The preset's settings with any of `overrides` in their place
+    pub fn with_overrides(self, overrides: StreamOverrides) -> StreamSettings {
+        let preset = self.settings();
+        StreamSettings {
+            quality: overrides.quality.unwrap_or(preset.quality),
+            frame_rate: overrides.frame_rate.unwrap_or(preset.frame_rate),
+            scale_percent: overrides.scale_percent.unwrap_or(preset.scale_percent),
+        }
+    }
+}
+
+/// Settings a host picked explicitly; they win over the preset's
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamOverrides {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frame_rate: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale_percent: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_presets_expand_and_overrides_win() {
+        let expanded = [QualityPreset::Low, QualityPreset::Balanced, QualityPreset::High, QualityPreset::Lossless]
+            .map(|preset| {
+                let settings = preset.settings();
+                (settings.quality, settings.frame_rate, settings.scale_percent)
+            });
+        assert_eq!(expanded, [(50, 10, 50), (80, 15, 100), (90, 30, 100), (100, 30, 100)]);
+        assert_eq!(QualityPreset::Low.settings().resolution((1920, 1080)), (960, 540));
+        
+        // No overrides is the preset itself, and each override only replaces its own setting
+        assert_eq!(QualityPreset::High.with_overrides(StreamOverrides::default()), QualityPreset::High.settings());
+        let overrides = StreamOverrides { frame_rate: Some(60), ..StreamOverrides::default() };
+        assert_eq!(
+            QualityPreset::Low.with_overrides(overrides),
+            StreamSettings { quality: 50, frame_rate: 60, scale_percent: 50 }
+        );
+        let overrides = StreamOverrides { quality: Some(65), frame_rate: None, scale_percent: Some(75) };
+        assert_eq!(
+            QualityPreset::Lossless.with_overrides(overrides),
+            StreamSettings { quality: 65, frame_rate: 30, scale_percent: 75 }
+        );
+        
+        assert_eq!(serde_json::to_string(&QualityPreset::Lossless).unwrap(), "\"lossless\"");
+        assert_eq!(serde_json::to_string(&StreamOverrides { frame_rate: Some(5), ..Default::default() }).unwrap(), r#"{"frame_rate":5}"#);
+    }
+}
@@ -0,0 +1,381 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot};
+use anyhow::Error;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+use crate::p2p::LanStream;
+use crate::security::{PeerIdentity, ProtocolClass};
+use crate::network::session_protocol::{AccessMode, ControlMessage, JoinRequest, JoinResponse, SessionToken, TokenGrant};
+use crate::network::lan_session::SessionMessage;
+
+use super::{ScreenShare, ViewerGrant};
+
+/// A join waiting for the host's approval
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingJoin {
+    pub request_id: String,
+    pub session_id: String,
+    pub peer_id: String,
+}
+
+impl ScreenShare {
+    /// Change who may join; Password mode needs a password
+    pub async fn set_access(
+        &self,
+        session_id: &str,
+        access_mode: AccessMode,
+        password: Option<String>,
+    ) -> Result<(), Error> {
+        let password_hash = match (access_mode, password) {
+            (AccessMode::Password, Some(password)) if !password.is_empty() => {
+                Some(Self::hash_password(session_id, &password))
+            }
+            (AccessMode::Password, _) => {
+                return Err(DeskShareError::InvalidConfig("a password is required".to_string()).into());
+            }
+            _ => None,
+        };
+        
+        {
+            let mut sessions = self.sessions.write().await;
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+            session.access_mode = access_mode;
+        }
+        
+        let mut passwords = self.passwords.write().await;
+        match password_hash {
+            Some(hash) => passwords.insert(session_id.to_string(), hash),
+            None => passwords.remove(session_id),
+        };
+        drop(passwords);
+        
+        self.announce_session(session_id).await
+    }
+    
+    /// Host side of a join: check the password or wait for the user's
+    /// approval. The request's peer id is only a claim here, so nobody
+    /// skips approval; see `handle_authenticated_join`.
+    pub async fn handle_join_request(&self, request: JoinRequest) -> JoinResponse {
+        self.admit_join(request, None).await
+    }
+    
+    /// A join arriving on a connection whose handshake proved who `viewer`
+    /// is. Asking under any other peer id is denied; a paired device may
+    /// skip approval.
+    pub async fn handle_authenticated_join(&self, request: JoinRequest, viewer: &PeerIdentity) -> JoinResponse {
+        if request.peer_id != viewer.peer_id {
+            tracing::warn!(
+                "Device {} at {} asked to join session {} as {}",
+                viewer.device_id, viewer.peer_id, request.session_id, request.peer_id
+            );
+            return JoinResponse::Denied;
+        }
+        self.admit_join(request, Some(viewer)).await
+    }
+    
+    async fn admit_join(&self, request: JoinRequest, viewer: Option<&PeerIdentity>) -> JoinResponse {
+        let blocked_device = viewer.is_some_and(|viewer| self.refuses(&viewer.device_id));
+        if blocked_device || self.refuses(&request.peer_id) || !self.admits(&request.peer_id, ProtocolClass::Signaling) {
+            return JoinResponse::Denied;
+        }
+        let Some(session) = self.get_session(&request.session_id).await else {
+            return JoinResponse::SessionNotFound;
+        };
+        // Not worth asking the host about
+        if session.is_full(&request.peer_id) {
+            tracing::info!("Session {} is full, turning {} away", request.session_id, request.peer_id);
+            return JoinResponse::SessionFull;
+        }
+        
+        let admitted = match session.access_mode {
+            AccessMode::Open => true,
+            AccessMode::Password => {
+                let expected = self.passwords.read().await.get(&request.session_id).copied();
+                let given = request
+                    .password
+                    .as_deref()
+                    .map(|password| Self::hash_password(&request.session_id, password));
+                // blake3::Hash compares in constant time
+                if expected.is_none() || given != expected {
+                    tracing::warn!("Wrong password from {} for session {}", request.peer_id, request.session_id);
+                    return JoinResponse::WrongPassword;
+                }
+                true
+            }
+            AccessMode::Approval if viewer.is_some_and(|viewer| self.skips_approval(viewer)) => {
+                tracing::info!("Admitting trusted peer {} to session {}", request.peer_id, request.session_id);
+                true
+            }
+            AccessMode::Approval => self.wait_for_approval(&request).await,
+        };
+        
+        if !admitted {
+            return JoinResponse::Denied;
+        }
+        
+        let (resolution, codec, joined) = {
+            let mut sessions = self.sessions.write().await;
+            match sessions.get_mut(&request.session_id) {
+                // Filled up while we were waiting for approval
+                Some(session) if session.is_full(&request.peer_id) => return JoinResponse::SessionFull,
+                Some(session) => {
+                    let joined = session.participants.insert(request.peer_id.clone()).then_some(session.participants.len());
+                    let codec = session.codec.fallback_for(&request.codecs);
+                    if codec != session.codec {
+                        tracing::info!(
+                            "{} can't decode {:?}, session {} goes out as {:?}",
+                            request.peer_id, session.codec, request.session_id, codec
+                        );
+                        session.codec = codec;
+                    }
+                    (session.resolution, session.codec, joined)
+                }
+                // Stopped while we were waiting for approval
+                None => return JoinResponse::SessionNotFound,
+            }
+        };
+        
+        let token = SessionToken::generate();
+        let grant = ViewerGrant {
+            audio: request.audio,
+            display_name: request.display_name.as_deref().and_then(Self::clean_display_name),
+            ..ViewerGrant::new(token.clone())
+        };
+        self.grants
+            .write()
+            .await
+            .entry(request.session_id.clone())
+            .or_default()
+            .insert(request.peer_id.clone(), grant);
+        
+        if let Some(participants) = joined {
+            self.notify_participants(&request.session_id, &request.peer_id, true, participants);
+        }
+        let _ = self.announce_session(&request.session_id).await;
+        JoinResponse::Accepted { resolution, token, codec }
+    }
+    
+    /// Serve a connection a viewer opened to our screen port, as its first
+    /// message asks: frames or notices go over it from then on, or its joins
+    /// and control messages are answered until it closes. The viewer is
+    /// known by the address it connected from, whatever it calls itself.
+    pub async fn accept_connection(&self, mut stream: LanStream, peer_id: String) -> Result<(), Error> {
+        let transport = self
+            .lan_transport
+            .clone()
+            .ok_or_else(|| DeskShareError::InvalidConfig("sessions aren't served to other devices".to_string()))?;
+        let viewer = stream.remote_device_id().map(|device_id| PeerIdentity { peer_id: peer_id.clone(), device_id });
+        loop {
+            let message = match SessionMessage::recv(&mut stream).await {
+                Ok(message) => message,
+                Err(e) => {
+                    tracing::debug!("Session connection from {} closed: {}", peer_id, e);
+                    return Ok(());
+                }
+            };
+            let answer = match message {
+                SessionMessage::WatchFrames => {
+                    let links = self
+                        .frame_links
+                        .clone()
+                        .ok_or_else(|| DeskShareError::InvalidConfig("frames aren't sent to other devices".to_string()))?;
+                    SessionMessage::Done(Ok(())).send(&mut stream).await?;
+                    links.attach(&peer_id, stream);
+                    return Ok(());
+                }
+                SessionMessage::WatchNotices => {
+                    SessionMessage::Done(Ok(())).send(&mut stream).await?;
+                    transport.attach_viewer(&peer_id, stream);
+                    return Ok(());
+                }
+                SessionMessage::Join(request) => {
+                    let request = JoinRequest { peer_id: peer_id.clone(), ..request };
+                    SessionMessage::Joined(match &viewer {
+                        Some(viewer) => self.handle_authenticated_join(request, viewer).await,
+                        None => self.handle_join_request(request).await,
+                    })
+                }
+                SessionMessage::Control(message) => {
+                    let message = ControlMessage { peer_id: peer_id.clone(), ..message };
+                    SessionMessage::Done(self.handle_control(message).await.map_err(|e| {
+                        e.downcast_ref::<DeskShareError>().map_or("internal", DeskShareError::code).to_string()
+                    }))
+                }
+                other => {
+                    tracing::warn!("Unexpected {:?} from {} on a session connection", other, peer_id);
+                    return Err(DeskShareError::InvalidMessageFormat.into());
+                }
+            };
+            answer.send(&mut stream).await?;
+        }
+    }
+    
+    /// Replace every viewer's token; viewers subscribe again with the new one
+    pub(super) async fn rotate_tokens(&self, session_id: &str) {
+        let issued: Vec<(String, SessionToken)> = {
+            let mut grants = self.grants.write().await;
+            let Some(viewers) = grants.get_mut(session_id) else {
+                return;
+            };
+            viewers
+                .iter_mut()
+                .map(|(peer_id, grant)| {
+                    grant.token = SessionToken::generate();
+                    grant.subscribed = false;
+                    (peer_id.clone(), grant.token.clone())
+                })
+                .collect()
+        };
+        
+        let Some(transport) = &self.transport else {
+            return;
+        };
+        for (peer_id, token) in issued {
+            let grant = TokenGrant {
+                session_id: session_id.to_string(),
+                host_peer_id: self.local_peer_id.clone(),
+                token,
+            };
+            if let Err(e) = transport.grant_token(&peer_id, grant).await {
+                tracing::debug!("New token for {} not delivered: {}", peer_id, e);
+            }
+        }
+    }
+    
+    pub(super) fn refuses(&self, peer_id: &str) -> bool {
+        self.trust_store
+            .as_ref()
+            .is_some_and(|trust_store| trust_store.refuses("screen_share", peer_id))
+    }
+    
+    pub(super) fn admits(&self, peer_id: &str, class: ProtocolClass) -> bool {
+        self.rate_limiter
+            .as_ref()
+            .map(|rate_limiter| rate_limiter.admit(peer_id, class))
+            .unwrap_or(true)
+    }
+    
+    fn skips_approval(&self, viewer: &PeerIdentity) -> bool {
+        self.trust_store
+            .as_ref()
+            .map(|trust_store| trust_store.policy_for(viewer).skip_join_approval)
+            .unwrap_or(false)
+    }
+    
+    async fn wait_for_approval(&self, request: &JoinRequest) -> bool {
+        let (tx, rx) = oneshot::channel();
+        self.pending_joins.insert(request.request_id.clone(), tx);
+        let _ = self.join_tx.send(PendingJoin {
+            request_id: request.request_id.clone(),
+            session_id: request.session_id.clone(),
+            peer_id: request.peer_id.clone(),
+        });
+        
+        let timeout = Duration::from_millis(self.join_approval_timeout_ms.load(Ordering::Relaxed));
+        let approved = matches!(tokio::time::timeout(timeout, rx).await, Ok(Ok(true)));
+        self.pending_joins.remove(&request.request_id);
+        approved
+    }
+    
+    pub fn subscribe_join_requests(&self) -> broadcast::Receiver<PendingJoin> {
+        self.join_tx.subscribe()
+    }
+    
+    /// Approve or deny a join waiting in Approval mode
+    pub fn respond_to_join(&self, request_id: &str, approve: bool) -> Result<(), Error> {
+        let (_, tx) = self
+            .pending_joins
+            .remove(request_id)
+            .ok_or_else(|| DeskShareError::JoinRequestNotFound(request_id.to_string()))?;
+        let _ = tx.send(approve);
+        Ok(())
+    }
+    
+    pub fn set_join_approval_timeout(&self, timeout: Duration) {
+        self.join_approval_timeout_ms.store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+    
+    pub async fn join_session(&self, session_id: &str, peer_id: String) -> Result<(), Error> {
+        let mut sessions = self.sessions.write().await;
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.participants.insert(peer_id);
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn leave_session(&self, session_id: &str, peer_id: String) -> Result<(), Error> {
+        self.revoke_grant(session_id, &peer_id).await;
+        let removed = {
+            let mut sessions = self.sessions.write().await;
+            sessions
+                .get_mut(session_id)
+                .and_then(|session| session.participants.remove(&peer_id).then_some(session.participants.len()))
+        };
+        
+        if let Some(participants) = removed {
+            self.notify_participants(session_id, &peer_id, false, participants);
+            self.announce_session(session_id).await?;
+        }
+        Ok(())
+    }
+    
+    fn hash_password(session_id: &str, password: &str) -> blake3::Hash {
+        blake3::hash(format!("{}:{}", session_id, password).as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::network::screen_share::tests::CountingTransport;
+    use crate::platform::fallback::FallbackCapture;
+    use crate::security::{DeviceIdentity, TrustStore};
+    
+    #[tokio::test]
+    async fn test_only_the_paired_key_skips_approval() {
+        let trust_store = Arc::new(TrustStore::new());
+        let bob = DeviceIdentity::generate();
+        trust_store.record_paired(&bob.device_id(), "10.0.0.3", &bob.public_key());
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), Arc::new(CountingTransport::default()))
+            .with_trust_store(trust_store);
+        host.set_join_approval_timeout(Duration::from_millis(50));
+        let mut asked = host.subscribe_join_requests();
+        let session_id = host.start_sharing("host".to_string(), 5, (64, 48), None, None, None).await.unwrap();
+        host.set_access(&session_id, AccessMode::Approval, None).await.unwrap();
+        let join = |peer_id: &str| JoinRequest {
+            request_id: format!("join-{}", peer_id),
+            session_id: session_id.clone(),
+            peer_id: peer_id.to_string(),
+            password: None,
+            codecs: Vec::new(),
+            audio: false,
+            display_name: None,
+        };
+        let at = |peer_id: &str, identity: &DeviceIdentity| PeerIdentity { peer_id: peer_id.to_string(), device_id: identity.device_id() };
+        
+        // An unpaired key at Bob's address, or claiming it from elsewhere
+        let mallory = DeviceIdentity::generate();
+        assert!(matches!(host.handle_authenticated_join(join("10.0.0.3"), &at("10.0.0.3", &mallory)).await, JoinResponse::Denied));
+        assert_eq!(asked.try_recv().unwrap().peer_id, "10.0.0.3");
+        assert!(matches!(host.handle_authenticated_join(join("10.0.0.3"), &at("10.0.0.9", &mallory)).await, JoinResponse::Denied));
+        // Bob's key under another peer id is refused before anyone is asked
+        assert!(matches!(host.handle_authenticated_join(join("10.0.0.3"), &at("10.0.0.9", &bob)).await, JoinResponse::Denied));
+        // Nor does the claim alone, with nothing to back it
+        assert!(matches!(host.handle_join_request(join("10.0.0.3")).await, JoinResponse::Denied));
+        assert_eq!(asked.try_recv().unwrap().peer_id, "10.0.0.3");
+        assert!(asked.try_recv().is_err());
+        assert!(host.get_session(&session_id).await.unwrap().participants.is_empty());
+        
+        assert!(matches!(host.handle_authenticated_join(join("10.0.0.3"), &at("10.0.0.3", &bob)).await, JoinResponse::Accepted { .. }));
+        assert!(asked.try_recv().is_err());
+        assert!(host.get_session(&session_id).await.unwrap().participants.contains("10.0.0.3"));
+        host.stop_sharing(&session_id, "host").await.unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use anyhow::Error;
+use serde::{Serialize, Deserialize};
+
+use crate::network::session_protocol::{AccessMode, SessionAnnouncement, SessionEnded};
+use crate::network::lan_announce::{SessionBeacon, ANNOUNCEMENT_TTL};
+use crate::network::quality_preset::QualityPreset;
+
+use super::ScreenShare;
+
+/// A session another peer announced
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RemoteSession {
+    pub session_id: String,
+    pub host_peer_id: String,
+    pub resolution: (u32, u32),
+    pub access_mode: AccessMode,
+    pub participant_count: usize,
+    /// When we last heard it announced, in seconds
+    pub last_seen: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_name: Option<String>,
+    pub started_at_ms: u64,
+    pub has_password: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<QualityPreset>,
+}
+
+impl ScreenShare {
+    /// Record a session announced by another peer. Our own come back to us
+    /// when broadcast and are left out.
+    pub async fn handle_announcement(&self, announcement: SessionAnnouncement) {
+        if announcement.host_peer_id == self.local_peer_id
+            || self.sessions.read().await.contains_key(&announcement.session_id)
+        {
+            return;
+        }
+        
+        self.remote_sessions.write().await.insert(announcement.session_id.clone(), RemoteSession {
+            session_id: announcement.session_id,
+            host_peer_id: announcement.host_peer_id,
+            resolution: announcement.resolution,
+            access_mode: announcement.access_mode,
+            participant_count: announcement.participant_count,
+            last_seen: Self::now_secs(),
+            host_name: announcement.host_name.as_deref().and_then(Self::clean_display_name),
+            started_at_ms: announcement.started_at_ms,
+            has_password: announcement.has_password,
+            preset: announcement.preset,
+        });
+    }
+    
+    /// A beacon broadcast from `sender`. Hosts are known by the address
+    /// they broadcast from, whatever they call themselves.
+    pub async fn handle_beacon(&self, beacon: SessionBeacon, sender: &str) {
+        match beacon {
+            SessionBeacon::Announce(announcement) => {
+                self.handle_announcement(SessionAnnouncement { host_peer_id: sender.to_string(), ..announcement }).await;
+            }
+            SessionBeacon::Retract(ended) => {
+                self.handle_session_ended(SessionEnded { host_peer_id: sender.to_string(), ..ended }).await;
+            }
+        }
+    }
+    
+    /// Sessions other peers are sharing that we've heard announced lately
+    pub async fn get_available_sessions(&self) -> Vec<RemoteSession> {
+        let fresh_since = Self::now_secs().saturating_sub(ANNOUNCEMENT_TTL.as_secs());
+        let mut sessions: Vec<RemoteSession> = self
+            .remote_sessions
+            .read()
+            .await
+            .values()
+            .filter(|session| session.last_seen >= fresh_since)
+            .cloned()
+            .collect();
+        sessions.sort_by(|a, b| a.host_peer_id.cmp(&b.host_peer_id).then(a.session_id.cmp(&b.session_id)));
+        sessions
+    }
+    
+    /// Announce our sessions again and forget other peers' that haven't
+    /// been; call every `ANNOUNCE_INTERVAL`
+    pub async fn refresh_announcements(&self) {
+        let hosted: Vec<String> = self.sessions.read().await.keys().cloned().collect();
+        for session_id in hosted {
+            let _ = self.announce_session(&session_id).await;
+        }
+        let fresh_since = Self::now_secs().saturating_sub(ANNOUNCEMENT_TTL.as_secs());
+        self.remote_sessions.write().await.retain(|_, session| session.last_seen >= fresh_since);
+    }
+    
+    pub(super) async fn announce_session(&self, session_id: &str) -> Result<(), Error> {
+        let Some(session) = self.get_session(session_id).await else {
+            return Ok(());
+        };
+        
+        let announcement = SessionAnnouncement {
+            session_id: session_id.to_string(),
+            host_peer_id: session.host_peer_id,
+            resolution: session.resolution,
+            access_mode: session.access_mode,
+            participant_count: session.participants.len(),
+            timestamp: Self::now_secs(),
+            host_name: self.display_name.clone(),
+            started_at_ms: session.started_at_ms,
+            has_password: session.access_mode == AccessMode::Password,
+            preset: Some(session.preset),
+        };
+        
+        // Broadcast announcement to the LAN and through the P2P network
+        if let Some(announcer) = &self.announcer {
+            if let Err(e) = announcer.send(&SessionBeacon::Announce(announcement.clone())).await {
+                tracing::warn!("Failed to broadcast session {}: {}", session_id, e);
+            }
+        }
+        if let Some(transport) = &self.transport {
+            if let Err(e) = transport.announce(announcement).await {
+                tracing::warn!("Failed to announce session {}: {}", session_id, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::network::lan_announce::LanAnnouncer;
+    use crate::platform::fallback::FallbackCapture;
+    
+    #[tokio::test]
+    async fn test_sessions_are_broadcast_listed_and_expire() {
+        let any = std::net::SocketAddr::from(([127, 0, 0, 1], 0));
+        let lan = LanAnnouncer::bind_to(any, any).await.unwrap();
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_display_name("Alice".to_string())
+            .with_announcer(Arc::new(LanAnnouncer::bind_to(any, lan.local_addr().unwrap()).await.unwrap()));
+        let viewer = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        let session_id = host.start_sharing("local".to_string(), 15, (1280, 720), None, None, None).await.unwrap();
+        host.set_access(&session_id, AccessMode::Password, Some("hunter2".to_string())).await.unwrap();
+        
+        let (started, sender) = lan.recv().await.unwrap();
+        viewer.handle_beacon(started, &sender.to_string()).await;
+        let (locked, _) = lan.recv().await.unwrap();
+        // The host hears its own broadcast and leaves it out
+        host.handle_beacon(locked.clone(), "127.0.0.1").await;
+        assert!(host.get_available_sessions().await.is_empty());
+        viewer.handle_beacon(locked.clone(), &sender.to_string()).await;
+        let listed = viewer.get_available_sessions().await;
+        assert_eq!(listed.len(), 1);
+        assert_eq!((listed[0].host_peer_id.as_str(), listed[0].host_name.as_deref()), ("127.0.0.1", Some("Alice")));
+        assert_eq!((listed[0].resolution, listed[0].has_password), ((1280, 720), true));
+        assert_eq!(listed[0].started_at_ms, host.get_session(&session_id).await.unwrap().started_at_ms);
+        assert_eq!(listed[0].preset, Some(QualityPreset::Balanced));
+        
+        // Unless the host repeats itself the session drops off the list
+        let age = |viewer: &ScreenShare| {
+            let remote_sessions = viewer.remote_sessions.clone();
+            let session_id = session_id.clone();
+            async move {
+                remote_sessions.write().await.get_mut(&session_id).unwrap().last_seen -= ANNOUNCEMENT_TTL.as_secs() + 1;
+            }
+        };
+        age(&viewer).await;
+        assert!(viewer.get_available_sessions().await.is_empty());
+        host.refresh_announcements().await;
+        let (repeated, _) = lan.recv().await.unwrap();
+        viewer.handle_beacon(repeated, &sender.to_string()).await;
+        assert_eq!(viewer.get_available_sessions().await.len(), 1);
+        age(&viewer).await;
+        viewer.refresh_announcements().await;
+        assert!(viewer.remote_sessions.read().await.is_empty());
+        
+        // Stopping takes it back at once
+        viewer.handle_beacon(locked, &sender.to_string()).await;
+        host.stop_sharing(&session_id, "local").await.unwrap();
+        let (retracted, _) = lan.recv().await.unwrap();
+        assert!(matches!(retracted, SessionBeacon::Retract(_)));
+        viewer.handle_beacon(retracted, &sender.to_string()).await;
+        assert!(viewer.get_available_sessions().await.is_empty());
+    }
+}
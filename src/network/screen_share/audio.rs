@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::platform::audio::{AudioCapture, AudioOutput};
+use crate::network::session_protocol::SessionTransport;
+use crate::network::lan_frames::LanFrameLinks;
+use crate::network::video::FrameCodec;
+
+use super::{Frame, FrameHeader, Media, ScreenShare, SessionId, SharingSession, ViewerGrant};
+
+impl ScreenShare {
+    /// Send what the system plays to the viewers that asked for it, or stop.
+    /// Returns whether audio is going out: where it can't be captured the
+    /// session carries on video-only.
+    pub async fn set_audio(&self, session_id: &str, enabled: bool) -> Result<bool, Error> {
+        if !self.sessions.read().await.contains_key(session_id) {
+            return Err(DeskShareError::SessionNotFound(session_id.to_string()).into());
+        }
+        if let Some(handle) = self.audio_handles.write().await.remove(session_id) {
+            handle.abort();
+        }
+        
+        let capture = if enabled {
+            let audio = self.audio.clone();
+            match tokio::task::spawn_blocking(move || audio.start_loopback()).await? {
+                Ok(Some(capture)) => Some(capture),
+                Ok(None) => {
+                    tracing::info!("No system audio to capture, session {} stays video-only", session_id);
+                    None
+                }
+                Err(e) => {
+                    tracing::warn!("System audio capture failed, session {} stays video-only: {}", session_id, e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let sending = capture.is_some();
+        {
+            let mut sessions = self.sessions.write().await;
+            // Stopped while the capture was starting
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+            session.audio = sending;
+        }
+        
+        if let Some(capture) = capture {
+            let handle = self.spawn_until_closed(Self::send_audio(
+                capture,
+                SessionId::from(session_id),
+                self.sessions.clone(),
+                self.grants.clone(),
+                self.transport.clone(),
+                self.frame_links.clone(),
+            ));
+            if let Some(previous) = self.audio_handles.write().await.insert(session_id.to_string(), handle) {
+                previous.abort();
+            }
+        }
+        Ok(sending)
+    }
+    
+    /// Subscribed viewers that asked for audio
+    async fn audio_listeners(
+        grants: &RwLock<HashMap<String, HashMap<String, ViewerGrant>>>,
+        session_id: &str,
+    ) -> Vec<String> {
+        grants
+            .read()
+            .await
+            .get(session_id)
+            .map(|viewers| {
+                viewers
+                    .iter()
+                    .filter(|(_, grant)| grant.subscribed && grant.audio)
+                    .map(|(peer_id, _)| peer_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    /// Somewhere to play a joined session's audio; without one we watch
+    /// without hearing it
+    pub(super) async fn open_audio_output(&self) -> Option<Arc<dyn AudioOutput>> {
+        let audio = self.audio.clone();
+        match tokio::task::spawn_blocking(move || audio.open_output()).await {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => {
+                tracing::warn!("No audio output, watching without sound: {}", e);
+                None
+            }
+            Err(e) => {
+                tracing::error!("Audio output task failed: {}", e);
+                None
+            }
+        }
+    }
+    
+    /// Forward captured audio packets until the capture ends or the task is
+    /// aborted, which drops the capture and so stops it. Packets keep the
+    /// capture's timestamps, on the same clock as frames', so viewers can
+    /// line the two up.
+    async fn send_audio(
+        mut capture: AudioCapture,
+        session_id: SessionId,
+        sessions: Arc<RwLock<HashMap<String, SharingSession>>>,
+        grants: Arc<RwLock<HashMap<String, HashMap<String, ViewerGrant>>>>,
+        transport: Option<Arc<dyn SessionTransport>>,
+        frame_links: Option<Arc<LanFrameLinks>>,
+    ) {
+        let mut sequence = 0;
+        while let Some(packet) = capture.next().await {
+            let paused = match sessions.read().await.get(session_id.as_str()) {
+                Some(session) => session.paused,
+                None => break,
+            };
+            if paused {
+                continue;
+            }
+            let listeners = Self::audio_listeners(&grants, session_id.as_str()).await;
+            if listeners.is_empty() {
+                continue;
+            }
+            
+            sequence += 1;
+            let frame = Frame {
+                header: Arc::new(FrameHeader {
+                    session_id: session_id.clone(),
+                    sequence,
+                    timestamp_ms: packet.timestamp_ms,
+                    width: 0,
+                    height: 0,
+                    unchanged: false,
+                    codec: FrameCodec::Jpeg,
+                    keyframe: true,
+                    media: Media::Audio,
+                }),
+                data: packet.data,
+            };
+            for viewer in &listeners {
+                if let Err(e) = Self::deliver_frame(frame_links.as_deref(), transport.as_deref(), viewer, frame.clone()).await {
+                    tracing::debug!("Audio to {} dropped: {}", viewer, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+    use bytes::Bytes;
+    use crate::network::screen_share::{RemoteSession, ViewingSession};
+    use crate::network::screen_share::tests::{frame, RecordingTransport};
+    use crate::network::session_protocol::{AccessMode, SessionToken};
+    use crate::platform::audio::{AudioBackend, AudioPacket};
+    use crate::platform::fallback::FallbackCapture;
+    
+    /// A packet every 20ms from a thread, like a device callback, and
+    /// playback that only notes what it was given
+    #[derive(Default)]
+    struct FakeAudio {
+        played: Arc<std::sync::Mutex<Vec<AudioPacket>>>,
+    }
+    
+    struct RecordingOutput(Arc<std::sync::Mutex<Vec<AudioPacket>>>);
+    
+    impl AudioOutput for RecordingOutput {
+        fn play(&self, packet: &AudioPacket) -> Result<(), Error> {
+            self.0.lock().unwrap().push(packet.clone());
+            Ok(())
+        }
+    }
+    
+    impl AudioBackend for FakeAudio {
+        fn start_loopback(&self) -> Result<Option<AudioCapture>, Error> {
+            let stopped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+            let stop = stopped.clone();
+            let (packets, capture) = AudioCapture::new(move || stop.store(true, Ordering::Relaxed));
+            std::thread::spawn(move || {
+                let started = chrono::Utc::now().timestamp_millis() as u64;
+                for n in 0.. {
+                    std::thread::sleep(Duration::from_millis(20));
+                    if stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let _ = packets.try_send(AudioPacket { data: Bytes::from(vec![n as u8; 40]), timestamp_ms: started + n * 20 });
+                }
+            });
+            Ok(Some(capture))
+        }
+        
+        fn open_output(&self) -> Result<Option<Arc<dyn AudioOutput>>, Error> {
+            Ok(Some(Arc::new(RecordingOutput(self.played.clone()))))
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_audio_goes_out_between_frames_to_viewers_that_asked() {
+        let transport = Arc::new(RecordingTransport::default());
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_audio_backend(Arc::new(FakeAudio::default()))
+            .with_transport("host".to_string(), transport.clone());
+        let session_id = host.start_sharing("host".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let grant = |audio| ViewerGrant { subscribed: true, audio, ..ViewerGrant::new(SessionToken::generate()) };
+        host.grants.write().await.insert(session_id.clone(), HashMap::from([
+            ("10.0.0.2".to_string(), grant(true)),
+            ("10.0.0.3".to_string(), grant(false)),
+        ]));
+        
+        assert!(host.set_audio(&session_id, true).await.unwrap());
+        assert!(host.get_session(&session_id).await.unwrap().audio);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!host.set_audio(&session_id, false).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let sent = transport.sent.lock().unwrap().clone();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        host.stop_sharing(&session_id, "host").await.unwrap();
+        
+        let to = |viewer: &str, media| -> Vec<u64> {
+            sent.iter().filter(|(peer, header)| peer == viewer && header.media == media).map(|(_, header)| header.timestamp_ms).collect()
+        };
+        let audio = to("10.0.0.2", Media::Audio);
+        let video = to("10.0.0.2", Media::Video);
+        assert!(audio.len() >= 10, "{} audio packets", audio.len());
+        assert!(!video.is_empty());
+        assert!(audio.windows(2).all(|w| w[0] < w[1]), "{:?}", audio);
+        assert!(video.windows(2).all(|w| w[0] <= w[1]), "{:?}", video);
+        // Interleaved rather than one after the other
+        let first_audio = sent.iter().position(|(_, header)| header.media == Media::Audio).unwrap();
+        assert!(sent[first_audio..].iter().any(|(_, header)| header.media == Media::Video));
+        assert!(to("10.0.0.3", Media::Audio).is_empty());
+        assert!(!to("10.0.0.3", Media::Video).is_empty());
+        // Turned off, it stops
+        let audio = |sent: &[(String, Arc<FrameHeader>)]| sent.iter().filter(|(_, header)| header.media == Media::Audio).count();
+        assert_eq!(audio(&transport.sent.lock().unwrap()), audio(&sent));
+        
+        // The viewer plays audio rather than showing it
+        let audio = FakeAudio::default();
+        let played = audio.played.clone();
+        let viewer = ScreenShare::with_capture_backend(Arc::new(FallbackCapture)).with_audio_backend(Arc::new(audio));
+        let output = viewer.open_audio_output().await;
+        assert!(output.is_some());
+        viewer.viewing.write().await.insert(session_id.clone(), ViewingSession {
+            remote: RemoteSession {
+                session_id: session_id.clone(),
+                host_peer_id: "host".to_string(),
+                resolution: (64, 48),
+                access_mode: AccessMode::Open,
+                participant_count: 1,
+                last_seen: 0,
+                host_name: None,
+                started_at_ms: 0,
+                has_password: false,
+                preset: None,
+            },
+            token: SessionToken::generate(),
+            decoder: Default::default(),
+            audio: output,
+        });
+        let packet = frame(&session_id, 40);
+        let packet = Frame {
+            header: Arc::new(FrameHeader { media: Media::Audio, timestamp_ms: 1234, ..(*packet.header).clone() }),
+            data: packet.data,
+        };
+        viewer.receive_frame(packet).await;
+        assert_eq!(played.lock().unwrap().iter().map(|p| p.timestamp_ms).collect::<Vec<_>>(), [1234]);
+        assert!(viewer.get_frame(&session_id).await.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_sessions_without_audio_stay_video_only() {
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        let session_id = host.start_sharing("host".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        assert!(!host.set_audio(&session_id, true).await.unwrap());
+        assert!(!host.get_session(&session_id).await.unwrap().audio);
+        assert!(host.set_audio("missing", true).await.is_err());
+        host.stop_sharing(&session_id, "host").await.unwrap();
+    }
+}
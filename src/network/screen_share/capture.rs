@@ -0,0 +1,694 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, broadcast};
+use anyhow::Error;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+use crate::platform::cursor::composite as composite_cursor;
+use crate::platform::{encode_jpeg, find_monitor, CaptureBackend, CaptureRegion, MonitorInfo};
+use crate::network::adaptive::{DeliveryReport, QualityController};
+use crate::network::idle::{FrameChange, IdleDetector};
+use crate::network::stream_meter::StreamMeter;
+use crate::network::video::{FrameCodec, VideoEncoder, VideoPacket};
+
+use super::{ADAPTATIONS_KEPT, Frame, FrameHeader, ScreenShare, SharingSession};
+
+/// Captures in a row that fail before a session counts as degraded, and
+/// then as failed
+const DEGRADED_AFTER_FAILURES: u32 = 3;
+const FAILED_AFTER_FAILURES: u32 = 30;
+
+/// How often a failed session's capture is retried, e.g. in case the host
+/// grants screen recording permission
+const FAILED_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether a hosted session's capture is working. While it isn't, viewers
+/// are sent nothing and `reason` says what capture failed with.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum SessionStatus {
+    #[default]
+    Active,
+    /// Capture has failed a few times in a row
+    Degraded { reason: String },
+    /// Capture keeps failing, e.g. for lack of screen recording permission;
+    /// it's retried now and then
+    Failed { reason: String },
+}
+
+/// A hosted session's status changed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionStatusChange {
+    pub session_id: String,
+    pub status: SessionStatus,
+}
+
+/// A session's monitor went away, so it captures the primary instead
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MonitorLost {
+    pub session_id: String,
+    pub monitor_id: u32,
+}
+
+/// What the capture loop grabs, as the session had it at the frame's start
+#[derive(Clone, Copy)]
+pub(super) struct CaptureTarget {
+    /// The primary when None
+    pub(super) monitor_id: Option<u32>,
+    pub(super) region: Option<CaptureRegion>,
+    pub(super) show_cursor: bool,
+}
+
+/// A capture ready to go out: a JPEG for the host's own preview and for
+/// viewers when the session sends no video, and a video packet when it does
+pub(super) struct CapturedFrame {
+    jpeg: Option<VideoPacket>,
+    video: Option<VideoPacket>,
+    /// A region comes out at its own size rather than the session's
+    size: (u32, u32),
+}
+
+impl CapturedFrame {
+    fn test_pattern(resolution: (u32, u32)) -> Self {
+        Self { jpeg: Some(VideoPacket::jpeg(ScreenShare::generate_test_pattern(resolution).into())), video: None, size: resolution }
+    }
+}
+
+impl ScreenShare {
+    pub(super) fn ensure_monitor(&self, monitor_id: u32) -> Result<MonitorInfo, Error> {
+        self.capture
+            .list_monitors()?
+            .into_iter()
+            .find(|m| m.id == monitor_id)
+            .ok_or_else(|| DeskShareError::MonitorNotFound(monitor_id).into())
+    }
+    
+    pub fn subscribe_monitor_lost(&self) -> broadcast::Receiver<MonitorLost> {
+        self.monitor_lost_tx.subscribe()
+    }
+    
+    /// Hosted sessions' capture failing and recovering
+    pub fn subscribe_session_status(&self) -> broadcast::Receiver<SessionStatusChange> {
+        self.status_tx.subscribe()
+    }
+    
+    pub(super) async fn start_screen_capture(&self, session_id: &str) -> Result<(), Error> {
+        let session_id = session_id.to_string();
+        let handle_key = session_id.clone();
+        let frame_buffer = self.frame_buffer.clone();
+        let frame_channels = self.frame_channels.clone();
+        let sessions = self.sessions.clone();
+        let grants = self.grants.clone();
+        let capture = self.capture.clone();
+        let transport = self.transport.clone();
+        let frame_links = self.frame_links.clone();
+        let capture_timings = self.capture_timings.clone();
+        let monitor_lost_tx = self.monitor_lost_tx.clone();
+        let status_tx = self.status_tx.clone();
+        let test_pattern_on_failure = self.capture_config.test_pattern_on_failure;
+        let detector = Arc::new(std::sync::Mutex::new(IdleDetector::new(self.capture_config)));
+        let encoder = Arc::new(std::sync::Mutex::new(VideoEncoder::new()));
+        let (mut ceiling, mut resolution, mut adaptive) = {
+            let mut sessions = self.sessions.write().await;
+            let session = sessions
+                .get_mut(&session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.clone()))?;
+            // Capture starting over starts its stats over
+            session.meter = StreamMeter::new(chrono::Utc::now().timestamp_millis() as u64);
+            session.dropped_frames = 0;
+            session.status = SessionStatus::Active;
+            (session.ceiling(), session.resolution, session.adaptive)
+        };
+        let mut controller = QualityController::new(ceiling, adaptive);
+        
+        let handle = self.spawn_until_closed(async move {
+            let mut audience: (Vec<String>, usize) = (Vec::new(), 0);
+            let mut was_paused = false;
+            // A heartbeat stands in for the last full frame
+            let mut full_frame_len = 0;
+            let mut frame_size = resolution;
+            // Transport deliveries since the last adaptation decision;
+            // frame connections keep their own
+            let mut deliveries: HashMap<String, DeliveryReport> = HashMap::new();
+            let mut window_started = Instant::now();
+            let mut failures = 0;
+            let mut status = SessionStatus::Active;
+            
+            loop {
+                // Check if session is still active, picking up monitor
+                // switches and new settings
+                let source = {
+                    let sessions = sessions.read().await;
+                    sessions
+                        .get(&session_id)
+                        .map(|s| (s.capture_target(), s.paused, s.codec, s.adaptive, s.ceiling(), s.resolution))
+                };
+                
+                let Some((target, paused, codec, latest_adaptive, latest_ceiling, latest_resolution)) = source else {
+                    break;
+                };
+                if latest_adaptive != adaptive {
+                    adaptive = latest_adaptive;
+                    controller.set_config(adaptive);
+                    if let Some(session) = sessions.write().await.get_mut(&session_id) {
+                        session.stream = controller.settings();
+                    }
+                }
+                // Adaptation starts over from whatever the host picked
+                if (latest_ceiling, latest_resolution) != (ceiling, resolution) {
+                    (ceiling, resolution) = (latest_ceiling, latest_resolution);
+                    controller.set_ceiling(ceiling);
+                    if let Some(session) = sessions.write().await.get_mut(&session_id) {
+                        session.stream = controller.settings();
+                    }
+                }
+                let settings = controller.settings();
+                if paused {
+                    was_paused = true;
+                    tokio::time::sleep(settings.frame_interval()).await;
+                    continue;
+                }
+                
+                // Only viewers holding a current token get frames. Anyone
+                // new, and everyone after a pause, needs a full frame rather
+                // than a heartbeat.
+                let viewers = Self::subscribed_viewers(&grants, &session_id).await;
+                let subscribers = frame_channels
+                    .read()
+                    .await
+                    .get(&session_id)
+                    .map_or(0, |channel| channel.sender.receiver_count());
+                let joined = viewers.iter().any(|viewer| !audience.0.contains(viewer)) || subscribers > audience.1;
+                // A viewer whose frame connection dropped tiles can't decode
+                // any more until the next keyframe; there's one encoder, so
+                // everyone gets it
+                let mut lagging = false;
+                if let Some(links) = &frame_links {
+                    for viewer in &viewers {
+                        lagging |= links.take_keyframe_request(viewer);
+                    }
+                }
+                if joined || lagging || std::mem::take(&mut was_paused) {
+                    detector.lock().unwrap().force_keyframe();
+                }
+                audience = (viewers, subscribers);
+                
+                // Capture screen (platform-specific implementation); an
+                // unchanged screen comes back as None and goes out as a heartbeat
+                let started = Instant::now();
+                let video = (codec != FrameCodec::Jpeg && !audience.0.is_empty()).then(|| (codec, encoder.clone()));
+                let scaled = settings.resolution(resolution);
+                let (captured, lost) = Self::capture_screen_frame(
+                    capture.clone(),
+                    detector.clone(),
+                    video,
+                    audience.1 > 0,
+                    target,
+                    scaled,
+                    settings.quality,
+                ).await;
+                capture_timings.lock().unwrap().record(started.elapsed());
+                if let Some(monitor_id) = lost {
+                    tracing::warn!("Monitor {} of session {} is gone, capturing the primary", monitor_id, session_id);
+                    if let Some(session) = sessions.write().await.get_mut(&session_id) {
+                        session.monitor_id = None;
+                        session.region = None;
+                    }
+                    let _ = monitor_lost_tx.send(MonitorLost { session_id: session_id.clone(), monitor_id });
+                }
+                
+                // Failing capture is reported rather than papered over, and
+                // viewers are sent nothing until it works again
+                let captured = match captured {
+                    Ok(captured) => {
+                        failures = 0;
+                        Self::report_status(&sessions, &status_tx, &session_id, &mut status, SessionStatus::Active).await;
+                        captured
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        let reason = match e.downcast_ref() {
+                            Some(DeskShareError::ScreenCaptureFailed(reason)) => reason.clone(),
+                            _ => e.to_string(),
+                        };
+                        tracing::error!("Screen capture of session {} failed: {}", session_id, reason);
+                        let next = if failures >= FAILED_AFTER_FAILURES {
+                            SessionStatus::Failed { reason }
+                        } else if failures >= DEGRADED_AFTER_FAILURES {
+                            SessionStatus::Degraded { reason }
+                        } else {
+                            status.clone()
+                        };
+                        Self::report_status(&sessions, &status_tx, &session_id, &mut status, next).await;
+                        if !test_pattern_on_failure {
+                            let retry = match status {
+                                SessionStatus::Failed { .. } => FAILED_RETRY_INTERVAL,
+                                _ => settings.frame_interval(),
+                            };
+                            tokio::time::sleep(retry).await;
+                            continue;
+                        }
+                        Some(CapturedFrame::test_pattern(scaled))
+                    }
+                };
+                
+                let (jpeg, mut video) = match captured {
+                    Some(captured) => {
+                        frame_size = captured.size;
+                        (captured.jpeg, captured.video)
+                    }
+                    None => (None, None),
+                };
+                if let Some(full) = jpeg.as_ref().or(video.as_ref().filter(|packet| packet.keyframe)) {
+                    full_frame_len = full.data.len() as u64;
+                }
+                // Store a JPEG in buffer and hand it to local subscribers
+                let frame = Self::publish_frame(
+                    &frame_channels,
+                    &frame_buffer,
+                    &session_id,
+                    frame_size,
+                    jpeg.or_else(|| video.take()),
+                ).await;
+                
+                // Viewers get the video packet under the same sequence number
+                let frame = match video {
+                    Some(packet) => Frame {
+                        header: Arc::new(FrameHeader {
+                            codec: packet.codec,
+                            keyframe: packet.keyframe,
+                            ..(*frame.header).clone()
+                        }),
+                        data: packet.data,
+                    },
+                    None => frame,
+                };
+                if let Some(session) = sessions.write().await.get_mut(&session_id) {
+                    session.meter.record(chrono::Utc::now().timestamp_millis() as u64, frame.data.len() as u64);
+                    if frame.header.keyframe && !frame.header.unchanged && !session.paused {
+                        session.last_keyframe = Some(frame.clone());
+                    }
+                    if !audience.0.is_empty() {
+                        session.traffic.record(&frame, full_frame_len);
+                    }
+                }
+                for viewer in &audience.0 {
+                    let sent = Self::deliver_frame(frame_links.as_deref(), transport.as_deref(), viewer, frame.clone()).await;
+                    let report = deliveries.entry(viewer.clone()).or_default();
+                    match sent {
+                        Ok(Some(latency)) => report.delivered_in(latency),
+                        Ok(None) => {}
+                        Err(e) => {
+                            tracing::debug!("Frame to {} dropped: {}", viewer, e);
+                            report.dropped += 1;
+                        }
+                    }
+                }
+                
+                if window_started.elapsed() >= adaptive.window() {
+                    window_started = Instant::now();
+                    let mut reports = std::mem::take(&mut deliveries);
+                    if let Some(links) = &frame_links {
+                        for viewer in &audience.0 {
+                            reports.entry(viewer.clone()).or_default().merge(links.take_report(viewer));
+                        }
+                    }
+                    if let Some(viewers) = grants.write().await.get_mut(&session_id) {
+                        for (viewer, report) in &reports {
+                            if let Some(grant) = viewers.get_mut(viewer) {
+                                grant.dropped_frames += report.dropped;
+                                if report.delivered > 0 {
+                                    grant.latency = Some(report.worst_latency);
+                                }
+                            }
+                        }
+                    }
+                    let dropped: u64 = reports.values().map(|report| report.dropped).sum();
+                    if dropped > 0 {
+                        if let Some(session) = sessions.write().await.get_mut(&session_id) {
+                            session.dropped_frames += dropped;
+                        }
+                    }
+                    let at_ms = chrono::Utc::now().timestamp_millis() as u64;
+                    if let Some(change) = controller.observe(reports.iter().map(|(viewer, report)| (viewer.as_str(), *report)), at_ms) {
+                        tracing::info!("Session {} stream now {:?}, was {:?}: {}", session_id, change.to, change.from, change.reason);
+                        if let Some(session) = sessions.write().await.get_mut(&session_id) {
+                            session.stream = change.to;
+                            session.adaptations.push_back(change);
+                            if session.adaptations.len() > ADAPTATIONS_KEPT {
+                                session.adaptations.pop_front();
+                            }
+                        }
+                    }
+                }
+                
+                tokio::time::sleep(controller.settings().frame_interval()).await;
+            }
+        });
+        
+        if let Some(previous) = self.capture_handles.write().await.insert(handle_key, handle) {
+            previous.abort();
+        }
+        
+        Ok(())
+    }
+    
+    /// Grabbing, scaling and JPEG-encoding a frame takes tens of
+    /// milliseconds, so it runs on a blocking thread rather than holding up
+    /// timers and network IO on the runtime. An aborted capture loop leaves
+    /// at most the frame in progress to finish. Returns None when the
+    /// screen hasn't changed, without encoding anything. Given an `encoder`
+    /// the frame is encoded as video in its codec for viewers, and as a JPEG
+    /// only for a `preview` on this side; without one it's only a JPEG. A `region` is cropped
+    /// out before encoding and kept within `resolution`, and the cursor
+    /// drawn in if the session shows it; a moving cursor counts as a change. A
+    /// monitor that's gone is replaced by the whole of the primary, and its
+    /// id returned alongside. A failed capture is returned as the error.
+    async fn capture_screen_frame(
+        capture: Arc<dyn CaptureBackend>,
+        detector: Arc<std::sync::Mutex<IdleDetector>>,
+        encoder: Option<(FrameCodec, Arc<std::sync::Mutex<VideoEncoder>>)>,
+        preview: bool,
+        target: CaptureTarget,
+        resolution: (u32, u32),
+        quality: u8,
+    ) -> (Result<Option<CapturedFrame>, Error>, Option<u32>) {
+        let runtime = tokio::runtime::Handle::current();
+        let captured = tokio::task::spawn_blocking(move || {
+            // Use platform-specific screen capture
+            let grab = |monitor_id, region| match region {
+                Some(region) => runtime.block_on(capture.capture_region(monitor_id, region, resolution)),
+                None => runtime.block_on(capture.capture_raw(monitor_id, resolution)),
+            };
+            let mut target = target;
+            let mut lost = None;
+            let raw = match grab(target.monitor_id, target.region) {
+                Err(e) if matches!(e.downcast_ref(), Some(DeskShareError::MonitorNotFound(_))) => {
+                    lost = target.monitor_id;
+                    target = CaptureTarget { monitor_id: None, region: None, ..target };
+                    grab(None, None)
+                }
+                raw => raw,
+            };
+            let frame = raw.and_then(|mut raw| {
+                if target.show_cursor {
+                    Self::draw_cursor(capture.as_ref(), &mut raw, target);
+                }
+                let keyframe = match detector.lock().unwrap().check(&raw) {
+                    FrameChange::Unchanged => return Ok(None),
+                    FrameChange::Changed => false,
+                    FrameChange::Keyframe => true,
+                };
+                let jpeg = if preview || encoder.is_none() {
+                    Some(VideoPacket::jpeg(Bytes::from(encode_jpeg(&raw, quality)?)))
+                } else {
+                    None
+                };
+                let video = encoder
+                    .map(|(codec, encoder)| encoder.lock().unwrap().encode(codec, &raw, keyframe))
+                    .transpose()?;
+                Ok(Some(CapturedFrame { jpeg, video, size: raw.dimensions() }))
+            });
+            (frame, lost)
+        })
+        .await;
+        
+        match captured {
+            Ok(captured) => captured,
+            Err(e) => (Err(DeskShareError::ScreenCaptureFailed(format!("capture task failed: {}", e)).into()), None),
+        }
+    }
+    
+    /// Move a session's capture from `status` to `next`, telling
+    /// subscribers when that's a change
+    async fn report_status(
+        sessions: &RwLock<HashMap<String, SharingSession>>,
+        status_tx: &broadcast::Sender<SessionStatusChange>,
+        session_id: &str,
+        status: &mut SessionStatus,
+        next: SessionStatus,
+    ) {
+        if *status == next {
+            return;
+        }
+        tracing::info!("Session {} capture now {:?}, was {:?}", session_id, next, status);
+        *status = next;
+        if let Some(session) = sessions.write().await.get_mut(session_id) {
+            session.status = status.clone();
+        }
+        let _ = status_tx.send(SessionStatusChange { session_id: session_id.to_string(), status: status.clone() });
+    }
+    
+    /// Draw the cursor onto a capture of `target`. A cursor that can't be
+    /// found only costs the frame its cursor.
+    fn draw_cursor(capture: &dyn CaptureBackend, frame: &mut image::RgbaImage, target: CaptureTarget) {
+        let drawn = capture.cursor().and_then(|cursor| {
+            let Some(cursor) = cursor else {
+                return Ok(());
+            };
+            let monitors = capture.list_monitors()?;
+            let monitor = find_monitor(&monitors, target.monitor_id)?;
+            composite_cursor(frame, &cursor, monitor, target.region.unwrap_or_else(|| CaptureRegion::whole(monitor)));
+            Ok(())
+        });
+        if let Err(e) = drawn {
+            tracing::debug!("Cursor left out of the frame: {}", e);
+        }
+    }
+    
+    fn generate_test_pattern(resolution: (u32, u32)) -> Vec<u8> {
+        // Generate a simple test pattern for demonstration
+        let (width, height) = resolution;
+        let mut data = Vec::with_capacity((width * height * 3) as usize);
+        
+        for y in 0..height {
+            for x in 0..width {
+                let r = ((x as f32 / width as f32) * 255.0) as u8;
+                let g = ((y as f32 / height as f32) * 255.0) as u8;
+                let b = (((x + y) as f32 / (width + height) as f32) * 255.0) as u8;
+                data.push(r);
+                data.push(g);
+                data.push(b);
+            }
+        }
+        
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use async_trait::async_trait;
+    use crate::network::idle::CaptureConfig;
+    use crate::network::screen_share::ViewerGrant;
+    use crate::network::screen_share::tests::{next_frame_where, CountingTransport, SlideCapture};
+    use crate::network::session_protocol::SessionToken;
+    use crate::platform::cursor::{Cursor, CursorImage};
+    use crate::platform::fallback::FallbackCapture;
+    
+    #[tokio::test]
+    async fn test_static_screen_sends_heartbeats_not_frames() {
+        let transport = Arc::new(CountingTransport::default());
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), transport.clone())
+            .with_capture_config(CaptureConfig { keyframe_interval_ms: 5_000, ..CaptureConfig::default() });
+        let session_id = screen_share.start_sharing("host".to_string(), 30, (320, 240), None, None, None).await.unwrap();
+        let viewer = ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) };
+        screen_share.grants.write().await.insert(session_id.clone(), HashMap::from([("10.0.0.2".to_string(), viewer)]));
+        
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        screen_share.stop_sharing(&session_id, "host").await.unwrap();
+        
+        // The fallback pattern never changes: one keyframe at most, then heartbeats
+        let (frames, heartbeats) = *transport.sent.lock().unwrap();
+        assert!(frames <= 1, "{} full frames sent", frames);
+        assert!(heartbeats >= 5, "only {} heartbeats", heartbeats);
+    }
+    
+    #[tokio::test]
+    async fn test_a_slide_sends_changed_tiles_and_counts_the_savings() {
+        let transport = Arc::new(CountingTransport::default());
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(SlideCapture::default()))
+            .with_transport("host".to_string(), transport.clone())
+            .with_capture_config(CaptureConfig { keyframe_interval_ms: 500, ..CaptureConfig::default() });
+        let session_id = screen_share.start_sharing("host".to_string(), 30, (320, 240), None, None, None).await.unwrap();
+        let viewer = ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) };
+        screen_share.grants.write().await.insert(session_id.clone(), HashMap::from([("10.0.0.2".to_string(), viewer)]));
+        
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let traffic = screen_share.session_stats(&session_id).await.unwrap().traffic;
+        screen_share.stop_sharing(&session_id, "host").await.unwrap();
+        
+        // A keyframe to start and every 500ms after, deltas in between
+        assert!((2..=3).contains(&traffic.full_frames), "{:?}", traffic);
+        assert!(traffic.delta_frames > traffic.full_frames, "{:?}", traffic);
+        assert_eq!(transport.sent.lock().unwrap().0 as u64, traffic.full_frames + traffic.delta_frames);
+        assert!(traffic.saved_bytes() > traffic.sent_bytes * 2, "{:?}", traffic);
+    }
+    
+    /// The fallback monitors, with the secondary unplugged on request
+    #[derive(Default)]
+    struct UnpluggableCapture {
+        unplugged: std::sync::atomic::AtomicBool,
+    }
+    
+    #[async_trait]
+    impl CaptureBackend for UnpluggableCapture {
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error> {
+            let mut monitors = crate::platform::fallback::list_monitors()?;
+            if self.unplugged.load(Ordering::Relaxed) {
+                monitors.retain(|m| m.is_primary);
+            }
+            Ok(monitors)
+        }
+        
+        async fn capture_raw(&self, monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<image::RgbaImage, Error> {
+            let monitors = self.list_monitors()?;
+            if let Some(id) = monitor_id.filter(|id| !monitors.iter().any(|m| m.id == *id)) {
+                return Err(DeskShareError::MonitorNotFound(id).into());
+            }
+            FallbackCapture.capture_raw(None, resolution).await
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_an_unplugged_monitor_falls_back_to_the_primary() {
+        let capture = Arc::new(UnpluggableCapture::default());
+        let screen_share = ScreenShare::with_capture_backend(capture.clone());
+        let mut lost = screen_share.subscribe_monitor_lost();
+        let session_id = screen_share.start_sharing("local".to_string(), 30, (64, 48), Some(2), None, None).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(lost.try_recv().is_err());
+        
+        capture.unplugged.store(true, Ordering::Relaxed);
+        let event = tokio::time::timeout(Duration::from_secs(2), lost.recv()).await.unwrap().unwrap();
+        assert_eq!(event, MonitorLost { session_id: session_id.clone(), monitor_id: 2 });
+        assert_eq!(screen_share.get_session(&session_id).await.unwrap().monitor_id, None);
+        
+        // Capture carries on from the primary, warning only the once
+        let captured = screen_share.capture_timings().runs;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(screen_share.capture_timings().runs > captured);
+        assert!(lost.try_recv().is_err());
+        screen_share.stop_sharing(&session_id, "local").await.unwrap();
+    }
+    
+    /// The fallback screen, until screen recording permission is taken away
+    #[derive(Default)]
+    struct DeniedCapture {
+        denied: std::sync::atomic::AtomicBool,
+    }
+    
+    #[async_trait]
+    impl CaptureBackend for DeniedCapture {
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error> {
+            crate::platform::fallback::list_monitors()
+        }
+        
+        async fn capture_raw(&self, monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<image::RgbaImage, Error> {
+            if self.denied.load(Ordering::Relaxed) {
+                return Err(DeskShareError::ScreenCaptureFailed("screen recording permission denied".to_string()).into());
+            }
+            FallbackCapture.capture_raw(monitor_id, resolution).await
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_failing_capture_is_reported_instead_of_streamed() {
+        let capture = Arc::new(DeniedCapture::default());
+        capture.denied.store(true, Ordering::Relaxed);
+        let screen_share = ScreenShare::with_capture_backend(capture.clone());
+        let mut status = screen_share.subscribe_session_status();
+        let session_id = screen_share.start_sharing("local".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let mut frames = screen_share.subscribe_frames(&session_id).await.unwrap();
+        async fn next_status(status: &mut broadcast::Receiver<SessionStatusChange>) -> SessionStatusChange {
+            tokio::time::timeout(Duration::from_secs(3), status.recv()).await.unwrap().unwrap()
+        }
+        
+        let reason = "screen recording permission denied".to_string();
+        let degraded = next_status(&mut status).await;
+        assert_eq!(degraded, SessionStatusChange { session_id: session_id.clone(), status: SessionStatus::Degraded { reason: reason.clone() } });
+        assert_eq!(next_status(&mut status).await.status, SessionStatus::Failed { reason: reason.clone() });
+        assert_eq!(screen_share.session_stats(&session_id).await.unwrap().status, SessionStatus::Failed { reason });
+        // Nothing went out in the meantime, test pattern or otherwise
+        assert!(frames.try_recv().is_err());
+        
+        // Granting permission picks capture back up on the next retry
+        capture.denied.store(false, Ordering::Relaxed);
+        assert_eq!(next_status(&mut status).await.status, SessionStatus::Active);
+        tokio::time::timeout(Duration::from_secs(1), frames.recv()).await.unwrap().unwrap();
+        assert_eq!(screen_share.get_session(&session_id).await.unwrap().status, SessionStatus::Active);
+        screen_share.stop_sharing(&session_id, "local").await.unwrap();
+        
+        // Debugging can ask for the test pattern regardless
+        let screen_share = ScreenShare::with_capture_backend(capture.clone())
+            .with_capture_config(CaptureConfig { test_pattern_on_failure: true, ..CaptureConfig::default() });
+        capture.denied.store(true, Ordering::Relaxed);
+        let session_id = screen_share.start_sharing("local".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let mut frames = screen_share.subscribe_frames(&session_id).await.unwrap();
+        let frame = tokio::time::timeout(Duration::from_secs(1), frames.recv()).await.unwrap().unwrap();
+        assert_eq!((frame.header.width, frame.header.height), (64, 48));
+        screen_share.stop_sharing(&session_id, "local").await.unwrap();
+    }
+    
+    /// Black monitors under a red square cursor that can be moved about
+    struct CursorCapture {
+        position: std::sync::Mutex<(i32, i32)>,
+    }
+    
+    #[async_trait]
+    impl CaptureBackend for CursorCapture {
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error> {
+            crate::platform::fallback::list_monitors()
+        }
+        
+        async fn capture_raw(&self, _monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<image::RgbaImage, Error> {
+            Ok(image::RgbaImage::from_pixel(resolution.0, resolution.1, image::Rgba([0, 0, 0, 255])))
+        }
+        
+        fn cursor(&self) -> Result<Option<Cursor>, Error> {
+            let (x, y) = *self.position.lock().unwrap();
+            let image = image::RgbaImage::from_pixel(16, 16, image::Rgba([255, 0, 0, 255]));
+            Ok(Some(Cursor { x, y, image: Arc::new(CursorImage { image, hotspot: (0, 0) }) }))
+        }
+    }
+    
+    /// Where the frame is red, roughly, JPEG being what it is
+    fn red_at(frame: &Frame) -> Option<(u32, u32)> {
+        let image = image::load_from_memory(&frame.data).unwrap().to_rgb8();
+        image.enumerate_pixels().find(|(_, _, p)| p[0] > 160 && p[1] < 80).map(|(x, y, _)| (x, y))
+    }
+    
+    #[tokio::test]
+    async fn test_the_cursor_is_drawn_where_it_is_until_turned_off() {
+        // Monitor 2 is 1280x1024 at x 1920, shared at half size
+        let capture = Arc::new(CursorCapture { position: std::sync::Mutex::new((1920 + 400, 200)) });
+        let screen_share = ScreenShare::with_capture_backend(capture.clone());
+        let session_id = screen_share.start_sharing("local".to_string(), 30, (640, 512), Some(2), None, None).await.unwrap();
+        let mut frames = screen_share.subscribe_frames(&session_id).await.unwrap();
+        
+        let frame = next_frame_where(&mut frames, |frame| red_at(frame).is_some()).await;
+        let (x, y) = red_at(&frame).unwrap();
+        assert!(x.abs_diff(200) <= 1 && y.abs_diff(100) <= 1, "cursor drawn at ({}, {})", x, y);
+        
+        // Moving counts as a change even on a still screen
+        *capture.position.lock().unwrap() = (1920 + 100, 300);
+        next_frame_where(&mut frames, |frame| red_at(frame).is_some_and(|(x, _)| x < 100)).await;
+        
+        // Over on the primary it's off this monitor
+        *capture.position.lock().unwrap() = (400, 200);
+        next_frame_where(&mut frames, |frame| red_at(frame).is_none()).await;
+        
+        *capture.position.lock().unwrap() = (1920 + 400, 200);
+        next_frame_where(&mut frames, |frame| red_at(frame).is_some()).await;
+        screen_share.set_show_cursor(&session_id, false).await.unwrap();
+        assert!(!screen_share.get_session(&session_id).await.unwrap().show_cursor);
+        next_frame_where(&mut frames, |frame| red_at(frame).is_none()).await;
+        screen_share.stop_sharing(&session_id, "local").await.unwrap();
+    }
+}
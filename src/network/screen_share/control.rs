@@ -0,0 +1,282 @@
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::platform::input::{to_desktop, InputAction};
+use crate::platform::{find_monitor, CaptureRegion};
+use crate::security::ProtocolClass;
+use crate::network::session_protocol::{ControlAction, ControlMessage, InputEvent, SessionToken, TokenGrant};
+
+use super::ScreenShare;
+
+impl ScreenShare {
+    /// Host side of a viewer's control message; refused unless it carries the
+    /// token currently issued to that viewer for that session
+    pub async fn handle_control(&self, message: ControlMessage) -> Result<(), Error> {
+        let class = match message.action {
+            ControlAction::Input(_) => ProtocolClass::RemoteInput,
+            _ => ProtocolClass::Signaling,
+        };
+        if !self.admits(&message.peer_id, class) {
+            return Ok(());
+        }
+        {
+            let mut grants = self.grants.write().await;
+            let grant = grants
+                .get_mut(&message.session_id)
+                .and_then(|viewers| viewers.get_mut(&message.peer_id))
+                .filter(|grant| grant.token == message.token)
+                .filter(|_| !self.refuses(&message.peer_id));
+            let Some(grant) = grant else {
+                tracing::warn!(
+                    "Refused {:?} from {} for session {}: not an authorized viewer",
+                    message.action,
+                    message.peer_id,
+                    message.session_id
+                );
+                return Err(DeskShareError::InvalidSessionToken(message.session_id).into());
+            };
+            
+            match message.action {
+                ControlAction::Subscribe if grant.streamed => {
+                    grant.subscribed = true;
+                    return Ok(());
+                }
+                ControlAction::Subscribe => {}
+                ControlAction::Input(event) => {
+                    let granted = grant.control;
+                    drop(grants);
+                    return self.play_input(&message.session_id, &message.peer_id, granted, event).await;
+                }
+                ControlAction::Leave => {
+                    drop(grants);
+                    return self.leave_session(&message.session_id, message.peer_id).await;
+                }
+            }
+        }
+        
+        // A first subscription gets the last keyframe straight away. The
+        // capture loop only sees the viewer after, so it can't have been
+        // sent deltas that keyframe would land among, and sends it a fresh
+        // keyframe next.
+        self.send_last_keyframe(&message.session_id, &message.peer_id).await;
+        let mut grants = self.grants.write().await;
+        let grant = grants
+            .get_mut(&message.session_id)
+            .and_then(|viewers| viewers.get_mut(&message.peer_id))
+            .filter(|grant| grant.token == message.token);
+        if let Some(grant) = grant {
+            grant.subscribed = true;
+            grant.streamed = true;
+        }
+        Ok(())
+    }
+    
+    /// Play a viewer's input back on our desktop, where it lands on the part
+    /// of the screen the session shows. Input is dropped while paused.
+    async fn play_input(&self, session_id: &str, peer_id: &str, granted: bool, event: InputEvent) -> Result<(), Error> {
+        let (monitor_id, region) = {
+            let sessions = self.sessions.read().await;
+            let session = sessions
+                .get(session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+            if !(session.allow_remote_control && granted) {
+                tracing::warn!("Refused input from {} for session {}: remote control not allowed", peer_id, session_id);
+                return Err(DeskShareError::RemoteControlNotAllowed(session_id.to_string()).into());
+            }
+            if session.paused {
+                return Ok(());
+            }
+            (session.monitor_id, session.region)
+        };
+        
+        let action = match event {
+            InputEvent::MouseMove { x, y } => {
+                let monitors = self.capture.list_monitors()?;
+                let monitor = find_monitor(&monitors, monitor_id)?;
+                let (x, y) = to_desktop(x, y, monitor, region.unwrap_or_else(|| CaptureRegion::whole(monitor)));
+                InputAction::MoveTo { x, y }
+            }
+            InputEvent::MouseButton { button, pressed } => InputAction::Button { button, pressed },
+            InputEvent::Scroll { dx, dy } => InputAction::Scroll { dx, dy },
+            InputEvent::Key { key, pressed } => InputAction::Key { key, pressed },
+        };
+        self.input.inject(action)
+    }
+    
+    /// Whether viewers of a session we host may drive the mouse and
+    /// keyboard at all. Each viewer still has to be let in on it with
+    /// `allow_control`.
+    pub async fn set_remote_control(&self, session_id: &str, allowed: bool) -> Result<(), Error> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+        session.allow_remote_control = allowed;
+        Ok(())
+    }
+    
+    /// Let one viewer drive the mouse and keyboard, or stop it
+    pub async fn allow_control(&self, session_id: &str, peer_id: &str, allowed: bool) -> Result<(), Error> {
+        let mut grants = self.grants.write().await;
+        let grant = grants
+            .get_mut(session_id)
+            .and_then(|viewers| viewers.get_mut(peer_id))
+            .ok_or_else(|| DeskShareError::PeerNotFound(peer_id.to_string()))?;
+        grant.control = allowed;
+        Ok(())
+    }
+    
+    /// Take control back from everyone, in every session we host; input
+    /// already on its way is refused
+    pub async fn revoke_control(&self) {
+        for session in self.sessions.write().await.values_mut() {
+            session.allow_remote_control = false;
+        }
+        for grant in self.grants.write().await.values_mut().flat_map(|viewers| viewers.values_mut()) {
+            grant.control = false;
+        }
+        tracing::info!("Remote control revoked from every viewer");
+    }
+    
+    pub(super) async fn revoke_grant(&self, session_id: &str, peer_id: &str) {
+        if let Some(viewers) = self.grants.write().await.get_mut(session_id) {
+            viewers.remove(peer_id);
+        }
+    }
+    
+    /// Viewer side of a token rotation: keep watching with the new token
+    pub async fn handle_token_grant(&self, grant: TokenGrant) -> Result<(), Error> {
+        {
+            let mut viewing = self.viewing.write().await;
+            let session = viewing
+                .get_mut(&grant.session_id)
+                .filter(|session| session.remote.host_peer_id == grant.host_peer_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(grant.session_id.clone()))?;
+            session.token = grant.token.clone();
+        }
+        
+        self.send_control(&grant.host_peer_id, &grant.session_id, grant.token, ControlAction::Subscribe).await
+    }
+    
+    /// Drive the mouse or keyboard of a session we're viewing, if its host
+    /// lets us
+    pub async fn send_input(&self, session_id: &str, event: InputEvent) -> Result<(), Error> {
+        let (host_peer_id, token) = self
+            .viewing
+            .read()
+            .await
+            .get(session_id)
+            .map(|viewing| (viewing.remote.host_peer_id.clone(), viewing.token.clone()))
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+        self.send_control(&host_peer_id, session_id, token, ControlAction::Input(event)).await
+    }
+    
+    pub(super) async fn send_control(
+        &self,
+        host_peer_id: &str,
+        session_id: &str,
+        token: SessionToken,
+        action: ControlAction,
+    ) -> Result<(), Error> {
+        let Some(transport) = &self.transport else {
+            return Ok(());
+        };
+        let message = ControlMessage {
+            session_id: session_id.to_string(),
+            peer_id: self.local_peer_id.clone(),
+            token,
+            action,
+        };
+        transport.send_control(host_peer_id, message).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::network::screen_share::tests::CountingTransport;
+    use crate::network::session_protocol::{JoinRequest, JoinResponse};
+    use crate::platform::fallback::FallbackCapture;
+    use crate::platform::input::InputBackend;
+    
+    /// Notes what it's asked to play back
+    #[derive(Default)]
+    struct RecordingInput {
+        played: std::sync::Mutex<Vec<InputAction>>,
+    }
+    
+    impl InputBackend for RecordingInput {
+        fn inject(&self, action: InputAction) -> Result<(), Error> {
+            self.played.lock().unwrap().push(action);
+            Ok(())
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_input_is_played_back_only_while_control_is_allowed() {
+        use crate::platform::input::{Key, MouseButton};
+        
+        let input = Arc::new(RecordingInput::default());
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), Arc::new(CountingTransport::default()))
+            .with_input_backend(input.clone());
+        let session_id = host.start_sharing("host".to_string(), 30, (64, 48), Some(2), None, None).await.unwrap();
+        let JoinResponse::Accepted { token, .. } = host
+            .handle_join_request(JoinRequest {
+                request_id: "join-1".to_string(),
+                session_id: session_id.clone(),
+                peer_id: "10.0.0.2".to_string(),
+                password: None,
+                codecs: Vec::new(),
+                audio: false,
+                display_name: None,
+            })
+            .await
+        else {
+            panic!("not admitted");
+        };
+        let send = |event: InputEvent| {
+            host.handle_control(ControlMessage {
+                session_id: session_id.clone(),
+                peer_id: "10.0.0.2".to_string(),
+                token: token.clone(),
+                action: ControlAction::Input(event),
+            })
+        };
+        let refused = |result: Result<(), Error>| {
+            matches!(result.unwrap_err().downcast_ref(), Some(DeskShareError::RemoteControlNotAllowed(_)))
+        };
+        let click = InputEvent::MouseButton { button: MouseButton::Left, pressed: true };
+        
+        // The session and the viewer both have to be let in on it
+        assert!(refused(send(click).await));
+        host.set_remote_control(&session_id, true).await.unwrap();
+        assert!(refused(send(click).await));
+        assert!(!host.get_participants(&session_id).await.unwrap()[0].can_control);
+        host.allow_control(&session_id, "10.0.0.2", true).await.unwrap();
+        assert!(host.get_participants(&session_id).await.unwrap()[0].can_control);
+        assert!(host.allow_control(&session_id, "10.0.0.9", true).await.is_err());
+        
+        // Monitor 2 is 1280x1024 at x 1920; positions land on the part shared
+        send(InputEvent::MouseMove { x: 0.5, y: 0.5 }).await.unwrap();
+        host.set_capture_region(&session_id, Some(CaptureRegion { x: 600, y: 300, width: 200, height: 100 })).await.unwrap();
+        send(InputEvent::MouseMove { x: 0.25, y: 1.0 }).await.unwrap();
+        send(click).await.unwrap();
+        send(InputEvent::Key { key: Key::Char('a'), pressed: true }).await.unwrap();
+        assert_eq!(*input.played.lock().unwrap(), [
+            InputAction::MoveTo { x: 1920 + 640, y: 512 },
+            InputAction::MoveTo { x: 1920 + 650, y: 399 },
+            InputAction::Button { button: MouseButton::Left, pressed: true },
+            InputAction::Key { key: Key::Char('a'), pressed: true },
+        ]);
+        
+        // Revoking stops it at once, and the viewer has to be let in again
+        host.revoke_control().await;
+        assert!(refused(send(click).await));
+        host.set_remote_control(&session_id, true).await.unwrap();
+        assert!(refused(send(click).await));
+        assert_eq!(input.played.lock().unwrap().len(), 4);
+        host.stop_sharing(&session_id, "host").await.unwrap();
+    }
+}
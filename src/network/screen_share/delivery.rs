@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{RwLock, broadcast};
+use anyhow::Error;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+use crate::network::session_protocol::SessionTransport;
+use crate::network::frame_buffer::FrameBuffer;
+use crate::network::lan_frames::LanFrameLinks;
+use crate::network::video::{FrameCodec, VideoPacket};
+
+use super::{Frame, FrameHeader, Media, ScreenShare, SessionId, ViewerGrant};
+
+/// Frames buffered per subscriber before the oldest are dropped
+const FRAME_CHANNEL_CAPACITY: usize = 8;
+
+/// Frame data a session sent each viewer, against what sending every frame
+/// in full would have cost
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameTraffic {
+    /// Keyframes, and every frame of a session sent as JPEG
+    pub full_frames: u64,
+    /// Frames sent as only the tiles that changed
+    pub delta_frames: u64,
+    pub heartbeats: u64,
+    pub sent_bytes: u64,
+    pub full_frame_bytes: u64,
+}
+
+impl FrameTraffic {
+    pub(super) fn record(&mut self, frame: &Frame, full_frame_len: u64) {
+        if frame.header.unchanged {
+            self.heartbeats += 1;
+        } else if frame.header.keyframe {
+            self.full_frames += 1;
+        } else {
+            self.delta_frames += 1;
+        }
+        self.sent_bytes += frame.data.len() as u64;
+        self.full_frame_bytes += full_frame_len;
+    }
+    
+    pub fn saved_bytes(&self) -> u64 {
+        self.full_frame_bytes.saturating_sub(self.sent_bytes)
+    }
+}
+
+pub(super) struct FrameChannel {
+    session_id: SessionId,
+    pub(super) sender: broadcast::Sender<Frame>,
+    next_sequence: u64,
+}
+
+impl FrameChannel {
+    pub(super) fn new(session_id: &str) -> Self {
+        Self {
+            session_id: SessionId::from(session_id),
+            sender: broadcast::channel(FRAME_CHANNEL_CAPACITY).0,
+            next_sequence: 0,
+        }
+    }
+}
+
+impl ScreenShare {
+    pub(super) async fn send_last_keyframe(&self, session_id: &str, peer_id: &str) {
+        // Nothing while paused, and nothing the viewer couldn't decode
+        let keyframe = self.sessions.read().await.get(session_id).filter(|session| !session.paused).and_then(|session| {
+            session
+                .last_keyframe
+                .clone()
+                .filter(|frame| frame.header.codec == FrameCodec::Jpeg || frame.header.codec == session.codec)
+        });
+        let Some(frame) = keyframe else {
+            return;
+        };
+        if let Err(e) = Self::deliver_frame(self.frame_links.as_deref(), self.transport.as_deref(), peer_id, frame).await {
+            tracing::debug!("Last keyframe of session {} not sent to {}: {}", session_id, peer_id, e);
+        }
+    }
+    
+    /// Viewers that subscribed with their current token
+    pub(super) async fn subscribed_viewers(
+        grants: &RwLock<HashMap<String, HashMap<String, ViewerGrant>>>,
+        session_id: &str,
+    ) -> Vec<String> {
+        grants
+            .read()
+            .await
+            .get(session_id)
+            .map(|viewers| {
+                viewers
+                    .iter()
+                    .filter(|(_, grant)| grant.subscribed)
+                    .map(|(peer_id, _)| peer_id.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    /// Sends to every subscribed viewer at once; a viewer that can't be
+    /// reached misses the frame without holding up the others.
+    pub async fn broadcast_to_session(&self, session_id: &str, frame_data: Bytes) -> Result<(), Error> {
+        let Some(resolution) = self.sessions.read().await.get(session_id).map(|session| session.resolution) else {
+            return Ok(());
+        };
+        
+        // Store frame in buffer and hand it to local subscribers
+        let frame = Self::publish_frame(
+            &self.frame_channels,
+            &self.frame_buffer,
+            session_id,
+            resolution,
+            Some(VideoPacket::jpeg(frame_data)),
+        ).await;
+        
+        // Send to subscribed viewers (mesh distribution)
+        let viewers = Self::subscribed_viewers(&self.grants, session_id).await;
+        futures::future::join_all(viewers.iter().map(|viewer| {
+            let frame = frame.clone();
+            async move {
+                if let Err(e) = self.send_frame_to_peer(viewer, frame).await {
+                    tracing::debug!("Frame to {} dropped: {}", viewer, e);
+                }
+            }
+        })).await;
+        
+        Ok(())
+    }
+    
+    pub async fn get_frame(&self, session_id: &str) -> Option<Bytes> {
+        let buffer = self.frame_buffer.read().await;
+        buffer.latest(session_id).map(|frame| frame.data.clone())
+    }
+    
+    /// Most recent frame with its header, e.g. for thumbnails
+    pub async fn get_latest_frame(&self, session_id: &str) -> Option<Frame> {
+        let buffer = self.frame_buffer.read().await;
+        buffer.latest(session_id).cloned()
+    }
+    
+    /// Receive every frame captured for a session. Slow receivers skip the
+    /// oldest frames instead of holding up capture.
+    pub async fn subscribe_frames(&self, session_id: &str) -> Result<broadcast::Receiver<Frame>, Error> {
+        self.frame_channels
+            .read()
+            .await
+            .get(session_id)
+            .map(|channel| channel.sender.subscribe())
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()).into())
+    }
+    
+    /// Number the next frame and, if it's a JPEG, buffer it and hand it to
+    /// local subscribers. No `packet` makes it a heartbeat.
+    pub(super) async fn publish_frame(
+        frame_channels: &RwLock<HashMap<String, FrameChannel>>,
+        frame_buffer: &RwLock<FrameBuffer>,
+        session_id: &str,
+        resolution: (u32, u32),
+        packet: Option<VideoPacket>,
+    ) -> Frame {
+        let mut channels = frame_channels.write().await;
+        let (session_id, sequence) = match channels.get_mut(session_id) {
+            Some(channel) => {
+                channel.next_sequence += 1;
+                (channel.session_id.clone(), channel.next_sequence)
+            }
+            None => (SessionId::from(session_id), 0),
+        };
+        
+        // A heartbeat carries no picture of its own
+        let (codec, keyframe) = packet.as_ref().map_or((FrameCodec::Jpeg, false), |packet| (packet.codec, packet.keyframe));
+        let frame = Frame {
+            header: Arc::new(FrameHeader {
+                session_id: session_id.clone(),
+                sequence,
+                timestamp_ms: chrono::Utc::now().timestamp_millis() as u64,
+                width: resolution.0,
+                height: resolution.1,
+                unchanged: packet.is_none(),
+                codec,
+                keyframe,
+                media: Media::Video,
+            }),
+            data: packet.map(|packet| packet.data).unwrap_or_default(),
+        };
+        // The buffer and subscribers only hold frames ready to show
+        if frame.header.unchanged || frame.header.codec != FrameCodec::Jpeg {
+            return frame;
+        }
+        
+        // A session without a channel has stopped; its buffer stays empty
+        if let Some(channel) = channels.get(session_id.as_str()) {
+            frame_buffer.write().await.insert(frame.clone());
+            // No subscribers is fine
+            let _ = channel.sender.send(frame.clone());
+        }
+        
+        frame
+    }
+    
+    async fn send_frame_to_peer(&self, peer_id: &str, frame: Frame) -> Result<(), Error> {
+        Self::deliver_frame(self.frame_links.as_deref(), self.transport.as_deref(), peer_id, frame).await.map(|_| ())
+    }
+    
+    /// Over the viewer's frame connection if it has one, which never waits
+    /// on the viewer, otherwise through the session transport. Returns how
+    /// long a transport send took; frames queued on a connection are
+    /// reported by its writer instead.
+    pub(super) async fn deliver_frame(
+        frame_links: Option<&LanFrameLinks>,
+        transport: Option<&dyn SessionTransport>,
+        peer_id: &str,
+        frame: Frame,
+    ) -> Result<Option<Duration>, Error> {
+        if let Some(links) = frame_links.filter(|links| links.is_attached(peer_id)) {
+            return links.send_frame(peer_id, frame).map(|_| None);
+        }
+        match transport {
+            Some(transport) => {
+                let started = Instant::now();
+                transport.send_frame(peer_id, frame).await?;
+                Ok(Some(started.elapsed()))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashSet, VecDeque};
+    use async_trait::async_trait;
+    use crate::network::counting_alloc;
+    use crate::network::adaptive::{AdaptiveConfig, StreamSettings};
+    use crate::network::frame_buffer::FrameBufferConfig;
+    use crate::network::quality_preset::{QualityPreset, StreamOverrides};
+    use crate::network::screen_share::{RemoteSession, SessionStatus, SharingSession, ViewingSession};
+    use crate::network::screen_share::tests::frame;
+    use crate::network::session_protocol::{
+        AccessMode, ControlMessage, JoinRequest, JoinResponse, SessionAnnouncement, SessionEnded, SessionToken, TokenGrant,
+    };
+    use crate::network::stream_meter::StreamMeter;
+    use crate::platform::DEFAULT_JPEG_QUALITY;
+    use crate::platform::fallback::FallbackCapture;
+    
+    const FRAME_LEN: usize = 1024 * 1024;
+    
+    /// Takes frames the way a socket writer would, without copying them
+    struct NullTransport;
+    
+    #[async_trait]
+    impl SessionTransport for NullTransport {
+        async fn announce(&self, _announcement: SessionAnnouncement) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn request_join(&self, _host_peer_id: &str, _request: JoinRequest) -> Result<JoinResponse, Error> {
+            Ok(JoinResponse::Denied)
+        }
+        
+        async fn send_control(&self, _host_peer_id: &str, _message: ControlMessage) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn grant_token(&self, _peer_id: &str, _grant: TokenGrant) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn end_session(&self, _peer_id: &str, _ended: SessionEnded) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn send_frame(&self, _peer_id: &str, frame: Frame) -> Result<(), Error> {
+            assert_eq!(frame.data.len(), FRAME_LEN);
+            Ok(())
+        }
+    }
+    
+    /// Bytes allocated while one frame of `session_id` goes to `viewers`
+    /// subscribed viewers and a local subscriber
+    async fn bytes_per_frame(session_id: &str, viewers: usize) -> usize {
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), Arc::new(NullTransport));
+        let session_id = session_id.to_string();
+        screen_share.sessions.write().await.insert(session_id.clone(), SharingSession {
+            session_id: session_id.clone(),
+            host_peer_id: "host".to_string(),
+            participants: HashSet::new(),
+            max_participants: None,
+            is_recording: true,
+            frame_rate: 15,
+            resolution: (1920, 1080),
+            codec: FrameCodec::Jpeg,
+            monitor_id: None,
+            region: None,
+            show_cursor: true,
+            audio: false,
+            quality: DEFAULT_JPEG_QUALITY,
+            scale_percent: 100,
+            preset: QualityPreset::Balanced,
+            overrides: StreamOverrides::default(),
+            access_mode: AccessMode::Open,
+            paused: false,
+            traffic: FrameTraffic::default(),
+            adaptive: AdaptiveConfig::default(),
+            stream: StreamSettings { quality: DEFAULT_JPEG_QUALITY, frame_rate: 15, scale_percent: 100 },
+            adaptations: VecDeque::new(),
+            started_at_ms: 0,
+            meter: StreamMeter::default(),
+            dropped_frames: 0,
+            allow_remote_control: false,
+            last_keyframe: None,
+            status: SessionStatus::Active,
+        });
+        screen_share.frame_channels.write().await.insert(session_id.clone(), FrameChannel::new(&session_id));
+        let grants = (0..viewers)
+            .map(|i| (format!("10.0.0.{}", i), ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) }))
+            .collect();
+        screen_share.grants.write().await.insert(session_id.clone(), grants);
+        let mut frames = screen_share.subscribe_frames(&session_id).await.unwrap();
+        
+        // The first frame also sets up the buffer slot
+        let captured = Bytes::from(vec![7u8; FRAME_LEN]);
+        screen_share.broadcast_to_session(&session_id, captured.clone()).await.unwrap();
+        
+        counting_alloc::start();
+        screen_share.broadcast_to_session(&session_id, captured.clone()).await.unwrap();
+        let allocated = counting_alloc::stop();
+        
+        let latest = screen_share.get_frame(&session_id).await.unwrap();
+        assert_eq!(latest.as_ptr(), captured.as_ptr());
+        assert_eq!(frames.recv().await.unwrap().data.as_ptr(), captured.as_ptr());
+        allocated
+    }
+    
+    #[tokio::test]
+    async fn test_frame_is_shared_not_copied_per_viewer() {
+        let one = bytes_per_frame("session", 1).await;
+        let many = bytes_per_frame("session", 16).await;
+        
+        // Only bookkeeping grows with the audience, never the frame itself
+        assert!(one < FRAME_LEN / 64, "{} bytes for one viewer", one);
+        assert!(many < FRAME_LEN / 64, "{} bytes for sixteen viewers", many);
+    }
+    
+    #[tokio::test]
+    async fn test_frames_allocate_no_session_key() {
+        // Any per-frame copy of a key this long would show up at once
+        const KEY_LEN: usize = 64 * 1024;
+        let session_id = "s".repeat(KEY_LEN);
+        let allocated = bytes_per_frame(&session_id, 4).await;
+        assert!(allocated < KEY_LEN, "{} bytes per frame", allocated);
+    }
+    
+    /// Fails every frame sent to "10.0.0.1" and records the rest
+    #[derive(Default)]
+    struct UnreachableTransport {
+        reached: std::sync::Mutex<Vec<String>>,
+    }
+    
+    #[async_trait]
+    impl SessionTransport for UnreachableTransport {
+        async fn announce(&self, _announcement: SessionAnnouncement) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn request_join(&self, _host_peer_id: &str, _request: JoinRequest) -> Result<JoinResponse, Error> {
+            Ok(JoinResponse::Denied)
+        }
+        
+        async fn send_control(&self, _host_peer_id: &str, _message: ControlMessage) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn grant_token(&self, _peer_id: &str, _grant: TokenGrant) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn end_session(&self, _peer_id: &str, _ended: SessionEnded) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn send_frame(&self, peer_id: &str, _frame: Frame) -> Result<(), Error> {
+            if peer_id == "10.0.0.1" {
+                return Err(DeskShareError::PeerConnectionFailed(peer_id.to_string()).into());
+            }
+            self.reached.lock().unwrap().push(peer_id.to_string());
+            Ok(())
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_broadcast_reaches_viewers_past_a_failed_one() {
+        let transport = Arc::new(UnreachableTransport::default());
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), transport.clone());
+        let session_id = screen_share.start_sharing("host".to_string(), 1, (64, 48), None, None, None).await.unwrap();
+        let grants = (0..4)
+            .map(|i| (format!("10.0.0.{}", i), ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) }))
+            .collect();
+        screen_share.grants.write().await.insert(session_id.clone(), grants);
+        transport.reached.lock().unwrap().clear();
+        
+        screen_share.broadcast_to_session(&session_id, Bytes::from_static(b"frame")).await.unwrap();
+        
+        let reached: HashSet<String> = transport.reached.lock().unwrap().drain(..).collect();
+        for viewer in ["10.0.0.0", "10.0.0.2", "10.0.0.3"] {
+            assert!(reached.contains(viewer), "{} missed the frame", viewer);
+        }
+        assert!(!reached.contains("10.0.0.1"));
+    }
+    
+    #[tokio::test]
+    async fn test_buffers_stay_in_budget_and_empty_after_sessions_end() {
+        const LIMIT: u64 = 256 * 1024;
+        const FRAME: usize = 16 * 1024;
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_frame_buffer(FrameBufferConfig { limit_bytes: LIMIT, frames_per_session: 4 });
+        
+        let mut live = std::collections::VecDeque::new();
+        for n in 0..100 {
+            // Hosted sessions, a handful alive at once so eviction has to kick in
+            let session_id = screen_share.start_sharing("local".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+            for _ in 0..8 {
+                screen_share.broadcast_to_session(&session_id, Bytes::from(vec![n as u8; FRAME])).await.unwrap();
+            }
+            let stats = screen_share.session_stats(&session_id).await.unwrap();
+            assert!(stats.buffered_frames <= 4 && stats.buffered_bytes <= LIMIT);
+            live.push_back(session_id);
+            if live.len() > 5 {
+                screen_share.stop_sharing(&live.pop_front().unwrap(), "local").await.unwrap();
+            }
+            
+            // And a session viewed from here, left after a few frames
+            let viewed = format!("remote-{}", n);
+            screen_share.viewing.write().await.insert(viewed.clone(), ViewingSession {
+                remote: RemoteSession {
+                    session_id: viewed.clone(),
+                    host_peer_id: "10.0.0.7".to_string(),
+                    resolution: (64, 48),
+                    access_mode: AccessMode::Open,
+                    participant_count: 1,
+                    last_seen: 0,
+                    host_name: None,
+                    started_at_ms: 0,
+                    has_password: false,
+                    preset: None,
+                },
+                token: SessionToken::generate(),
+                decoder: Default::default(),
+                audio: None,
+            });
+            for _ in 0..3 {
+                screen_share.receive_frame(frame(&viewed, FRAME)).await;
+            }
+            screen_share.leave_remote_session(&viewed).await.unwrap();
+            assert_eq!(screen_share.session_stats(&viewed).await, None);
+            
+            assert!(screen_share.buffer_usage().await.bytes <= LIMIT);
+        }
+        for session_id in live {
+            screen_share.stop_sharing(&session_id, "local").await.unwrap();
+        }
+        // A capture that was mid-frame when its session stopped finishes now
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        
+        let usage = screen_share.buffer_usage().await;
+        assert_eq!((usage.bytes, usage.frames, usage.sessions), (0, 0, 0));
+        assert!(usage.peak_bytes <= LIMIT && usage.peak_bytes > LIMIT / 2, "{:?}", usage);
+        assert!(usage.evicted_frames > 0);
+        assert!(screen_share.capture_handles.read().await.is_empty());
+    }
+    
+    #[test]
+    fn test_eviction_prefers_superseded_frames_then_idle_sessions() {
+        let mut buffer = FrameBuffer::new(FrameBufferConfig { limit_bytes: 100, frames_per_session: 4 });
+        buffer.insert(frame("idle", 20));
+        buffer.insert(frame("busy", 20));
+        buffer.insert(frame("busy", 20));
+        buffer.insert(frame("busy", 20));
+        buffer.insert(frame("busy", 30));
+        
+        // Busy's oldest frame went first; idle's only frame survives
+        assert_eq!(buffer.session_usage("busy"), (3, 70));
+        assert_eq!(buffer.session_usage("idle"), (1, 20));
+        
+        buffer.insert(frame("new", 60));
+        assert_eq!(buffer.session_usage("idle"), (0, 0));
+        assert!(buffer.usage().bytes <= 100);
+        assert_eq!(buffer.latest("busy").unwrap().data.len(), 30);
+        
+        // Too big to ever fit: not buffered, and nothing else is lost for it
+        let before = buffer.usage();
+        buffer.insert(frame("huge", 101));
+        assert_eq!(buffer.usage().bytes, before.bytes);
+        assert!(buffer.latest("huge").is_none());
+    }
+}
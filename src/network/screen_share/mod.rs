@@ -0,0 +1,1070 @@
+// Sharing a screen with viewers and watching other hosts' screens
+//
+// `ScreenShare` and the types every part of it uses live here; its methods
+// are spread over the submodules by what they do, from capturing and
+// delivering frames to admitting viewers and handing them control.
+
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+use tokio::sync::{RwLock, broadcast, oneshot, watch};
+use dashmap::DashMap;
+use anyhow::Error;
+use bytes::Bytes;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+use crate::platform::audio::{AudioBackend, NoAudio};
+use crate::platform::input::{InputBackend, NoInput};
+use crate::platform::{CaptureBackend, CaptureRegion, MonitorInfo, NativeAudio, NativeCapture, NativeInput};
+use crate::security::{RateLimiter, TrustStore};
+use super::session_protocol::{AccessMode, SessionEnded, SessionToken, SessionTransport};
+use super::adaptive::{Adaptation, AdaptiveConfig, StreamSettings};
+use super::frame_buffer::{BufferUsage, FrameBuffer, FrameBufferConfig};
+use super::idle::CaptureConfig;
+use super::lan_announce::{LanAnnouncer, SessionBeacon};
+use super::lan_frames::LanFrameLinks;
+use super::lan_session::LanSessionTransport;
+use super::quality_preset::{QualityPreset, StreamOverrides};
+use super::stream_meter::StreamMeter;
+use super::timings::StageTimings;
+use super::video::FrameCodec;
+
+mod access;
+mod announce;
+mod audio;
+mod capture;
+mod control;
+mod delivery;
+mod participants;
+mod settings;
+mod viewer;
+
+pub use access::PendingJoin;
+pub use announce::RemoteSession;
+pub use capture::{MonitorLost, SessionStatus, SessionStatusChange};
+pub use delivery::FrameTraffic;
+pub use participants::{Participant, ParticipantChange};
+use capture::CaptureTarget;
+use delivery::FrameChannel;
+use viewer::ViewingSession;
+
+/// Stream adaptations a session remembers for its stats
+const ADAPTATIONS_KEPT: usize = 16;
+
+/// How long a viewer waits for the host to approve a join
+const DEFAULT_JOIN_APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Clones share the same sessions
+#[derive(Clone)]
+pub struct ScreenShare {
+    sessions: Arc<RwLock<HashMap<String, SharingSession>>>,
+    frame_buffer: Arc<RwLock<FrameBuffer>>,
+    frame_channels: Arc<RwLock<HashMap<String, FrameChannel>>>,
+    capture_handles: Arc<RwLock<HashMap<String, tokio::task::JoinHandle<()>>>>,
+    audio_handles: Arc<RwLock<HashMap<String, tokio::task::JoinHandle<()>>>>,
+    capture: Arc<dyn CaptureBackend>,
+    audio: Arc<dyn AudioBackend>,
+    /// Where viewers' input is played back, in sessions that allow it
+    input: Arc<dyn InputBackend>,
+    transport: Option<Arc<dyn SessionTransport>>,
+    /// Viewers that connected to us for frames get them this way
+    frame_links: Option<Arc<LanFrameLinks>>,
+    /// Our sessions are broadcast to the LAN this way
+    announcer: Option<Arc<LanAnnouncer>>,
+    /// Viewers' notice connections, and our own to hosts
+    lan_transport: Option<Arc<LanSessionTransport>>,
+    local_peer_id: String,
+    passwords: Arc<RwLock<HashMap<String, blake3::Hash>>>,
+    remote_sessions: Arc<RwLock<HashMap<String, RemoteSession>>>,
+    viewing: Arc<RwLock<HashMap<String, ViewingSession>>>,
+    grants: Arc<RwLock<HashMap<String, HashMap<String, ViewerGrant>>>>,
+    pending_joins: Arc<DashMap<String, oneshot::Sender<bool>>>,
+    join_tx: broadcast::Sender<PendingJoin>,
+    monitor_lost_tx: broadcast::Sender<MonitorLost>,
+    status_tx: broadcast::Sender<SessionStatusChange>,
+    participant_tx: broadcast::Sender<ParticipantChange>,
+    summary_tx: broadcast::Sender<SessionSummary>,
+    /// Sent with our joins so hosts can list us by name
+    display_name: Option<String>,
+    join_approval_timeout_ms: Arc<AtomicU64>,
+    trust_store: Option<Arc<TrustStore>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    capture_timings: Arc<std::sync::Mutex<StageTimings>>,
+    capture_config: CaptureConfig,
+    /// Set once the last clone goes away; capture and audio tasks stop on it
+    closed: Arc<CloseSignal>,
+}
+
+struct CloseSignal(watch::Sender<bool>);
+
+/// Capture tasks don't outlive the service that started them. Signalled
+/// rather than aborted here, since the handles are behind an async lock.
+impl Drop for CloseSignal {
+    fn drop(&mut self) {
+        self.0.send_replace(true);
+    }
+}
+
+#[derive(Clone)]
+pub struct SharingSession {
+    pub session_id: String,
+    pub host_peer_id: String,
+    pub participants: HashSet<String>,
+    /// Joins past this many viewers are turned away
+    pub max_participants: Option<usize>,
+    pub is_recording: bool,
+    pub frame_rate: u32,
+    pub resolution: (u32, u32),
+    pub codec: FrameCodec,
+    pub monitor_id: Option<u32>,
+    /// Share only this part of the monitor
+    pub region: Option<CaptureRegion>,
+    /// Draw the cursor into frames
+    pub show_cursor: bool,
+    /// System audio goes out alongside the frames
+    pub audio: bool,
+    pub quality: u8,
+    /// Share of `resolution` to capture at
+    pub scale_percent: u32,
+    /// What `quality`, `frame_rate` and `scale_percent` came from
+    pub preset: QualityPreset,
+    pub overrides: StreamOverrides,
+    pub access_mode: AccessMode,
+    /// Paused for privacy: nothing is captured or sent
+    pub paused: bool,
+    pub traffic: FrameTraffic,
+    pub adaptive: AdaptiveConfig,
+    /// What capture is producing now; `quality`, `frame_rate` and
+    /// `resolution` are the most it may produce
+    pub stream: StreamSettings,
+    /// Most recent last
+    pub adaptations: VecDeque<Adaptation>,
+    pub started_at_ms: u64,
+    /// Frames capture produced lately; starts over with the capture
+    pub meter: StreamMeter,
+    /// Frames viewers missed for not keeping up, since capture started
+    pub dropped_frames: u64,
+    /// Viewers the host lets in on it may drive the mouse and keyboard
+    pub allow_remote_control: bool,
+    /// The latest frame a viewer could start from, as viewers were sent
+    /// it. Dropped when the session pauses or what it shows moves.
+    pub last_keyframe: Option<Frame>,
+    /// Whether capture is working
+    pub status: SessionStatus,
+}
+
+impl SharingSession {
+    /// No room for `peer_id`; a viewer already in keeps its place
+    fn is_full(&self, peer_id: &str) -> bool {
+        self.max_participants
+            .is_some_and(|max| self.participants.len() >= max && !self.participants.contains(peer_id))
+    }
+    
+    /// The most the capture loop may produce
+    fn ceiling(&self) -> StreamSettings {
+        StreamSettings { quality: self.quality, frame_rate: self.frame_rate, scale_percent: self.scale_percent }
+    }
+    
+    fn capture_target(&self) -> CaptureTarget {
+        CaptureTarget {
+            monitor_id: self.monitor_id,
+            region: self.region,
+            show_cursor: self.show_cursor,
+        }
+    }
+}
+
+/// A session id resolved once when the session starts. Frames and the
+/// frame buffer share it, so tagging and buffering a frame allocates no key.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct SessionId(Arc<str>);
+
+impl SessionId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for SessionId {
+    fn from(session_id: &str) -> Self {
+        Self(Arc::from(session_id))
+    }
+}
+
+impl From<String> for SessionId {
+    fn from(session_id: String) -> Self {
+        Self(Arc::from(session_id))
+    }
+}
+
+impl From<SessionId> for String {
+    fn from(session_id: SessionId) -> Self {
+        session_id.0.to_string()
+    }
+}
+
+impl Borrow<str> for SessionId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Metadata sent ahead of every encoded frame
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FrameHeader {
+    pub session_id: SessionId,
+    pub sequence: u64,
+    pub timestamp_ms: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Heartbeat for a screen that hasn't changed since the last frame;
+    /// carries no data
+    #[serde(default)]
+    pub unchanged: bool,
+    #[serde(default)]
+    pub codec: FrameCodec,
+    /// Decodes without the frames before it; always so for JPEG
+    #[serde(default = "standalone")]
+    pub keyframe: bool,
+    #[serde(default)]
+    pub media: Media,
+}
+
+/// What a frame carries. Audio frames hold one Opus packet, have no size
+/// and are numbered apart from the picture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Media {
+    #[default]
+    Video,
+    Audio,
+}
+
+fn standalone() -> bool {
+    true
+}
+
+/// One encoded frame. Cloning only bumps reference counts, so the buffer,
+/// every subscriber and every viewer's send share a single copy.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub header: Arc<FrameHeader>,
+    pub data: Bytes,
+}
+
+/// Per-session figures for the stats panel
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionStats {
+    pub session_id: String,
+    pub participants: usize,
+    pub buffered_frames: usize,
+    pub buffered_bytes: u64,
+    /// What frames to viewers cost; only kept by the host
+    #[serde(default)]
+    pub traffic: FrameTraffic,
+    /// Hosted sessions only
+    #[serde(default)]
+    pub stream: Option<StreamSettings>,
+    /// What the host picked; known to viewers from its announcements
+    #[serde(default)]
+    pub preset: Option<QualityPreset>,
+    #[serde(default)]
+    pub adaptations: Vec<Adaptation>,
+    /// Frames capture actually produced a second, over the last few
+    /// seconds; hosted sessions only, as are the rest
+    #[serde(default)]
+    pub frames_per_second: f64,
+    /// Encoded frame data produced a second, over the same stretch
+    #[serde(default)]
+    pub bytes_per_second: u64,
+    /// Frames viewers missed for not keeping up
+    #[serde(default)]
+    pub dropped_frames: u64,
+    #[serde(default)]
+    pub viewers: Vec<ViewerStats>,
+    #[serde(default)]
+    pub status: SessionStatus,
+}
+
+/// How one viewer of a hosted session is keeping up
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewerStats {
+    pub peer_id: String,
+    /// Slowest a frame took to reach them lately; None until one has
+    pub latency_ms: Option<u64>,
+    pub dropped_frames: u64,
+}
+
+/// A hosted session's stats as it ended
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub stats: SessionStats,
+    pub duration_ms: u64,
+}
+
+/// A viewer admitted to a session we host
+struct ViewerGrant {
+    token: SessionToken,
+    subscribed: bool,
+    /// Asked for the session's audio when joining
+    audio: bool,
+    display_name: Option<String>,
+    joined_at_ms: u64,
+    latency: Option<Duration>,
+    dropped_frames: u64,
+    /// Let drive the mouse and keyboard, when the session allows it
+    control: bool,
+    /// Subscribed at some point, so it has had the last keyframe
+    streamed: bool,
+}
+
+impl ViewerGrant {
+    fn new(token: SessionToken) -> Self {
+        Self {
+            token,
+            subscribed: false,
+            audio: false,
+            display_name: None,
+            joined_at_ms: chrono::Utc::now().timestamp_millis() as u64,
+            latency: None,
+            dropped_frames: 0,
+            control: false,
+            streamed: false,
+        }
+    }
+}
+
+impl ScreenShare {
+    pub async fn new() -> Self {
+        Self::with_capture_backend(Arc::new(NativeCapture))
+            .with_audio_backend(Arc::new(NativeAudio))
+            .with_input_backend(Arc::new(NativeInput))
+    }
+    
+    /// Use a specific capture backend, e.g. the fallback one in tests
+    pub fn with_capture_backend(capture: Arc<dyn CaptureBackend>) -> Self {
+        Self {
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            frame_buffer: Arc::new(RwLock::new(FrameBuffer::new(FrameBufferConfig::default()))),
+            frame_channels: Arc::new(RwLock::new(HashMap::new())),
+            capture_handles: Arc::new(RwLock::new(HashMap::new())),
+            audio_handles: Arc::new(RwLock::new(HashMap::new())),
+            capture,
+            audio: Arc::new(NoAudio),
+            input: Arc::new(NoInput),
+            transport: None,
+            frame_links: None,
+            announcer: None,
+            lan_transport: None,
+            local_peer_id: "local".to_string(),
+            passwords: Arc::new(RwLock::new(HashMap::new())),
+            remote_sessions: Arc::new(RwLock::new(HashMap::new())),
+            viewing: Arc::new(RwLock::new(HashMap::new())),
+            grants: Arc::new(RwLock::new(HashMap::new())),
+            pending_joins: Arc::new(DashMap::new()),
+            join_tx: broadcast::channel(32).0,
+            monitor_lost_tx: broadcast::channel(8).0,
+            status_tx: broadcast::channel(8).0,
+            participant_tx: broadcast::channel(32).0,
+            summary_tx: broadcast::channel(8).0,
+            display_name: None,
+            join_approval_timeout_ms: Arc::new(AtomicU64::new(DEFAULT_JOIN_APPROVAL_TIMEOUT.as_millis() as u64)),
+            trust_store: None,
+            rate_limiter: None,
+            capture_timings: Arc::new(std::sync::Mutex::new(StageTimings::default())),
+            capture_config: CaptureConfig::default(),
+            closed: Arc::new(CloseSignal(watch::channel(false).0)),
+        }
+    }
+    
+    pub fn local_peer_id(&self) -> &str {
+        &self.local_peer_id
+    }
+    
+    /// Send announcements, joins and frames to other peers through `transport`
+    pub fn with_transport(mut self, local_peer_id: String, transport: Arc<dyn SessionTransport>) -> Self {
+        self.local_peer_id = local_peer_id;
+        self.transport = Some(transport);
+        self
+    }
+    
+    /// Reach hosts, and the viewers who connected to us, over direct
+    /// connections; see `accept_connection`
+    pub fn with_lan_transport(mut self, local_peer_id: String, transport: Arc<LanSessionTransport>) -> Self {
+        self.local_peer_id = local_peer_id;
+        self.transport = Some(transport.clone());
+        self.lan_transport = Some(transport);
+        self
+    }
+    
+    /// Send frames over these connections to the viewers attached to them
+    pub fn with_frame_links(mut self, links: Arc<LanFrameLinks>) -> Self {
+        self.frame_links = Some(links);
+        self
+    }
+    
+    /// Broadcast our sessions, and their ending, through `announcer`
+    pub fn with_announcer(mut self, announcer: Arc<LanAnnouncer>) -> Self {
+        self.announcer = Some(announcer);
+        self
+    }
+    
+    /// Let paired devices skip approval, as their trust policy allows, and
+    /// turn blocked ones away
+    pub fn with_trust_store(mut self, trust_store: Arc<TrustStore>) -> Self {
+        self.trust_store = Some(trust_store);
+        self
+    }
+    
+    /// Drop joins and control messages from devices sending too many
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+    
+    /// Cap the memory buffered frames may use, across all sessions
+    pub fn with_frame_buffer(mut self, config: FrameBufferConfig) -> Self {
+        self.frame_buffer = Arc::new(RwLock::new(FrameBuffer::new(config)));
+        self
+    }
+    
+    /// Capture and play session audio through `audio`; without one every
+    /// session is video-only
+    pub fn with_audio_backend(mut self, audio: Arc<dyn AudioBackend>) -> Self {
+        self.audio = audio;
+        self
+    }
+    
+    /// Play viewers' input back through `input`; without one remote
+    /// control is refused
+    pub fn with_input_backend(mut self, input: Arc<dyn InputBackend>) -> Self {
+        self.input = input;
+        self
+    }
+    
+    /// What hosts we join list us as
+    pub fn with_display_name(mut self, display_name: String) -> Self {
+        self.display_name = Some(display_name);
+        self
+    }
+    
+    /// How often an unchanged screen is still sent in full
+    pub fn with_capture_config(mut self, config: CaptureConfig) -> Self {
+        self.capture_config = config;
+        self
+    }
+    
+    pub fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error> {
+        self.capture.list_monitors()
+    }
+    
+    /// Share `monitor_id` (primary when None) at the given JPEG quality,
+    /// to at most `max_participants` viewers at once
+    pub async fn start_sharing(
+        &self,
+        peer_id: String,
+        frame_rate: u32,
+        resolution: (u32, u32),
+        monitor_id: Option<u32>,
+        quality: Option<u8>,
+        max_participants: Option<usize>,
+    ) -> Result<String, Error> {
+        let overrides = StreamOverrides { quality, frame_rate: Some(frame_rate), scale_percent: None };
+        self.start_sharing_preset(peer_id, QualityPreset::Balanced, overrides, resolution, monitor_id, max_participants)
+            .await
+    }
+    
+    /// Share `monitor_id` (primary when None) at `preset`, with any of
+    /// `overrides` in place of its settings. `resolution` is the monitor's
+    /// own; the preset may capture at less.
+    pub async fn start_sharing_preset(
+        &self,
+        peer_id: String,
+        preset: QualityPreset,
+        overrides: StreamOverrides,
+        resolution: (u32, u32),
+        monitor_id: Option<u32>,
+        max_participants: Option<usize>,
+    ) -> Result<String, Error> {
+        let settings = Self::preset_settings(preset, overrides)?;
+        if let Some(id) = monitor_id {
+            self.ensure_monitor(id)?;
+        }
+        
+        let session_id = Self::generate_session_id();
+        let StreamSettings { quality, frame_rate, scale_percent } = settings;
+        let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+        
+        let session = SharingSession {
+            session_id: session_id.clone(),
+            host_peer_id: peer_id.clone(),
+            participants: HashSet::new(),
+            max_participants,
+            is_recording: true,
+            frame_rate,
+            resolution,
+            // A codec this build can't encode, say from another build's
+            // settings, falls back as it would for a viewer
+            codec: self.capture_config.codec.fallback_for(&FrameCodec::decodable()),
+            monitor_id,
+            region: None,
+            show_cursor: true,
+            audio: false,
+            quality,
+            scale_percent,
+            preset,
+            overrides,
+            access_mode: AccessMode::Open,
+            paused: false,
+            traffic: FrameTraffic::default(),
+            adaptive: self.capture_config.adaptive,
+            stream: settings,
+            adaptations: VecDeque::new(),
+            started_at_ms: now_ms,
+            meter: StreamMeter::new(now_ms),
+            dropped_frames: 0,
+            allow_remote_control: false,
+            last_keyframe: None,
+            status: SessionStatus::Active,
+        };
+        
+        self.sessions.write().await.insert(session_id.clone(), session);
+        self.frame_channels.write().await.insert(session_id.clone(), FrameChannel::new(&session_id));
+        
+        // Start screen capture
+        self.start_screen_capture(&session_id).await?;
+        
+        // Announce session to network
+        self.announce_session(&session_id).await?;
+        
+        Ok(session_id)
+    }
+    
+    pub async fn get_session(&self, session_id: &str) -> Option<SharingSession> {
+        self.sessions.read().await.get(session_id).cloned()
+    }
+    
+    fn now_secs() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+    
+    /// Each hosted session's final stats, once it's stopped
+    pub fn subscribe_session_summaries(&self) -> broadcast::Receiver<SessionSummary> {
+        self.summary_tx.subscribe()
+    }
+    
+    /// End a session we host. Only `peer_id` the host may; everyone who was
+    /// let in is told it's over.
+    pub async fn stop_sharing(&self, session_id: &str, peer_id: &str) -> Result<(), Error> {
+        let stats = self.session_stats(session_id).await;
+        let session = {
+            let mut sessions = self.sessions.write().await;
+            match sessions.get(session_id) {
+                None => return Ok(()),
+                Some(session) if session.host_peer_id != peer_id => {
+                    return Err(DeskShareError::NotSessionHost(session_id.to_string()).into());
+                }
+                Some(_) => sessions.remove(session_id).unwrap(),
+            }
+        };
+        
+        if let Some(handle) = self.capture_handles.write().await.remove(session_id) {
+            handle.abort();
+        }
+        if let Some(handle) = self.audio_handles.write().await.remove(session_id) {
+            handle.abort();
+        }
+        let granted = self.grants.write().await.remove(session_id).unwrap_or_default();
+        self.passwords.write().await.remove(session_id);
+        // The channel goes before the buffer: publish_frame only buffers
+        // frames for sessions that still have one
+        self.frame_channels.write().await.remove(session_id);
+        self.frame_buffer.write().await.remove_session(session_id);
+        if let Some(stats) = stats {
+            let duration_ms = (chrono::Utc::now().timestamp_millis() as u64).saturating_sub(session.started_at_ms);
+            let _ = self.summary_tx.send(SessionSummary { stats, duration_ms });
+        }
+        if let Some(announcer) = &self.announcer {
+            let ended = SessionEnded {
+                session_id: session_id.to_string(),
+                host_peer_id: session.host_peer_id.clone(),
+            };
+            if let Err(e) = announcer.send(&SessionBeacon::Retract(ended)).await {
+                tracing::debug!("Ending {} not broadcast: {}", session_id, e);
+            }
+        }
+        
+        let Some(transport) = &self.transport else {
+            return Ok(());
+        };
+        let viewers: HashSet<String> = session.participants.into_iter().chain(granted.into_keys()).collect();
+        for viewer in viewers {
+            let ended = SessionEnded {
+                session_id: session_id.to_string(),
+                host_peer_id: session.host_peer_id.clone(),
+            };
+            if let Err(e) = transport.end_session(&viewer, ended).await {
+                tracing::debug!("{} not told that {} ended: {}", viewer, session_id, e);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Frame buffer use across every session
+    pub async fn buffer_usage(&self) -> BufferUsage {
+        self.frame_buffer.read().await.usage()
+    }
+    
+    /// A hosted or viewed session's participants and buffered frames, and
+    /// for hosted ones how capture and each viewer are keeping up
+    pub async fn session_stats(&self, session_id: &str) -> Option<SessionStats> {
+        let now_ms = chrono::Utc::now().timestamp_millis() as u64;
+        let mut stats = match self.sessions.read().await.get(session_id) {
+            Some(session) => SessionStats {
+                participants: session.participants.len(),
+                traffic: session.traffic,
+                stream: Some(session.stream),
+                preset: Some(session.preset),
+                adaptations: session.adaptations.iter().cloned().collect(),
+                frames_per_second: session.meter.frames_per_second(now_ms),
+                bytes_per_second: session.meter.bytes_per_second(now_ms),
+                dropped_frames: session.dropped_frames,
+                status: session.status.clone(),
+                ..SessionStats::default()
+            },
+            None => {
+                let viewing = self.viewing.read().await;
+                let remote = &viewing.get(session_id)?.remote;
+                SessionStats {
+                    participants: remote.participant_count,
+                    preset: remote.preset,
+                    ..SessionStats::default()
+                }
+            }
+        };
+        if let Some(viewers) = self.grants.read().await.get(session_id) {
+            stats.viewers = viewers
+                .iter()
+                .map(|(peer_id, grant)| ViewerStats {
+                    peer_id: peer_id.clone(),
+                    latency_ms: grant.latency.map(|latency| latency.as_millis() as u64),
+                    dropped_frames: grant.dropped_frames,
+                })
+                .collect();
+            stats.viewers.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        }
+        stats.session_id = session_id.to_string();
+        (stats.buffered_frames, stats.buffered_bytes) = self.frame_buffer.read().await.session_usage(session_id);
+        Some(stats)
+    }
+    
+    /// Time spent capturing and encoding each frame
+    pub fn capture_timings(&self) -> StageTimings {
+        *self.capture_timings.lock().unwrap()
+    }
+    
+    fn generate_session_id() -> String {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        format!("{:x}", rng.gen::<u128>())
+    }
+    
+    /// Run `task` until it ends or the service shuts down
+    fn spawn_until_closed(&self, task: impl Future<Output = ()> + Send + 'static) -> tokio::task::JoinHandle<()> {
+        let mut closed = self.closed.0.subscribe();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = closed.wait_for(|closed| *closed) => {}
+            }
+        })
+    }
+    
+    /// End every session we host, telling their viewers, and stop all
+    /// capture. Call before dropping the service; dropping it only stops
+    /// the capture.
+    pub async fn shutdown(&self) {
+        let hosted: Vec<(String, String)> = self
+            .sessions
+            .read()
+            .await
+            .values()
+            .map(|session| (session.session_id.clone(), session.host_peer_id.clone()))
+            .collect();
+        for (session_id, host_peer_id) in hosted {
+            if let Err(e) = self.stop_sharing(&session_id, &host_peer_id).await {
+                tracing::warn!("Session {} didn't end cleanly: {}", session_id, e);
+            }
+        }
+        self.closed.0.send_replace(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+    use async_trait::async_trait;
+    use crate::platform::fallback::FallbackCapture;
+    use crate::network::session_protocol::{ControlMessage, JoinRequest, JoinResponse, SessionAnnouncement, TokenGrant};
+    
+    /// Counts what reaches viewers: (full frames, heartbeats), and who was
+    /// told a session ended
+    #[derive(Default)]
+    pub(super) struct CountingTransport {
+        pub(super) sent: std::sync::Mutex<(usize, usize)>,
+        ended: std::sync::Mutex<Vec<String>>,
+    }
+    
+    #[async_trait]
+    impl SessionTransport for CountingTransport {
+        async fn announce(&self, _announcement: SessionAnnouncement) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn request_join(&self, _host_peer_id: &str, _request: JoinRequest) -> Result<JoinResponse, Error> {
+            Ok(JoinResponse::Denied)
+        }
+        
+        async fn send_control(&self, _host_peer_id: &str, _message: ControlMessage) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn grant_token(&self, _peer_id: &str, _grant: TokenGrant) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn end_session(&self, peer_id: &str, _ended: SessionEnded) -> Result<(), Error> {
+            self.ended.lock().unwrap().push(peer_id.to_string());
+            Ok(())
+        }
+        
+        async fn send_frame(&self, _peer_id: &str, frame: Frame) -> Result<(), Error> {
+            let mut sent = self.sent.lock().unwrap();
+            if frame.header.unchanged {
+                assert!(frame.data.is_empty());
+                sent.1 += 1;
+            } else {
+                sent.0 += 1;
+            }
+            Ok(())
+        }
+    }
+    
+    /// A slide being presented: still but for a pointer moving over it
+    #[derive(Default)]
+    pub(super) struct SlideCapture {
+        captures: std::sync::atomic::AtomicU32,
+    }
+    
+    #[async_trait]
+    impl CaptureBackend for SlideCapture {
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error> {
+            Ok(Vec::new())
+        }
+        
+        async fn capture_raw(&self, _monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<image::RgbaImage, Error> {
+            let n = self.captures.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let pointer = (20 + n * 4 % 200, 40);
+            Ok(image::RgbaImage::from_fn(resolution.0, resolution.1, |x, y| {
+                if x.abs_diff(pointer.0) < 4 && y.abs_diff(pointer.1) < 6 {
+                    image::Rgba([255, 0, 0, 255])
+                } else if (y / 10) % 3 == 0 && (x / 6) % 4 != 0 && x > 30 {
+                    image::Rgba([20, 20, 20, 255])
+                } else {
+                    image::Rgba([(x / 2) as u8, 220, (y / 2) as u8, 255])
+                }
+            }))
+        }
+    }
+    
+    /// The next frame published that's `wanted`, within a few seconds
+    pub(super) async fn next_frame_where(frames: &mut broadcast::Receiver<Frame>, wanted: impl Fn(&Frame) -> bool) -> Frame {
+        let wait = async {
+            loop {
+                let frame = frames.recv().await.unwrap();
+                if wanted(&frame) {
+                    return frame;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait).await.expect("no such frame")
+    }
+    
+    /// A viewer whose link can be made slow and fast again, and viewers
+    /// that can't be reached at all
+    #[derive(Default)]
+    struct SlowTransport {
+        slow: std::sync::atomic::AtomicBool,
+        unreachable: std::sync::Mutex<HashSet<String>>,
+    }
+    
+    #[async_trait]
+    impl SessionTransport for SlowTransport {
+        async fn announce(&self, _announcement: SessionAnnouncement) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn request_join(&self, _host_peer_id: &str, _request: JoinRequest) -> Result<JoinResponse, Error> {
+            Ok(JoinResponse::Denied)
+        }
+        
+        async fn send_control(&self, _host_peer_id: &str, _message: ControlMessage) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn grant_token(&self, _peer_id: &str, _grant: TokenGrant) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn end_session(&self, _peer_id: &str, _ended: SessionEnded) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn send_frame(&self, peer_id: &str, _frame: Frame) -> Result<(), Error> {
+            if self.unreachable.lock().unwrap().contains(peer_id) {
+                return Err(DeskShareError::PeerConnectionFailed(peer_id.to_string()).into());
+            }
+            if self.slow.load(Ordering::Relaxed) {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            Ok(())
+        }
+    }
+    
+    /// Stream settings once `done` holds of them, waiting at most `limit`
+    async fn wait_for_stream(
+        screen_share: &ScreenShare,
+        session_id: &str,
+        limit: Duration,
+        done: impl Fn(&StreamSettings) -> bool,
+    ) -> SessionStats {
+        let waited = async {
+            loop {
+                let stats = screen_share.session_stats(session_id).await.unwrap();
+                if done(&stats.stream.unwrap()) {
+                    return stats;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::time::timeout(limit, waited).await.unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_a_slow_viewer_lowers_quality_until_it_catches_up() {
+        let transport = Arc::new(SlowTransport::default());
+        transport.slow.store(true, Ordering::Relaxed);
+        let adaptive = AdaptiveConfig { window_ms: 100, ..AdaptiveConfig::default() };
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(SlideCapture::default()))
+            .with_transport("host".to_string(), transport.clone())
+            .with_capture_config(CaptureConfig { adaptive, ..CaptureConfig::default() });
+        let session_id = screen_share.start_sharing("host".to_string(), 30, (320, 240), None, Some(80), None).await.unwrap();
+        let full = StreamSettings { quality: 80, frame_rate: 30, scale_percent: 100 };
+        assert_eq!(screen_share.session_stats(&session_id).await.unwrap().stream, Some(full));
+        let viewer = ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) };
+        screen_share.grants.write().await.insert(session_id.clone(), HashMap::from([("10.0.0.2".to_string(), viewer)]));
+        
+        let stats = wait_for_stream(&screen_share, &session_id, Duration::from_secs(5), |s| s.quality < 80).await;
+        let first = &stats.adaptations[0];
+        assert!(first.reason.starts_with("10.0.0.2 behind"), "{}", first.reason);
+        // Quality goes first
+        assert_eq!(first.to, StreamSettings { quality: 65, ..full });
+        
+        transport.slow.store(false, Ordering::Relaxed);
+        let stats = wait_for_stream(&screen_share, &session_id, Duration::from_secs(15), |s| *s == full).await;
+        assert_eq!(stats.adaptations.last().unwrap().reason, "every viewer keeping up");
+        
+        // Turned off, nothing moves however slow the viewer
+        screen_share.set_adaptive(&session_id, AdaptiveConfig { enabled: false, ..adaptive }).await.unwrap();
+        transport.slow.store(true, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(screen_share.session_stats(&session_id).await.unwrap().stream, Some(full));
+        screen_share.stop_sharing(&session_id, "host").await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_stats_show_the_rate_achieved_and_who_misses_frames() {
+        let transport = Arc::new(SlowTransport::default());
+        transport.slow.store(true, Ordering::Relaxed);
+        transport.unreachable.lock().unwrap().insert("10.0.0.3".to_string());
+        let adaptive = AdaptiveConfig { enabled: false, window_ms: 100, ..AdaptiveConfig::default() };
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(SlideCapture::default()))
+            .with_transport("host".to_string(), transport.clone())
+            .with_capture_config(CaptureConfig { adaptive, ..CaptureConfig::default() });
+        let mut summaries = screen_share.subscribe_session_summaries();
+        let session_id = screen_share.start_sharing("host".to_string(), 30, (320, 240), None, None, None).await.unwrap();
+        let viewers = ["10.0.0.2", "10.0.0.3"]
+            .map(|peer| (peer.to_string(), ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) }));
+        screen_share.grants.write().await.insert(session_id.clone(), HashMap::from(viewers));
+        
+        // Every frame waits 300ms on the slow viewer, so 30fps is out of reach
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let stats = screen_share.session_stats(&session_id).await.unwrap();
+        assert!(stats.frames_per_second > 1.0 && stats.frames_per_second < 10.0, "{}", stats.frames_per_second);
+        assert!(stats.bytes_per_second > 0);
+        let [slow, gone] = &stats.viewers[..] else {
+            panic!("{:?}", stats.viewers);
+        };
+        assert_eq!((slow.peer_id.as_str(), slow.dropped_frames), ("10.0.0.2", 0));
+        assert!(slow.latency_ms.unwrap() >= 300);
+        assert_eq!((gone.peer_id.as_str(), gone.latency_ms), ("10.0.0.3", None));
+        assert!(gone.dropped_frames >= 2);
+        assert_eq!(stats.dropped_frames, gone.dropped_frames);
+        
+        screen_share.stop_sharing(&session_id, "host").await.unwrap();
+        let summary = summaries.try_recv().unwrap();
+        assert_eq!(summary.stats.session_id, session_id);
+        assert_eq!(summary.stats.dropped_frames, stats.dropped_frames);
+        assert!(summary.duration_ms >= 1500);
+        
+        // The next session starts from nothing
+        let session_id = screen_share.start_sharing("host".to_string(), 30, (320, 240), None, None, None).await.unwrap();
+        let stats = screen_share.session_stats(&session_id).await.unwrap();
+        assert_eq!((stats.dropped_frames, stats.viewers.len()), (0, 0));
+        assert!(stats.frames_per_second < 30.0);
+        screen_share.stop_sharing(&session_id, "host").await.unwrap();
+    }
+    
+    /// Records the header of everything that reached each viewer
+    #[derive(Default)]
+    pub(super) struct RecordingTransport {
+        pub(super) sent: std::sync::Mutex<Vec<(String, Arc<FrameHeader>)>>,
+    }
+    
+    #[async_trait]
+    impl SessionTransport for RecordingTransport {
+        async fn announce(&self, _announcement: SessionAnnouncement) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn request_join(&self, _host_peer_id: &str, _request: JoinRequest) -> Result<JoinResponse, Error> {
+            Ok(JoinResponse::Denied)
+        }
+        
+        async fn send_control(&self, _host_peer_id: &str, _message: ControlMessage) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn grant_token(&self, _peer_id: &str, _grant: TokenGrant) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn end_session(&self, _peer_id: &str, _ended: SessionEnded) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn send_frame(&self, peer_id: &str, frame: Frame) -> Result<(), Error> {
+            self.sent.lock().unwrap().push((peer_id.to_string(), frame.header));
+            Ok(())
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_only_the_host_stops_a_session() {
+        let transport = Arc::new(CountingTransport::default());
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), transport.clone());
+        let session_id = screen_share.start_sharing("host".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let viewer = ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) };
+        screen_share.grants.write().await.insert(session_id.clone(), HashMap::from([("10.0.0.2".to_string(), viewer)]));
+        let capture = screen_share.capture_handles.read().await.get(&session_id).unwrap().abort_handle();
+        
+        let err = screen_share.stop_sharing(&session_id, "10.0.0.2").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "not_session_host");
+        assert!(screen_share.get_session(&session_id).await.is_some());
+        assert!(!capture.is_finished());
+        
+        screen_share.stop_sharing(&session_id, "host").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(screen_share.sessions.read().await.is_empty());
+        assert!(capture.is_finished());
+        assert_eq!(screen_share.buffer_usage().await.sessions, 0);
+        assert_eq!(*transport.ended.lock().unwrap(), vec!["10.0.0.2".to_string()]);
+        // Already gone
+        screen_share.stop_sharing(&session_id, "host").await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_sessions_capture_and_stop_independently() {
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        let first = screen_share.start_sharing("local".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let second = screen_share.start_sharing("local".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let (first_capture, second_capture) = {
+            let handles = screen_share.capture_handles.read().await;
+            (handles[&first].abort_handle(), handles[&second].abort_handle())
+        };
+        
+        screen_share.stop_sharing(&first, "local").await.unwrap();
+        let captured = screen_share.capture_timings().runs;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(first_capture.is_finished());
+        // The second keeps capturing
+        assert!(!second_capture.is_finished());
+        assert!(screen_share.capture_timings().runs > captured);
+        
+        let third = screen_share.start_sharing("local".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let third_capture = screen_share.capture_handles.read().await[&third].abort_handle();
+        screen_share.stop_sharing(&second, "local").await.unwrap();
+        assert_eq!(screen_share.capture_handles.read().await.len(), 1);
+        
+        // Whatever is still running goes with the service
+        drop(screen_share);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(second_capture.is_finished() && third_capture.is_finished());
+    }
+    
+    #[tokio::test]
+    async fn test_shutdown_ends_every_session() {
+        let transport = Arc::new(CountingTransport::default());
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), transport.clone());
+        let first = screen_share.start_sharing("host".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let second = screen_share.start_sharing("host".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        let viewer = ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) };
+        screen_share.grants.write().await.insert(first.clone(), HashMap::from([("10.0.0.2".to_string(), viewer)]));
+        let captures: Vec<_> = {
+            let handles = screen_share.capture_handles.read().await;
+            [&first, &second].iter().map(|session_id| handles[*session_id].abort_handle()).collect()
+        };
+        
+        screen_share.shutdown().await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(screen_share.sessions.read().await.is_empty());
+        assert!(screen_share.capture_handles.read().await.is_empty());
+        assert!(captures.iter().all(|capture| capture.is_finished()));
+        assert_eq!(*transport.ended.lock().unwrap(), vec!["10.0.0.2".to_string()]);
+    }
+    
+    pub(super) fn frame(session_id: &str, len: usize) -> Frame {
+        Frame {
+            header: Arc::new(FrameHeader {
+                session_id: session_id.into(),
+                sequence: 0,
+                timestamp_ms: 0,
+                width: 64,
+                height: 48,
+                unchanged: false,
+                codec: FrameCodec::Jpeg,
+                keyframe: true,
+                media: Media::Video,
+            }),
+            data: Bytes::from(vec![0u8; len]),
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use tokio::sync::broadcast;
+use anyhow::Error;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+
+use super::ScreenShare;
+
+/// Longest display name a viewer may give itself, in characters
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+
+/// A viewer of a session we host
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Participant {
+    pub peer_id: String,
+    /// As the viewer gave it, or its peer id
+    pub display_name: String,
+    pub joined_at_ms: u64,
+    /// Slowest frame delivery to the viewer over the last adaptation
+    /// window; None until frames have gone out
+    pub latency_ms: Option<u64>,
+    /// Let drive the mouse and keyboard, when the session allows it
+    pub can_control: bool,
+}
+
+/// A viewer joined or left a session we host
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParticipantChange {
+    pub session_id: String,
+    pub peer_id: String,
+    pub joined: bool,
+    /// Viewers in the session after the change
+    pub participants: usize,
+}
+
+impl ScreenShare {
+    /// Who is watching a session we host, longest first
+    pub async fn get_participants(&self, session_id: &str) -> Result<Vec<Participant>, Error> {
+        let participants = self
+            .sessions
+            .read()
+            .await
+            .get(session_id)
+            .map(|session| session.participants.clone())
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+        let grants = self.grants.read().await;
+        let granted = grants.get(session_id);
+        let mut listed: Vec<Participant> = participants
+            .into_iter()
+            .map(|peer_id| {
+                let grant = granted.and_then(|viewers| viewers.get(&peer_id));
+                Participant {
+                    display_name: grant.and_then(|grant| grant.display_name.clone()).unwrap_or_else(|| peer_id.clone()),
+                    joined_at_ms: grant.map_or(0, |grant| grant.joined_at_ms),
+                    latency_ms: grant.and_then(|grant| grant.latency).map(|latency| latency.as_millis() as u64),
+                    can_control: grant.is_some_and(|grant| grant.control),
+                    peer_id,
+                }
+            })
+            .collect();
+        listed.sort_by(|a, b| a.joined_at_ms.cmp(&b.joined_at_ms).then(a.peer_id.cmp(&b.peer_id)));
+        Ok(listed)
+    }
+    
+    pub fn subscribe_participants(&self) -> broadcast::Receiver<ParticipantChange> {
+        self.participant_tx.subscribe()
+    }
+    
+    pub(super) fn notify_participants(&self, session_id: &str, peer_id: &str, joined: bool, participants: usize) {
+        // No listeners is fine
+        let _ = self.participant_tx.send(ParticipantChange {
+            session_id: session_id.to_string(),
+            peer_id: peer_id.to_string(),
+            joined,
+            participants,
+        });
+    }
+    
+    /// A viewer's name for itself, trimmed and stripped of control
+    /// characters; None when nothing is left
+    pub(super) fn clean_display_name(name: &str) -> Option<String> {
+        let cleaned: String = name.chars().filter(|c| !c.is_control()).take(MAX_DISPLAY_NAME_LEN).collect();
+        let cleaned = cleaned.trim();
+        (!cleaned.is_empty()).then(|| cleaned.to_string())
+    }
+    
+    /// Remove a viewer from a session we host; its token stops working at once,
+    /// frames stop going to it and everyone else gets a fresh token
+    pub async fn kick_participant(&self, session_id: &str, peer_id: &str) -> Result<(), Error> {
+        let removed = {
+            let mut sessions = self.sessions.write().await;
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+            session.participants.remove(peer_id).then_some(session.participants.len())
+        };
+        let Some(participants) = removed else {
+            return Err(DeskShareError::PeerNotFound(peer_id.to_string()).into());
+        };
+        
+        tracing::info!("Kicked {} from session {}", peer_id, session_id);
+        self.revoke_grant(session_id, peer_id).await;
+        self.notify_participants(session_id, peer_id, false, participants);
+        self.rotate_tokens(session_id).await;
+        self.announce_session(session_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::network::adaptive::AdaptiveConfig;
+    use crate::network::idle::CaptureConfig;
+    use crate::network::screen_share::tests::CountingTransport;
+    use crate::network::session_protocol::{ControlAction, ControlMessage, JoinRequest, JoinResponse};
+    use crate::platform::fallback::FallbackCapture;
+    
+    #[tokio::test]
+    async fn test_participants_show_who_joined_and_how_far_behind() {
+        let adaptive = AdaptiveConfig { window_ms: 100, ..AdaptiveConfig::default() };
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), Arc::new(CountingTransport::default()))
+            .with_capture_config(CaptureConfig { adaptive, ..CaptureConfig::default() });
+        let mut changes = host.subscribe_participants();
+        let session_id = host.start_sharing("host".to_string(), 30, (64, 48), None, None, Some(2)).await.unwrap();
+        let join = |peer_id: &str, display_name: Option<&str>| JoinRequest {
+            request_id: format!("join-{}", peer_id),
+            session_id: session_id.clone(),
+            peer_id: peer_id.to_string(),
+            password: None,
+            codecs: Vec::new(),
+            audio: false,
+            display_name: display_name.map(str::to_string),
+        };
+        let JoinResponse::Accepted { token, .. } = host.handle_join_request(join("10.0.0.2", Some("  Carol\u{7}'s tablet "))).await else {
+            panic!("not admitted");
+        };
+        let subscribe = ControlMessage {
+            session_id: session_id.clone(),
+            peer_id: "10.0.0.2".to_string(),
+            token,
+            action: ControlAction::Subscribe,
+        };
+        host.handle_control(subscribe).await.unwrap();
+        assert!(matches!(host.handle_join_request(join("10.0.0.3", Some("\n"))).await, JoinResponse::Accepted { .. }));
+        // Full, though a viewer already in may join again
+        assert!(matches!(host.handle_join_request(join("10.0.0.4", None)).await, JoinResponse::SessionFull));
+        assert!(matches!(host.handle_join_request(join("10.0.0.3", None)).await, JoinResponse::Accepted { .. }));
+        
+        let measured = async {
+            loop {
+                let participants = host.get_participants(&session_id).await.unwrap();
+                if participants[0].latency_ms.is_some() {
+                    return participants;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        let participants = tokio::time::timeout(Duration::from_secs(5), measured).await.unwrap();
+        assert_eq!(participants.len(), 2);
+        assert_eq!(participants[0].display_name, "Carol's tablet");
+        assert_eq!(participants[1].display_name, "10.0.0.3");
+        assert!(participants[0].joined_at_ms <= participants[1].joined_at_ms);
+        // Never subscribed, so never sent anything
+        assert_eq!(participants[1].latency_ms, None);
+        
+        host.kick_participant(&session_id, "10.0.0.2").await.unwrap();
+        host.leave_session(&session_id, "10.0.0.3".to_string()).await.unwrap();
+        let mut seen = Vec::new();
+        while let Ok(change) = changes.try_recv() {
+            seen.push((change.peer_id, change.joined, change.participants));
+        }
+        assert_eq!(seen, [
+            ("10.0.0.2".to_string(), true, 1),
+            ("10.0.0.3".to_string(), true, 2),
+            ("10.0.0.2".to_string(), false, 1),
+            ("10.0.0.3".to_string(), false, 0),
+        ]);
+        assert!(host.get_participants(&session_id).await.unwrap().is_empty());
+        host.stop_sharing(&session_id, "host").await.unwrap();
+        assert!(host.get_participants(&session_id).await.is_err());
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::platform::{find_monitor, CaptureRegion};
+use crate::network::adaptive::{AdaptiveConfig, StreamSettings};
+use crate::network::quality_preset::{QualityPreset, StreamOverrides};
+
+use super::ScreenShare;
+
+impl ScreenShare {
+    /// Move a running session to another monitor, captured at that
+    /// monitor's size; takes effect on the next frame. Any region is
+    /// dropped, since it was drawn on the old monitor.
+    pub async fn switch_monitor(&self, session_id: &str, monitor_id: u32) -> Result<(), Error> {
+        let monitor = self.ensure_monitor(monitor_id)?;
+        
+        {
+            let mut sessions = self.sessions.write().await;
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+            session.monitor_id = Some(monitor_id);
+            session.resolution = (monitor.width, monitor.height);
+            session.region = None;
+            session.last_keyframe = None;
+        }
+        
+        self.announce_session(session_id).await
+    }
+    
+    /// Share only `region` of the session's monitor, or all of it again
+    /// when None; takes effect on the next frame. Odd sizes are trimmed to
+    /// even ones.
+    pub async fn set_capture_region(&self, session_id: &str, region: Option<CaptureRegion>) -> Result<(), Error> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+        session.region = match region {
+            Some(region) => Some(region.fit(find_monitor(&self.capture.list_monitors()?, session.monitor_id)?)?),
+            None => None,
+        };
+        session.last_keyframe = None;
+        
+        Ok(())
+    }
+    
+    /// Capture at `frame_rate` and `resolution` from the next frame on.
+    /// Every frame header carries its size, so viewers follow along.
+    pub async fn update_session_settings(
+        &self,
+        session_id: &str,
+        frame_rate: u32,
+        resolution: (u32, u32),
+    ) -> Result<(), Error> {
+        if frame_rate == 0 || resolution.0 == 0 || resolution.1 == 0 {
+            return Err(DeskShareError::InvalidConfig("frame rate and resolution must be above zero".to_string()).into());
+        }
+        {
+            let mut sessions = self.sessions.write().await;
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+            session.frame_rate = frame_rate;
+            session.overrides.frame_rate = Some(frame_rate);
+            session.resolution = resolution;
+        }
+        
+        self.announce_session(session_id).await
+    }
+    
+    /// Capture at `preset`, with any of `overrides` in place of its
+    /// settings, from the next frame on
+    pub async fn set_quality_preset(
+        &self,
+        session_id: &str,
+        preset: QualityPreset,
+        overrides: StreamOverrides,
+    ) -> Result<(), Error> {
+        let settings = Self::preset_settings(preset, overrides)?;
+        {
+            let mut sessions = self.sessions.write().await;
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+            session.preset = preset;
+            session.overrides = overrides;
+            session.quality = settings.quality;
+            session.frame_rate = settings.frame_rate;
+            session.scale_percent = settings.scale_percent;
+        }
+        
+        self.announce_session(session_id).await
+    }
+    
+    /// What `preset` with `overrides` captures at, or why it can't
+    pub(super) fn preset_settings(preset: QualityPreset, overrides: StreamOverrides) -> Result<StreamSettings, Error> {
+        let settings = preset.with_overrides(overrides);
+        if settings.frame_rate == 0 || !(1..=100).contains(&settings.scale_percent) {
+            return Err(DeskShareError::InvalidConfig(
+                "frame rate must be above zero and scale between 1 and 100 percent".to_string(),
+            )
+            .into());
+        }
+        Ok(StreamSettings { quality: settings.quality.clamp(1, 100), ..settings })
+    }
+    
+    /// Draw the cursor into the session's frames or leave it out
+    pub async fn set_show_cursor(&self, session_id: &str, show: bool) -> Result<(), Error> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+        session.show_cursor = show;
+        
+        Ok(())
+    }
+    
+    /// Stop capturing without ending the session. Pausing rotates every
+    /// viewer's token, so nothing issued before the pause works after it.
+    pub async fn set_paused(&self, session_id: &str, paused: bool) -> Result<(), Error> {
+        {
+            let mut sessions = self.sessions.write().await;
+            let session = sessions
+                .get_mut(session_id)
+                .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+            session.paused = paused;
+            if paused {
+                session.last_keyframe = None;
+            }
+        }
+        
+        if paused {
+            self.rotate_tokens(session_id).await;
+        }
+        Ok(())
+    }
+    
+    /// How far the session's stream may fall when viewers can't keep up
+    pub async fn set_adaptive(&self, session_id: &str, config: AdaptiveConfig) -> Result<(), Error> {
+        let mut sessions = self.sessions.write().await;
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+        session.adaptive = config;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::sync::broadcast;
+    use crate::network::screen_share::{Frame, ViewerGrant};
+    use crate::network::screen_share::tests::{next_frame_where, RecordingTransport};
+    use crate::network::session_protocol::SessionToken;
+    use crate::platform::fallback::FallbackCapture;
+    
+    async fn next_size(frames: &mut broadcast::Receiver<Frame>, size: (u32, u32)) -> Frame {
+        next_frame_where(frames, |frame| (frame.header.width, frame.header.height) == size).await
+    }
+    
+    #[tokio::test]
+    async fn test_a_region_is_shared_at_its_own_even_size_until_cleared() {
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        let session_id = screen_share.start_sharing("local".to_string(), 30, (640, 480), Some(2), None, None).await.unwrap();
+        let mut frames = screen_share.subscribe_frames(&session_id).await.unwrap();
+        next_size(&mut frames, (640, 480)).await;
+        
+        let region = CaptureRegion { x: 100, y: 100, width: 301, height: 201 };
+        screen_share.set_capture_region(&session_id, Some(region)).await.unwrap();
+        let even = CaptureRegion { width: 300, height: 200, ..region };
+        assert_eq!(screen_share.get_session(&session_id).await.unwrap().region, Some(even));
+        let frame = next_size(&mut frames, (300, 200)).await;
+        let decoded = image::load_from_memory(&frame.data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (300, 200));
+        
+        // Bigger than the stream is shrunk to fit, keeping its shape
+        let wide = CaptureRegion { x: 0, y: 0, width: 1280, height: 480 };
+        screen_share.set_capture_region(&session_id, Some(wide)).await.unwrap();
+        next_size(&mut frames, (640, 240)).await;
+        
+        // Monitor 2 is 1280x1024
+        for outside in [
+            CaptureRegion { x: 1200, y: 0, width: 200, height: 200 },
+            CaptureRegion { x: 0, y: 1000, width: 100, height: 100 },
+            CaptureRegion { x: u32::MAX, y: 0, width: 2, height: 2 },
+            CaptureRegion { x: 0, y: 0, width: 1, height: 100 },
+        ] {
+            let refused = screen_share.set_capture_region(&session_id, Some(outside)).await.unwrap_err();
+            assert!(matches!(refused.downcast_ref(), Some(DeskShareError::InvalidCaptureRegion(_))), "{}", refused);
+        }
+        assert_eq!(screen_share.get_session(&session_id).await.unwrap().region, Some(wide));
+        
+        screen_share.set_capture_region(&session_id, None).await.unwrap();
+        next_size(&mut frames, (640, 480)).await;
+        
+        screen_share.set_capture_region(&session_id, Some(region)).await.unwrap();
+        screen_share.switch_monitor(&session_id, 1).await.unwrap();
+        assert_eq!(screen_share.get_session(&session_id).await.unwrap().region, None);
+        screen_share.stop_sharing(&session_id, "local").await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_switching_monitors_captures_at_the_new_size() {
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        // Monitor 2 is 1280x1024, the primary 1920x1080
+        let session_id = screen_share.start_sharing("local".to_string(), 5, (1280, 1024), Some(2), None, None).await.unwrap();
+        let mut frames = screen_share.subscribe_frames(&session_id).await.unwrap();
+        next_size(&mut frames, (1280, 1024)).await;
+        
+        screen_share.switch_monitor(&session_id, 1).await.unwrap();
+        let session = screen_share.get_session(&session_id).await.unwrap();
+        assert_eq!((session.monitor_id, session.resolution), (Some(1), (1920, 1080)));
+        next_size(&mut frames, (1920, 1080)).await;
+        screen_share.stop_sharing(&session_id, "local").await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_a_preset_sets_the_stream_and_overrides_win() {
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        let overrides = StreamOverrides { frame_rate: Some(30), ..StreamOverrides::default() };
+        let session_id = screen_share
+            .start_sharing_preset("local".to_string(), QualityPreset::Low, overrides, (640, 480), None, None)
+            .await
+            .unwrap();
+        let session = screen_share.get_session(&session_id).await.unwrap();
+        assert_eq!((session.quality, session.frame_rate, session.scale_percent), (50, 30, 50));
+        // Low captures at half the monitor's size
+        let mut frames = screen_share.subscribe_frames(&session_id).await.unwrap();
+        next_size(&mut frames, (320, 240)).await;
+        let stats = screen_share.session_stats(&session_id).await.unwrap();
+        assert_eq!(stats.preset, Some(QualityPreset::Low));
+        assert_eq!(stats.stream, Some(StreamSettings { quality: 50, frame_rate: 30, scale_percent: 50 }));
+        
+        // Changed live, the new preset's overrides replace the old ones
+        let overrides = StreamOverrides { quality: Some(70), ..StreamOverrides::default() };
+        screen_share.set_quality_preset(&session_id, QualityPreset::High, overrides).await.unwrap();
+        next_size(&mut frames, (640, 480)).await;
+        let stats = screen_share.session_stats(&session_id).await.unwrap();
+        assert_eq!(stats.preset, Some(QualityPreset::High));
+        assert_eq!(stats.stream, Some(StreamSettings { quality: 70, frame_rate: 30, scale_percent: 100 }));
+        
+        // An explicit frame rate is an override like any other
+        screen_share.update_session_settings(&session_id, 12, (640, 480)).await.unwrap();
+        let session = screen_share.get_session(&session_id).await.unwrap();
+        assert_eq!(session.overrides, StreamOverrides { quality: Some(70), frame_rate: Some(12), scale_percent: None });
+        
+        for nonsense in [
+            StreamOverrides { frame_rate: Some(0), ..StreamOverrides::default() },
+            StreamOverrides { scale_percent: Some(0), ..StreamOverrides::default() },
+            StreamOverrides { scale_percent: Some(150), ..StreamOverrides::default() },
+        ] {
+            let refused = screen_share.set_quality_preset(&session_id, QualityPreset::Low, nonsense).await.unwrap_err();
+            assert!(matches!(refused.downcast_ref(), Some(DeskShareError::InvalidConfig(_))), "{:?}", nonsense);
+        }
+        assert_eq!(screen_share.get_session(&session_id).await.unwrap().preset, QualityPreset::High);
+        screen_share.stop_sharing(&session_id, "local").await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_new_settings_take_effect_within_a_couple_of_frames() {
+        let transport = Arc::new(RecordingTransport::default());
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture))
+            .with_transport("host".to_string(), transport.clone());
+        let session_id = host.start_sharing("host".to_string(), 30, (1920, 1080), None, None, None).await.unwrap();
+        let viewer = ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) };
+        host.grants.write().await.insert(session_id.clone(), HashMap::from([("10.0.0.2".to_string(), viewer)]));
+        let mut frames = host.subscribe_frames(&session_id).await.unwrap();
+        next_size(&mut frames, (1920, 1080)).await;
+        
+        let switched = transport.sent.lock().unwrap().len();
+        host.update_session_settings(&session_id, 15, (1280, 720)).await.unwrap();
+        let frame = next_size(&mut frames, (1280, 720)).await;
+        let decoded = image::load_from_memory(&frame.data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (1280, 720));
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        let stream = host.session_stats(&session_id).await.unwrap().stream.unwrap();
+        assert_eq!((stream.frame_rate, stream.scale_percent), (15, 100));
+        assert!(host.update_session_settings(&session_id, 0, (1280, 720)).await.is_err());
+        host.stop_sharing(&session_id, "host").await.unwrap();
+        
+        // Heartbeats included, everything after the one or two frames
+        // already under way is the new size, at the new rate
+        let sent = transport.sent.lock().unwrap()[switched..].to_vec();
+        let first_new = sent.iter().position(|(_, header)| (header.width, header.height) == (1280, 720)).unwrap();
+        assert!(first_new <= 2, "{} frames at the old size", first_new);
+        let after: Vec<u64> = sent[first_new..]
+            .iter()
+            .inspect(|(_, header)| assert_eq!((header.width, header.height), (1280, 720)))
+            .map(|(_, header)| header.timestamp_ms)
+            .collect();
+        assert!(after.len() >= 4, "{} frames", after.len());
+        assert!(after.windows(2).all(|w| w[1] - w[0] >= 60), "{:?}", after);
+    }
+}
@@ -0,0 +1,562 @@
+use std::sync::Arc;
+use anyhow::Error;
+use bytes::Bytes;
+
+use crate::error::DeskShareError;
+use crate::platform::audio::{AudioOutput, AudioPacket};
+use crate::platform::{encode_jpeg, DEFAULT_JPEG_QUALITY};
+use crate::network::session_protocol::{ControlAction, JoinRequest, JoinResponse, SessionEnded, SessionToken};
+use crate::network::video::{FrameCodec, VideoDecoder};
+
+use super::{Frame, FrameChannel, FrameHeader, Media, RemoteSession, ScreenShare};
+
+/// A remote session we joined, with the token its host gave us
+pub(super) struct ViewingSession {
+    pub(super) remote: RemoteSession,
+    pub(super) token: SessionToken,
+    /// Picture so far, for hosts sending tiled or H.264 video
+    pub(super) decoder: Arc<std::sync::Mutex<VideoDecoder>>,
+    /// Where the session's audio plays; None leaves it unheard
+    pub(super) audio: Option<Arc<dyn AudioOutput>>,
+}
+
+impl ScreenShare {
+    /// Viewer side of a join; frames from the host become available through
+    /// `subscribe_frames` once this returns
+    pub async fn join_remote_session(&self, session_id: &str, password: Option<String>) -> Result<RemoteSession, Error> {
+        let mut remote = self
+            .remote_sessions
+            .read()
+            .await
+            .get(session_id)
+            .cloned()
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+        let transport = self
+            .transport
+            .clone()
+            .ok_or_else(|| DeskShareError::PeerConnectionFailed("no session transport".to_string()))?;
+        
+        let audio = self.open_audio_output().await;
+        let request = JoinRequest {
+            request_id: Self::generate_session_id(),
+            session_id: session_id.to_string(),
+            peer_id: self.local_peer_id.clone(),
+            password,
+            codecs: FrameCodec::decodable(),
+            audio: audio.is_some(),
+            display_name: self.display_name.clone(),
+        };
+        let response = transport
+            .request_join(&remote.host_peer_id, request)
+            .await
+            .map_err(|e| DeskShareError::PeerConnectionFailed(e.to_string()))?;
+        
+        match response {
+            JoinResponse::Accepted { resolution, token, codec } => {
+                tracing::debug!("Joined {} at {:?}, frames as {:?}", session_id, resolution, codec);
+                remote.resolution = resolution;
+                self.frame_channels.write().await.insert(session_id.to_string(), FrameChannel::new(session_id));
+                self.viewing.write().await.insert(session_id.to_string(), ViewingSession {
+                    remote: remote.clone(),
+                    token: token.clone(),
+                    decoder: Default::default(),
+                    audio,
+                });
+                
+                if let Err(e) = self.send_control(&remote.host_peer_id, session_id, token, ControlAction::Subscribe).await {
+                    self.viewing.write().await.remove(session_id);
+                    self.frame_channels.write().await.remove(session_id);
+                    return Err(DeskShareError::PeerConnectionFailed(e.to_string()).into());
+                }
+                Ok(remote)
+            }
+            JoinResponse::WrongPassword => Err(DeskShareError::InvalidSessionPassword.into()),
+            JoinResponse::Denied => Err(DeskShareError::JoinDenied(session_id.to_string()).into()),
+            JoinResponse::SessionFull => Err(DeskShareError::SessionFull(session_id.to_string()).into()),
+            JoinResponse::SessionNotFound => {
+                self.remote_sessions.write().await.remove(session_id);
+                Err(DeskShareError::SessionNotFound(session_id.to_string()).into())
+            }
+        }
+    }
+    
+    pub async fn leave_remote_session(&self, session_id: &str) -> Result<(), Error> {
+        let viewing = self
+            .viewing
+            .write()
+            .await
+            .remove(session_id)
+            .ok_or_else(|| DeskShareError::SessionNotFound(session_id.to_string()))?;
+        
+        self.frame_channels.write().await.remove(session_id);
+        self.frame_buffer.write().await.remove_session(session_id);
+        
+        let host_peer_id = viewing.remote.host_peer_id;
+        if let Err(e) = self.send_control(&host_peer_id, session_id, viewing.token, ControlAction::Leave).await {
+            tracing::debug!("Host of {} not told about leave: {}", session_id, e);
+        }
+        
+        Ok(())
+    }
+    
+    /// Accept a frame from the host of a session we're viewing
+    pub async fn receive_frame(&self, frame: Frame) {
+        // Audio is played as it comes rather than buffered like the picture
+        if frame.header.media == Media::Audio {
+            let output = self.viewing.read().await.get(frame.header.session_id.as_str()).and_then(|session| session.audio.clone());
+            if let Some(output) = output {
+                let packet = AudioPacket { data: frame.data, timestamp_ms: frame.header.timestamp_ms };
+                if let Err(e) = output.play(&packet) {
+                    tracing::debug!("Audio not played: {}", e);
+                }
+            }
+            return;
+        }
+        // A heartbeat only says the latest frame still stands
+        if frame.header.unchanged {
+            return;
+        }
+        let frame = match frame.header.codec {
+            FrameCodec::Jpeg => frame,
+            FrameCodec::Tiled | FrameCodec::H264 => {
+                let decoder = self.viewing.read().await.get(frame.header.session_id.as_str()).map(|session| session.decoder.clone());
+                let Some(decoder) = decoder else {
+                    return;
+                };
+                match tokio::task::spawn_blocking(move || Self::decode_video(&decoder, frame)).await {
+                    Ok(Ok(frame)) => frame,
+                    Ok(Err(e)) => {
+                        tracing::debug!("Frame not shown: {}", e);
+                        return;
+                    }
+                    Err(e) => {
+                        tracing::error!("Frame decoding task failed: {}", e);
+                        return;
+                    }
+                }
+            }
+        };
+        let session_id = frame.header.session_id.clone();
+        // Held while buffering, so a concurrent leave can't be followed by a
+        // frame for the session it just cleared
+        let viewing = self.viewing.read().await;
+        if !viewing.contains_key(session_id.as_str()) {
+            return;
+        }
+        
+        self.frame_buffer.write().await.insert(frame.clone());
+        drop(viewing);
+        if let Some(channel) = self.frame_channels.read().await.get(session_id.as_str()) {
+            let _ = channel.sender.send(frame);
+        }
+    }
+    
+    /// Rebuild a tiled or H.264 frame and turn it into a JPEG like any
+    /// other, so the buffer and subscribers only ever hold frames ready to show
+    fn decode_video(decoder: &std::sync::Mutex<VideoDecoder>, frame: Frame) -> Result<Frame, Error> {
+        let mut decoder = decoder.lock().unwrap();
+        // Whatever came before a keyframe has no bearing on what follows it
+        if frame.header.keyframe {
+            *decoder = VideoDecoder::new();
+        }
+        let image = decoder.decode(frame.header.codec, &frame.data)?;
+        drop(decoder);
+        let jpeg = encode_jpeg(&image, DEFAULT_JPEG_QUALITY)?;
+        Ok(Frame {
+            header: Arc::new(FrameHeader {
+                codec: FrameCodec::Jpeg,
+                keyframe: true,
+                ..(*frame.header).clone()
+            }),
+            data: Bytes::from(jpeg),
+        })
+    }
+    
+    /// Viewer side of a host ending its session: stop watching it
+    pub async fn handle_session_ended(&self, ended: SessionEnded) {
+        {
+            let mut remote_sessions = self.remote_sessions.write().await;
+            if remote_sessions.get(&ended.session_id).is_some_and(|remote| remote.host_peer_id == ended.host_peer_id) {
+                remote_sessions.remove(&ended.session_id);
+            }
+        }
+        {
+            let mut viewing = self.viewing.write().await;
+            if viewing.get(&ended.session_id).is_none_or(|session| session.remote.host_peer_id != ended.host_peer_id) {
+                return;
+            }
+            viewing.remove(&ended.session_id);
+        }
+        self.frame_channels.write().await.remove(&ended.session_id);
+        self.frame_buffer.write().await.remove_session(&ended.session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+    use async_trait::async_trait;
+    use crate::network::adaptive::AdaptiveConfig;
+    use crate::network::idle::CaptureConfig;
+    use crate::network::lan_frames::LanFrameLinks;
+    use crate::network::screen_share::ViewerGrant;
+    use crate::network::screen_share::tests::frame;
+    use crate::network::session_protocol::{AccessMode, ControlMessage, SessionAnnouncement, SessionTransport, TokenGrant};
+    use crate::network::video::TileEncoder;
+    use crate::platform::{CaptureBackend, MonitorInfo};
+    use crate::platform::fallback::FallbackCapture;
+    
+    /// A white bar 4px further right on black each capture, so all but the
+    /// first frame of a tiled stream are deltas
+    #[derive(Default)]
+    struct MovingBar {
+        captures: AtomicU64,
+    }
+    
+    impl MovingBar {
+        const WIDTH: u32 = 128;
+        
+        /// Where the bar starts in the `capture`th capture, counting from 0
+        fn position(capture: u64) -> u32 {
+            (capture * 4 % Self::WIDTH as u64) as u32
+        }
+    }
+    
+    #[async_trait]
+    impl CaptureBackend for MovingBar {
+        fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error> {
+            crate::platform::fallback::list_monitors()
+        }
+        
+        async fn capture_raw(&self, _monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<image::RgbaImage, Error> {
+            let at = Self::position(self.captures.fetch_add(1, Ordering::Relaxed));
+            Ok(image::RgbaImage::from_fn(resolution.0, resolution.1, |x, _| {
+                if (at..at + 8).contains(&x) { image::Rgba([255; 4]) } else { image::Rgba([0, 0, 0, 255]) }
+            }))
+        }
+    }
+    
+    /// Where the bar starts in a shown frame, give or take JPEG's blur
+    fn bar_at(frame: &Frame) -> Option<u32> {
+        let image = image::load_from_memory(&frame.data).unwrap().to_luma8();
+        (0..image.width()).find(|&x| (0..image.height()).all(|y| image.get_pixel(x, y)[0] > 160))
+    }
+    
+    /// Notes the header of every frame sent, and hands one viewer's to it
+    struct ViewerTransport {
+        peer_id: &'static str,
+        viewer: ScreenShare,
+        sent: std::sync::Mutex<Vec<(String, Arc<FrameHeader>)>>,
+    }
+    
+    #[async_trait]
+    impl SessionTransport for ViewerTransport {
+        async fn announce(&self, _announcement: SessionAnnouncement) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn request_join(&self, _host_peer_id: &str, _request: JoinRequest) -> Result<JoinResponse, Error> {
+            Ok(JoinResponse::Denied)
+        }
+        
+        async fn send_control(&self, _host_peer_id: &str, _message: ControlMessage) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn grant_token(&self, _peer_id: &str, _grant: TokenGrant) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn end_session(&self, _peer_id: &str, _ended: SessionEnded) -> Result<(), Error> {
+            Ok(())
+        }
+        
+        async fn send_frame(&self, peer_id: &str, frame: Frame) -> Result<(), Error> {
+            self.sent.lock().unwrap().push((peer_id.to_string(), frame.header.clone()));
+            if peer_id == self.peer_id {
+                self.viewer.receive_frame(frame).await;
+            }
+            Ok(())
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_a_late_joiner_shows_the_screen_from_its_first_frame() {
+        let viewer = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        let transport = Arc::new(ViewerTransport { peer_id: "10.0.0.3", viewer, sent: Default::default() });
+        // No scheduled keyframe in the life of the test, and no adapting
+        let config = CaptureConfig {
+            keyframe_interval_ms: 60_000,
+            codec: FrameCodec::Tiled,
+            adaptive: AdaptiveConfig { enabled: false, ..AdaptiveConfig::default() },
+            ..CaptureConfig::default()
+        };
+        let host = ScreenShare::with_capture_backend(Arc::new(MovingBar::default()))
+            .with_transport("host".to_string(), transport.clone())
+            .with_capture_config(config);
+        let session_id = host.start_sharing("host".to_string(), 200, (MovingBar::WIDTH, 96), None, None, None).await.unwrap();
+        let join = |peer_id: &str| JoinRequest {
+            request_id: format!("join-{}", peer_id),
+            session_id: session_id.clone(),
+            peer_id: peer_id.to_string(),
+            password: None,
+            codecs: FrameCodec::decodable(),
+            audio: false,
+            display_name: None,
+        };
+        let subscribe = |peer_id: &str, token: SessionToken| ControlMessage {
+            session_id: session_id.clone(),
+            peer_id: peer_id.to_string(),
+            token,
+            action: ControlAction::Subscribe,
+        };
+        
+        // An early viewer keeps the stream tiled while 100 frames go by
+        let JoinResponse::Accepted { token, .. } = host.handle_join_request(join("10.0.0.2")).await else {
+            panic!("not admitted");
+        };
+        host.handle_control(subscribe("10.0.0.2", token)).await.unwrap();
+        let mut hosted = host.subscribe_frames(&session_id).await.unwrap();
+        while hosted.recv().await.unwrap().header.sequence < 100 {}
+        let deltas = transport.sent.lock().unwrap().iter().filter(|(_, header)| !header.keyframe).count();
+        assert!(deltas > 90, "{}", deltas);
+        
+        transport.viewer.viewing.write().await.insert(session_id.clone(), ViewingSession {
+            remote: RemoteSession {
+                session_id: session_id.clone(),
+                host_peer_id: "host".to_string(),
+                resolution: (MovingBar::WIDTH, 96),
+                access_mode: AccessMode::Open,
+                participant_count: 1,
+                last_seen: 0,
+                host_name: None,
+                started_at_ms: 0,
+                has_password: false,
+                preset: None,
+            },
+            token: SessionToken::generate(),
+            decoder: Default::default(),
+            audio: None,
+        });
+        transport.viewer.frame_channels.write().await.insert(session_id.clone(), FrameChannel::new(&session_id));
+        let mut shown = transport.viewer.subscribe_frames(&session_id).await.unwrap();
+        let JoinResponse::Accepted { token, .. } = host.handle_join_request(join("10.0.0.3")).await else {
+            panic!("not admitted");
+        };
+        host.handle_control(subscribe("10.0.0.3", token)).await.unwrap();
+        
+        // The host's last keyframe comes with the subscription, then deltas
+        // from a fresh one; every frame shows as the host captured it
+        let first = transport.sent.lock().unwrap().iter().find(|(peer_id, _)| peer_id == "10.0.0.3").unwrap().1.clone();
+        assert!(first.keyframe && first.codec == FrameCodec::Tiled, "{:?}", first);
+        let mut sequences = Vec::new();
+        while sequences.len() < 30 {
+            let frame = tokio::time::timeout(Duration::from_secs(5), shown.recv()).await.unwrap().unwrap();
+            let at = bar_at(&frame).unwrap();
+            // Frames are numbered from 1
+            assert!(at.abs_diff(MovingBar::position(frame.header.sequence - 1)) <= 1, "{} at {}", frame.header.sequence, at);
+            sequences.push(frame.header.sequence);
+        }
+        assert_eq!(sequences[0], first.sequence);
+        assert!(sequences.windows(2).all(|pair| pair[0] < pair[1]));
+        let late: Vec<_> = transport.sent.lock().unwrap().iter().filter(|(peer_id, _)| peer_id == "10.0.0.3").map(|(_, header)| header.keyframe).collect();
+        assert!(late[2..].contains(&false));
+        host.stop_sharing(&session_id, "host").await.unwrap();
+    }
+    
+    #[cfg(feature = "h264")]
+    #[tokio::test]
+    async fn test_h264_sessions_show_as_captured() {
+        let viewer = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        let transport = Arc::new(ViewerTransport { peer_id: "10.0.0.2", viewer, sent: Default::default() });
+        let config = CaptureConfig {
+            keyframe_interval_ms: 60_000,
+            adaptive: AdaptiveConfig { enabled: false, ..AdaptiveConfig::default() },
+            ..CaptureConfig::default()
+        };
+        let host = ScreenShare::with_capture_backend(Arc::new(MovingBar::default()))
+            .with_transport("host".to_string(), transport.clone())
+            .with_capture_config(config);
+        let session_id = host.start_sharing("host".to_string(), 60, (MovingBar::WIDTH, 96), None, None, None).await.unwrap();
+        transport.viewer.viewing.write().await.insert(session_id.clone(), ViewingSession {
+            remote: RemoteSession {
+                session_id: session_id.clone(),
+                host_peer_id: "host".to_string(),
+                resolution: (MovingBar::WIDTH, 96),
+                access_mode: AccessMode::Open,
+                participant_count: 1,
+                last_seen: 0,
+                host_name: None,
+                started_at_ms: 0,
+                has_password: false,
+                preset: None,
+            },
+            token: SessionToken::generate(),
+            decoder: Default::default(),
+            audio: None,
+        });
+        transport.viewer.frame_channels.write().await.insert(session_id.clone(), FrameChannel::new(&session_id));
+        let mut shown = transport.viewer.subscribe_frames(&session_id).await.unwrap();
+        
+        let request = JoinRequest {
+            request_id: "join-10.0.0.2".to_string(),
+            session_id: session_id.clone(),
+            peer_id: "10.0.0.2".to_string(),
+            password: None,
+            codecs: FrameCodec::decodable(),
+            audio: false,
+            display_name: None,
+        };
+        let JoinResponse::Accepted { token, codec, .. } = host.handle_join_request(request).await else {
+            panic!("not admitted");
+        };
+        assert_eq!(codec, FrameCodec::H264);
+        host.handle_control(ControlMessage {
+            session_id: session_id.clone(),
+            peer_id: "10.0.0.2".to_string(),
+            token,
+            action: ControlAction::Subscribe,
+        }).await.unwrap();
+        
+        // Decoded back into frames showing what the host captured
+        let mut sequences = Vec::new();
+        while sequences.len() < 20 {
+            let frame = tokio::time::timeout(Duration::from_secs(5), shown.recv()).await.unwrap().unwrap();
+            let at = bar_at(&frame).unwrap();
+            assert!(at.abs_diff(MovingBar::position(frame.header.sequence - 1)) <= 1, "{} at {}", frame.header.sequence, at);
+            sequences.push(frame.header.sequence);
+        }
+        let sent = transport.sent.lock().unwrap().clone();
+        assert!(sent.iter().all(|(_, header)| header.codec == FrameCodec::H264), "{:?}", sent);
+        assert!(sent.iter().any(|(_, header)| !header.keyframe));
+        host.stop_sharing(&session_id, "host").await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_viewers_receive_frames_over_the_network() {
+        use crate::p2p::TcpTransport;
+        use crate::security::{DeviceIdentity, SecurityConfig};
+        const FRAMES: usize = 24;
+        
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accept = tokio::spawn(async move {
+            let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+            transport.accept(&listener).await.unwrap()
+        });
+        let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+        let mut stream = transport.connect(addr).await.unwrap();
+        let (host_end, viewer_addr) = accept.await.unwrap();
+        let viewer_id = viewer_addr.ip().to_string();
+        
+        let links = Arc::new(LanFrameLinks::new());
+        links.attach(&viewer_id, host_end);
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture)).with_frame_links(links.clone());
+        let session_id = host.start_sharing("local".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        // Only the frames sent below, not the capture loop's
+        host.set_paused(&session_id, true).await.unwrap();
+        let grant = ViewerGrant { subscribed: true, ..ViewerGrant::new(SessionToken::generate()) };
+        host.grants.write().await.insert(session_id.clone(), HashMap::from([(viewer_id, grant)]));
+        
+        let viewer = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        viewer.viewing.write().await.insert(session_id.clone(), ViewingSession {
+            remote: RemoteSession {
+                session_id: session_id.clone(),
+                host_peer_id: "127.0.0.1".to_string(),
+                resolution: (64, 48),
+                access_mode: AccessMode::Open,
+                participant_count: 1,
+                last_seen: 0,
+                host_name: None,
+                started_at_ms: 0,
+                has_password: false,
+                preset: None,
+            },
+            token: SessionToken::generate(),
+            decoder: Default::default(),
+            audio: None,
+        });
+        
+        let mut sequences = HashSet::new();
+        for n in 0..FRAMES {
+            host.broadcast_to_session(&session_id, Bytes::from(vec![n as u8; 4096])).await.unwrap();
+            let frame = Frame::recv(&mut stream).await.unwrap();
+            sequences.insert(frame.header.sequence);
+            viewer.receive_frame(frame).await;
+            assert_eq!(viewer.get_frame(&session_id).await.unwrap(), vec![n as u8; 4096]);
+        }
+        assert_eq!(sequences.len(), FRAMES);
+        assert_eq!(links.dropped_frames(&viewer_addr.ip().to_string()), 0);
+    }
+    
+    #[tokio::test]
+    async fn test_video_falls_back_to_jpeg_for_viewers_without_it() {
+        let host = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        let session_id = host.start_sharing("local".to_string(), 30, (64, 48), None, None, None).await.unwrap();
+        host.set_paused(&session_id, true).await.unwrap();
+        let join = |peer_id: &str, codecs: Vec<FrameCodec>| JoinRequest {
+            request_id: format!("join-{}", peer_id),
+            session_id: session_id.clone(),
+            peer_id: peer_id.to_string(),
+            password: None,
+            codecs,
+            audio: false,
+            display_name: None,
+        };
+        let JoinResponse::Accepted { codec, .. } = host.handle_join_request(join("10.0.0.2", FrameCodec::decodable())).await else {
+            panic!("not admitted");
+        };
+        assert_eq!(codec, CaptureConfig::default().codec);
+        // A viewer without H.264 steps the session down to tiles, and an
+        // older one turns it over to JPEG, for everyone
+        let JoinResponse::Accepted { codec, .. } = host.handle_join_request(join("10.0.0.4", vec![FrameCodec::Jpeg, FrameCodec::Tiled])).await else {
+            panic!("not admitted");
+        };
+        assert_eq!(codec, FrameCodec::Tiled);
+        let JoinResponse::Accepted { codec, .. } = host.handle_join_request(join("10.0.0.3", Vec::new())).await else {
+            panic!("not admitted");
+        };
+        assert_eq!(codec, FrameCodec::Jpeg);
+        assert_eq!(host.get_session(&session_id).await.unwrap().codec, FrameCodec::Jpeg);
+        
+        // Viewers buffer decoded video as JPEG, and skip what follows a gap
+        let viewer = ScreenShare::with_capture_backend(Arc::new(FallbackCapture));
+        viewer.viewing.write().await.insert(session_id.clone(), ViewingSession {
+            remote: RemoteSession {
+                session_id: session_id.clone(),
+                host_peer_id: "127.0.0.1".to_string(),
+                resolution: (64, 48),
+                access_mode: AccessMode::Open,
+                participant_count: 1,
+                last_seen: 0,
+                host_name: None,
+                started_at_ms: 0,
+                has_password: false,
+                preset: None,
+            },
+            token: SessionToken::generate(),
+            decoder: Default::default(),
+            audio: None,
+        });
+        let mut encoder = TileEncoder::new();
+        let video = |encoder: &mut TileEncoder, shade: u8| {
+            let packet = encoder.encode(&image::RgbaImage::from_pixel(64, 48, image::Rgba([shade, 0, 0, 255])), false).unwrap();
+            Frame {
+                header: Arc::new(FrameHeader { codec: FrameCodec::Tiled, keyframe: packet.keyframe, ..(*frame(&session_id, 0).header).clone() }),
+                data: packet.data,
+            }
+        };
+        viewer.receive_frame(video(&mut encoder, 10)).await;
+        let shown = viewer.get_frame(&session_id).await.unwrap();
+        let decoded = image::load_from_memory(&shown).unwrap().to_rgba8();
+        assert_eq!(decoded.dimensions(), (64, 48));
+        assert!(decoded.get_pixel(5, 5)[0].abs_diff(10) < 8);
+        
+        video(&mut encoder, 200);
+        viewer.receive_frame(video(&mut encoder, 250)).await;
+        assert_eq!(viewer.get_frame(&session_id).await.unwrap(), shown);
+    }
+}
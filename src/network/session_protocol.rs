@@ -0,0 +1,205 @@
+// Messages exchanged between a screen share host and its viewers
+
+use std::fmt;
+use std::sync::Arc;
+use async_trait::async_trait;
+use anyhow::Error;
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+use crate::platform::input::{Key, MouseButton};
+use super::quality_preset::QualityPreset;
+use super::screen_share::{Frame, FrameHeader};
+use super::video::FrameCodec;
+
+/// Version written at the front of every frame sent to a viewer
+pub const FRAME_FORMAT_VERSION: u8 = 1;
+
+/// Version byte, then the header's length as a big-endian u32
+const FRAME_PREFIX_LEN: usize = 1 + 4;
+
+/// Who may join a session
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccessMode {
+    Open,
+    Password,
+    Approval,
+}
+
+/// Broadcast by hosts so viewers can list joinable sessions
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionAnnouncement {
+    pub session_id: String,
+    pub host_peer_id: String,
+    pub resolution: (u32, u32),
+    pub access_mode: AccessMode,
+    pub participant_count: usize,
+    pub timestamp: u64,
+    /// What the host calls itself
+    #[serde(default)]
+    pub host_name: Option<String>,
+    #[serde(default)]
+    pub started_at_ms: u64,
+    #[serde(default)]
+    pub has_password: bool,
+    #[serde(default)]
+    pub preset: Option<QualityPreset>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JoinRequest {
+    pub request_id: String,
+    pub session_id: String,
+    pub peer_id: String,
+    pub password: Option<String>,
+    /// Codecs the viewer can decode; JPEG whatever this says
+    #[serde(default)]
+    pub codecs: Vec<FrameCodec>,
+    /// Has somewhere to play the session's audio
+    #[serde(default)]
+    pub audio: bool,
+    /// What the host's viewer list calls us; the peer id when None
+    #[serde(default)]
+    pub display_name: Option<String>,
+}
+
+/// Credential a host issues to each admitted viewer. Every control message
+/// for the session must carry it; it compares in constant time and never
+/// shows up in debug output.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SessionToken(String);
+
+impl SessionToken {
+    pub fn generate() -> Self {
+        use rand::RngCore;
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Self(hex::encode(bytes))
+    }
+}
+
+impl PartialEq for SessionToken {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.0.as_bytes(), other.0.as_bytes());
+        a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+    }
+}
+
+impl Eq for SessionToken {}
+
+impl fmt::Debug for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SessionToken(..)")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum JoinResponse {
+    Accepted {
+        resolution: (u32, u32),
+        token: SessionToken,
+        /// What the session's frames go out as for now
+        #[serde(default)]
+        codec: FrameCodec,
+    },
+    WrongPassword,
+    Denied,
+    SessionNotFound,
+    /// The host's participant limit is reached
+    SessionFull,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ControlAction {
+    /// Start receiving frames
+    Subscribe,
+    Leave,
+    /// Drive the host's mouse or keyboard, if it allows
+    Input(InputEvent),
+}
+
+/// Viewer input for the host to play back. Positions are fractions of the
+/// frame's width and height, so they don't depend on the size it's shown at.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum InputEvent {
+    MouseMove { x: f32, y: f32 },
+    MouseButton { button: MouseButton, pressed: bool },
+    /// In wheel notches; positive is right and down
+    Scroll { dx: i32, dy: i32 },
+    Key { key: Key, pressed: bool },
+}
+
+/// Viewer-to-host message about a joined session
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ControlMessage {
+    pub session_id: String,
+    pub peer_id: String,
+    pub token: SessionToken,
+    pub action: ControlAction,
+}
+
+/// Sent by the host when it rotates a viewer's token
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenGrant {
+    pub session_id: String,
+    pub host_peer_id: String,
+    pub token: SessionToken,
+}
+
+/// Sent by the host to everyone it let in when it ends a session
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionEnded {
+    pub session_id: String,
+    pub host_peer_id: String,
+}
+
+/// On the wire a frame is its JSON header followed by the encoded image
+impl Frame {
+    pub fn encode(&self) -> Result<Bytes, Error> {
+        let header = serde_json::to_vec(&*self.header)?;
+        let mut message = BytesMut::with_capacity(FRAME_PREFIX_LEN + header.len() + self.data.len());
+        message.put_u8(FRAME_FORMAT_VERSION);
+        message.put_u32(header.len() as u32);
+        message.put_slice(&header);
+        message.put_slice(&self.data);
+        Ok(message.freeze())
+    }
+    
+    /// The image comes back as a view into `message`, not a copy
+    pub fn decode(message: Bytes) -> Result<Self, Error> {
+        let version = *message.first().ok_or(DeskShareError::InvalidMessageFormat)?;
+        if version > FRAME_FORMAT_VERSION {
+            return Err(DeskShareError::UnsupportedProtocolVersion(version).into());
+        }
+        let header_len = message
+            .get(1..FRAME_PREFIX_LEN)
+            .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)
+            .ok_or(DeskShareError::InvalidMessageFormat)?;
+        let header = message
+            .get(FRAME_PREFIX_LEN..FRAME_PREFIX_LEN + header_len)
+            .ok_or(DeskShareError::InvalidMessageFormat)?;
+        let header: FrameHeader = serde_json::from_slice(header).map_err(|_| DeskShareError::InvalidMessageFormat)?;
+        Ok(Frame {
+            header: Arc::new(header),
+            data: message.slice(FRAME_PREFIX_LEN + header_len..),
+        })
+    }
+}
+
+/// Carries session traffic between peers
+#[async_trait]
+pub trait SessionTransport: Send + Sync {
+    async fn announce(&self, announcement: SessionAnnouncement) -> Result<(), Error>;
+    
+    async fn request_join(&self, host_peer_id: &str, request: JoinRequest) -> Result<JoinResponse, Error>;
+    
+    async fn send_control(&self, host_peer_id: &str, message: ControlMessage) -> Result<(), Error>;
+    
+    async fn grant_token(&self, peer_id: &str, grant: TokenGrant) -> Result<(), Error>;
+    
+    async fn end_session(&self, peer_id: &str, ended: SessionEnded) -> Result<(), Error>;
+    
+    async fn send_frame(&self, peer_id: &str, frame: Frame) -> Result<(), Error>;
+}
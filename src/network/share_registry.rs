@@ -0,0 +1,95 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use anyhow::Error;
+use serde::{Serialize, Deserialize};
+
+use super::file_transfer::SharedFile;
+
+/// A path a share is served from, as it was when it was recorded
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareSource {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl ShareSource {
+    pub fn stamp(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+    
+    /// Whether the file is still there at the same size and modified time
+    pub fn unchanged(&self) -> bool {
+        Self::stamp(&self.path).is_ok_and(|now| now == *self)
+    }
+}
+
+/// A share as recorded: its metadata and where it's served from, never
+/// chunk data
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PersistedShare {
+    pub file: SharedFile,
+    pub sources: Vec<ShareSource>,
+    /// When the share lapses, in seconds since the epoch
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RegistryFile {
+    shares: Vec<PersistedShare>,
+}
+
+/// The files we share, kept in one JSON file so they're shared again after
+/// a restart. Each save replaces the whole file, written aside and moved
+/// into place; a file that can't be read is treated as empty.
+pub struct ShareRegistry {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl ShareRegistry {
+    pub fn open(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            lock: Mutex::new(()),
+        }
+    }
+    
+    pub fn load(&self) -> Vec<PersistedShare> {
+        let _guard = self.lock.lock().unwrap();
+        let json = match std::fs::read(&self.path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+            Err(e) => {
+                tracing::warn!("Couldn't read shares from {}, starting without them: {}", self.path.display(), e);
+                return Vec::new();
+            }
+        };
+        match serde_json::from_slice::<RegistryFile>(&json) {
+            Ok(registry) => registry.shares,
+            Err(e) => {
+                tracing::warn!("Shares in {} are unreadable, starting without them: {}", self.path.display(), e);
+                Vec::new()
+            }
+        }
+    }
+    
+    pub fn save(&self, shares: Vec<PersistedShare>) -> Result<(), Error> {
+        let json = serde_json::to_vec(&RegistryFile { shares })?;
+        let _guard = self.lock.lock().unwrap();
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let staged = self.path.with_extension("json.tmp");
+        std::fs::write(&staged, json)?;
+        std::fs::rename(&staged, &self.path)?;
+        Ok(())
+    }
+}
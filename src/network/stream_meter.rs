@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How far back a session's achieved frame rate and bandwidth look
+pub const METER_WINDOW: Duration = Duration::from_secs(5);
+
+/// Frames a session actually produced over the last `METER_WINDOW`, as
+/// opposed to the rate it asked for. Times are passed in, in milliseconds
+/// on the same clock as frame timestamps.
+#[derive(Clone, Debug, Default)]
+pub struct StreamMeter {
+    started_ms: u64,
+    /// (at_ms, encoded bytes), oldest first
+    frames: VecDeque<(u64, u64)>,
+}
+
+impl StreamMeter {
+    pub fn new(started_ms: u64) -> Self {
+        Self { started_ms, frames: VecDeque::new() }
+    }
+    
+    /// One frame produced; heartbeats count with no bytes
+    pub fn record(&mut self, at_ms: u64, bytes: u64) {
+        self.frames.push_back((at_ms, bytes));
+        let window_ms = METER_WINDOW.as_millis() as u64;
+        while self.frames.front().is_some_and(|(oldest, _)| oldest + window_ms <= at_ms) {
+            self.frames.pop_front();
+        }
+    }
+    
+    pub fn frames_per_second(&self, now_ms: u64) -> f64 {
+        let (frames, _) = self.window(now_ms);
+        match self.span_secs(now_ms) {
+            span if span > 0.0 => frames as f64 / span,
+            _ => 0.0,
+        }
+    }
+    
+    pub fn bytes_per_second(&self, now_ms: u64) -> u64 {
+        let (_, bytes) = self.window(now_ms);
+        match self.span_secs(now_ms) {
+            span if span > 0.0 => (bytes as f64 / span) as u64,
+            _ => 0,
+        }
+    }
+    
+    /// Frames and bytes produced within the window before `now_ms`
+    fn window(&self, now_ms: u64) -> (u64, u64) {
+        let window_ms = METER_WINDOW.as_millis() as u64;
+        self.frames
+            .iter()
+            .filter(|(at_ms, _)| at_ms + window_ms > now_ms && *at_ms <= now_ms)
+            .fold((0, 0), |(frames, bytes), (_, len)| (frames + 1, bytes + len))
+    }
+    
+    /// The window, or less while it hasn't been that long since starting
+    fn span_secs(&self, now_ms: u64) -> f64 {
+        now_ms.saturating_sub(self.started_ms).min(METER_WINDOW.as_millis() as u64) as f64 / 1000.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_rates_follow_the_frames_actually_produced() {
+        let mut meter = StreamMeter::new(10_000);
+        assert_eq!(meter.frames_per_second(10_000), 0.0);
+        
+        // A 10fps share of 2KB frames, one second in
+        for at_ms in (10_100..=11_000).step_by(100) {
+            meter.record(at_ms, 2_000);
+        }
+        assert_eq!(meter.frames_per_second(11_000), 10.0);
+        assert_eq!(meter.bytes_per_second(11_000), 20_000);
+        
+        // Past the window only its last five seconds count, and a heartbeat
+        // is a frame without bytes
+        for at_ms in (11_100..=15_000).step_by(100) {
+            meter.record(at_ms, 2_000);
+        }
+        for at_ms in (15_100..=17_000).step_by(100) {
+            meter.record(at_ms, 0);
+        }
+        assert_eq!(meter.bytes_per_second(17_000), 2_000 * 30 / 5);
+        for at_ms in (17_100..=20_000).step_by(100) {
+            meter.record(at_ms, 0);
+        }
+        assert_eq!(meter.frames_per_second(20_000), 10.0);
+        assert_eq!(meter.bytes_per_second(20_000), 0);
+        
+        // Capture stalling halves the rate, then takes it to nothing
+        assert_eq!(meter.frames_per_second(22_500), 5.0);
+        assert_eq!(meter.frames_per_second(25_000), 0.0);
+        meter.record(30_000, 500);
+        assert_eq!(meter.frames.len(), 1);
+        assert_eq!(meter.bytes_per_second(30_000), 100);
+    }
+}
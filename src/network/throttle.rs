@@ -0,0 +1,103 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+
+/// Longest single sleep, so a changed limit is picked up promptly
+const MAX_WAIT: Duration = Duration::from_millis(50);
+
+/// Transfer rate limits; zero means unlimited
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BandwidthConfig {
+    pub upload_bytes_per_second: u64,
+    pub download_bytes_per_second: u64,
+}
+
+/// Token bucket that paces traffic to a byte rate. A chunk goes out once
+/// the bucket isn't in debt, and its bytes are then owed, so the rate holds
+/// for chunks larger than a second's worth too. At most a second's worth
+/// builds up while idle.
+pub struct Throttle {
+    bytes_per_second: AtomicU64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: AtomicU64::new(bytes_per_second),
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+    
+    pub fn limit(&self) -> u64 {
+        self.bytes_per_second.load(Ordering::Relaxed)
+    }
+    
+    /// Takes effect for chunks already waiting, not just new ones
+    pub fn set_limit(&self, bytes_per_second: u64) {
+        self.bytes_per_second.store(bytes_per_second, Ordering::Relaxed);
+    }
+    
+    /// Wait until `bytes` may go
+    pub async fn acquire(&self, bytes: u64) {
+        loop {
+            let limit = self.limit();
+            if limit == 0 {
+                return;
+            }
+            let wait = {
+                let mut bucket = self.bucket.lock().unwrap();
+                let now = Instant::now();
+                let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * limit as f64;
+                bucket.tokens = (bucket.tokens + refill).min(limit as f64);
+                bucket.refilled_at = now;
+                if bucket.tokens >= 0.0 {
+                    bucket.tokens -= bytes as f64;
+                    return;
+                }
+                Duration::from_secs_f64(-bucket.tokens / limit as f64)
+            };
+            tokio::time::sleep(wait.min(MAX_WAIT)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    
+    #[tokio::test]
+    async fn test_unlimited_never_waits_and_limits_apply_mid_wait() {
+        let throttle = Arc::new(Throttle::new(0));
+        let started = Instant::now();
+        for _ in 0..100 {
+            throttle.acquire(1024 * 1024).await;
+        }
+        assert!(started.elapsed() < Duration::from_millis(50));
+        
+        // Owes over a second at 1 KB/s, but a raised limit clears the debt at once
+        throttle.set_limit(1000);
+        throttle.acquire(2000).await;
+        let waiting = throttle.clone();
+        let raised = tokio::spawn(async move {
+            let started = Instant::now();
+            waiting.acquire(1).await;
+            started.elapsed()
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        throttle.set_limit(1_000_000);
+        let waited = raised.await.unwrap();
+        assert!(waited >= Duration::from_millis(15));
+        assert!(waited < Duration::from_millis(300), "{:?}", waited);
+    }
+}
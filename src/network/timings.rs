@@ -0,0 +1,29 @@
+use std::time::Duration;
+use serde::{Serialize, Deserialize};
+
+/// How long a CPU-heavy stage (hashing, frame encoding) has been taking
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageTimings {
+    pub runs: u64,
+    pub last_ms: f64,
+    pub max_ms: f64,
+    pub total_ms: f64,
+}
+
+impl StageTimings {
+    pub fn record(&mut self, elapsed: Duration) {
+        let ms = elapsed.as_secs_f64() * 1000.0;
+        self.runs += 1;
+        self.last_ms = ms;
+        self.max_ms = self.max_ms.max(ms);
+        self.total_ms += ms;
+    }
+    
+    pub fn mean_ms(&self) -> f64 {
+        if self.runs == 0 {
+            0.0
+        } else {
+            self.total_ms / self.runs as f64
+        }
+    }
+}
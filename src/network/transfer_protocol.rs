@@ -0,0 +1,264 @@
+// Messages exchanged between the two ends of a file transfer
+
+use anyhow::Error;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::error::DeskShareError;
+use super::file_transfer::SharedFile;
+
+/// Version written at the front of every message. Decoders take anything up
+/// to their own version and skip header fields past the ones they know, so a
+/// newer sender only has to bump this for changes older peers can't skip.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Version byte, message kind and header length
+const PREFIX_LEN: usize = 1 + 1 + 2;
+
+/// Chunk index of an `Error` about a whole transfer rather than one chunk
+const NO_CHUNK: u32 = u32::MAX;
+
+const KIND_OFFER: u8 = 0;
+const KIND_CHUNK_REQUEST: u8 = 1;
+const KIND_CHUNK_DATA: u8 = 2;
+const KIND_TRANSFER_COMPLETE: u8 = 3;
+const KIND_ERROR: u8 = 4;
+
+/// One file transfer message. On the wire it's a version byte, a kind byte
+/// and a length-prefixed header of fixed binary fields, then a body: a
+/// chunk's data as raw bytes, or the JSON manifest of an offered file, whose
+/// optional fields older peers already know how to fill in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileTransferMessage {
+    Offer {
+        sender_name: String,
+        file: SharedFile,
+    },
+    ChunkRequest {
+        file_hash: String,
+        chunk_index: usize,
+    },
+    ChunkData {
+        file_hash: String,
+        chunk_index: usize,
+        data: Bytes,
+    },
+    TransferComplete {
+        file_hash: String,
+    },
+    /// A chunk that couldn't be served, or, without an index, a whole
+    /// transfer turned down
+    Error {
+        file_hash: String,
+        chunk_index: Option<usize>,
+        reason: String,
+    },
+}
+
+impl FileTransferMessage {
+    pub fn encode(&self) -> Result<Bytes, Error> {
+        let mut header = BytesMut::new();
+        let (kind, body) = match self {
+            FileTransferMessage::Offer { sender_name, file } => {
+                put_str(&mut header, sender_name)?;
+                (KIND_OFFER, Bytes::from(serde_json::to_vec(file)?))
+            }
+            FileTransferMessage::ChunkRequest { file_hash, chunk_index } => {
+                put_str(&mut header, file_hash)?;
+                header.put_u32(index_field(*chunk_index)?);
+                (KIND_CHUNK_REQUEST, Bytes::new())
+            }
+            FileTransferMessage::ChunkData { file_hash, chunk_index, data } => {
+                put_str(&mut header, file_hash)?;
+                header.put_u32(index_field(*chunk_index)?);
+                (KIND_CHUNK_DATA, data.clone())
+            }
+            FileTransferMessage::TransferComplete { file_hash } => {
+                put_str(&mut header, file_hash)?;
+                (KIND_TRANSFER_COMPLETE, Bytes::new())
+            }
+            FileTransferMessage::Error { file_hash, chunk_index, reason } => {
+                put_str(&mut header, file_hash)?;
+                header.put_u32(chunk_index.map_or(Ok(NO_CHUNK), index_field)?);
+                put_str(&mut header, reason)?;
+                (KIND_ERROR, Bytes::new())
+            }
+        };
+        let header_len = u16::try_from(header.len()).map_err(|_| DeskShareError::InvalidMessageFormat)?;
+        let mut message = BytesMut::with_capacity(PREFIX_LEN + header.len() + body.len());
+        message.put_u8(PROTOCOL_VERSION);
+        message.put_u8(kind);
+        message.put_u16(header_len);
+        message.put_slice(&header);
+        message.put_slice(&body);
+        Ok(message.freeze())
+    }
+    
+    /// A chunk's data comes back as a view into `message`, not a copy
+    pub fn decode(message: Bytes) -> Result<Self, Error> {
+        let version = *message.first().ok_or(DeskShareError::InvalidMessageFormat)?;
+        if version > PROTOCOL_VERSION {
+            return Err(DeskShareError::UnsupportedProtocolVersion(version).into());
+        }
+        if message.len() < PREFIX_LEN {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        }
+        let kind = message[1];
+        let header_len = u16::from_be_bytes([message[2], message[3]]) as usize;
+        let mut header = message
+            .get(PREFIX_LEN..PREFIX_LEN + header_len)
+            .ok_or(DeskShareError::InvalidMessageFormat)?;
+        let body = message.slice(PREFIX_LEN + header_len..);
+        
+        let decoded = match kind {
+            KIND_OFFER => {
+                let sender_name = get_str(&mut header)?;
+                let file = serde_json::from_slice(&body).map_err(|_| DeskShareError::InvalidMessageFormat)?;
+                return Ok(FileTransferMessage::Offer { sender_name, file });
+            }
+            KIND_CHUNK_REQUEST => FileTransferMessage::ChunkRequest {
+                file_hash: get_str(&mut header)?,
+                chunk_index: get_u32(&mut header)? as usize,
+            },
+            KIND_CHUNK_DATA => {
+                return Ok(FileTransferMessage::ChunkData {
+                    file_hash: get_str(&mut header)?,
+                    chunk_index: get_u32(&mut header)? as usize,
+                    data: body,
+                });
+            }
+            KIND_TRANSFER_COMPLETE => FileTransferMessage::TransferComplete { file_hash: get_str(&mut header)? },
+            KIND_ERROR => FileTransferMessage::Error {
+                file_hash: get_str(&mut header)?,
+                chunk_index: Some(get_u32(&mut header)?).filter(|&index| index != NO_CHUNK).map(|index| index as usize),
+                reason: get_str(&mut header)?,
+            },
+            _ => return Err(DeskShareError::InvalidMessageFormat.into()),
+        };
+        // Only offers and chunk data have a body
+        if !body.is_empty() {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        }
+        Ok(decoded)
+    }
+}
+
+/// A string field: its length, then its UTF-8 bytes
+fn put_str(header: &mut BytesMut, value: &str) -> Result<(), DeskShareError> {
+    let len = u16::try_from(value.len()).map_err(|_| DeskShareError::InvalidMessageFormat)?;
+    header.put_u16(len);
+    header.put_slice(value.as_bytes());
+    Ok(())
+}
+
+fn get_str(header: &mut &[u8]) -> Result<String, DeskShareError> {
+    if header.remaining() < 2 {
+        return Err(DeskShareError::InvalidMessageFormat);
+    }
+    let len = header.get_u16() as usize;
+    let value = header.get(..len).ok_or(DeskShareError::InvalidMessageFormat)?;
+    let value = std::str::from_utf8(value).map_err(|_| DeskShareError::InvalidMessageFormat)?.to_string();
+    header.advance(len);
+    Ok(value)
+}
+
+fn get_u32(header: &mut &[u8]) -> Result<u32, DeskShareError> {
+    if header.remaining() < 4 {
+        return Err(DeskShareError::InvalidMessageFormat);
+    }
+    Ok(header.get_u32())
+}
+
+/// Chunk indices travel as u32, with `NO_CHUNK` kept back
+fn index_field(index: usize) -> Result<u32, DeskShareError> {
+    u32::try_from(index)
+        .ok()
+        .filter(|&index| index != NO_CHUNK)
+        .ok_or(DeskShareError::InvalidMessageFormat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{ChunkCodec, HashAlgorithm, ShareKind, MANIFEST_VERSION_FLAT};
+    
+    fn round_trip(message: FileTransferMessage) {
+        let encoded = message.encode().unwrap();
+        assert_eq!(encoded[0], PROTOCOL_VERSION);
+        assert_eq!(FileTransferMessage::decode(encoded).unwrap(), message);
+    }
+    
+    #[test]
+    fn test_every_message_survives_the_wire() {
+        let file = SharedFile {
+            hash: "f00d".to_string(),
+            name: "report.pdf".to_string(),
+            size: 3,
+            chunks: vec!["c0".to_string()],
+            chunk_size: 4,
+            total_chunks: 1,
+            peer_id: "local".to_string(),
+            timestamp: 1_700_000_000,
+            kind: ShareKind::File,
+            codec: ChunkCodec::Zstd,
+            encrypted: true,
+            manifest_version: MANIFEST_VERSION_FLAT,
+            merkle_root: None,
+            hash_algorithm: HashAlgorithm::Blake3,
+        };
+        round_trip(FileTransferMessage::Offer { sender_name: "Alice".to_string(), file });
+        round_trip(FileTransferMessage::ChunkRequest { file_hash: "f00d".to_string(), chunk_index: 7 });
+        round_trip(FileTransferMessage::TransferComplete { file_hash: "f00d".to_string() });
+        round_trip(FileTransferMessage::Error { file_hash: "f00d".to_string(), chunk_index: Some(2), reason: "gone".to_string() });
+        round_trip(FileTransferMessage::Error { file_hash: "f00d".to_string(), chunk_index: None, reason: "no room".to_string() });
+        round_trip(FileTransferMessage::ChunkData { file_hash: "f00d".to_string(), chunk_index: 0, data: Bytes::new() });
+        
+        // Requests are a handful of fixed fields
+        let request = FileTransferMessage::ChunkRequest { file_hash: "f00d".to_string(), chunk_index: 7 }.encode().unwrap();
+        assert_eq!(&request[..], &[PROTOCOL_VERSION, KIND_CHUNK_REQUEST, 0, 10, 0, 4, b'f', b'0', b'0', b'd', 0, 0, 0, 7]);
+        
+        // Chunk data travels as-is after the header
+        let data: Bytes = (0..1024 * 1024).map(|i| (i % 251) as u8).collect::<Vec<u8>>().into();
+        let message = FileTransferMessage::ChunkData { file_hash: "f00d".to_string(), chunk_index: 3, data: data.clone() };
+        let encoded = message.encode().unwrap();
+        assert!(encoded.len() < data.len() + 128);
+        assert!(encoded.ends_with(&data[..]));
+        round_trip(message);
+    }
+    
+    #[test]
+    fn test_newer_and_malformed_messages_are_refused() {
+        let mut message = FileTransferMessage::TransferComplete { file_hash: "f00d".to_string() }.encode().unwrap().to_vec();
+        
+        // Trailing bytes only belong to chunk data
+        let mut padded = message.clone();
+        padded.push(0);
+        let err = FileTransferMessage::decode(padded.into()).unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "invalid_message_format");
+        let err = FileTransferMessage::decode(Bytes::copy_from_slice(&message[..message.len() - 1])).unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "invalid_message_format");
+        assert!(FileTransferMessage::decode(Bytes::new()).is_err());
+        
+        message[0] = PROTOCOL_VERSION + 1;
+        let err = FileTransferMessage::decode(message.into()).unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "unsupported_protocol_version");
+        
+        // Unknown kinds are refused
+        let mut unknown = FileTransferMessage::TransferComplete { file_hash: "f00d".to_string() }.encode().unwrap().to_vec();
+        unknown[1] = 0x7f;
+        let err = FileTransferMessage::decode(unknown.into()).unwrap_err();
+        assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "invalid_message_format");
+        
+        // Fields a later version adds to the header are skipped
+        let mut header = vec![0, 4];
+        header.extend_from_slice(b"f00d");
+        header.extend_from_slice(&1u32.to_be_bytes());
+        header.extend_from_slice(&[0xaa; 6]);
+        let mut message = vec![PROTOCOL_VERSION, KIND_CHUNK_REQUEST];
+        message.extend_from_slice(&(header.len() as u16).to_be_bytes());
+        message.extend_from_slice(&header);
+        assert_eq!(
+            FileTransferMessage::decode(message.into()).unwrap(),
+            FileTransferMessage::ChunkRequest { file_hash: "f00d".to_string(), chunk_index: 1 }
+        );
+    }
+}
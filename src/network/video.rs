@@ -0,0 +1,414 @@
+use anyhow::Error;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use image::RgbaImage;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+#[cfg(feature = "h264")]
+use super::h264::{H264Decoder, H264Encoder};
+
+/// Side of the square tiles frames are compared and sent in
+const TILE: u32 = 32;
+
+/// Screens are mostly flat colour, so a fast level already does well
+const ZSTD_LEVEL: i32 = 1;
+
+/// Keyframe flag (u8), then the packet number, width, height and tile
+/// count (u32s)
+const PACKET_HEADER_LEN: usize = 1 + 4 * 4;
+
+/// Largest frame a packet may decode to, 8K RGBA
+const MAX_FRAME_BYTES: usize = 7680 * 4320 * 4;
+
+/// How a frame's image is encoded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameCodec {
+    /// Each frame a JPEG of its own; every viewer can show these
+    #[default]
+    Jpeg,
+    /// Only the tiles that changed, see `TileEncoder`
+    Tiled,
+    /// H.264 video, see `H264Encoder`; only builds with the `h264`
+    /// feature encode or decode it
+    H264,
+}
+
+impl FrameCodec {
+    /// What this build can decode, offered when joining a session
+    pub fn decodable() -> Vec<FrameCodec> {
+        let mut codecs = vec![FrameCodec::Jpeg, FrameCodec::Tiled];
+        if cfg!(feature = "h264") {
+            codecs.push(FrameCodec::H264);
+        }
+        codecs
+    }
+
+    /// What a session going out as this codec falls back to for a viewer
+    /// that can decode `codecs`. Every build that decodes H.264 decodes
+    /// tiles as well, so it steps down to those where it can; anything else
+    /// goes to JPEG, which every viewer shows.
+    pub fn fallback_for(self, codecs: &[FrameCodec]) -> FrameCodec {
+        if codecs.contains(&self) {
+            self
+        } else if self == FrameCodec::H264 && codecs.contains(&FrameCodec::Tiled) {
+            FrameCodec::Tiled
+        } else {
+            FrameCodec::Jpeg
+        }
+    }
+}
+
+/// One encoded frame of video
+#[derive(Clone, Debug)]
+pub struct VideoPacket {
+    pub data: Bytes,
+    /// Decodes on its own, without the frames before it
+    pub keyframe: bool,
+    pub codec: FrameCodec,
+}
+
+impl VideoPacket {
+    /// A JPEG, which never depends on another frame
+    pub fn jpeg(data: Bytes) -> Self {
+        Self { data, keyframe: true, codec: FrameCodec::Jpeg }
+    }
+}
+
+/// Encodes frames as whichever codec their session goes out as
+#[derive(Default)]
+pub struct VideoEncoder {
+    tiled: TileEncoder,
+    #[cfg(feature = "h264")]
+    h264: Option<H264Encoder>,
+}
+
+impl VideoEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn encode(&mut self, codec: FrameCodec, image: &RgbaImage, keyframe: bool) -> Result<VideoPacket, Error> {
+        match codec {
+            FrameCodec::Tiled => self.tiled.encode(image, keyframe),
+            #[cfg(feature = "h264")]
+            FrameCodec::H264 => match &mut self.h264 {
+                Some(encoder) => encoder.encode(image, keyframe),
+                encoder => encoder.insert(H264Encoder::new()?).encode(image, keyframe),
+            },
+            codec => Err(DeskShareError::EncodingFailed(format!("no {:?} encoder in this build", codec)).into()),
+        }
+    }
+}
+
+/// Rebuilds frames of whichever codec the host sends
+#[derive(Default)]
+pub struct VideoDecoder {
+    tiled: TileDecoder,
+    #[cfg(feature = "h264")]
+    h264: H264Decoder,
+}
+
+impl VideoDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decode(&mut self, codec: FrameCodec, data: &[u8]) -> Result<RgbaImage, Error> {
+        match codec {
+            FrameCodec::Tiled => self.tiled.decode(data),
+            #[cfg(feature = "h264")]
+            FrameCodec::H264 => self.h264.decode(data),
+            codec => Err(DeskShareError::EncodingFailed(format!("no {:?} decoder in this build", codec)).into()),
+        }
+    }
+}
+
+/// Screen codec that sends only the tiles that changed since the previous
+/// frame, losslessly and zstd-compressed. Most frames of a screen come to a
+/// few tiles; keyframes carry them all, so a viewer can start, or recover
+/// from a missed packet, at any keyframe.
+///
+/// Unlike H.264 it's lossless, so text stays sharp, and needs nothing built
+/// for the platform; it's what sessions go out as in builds without the
+/// `h264` feature.
+#[derive(Default)]
+pub struct TileEncoder {
+    previous: Option<RgbaImage>,
+    packets: u32,
+}
+
+impl TileEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Encode `image`; a keyframe if asked for, or if there's nothing the
+    /// same size to go from
+    pub fn encode(&mut self, image: &RgbaImage, keyframe: bool) -> Result<VideoPacket, Error> {
+        let keyframe = keyframe || self.previous.as_ref().is_none_or(|previous| previous.dimensions() != image.dimensions());
+        self.packets = if keyframe { 0 } else { self.packets.wrapping_add(1) };
+
+        let (width, height) = image.dimensions();
+        let mut body = BytesMut::with_capacity(PACKET_HEADER_LEN);
+        body.put_u8(keyframe as u8);
+        body.put_u32(self.packets);
+        body.put_u32(width);
+        body.put_u32(height);
+        body.put_u32(0);
+        let mut tiles = 0u32;
+        for index in 0..tile_count(width, height) {
+            let changed = match &self.previous {
+                Some(previous) if !keyframe => tile_rows(previous, index).ne(tile_rows(image, index)),
+                _ => true,
+            };
+            if changed {
+                body.put_u32(index);
+                for row in tile_rows(image, index) {
+                    body.put_slice(row);
+                }
+                tiles += 1;
+            }
+        }
+        body[PACKET_HEADER_LEN - 4..PACKET_HEADER_LEN].copy_from_slice(&tiles.to_be_bytes());
+
+        let data = zstd::bulk::compress(&body, ZSTD_LEVEL)?;
+        match &mut self.previous {
+            Some(previous) if previous.dimensions() == image.dimensions() => previous.copy_from_slice(image),
+            previous => *previous = Some(image.clone()),
+        }
+        Ok(VideoPacket { data: Bytes::from(data), keyframe, codec: FrameCodec::Tiled })
+    }
+}
+
+/// Rebuilds frames from `TileEncoder` packets. After a missed or broken
+/// packet it refuses everything up to the next keyframe.
+#[derive(Default)]
+pub struct TileDecoder {
+    canvas: Option<RgbaImage>,
+    packets: u32,
+}
+
+impl TileDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn decode(&mut self, data: &[u8]) -> Result<RgbaImage, Error> {
+        let decoded = self.apply(data);
+        if decoded.is_err() {
+            self.canvas = None;
+        }
+        decoded
+    }
+
+    fn apply(&mut self, data: &[u8]) -> Result<RgbaImage, Error> {
+        let len = zstd::zstd_safe::get_frame_content_size(data)
+            .ok()
+            .flatten()
+            .filter(|len| *len as usize <= MAX_FRAME_BYTES + MAX_FRAME_BYTES / 64)
+            .ok_or(DeskShareError::InvalidMessageFormat)?;
+        let body = zstd::bulk::decompress(data, len as usize).map_err(|_| DeskShareError::InvalidMessageFormat)?;
+        let mut body = &body[..];
+        if body.len() < PACKET_HEADER_LEN {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        }
+        let keyframe = body.get_u8() == 1;
+        let packet = body.get_u32();
+        let (width, height) = (body.get_u32(), body.get_u32());
+        let tiles = body.get_u32();
+        if width as usize * height as usize * 4 > MAX_FRAME_BYTES {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        }
+
+        if keyframe {
+            self.canvas = Some(RgbaImage::new(width, height));
+        } else if self.canvas.as_ref().is_none_or(|canvas| canvas.dimensions() != (width, height))
+            || packet != self.packets.wrapping_add(1)
+        {
+            return Err(DeskShareError::EncodingFailed("missed a frame, waiting for the next keyframe".to_string()).into());
+        }
+        let canvas: &mut [u8] = self.canvas.as_mut().unwrap();
+
+        let count = tile_count(width, height);
+        let stride = width as usize * 4;
+        for _ in 0..tiles {
+            if body.remaining() < 4 {
+                return Err(DeskShareError::InvalidMessageFormat.into());
+            }
+            let index = body.get_u32();
+            if index >= count {
+                return Err(DeskShareError::InvalidMessageFormat.into());
+            }
+            let (x, y, tile_width, tile_height) = tile_bounds(width, height, index);
+            let row_len = tile_width as usize * 4;
+            if body.remaining() < row_len * tile_height as usize {
+                return Err(DeskShareError::InvalidMessageFormat.into());
+            }
+            for row in 0..tile_height as usize {
+                let start = (y as usize + row) * stride + x as usize * 4;
+                canvas[start..start + row_len].copy_from_slice(&body[..row_len]);
+                body.advance(row_len);
+            }
+        }
+        if body.has_remaining() {
+            return Err(DeskShareError::InvalidMessageFormat.into());
+        }
+
+        self.packets = packet;
+        Ok(self.canvas.clone().unwrap())
+    }
+}
+
+fn tile_count(width: u32, height: u32) -> u32 {
+    width.div_ceil(TILE) * height.div_ceil(TILE)
+}
+
+/// (x, y, width, height) of tile `index`; edge tiles are cut short
+fn tile_bounds(width: u32, height: u32, index: u32) -> (u32, u32, u32, u32) {
+    let columns = width.div_ceil(TILE);
+    let (x, y) = ((index % columns) * TILE, (index / columns) * TILE);
+    (x, y, TILE.min(width - x), TILE.min(height - y))
+}
+
+fn tile_rows(image: &RgbaImage, index: u32) -> impl Iterator<Item = &[u8]> {
+    let (x, y, tile_width, tile_height) = tile_bounds(image.width(), image.height(), index);
+    let stride = image.width() as usize * 4;
+    let start = x as usize * 4;
+    image
+        .as_raw()
+        .chunks_exact(stride)
+        .skip(y as usize)
+        .take(tile_height as usize)
+        .map(move |row| &row[start..start + tile_width as usize * 4])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::encode_jpeg;
+
+    /// A still desktop: flat panels, a few bars of "text", and a cursor that
+    /// moves a little each frame
+    fn desktop(width: u32, height: u32, frame: u32) -> RgbaImage {
+        let cursor = (40 + frame * 3, 60 + frame * 2);
+        RgbaImage::from_fn(width, height, |x, y| {
+            if x.abs_diff(cursor.0) < 6 && y.abs_diff(cursor.1) < 10 {
+                image::Rgba([0, 0, 0, 255])
+            } else if y < 24 {
+                image::Rgba([40, 44, 52, 255])
+            } else if (y / 12) % 3 == 0 && (x / 7) % 5 != 0 && x > 100 {
+                image::Rgba([30, 30, 30, 255])
+            } else {
+                image::Rgba([(x / 4) as u8, 200, (y / 4) as u8, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_frames_decode_exactly() {
+        let mut encoder = TileEncoder::new();
+        let mut decoder = TileDecoder::new();
+        for frame in 0..5 {
+            // 100x70 leaves short tiles on both edges
+            let image = desktop(100, 70, frame);
+            let packet = encoder.encode(&image, false).unwrap();
+            assert_eq!(packet.keyframe, frame == 0);
+            assert_eq!(decoder.decode(&packet.data).unwrap(), image);
+        }
+
+        // A missed packet stops everything up to the next keyframe
+        encoder.encode(&desktop(100, 70, 5), false).unwrap();
+        let after_gap = encoder.encode(&desktop(100, 70, 6), false).unwrap();
+        assert!(decoder.decode(&after_gap.data).is_err());
+        let next = encoder.encode(&desktop(100, 70, 7), false).unwrap();
+        assert!(decoder.decode(&next.data).is_err());
+        let keyframe = encoder.encode(&desktop(100, 70, 8), true).unwrap();
+        assert_eq!(decoder.decode(&keyframe.data).unwrap(), desktop(100, 70, 8));
+
+        // A new size starts over
+        let resized = encoder.encode(&desktop(64, 48, 9), false).unwrap();
+        assert!(resized.keyframe);
+        assert_eq!(decoder.decode(&resized.data).unwrap(), desktop(64, 48, 9));
+        assert!(decoder.decode(b"not a packet").is_err());
+    }
+
+    #[test]
+    fn test_a_still_screen_costs_a_fraction_of_jpeg() {
+        const FRAMES: u32 = 60;
+        let mut encoder = TileEncoder::new();
+        let (mut tiled, mut jpeg) = (0, 0);
+        for frame in 0..FRAMES {
+            let image = desktop(1280, 720, frame);
+            tiled += encoder.encode(&image, frame == 0).unwrap().data.len();
+            jpeg += encode_jpeg(&image, 75).unwrap().len();
+        }
+        // The keyframe included
+        assert!(tiled * 20 < jpeg, "{} bytes tiled against {} as JPEG", tiled, jpeg);
+    }
+
+    #[test]
+    fn test_sessions_fall_back_to_what_viewers_decode() {
+        let everything = [FrameCodec::Jpeg, FrameCodec::Tiled, FrameCodec::H264];
+        assert_eq!(FrameCodec::H264.fallback_for(&everything), FrameCodec::H264);
+        assert_eq!(FrameCodec::H264.fallback_for(&[FrameCodec::Jpeg, FrameCodec::Tiled]), FrameCodec::Tiled);
+        assert_eq!(FrameCodec::H264.fallback_for(&[]), FrameCodec::Jpeg);
+        assert_eq!(FrameCodec::Tiled.fallback_for(&[FrameCodec::Jpeg, FrameCodec::H264]), FrameCodec::Jpeg);
+        assert_eq!(FrameCodec::Jpeg.fallback_for(&[]), FrameCodec::Jpeg);
+        assert_eq!(FrameCodec::decodable().contains(&FrameCodec::H264), cfg!(feature = "h264"));
+    }
+
+    /// Mean difference per channel between two pictures the same size
+    #[cfg(feature = "h264")]
+    fn distance(a: &RgbaImage, b: &RgbaImage) -> f64 {
+        let total: u64 = a.as_raw().iter().zip(b.as_raw()).map(|(a, b)| a.abs_diff(*b) as u64).sum();
+        total as f64 / a.as_raw().len() as f64
+    }
+
+    #[cfg(feature = "h264")]
+    #[test]
+    fn test_h264_frames_decode_close_to_the_original() {
+        let mut encoder = VideoEncoder::new();
+        let mut decoder = VideoDecoder::new();
+        for frame in 0..5 {
+            // Odd sides are padded for the encoder and cropped back off
+            let image = desktop(101, 71, frame);
+            let packet = encoder.encode(FrameCodec::H264, &image, false).unwrap();
+            assert_eq!((packet.codec, packet.keyframe), (FrameCodec::H264, frame == 0));
+            let decoded = decoder.decode(FrameCodec::H264, &packet.data).unwrap();
+            assert_eq!(decoded.dimensions(), (101, 71));
+            assert!(distance(&decoded, &image) < 8.0, "{}", distance(&decoded, &image));
+        }
+
+        // A missed packet stops everything up to the next keyframe
+        encoder.encode(FrameCodec::H264, &desktop(101, 71, 5), false).unwrap();
+        let after_gap = encoder.encode(FrameCodec::H264, &desktop(101, 71, 6), false).unwrap();
+        assert!(decoder.decode(FrameCodec::H264, &after_gap.data).is_err());
+        let next = encoder.encode(FrameCodec::H264, &desktop(101, 71, 7), false).unwrap();
+        assert!(decoder.decode(FrameCodec::H264, &next.data).is_err());
+        let keyframe = encoder.encode(FrameCodec::H264, &desktop(101, 71, 8), true).unwrap();
+        assert!(keyframe.keyframe);
+        assert!(distance(&decoder.decode(FrameCodec::H264, &keyframe.data).unwrap(), &desktop(101, 71, 8)) < 8.0);
+
+        // A new size starts over
+        let resized = encoder.encode(FrameCodec::H264, &desktop(64, 48, 9), false).unwrap();
+        assert!(resized.keyframe);
+        assert_eq!(decoder.decode(FrameCodec::H264, &resized.data).unwrap().dimensions(), (64, 48));
+        assert!(decoder.decode(FrameCodec::H264, b"not a packet").is_err());
+    }
+
+    #[cfg(feature = "h264")]
+    #[test]
+    fn test_h264_still_screen_costs_a_fraction_of_jpeg() {
+        const FRAMES: u32 = 60;
+        let mut encoder = VideoEncoder::new();
+        let (mut h264, mut jpeg) = (0, 0);
+        for frame in 0..FRAMES {
+            let image = desktop(1280, 720, frame);
+            h264 += encoder.encode(FrameCodec::H264, &image, frame == 0).unwrap().data.len();
+            jpeg += encode_jpeg(&image, 75).unwrap().len();
+        }
+        // The keyframe included
+        assert!(h264 * 20 < jpeg, "{} bytes as H.264 against {} as JPEG", h264, jpeg);
+    }
+}
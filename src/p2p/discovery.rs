@@ -3,11 +3,15 @@
 
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 use serde::{Serialize, Deserialize};
 use tokio::sync::broadcast;
+use anyhow::Error;
 
 use crate::app::Device;
+use crate::error::DeskShareError;
+use crate::security::{DeviceIdentity, ProtocolClass, RateLimiter, TrustStore};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct DeviceInfo {
@@ -16,6 +20,9 @@ pub struct DeviceInfo {
     pub port: u16,
     pub services: Vec<String>,
     pub last_seen: u64,
+    /// Fingerprint of the key the device signed its reply with
+    #[serde(default)]
+    pub fingerprint: Option<String>,
 }
 
 impl From<DeviceInfo> for Device {
@@ -28,6 +35,86 @@ impl From<DeviceInfo> for Device {
             last_seen: chrono::DateTime::from_timestamp(info.last_seen as i64, 0)
                 .unwrap_or_else(|| chrono::Utc::now())
                 .to_rfc3339(),
+            fingerprint: info.fingerprint,
+        }
+    }
+}
+
+/// Unicast answer to a discovery probe. It carries only what the device list
+/// shows, signed with the device's identity key; everything else waits for an
+/// encrypted connection.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DiscoveryReply {
+    pub name: String,
+    pub port: u16,
+    pub services: Vec<String>,
+    pub public_key: [u8; 32],
+    pub timestamp: u64,
+    pub signature: Vec<u8>,
+}
+
+impl DiscoveryReply {
+    pub fn new(identity: &DeviceIdentity, name: String, port: u16, services: Vec<String>) -> Self {
+        let mut reply = DiscoveryReply {
+            name,
+            port,
+            services,
+            public_key: identity.public_key(),
+            timestamp: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            signature: Vec::new(),
+        };
+        reply.signature = identity.sign(&reply.signed_bytes()).to_vec();
+        reply
+    }
+    
+    pub fn device_id(&self) -> String {
+        DeviceIdentity::device_id_for(&self.public_key)
+    }
+    
+    /// Check the signature and turn the reply into a device seen at `ip`
+    pub fn verify(&self, ip: &str) -> Result<DeviceInfo, Error> {
+        let valid = <[u8; 64]>::try_from(self.signature.as_slice())
+            .map(|signature| DeviceIdentity::verify(&self.public_key, &self.signed_bytes(), &signature))
+            .unwrap_or(false);
+        if !valid {
+            return Err(DeskShareError::DiscoveryFailed(format!("unsigned reply from {}", ip)).into());
+        }
+        
+        Ok(DeviceInfo {
+            name: self.name.clone(),
+            ip: ip.to_string(),
+            port: self.port,
+            services: self.services.clone(),
+            last_seen: self.timestamp,
+            fingerprint: Some(DeviceIdentity::fingerprint_for(&self.public_key)),
+        })
+    }
+    
+    fn signed_bytes(&self) -> Vec<u8> {
+        let fields = (&self.name, self.port, &self.services, &self.public_key, self.timestamp);
+        let mut bytes = b"desk-share-net discovery reply v1:".to_vec();
+        bytes.extend(serde_json::to_vec(&fields).unwrap_or_default());
+        bytes
+    }
+}
+
+/// Change to the set of known devices, keyed by device IP
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub enum DeviceEvent {
+    Added(Device),
+    Updated(Device),
+    Removed(Device),
+}
+
+impl DeviceEvent {
+    pub fn device(&self) -> &Device {
+        match self {
+            DeviceEvent::Added(device)
+            | DeviceEvent::Updated(device)
+            | DeviceEvent::Removed(device) => device,
         }
     }
 }
@@ -35,7 +122,10 @@ impl From<DeviceInfo> for Device {
 pub struct NetworkDiscovery {
     devices: HashMap<String, DeviceInfo>,
     broadcast_sender: broadcast::Sender<DeviceInfo>,
+    event_sender: broadcast::Sender<DeviceEvent>,
     local_ip: IpAddr,
+    trust_store: Option<Arc<TrustStore>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
 }
 
 impl NetworkDiscovery {
@@ -43,14 +133,30 @@ impl NetworkDiscovery {
         let local_ip = local_ip_address::local_ip()
             .unwrap_or_else(|_| "127.0.0.1".parse().unwrap());
         let (tx, _) = broadcast::channel(100);
+        let (event_sender, _) = broadcast::channel(256);
         
         NetworkDiscovery {
             devices: HashMap::new(),
             broadcast_sender: tx,
+            event_sender,
             local_ip,
+            trust_store: None,
+            rate_limiter: None,
         }
     }
     
+    /// Ignore devices the trust store has blocked
+    pub fn with_trust_store(mut self, trust_store: Arc<TrustStore>) -> Self {
+        self.trust_store = Some(trust_store);
+        self
+    }
+    
+    /// Drop replies from addresses sending too many
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+    
     pub async fn start_discovery(&mut self) {
         let local_ip = self.local_ip;
         
@@ -84,6 +190,49 @@ impl NetworkDiscovery {
         // Device listening implementation
     }
     
+    /// Record the device behind a signed reply from `ip`, unless it's blocked
+    pub fn record_reply(&mut self, reply: &DiscoveryReply, ip: &str) -> Result<(), Error> {
+        let admitted = self
+            .rate_limiter
+            .as_ref()
+            .map(|rate_limiter| rate_limiter.admit(ip, ProtocolClass::Discovery))
+            .unwrap_or(true);
+        if !admitted {
+            return Ok(());
+        }
+        let info = reply.verify(ip)?;
+        if self.refuses(&reply.device_id()) {
+            return Ok(());
+        }
+        self.record_device(info);
+        Ok(())
+    }
+    
+    /// Insert or refresh a device and notify subscribers
+    pub fn record_device(&mut self, info: DeviceInfo) {
+        if self.refuses(&info.ip) {
+            return;
+        }
+        
+        let event = match self.devices.insert(info.ip.clone(), info.clone()) {
+            Some(_) => DeviceEvent::Updated(info.into()),
+            None => DeviceEvent::Added(info.into()),
+        };
+        
+        let _ = self.event_sender.send(event);
+    }
+    
+    fn refuses(&self, peer_or_device_id: &str) -> bool {
+        self.trust_store
+            .as_ref()
+            .is_some_and(|trust_store| trust_store.refuses("discovery", peer_or_device_id))
+    }
+    
+    /// Stream of device additions, updates and removals
+    pub fn subscribe_device_events(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.event_sender.subscribe()
+    }
+    
     pub fn get_devices(&self) -> Vec<Device> {
         self.devices
             .values()
@@ -96,11 +245,38 @@ impl NetworkDiscovery {
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs();
-            
+        
+        let event_sender = &self.event_sender;
         self.devices.retain(|_, device| {
-            now - device.last_seen < max_age_seconds
+            let keep = now.saturating_sub(device.last_seen) < max_age_seconds;
+            if !keep {
+                let mut removed: Device = device.clone().into();
+                removed.mark_offline();
+                let _ = event_sender.send(DeviceEvent::Removed(removed));
+            }
+            keep
         });
         
         tracing::debug!("Cleaned up old devices, {} remaining", self.devices.len());
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_discovery_reply_must_be_signed() {
+        let identity = DeviceIdentity::generate();
+        let reply = DiscoveryReply::new(&identity, "Bob's PC".to_string(), 8080, vec!["files".to_string()]);
+        
+        let info = reply.verify("10.0.0.3").unwrap();
+        assert_eq!(info.name, "Bob's PC");
+        assert_eq!(info.ip, "10.0.0.3");
+        assert_eq!(reply.device_id(), identity.device_id());
+        
+        let mut forged = reply.clone();
+        forged.name = "Bank Laptop".to_string();
+        assert!(forged.verify("10.0.0.3").is_err());
+    }
+}
@@ -11,6 +11,6 @@ pub type PeerId = String;
 
 // Re-export commonly used types
 pub use network::P2PNetwork;
-pub use discovery::NetworkDiscovery;
+pub use discovery::{DeviceEvent, DeviceInfo, DiscoveryReply, NetworkDiscovery};
 pub use signalling::SignalingServer;
-pub use transport::P2PTransport;
+pub use transport::{LanStream, P2PTransport, TcpTransport};
@@ -2,8 +2,19 @@
 // Handles data transfer between peers
 
 use tokio::sync::mpsc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
 use bytes::Bytes;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use anyhow::Error;
+
+use crate::error::DeskShareError;
+use crate::security::{DeviceIdentity, SecureChannel, SecurityConfig, TrustStore};
+
+/// Largest message accepted on a plaintext fallback connection
+const MAX_PLAINTEXT_MESSAGE: usize = 16 * 1024 * 1024;
 
 pub struct P2PTransport {
     connections: HashMap<String, Connection>,
@@ -70,4 +81,258 @@ impl Connection {
     pub fn peer_id(&self) -> &str {
         &self.peer_id
     }
-}
\ No newline at end of file
+}
+/// Direct TCP connections to other devices, encrypted unless the security
+/// config allows falling back to plaintext for peers that can't handshake
+pub struct TcpTransport {
+    identity: Arc<DeviceIdentity>,
+    config: SecurityConfig,
+    trust_store: Option<Arc<TrustStore>>,
+}
+
+/// One direct connection, as the handshake left it
+pub enum LanStream {
+    Secure(SecureChannel<TcpStream>),
+    Plain(TcpStream),
+}
+
+impl TcpTransport {
+    pub fn new(identity: Arc<DeviceIdentity>, config: SecurityConfig) -> Self {
+        Self { identity, config, trust_store: None }
+    }
+    
+    /// Check every authenticated peer's key against the trust records
+    pub fn with_trust_store(mut self, trust_store: Arc<TrustStore>) -> Self {
+        self.trust_store = Some(trust_store);
+        self
+    }
+    
+    pub async fn connect(&self, addr: SocketAddr) -> Result<LanStream, Error> {
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|e| DeskShareError::PeerConnectionFailed(format!("{}: {}", addr, e)))?;
+        
+        match SecureChannel::connect(stream, &self.identity).await {
+            Ok(channel) => {
+                self.observe(addr, &channel);
+                Ok(LanStream::Secure(channel))
+            }
+            Err(e) => {
+                tracing::warn!("Secure handshake with {} failed: {}", addr, e);
+                self.config.allow_plaintext(&format!("connection to {}", addr))?;
+                let stream = TcpStream::connect(addr)
+                    .await
+                    .map_err(|e| DeskShareError::PeerConnectionFailed(format!("{}: {}", addr, e)))?;
+                Ok(LanStream::Plain(stream))
+            }
+        }
+    }
+    
+    /// Accept the next connection; incoming connections are always encrypted
+    pub async fn accept(&self, listener: &TcpListener) -> Result<(LanStream, SocketAddr), Error> {
+        let (stream, addr) = listener.accept().await?;
+        let channel = SecureChannel::accept(stream, &self.identity).await?;
+        self.observe(addr, &channel);
+        Ok((LanStream::Secure(channel), addr))
+    }
+    
+    fn observe(&self, addr: SocketAddr, channel: &SecureChannel<TcpStream>) {
+        if let Some(trust_store) = &self.trust_store {
+            trust_store.observe_key(&addr.ip().to_string(), &channel.remote_public_key());
+        }
+    }
+}
+
+impl LanStream {
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, LanStream::Secure(_))
+    }
+    
+    /// Device id the other side authenticated as, if the stream is encrypted
+    pub fn remote_device_id(&self) -> Option<String> {
+        match self {
+            LanStream::Secure(channel) => Some(channel.remote_device_id()),
+            LanStream::Plain(_) => None,
+        }
+    }
+    
+    pub async fn send(&mut self, message: &[u8]) -> Result<(), Error> {
+        match self {
+            LanStream::Secure(channel) => channel.send(message).await,
+            LanStream::Plain(stream) => {
+                stream.write_all(&(message.len() as u32).to_be_bytes()).await?;
+                stream.write_all(message).await?;
+                Ok(())
+            }
+        }
+    }
+    
+    pub async fn recv(&mut self) -> Result<Vec<u8>, Error> {
+        match self {
+            LanStream::Secure(channel) => channel.recv().await,
+            LanStream::Plain(stream) => {
+                let mut len = [0u8; 4];
+                stream.read_exact(&mut len).await?;
+                let len = u32::from_be_bytes(len) as usize;
+                if len > MAX_PLAINTEXT_MESSAGE {
+                    return Err(DeskShareError::InvalidMessageFormat.into());
+                }
+                let mut message = vec![0u8; len];
+                stream.read_exact(&mut message).await?;
+                Ok(message)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::security::{PeerIdentity, TrustLevel};
+    use crate::services::chat::{ChatMessage, ChatPacket, DeliveryState};
+    
+    const CHAT_MARKER: &str = "MARKER-chat-body-7f3a91";
+    const FILE_MARKER: &[u8] = b"MARKER-file-bytes-c0ffee";
+    
+    /// Forwards a loopback connection to `target`, keeping every byte seen in
+    /// either direction
+    async fn capturing_proxy(target: SocketAddr) -> (SocketAddr, Arc<Mutex<Vec<u8>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        
+        let capture = captured.clone();
+        tokio::spawn(async move {
+            let (client, _) = listener.accept().await.unwrap();
+            let server = TcpStream::connect(target).await.unwrap();
+            let (client_read, client_write) = client.into_split();
+            let (server_read, server_write) = server.into_split();
+            tokio::spawn(pump(client_read, server_write, capture.clone()));
+            pump(server_read, client_write, capture).await;
+        });
+        
+        (addr, captured)
+    }
+    
+    async fn pump(
+        mut from: tokio::net::tcp::OwnedReadHalf,
+        mut to: tokio::net::tcp::OwnedWriteHalf,
+        captured: Arc<Mutex<Vec<u8>>>,
+    ) {
+        let mut buf = vec![0u8; 8192];
+        while let Ok(n) = from.read(&mut buf).await {
+            if n == 0 || to.write_all(&buf[..n]).await.is_err() {
+                break;
+            }
+            captured.lock().unwrap().extend_from_slice(&buf[..n]);
+        }
+    }
+    
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+    
+    #[tokio::test]
+    async fn test_loopback_capture_has_no_plaintext() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        let (proxy_addr, captured) = capturing_proxy(server_addr).await;
+        
+        let alice = Arc::new(DeviceIdentity::generate());
+        let bob = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default());
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = bob.accept(&listener).await.unwrap();
+            let chat = stream.recv().await.unwrap();
+            let chunk = stream.recv().await.unwrap();
+            (stream.remote_device_id(), chat, chunk)
+        });
+        
+        let mut stream = TcpTransport::new(alice.clone(), SecurityConfig::default())
+            .connect(proxy_addr)
+            .await
+            .unwrap();
+        assert!(stream.is_encrypted());
+        
+        let chat = serde_json::to_vec(&ChatPacket::Message(ChatMessage {
+            id: "m1".to_string(),
+            from: "alice".to_string(),
+            to: Some("bob".to_string()),
+            content: CHAT_MARKER.to_string(),
+            timestamp: 0,
+            attachment: None,
+            state: DeliveryState::Sent,
+        }))
+        .unwrap();
+        // Raw chunk data, spanning several Noise frames
+        let chunk = FILE_MARKER.repeat(4096);
+        stream.send(&chat).await.unwrap();
+        stream.send(&chunk).await.unwrap();
+        
+        let (device_id, received_chat, received_chunk) = server.await.unwrap();
+        assert_eq!(device_id, Some(alice.device_id()));
+        assert_eq!(received_chat, chat);
+        assert_eq!(received_chunk, chunk);
+        
+        let captured = captured.lock().unwrap();
+        assert!(captured.len() > chunk.len());
+        assert!(!contains(&captured, CHAT_MARKER.as_bytes()));
+        assert!(!contains(&captured, FILE_MARKER));
+    }
+    
+    #[tokio::test]
+    async fn test_plaintext_fallback_needs_opting_out() {
+        // A peer that doesn't speak the handshake
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.write_all(b"\x00\x05hello").await;
+            }
+        });
+        
+        let identity = Arc::new(DeviceIdentity::generate());
+        let err = TcpTransport::new(identity.clone(), SecurityConfig::default())
+            .connect(addr)
+            .await
+            .err()
+            .unwrap();
+        let err = err.downcast_ref::<DeskShareError>().unwrap();
+        assert_eq!(err.code(), "encryption_required");
+        
+        let stream = TcpTransport::new(identity, SecurityConfig { require_encryption: false, ..SecurityConfig::default() })
+            .connect(addr)
+            .await
+            .unwrap();
+        assert!(!stream.is_encrypted());
+        assert_eq!(stream.remote_device_id(), None);
+    }
+    
+    #[tokio::test]
+    async fn test_rotated_key_loses_pairing_on_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let paired = DeviceIdentity::generate();
+        let trust_store = Arc::new(TrustStore::new());
+        trust_store.record_paired(&paired.device_id(), "127.0.0.1", &paired.public_key());
+        let mut changes = trust_store.subscribe_identity_changes();
+        
+        // Whoever now answers at the paired address holds a different key
+        let rotated = Arc::new(DeviceIdentity::generate());
+        let rotated_fingerprint = rotated.fingerprint();
+        let server = TcpTransport::new(rotated, SecurityConfig::default());
+        tokio::spawn(async move { server.accept(&listener).await.map(|_| ()) });
+        
+        let transport = TcpTransport::new(Arc::new(DeviceIdentity::generate()), SecurityConfig::default())
+            .with_trust_store(trust_store.clone());
+        transport.connect(addr).await.unwrap();
+        
+        let change = changes.try_recv().unwrap();
+        assert_eq!(change.device_id, paired.device_id());
+        assert_eq!(change.previous_fingerprint, paired.fingerprint());
+        assert_eq!(change.new_fingerprint, rotated_fingerprint);
+        assert_eq!(trust_store.level(&paired.device_id()), TrustLevel::Unknown);
+        let at_paired_address = PeerIdentity { peer_id: "127.0.0.1".to_string(), device_id: paired.device_id() };
+        assert!(!trust_store.policy_for(&at_paired_address).skip_join_approval);
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::Arc;
+use anyhow::Error;
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+/// Every backend sends Opus at this rate, in stereo
+pub const AUDIO_SAMPLE_RATE: u32 = 48_000;
+pub const AUDIO_CHANNELS: usize = 2;
+
+/// Audio in each packet
+pub const AUDIO_PACKET_MS: u64 = 20;
+
+/// Samples per channel in each packet
+pub const AUDIO_PACKET_SAMPLES: usize = (AUDIO_SAMPLE_RATE as u64 * AUDIO_PACKET_MS / 1000) as usize;
+
+/// Packets waiting for the session to send them before newer ones are dropped
+const CAPTURE_QUEUE_DEPTH: usize = 16;
+
+/// One Opus packet of system audio
+#[derive(Clone, Debug)]
+pub struct AudioPacket {
+    pub data: Bytes,
+    /// When its first sample was played, on the same clock as frame
+    /// timestamps
+    pub timestamp_ms: u64,
+}
+
+/// System audio being captured; dropping it stops the capture
+pub struct AudioCapture {
+    packets: mpsc::Receiver<AudioPacket>,
+    stop: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl AudioCapture {
+    /// A capture fed through the returned sender, with `stop` called once
+    /// it's no longer wanted
+    pub fn new(stop: impl FnOnce() + Send + 'static) -> (mpsc::Sender<AudioPacket>, Self) {
+        let (sender, packets) = mpsc::channel(CAPTURE_QUEUE_DEPTH);
+        (sender, Self { packets, stop: Some(Box::new(stop)) })
+    }
+    
+    /// None once the capture has ended
+    pub async fn next(&mut self) -> Option<AudioPacket> {
+        self.packets.recv().await
+    }
+}
+
+impl Drop for AudioCapture {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop();
+        }
+    }
+}
+
+/// Plays the audio of a session we're watching
+pub trait AudioOutput: Send + Sync {
+    /// Queue one packet and return without waiting for it to play
+    fn play(&self, packet: &AudioPacket) -> Result<(), Error>;
+}
+
+/// Where session audio comes from and goes to; the screen share service
+/// only talks to this
+pub trait AudioBackend: Send + Sync {
+    /// Start capturing what the system plays. None where the platform
+    /// can't hear its own output, which leaves sessions video-only.
+    fn start_loopback(&self) -> Result<Option<AudioCapture>, Error> {
+        Ok(None)
+    }
+    
+    /// Somewhere to play received audio; None without an output device
+    fn open_output(&self) -> Result<Option<Arc<dyn AudioOutput>>, Error> {
+        Ok(None)
+    }
+}
+
+/// Neither captures nor plays anything
+pub struct NoAudio;
+
+impl AudioBackend for NoAudio {}
+
+/// Linear resampling to stereo at another rate, a chunk at a time: from
+/// whatever a device gives to what Opus takes, and back
+pub struct Resampler {
+    /// Input frames per output frame
+    step: f64,
+    channels: usize,
+    /// How far the next output frame is past `previous`, in input frames
+    position: f64,
+    previous: [f32; 2],
+    partial: ([f32; 2], usize),
+}
+
+impl Resampler {
+    pub fn new(from_rate: u32, from_channels: usize, to_rate: u32) -> Self {
+        Self {
+            step: from_rate as f64 / to_rate as f64,
+            channels: from_channels.max(1),
+            position: 1.0,
+            previous: [0.0; 2],
+            partial: ([0.0; 2], 0),
+        }
+    }
+    
+    /// Append the stereo frames `samples`, interleaved, come to. Mono is
+    /// doubled; past two channels only the first two are kept.
+    pub fn push(&mut self, samples: impl IntoIterator<Item = f32>, out: &mut Vec<f32>) {
+        for sample in samples {
+            let (frame, filled) = &mut self.partial;
+            if *filled < 2 {
+                frame[*filled] = sample;
+            }
+            *filled += 1;
+            if *filled < self.channels {
+                continue;
+            }
+            let mut frame = std::mem::take(&mut self.partial).0;
+            if self.channels == 1 {
+                frame[1] = frame[0];
+            }
+            
+            while self.position <= 1.0 {
+                let t = self.position as f32;
+                out.push(self.previous[0] + (frame[0] - self.previous[0]) * t);
+                out.push(self.previous[1] + (frame[1] - self.previous[1]) * t);
+                self.position += self.step;
+            }
+            self.position -= 1.0;
+            self.previous = frame;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_resampling_keeps_the_length_and_shape_of_the_sound() {
+        // One second of a 441Hz tone at CD rate, mono, in uneven chunks
+        let tone: Vec<f32> = (0..44_100).map(|i| (i as f32 * 441.0 * std::f32::consts::TAU / 44_100.0).sin()).collect();
+        let mut resampler = Resampler::new(44_100, 1, AUDIO_SAMPLE_RATE);
+        let mut stereo = Vec::new();
+        for chunk in tone.chunks(997) {
+            resampler.push(chunk.iter().copied(), &mut stereo);
+        }
+        let frames = stereo.len() / 2;
+        assert!(frames.abs_diff(AUDIO_SAMPLE_RATE as usize) <= 1, "{} frames", frames);
+        
+        for (i, frame) in stereo.chunks_exact(2).enumerate() {
+            assert_eq!(frame[0], frame[1]);
+            let expected = (i as f32 * 441.0 * std::f32::consts::TAU / AUDIO_SAMPLE_RATE as f32).sin();
+            assert!((frame[0] - expected).abs() < 0.01, "frame {}: {} against {}", i, frame[0], expected);
+        }
+        
+        // Surround comes down to its front pair
+        let mut resampler = Resampler::new(AUDIO_SAMPLE_RATE, 6, AUDIO_SAMPLE_RATE);
+        let mut stereo = Vec::new();
+        resampler.push([0.5, -0.5, 0.1, 0.1, 0.1, 0.1].repeat(3), &mut stereo);
+        assert_eq!(stereo, [0.5, -0.5, 0.5, -0.5, 0.5, -0.5]);
+    }
+}
@@ -1,13 +1,95 @@
+use std::sync::Arc;
 use anyhow::Error;
-use image::{ImageBuffer, Rgba};
+use async_trait::async_trait;
+use image::{ImageBuffer, Rgba, RgbaImage};
+
+use super::audio::{AudioCapture, AudioOutput};
+use super::cursor::Cursor;
+use super::input::InputAction;
+use super::{encode_jpeg, CaptureBackend, MonitorInfo};
+use crate::error::DeskShareError;
+
+/// Fake displays reported where no capture API is available
+pub fn list_monitors() -> Result<Vec<MonitorInfo>, Error> {
+    Ok(vec![
+        MonitorInfo {
+            id: 1,
+            name: "Fallback Primary".to_string(),
+            width: 1920,
+            height: 1080,
+            x: 0,
+            y: 0,
+            is_primary: true,
+            scale_factor: 1.0,
+        },
+        MonitorInfo {
+            id: 2,
+            name: "Fallback Secondary".to_string(),
+            width: 1280,
+            height: 1024,
+            x: 1920,
+            y: 0,
+            is_primary: false,
+            scale_factor: 1.0,
+        },
+    ])
+}
 
 /// Fallback screen capture for unsupported platforms
-pub async fn capture_screen(resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
+pub async fn capture_screen(
+    monitor_id: Option<u32>,
+    resolution: (u32, u32),
+    quality: u8,
+) -> Result<Vec<u8>, Error> {
+    encode_jpeg(&capture_raw(monitor_id, resolution)?, quality)
+}
+
+/// The test pattern, unencoded; it never changes between captures
+pub fn capture_raw(monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<RgbaImage, Error> {
+    if let Some(id) = monitor_id {
+        if !list_monitors()?.iter().any(|m| m.id == id) {
+            return Err(DeskShareError::MonitorNotFound(id).into());
+        }
+    }
+    
     Ok(generate_test_pattern(resolution))
 }
 
+/// The fake monitors have no cursor
+pub fn current_cursor() -> Result<Option<Cursor>, Error> {
+    Ok(None)
+}
+
+/// Nothing to hear without a real audio device
+pub fn start_loopback() -> Result<Option<AudioCapture>, Error> {
+    Ok(None)
+}
+
+pub fn open_audio_output() -> Result<Option<Arc<dyn AudioOutput>>, Error> {
+    Ok(None)
+}
+
+/// No desktop to play remote input into
+pub fn inject_input(_action: InputAction) -> Result<(), Error> {
+    Err(anyhow::anyhow!("Remote input is not supported on this platform"))
+}
+
+/// Capture backend producing test patterns for the fake monitors
+pub struct FallbackCapture;
+
+#[async_trait]
+impl CaptureBackend for FallbackCapture {
+    fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error> {
+        list_monitors()
+    }
+    
+    async fn capture_raw(&self, monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<RgbaImage, Error> {
+        capture_raw(monitor_id, resolution)
+    }
+}
+
 /// Generate test pattern
-fn generate_test_pattern(resolution: (u32, u32)) -> Vec<u8> {
+fn generate_test_pattern(resolution: (u32, u32)) -> RgbaImage {
     let (width, height) = resolution;
     let mut img = ImageBuffer::new(width, height);
     
@@ -18,10 +100,5 @@ fn generate_test_pattern(resolution: (u32, u32)) -> Vec<u8> {
         *pixel = Rgba([r, g, b, 255]);
     }
     
-    // Convert to JPEG
-    let mut buffer = Vec::new();
-    let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 70);
-    encoder.encode(&img, width, height, image::ColorType::Rgba8).unwrap();
-    
-    buffer
-}
\ No newline at end of file
+    img
+}
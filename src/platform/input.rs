@@ -0,0 +1,118 @@
+use anyhow::Error;
+use serde::{Serialize, Deserialize};
+
+use super::{CaptureRegion, MonitorInfo};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// A key as the viewer pressed it; characters are typed as themselves
+/// rather than by their position on the viewer's layout
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Key {
+    Char(char),
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    Space,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    ArrowUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    Shift,
+    Control,
+    Alt,
+    Meta,
+    /// F1 to F12
+    Function(u8),
+}
+
+/// Input to reproduce on this machine, positions in the same desktop
+/// coordinates as `MonitorInfo`'s
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputAction {
+    MoveTo { x: i32, y: i32 },
+    Button { button: MouseButton, pressed: bool },
+    /// In wheel notches; positive is right and down
+    Scroll { dx: i32, dy: i32 },
+    Key { key: Key, pressed: bool },
+}
+
+/// Where remote input is played back; the screen share service only talks
+/// to this
+pub trait InputBackend: Send + Sync {
+    fn inject(&self, action: InputAction) -> Result<(), Error>;
+}
+
+/// Refuses all input
+pub struct NoInput;
+
+impl InputBackend for NoInput {
+    fn inject(&self, _action: InputAction) -> Result<(), Error> {
+        Err(anyhow::anyhow!("Remote input is not supported on this platform"))
+    }
+}
+
+/// The desktop position of `(x, y)`, a point on a frame of `area` of
+/// `monitor` given as fractions of the frame's width and height. Points
+/// off the frame are pulled back to its edge.
+pub fn to_desktop(x: f32, y: f32, monitor: &MonitorInfo, area: CaptureRegion) -> (i32, i32) {
+    let scale = |fraction: f32, side: u32| {
+        let fraction = if fraction.is_nan() { 0.0 } else { fraction.clamp(0.0, 1.0) };
+        ((fraction * side as f32) as u32).min(side.saturating_sub(1)) as i32
+    };
+    (monitor.x + area.x as i32 + scale(x, area.width), monitor.y + area.y as i32 + scale(y, area.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn monitor(x: i32, y: i32) -> MonitorInfo {
+        MonitorInfo {
+            id: 2,
+            name: "Secondary".to_string(),
+            width: 1280,
+            height: 1024,
+            x,
+            y,
+            is_primary: false,
+            scale_factor: 1.0,
+        }
+    }
+    
+    #[test]
+    fn test_frame_points_land_on_the_captured_part_of_the_desktop() {
+        let right_of_primary = monitor(1920, 0);
+        let whole = CaptureRegion::whole(&right_of_primary);
+        assert_eq!(to_desktop(0.0, 0.0, &right_of_primary, whole), (1920, 0));
+        assert_eq!(to_desktop(0.5, 0.5, &right_of_primary, whole), (1920 + 640, 512));
+        // The far edge is the last pixel, not the next monitor's first
+        assert_eq!(to_desktop(1.0, 1.0, &right_of_primary, whole), (1920 + 1279, 1023));
+        
+        // Only part of the monitor shared: the frame's corners are the region's
+        let region = CaptureRegion { x: 600, y: 300, width: 200, height: 100 };
+        assert_eq!(to_desktop(0.0, 0.0, &right_of_primary, region), (2520, 300));
+        assert_eq!(to_desktop(0.25, 0.5, &right_of_primary, region), (2570, 350));
+        
+        // Monitors left of and above the primary sit at negative positions
+        let above_left = monitor(-1280, -1024);
+        assert_eq!(to_desktop(0.5, 0.25, &above_left, region), (-1280 + 700, -1024 + 325));
+        
+        // Nothing outside the frame reaches the rest of the desktop
+        assert_eq!(to_desktop(-0.5, 7.0, &right_of_primary, region), (2520, 399));
+        assert_eq!(to_desktop(f32::NAN, f32::INFINITY, &right_of_primary, region), (2520, 399));
+    }
+}
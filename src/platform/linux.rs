@@ -1,11 +1,21 @@
+use std::sync::{Arc, Mutex};
 use anyhow::Error;
-use image::{ImageBuffer, Rgba, DynamicImage};
+use image::{ImageBuffer, Rgba, RgbaImage, DynamicImage, GenericImageView};
 
 #[cfg(target_os = "linux")]
-use x11::xlib::{XOpenDisplay, XDefaultRootWindow, XGetImage, ZPixmap};
+use x11::xlib::{XOpenDisplay, XDefaultRootWindow, XGetImage, ZPixmap, XFree, Display};
+
+#[cfg(target_os = "linux")]
+use x11::xfixes::{XFixesGetCursorImage, XFixesCursorImage};
+
+use super::cursor::{Cursor, CursorImage};
 
 /// Capture the screen on Linux (X11 or Wayland)
-pub async fn capture_screen(resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
+pub async fn capture_screen(
+    monitor_id: Option<u32>,
+    resolution: (u32, u32),
+    quality: u8,
+) -> Result<Vec<u8>, Error> {
     // Detect display server
     let display_server = detect_display_server();
     
@@ -31,32 +41,32 @@ pub async fn capture_screen(resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
     
     
     // Fallback to xcap crate
-    match xcap::Monitor::all() {
-        Ok(monitors) => {
-            if let Some(monitor) = monitors.first() {
-                match monitor.capture_image() {
-                    Ok(image) => {
-                        let dynamic_image = image::DynamicImage::ImageRgba8(image);
-                        let (width, height) = dynamic_image.dimensions();
-                        
-                        // Resize if needed
-                        if width != resolution.0 || height != resolution.1 {
-                            let resized = dynamic_image.resize_exact(
-                                resolution.0,
-                                resolution.1,
-                                image::imageops::FilterType::Lanczos3
-                            );
-                            return encode_image(&resized);
-                        }
-                        
-                        return encode_image(&dynamic_image);
-                    }
-                    Err(e) => {
-                        tracing::warn!("XCap capture failed: {}, using test pattern", e);
+    match super::select_monitor(monitor_id) {
+        Ok(monitor) => {
+            match monitor.capture_image() {
+                Ok(image) => {
+                    let dynamic_image = image::DynamicImage::ImageRgba8(image);
+                    let (width, height) = dynamic_image.dimensions();
+                    
+                    // Resize if needed
+                    if width != resolution.0 || height != resolution.1 {
+                        let resized = dynamic_image.resize_exact(
+                            resolution.0,
+                            resolution.1,
+                            image::imageops::FilterType::Lanczos3
+                        );
+                        return encode_image(&resized, quality);
                     }
+                    
+                    return encode_image(&dynamic_image, quality);
+                }
+                Err(e) => {
+                    tracing::warn!("XCap capture failed: {}, using test pattern", e);
                 }
             }
         }
+        // A monitor the caller picked explicitly must exist
+        Err(e) if monitor_id.is_some() => return Err(e),
         Err(e) => {
             tracing::warn!("XCap monitor enumeration failed: {}, using test pattern", e);
         }
@@ -96,10 +106,77 @@ async fn capture_with_x11(resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
     Err(anyhow::anyhow!("X11 capture not fully implemented"))
 }
 
+/// X connection kept for cursor queries; opening one a frame costs more
+/// than the query itself
+struct CursorDisplay(*mut Display);
+
+// Only ever used under the mutex
+unsafe impl Send for CursorDisplay {}
+
+static CURSOR_DISPLAY: Mutex<Option<CursorDisplay>> = Mutex::new(None);
+
+/// Last cursor shape converted, by XFixes serial; shapes rarely change
+/// between frames
+static CURSOR_SHAPE: Mutex<Option<(u64, Arc<CursorImage>)>> = Mutex::new(None);
+
+/// Cursor position and shape through XFixes. None on Wayland, where
+/// clients aren't told where the pointer is.
+#[cfg(target_os = "linux")]
+pub fn current_cursor() -> Result<Option<Cursor>, Error> {
+    if detect_display_server() != "x11" {
+        return Ok(None);
+    }
+    
+    let mut display = CURSOR_DISPLAY.lock().unwrap();
+    if display.is_none() {
+        let opened = unsafe { XOpenDisplay(std::ptr::null()) };
+        if opened.is_null() {
+            return Err(anyhow::anyhow!("Couldn't open the X display"));
+        }
+        *display = Some(CursorDisplay(opened));
+    }
+    let raw = unsafe { XFixesGetCursorImage(display.as_ref().unwrap().0) };
+    if raw.is_null() {
+        return Ok(None);
+    }
+    
+    let (x, y, serial) = unsafe { ((*raw).x as i32, (*raw).y as i32, (*raw).cursor_serial as u64) };
+    let mut shape = CURSOR_SHAPE.lock().unwrap();
+    let image = match &*shape {
+        Some((cached, image)) if *cached == serial => image.clone(),
+        _ => {
+            let image = Arc::new(unsafe { cursor_image(&*raw) });
+            *shape = Some((serial, image.clone()));
+            image
+        }
+    };
+    unsafe { XFree(raw.cast()) };
+    
+    Ok(Some(Cursor { x, y, image }))
+}
+
+/// XFixes hands over premultiplied ARGB, a pixel to each c_ulong
+#[cfg(target_os = "linux")]
+unsafe fn cursor_image(raw: &XFixesCursorImage) -> CursorImage {
+    let (width, height) = (raw.width as u32, raw.height as u32);
+    let pixels = std::slice::from_raw_parts(raw.pixels, (width * height) as usize);
+    let mut image = RgbaImage::new(width, height);
+    for (pixel, argb) in image.pixels_mut().zip(pixels) {
+        let argb = *argb as u32;
+        let alpha = argb >> 24;
+        let channel = |shift: u32| match alpha {
+            0 => 0,
+            _ => (((argb >> shift) & 0xff) * 255 / alpha).min(255) as u8,
+        };
+        *pixel = Rgba([channel(16), channel(8), channel(0), alpha as u8]);
+    }
+    CursorImage { image, hotspot: (raw.xhot as u32, raw.yhot as u32) }
+}
+
 /// Encode image as JPEG
-fn encode_image(img: &DynamicImage) -> Result<Vec<u8>, Error> {
+fn encode_image(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, Error> {
     let mut buffer = Vec::new();
-    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 80);
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, quality);
     encoder.encode_image(img)?;
     Ok(buffer)
 }
@@ -119,7 +196,7 @@ fn generate_test_pattern(resolution: (u32, u32)) -> Vec<u8> {
     
     // Convert to JPEG
     let mut buffer = Vec::new();
-    let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 70);
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 70);
     encoder.encode(&img, width, height, image::ColorType::Rgba8).unwrap();
     
     buffer
@@ -137,7 +214,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_capture_screen() {
-        let result = capture_screen((1920, 1080)).await;
+        let result = capture_screen(None, (1920, 1080), crate::platform::DEFAULT_JPEG_QUALITY).await;
         assert!(result.is_ok());
         
         let data = result.unwrap();
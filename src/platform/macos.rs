@@ -1,14 +1,22 @@
 use anyhow::Error;
-use image::{ImageBuffer, Rgba, DynamicImage};
+use image::{ImageBuffer, Rgba, DynamicImage, GenericImageView};
 
 #[cfg(target_os = "macos")]
 use core_graphics::{
     display::{CGDisplay, CGDisplayStreamRef, CGDisplayStreamUpdateRef},
+    event::CGEvent,
+    event_source::{CGEventSource, CGEventSourceStateID},
     image::CGImageRef,
 };
 
+use super::cursor::{Cursor, CursorImage};
+
 /// Capture the screen on macOS using Core Graphics
-pub async fn capture_screen(resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
+pub async fn capture_screen(
+    monitor_id: Option<u32>,
+    resolution: (u32, u32),
+    quality: u8,
+) -> Result<Vec<u8>, Error> {
     #[cfg(target_os = "macos")]
     {
         match capture_with_core_graphics(resolution).await {
@@ -21,32 +29,32 @@ pub async fn capture_screen(resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
     
     
     // Fallback to xcap crate
-    match xcap::Monitor::all() {
-        Ok(monitors) => {
-            if let Some(monitor) = monitors.first() {
-                match monitor.capture_image() {
-                    Ok(image) => {
-                        let dynamic_image = image::DynamicImage::ImageRgba8(image);
-                        let (width, height) = dynamic_image.dimensions();
-                        
-                        // Resize if needed
-                        if width != resolution.0 || height != resolution.1 {
-                            let resized = dynamic_image.resize_exact(
-                                resolution.0,
-                                resolution.1,
-                                image::imageops::FilterType::Lanczos3
-                            );
-                            return encode_image(&resized);
-                        }
-                        
-                        return encode_image(&dynamic_image);
-                    }
-                    Err(e) => {
-                        tracing::warn!("XCap capture failed: {}, using test pattern", e);
+    match super::select_monitor(monitor_id) {
+        Ok(monitor) => {
+            match monitor.capture_image() {
+                Ok(image) => {
+                    let dynamic_image = image::DynamicImage::ImageRgba8(image);
+                    let (width, height) = dynamic_image.dimensions();
+                    
+                    // Resize if needed
+                    if width != resolution.0 || height != resolution.1 {
+                        let resized = dynamic_image.resize_exact(
+                            resolution.0,
+                            resolution.1,
+                            image::imageops::FilterType::Lanczos3
+                        );
+                        return encode_image(&resized, quality);
                     }
+                    
+                    return encode_image(&dynamic_image, quality);
+                }
+                Err(e) => {
+                    tracing::warn!("XCap capture failed: {}, using test pattern", e);
                 }
             }
         }
+        // A monitor the caller picked explicitly must exist
+        Err(e) if monitor_id.is_some() => return Err(e),
         Err(e) => {
             tracing::warn!("XCap monitor enumeration failed: {}, using test pattern", e);
         }
@@ -70,10 +78,26 @@ async fn capture_with_core_graphics(resolution: (u32, u32)) -> Result<Vec<u8>, E
     Err(anyhow::anyhow!("Core Graphics capture not fully implemented"))
 }
 
+/// Cursor position from a null CGEvent, drawn as the stock arrow. Core
+/// Graphics gives it in points, the same as the monitors' bounds.
+#[cfg(target_os = "macos")]
+pub fn current_cursor() -> Result<Option<Cursor>, Error> {
+    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState)
+        .map_err(|_| anyhow::anyhow!("Couldn't create a CGEventSource"))?;
+    let event = CGEvent::new(source).map_err(|_| anyhow::anyhow!("Couldn't create a CGEvent"))?;
+    let location = event.location();
+    
+    Ok(Some(Cursor {
+        x: location.x as i32,
+        y: location.y as i32,
+        image: CursorImage::arrow(),
+    }))
+}
+
 /// Encode image as JPEG
-fn encode_image(img: &DynamicImage) -> Result<Vec<u8>, Error> {
+fn encode_image(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, Error> {
     let mut buffer = Vec::new();
-    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 80);
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, quality);
     encoder.encode_image(img)?;
     Ok(buffer)
 }
@@ -93,7 +117,7 @@ fn generate_test_pattern(resolution: (u32, u32)) -> Vec<u8> {
     
     // Convert to JPEG
     let mut buffer = Vec::new();
-    let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 70);
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 70);
     encoder.encode(&img, width, height, image::ColorType::Rgba8).unwrap();
     
     buffer
@@ -105,7 +129,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_capture_screen() {
-        let result = capture_screen((1920, 1080)).await;
+        let result = capture_screen(None, (1920, 1080), crate::platform::DEFAULT_JPEG_QUALITY).await;
         assert!(result.is_ok());
         
         let data = result.unwrap();
@@ -1,3 +1,10 @@
+use anyhow::Error;
+use async_trait::async_trait;
+use image::RgbaImage;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+
 #[cfg(target_os = "windows")]
 pub mod windows;
 
@@ -16,8 +23,264 @@ pub use macos::capture_screen;
 #[cfg(target_os = "linux")]
 pub use linux::capture_screen;
 
-#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+#[cfg(target_os = "windows")]
+pub use windows::current_cursor;
+
+#[cfg(target_os = "macos")]
+pub use macos::current_cursor;
+
+#[cfg(target_os = "linux")]
+pub use linux::current_cursor;
+
+// Always built so tests can capture from fake monitors on every platform
 pub mod fallback;
 
+pub mod cursor;
+
+pub mod audio;
+
+pub mod input;
+
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+mod native_audio;
+
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+pub use native_audio::{open_audio_output, start_loopback};
+
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+mod native_input;
+
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+pub use native_input::inject_input;
+
 #[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
-pub use fallback::capture_screen;
\ No newline at end of file
+pub use fallback::{capture_raw, capture_screen, current_cursor, inject_input, list_monitors, open_audio_output, start_loopback};
+
+use audio::{AudioBackend, AudioCapture, AudioOutput};
+use input::{InputAction, InputBackend};
+use cursor::Cursor;
+
+/// JPEG quality used when the caller doesn't ask for one
+pub const DEFAULT_JPEG_QUALITY: u8 = 80;
+
+/// A display that can be selected for capture
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MonitorInfo {
+    pub id: u32,
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub x: i32,
+    pub y: i32,
+    pub is_primary: bool,
+    pub scale_factor: f32,
+}
+
+/// Part of a monitor to share instead of all of it, in pixels from the
+/// monitor's top-left corner
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl CaptureRegion {
+    /// All of `monitor`
+    pub fn whole(monitor: &MonitorInfo) -> Self {
+        Self { x: 0, y: 0, width: monitor.width, height: monitor.height }
+    }
+    
+    /// Check the region lies on `monitor`. Odd sizes lose their last row or
+    /// column, since video encoders work in 2x2 blocks.
+    pub fn fit(self, monitor: &MonitorInfo) -> Result<Self, DeskShareError> {
+        let region = Self { width: self.width & !1, height: self.height & !1, ..self };
+        let inside = region.x.checked_add(region.width).is_some_and(|right| right <= monitor.width)
+            && region.y.checked_add(region.height).is_some_and(|bottom| bottom <= monitor.height);
+        if region.width == 0 || region.height == 0 || !inside {
+            return Err(DeskShareError::InvalidCaptureRegion(format!(
+                "{}x{} at ({}, {}) on a {}x{} monitor",
+                self.width, self.height, self.x, self.y, monitor.width, monitor.height
+            )));
+        }
+        Ok(region)
+    }
+    
+    /// Size to send the region at: as it is, or shrunk to fit within
+    /// `bounds` keeping its shape, even either way
+    pub fn output_size(&self, bounds: (u32, u32)) -> (u32, u32) {
+        let scale = (bounds.0 as f64 / self.width as f64).min(bounds.1 as f64 / self.height as f64).min(1.0);
+        let even = |side: u32| ((side as f64 * scale) as u32).max(2) & !1;
+        (even(self.width), even(self.height))
+    }
+}
+
+/// The monitor `monitor_id` names, or the primary when None
+pub fn find_monitor(monitors: &[MonitorInfo], monitor_id: Option<u32>) -> Result<&MonitorInfo, Error> {
+    match monitor_id {
+        Some(id) => monitors.iter().find(|m| m.id == id).ok_or_else(|| DeskShareError::MonitorNotFound(id).into()),
+        None => monitors
+            .iter()
+            .find(|m| m.is_primary)
+            .or(monitors.first())
+            .ok_or_else(|| anyhow::anyhow!("No monitors found")),
+    }
+}
+
+/// Source of captured frames; the screen share service only talks to this
+#[async_trait]
+pub trait CaptureBackend: Send + Sync {
+    fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error>;
+    
+    /// Grab one unencoded frame from `monitor_id` (primary when None),
+    /// scaled to `resolution`
+    async fn capture_raw(&self, monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<RgbaImage, Error>;
+    
+    /// Grab `region` of `monitor_id` at the monitor's own size, shrunk to
+    /// fit within `resolution`
+    async fn capture_region(
+        &self,
+        monitor_id: Option<u32>,
+        region: CaptureRegion,
+        resolution: (u32, u32),
+    ) -> Result<RgbaImage, Error> {
+        let monitors = self.list_monitors()?;
+        let monitor = find_monitor(&monitors, monitor_id)?;
+        let region = region.fit(monitor)?;
+        let full = self.capture_raw(monitor_id, (monitor.width, monitor.height)).await?;
+        let cropped = image::imageops::crop_imm(&full, region.x, region.y, region.width, region.height).to_image();
+        let (width, height) = region.output_size(resolution);
+        if (width, height) == cropped.dimensions() {
+            return Ok(cropped);
+        }
+        Ok(image::imageops::resize(&cropped, width, height, image::imageops::FilterType::Triangle))
+    }
+    
+    /// Where the cursor is and what it looks like; None when it's hidden or
+    /// the platform can't say
+    fn cursor(&self) -> Result<Option<Cursor>, Error> {
+        Ok(None)
+    }
+    
+    /// Capture one JPEG frame from `monitor_id` (primary when None)
+    async fn capture(
+        &self,
+        monitor_id: Option<u32>,
+        resolution: (u32, u32),
+        quality: u8,
+    ) -> Result<Vec<u8>, Error> {
+        encode_jpeg(&self.capture_raw(monitor_id, resolution).await?, quality)
+    }
+}
+
+pub fn encode_jpeg(image: &RgbaImage, quality: u8) -> Result<Vec<u8>, Error> {
+    let mut buffer = Vec::new();
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, quality);
+    encoder.encode(image, image.width(), image.height(), image::ColorType::Rgba8)?;
+    Ok(buffer)
+}
+
+/// Capture backend for the platform we were built for
+pub struct NativeCapture;
+
+#[async_trait]
+impl CaptureBackend for NativeCapture {
+    fn list_monitors(&self) -> Result<Vec<MonitorInfo>, Error> {
+        list_monitors()
+    }
+    
+    async fn capture_raw(&self, monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<RgbaImage, Error> {
+        capture_raw(monitor_id, resolution)
+    }
+    
+    fn cursor(&self) -> Result<Option<Cursor>, Error> {
+        current_cursor()
+    }
+    
+    async fn capture(
+        &self,
+        monitor_id: Option<u32>,
+        resolution: (u32, u32),
+        quality: u8,
+    ) -> Result<Vec<u8>, Error> {
+        capture_screen(monitor_id, resolution, quality).await
+    }
+}
+
+/// Audio backend for the platform we were built for
+pub struct NativeAudio;
+
+impl AudioBackend for NativeAudio {
+    fn start_loopback(&self) -> Result<Option<AudioCapture>, Error> {
+        start_loopback()
+    }
+    
+    fn open_output(&self) -> Result<Option<std::sync::Arc<dyn AudioOutput>>, Error> {
+        open_audio_output()
+    }
+}
+
+/// Input injection for the platform we were built for
+pub struct NativeInput;
+
+impl InputBackend for NativeInput {
+    fn inject(&self, action: InputAction) -> Result<(), Error> {
+        inject_input(action)
+    }
+}
+
+/// Enumerate the attached displays
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+pub fn list_monitors() -> Result<Vec<MonitorInfo>, Error> {
+    xcap::Monitor::all()?
+        .iter()
+        .map(|monitor| {
+            Ok(MonitorInfo {
+                id: monitor.id()?,
+                name: monitor.name()?,
+                width: monitor.width()?,
+                height: monitor.height()?,
+                x: monitor.x()?,
+                y: monitor.y()?,
+                is_primary: monitor.is_primary()?,
+                scale_factor: monitor.scale_factor()?,
+            })
+        })
+        .collect()
+}
+
+/// Pick the xcap monitor to capture; the primary one when no id is given
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+pub(crate) fn select_monitor(monitor_id: Option<u32>) -> Result<xcap::Monitor, Error> {
+    let monitors = xcap::Monitor::all()?;
+    
+    let selected = match monitor_id {
+        Some(id) => monitors.into_iter().find(|m| m.id().ok() == Some(id)),
+        None => {
+            let primary = monitors.iter().position(|m| m.is_primary().unwrap_or(false));
+            let mut monitors = monitors;
+            match primary {
+                Some(index) => Some(monitors.swap_remove(index)),
+                None => monitors.into_iter().next(),
+            }
+        }
+    };
+    
+    selected.ok_or_else(|| match monitor_id {
+        Some(id) => crate::error::DeskShareError::MonitorNotFound(id).into(),
+        None => anyhow::anyhow!("No monitors found"),
+    })
+}
+
+/// Grab the selected monitor through xcap, scaled to `resolution`
+#[cfg(any(target_os = "windows", target_os = "macos", target_os = "linux"))]
+pub fn capture_raw(monitor_id: Option<u32>, resolution: (u32, u32)) -> Result<RgbaImage, Error> {
+    let image = select_monitor(monitor_id)?
+        .capture_image()
+        .map_err(|e| crate::error::DeskShareError::ScreenCaptureFailed(e.to_string()))?;
+    if image.dimensions() == resolution {
+        return Ok(image);
+    }
+    Ok(image::imageops::resize(&image, resolution.0, resolution.1, image::imageops::FilterType::Lanczos3))
+}
@@ -0,0 +1,275 @@
+use std::collections::VecDeque;
+use std::sync::{mpsc as std_mpsc, Arc, Mutex};
+use anyhow::Error;
+use bytes::Bytes;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{SampleFormat, StreamConfig, SupportedStreamConfig};
+use tokio::sync::mpsc;
+
+use super::audio::{
+    AudioCapture, AudioOutput, AudioPacket, Resampler, AUDIO_CHANNELS, AUDIO_PACKET_MS, AUDIO_PACKET_SAMPLES,
+    AUDIO_SAMPLE_RATE,
+};
+
+/// Room for the largest Opus packet 20ms of stereo comes to
+const MAX_PACKET_LEN: usize = 4000;
+
+/// Longest frame an Opus packet may decode to, 120ms, per channel
+const MAX_DECODED_SAMPLES: usize = AUDIO_SAMPLE_RATE as usize * 120 / 1000;
+
+/// Received audio queued beyond this is dropped, oldest first, so playback
+/// can't fall behind the picture
+const MAX_PLAYBACK_MS: usize = 200;
+
+/// A gap this long in what the device hands us (WASAPI loopback goes quiet
+/// with the system) restarts the packet clock
+const RESYNC_MS: u64 = 100;
+
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+/// WASAPI loops any output device back as an input
+#[cfg(target_os = "windows")]
+fn loopback_device(host: &cpal::Host) -> Option<(cpal::Device, SupportedStreamConfig)> {
+    let device = host.default_output_device()?;
+    let config = device.default_output_config().ok()?;
+    Some((device, config))
+}
+
+/// PulseAudio and PipeWire offer what each sink plays as a "monitor" source
+#[cfg(target_os = "linux")]
+fn loopback_device(host: &cpal::Host) -> Option<(cpal::Device, SupportedStreamConfig)> {
+    let device = host
+        .input_devices()
+        .ok()?
+        .find(|device| device.name().is_ok_and(|name| name.to_lowercase().contains("monitor")))?;
+    let config = device.default_input_config().ok()?;
+    Some((device, config))
+}
+
+/// Core Audio can't capture its own output without a virtual device
+#[cfg(target_os = "macos")]
+fn loopback_device(_host: &cpal::Host) -> Option<(cpal::Device, SupportedStreamConfig)> {
+    None
+}
+
+/// Capture what the system plays, as Opus packets; None without a loopback
+/// device
+pub fn start_loopback() -> Result<Option<AudioCapture>, Error> {
+    let Some((device, config)) = loopback_device(&cpal::default_host()) else {
+        return Ok(None);
+    };
+    let (stop_tx, stop_rx) = std_mpsc::channel::<()>();
+    let (packets, capture) = AudioCapture::new(move || {
+        let _ = stop_tx.send(());
+    });
+    
+    // Streams can't move between threads on every platform, so each lives
+    // on its own until the capture is dropped
+    let (started_tx, started_rx) = std_mpsc::channel();
+    std::thread::spawn(move || {
+        let stream = match capture_stream(&device, config, packets) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = started_tx.send(Err(e));
+                return;
+            }
+        };
+        let _ = started_tx.send(Ok(()));
+        let _ = stop_rx.recv();
+        drop(stream);
+    });
+    started_rx.recv()??;
+    
+    Ok(Some(capture))
+}
+
+fn capture_stream(
+    device: &cpal::Device,
+    config: SupportedStreamConfig,
+    packets: mpsc::Sender<AudioPacket>,
+) -> Result<cpal::Stream, Error> {
+    let format = config.sample_format();
+    let config: StreamConfig = config.into();
+    let mut encoder = PacketEncoder::new(&config, packets)?;
+    let on_error = |e: cpal::StreamError| tracing::warn!("System audio capture failed: {}", e);
+    let stream = match format {
+        SampleFormat::F32 => device.build_input_stream(
+            &config,
+            move |data: &[f32], _: &cpal::InputCallbackInfo| encoder.push(data.iter().copied()),
+            on_error,
+            None,
+        )?,
+        SampleFormat::I16 => device.build_input_stream(
+            &config,
+            move |data: &[i16], _: &cpal::InputCallbackInfo| encoder.push(data.iter().map(|s| *s as f32 / 32768.0)),
+            on_error,
+            None,
+        )?,
+        other => return Err(anyhow::anyhow!("Unsupported audio sample format {:?}", other)),
+    };
+    stream.play()?;
+    Ok(stream)
+}
+
+/// Turns what the device gives into 20ms Opus packets at 48kHz stereo,
+/// stamped from the sample count so they stay evenly spaced
+struct PacketEncoder {
+    encoder: opus::Encoder,
+    resampler: Resampler,
+    pending: Vec<f32>,
+    packets: mpsc::Sender<AudioPacket>,
+    /// When the packet being filled started
+    next_timestamp_ms: Option<u64>,
+}
+
+impl PacketEncoder {
+    fn new(config: &StreamConfig, packets: mpsc::Sender<AudioPacket>) -> Result<Self, Error> {
+        Ok(Self {
+            encoder: opus::Encoder::new(AUDIO_SAMPLE_RATE, opus::Channels::Stereo, opus::Application::Audio)?,
+            resampler: Resampler::new(config.sample_rate.0, config.channels as usize, AUDIO_SAMPLE_RATE),
+            pending: Vec::with_capacity(AUDIO_PACKET_SAMPLES * AUDIO_CHANNELS * 2),
+            packets,
+            next_timestamp_ms: None,
+        })
+    }
+    
+    fn push(&mut self, samples: impl Iterator<Item = f32>) {
+        let now = now_ms();
+        let next = match self.next_timestamp_ms {
+            Some(next) if now < next + RESYNC_MS => next,
+            // Never behind a packet already sent
+            previous => now.max(previous.unwrap_or(0)),
+        };
+        self.next_timestamp_ms = Some(next);
+        self.resampler.push(samples, &mut self.pending);
+        
+        let packet_len = AUDIO_PACKET_SAMPLES * AUDIO_CHANNELS;
+        while self.pending.len() >= packet_len {
+            let mut data = [0u8; MAX_PACKET_LEN];
+            let encoded = self.encoder.encode_float(&self.pending[..packet_len], &mut data);
+            self.pending.drain(..packet_len);
+            let timestamp_ms = self.next_timestamp_ms.unwrap();
+            self.next_timestamp_ms = Some(timestamp_ms + AUDIO_PACKET_MS);
+            match encoded {
+                // A session that's behind loses packets rather than holding up the device
+                Ok(len) => {
+                    let _ = self.packets.try_send(AudioPacket { data: Bytes::copy_from_slice(&data[..len]), timestamp_ms });
+                }
+                Err(e) => tracing::debug!("Audio packet not encoded: {}", e),
+            }
+        }
+    }
+}
+
+/// Plays into the default output device from a queue of its own samples
+struct NativeOutput {
+    decoder: Mutex<opus::Decoder>,
+    resampler: Mutex<Resampler>,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    channels: usize,
+    max_queued: usize,
+    /// Dropped with us, which ends the stream's thread
+    _stop: std_mpsc::Sender<()>,
+}
+
+impl AudioOutput for NativeOutput {
+    fn play(&self, packet: &AudioPacket) -> Result<(), Error> {
+        let mut decoded = vec![0f32; MAX_DECODED_SAMPLES * AUDIO_CHANNELS];
+        let samples = self.decoder.lock().unwrap().decode_float(&packet.data, &mut decoded, false)?;
+        let mut stereo = Vec::with_capacity(samples * AUDIO_CHANNELS);
+        self.resampler.lock().unwrap().push(decoded[..samples * AUDIO_CHANNELS].iter().copied(), &mut stereo);
+        
+        let mut queue = self.queue.lock().unwrap();
+        for frame in stereo.chunks_exact(2) {
+            match self.channels {
+                1 => queue.push_back((frame[0] + frame[1]) / 2.0),
+                channels => {
+                    queue.extend(frame);
+                    queue.extend(std::iter::repeat(0.0).take(channels - 2));
+                }
+            }
+        }
+        if queue.len() > self.max_queued {
+            let excess = queue.len() - self.max_queued;
+            queue.drain(..excess);
+        }
+        Ok(())
+    }
+}
+
+/// The default output device, ready for Opus packets; None without one
+pub fn open_audio_output() -> Result<Option<Arc<dyn AudioOutput>>, Error> {
+    let Some(device) = cpal::default_host().default_output_device() else {
+        return Ok(None);
+    };
+    let config = device.default_output_config()?;
+    let (format, config): (SampleFormat, StreamConfig) = (config.sample_format(), config.into());
+    let channels = config.channels as usize;
+    let queue = Arc::new(Mutex::new(VecDeque::new()));
+    
+    let (stop_tx, stop_rx) = std_mpsc::channel::<()>();
+    let (started_tx, started_rx) = std_mpsc::channel();
+    let playing = queue.clone();
+    let stream_config = config.clone();
+    std::thread::spawn(move || {
+        let stream = match playback_stream(&device, format, &stream_config, playing) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = started_tx.send(Err(e));
+                return;
+            }
+        };
+        let _ = started_tx.send(Ok(()));
+        let _ = stop_rx.recv();
+        drop(stream);
+    });
+    started_rx.recv()??;
+    
+    Ok(Some(Arc::new(NativeOutput {
+        decoder: Mutex::new(opus::Decoder::new(AUDIO_SAMPLE_RATE, opus::Channels::Stereo)?),
+        resampler: Mutex::new(Resampler::new(AUDIO_SAMPLE_RATE, AUDIO_CHANNELS, config.sample_rate.0)),
+        queue,
+        channels,
+        max_queued: config.sample_rate.0 as usize * channels * MAX_PLAYBACK_MS / 1000,
+        _stop: stop_tx,
+    })))
+}
+
+/// Silence whenever the queue runs dry
+fn playback_stream(
+    device: &cpal::Device,
+    format: SampleFormat,
+    config: &StreamConfig,
+    queue: Arc<Mutex<VecDeque<f32>>>,
+) -> Result<cpal::Stream, Error> {
+    let on_error = |e: cpal::StreamError| tracing::warn!("Audio playback failed: {}", e);
+    let stream = match format {
+        SampleFormat::F32 => device.build_output_stream(
+            config,
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let mut queue = queue.lock().unwrap();
+                for sample in data.iter_mut() {
+                    *sample = queue.pop_front().unwrap_or(0.0);
+                }
+            },
+            on_error,
+            None,
+        )?,
+        SampleFormat::I16 => device.build_output_stream(
+            config,
+            move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
+                let mut queue = queue.lock().unwrap();
+                for sample in data.iter_mut() {
+                    *sample = (queue.pop_front().unwrap_or(0.0).clamp(-1.0, 1.0) * 32767.0) as i16;
+                }
+            },
+            on_error,
+            None,
+        )?,
+        other => return Err(anyhow::anyhow!("Unsupported audio sample format {:?}", other)),
+    };
+    stream.play()?;
+    Ok(stream)
+}
@@ -0,0 +1,115 @@
+use std::sync::{mpsc as std_mpsc, Mutex, OnceLock};
+use anyhow::Error;
+use enigo::{Axis, Button, Coordinate, Direction, Enigo, Keyboard, Mouse, Settings};
+
+use super::input::{InputAction, Key, MouseButton};
+
+/// The thread playing input back; None once it couldn't start
+static INJECTOR: OnceLock<Option<Mutex<std_mpsc::Sender<InputAction>>>> = OnceLock::new();
+
+/// Play `action` back as if it came from this machine's own mouse or
+/// keyboard. It's queued, so a failure to inject is only logged.
+pub fn inject_input(action: InputAction) -> Result<(), Error> {
+    let injector = INJECTOR.get_or_init(|| start_injector().map(Mutex::new));
+    let Some(sender) = injector else {
+        return Err(anyhow::anyhow!("Input can't be injected on this desktop"));
+    };
+    sender.lock().unwrap().send(action).map_err(|_| anyhow::anyhow!("Input injection stopped"))
+}
+
+/// Enigo can't move between threads on every platform, so one thread
+/// owns it for the life of the process
+fn start_injector() -> Option<std_mpsc::Sender<InputAction>> {
+    let (actions_tx, actions) = std_mpsc::channel::<InputAction>();
+    let (started_tx, started_rx) = std_mpsc::channel();
+    std::thread::spawn(move || {
+        let mut enigo = match Enigo::new(&Settings::default()) {
+            Ok(enigo) => enigo,
+            Err(e) => {
+                let _ = started_tx.send(Err(e.to_string()));
+                return;
+            }
+        };
+        let _ = started_tx.send(Ok(()));
+        for action in actions {
+            if let Err(e) = play(&mut enigo, action) {
+                tracing::debug!("Remote input {:?} not injected: {}", action, e);
+            }
+        }
+    });
+    match started_rx.recv() {
+        Ok(Ok(())) => Some(actions_tx),
+        Ok(Err(e)) => {
+            tracing::warn!("Remote control unavailable: {}", e);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+fn play(enigo: &mut Enigo, action: InputAction) -> Result<(), enigo::InputError> {
+    let direction = |pressed: bool| if pressed { Direction::Press } else { Direction::Release };
+    match action {
+        InputAction::MoveTo { x, y } => enigo.move_mouse(x, y, Coordinate::Abs),
+        InputAction::Button { button, pressed } => {
+            let button = match button {
+                MouseButton::Left => Button::Left,
+                MouseButton::Right => Button::Right,
+                MouseButton::Middle => Button::Middle,
+            };
+            enigo.button(button, direction(pressed))
+        }
+        InputAction::Scroll { dx, dy } => {
+            if dx != 0 {
+                enigo.scroll(dx, Axis::Horizontal)?;
+            }
+            if dy != 0 {
+                enigo.scroll(dy, Axis::Vertical)?;
+            }
+            Ok(())
+        }
+        InputAction::Key { key, pressed } => match native_key(key) {
+            Some(key) => enigo.key(key, direction(pressed)),
+            None => Ok(()),
+        },
+    }
+}
+
+fn native_key(key: Key) -> Option<enigo::Key> {
+    Some(match key {
+        Key::Char(c) => enigo::Key::Unicode(c),
+        Key::Enter => enigo::Key::Return,
+        Key::Escape => enigo::Key::Escape,
+        Key::Backspace => enigo::Key::Backspace,
+        Key::Tab => enigo::Key::Tab,
+        Key::Space => enigo::Key::Space,
+        Key::Delete => enigo::Key::Delete,
+        Key::Home => enigo::Key::Home,
+        Key::End => enigo::Key::End,
+        Key::PageUp => enigo::Key::PageUp,
+        Key::PageDown => enigo::Key::PageDown,
+        Key::ArrowUp => enigo::Key::UpArrow,
+        Key::ArrowDown => enigo::Key::DownArrow,
+        Key::ArrowLeft => enigo::Key::LeftArrow,
+        Key::ArrowRight => enigo::Key::RightArrow,
+        Key::Shift => enigo::Key::Shift,
+        Key::Control => enigo::Key::Control,
+        Key::Alt => enigo::Key::Alt,
+        Key::Meta => enigo::Key::Meta,
+        Key::Function(n) => match n {
+            1 => enigo::Key::F1,
+            2 => enigo::Key::F2,
+            3 => enigo::Key::F3,
+            4 => enigo::Key::F4,
+            5 => enigo::Key::F5,
+            6 => enigo::Key::F6,
+            7 => enigo::Key::F7,
+            8 => enigo::Key::F8,
+            9 => enigo::Key::F9,
+            10 => enigo::Key::F10,
+            11 => enigo::Key::F11,
+            12 => enigo::Key::F12,
+            _ => return None,
+        },
+    })
+}
@@ -1,5 +1,5 @@
 use anyhow::Error;
-use image::{ImageBuffer, Rgba, DynamicImage};
+use image::{ImageBuffer, Rgba, DynamicImage, GenericImageView};
 use std::io::Cursor;
 
 // Windows Graphics Capture API types - currently using xcap fallback instead
@@ -7,9 +7,18 @@ use std::io::Cursor;
 #[cfg(target_os = "windows")]
 use windows::Graphics::Capture::GraphicsCaptureItem;
 
+#[cfg(target_os = "windows")]
+use windows::Win32::UI::WindowsAndMessaging::{GetCursorInfo, CURSORINFO, CURSOR_SHOWING};
+
+use super::cursor::{Cursor, CursorImage};
+
 /// Capture the screen on Windows using Graphics Capture API
 /// Falls back to screenshot crate if native API fails
-pub async fn capture_screen(resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
+pub async fn capture_screen(
+    monitor_id: Option<u32>,
+    resolution: (u32, u32),
+    quality: u8,
+) -> Result<Vec<u8>, Error> {
     // Try native Windows Graphics Capture API first
     #[cfg(target_os = "windows")]
     {
@@ -23,32 +32,32 @@ pub async fn capture_screen(resolution: (u32, u32)) -> Result<Vec<u8>, Error> {
     
     
     // Fallback to xcap crate
-    match xcap::Monitor::all() {
-        Ok(monitors) => {
-            if let Some(monitor) = monitors.first() {
-                match monitor.capture_image() {
-                    Ok(image) => {
-                        let dynamic_image = image::DynamicImage::ImageRgba8(image);
-                        let (width, height) = dynamic_image.dimensions();
-                        
-                        // Resize if needed
-                        if width != resolution.0 || height != resolution.1 {
-                            let resized = dynamic_image.resize_exact(
-                                resolution.0, 
-                                resolution.1, 
-                                image::imageops::FilterType::Lanczos3
-                            );
-                            return encode_image(&resized);
-                        }
-                        
-                        return encode_image(&dynamic_image);
-                    }
-                    Err(e) => {
-                        tracing::warn!("XCap capture failed: {}, using test pattern", e);
+    match super::select_monitor(monitor_id) {
+        Ok(monitor) => {
+            match monitor.capture_image() {
+                Ok(image) => {
+                    let dynamic_image = image::DynamicImage::ImageRgba8(image);
+                    let (width, height) = dynamic_image.dimensions();
+                    
+                    // Resize if needed
+                    if width != resolution.0 || height != resolution.1 {
+                        let resized = dynamic_image.resize_exact(
+                            resolution.0, 
+                            resolution.1, 
+                            image::imageops::FilterType::Lanczos3
+                        );
+                        return encode_image(&resized, quality);
                     }
+                    
+                    return encode_image(&dynamic_image, quality);
+                }
+                Err(e) => {
+                    tracing::warn!("XCap capture failed: {}, using test pattern", e);
                 }
             }
         }
+        // A monitor the caller picked explicitly must exist
+        Err(e) if monitor_id.is_some() => return Err(e),
         Err(e) => {
             tracing::warn!("XCap monitor enumeration failed: {}, using test pattern", e);
         }
@@ -74,10 +83,29 @@ async fn capture_with_graphics_api(_resolution: (u32, u32)) -> Result<Vec<u8>, E
     Err(anyhow::anyhow!("Windows Graphics Capture API not fully implemented"))
 }
 
+/// Cursor position through GetCursorInfo, drawn as the stock arrow
+#[cfg(target_os = "windows")]
+pub fn current_cursor() -> Result<Option<Cursor>, Error> {
+    let mut info = CURSORINFO {
+        cbSize: std::mem::size_of::<CURSORINFO>() as u32,
+        ..Default::default()
+    };
+    unsafe { GetCursorInfo(&mut info)? };
+    if info.flags.0 & CURSOR_SHOWING.0 == 0 {
+        return Ok(None);
+    }
+    
+    Ok(Some(Cursor {
+        x: info.ptScreenPos.x,
+        y: info.ptScreenPos.y,
+        image: CursorImage::arrow(),
+    }))
+}
+
 /// Encode image as JPEG with quality settings
-fn encode_image(img: &DynamicImage) -> Result<Vec<u8>, Error> {
+fn encode_image(img: &DynamicImage, quality: u8) -> Result<Vec<u8>, Error> {
     let mut buffer = Vec::new();
-    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, 80);
+    let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut buffer, quality);
     encoder.encode_image(img)?;
     Ok(buffer)
 }
@@ -109,7 +137,7 @@ mod tests {
     
     #[tokio::test]
     async fn test_capture_screen() {
-        let result = capture_screen((1920, 1080)).await;
+        let result = capture_screen(None, (1920, 1080), crate::platform::DEFAULT_JPEG_QUALITY).await;
         assert!(result.is_ok());
         
         let data = result.unwrap();
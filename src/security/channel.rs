@@ -0,0 +1,271 @@
+// Encrypted channels
+// Every direct exchange between devices runs over Noise XX. Each handshake
+// payload carries the sender's identity key and a signature over its Noise
+// static key, so a channel is bound to the device identities the trust store
+// knows about rather than to whatever address it came from.
+
+use std::time::Duration;
+use anyhow::Error;
+use serde::{Serialize, Deserialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::DeskShareError;
+use super::identity::DeviceIdentity;
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+const STATIC_KEY_CONTEXT: &[u8] = b"desk-share-net noise static key v1:";
+const DERIVED_KEY_CONTEXT: &str = "desk-share-net channel derived key v1";
+
+const MAX_NOISE_MESSAGE: usize = 65535;
+const TAG_LEN: usize = 16;
+/// Plaintext per frame once the continuation flag and AEAD tag fit
+const MAX_FRAME_PAYLOAD: usize = MAX_NOISE_MESSAGE - TAG_LEN - 1;
+/// Largest message either side will reassemble
+const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Transport security settings
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    /// Refuse any plaintext path instead of falling back to it
+    pub require_encryption: bool,
+    /// Only take file announcements from paired devices
+    pub strict_announcements: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            require_encryption: true,
+            strict_announcements: false,
+        }
+    }
+}
+
+impl SecurityConfig {
+    /// Check whether `path` may run unencrypted
+    pub fn allow_plaintext(&self, path: &str) -> Result<(), Error> {
+        if self.require_encryption {
+            return Err(DeskShareError::EncryptionRequired(path.to_string()).into());
+        }
+        tracing::warn!("Falling back to plaintext for {}", path);
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct IdentityPayload {
+    public_key: [u8; 32],
+    signature: Vec<u8>,
+}
+
+/// An authenticated, encrypted message stream to another device
+pub struct SecureChannel<S> {
+    stream: S,
+    noise: snow::TransportState,
+    remote_public_key: [u8; 32],
+    handshake_hash: Vec<u8>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> SecureChannel<S> {
+    /// Run the handshake as the side that opened the connection
+    pub async fn connect(stream: S, identity: &DeviceIdentity) -> Result<Self, Error> {
+        Self::handshake(stream, identity, true).await
+    }
+    
+    /// Run the handshake as the side that accepted the connection
+    pub async fn accept(stream: S, identity: &DeviceIdentity) -> Result<Self, Error> {
+        Self::handshake(stream, identity, false).await
+    }
+    
+    /// Identity key the other device proved it holds
+    pub fn remote_public_key(&self) -> [u8; 32] {
+        self.remote_public_key
+    }
+    
+    pub fn remote_device_id(&self) -> String {
+        DeviceIdentity::device_id_for(&self.remote_public_key)
+    }
+    
+    pub async fn send(&mut self, message: &[u8]) -> Result<(), Error> {
+        let mut buf = vec![0u8; MAX_NOISE_MESSAGE];
+        let mut plaintext = Vec::with_capacity(MAX_FRAME_PAYLOAD + 1);
+        let mut chunks = message.chunks(MAX_FRAME_PAYLOAD).peekable();
+        
+        // An empty message still goes out as a single frame
+        loop {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let more = chunks.peek().is_some();
+            plaintext.clear();
+            plaintext.push(more as u8);
+            plaintext.extend_from_slice(chunk);
+            
+            let len = self
+                .noise
+                .write_message(&plaintext, &mut buf)
+                .map_err(|e| DeskShareError::Internal(e.to_string()))?;
+            Self::write_frame(&mut self.stream, &buf[..len]).await?;
+            if !more {
+                return Ok(());
+            }
+        }
+    }
+    
+    pub async fn recv(&mut self) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0u8; MAX_NOISE_MESSAGE];
+        let mut message = Vec::new();
+        
+        loop {
+            let frame = Self::read_frame(&mut self.stream).await?;
+            let len = self
+                .noise
+                .read_message(&frame, &mut buf)
+                .map_err(|e| DeskShareError::DecryptionFailed(e.to_string()))?;
+            let (more, chunk) = match buf[..len].split_first() {
+                Some((flag, chunk)) => (*flag != 0, chunk),
+                None => return Err(DeskShareError::DecryptionFailed("empty frame".to_string()).into()),
+            };
+            
+            if message.len() + chunk.len() > MAX_MESSAGE_BYTES {
+                return Err(DeskShareError::DecryptionFailed("message too large".to_string()).into());
+            }
+            message.extend_from_slice(chunk);
+            if !more {
+                return Ok(message);
+            }
+        }
+    }
+    
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+    
+    async fn handshake(stream: S, identity: &DeviceIdentity, initiator: bool) -> Result<Self, Error> {
+        tokio::time::timeout(HANDSHAKE_TIMEOUT, Self::run_handshake(stream, identity, initiator))
+            .await
+            .map_err(|_| DeskShareError::HandshakeFailed("timed out".to_string()))?
+    }
+    
+    async fn run_handshake(mut stream: S, identity: &DeviceIdentity, initiator: bool) -> Result<Self, Error> {
+        let params: snow::params::NoiseParams = NOISE_PARAMS.parse().map_err(handshake_error)?;
+        let keypair = snow::Builder::new(params.clone()).generate_keypair().map_err(handshake_error)?;
+        let builder = snow::Builder::new(params).local_private_key(&keypair.private);
+        let mut noise = if initiator {
+            builder.build_initiator()
+        } else {
+            builder.build_responder()
+        }
+        .map_err(handshake_error)?;
+        
+        let payload = serde_json::to_vec(&IdentityPayload {
+            public_key: identity.public_key(),
+            signature: identity.sign(&Self::static_key_message(&keypair.public)).to_vec(),
+        })?;
+        let mut buf = vec![0u8; MAX_NOISE_MESSAGE];
+        
+        // XX: -> e; <- e, ee, s, es; -> s, se. Identities ride in the last two.
+        let remote_payload = if initiator {
+            let len = noise.write_message(&[], &mut buf).map_err(handshake_error)?;
+            Self::write_frame(&mut stream, &buf[..len]).await?;
+            
+            let frame = Self::read_frame(&mut stream).await.map_err(handshake_error)?;
+            let len = noise.read_message(&frame, &mut buf).map_err(handshake_error)?;
+            let remote_payload = buf[..len].to_vec();
+            
+            let len = noise.write_message(&payload, &mut buf).map_err(handshake_error)?;
+            Self::write_frame(&mut stream, &buf[..len]).await?;
+            remote_payload
+        } else {
+            let frame = Self::read_frame(&mut stream).await.map_err(handshake_error)?;
+            noise.read_message(&frame, &mut buf).map_err(handshake_error)?;
+            
+            let len = noise.write_message(&payload, &mut buf).map_err(handshake_error)?;
+            Self::write_frame(&mut stream, &buf[..len]).await?;
+            
+            let frame = Self::read_frame(&mut stream).await.map_err(handshake_error)?;
+            let len = noise.read_message(&frame, &mut buf).map_err(handshake_error)?;
+            buf[..len].to_vec()
+        };
+        
+        let remote_static = noise
+            .get_remote_static()
+            .ok_or_else(|| DeskShareError::HandshakeFailed("no remote static key".to_string()))?
+            .to_vec();
+        let remote_payload: IdentityPayload = serde_json::from_slice(&remote_payload).map_err(handshake_error)?;
+        let signature = <[u8; 64]>::try_from(remote_payload.signature.as_slice()).map_err(handshake_error)?;
+        if !DeviceIdentity::verify(&remote_payload.public_key, &Self::static_key_message(&remote_static), &signature) {
+            return Err(DeskShareError::HandshakeFailed("identity signature doesn't cover the channel key".to_string()).into());
+        }
+        
+        let handshake_hash = noise.get_handshake_hash().to_vec();
+        let noise = noise.into_transport_mode().map_err(handshake_error)?;
+        Ok(Self {
+            stream,
+            noise,
+            remote_public_key: remote_payload.public_key,
+            handshake_hash,
+        })
+    }
+    
+    fn static_key_message(static_key: &[u8]) -> Vec<u8> {
+        [STATIC_KEY_CONTEXT, static_key].concat()
+    }
+    
+    async fn write_frame(stream: &mut S, frame: &[u8]) -> Result<(), Error> {
+        stream.write_all(&(frame.len() as u16).to_be_bytes()).await?;
+        stream.write_all(frame).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+    
+    async fn read_frame(stream: &mut S) -> Result<Vec<u8>, Error> {
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await?;
+        let mut frame = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut frame).await?;
+        Ok(frame)
+    }
+}
+
+impl<S> SecureChannel<S> {
+    /// Key only the two ends of this channel can compute, bound to `purpose`
+    pub fn derive_key(&self, purpose: &[u8]) -> [u8; 32] {
+        blake3::derive_key(DERIVED_KEY_CONTEXT, &[self.handshake_hash.as_slice(), purpose].concat())
+    }
+}
+
+fn handshake_error(e: impl std::fmt::Display) -> Error {
+    DeskShareError::HandshakeFailed(e.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test]
+    async fn test_channel_authenticates_both_identities() {
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        let alice = DeviceIdentity::generate();
+        let bob = DeviceIdentity::generate();
+        let bob_key = bob.public_key();
+        
+        let server = tokio::spawn(async move {
+            let mut channel = SecureChannel::accept(server_io, &bob).await.unwrap();
+            let request = channel.recv().await.unwrap();
+            channel.send(&request).await.unwrap();
+            (channel.remote_device_id(), channel.derive_key(b"purpose"))
+        });
+        
+        let mut channel = SecureChannel::connect(client_io, &alice).await.unwrap();
+        // Larger than a single Noise message
+        let message: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        channel.send(&message).await.unwrap();
+        assert_eq!(channel.recv().await.unwrap(), message);
+        
+        let (remote_device_id, server_key) = server.await.unwrap();
+        assert_eq!(remote_device_id, alice.device_id());
+        assert_eq!(channel.remote_public_key(), bob_key);
+        assert_eq!(channel.derive_key(b"purpose"), server_key);
+        assert_ne!(channel.derive_key(b"other purpose"), server_key);
+    }
+}
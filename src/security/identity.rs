@@ -0,0 +1,108 @@
+// Persistent device identity
+// An Ed25519 key pair generated on first run; the device id is derived from
+// the public key so it can't be claimed without holding the key.
+
+use std::path::Path;
+use anyhow::Error;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+/// Bytes of the public key hash used as the device id
+const DEVICE_ID_BYTES: usize = 16;
+const FINGERPRINT_CONTEXT: &str = "desk-share-net identity fingerprint v1";
+
+pub struct DeviceIdentity {
+    signing_key: SigningKey,
+}
+
+impl DeviceIdentity {
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut rand::rngs::OsRng),
+        }
+    }
+    
+    /// Load the key stored at `path`, creating it on first run
+    pub fn load_or_generate(path: &Path) -> Result<Self, Error> {
+        if let Ok(bytes) = std::fs::read(path) {
+            let secret: [u8; 32] = bytes
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("identity key at {} is corrupt", path.display()))?;
+            return Ok(Self { signing_key: SigningKey::from_bytes(&secret) });
+        }
+        
+        let identity = Self::generate();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, identity.signing_key.to_bytes())?;
+        tracing::info!("Generated device identity {}", identity.device_id());
+        Ok(identity)
+    }
+    
+    pub fn public_key(&self) -> [u8; 32] {
+        self.signing_key.verifying_key().to_bytes()
+    }
+    
+    pub fn device_id(&self) -> String {
+        Self::device_id_for(&self.public_key())
+    }
+    
+    /// Device id belonging to a peer's public key
+    pub fn device_id_for(public_key: &[u8; 32]) -> String {
+        hex::encode(&blake3::hash(public_key).as_bytes()[..DEVICE_ID_BYTES])
+    }
+    
+    pub fn fingerprint(&self) -> String {
+        Self::fingerprint_for(&self.public_key())
+    }
+    
+    /// What users read to each other to check a key: 8 groups of 4 hex digits
+    pub fn fingerprint_for(public_key: &[u8; 32]) -> String {
+        let digest = blake3::derive_key(FINGERPRINT_CONTEXT, public_key);
+        digest[..16]
+            .chunks(2)
+            .map(hex::encode)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+    
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        self.signing_key.sign(message).to_bytes()
+    }
+    
+    pub fn verify(public_key: &[u8; 32], message: &[u8], signature: &[u8; 64]) -> bool {
+        VerifyingKey::from_bytes(public_key)
+            .map(|key| key.verify(message, &Signature::from_bytes(signature)).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_identity_persists_and_signs() {
+        let path = std::env::temp_dir().join(format!("dsn-identity-{}/identity.key", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        
+        let first = DeviceIdentity::load_or_generate(&path).unwrap();
+        let second = DeviceIdentity::load_or_generate(&path).unwrap();
+        assert_eq!(first.device_id(), second.device_id());
+        assert_eq!(first.device_id().len(), DEVICE_ID_BYTES * 2);
+        
+        let fingerprint = first.fingerprint();
+        let groups: Vec<&str> = fingerprint.split(' ').collect();
+        assert_eq!(groups.len(), 8);
+        assert!(groups.iter().all(|group| group.len() == 4 && group.chars().all(|c| c.is_ascii_hexdigit())));
+        assert_eq!(fingerprint, second.fingerprint());
+        assert_ne!(fingerprint, DeviceIdentity::generate().fingerprint());
+        
+        let signature = first.sign(b"hello");
+        assert!(DeviceIdentity::verify(&second.public_key(), b"hello", &signature));
+        assert!(!DeviceIdentity::verify(&DeviceIdentity::generate().public_key(), b"hello", &signature));
+        
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,17 @@
+// Security module
+// Device identity, trust records, pairing and encrypted channels between devices
+
+pub mod channel;
+pub mod identity;
+pub mod pairing;
+pub mod rate_limit;
+pub mod sanitize;
+pub mod trust;
+
+// Re-export commonly used types
+pub use channel::{SecureChannel, SecurityConfig};
+pub use identity::DeviceIdentity;
+pub use pairing::{PairingEvent, PairingHandle, PairingManager, PairingReply, PairingRequest, PairingReveal, PairingTransport};
+pub use rate_limit::{ProtocolClass, RateLimit, RateLimitConfig, RateLimitEvent, RateLimiter, RateLimiterState};
+pub use sanitize::{resolve_remote_path, sanitize_remote_path};
+pub use trust::{IdentityChange, PeerIdentity, PeerMute, TrustLevel, TrustPolicy, TrustRecord, TrustStore};
@@ -0,0 +1,578 @@
+// Device pairing
+// The devices run an ephemeral X25519 exchange and show a 6-digit code
+// derived from the shared secret and both identity keys; each side signs its
+// ephemeral key with its identity key. The initiator commits to its ephemeral
+// key before seeing the responder's, so a man in the middle can't steer both
+// exchanges to the same code. Once both users confirm that the codes match,
+// each side records the other as Paired.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use anyhow::Error;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::error::DeskShareError;
+use super::identity::DeviceIdentity;
+use super::trust::TrustStore;
+
+/// How long a pairing may wait for both confirmations
+const DEFAULT_PAIRING_TIMEOUT: Duration = Duration::from_secs(120);
+
+const SAS_CONTEXT: &str = "desk-share-net pairing SAS v2";
+const COMMITMENT_CONTEXT: &str = "desk-share-net pairing commitment v2";
+const TRANSCRIPT_CONTEXT: &str = "desk-share-net pairing transcript v2";
+
+/// Sent by the device that starts pairing
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PairingRequest {
+    pub request_id: String,
+    /// Address to send the confirmation back to
+    pub peer_id: String,
+    pub device_id: String,
+    pub public_key: [u8; 32],
+    /// Hash of the initiator's ephemeral key and nonce, opened by the reveal
+    pub commitment: [u8; 32],
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PairingReply {
+    pub device_id: String,
+    pub public_key: [u8; 32],
+    pub ephemeral_key: [u8; 32],
+    /// Identity signature over the request, commitment and ephemeral key
+    pub signature: Vec<u8>,
+}
+
+/// Sent by the initiator once it has the responder's ephemeral key
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PairingReveal {
+    pub request_id: String,
+    pub peer_id: String,
+    pub ephemeral_key: [u8; 32],
+    pub nonce: [u8; 32],
+    /// Identity signature over the request and both ephemeral keys
+    pub signature: Vec<u8>,
+}
+
+/// Returned to the initiator; `code` is what both screens show
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PairingHandle {
+    pub request_id: String,
+    pub device_id: String,
+    pub code: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PairingEvent {
+    /// Another device wants to pair with us
+    Requested { request_id: String, peer_id: String, device_id: String, code: String },
+    /// Pairing finished on this side, either way
+    Completed {
+        request_id: String,
+        peer_id: String,
+        device_id: String,
+        paired: bool,
+        reason: Option<String>,
+    },
+}
+
+/// Carries pairing messages between devices
+#[async_trait]
+pub trait PairingTransport: Send + Sync {
+    async fn send_request(&self, peer_id: &str, request: PairingRequest) -> Result<PairingReply, Error>;
+    
+    async fn send_reveal(&self, peer_id: &str, reveal: PairingReveal) -> Result<(), Error>;
+    
+    async fn send_confirm(&self, peer_id: &str, from_peer_id: &str, request_id: &str, accept: bool) -> Result<(), Error>;
+}
+
+struct PendingPairing {
+    peer_id: String,
+    device_id: String,
+    public_key: [u8; 32],
+    /// Responder side until the reveal arrives
+    exchange: Option<ResponderExchange>,
+    /// Whether the code has been shown; nothing can be confirmed before that
+    code_shown: bool,
+    local_accept: Option<bool>,
+    remote_accept: Option<bool>,
+}
+
+struct ResponderExchange {
+    commitment: [u8; 32],
+    secret: EphemeralSecret,
+    ephemeral_key: [u8; 32],
+}
+
+pub struct PairingManager {
+    identity: Arc<DeviceIdentity>,
+    trust_store: Arc<TrustStore>,
+    local_peer_id: String,
+    transport: Option<Arc<dyn PairingTransport>>,
+    pending: Arc<DashMap<String, PendingPairing>>,
+    event_tx: broadcast::Sender<PairingEvent>,
+    timeout_ms: Arc<AtomicU64>,
+}
+
+impl PairingManager {
+    pub fn new(identity: Arc<DeviceIdentity>, trust_store: Arc<TrustStore>) -> Self {
+        let (event_tx, _) = broadcast::channel(32);
+        Self {
+            identity,
+            trust_store,
+            local_peer_id: "local".to_string(),
+            transport: None,
+            pending: Arc::new(DashMap::new()),
+            event_tx,
+            timeout_ms: Arc::new(AtomicU64::new(DEFAULT_PAIRING_TIMEOUT.as_millis() as u64)),
+        }
+    }
+    
+    pub fn with_transport(mut self, local_peer_id: String, transport: Arc<dyn PairingTransport>) -> Self {
+        self.local_peer_id = local_peer_id;
+        self.transport = Some(transport);
+        self
+    }
+    
+    pub fn subscribe(&self) -> broadcast::Receiver<PairingEvent> {
+        self.event_tx.subscribe()
+    }
+    
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.timeout_ms.store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+    
+    /// Start pairing with the device at `peer_id`
+    pub async fn request_pairing(&self, peer_id: &str) -> Result<PairingHandle, Error> {
+        let transport = self
+            .transport
+            .clone()
+            .ok_or_else(|| DeskShareError::PeerConnectionFailed("no pairing transport".to_string()))?;
+        
+        let request_id = Self::generate_request_id();
+        let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let ephemeral_key = PublicKey::from(&secret).to_bytes();
+        let nonce: [u8; 32] = rand::random();
+        let commitment = Self::commit(&ephemeral_key, &nonce);
+        let request = PairingRequest {
+            request_id: request_id.clone(),
+            peer_id: self.local_peer_id.clone(),
+            device_id: self.identity.device_id(),
+            public_key: self.identity.public_key(),
+            commitment,
+        };
+        
+        let reply = transport
+            .send_request(peer_id, request)
+            .await
+            .map_err(|e| DeskShareError::PeerConnectionFailed(e.to_string()))?;
+        Self::check_device_id(&reply.device_id, &reply.public_key)?;
+        let transcript = Self::transcript(&request_id, "responder", &commitment, &reply.ephemeral_key);
+        Self::check_signature(&reply.public_key, &transcript, &reply.signature)?;
+        self.trust_store.observe_key(peer_id, &reply.public_key);
+        
+        let shared = secret.diffie_hellman(&PublicKey::from(reply.ephemeral_key));
+        let code = Self::derive_code(
+            shared.as_bytes(),
+            (&self.identity.public_key(), &ephemeral_key),
+            (&reply.public_key, &reply.ephemeral_key),
+        );
+        
+        // Registered before the reveal so an early answer from the peer isn't lost
+        self.insert_pending(&request_id, PendingPairing {
+            peer_id: peer_id.to_string(),
+            device_id: reply.device_id.clone(),
+            public_key: reply.public_key,
+            exchange: None,
+            code_shown: true,
+            local_accept: None,
+            remote_accept: None,
+        });
+        
+        let transcript = Self::transcript(&request_id, "initiator", &ephemeral_key, &reply.ephemeral_key);
+        let reveal = PairingReveal {
+            request_id: request_id.clone(),
+            peer_id: self.local_peer_id.clone(),
+            ephemeral_key,
+            nonce,
+            signature: self.identity.sign(&transcript).to_vec(),
+        };
+        if let Err(e) = transport.send_reveal(peer_id, reveal).await {
+            Self::finish(&self.pending, &self.event_tx, &request_id, false, Some("peer unreachable".to_string()));
+            return Err(DeskShareError::PeerConnectionFailed(e.to_string()).into());
+        }
+        
+        Ok(PairingHandle {
+            request_id,
+            device_id: reply.device_id,
+            code,
+        })
+    }
+    
+    /// Answer a pairing request from another device with our ephemeral key;
+    /// the code is shown once the initiator reveals its own
+    pub fn handle_request(&self, request: PairingRequest) -> Result<PairingReply, Error> {
+        Self::check_device_id(&request.device_id, &request.public_key)?;
+        self.trust_store.observe_key(&request.peer_id, &request.public_key);
+        
+        let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let ephemeral_key = PublicKey::from(&secret).to_bytes();
+        let transcript = Self::transcript(&request.request_id, "responder", &request.commitment, &ephemeral_key);
+        
+        self.insert_pending(&request.request_id, PendingPairing {
+            peer_id: request.peer_id,
+            device_id: request.device_id,
+            public_key: request.public_key,
+            exchange: Some(ResponderExchange {
+                commitment: request.commitment,
+                secret,
+                ephemeral_key,
+            }),
+            code_shown: false,
+            local_accept: None,
+            remote_accept: None,
+        });
+        
+        Ok(PairingReply {
+            device_id: self.identity.device_id(),
+            public_key: self.identity.public_key(),
+            ephemeral_key,
+            signature: self.identity.sign(&transcript).to_vec(),
+        })
+    }
+    
+    /// Check the initiator's reveal against its commitment and show the code.
+    /// A reveal that doesn't verify ends the pairing.
+    pub fn handle_reveal(&self, reveal: PairingReveal) -> Result<(), Error> {
+        let (exchange, public_key, device_id) = {
+            let mut pending = match self.pending.get_mut(&reveal.request_id) {
+                Some(pending) if pending.peer_id == reveal.peer_id => pending,
+                _ => return Err(DeskShareError::PairingRequestNotFound(reveal.request_id).into()),
+            };
+            let exchange = pending
+                .exchange
+                .take()
+                .ok_or_else(|| DeskShareError::PairingFailed("duplicate reveal".to_string()))?;
+            (exchange, pending.public_key, pending.device_id.clone())
+        };
+        
+        // blake3::Hash compares in constant time
+        let opened = blake3::Hash::from(Self::commit(&reveal.ephemeral_key, &reveal.nonce));
+        let transcript = Self::transcript(&reveal.request_id, "initiator", &reveal.ephemeral_key, &exchange.ephemeral_key);
+        let verified = if opened != blake3::Hash::from(exchange.commitment) {
+            Err(DeskShareError::PairingFailed("reveal doesn't match commitment".to_string()).into())
+        } else {
+            Self::check_signature(&public_key, &transcript, &reveal.signature)
+        };
+        if let Err(e) = verified {
+            tracing::warn!("Pairing {} from {} failed verification: {}", reveal.request_id, reveal.peer_id, e);
+            Self::finish(&self.pending, &self.event_tx, &reveal.request_id, false, Some("verification failed".to_string()));
+            return Err(e);
+        }
+        
+        let shared = exchange.secret.diffie_hellman(&PublicKey::from(reveal.ephemeral_key));
+        let code = Self::derive_code(
+            shared.as_bytes(),
+            (&public_key, &reveal.ephemeral_key),
+            (&self.identity.public_key(), &exchange.ephemeral_key),
+        );
+        match self.pending.get_mut(&reveal.request_id) {
+            Some(mut pending) => pending.code_shown = true,
+            None => return Err(DeskShareError::PairingRequestNotFound(reveal.request_id).into()),
+        }
+        
+        let _ = self.event_tx.send(PairingEvent::Requested {
+            request_id: reveal.request_id,
+            peer_id: reveal.peer_id,
+            device_id,
+            code,
+        });
+        Ok(())
+    }
+    
+    /// The local user compared the codes and accepted or rejected
+    pub async fn confirm_pairing(&self, request_id: &str, accept: bool) -> Result<(), Error> {
+        let peer_id = {
+            let mut pending = self
+                .pending
+                .get_mut(request_id)
+                .filter(|pending| pending.code_shown)
+                .ok_or_else(|| DeskShareError::PairingRequestNotFound(request_id.to_string()))?;
+            pending.local_accept = Some(accept);
+            pending.peer_id.clone()
+        };
+        
+        if let Some(transport) = &self.transport {
+            if let Err(e) = transport.send_confirm(&peer_id, &self.local_peer_id, request_id, accept).await {
+                Self::finish(&self.pending, &self.event_tx, request_id, false, Some("peer unreachable".to_string()));
+                return Err(DeskShareError::PeerConnectionFailed(e.to_string()).into());
+            }
+        }
+        
+        self.settle(request_id);
+        Ok(())
+    }
+    
+    /// The other device's user accepted or rejected
+    pub fn handle_confirm(&self, from_peer_id: &str, request_id: &str, accept: bool) {
+        match self.pending.get_mut(request_id) {
+            Some(mut pending) if pending.peer_id == from_peer_id => {
+                pending.remote_accept = Some(accept);
+            }
+            Some(_) => {
+                tracing::warn!("Pairing confirmation for {} from unexpected peer {}", request_id, from_peer_id);
+                return;
+            }
+            None => return,
+        }
+        
+        self.settle(request_id);
+    }
+    
+    /// Complete the pairing once both sides have answered
+    fn settle(&self, request_id: &str) {
+        let decision = match self.pending.get(request_id) {
+            Some(pending) => match (pending.local_accept, pending.remote_accept) {
+                (Some(false), _) => Some((false, "rejected locally")),
+                (_, Some(false)) => Some((false, "rejected by peer")),
+                (Some(true), Some(true)) => {
+                    self.trust_store.record_paired(&pending.device_id, &pending.peer_id, &pending.public_key);
+                    Some((true, ""))
+                }
+                _ => None,
+            },
+            None => None,
+        };
+        
+        if let Some((paired, reason)) = decision {
+            let reason = (!paired).then(|| reason.to_string());
+            Self::finish(&self.pending, &self.event_tx, request_id, paired, reason);
+        }
+    }
+    
+    fn insert_pending(&self, request_id: &str, pending: PendingPairing) {
+        self.pending.insert(request_id.to_string(), pending);
+        
+        // Drop the pairing if it isn't settled in time
+        let pending = self.pending.clone();
+        let event_tx = self.event_tx.clone();
+        let request_id = request_id.to_string();
+        let timeout = Duration::from_millis(self.timeout_ms.load(Ordering::Relaxed));
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            Self::finish(&pending, &event_tx, &request_id, false, Some("timed out".to_string()));
+        });
+    }
+    
+    fn finish(
+        pending: &DashMap<String, PendingPairing>,
+        event_tx: &broadcast::Sender<PairingEvent>,
+        request_id: &str,
+        paired: bool,
+        reason: Option<String>,
+    ) {
+        if let Some((request_id, pending)) = pending.remove(request_id) {
+            let _ = event_tx.send(PairingEvent::Completed {
+                request_id,
+                peer_id: pending.peer_id,
+                device_id: pending.device_id,
+                paired,
+                reason,
+            });
+        }
+    }
+    
+    fn check_device_id(device_id: &str, public_key: &[u8; 32]) -> Result<(), Error> {
+        if DeviceIdentity::device_id_for(public_key) != device_id {
+            return Err(DeskShareError::PairingFailed("device id doesn't match its key".to_string()).into());
+        }
+        Ok(())
+    }
+    
+    fn check_signature(public_key: &[u8; 32], transcript: &[u8], signature: &[u8]) -> Result<(), Error> {
+        let valid = <[u8; 64]>::try_from(signature)
+            .map(|signature| DeviceIdentity::verify(public_key, transcript, &signature))
+            .unwrap_or(false);
+        if !valid {
+            return Err(DeskShareError::PairingFailed("bad identity signature".to_string()).into());
+        }
+        Ok(())
+    }
+    
+    fn commit(ephemeral_key: &[u8; 32], nonce: &[u8; 32]) -> [u8; 32] {
+        let mut material = [0u8; 64];
+        material[..32].copy_from_slice(ephemeral_key);
+        material[32..].copy_from_slice(nonce);
+        blake3::derive_key(COMMITMENT_CONTEXT, &material)
+    }
+    
+    /// What a side signs to bind its ephemeral key to its identity
+    fn transcript(request_id: &str, role: &str, first: &[u8; 32], second: &[u8; 32]) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new_derive_key(TRANSCRIPT_CONTEXT);
+        for part in [request_id.as_bytes(), role.as_bytes()] {
+            hasher.update(&(part.len() as u64).to_be_bytes());
+            hasher.update(part);
+        }
+        hasher.update(first);
+        hasher.update(second);
+        hasher.finalize().as_bytes().to_vec()
+    }
+    
+    /// Six digits from the shared secret, both identity keys and both
+    /// ephemeral keys, initiator first
+    fn derive_code(shared: &[u8; 32], initiator: (&[u8; 32], &[u8; 32]), responder: (&[u8; 32], &[u8; 32])) -> String {
+        let mut material = Vec::with_capacity(160);
+        for part in [shared, initiator.0, initiator.1, responder.0, responder.1] {
+            material.extend_from_slice(part);
+        }
+        let digest = blake3::derive_key(SAS_CONTEXT, &material);
+        let value = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+        format!("{:06}", value % 1_000_000)
+    }
+    
+    fn generate_request_id() -> String {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        format!("{:x}", rng.gen::<u128>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    
+    /// Delivers everything to one manager, whatever the address
+    struct Direct(Arc<PairingManager>);
+    
+    #[async_trait]
+    impl PairingTransport for Direct {
+        async fn send_request(&self, _peer_id: &str, request: PairingRequest) -> Result<PairingReply, Error> {
+            self.0.handle_request(request)
+        }
+        
+        async fn send_reveal(&self, _peer_id: &str, reveal: PairingReveal) -> Result<(), Error> {
+            self.0.handle_reveal(reveal)
+        }
+        
+        async fn send_confirm(&self, _peer_id: &str, from_peer_id: &str, request_id: &str, accept: bool) -> Result<(), Error> {
+            self.0.handle_confirm(from_peer_id, request_id, accept);
+            Ok(())
+        }
+    }
+    
+    /// Sits between the two devices and runs a separate exchange with each
+    struct Mitm {
+        facing_initiator: Arc<PairingManager>,
+        facing_responder: Arc<PairingManager>,
+        responder_code: Mutex<Option<String>>,
+    }
+    
+    #[async_trait]
+    impl PairingTransport for Mitm {
+        async fn send_request(&self, peer_id: &str, request: PairingRequest) -> Result<PairingReply, Error> {
+            let handle = self.facing_responder.request_pairing(peer_id).await?;
+            *self.responder_code.lock().unwrap() = Some(handle.code);
+            self.facing_initiator.handle_request(request)
+        }
+        
+        async fn send_reveal(&self, _peer_id: &str, reveal: PairingReveal) -> Result<(), Error> {
+            self.facing_initiator.handle_reveal(reveal)
+        }
+        
+        async fn send_confirm(&self, _peer_id: &str, from_peer_id: &str, request_id: &str, accept: bool) -> Result<(), Error> {
+            self.facing_initiator.handle_confirm(from_peer_id, request_id, accept);
+            Ok(())
+        }
+    }
+    
+    fn manager() -> PairingManager {
+        PairingManager::new(Arc::new(DeviceIdentity::generate()), Arc::new(TrustStore::new()))
+    }
+    
+    async fn next_requested(rx: &mut broadcast::Receiver<PairingEvent>) -> (String, String) {
+        loop {
+            if let PairingEvent::Requested { request_id, code, .. } = rx.recv().await.unwrap() {
+                return (request_id, code);
+            }
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_codes_match_without_interference() {
+        let bob = Arc::new(manager());
+        let mut bob_events = bob.subscribe();
+        let alice = manager().with_transport("alice".to_string(), Arc::new(Direct(bob.clone())));
+        
+        let handle = alice.request_pairing("bob").await.unwrap();
+        let (request_id, code) = next_requested(&mut bob_events).await;
+        assert_eq!(request_id, handle.request_id);
+        assert_eq!(code, handle.code);
+    }
+    
+    #[tokio::test]
+    async fn test_man_in_the_middle_shows_different_codes() {
+        let bob = Arc::new(manager());
+        let mut bob_events = bob.subscribe();
+        let mallory = Arc::new(Mitm {
+            facing_initiator: Arc::new(manager()),
+            facing_responder: Arc::new(manager().with_transport("mallory".to_string(), Arc::new(Direct(bob.clone())))),
+            responder_code: Mutex::new(None),
+        });
+        let alice = manager().with_transport("alice".to_string(), mallory.clone());
+        
+        let handle = alice.request_pairing("bob").await.unwrap();
+        let (_, bob_code) = next_requested(&mut bob_events).await;
+        
+        // Each side matches Mallory, but the users comparing screens see different codes
+        assert_eq!(mallory.responder_code.lock().unwrap().as_deref(), Some(bob_code.as_str()));
+        assert_ne!(handle.code, bob_code);
+    }
+    
+    #[tokio::test]
+    async fn test_reveal_must_open_commitment() {
+        let alice = DeviceIdentity::generate();
+        let bob = manager();
+        let mut events = bob.subscribe();
+        
+        let ephemeral_key = PublicKey::from(&EphemeralSecret::random_from_rng(rand::rngs::OsRng)).to_bytes();
+        let nonce: [u8; 32] = rand::random();
+        let reply = bob
+            .handle_request(PairingRequest {
+                request_id: "r1".to_string(),
+                peer_id: "alice".to_string(),
+                device_id: alice.device_id(),
+                public_key: alice.public_key(),
+                commitment: PairingManager::commit(&ephemeral_key, &nonce),
+            })
+            .unwrap();
+        
+        // A different ephemeral key than the one committed to is refused
+        let swapped = PublicKey::from(&EphemeralSecret::random_from_rng(rand::rngs::OsRng)).to_bytes();
+        let transcript = PairingManager::transcript("r1", "initiator", &swapped, &reply.ephemeral_key);
+        let err = bob
+            .handle_reveal(PairingReveal {
+                request_id: "r1".to_string(),
+                peer_id: "alice".to_string(),
+                ephemeral_key: swapped,
+                nonce,
+                signature: alice.sign(&transcript).to_vec(),
+            })
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<DeskShareError>(), Some(DeskShareError::PairingFailed(_))));
+        
+        match events.recv().await.unwrap() {
+            PairingEvent::Completed { paired, reason, .. } => {
+                assert!(!paired);
+                assert_eq!(reason.as_deref(), Some("verification failed"));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,434 @@
+// Inbound rate limiting
+// Every message from another device spends a token from that device's bucket
+// for its kind of message and from one bucket shared by all devices. A device
+// that empties its own bucket has the excess dropped and is muted through the
+// trust store, for longer each time it happens.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+
+use super::trust::{PeerMute, TrustLevel, TrustStore};
+
+/// Buckets kept before full ones are pruned
+const MAX_TRACKED_BUCKETS: usize = 4096;
+
+/// Kinds of inbound message with their own limits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProtocolClass {
+    ChunkRequest,
+    /// Transfer offers and screen share joins and control messages
+    Signaling,
+    Discovery,
+    Chat,
+    /// A viewer's mouse and keyboard, while it controls a shared screen
+    RemoteInput,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub per_second: u32,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn scaled(self, factor: u32) -> Self {
+        Self {
+            per_second: self.per_second.saturating_mul(factor),
+            burst: self.burst.saturating_mul(factor),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub chunk_requests: RateLimit,
+    pub signaling: RateLimit,
+    pub discovery: RateLimit,
+    pub chat: RateLimit,
+    pub remote_input: RateLimit,
+    /// Paired devices get this many times the limits above
+    pub paired_multiplier: u32,
+    /// All devices together, per class or not
+    pub global: RateLimit,
+    /// Length of a first mute; each later one doubles, up to `max_mute_secs`
+    pub base_mute_secs: u64,
+    pub max_mute_secs: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            chunk_requests: RateLimit { per_second: 200, burst: 400 },
+            signaling: RateLimit { per_second: 10, burst: 20 },
+            discovery: RateLimit { per_second: 5, burst: 10 },
+            chat: RateLimit { per_second: 20, burst: 40 },
+            // Enough for a mouse moving at 120Hz
+            remote_input: RateLimit { per_second: 150, burst: 300 },
+            paired_multiplier: 4,
+            global: RateLimit { per_second: 2000, burst: 4000 },
+            base_mute_secs: 10,
+            max_mute_secs: 3600,
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn limit(&self, class: ProtocolClass) -> RateLimit {
+        match class {
+            ProtocolClass::ChunkRequest => self.chunk_requests,
+            ProtocolClass::Signaling => self.signaling,
+            ProtocolClass::Discovery => self.discovery,
+            ProtocolClass::Chat => self.chat,
+            ProtocolClass::RemoteInput => self.remote_input,
+        }
+    }
+}
+
+/// Raised once each time a device is muted
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct RateLimitEvent {
+    pub peer_id: String,
+    pub class: ProtocolClass,
+    pub muted_for_secs: u64,
+}
+
+/// What the limiter is doing, for diagnostics
+#[derive(Clone, Debug, Serialize)]
+pub struct RateLimiterState {
+    pub config: RateLimitConfig,
+    /// Messages dropped per class, whether from a muted or a limited peer
+    pub dropped: BTreeMap<ProtocolClass, u64>,
+    /// Messages dropped because everyone together went over the global limit
+    pub dropped_global: u64,
+    pub muted: Vec<PeerMute>,
+    pub tracked_buckets: usize,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn full(limit: RateLimit) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+    
+    fn refill(&mut self, limit: RateLimit) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second as f64).min(limit.burst as f64);
+        self.refilled_at = now;
+    }
+    
+    fn take(&mut self, limit: RateLimit) -> bool {
+        self.refill(limit);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    trust_store: Arc<TrustStore>,
+    buckets: Mutex<HashMap<(String, ProtocolClass), Bucket>>,
+    global: Mutex<Bucket>,
+    dropped: Mutex<BTreeMap<ProtocolClass, u64>>,
+    dropped_global: Mutex<u64>,
+    event_tx: broadcast::Sender<RateLimitEvent>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, trust_store: Arc<TrustStore>) -> Self {
+        let (event_tx, _) = broadcast::channel(64);
+        Self {
+            config,
+            trust_store,
+            buckets: Mutex::new(HashMap::new()),
+            global: Mutex::new(Bucket::full(config.global)),
+            dropped: Mutex::new(BTreeMap::new()),
+            dropped_global: Mutex::new(0),
+            event_tx,
+        }
+    }
+    
+    pub fn subscribe(&self) -> broadcast::Receiver<RateLimitEvent> {
+        self.event_tx.subscribe()
+    }
+    
+    /// Whether to handle a message of `class` from `peer_id`. Refused messages
+    /// are to be dropped without a reply.
+    pub fn admit(&self, peer_id: &str, class: ProtocolClass) -> bool {
+        if self.trust_store.is_muted(peer_id) {
+            self.count_drop(class);
+            return false;
+        }
+        
+        let mut limit = self.config.limit(class);
+        if self.trust_store.level_for_peer(peer_id) == TrustLevel::Paired {
+            limit = limit.scaled(self.config.paired_multiplier);
+        }
+        let admitted = {
+            let mut buckets = self.buckets.lock().unwrap();
+            if buckets.len() >= MAX_TRACKED_BUCKETS {
+                Self::prune(&mut buckets, &self.config);
+            }
+            buckets
+                .entry((peer_id.to_string(), class))
+                .or_insert_with(|| Bucket::full(limit))
+                .take(limit)
+        };
+        if !admitted {
+            self.count_drop(class);
+            let muted_for = self.trust_store.mute(
+                peer_id,
+                Duration::from_secs(self.config.base_mute_secs),
+                Duration::from_secs(self.config.max_mute_secs),
+            );
+            tracing::warn!("{} went over its {:?} limit; muted for {:?}", peer_id, class, muted_for);
+            let _ = self.event_tx.send(RateLimitEvent {
+                peer_id: peer_id.to_string(),
+                class,
+                muted_for_secs: muted_for.as_secs(),
+            });
+            return false;
+        }
+        
+        // Nobody is to blame for the aggregate, so going over it mutes no one
+        if !self.global.lock().unwrap().take(self.config.global) {
+            *self.dropped_global.lock().unwrap() += 1;
+            return false;
+        }
+        true
+    }
+    
+    pub fn state(&self) -> RateLimiterState {
+        RateLimiterState {
+            config: self.config,
+            dropped: self.dropped.lock().unwrap().clone(),
+            dropped_global: *self.dropped_global.lock().unwrap(),
+            muted: self.trust_store.mutes(),
+            tracked_buckets: self.buckets.lock().unwrap().len(),
+        }
+    }
+    
+    fn count_drop(&self, class: ProtocolClass) {
+        *self.dropped.lock().unwrap().entry(class).or_insert(0) += 1;
+    }
+    
+    /// Drop buckets that have refilled, since a fresh one behaves the same
+    fn prune(buckets: &mut HashMap<(String, ProtocolClass), Bucket>, config: &RateLimitConfig) {
+        buckets.retain(|(_, class), bucket| {
+            let limit = config.limit(*class);
+            bucket.refill(limit);
+            bucket.tokens < limit.burst as f64
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{JoinRequest, JoinResponse, ChunkCodec, HashAlgorithm, ShareKind, SharedFile, MANIFEST_VERSION_FLAT};
+    use crate::p2p::DiscoveryReply;
+    use crate::platform::fallback::FallbackCapture;
+    use crate::security::DeviceIdentity;
+    use crate::services::{ChatMessage, ChatPacket, DeliveryState};
+    use crate::{ChatService, FileTransfer, NetworkDiscovery, ScreenShare};
+    
+    const CLASSES: [ProtocolClass; 5] = [
+        ProtocolClass::ChunkRequest,
+        ProtocolClass::Signaling,
+        ProtocolClass::Discovery,
+        ProtocolClass::Chat,
+        ProtocolClass::RemoteInput,
+    ];
+    
+    /// Limits low enough that no token refills while a test runs
+    fn config() -> RateLimitConfig {
+        let limit = RateLimit { per_second: 1, burst: 5 };
+        RateLimitConfig {
+            chunk_requests: limit,
+            signaling: limit,
+            discovery: limit,
+            chat: limit,
+            remote_input: limit,
+            paired_multiplier: 3,
+            global: RateLimit { per_second: 1, burst: 1000 },
+            base_mute_secs: 60,
+            max_mute_secs: 600,
+        }
+    }
+    
+    fn burst(limiter: &RateLimiter, peer_id: &str, class: ProtocolClass, count: usize) -> usize {
+        (0..count).filter(|_| limiter.admit(peer_id, class)).count()
+    }
+    
+    #[test]
+    fn test_bursts_are_limited_per_peer() {
+        let trust_store = Arc::new(TrustStore::new());
+        let paired = DeviceIdentity::generate();
+        trust_store.record_paired(&paired.device_id(), "10.0.0.3", &paired.public_key());
+        let limiter = RateLimiter::new(config(), trust_store.clone());
+        let mut events = limiter.subscribe();
+        
+        // Each class has its own bucket, so a full burst of each gets through
+        for class in CLASSES {
+            assert_eq!(burst(&limiter, "10.0.0.66", class, 5), 5, "{:?}", class);
+        }
+        assert!(events.try_recv().is_err());
+        
+        // One more chat message and the peer is muted for everything, with a single warning
+        assert_eq!(burst(&limiter, "10.0.0.66", ProtocolClass::Chat, 50), 0);
+        for class in CLASSES {
+            assert_eq!(burst(&limiter, "10.0.0.66", class, 10), 0, "{:?}", class);
+        }
+        assert_eq!(
+            events.try_recv().unwrap(),
+            RateLimitEvent { peer_id: "10.0.0.66".to_string(), class: ProtocolClass::Chat, muted_for_secs: 60 }
+        );
+        assert!(events.try_recv().is_err());
+        
+        // Other peers keep their own allowance, and paired ones get more
+        assert_eq!(burst(&limiter, "10.0.0.7", ProtocolClass::Chat, 50), 5);
+        assert_eq!(burst(&limiter, "10.0.0.3", ProtocolClass::Chat, 50), 15);
+        assert_eq!(burst(&limiter, "10.0.0.3", ProtocolClass::Discovery, 5), 0);
+        
+        let state = limiter.state();
+        assert_eq!(state.dropped[&ProtocolClass::Chat], 50 + 10 + 45 + 35);
+        assert_eq!(state.dropped[&ProtocolClass::ChunkRequest], 10);
+        assert_eq!(state.dropped_global, 0);
+        let muted: Vec<_> = state.muted.iter().map(|mute| (mute.peer_id.as_str(), mute.strikes)).collect();
+        assert_eq!(muted, [("10.0.0.3", 1), ("10.0.0.66", 1), ("10.0.0.7", 1)]);
+        
+        // Repeat offences mute for longer, up to the cap
+        let strike = |_| trust_store.mute("10.0.0.66", Duration::from_secs(60), Duration::from_secs(600)).as_secs();
+        assert_eq!((0..4).map(strike).collect::<Vec<_>>(), [120, 240, 480, 600]);
+    }
+    
+    #[test]
+    fn test_global_limit_mutes_nobody() {
+        let trust_store = Arc::new(TrustStore::new());
+        let limiter = RateLimiter::new(
+            RateLimitConfig { global: RateLimit { per_second: 1, burst: 8 }, ..config() },
+            trust_store.clone(),
+        );
+        
+        assert_eq!(burst(&limiter, "10.0.0.7", ProtocolClass::Chat, 5), 5);
+        assert_eq!(burst(&limiter, "10.0.0.8", ProtocolClass::Chat, 5), 3);
+        assert_eq!(limiter.state().dropped_global, 2);
+        assert!(!trust_store.is_muted("10.0.0.8"));
+    }
+    
+    struct Services {
+        chat: ChatService,
+        file_transfer: FileTransfer,
+        discovery: NetworkDiscovery,
+        screen_share: ScreenShare,
+        session_id: String,
+        identity: DeviceIdentity,
+    }
+    
+    impl Services {
+        /// What got through from `count` rounds of every kind of message:
+        /// (chat messages, offers, joins, discovery replies)
+        async fn flood(&mut self, peer_id: &str, count: usize) -> (usize, usize, usize, usize) {
+            let mut device_events = self.discovery.subscribe_device_events();
+            let (mut offers, mut joins, mut replies) = (0, 0, 0);
+            for i in 0..count {
+                let message = ChatMessage {
+                    id: format!("{}-{}", peer_id, i),
+                    from: peer_id.to_string(),
+                    to: None,
+                    content: "spam".to_string(),
+                    timestamp: 0,
+                    attachment: None,
+                    state: DeliveryState::Sent,
+                };
+                self.chat.receive_packet(ChatPacket::Message(message)).await.unwrap();
+                
+                let file = SharedFile {
+                    hash: format!("{}-{}", peer_id, i),
+                    name: "spam.bin".to_string(),
+                    size: 16,
+                    chunks: vec!["chunk".to_string()],
+                    chunk_size: 16,
+                    total_chunks: 1,
+                    peer_id: peer_id.to_string(),
+                    timestamp: 0,
+                    kind: ShareKind::File,
+                    codec: ChunkCodec::Raw,
+                    encrypted: true,
+                    manifest_version: MANIFEST_VERSION_FLAT,
+                    merkle_root: None,
+                    hash_algorithm: HashAlgorithm::Blake3,
+                };
+                let offer_id = self.file_transfer.receive_offer(peer_id.to_string(), "Spammer".to_string(), file).await;
+                if self.file_transfer.get_pending_offers().iter().any(|offer| offer.offer_id == offer_id) {
+                    offers += 1;
+                }
+                
+                let request = JoinRequest {
+                    request_id: format!("{}-{}", peer_id, i),
+                    session_id: self.session_id.clone(),
+                    peer_id: peer_id.to_string(),
+                    password: None,
+                    codecs: Vec::new(),
+                    audio: false,
+                    display_name: None,
+                };
+                if matches!(self.screen_share.handle_join_request(request).await, JoinResponse::Accepted { .. }) {
+                    joins += 1;
+                }
+                
+                let reply = DiscoveryReply::new(&self.identity, "Spammer".to_string(), 8080, Vec::new());
+                self.discovery.record_reply(&reply, peer_id).unwrap();
+                if device_events.try_recv().is_ok() {
+                    replies += 1;
+                }
+            }
+            let messages = self.chat.get_messages().await.iter().filter(|m| m.from == peer_id).count();
+            (messages, offers, joins, replies)
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_services_drop_a_flooding_peer() {
+        let trust_store = Arc::new(TrustStore::new());
+        // Offers and joins share the signaling allowance
+        let config = RateLimitConfig { signaling: RateLimit { per_second: 1, burst: 10 }, ..config() };
+        let limiter = Arc::new(RateLimiter::new(config, trust_store.clone()));
+        let mut events = limiter.subscribe();
+        let screen_share = ScreenShare::with_capture_backend(Arc::new(FallbackCapture)).with_rate_limiter(limiter.clone());
+        let session_id = screen_share.start_sharing(5, (64, 48), None, None, None).await.unwrap();
+        let mut services = Services {
+            chat: ChatService::new().await.with_rate_limiter(limiter.clone()),
+            file_transfer: FileTransfer::new().await.with_rate_limiter(limiter.clone()),
+            discovery: NetworkDiscovery::new().await.with_rate_limiter(limiter.clone()),
+            screen_share,
+            session_id,
+            identity: DeviceIdentity::generate(),
+        };
+        
+        // Chat runs dry first and mutes the peer for the rest of the round
+        assert_eq!(services.flood("10.0.0.66", 20).await, (5, 5, 5, 5));
+        assert_eq!(services.flood("10.0.0.7", 5).await, (5, 5, 5, 5));
+        
+        let event = events.try_recv().unwrap();
+        assert_eq!((event.peer_id.as_str(), event.class), ("10.0.0.66", ProtocolClass::Chat));
+        assert!(events.try_recv().is_err());
+        assert!(trust_store.is_muted("10.0.0.66"));
+        assert!(!trust_store.is_muted("10.0.0.7"));
+        assert_eq!(limiter.state().dropped[&ProtocolClass::Signaling], 2 * 15);
+    }
+}
@@ -0,0 +1,241 @@
+// Remote path sanitizing
+// File names and relative paths from other devices are untrusted. They are
+// reduced to plain relative components here before anything touches the
+// filesystem, and joined paths are checked against the destination again
+// once symlinks are resolved.
+
+use std::path::{Path, PathBuf};
+use anyhow::Error;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::DeskShareError;
+
+/// Longest single component most filesystems accept
+const MAX_COMPONENT_BYTES: usize = 255;
+const MAX_PATH_BYTES: usize = 1024;
+
+/// Device names Windows reserves in every directory, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$",
+    "COM0", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT0", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turn a remote file name or relative path into a relative path that stays
+/// inside whatever directory it is joined to. Separators of either platform
+/// split components, and root, drive, `.` and `..` components are dropped.
+pub fn sanitize_remote_path(raw: &str) -> Result<PathBuf, Error> {
+    // Compatibility folding first, so fullwidth slashes and dot leaders
+    // become the separators and dots they imitate
+    let normalized: String = raw.nfkc().collect();
+    if normalized.len() > MAX_PATH_BYTES {
+        return Err(unsafe_path(raw, "path is too long"));
+    }
+    
+    let mut path = PathBuf::new();
+    for (index, component) in normalized.split(['/', '\\']).enumerate() {
+        if component.is_empty() || component == "." || component == ".." {
+            continue;
+        }
+        if index == 0 && is_drive_prefix(component) {
+            continue;
+        }
+        path.push(sanitize_component(raw, component)?);
+    }
+    
+    if path.as_os_str().is_empty() {
+        return Err(unsafe_path(raw, "no file name left"));
+    }
+    Ok(path)
+}
+
+/// Sanitize `raw` and join it to `dest`, failing if the result would land
+/// outside `dest` once existing directories and symlinks are resolved
+pub fn resolve_remote_path(dest: &Path, raw: &str) -> Result<PathBuf, Error> {
+    let relative = sanitize_remote_path(raw)?;
+    let root = canonicalize_existing(dest)?;
+    let joined = root.join(&relative);
+    
+    if !joined.starts_with(&root) || !canonicalize_existing(&joined)?.starts_with(&root) {
+        return Err(unsafe_path(raw, "escapes the destination folder"));
+    }
+    Ok(joined)
+}
+
+fn sanitize_component(raw: &str, component: &str) -> Result<String, Error> {
+    if let Some(c) = component.chars().find(|&c| c.is_control() || is_invisible(c)) {
+        return Err(unsafe_path(raw, &format!("contains hidden character U+{:04X}", c as u32)));
+    }
+    if component.ends_with('.') || component.ends_with(' ') {
+        return Err(unsafe_path(raw, "ends with a dot or space"));
+    }
+    
+    let stem = component.split('.').next().unwrap_or(component).trim_end();
+    if RESERVED_NAMES.iter().any(|name| stem.eq_ignore_ascii_case(name)) {
+        return Err(unsafe_path(raw, "uses a reserved device name"));
+    }
+    if component.len() > MAX_COMPONENT_BYTES {
+        return Err(unsafe_path(raw, "name is too long"));
+    }
+    
+    // Characters Windows refuses; `:` would also open an alternate data stream
+    Ok(component
+        .chars()
+        .map(|c| if matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') { '_' } else { c })
+        .collect())
+}
+
+/// Format and bidi characters that change how a name reads without changing
+/// what it is, e.g. `invoice.pdf\u{202E}exe.` showing as a PDF
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}'
+            | '\u{061C}'
+            | '\u{180E}'
+            | '\u{200B}'..='\u{200F}'
+            | '\u{202A}'..='\u{202E}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{206F}'
+            | '\u{FEFF}'
+            | '\u{FFF9}'..='\u{FFFB}'
+    )
+}
+
+fn is_drive_prefix(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    bytes.len() == 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':'
+}
+
+/// Canonical form of `path`, or of its nearest existing ancestor with the
+/// rest appended
+fn canonicalize_existing(path: &Path) -> Result<PathBuf, Error> {
+    let mut existing = path;
+    let mut rest = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => return Ok(rest.iter().rev().fold(resolved, |acc, part| acc.join(part))),
+            Err(_) => {
+                rest.push(existing.file_name().ok_or_else(|| {
+                    DeskShareError::FileNotFound(path.display().to_string())
+                })?);
+                existing = existing.parent().ok_or_else(|| {
+                    DeskShareError::FileNotFound(path.display().to_string())
+                })?;
+            }
+        }
+    }
+}
+
+fn unsafe_path(raw: &str, reason: &str) -> Error {
+    DeskShareError::UnsafeRemotePath(format!("{:?} {}", raw, reason)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_hostile_names() {
+        // (remote name, sanitized relative path or None when rejected)
+        let cases: &[(&str, Option<&str>)] = &[
+            ("report.pdf", Some("report.pdf")),
+            ("photos/2024/beach.jpg", Some("photos/2024/beach.jpg")),
+            ("Café.txt", Some("Café.txt")),
+            // Decomposed é composes to the same name
+            ("Cafe\u{0301}.txt", Some("Café.txt")),
+            // Unix traversal
+            ("../../etc/passwd", Some("etc/passwd")),
+            ("/etc/shadow", Some("etc/shadow")),
+            ("a/./b/../c", Some("a/b/c")),
+            ("....//....//x", None),
+            // Windows traversal, drives and UNC paths
+            ("..\\..\\Windows\\System32\\evil.dll", Some("Windows/System32/evil.dll")),
+            ("C:\\Users\\me\\.ssh\\id_rsa", Some("Users/me/.ssh/id_rsa")),
+            ("c:evil.exe", Some("c_evil.exe")),
+            ("\\\\server\\share\\x.txt", Some("server/share/x.txt")),
+            ("mixed/..\\../x", Some("mixed/x")),
+            // Fullwidth slash and dot leaders fold to real separators and dots
+            ("\u{FF0E}\u{FF0E}\u{FF0F}secret", Some("secret")),
+            ("\u{2024}\u{2024}/secret", Some("secret")),
+            // Characters Windows refuses are replaced
+            ("notes: draft?.txt", Some("notes_ draft_.txt")),
+            ("file.txt:hidden_stream", Some("file.txt_hidden_stream")),
+            ("a<b>c|d\"e*.txt", Some("a_b_c_d_e_.txt")),
+            // Reserved device names, with or without extensions
+            ("CON", None),
+            ("nul.txt", None),
+            ("Aux.tar.gz", None),
+            ("dir/COM1", None),
+            ("lpt9.log", None),
+            ("COM\u{00B9}.txt", None),
+            ("CONOUT$", None),
+            ("NUL .txt", None),
+            ("console.txt", Some("console.txt")),
+            ("COM10", Some("COM10")),
+            // Trailing dots and spaces vanish on Windows
+            ("invoice.pdf.", None),
+            ("invoice.pdf ", None),
+            ("dir./file", None),
+            // Bidi and invisible tricks
+            ("invoice.pdf\u{202E}exe.", None),
+            ("invoice\u{202E}fdp.exe", None),
+            ("report\u{200B}.exe", None),
+            ("a\u{2066}b.txt", None),
+            ("\u{FEFF}bom.txt", None),
+            ("line\nbreak.txt", None),
+            ("nul\0byte.txt", None),
+            // Nothing left to name the file
+            ("", None),
+            ("..", None),
+            ("/", None),
+            ("..\\..\\", None),
+            ("C:\\", None),
+            // Length limits
+            (&"a".repeat(255), Some(&"a".repeat(255))),
+        ];
+        
+        for (raw, expected) in cases {
+            let result = sanitize_remote_path(raw);
+            match expected {
+                Some(expected) => {
+                    let path = result.unwrap_or_else(|e| panic!("{:?} was rejected: {}", raw, e));
+                    let components: Vec<_> = path.iter().map(|c| c.to_str().unwrap()).collect();
+                    assert_eq!(components.join("/"), *expected, "for {:?}", raw);
+                }
+                None => {
+                    let err = result.expect_err(&format!("{:?} was accepted", raw));
+                    let err = err.downcast_ref::<DeskShareError>().unwrap();
+                    assert_eq!(err.code(), "unsafe_remote_path", "for {:?}", raw);
+                }
+            }
+        }
+        
+        assert!(sanitize_remote_path(&"a".repeat(256)).is_err());
+        assert!(sanitize_remote_path(&"abc/".repeat(300)).is_err());
+    }
+    
+    #[test]
+    fn test_resolved_paths_stay_in_destination() {
+        let dest = std::env::temp_dir().join(format!("desk-share-sanitize-{}", std::process::id()));
+        std::fs::create_dir_all(&dest).unwrap();
+        let root = dest.canonicalize().unwrap();
+        
+        let path = resolve_remote_path(&dest, "../../etc/passwd").unwrap();
+        assert_eq!(path, root.join("etc").join("passwd"));
+        
+        // The destination itself may not exist yet
+        let path = resolve_remote_path(&dest.join("new"), "a.txt").unwrap();
+        assert_eq!(path, root.join("new").join("a.txt"));
+        
+        // A symlink already inside the destination can't carry a write outside it
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(std::env::temp_dir(), dest.join("link")).unwrap();
+            let err = resolve_remote_path(&dest, "link/escaped.txt").unwrap_err();
+            assert_eq!(err.downcast_ref::<DeskShareError>().unwrap().code(), "unsafe_remote_path");
+        }
+        
+        std::fs::remove_dir_all(&dest).unwrap();
+    }
+}
@@ -0,0 +1,537 @@
+// Trust store
+// What we know about other devices, keyed by device id. Blocks live here too,
+// so every subsystem refuses a blocked device through the same check.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+
+use super::identity::DeviceIdentity;
+
+/// Offers up to this size from paired devices are accepted without asking
+const DEFAULT_PAIRED_AUTO_ACCEPT_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TrustLevel {
+    Unknown,
+    Paired,
+    Blocked,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrustRecord {
+    pub device_id: String,
+    /// Address the device was last seen at
+    pub peer_id: String,
+    /// Hex-encoded identity key; empty for blocks made before the key was known
+    pub public_key: String,
+    pub level: TrustLevel,
+    pub updated_at: u64,
+}
+
+impl TrustRecord {
+    fn matches(&self, peer_or_device_id: &str) -> bool {
+        self.device_id == peer_or_device_id || self.peer_id == peer_or_device_id
+    }
+    
+    /// Fingerprint of the recorded key; None for keyless blocks
+    pub fn fingerprint(&self) -> Option<String> {
+        let key: [u8; 32] = hex::decode(&self.public_key).ok()?.try_into().ok()?;
+        Some(DeviceIdentity::fingerprint_for(&key))
+    }
+}
+
+/// A paired device's address answered with a different identity key
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct IdentityChange {
+    pub peer_id: String,
+    /// The paired device, which is no longer trusted
+    pub device_id: String,
+    pub previous_fingerprint: String,
+    pub new_fingerprint: String,
+}
+
+/// Whoever is at the other end of a connection, as its handshake proved.
+/// Trust decisions go by `device_id`; an address only says where the
+/// device was last seen, and someone else may be there now.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PeerIdentity {
+    /// Address the connection came from
+    pub peer_id: String,
+    /// Device id of the key the other end authenticated with
+    pub device_id: String,
+}
+
+/// Conveniences granted to devices at a trust level
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrustPolicy {
+    /// Accept offers up to this many bytes without prompting
+    pub auto_accept_max_bytes: Option<u64>,
+    /// Let the device into Approval sessions without asking the host
+    pub skip_join_approval: bool,
+}
+
+/// A device silenced for a while after going over its rate limits
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PeerMute {
+    pub peer_id: String,
+    pub remaining_secs: u64,
+    /// Mutes so far, including this one
+    pub strikes: u32,
+}
+
+impl TrustPolicy {
+    pub fn auto_accepts(&self, size: u64) -> bool {
+        matches!(self.auto_accept_max_bytes, Some(max) if size <= max)
+    }
+}
+
+pub struct TrustStore {
+    records: RwLock<HashMap<String, TrustRecord>>,
+    policies: RwLock<HashMap<TrustLevel, TrustPolicy>>,
+    /// Where records are kept between runs, if anywhere
+    path: Option<PathBuf>,
+    /// Refused attempts per subsystem
+    blocked_activity: Mutex<BTreeMap<String, u64>>,
+    /// Chunks per peer that failed authentication
+    integrity_violations: Mutex<HashMap<String, u64>>,
+    /// Rate limit mutes per peer: (muted until, strikes)
+    mutes: Mutex<HashMap<String, (Instant, u32)>>,
+    identity_tx: broadcast::Sender<IdentityChange>,
+}
+
+impl Default for TrustStore {
+    fn default() -> Self {
+        let mut policies = HashMap::new();
+        policies.insert(TrustLevel::Paired, TrustPolicy {
+            auto_accept_max_bytes: Some(DEFAULT_PAIRED_AUTO_ACCEPT_BYTES),
+            skip_join_approval: true,
+        });
+        
+        Self {
+            records: RwLock::new(HashMap::new()),
+            policies: RwLock::new(policies),
+            path: None,
+            blocked_activity: Mutex::new(BTreeMap::new()),
+            integrity_violations: Mutex::new(HashMap::new()),
+            mutes: Mutex::new(HashMap::new()),
+            identity_tx: broadcast::channel(32).0,
+        }
+    }
+}
+
+impl TrustStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Load the records kept at `path`; every change is written back there
+    pub fn open(path: &Path) -> Self {
+        let records: Vec<TrustRecord> = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                tracing::warn!("Ignoring unreadable trust records at {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        
+        let store = Self {
+            path: Some(path.to_path_buf()),
+            ..Self::default()
+        };
+        store.records.write().unwrap().extend(
+            records.into_iter().map(|record| (record.device_id.clone(), record)),
+        );
+        store
+    }
+    
+    pub fn get(&self, device_id: &str) -> Option<TrustRecord> {
+        self.records.read().unwrap().get(device_id).cloned()
+    }
+    
+    pub fn level(&self, device_id: &str) -> TrustLevel {
+        self.get(device_id).map(|record| record.level).unwrap_or(TrustLevel::Unknown)
+    }
+    
+    pub fn is_paired(&self, device_id: &str) -> bool {
+        self.level(device_id) == TrustLevel::Paired
+    }
+    
+    pub fn record_paired(&self, device_id: &str, peer_id: &str, public_key: &[u8; 32]) {
+        tracing::info!("Paired with device {} at {}", device_id, peer_id);
+        let mut records = self.records.write().unwrap();
+        records.insert(device_id.to_string(), TrustRecord {
+            device_id: device_id.to_string(),
+            peer_id: peer_id.to_string(),
+            public_key: hex::encode(public_key),
+            level: TrustLevel::Paired,
+            updated_at: Self::now(),
+        });
+        self.save(&records);
+    }
+    
+    /// Block a device by id, or whatever is at an address. Unknown ids get a
+    /// keyless record so the block holds before the device is ever seen.
+    pub fn block(&self, peer_or_device_id: &str) {
+        tracing::info!("Blocking {}", peer_or_device_id);
+        let mut records = self.records.write().unwrap();
+        let mut matched = false;
+        for record in records.values_mut().filter(|record| record.matches(peer_or_device_id)) {
+            record.level = TrustLevel::Blocked;
+            record.updated_at = Self::now();
+            matched = true;
+        }
+        if !matched {
+            records.insert(peer_or_device_id.to_string(), TrustRecord {
+                device_id: peer_or_device_id.to_string(),
+                peer_id: peer_or_device_id.to_string(),
+                public_key: String::new(),
+                level: TrustLevel::Blocked,
+                updated_at: Self::now(),
+            });
+        }
+        self.save(&records);
+    }
+    
+    /// Lift a block; returns false when nothing matching was blocked
+    pub fn unblock(&self, peer_or_device_id: &str) -> bool {
+        let mut records = self.records.write().unwrap();
+        let blocked = |record: &TrustRecord| record.level == TrustLevel::Blocked && record.matches(peer_or_device_id);
+        let before = records.len();
+        records.retain(|_, record| !(blocked(record) && record.public_key.is_empty()));
+        let mut unblocked = records.len() != before;
+        for record in records.values_mut().filter(|record| blocked(record)) {
+            record.level = TrustLevel::Unknown;
+            record.updated_at = Self::now();
+            unblocked = true;
+        }
+        
+        if unblocked {
+            tracing::info!("Unblocked {}", peer_or_device_id);
+            self.save(&records);
+        }
+        unblocked
+    }
+    
+    pub fn is_blocked(&self, peer_or_device_id: &str) -> bool {
+        self.records
+            .read()
+            .unwrap()
+            .values()
+            .any(|record| record.level == TrustLevel::Blocked && record.matches(peer_or_device_id))
+    }
+    
+    /// The check subsystems make before acting on remote input; blocked
+    /// attempts are counted under `subsystem`
+    pub fn refuses(&self, subsystem: &str, peer_or_device_id: &str) -> bool {
+        if !self.is_blocked(peer_or_device_id) {
+            return false;
+        }
+        tracing::debug!("Refused {} activity from blocked {}", subsystem, peer_or_device_id);
+        *self.blocked_activity.lock().unwrap().entry(subsystem.to_string()).or_insert(0) += 1;
+        true
+    }
+    
+    /// Refused attempts from blocked devices, per subsystem
+    pub fn blocked_activity(&self) -> BTreeMap<String, u64> {
+        self.blocked_activity.lock().unwrap().clone()
+    }
+    
+    /// Check the key presented by whoever is at `peer_id`, on every new
+    /// connection. A device paired at that address under a different key
+    /// loses its Paired status until it is paired again, since the code the
+    /// users compared no longer vouches for the new key.
+    pub fn observe_key(&self, peer_id: &str, public_key: &[u8; 32]) -> TrustLevel {
+        let device_id = DeviceIdentity::device_id_for(public_key);
+        let mut records = self.records.write().unwrap();
+        if let Some(record) = records.get(&device_id) {
+            return record.level;
+        }
+        
+        let presented = hex::encode(public_key);
+        let mut changes = Vec::new();
+        for record in records.values_mut() {
+            if record.peer_id == peer_id && record.level == TrustLevel::Paired && record.public_key != presented {
+                tracing::warn!(
+                    "Device at {} presented a new key; {} is no longer trusted",
+                    peer_id,
+                    record.device_id
+                );
+                record.level = TrustLevel::Unknown;
+                record.updated_at = Self::now();
+                changes.push(IdentityChange {
+                    peer_id: peer_id.to_string(),
+                    device_id: record.device_id.clone(),
+                    previous_fingerprint: record.fingerprint().unwrap_or_default(),
+                    new_fingerprint: DeviceIdentity::fingerprint_for(public_key),
+                });
+            }
+        }
+        if !changes.is_empty() {
+            self.save(&records);
+        }
+        for change in changes {
+            let _ = self.identity_tx.send(change);
+        }
+        TrustLevel::Unknown
+    }
+    
+    pub fn subscribe_identity_changes(&self) -> broadcast::Receiver<IdentityChange> {
+        self.identity_tx.subscribe()
+    }
+    
+    /// Fingerprint of the key on record for a device id or address,
+    /// preferring a paired record, then the most recently updated
+    pub fn fingerprint(&self, peer_or_device_id: &str) -> Option<String> {
+        self.records
+            .read()
+            .unwrap()
+            .values()
+            .filter(|record| record.matches(peer_or_device_id))
+            .filter_map(|record| Some(((record.level == TrustLevel::Paired, record.updated_at), record.fingerprint()?)))
+            .max_by_key(|(rank, _)| *rank)
+            .map(|(_, fingerprint)| fingerprint)
+    }
+    
+    pub fn policy(&self, level: TrustLevel) -> TrustPolicy {
+        if level == TrustLevel::Blocked {
+            return TrustPolicy::default();
+        }
+        self.policies.read().unwrap().get(&level).copied().unwrap_or_default()
+    }
+    
+    pub fn set_policy(&self, level: TrustLevel, policy: TrustPolicy) {
+        self.policies.write().unwrap().insert(level, policy);
+    }
+    
+    /// Level of whoever is at `peer_id`, going by where devices were last
+    /// seen. Only for showing; an address proves nothing, so privileges go
+    /// through `level_for` and `policy_for`.
+    pub fn level_for_peer(&self, peer_id: &str) -> TrustLevel {
+        self
+            .records
+            .read()
+            .unwrap()
+            .values()
+            .filter(|record| record.peer_id == peer_id)
+            .map(|record| record.level)
+            .max_by_key(|level| match level {
+                TrustLevel::Unknown => 0,
+                TrustLevel::Paired => 1,
+                TrustLevel::Blocked => 2,
+            })
+            .unwrap_or(TrustLevel::Unknown)
+    }
+    
+    /// Level of an authenticated peer: that of its key, unless the device
+    /// or its address is blocked
+    pub fn level_for(&self, peer: &PeerIdentity) -> TrustLevel {
+        if self.is_blocked(&peer.device_id) || self.is_blocked(&peer.peer_id) {
+            return TrustLevel::Blocked;
+        }
+        self.level(&peer.device_id)
+    }
+    
+    /// Conveniences for an authenticated peer; one that has sent forged
+    /// chunks gets none, whatever its level
+    pub fn policy_for(&self, peer: &PeerIdentity) -> TrustPolicy {
+        if self.integrity_violations(&peer.peer_id) > 0 {
+            return TrustPolicy::default();
+        }
+        self.policy(self.level_for(peer))
+    }
+    
+    /// Count a chunk from `peer_id` that failed authentication; returns the total
+    pub fn record_integrity_violation(&self, peer_id: &str) -> u64 {
+        let mut violations = self.integrity_violations.lock().unwrap();
+        let count = violations.entry(peer_id.to_string()).or_insert(0);
+        *count += 1;
+        tracing::warn!("Integrity violation #{} from {}", count, peer_id);
+        *count
+    }
+    
+    pub fn integrity_violations(&self, peer_id: &str) -> u64 {
+        self.integrity_violations.lock().unwrap().get(peer_id).copied().unwrap_or(0)
+    }
+    
+    /// Mute `peer_id` for `base`, doubled for every earlier strike and capped
+    /// at `max`; returns how long the mute lasts
+    pub fn mute(&self, peer_id: &str, base: Duration, max: Duration) -> Duration {
+        let mut mutes = self.mutes.lock().unwrap();
+        let (until, strikes) = mutes.entry(peer_id.to_string()).or_insert((Instant::now(), 0));
+        *strikes += 1;
+        let duration = base.saturating_mul(1u32 << (*strikes - 1).min(16)).min(max);
+        *until = Instant::now() + duration;
+        tracing::warn!("Muted {} for {:?} (strike {})", peer_id, duration, strikes);
+        duration
+    }
+    
+    pub fn is_muted(&self, peer_id: &str) -> bool {
+        self.mutes
+            .lock()
+            .unwrap()
+            .get(peer_id)
+            .is_some_and(|(until, _)| *until > Instant::now())
+    }
+    
+    /// Mutes still in force
+    pub fn mutes(&self) -> Vec<PeerMute> {
+        let now = Instant::now();
+        let mut mutes: Vec<PeerMute> = self
+            .mutes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, (until, _))| *until > now)
+            .map(|(peer_id, (until, strikes))| PeerMute {
+                peer_id: peer_id.clone(),
+                remaining_secs: (*until - now).as_secs(),
+                strikes: *strikes,
+            })
+            .collect();
+        mutes.sort_by(|a, b| a.peer_id.cmp(&b.peer_id));
+        mutes
+    }
+    
+    /// Forget a device; returns false when there was no record
+    pub fn remove(&self, device_id: &str) -> bool {
+        let mut records = self.records.write().unwrap();
+        let removed = records.remove(device_id).is_some();
+        if removed {
+            self.save(&records);
+        }
+        removed
+    }
+    
+    pub fn list(&self) -> Vec<TrustRecord> {
+        let mut records: Vec<TrustRecord> = self.records.read().unwrap().values().cloned().collect();
+        records.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        records
+    }
+    
+    fn save(&self, records: &HashMap<String, TrustRecord>) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let mut list: Vec<&TrustRecord> = records.values().collect();
+        list.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+        
+        let result = serde_json::to_vec_pretty(&list)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                Ok(std::fs::write(path, bytes)?)
+            });
+        if let Err(e) = result {
+            tracing::warn!("Couldn't save trust records to {}: {}", path.display(), e);
+        }
+    }
+    
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn at(peer_id: &str, identity: &DeviceIdentity) -> PeerIdentity {
+        PeerIdentity { peer_id: peer_id.to_string(), device_id: identity.device_id() }
+    }
+    
+    #[test]
+    fn test_key_change_downgrades_paired_device() {
+        let store = TrustStore::new();
+        let mut changes = store.subscribe_identity_changes();
+        let original = DeviceIdentity::generate();
+        store.record_paired(&original.device_id(), "10.0.0.3", &original.public_key());
+        assert_eq!(store.fingerprint("10.0.0.3"), Some(original.fingerprint()));
+        
+        assert_eq!(store.observe_key("10.0.0.3", &original.public_key()), TrustLevel::Paired);
+        assert!(store.policy_for(&at("10.0.0.3", &original)).skip_join_approval);
+        assert!(store.policy_for(&at("10.0.0.3", &original)).auto_accepts(1024));
+        
+        // Same address, different key: the old pairing no longer applies
+        let replacement = DeviceIdentity::generate();
+        assert_eq!(store.observe_key("10.0.0.3", &replacement.public_key()), TrustLevel::Unknown);
+        assert_eq!(store.level(&original.device_id()), TrustLevel::Unknown);
+        assert_eq!(store.policy_for(&at("10.0.0.3", &replacement)), TrustPolicy::default());
+        assert_eq!(changes.try_recv().unwrap(), IdentityChange {
+            peer_id: "10.0.0.3".to_string(),
+            device_id: original.device_id(),
+            previous_fingerprint: original.fingerprint(),
+            new_fingerprint: replacement.fingerprint(),
+        });
+        // Reported once; the old record is no longer Paired
+        store.observe_key("10.0.0.3", &replacement.public_key());
+        assert!(changes.try_recv().is_err());
+        
+        // Pairing again with the new key restores the privileges
+        store.record_paired(&replacement.device_id(), "10.0.0.3", &replacement.public_key());
+        assert!(store.policy_for(&at("10.0.0.3", &replacement)).skip_join_approval);
+        assert_eq!(store.fingerprint("10.0.0.3"), Some(replacement.fingerprint()));
+        
+        // Unknown devices get whatever the Unknown level is configured with
+        let stranger = DeviceIdentity::generate();
+        store.set_policy(TrustLevel::Unknown, TrustPolicy { auto_accept_max_bytes: Some(10), skip_join_approval: false });
+        assert!(store.policy_for(&at("10.0.0.9", &stranger)).auto_accepts(10));
+        assert!(!store.policy_for(&at("10.0.0.9", &stranger)).auto_accepts(11));
+    }
+    
+    #[test]
+    fn test_privileges_follow_the_key_not_the_address() {
+        let store = TrustStore::new();
+        let bob = DeviceIdentity::generate();
+        let mallory = DeviceIdentity::generate();
+        store.record_paired(&bob.device_id(), "10.0.0.3", &bob.public_key());
+        
+        // Another key at Bob's address, or Bob's key at a new one
+        assert_eq!(store.level_for(&at("10.0.0.3", &mallory)), TrustLevel::Unknown);
+        assert_eq!(store.policy_for(&at("10.0.0.3", &mallory)), TrustPolicy::default());
+        assert_eq!(store.level_for(&at("10.0.0.7", &bob)), TrustLevel::Paired);
+        
+        // A block on either side holds
+        store.block("10.0.0.7");
+        assert_eq!(store.level_for(&at("10.0.0.7", &bob)), TrustLevel::Blocked);
+        assert_eq!(store.policy_for(&at("10.0.0.7", &bob)), TrustPolicy::default());
+        store.block(&bob.device_id());
+        assert_eq!(store.level_for(&at("10.0.0.3", &bob)), TrustLevel::Blocked);
+    }
+    
+    #[test]
+    fn test_blocks_persist_and_lift() {
+        let path = std::env::temp_dir().join(format!("dsn-trust-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let bob = DeviceIdentity::generate();
+        
+        let store = TrustStore::open(&path);
+        store.record_paired(&bob.device_id(), "10.0.0.3", &bob.public_key());
+        store.block(&bob.device_id());
+        store.block("10.0.0.9");
+        
+        // Blocked by device id means blocked at its address too
+        let store = TrustStore::open(&path);
+        assert!(store.is_blocked(&bob.device_id()) && store.is_blocked("10.0.0.3"));
+        assert!(store.refuses("chat", "10.0.0.9"));
+        assert!(!store.refuses("chat", "10.0.0.4"));
+        assert_eq!(store.blocked_activity().get("chat"), Some(&1));
+        
+        assert!(store.unblock("10.0.0.3"));
+        assert!(store.unblock("10.0.0.9"));
+        assert!(!store.unblock("10.0.0.9"));
+        let store = TrustStore::open(&path);
+        assert!(!store.is_blocked("10.0.0.3") && !store.is_blocked("10.0.0.9"));
+        assert_eq!(store.level(&bob.device_id()), TrustLevel::Unknown);
+        assert_eq!(store.list().len(), 1);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -1,7 +1,32 @@
 // Chat service
 // Simplified interface for messaging
 
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+
+use crate::error::DeskShareError;
+use crate::security::{ProtocolClass, RateLimiter, TrustStore};
+use super::chat_store::{ChatStore, KeySource};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryState {
+    Pending,
+    Sent,
+    Delivered,
+    Read,
+    Failed,
+}
+
+/// File offered alongside a message; the receiver downloads it by hash
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChatAttachment {
+    pub file_hash: String,
+    pub name: String,
+    pub size: u64,
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ChatMessage {
@@ -10,16 +35,129 @@ pub struct ChatMessage {
     pub to: Option<String>,
     pub content: String,
     pub timestamp: u64,
+    pub attachment: Option<ChatAttachment>,
+    pub state: DeliveryState,
+}
+
+/// Wire format exchanged between chat services
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ChatPacket {
+    Message(ChatMessage),
+    Delivered { message_id: String },
+    Read { message_id: String },
+    Typing { from: String, is_typing: bool },
+}
+
+/// Changes pushed to the UI
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    MessageSent { message: ChatMessage },
+    MessageReceived { message: ChatMessage },
+    StateChanged { message_id: String, state: DeliveryState },
+    Typing { peer_id: String, is_typing: bool },
+}
+
+/// History query; `before` is a message id cursor for paging backwards
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MessageFilter {
+    pub peer_id: Option<String>,
+    pub before: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Carries packets to other peers; `to` of None means every peer
+#[async_trait]
+pub trait ChatTransport: Send + Sync {
+    async fn send(&self, from: &str, to: Option<&str>, packet: ChatPacket) -> Result<(), anyhow::Error>;
 }
 
 pub struct ChatService {
-    // Internal implementation will be added later
+    local_id: String,
+    messages: RwLock<Vec<ChatMessage>>,
+    event_tx: broadcast::Sender<ChatEvent>,
+    transport: Option<Arc<dyn ChatTransport>>,
+    trust_store: Option<Arc<TrustStore>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Where history is kept between runs; none until one is attached
+    store: Mutex<Option<ChatStore>>,
 }
 
 impl ChatService {
     pub async fn new() -> Self {
         tracing::info!("ChatService initialized");
-        Self {}
+        Self::build("local".to_string(), None)
+    }
+    
+    pub fn with_transport(local_id: String, transport: Arc<dyn ChatTransport>) -> Self {
+        Self::build(local_id, Some(transport))
+    }
+    
+    fn build(local_id: String, transport: Option<Arc<dyn ChatTransport>>) -> Self {
+        let (event_tx, _) = broadcast::channel(256);
+        Self {
+            local_id,
+            messages: RwLock::new(Vec::new()),
+            event_tx,
+            transport,
+            trust_store: None,
+            rate_limiter: None,
+            store: Mutex::new(None),
+        }
+    }
+    
+    /// Drop packets from devices the trust store has blocked
+    pub fn with_trust_store(mut self, trust_store: Arc<TrustStore>) -> Self {
+        self.trust_store = Some(trust_store);
+        self
+    }
+    
+    /// Drop messages and typing updates from devices sending too many
+    pub fn with_rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+    
+    /// Keep history in `store`, starting with what it already holds.
+    /// Messages from before it was attached are kept too.
+    pub fn attach_store(&self, store: ChatStore) -> Result<(), anyhow::Error> {
+        let mut stored = store.load()?;
+        {
+            let mut messages = self.messages.write().unwrap();
+            stored.retain(|old| !messages.iter().any(|m| m.id == old.id));
+            messages.splice(0..0, stored);
+            store.save(&messages)?;
+        }
+        *self.store.lock().unwrap() = Some(store);
+        Ok(())
+    }
+    
+    /// Whether the attached store is encrypted; None when there is no store
+    pub fn history_encrypted(&self) -> Option<bool> {
+        self.store.lock().unwrap().as_ref().map(ChatStore::is_encrypted)
+    }
+    
+    /// Encrypt the attached store under `key`, reporting (done, total)
+    pub fn encrypt_history(&self, key: &KeySource, progress: impl FnMut(usize, usize)) -> Result<(), anyhow::Error> {
+        let mut store = self.store.lock().unwrap();
+        let store = store
+            .as_mut()
+            .ok_or_else(|| DeskShareError::ChatHistoryLocked("no history store is open".to_string()))?;
+        store.encrypt(key, progress)?;
+        store.save(&self.messages.read().unwrap())
+    }
+    
+    /// Forget all history, in memory and on disk, and keep new messages in a
+    /// fresh unencrypted store at `path`. Nothing needs unlocking first.
+    pub fn reset_history(&self, path: &Path) -> Result<(), anyhow::Error> {
+        *self.store.lock().unwrap() = None;
+        self.messages.write().unwrap().clear();
+        ChatStore::reset(path)?;
+        self.attach_store(ChatStore::open(path, None)?)
+    }
+    
+    pub fn subscribe(&self) -> broadcast::Receiver<ChatEvent> {
+        self.event_tx.subscribe()
     }
     
     pub async fn send_message(
@@ -28,21 +166,221 @@ impl ChatService {
         to: Option<String>,
     ) -> Result<ChatMessage, anyhow::Error> {
         tracing::info!("Sending message to {:?}: {}", to, content);
-        // Implementation will be added
-        Ok(ChatMessage {
-            id: "msg_placeholder".to_string(),
-            from: "local".to_string(),
+        self.send(content, to, None).await
+    }
+    
+    pub async fn send_attachment(
+        &self,
+        attachment: ChatAttachment,
+        to: Option<String>,
+    ) -> Result<ChatMessage, anyhow::Error> {
+        tracing::info!("Sending attachment {} to {:?}", attachment.name, to);
+        self.send(attachment.name.clone(), to, Some(attachment)).await
+    }
+    
+    async fn send(
+        &self,
+        content: String,
+        to: Option<String>,
+        attachment: Option<ChatAttachment>,
+    ) -> Result<ChatMessage, anyhow::Error> {
+        let mut message = ChatMessage {
+            id: Self::generate_message_id(),
+            from: self.local_id.clone(),
             to,
             content,
             timestamp: std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs(),
-        })
+            attachment,
+            state: DeliveryState::Pending,
+        };
+        
+        self.messages.write().unwrap().push(message.clone());
+        self.persist();
+        let _ = self.event_tx.send(ChatEvent::MessageSent { message: message.clone() });
+        
+        // Without a transport the message stays pending until one is attached
+        if let Some(transport) = &self.transport {
+            let state = match transport
+                .send(&self.local_id, message.to.as_deref(), ChatPacket::Message(message.clone()))
+                .await
+            {
+                Ok(()) => DeliveryState::Sent,
+                Err(e) => {
+                    tracing::warn!("Failed to send message {}: {}", message.id, e);
+                    DeliveryState::Failed
+                }
+            };
+            // A fast peer may already have acknowledged delivery
+            self.advance_state(&message.id, state);
+            message.state = self.message_state(&message.id).unwrap_or(state);
+        }
+        
+        Ok(message)
+    }
+    
+    /// Handle a packet arriving from another peer
+    pub async fn receive_packet(&self, packet: ChatPacket) -> Result<(), anyhow::Error> {
+        match packet {
+            // Blocked senders are dropped before anything is stored or acknowledged
+            ChatPacket::Message(message) if self.refuses(&message.from) => {}
+            ChatPacket::Typing { from, .. } if self.refuses(&from) => {}
+            ChatPacket::Message(message) if !self.admits(&message.from) => {}
+            ChatPacket::Typing { from, .. } if !self.admits(&from) => {}
+            ChatPacket::Message(mut message) => {
+                message.state = DeliveryState::Delivered;
+                let sender = message.from.clone();
+                let message_id = message.id.clone();
+                
+                self.messages.write().unwrap().push(message.clone());
+                self.persist();
+                let _ = self.event_tx.send(ChatEvent::MessageReceived { message });
+                
+                if let Some(transport) = &self.transport {
+                    transport
+                        .send(&self.local_id, Some(&sender), ChatPacket::Delivered { message_id })
+                        .await?;
+                }
+            }
+            ChatPacket::Delivered { message_id } => {
+                self.advance_state(&message_id, DeliveryState::Delivered);
+            }
+            ChatPacket::Read { message_id } => {
+                self.advance_state(&message_id, DeliveryState::Read);
+            }
+            ChatPacket::Typing { from, is_typing } => {
+                let _ = self.event_tx.send(ChatEvent::Typing { peer_id: from, is_typing });
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn refuses(&self, peer_id: &str) -> bool {
+        self.trust_store
+            .as_ref()
+            .is_some_and(|trust_store| trust_store.refuses("chat", peer_id))
+    }
+    
+    fn admits(&self, peer_id: &str) -> bool {
+        self.rate_limiter
+            .as_ref()
+            .map(|rate_limiter| rate_limiter.admit(peer_id, ProtocolClass::Chat))
+            .unwrap_or(true)
+    }
+    
+    /// Mark a received message as read and tell its sender
+    pub async fn mark_read(&self, message_id: &str) -> Result<(), anyhow::Error> {
+        let sender = self
+            .messages
+            .read()
+            .unwrap()
+            .iter()
+            .find(|m| m.id == message_id && m.from != self.local_id)
+            .map(|m| m.from.clone())
+            .ok_or_else(|| DeskShareError::MessageNotFound(message_id.to_string()))?;
+        
+        self.advance_state(message_id, DeliveryState::Read);
+        
+        if let Some(transport) = &self.transport {
+            transport
+                .send(&self.local_id, Some(&sender), ChatPacket::Read { message_id: message_id.to_string() })
+                .await?;
+        }
+        
+        Ok(())
+    }
+    
+    pub async fn set_typing(&self, to: Option<String>, is_typing: bool) -> Result<(), anyhow::Error> {
+        if let Some(transport) = &self.transport {
+            let packet = ChatPacket::Typing { from: self.local_id.clone(), is_typing };
+            transport.send(&self.local_id, to.as_deref(), packet).await?;
+        }
+        
+        Ok(())
     }
     
     pub async fn get_messages(&self) -> Vec<ChatMessage> {
-        // Implementation will be added
-        Vec::new()
+        self.messages.read().unwrap().clone()
+    }
+    
+    /// Oldest-first page of the conversation matching `filter`
+    pub fn get_history(&self, filter: &MessageFilter) -> Vec<ChatMessage> {
+        let messages = self.messages.read().unwrap();
+        
+        let end = match &filter.before {
+            Some(before) => messages.iter().position(|m| &m.id == before).unwrap_or(0),
+            None => messages.len(),
+        };
+        
+        let mut page: Vec<ChatMessage> = messages[..end]
+            .iter()
+            .rev()
+            .filter(|m| match &filter.peer_id {
+                Some(peer) => &m.from == peer || m.to.as_ref() == Some(peer),
+                None => true,
+            })
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect();
+        page.reverse();
+        page
+    }
+    
+    fn message_state(&self, message_id: &str) -> Option<DeliveryState> {
+        self.messages
+            .read()
+            .unwrap()
+            .iter()
+            .find(|m| m.id == message_id)
+            .map(|m| m.state)
+    }
+    
+    /// Move a message forward through Pending → Sent → Delivered → Read; never backwards
+    fn advance_state(&self, message_id: &str, state: DeliveryState) {
+        let changed = {
+            let mut messages = self.messages.write().unwrap();
+            match messages.iter_mut().find(|m| m.id == message_id) {
+                Some(message) if Self::rank(state) > Self::rank(message.state) => {
+                    message.state = state;
+                    true
+                }
+                _ => false,
+            }
+        };
+        
+        if changed {
+            self.persist();
+            let _ = self.event_tx.send(ChatEvent::StateChanged {
+                message_id: message_id.to_string(),
+                state,
+            });
+        }
+    }
+    
+    fn persist(&self) {
+        if let Some(store) = self.store.lock().unwrap().as_ref() {
+            if let Err(e) = store.save(&self.messages.read().unwrap()) {
+                tracing::warn!("Couldn't save chat history: {}", e);
+            }
+        }
+    }
+    
+    fn rank(state: DeliveryState) -> u8 {
+        match state {
+            DeliveryState::Pending => 0,
+            DeliveryState::Failed => 1,
+            DeliveryState::Sent => 2,
+            DeliveryState::Delivered => 3,
+            DeliveryState::Read => 4,
+        }
+    }
+    
+    fn generate_message_id() -> String {
+        use rand::Rng;
+        let mut rng = rand::thread_rng();
+        format!("{:x}", rng.gen::<u128>())
     }
 }
@@ -0,0 +1,380 @@
+// Chat history on disk
+// Messages are kept in one JSON file in the config directory. Once encrypted,
+// message text and attachments are sealed with ChaCha20-Poly1305 under a key
+// from the OS keychain or a passphrase. Ids, peers and timestamps stay
+// readable, so a store whose key is lost can still be reset.
+
+use std::path::{Path, PathBuf};
+use anyhow::Error;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+
+use crate::error::DeskShareError;
+use super::chat::{ChatAttachment, ChatMessage, DeliveryState};
+
+const KEYCHAIN_SERVICE: &str = "desk-share-net";
+const KEYCHAIN_ACCOUNT: &str = "chat-history";
+const KEYCHAIN_KEY_CONTEXT: &str = "desk-share-net chat history key v1";
+/// Sealed into the header so a wrong key is caught before any message
+const VERIFIER: &[u8] = b"desk-share-net chat history v1";
+
+/// Where the encryption key comes from
+#[derive(Clone, Debug)]
+pub enum KeySource {
+    /// A random secret kept in the OS keychain
+    Keychain,
+    Passphrase(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyKind {
+    Keychain,
+    Passphrase,
+}
+
+impl KeySource {
+    fn kind(&self) -> KeyKind {
+        match self {
+            KeySource::Keychain => KeyKind::Keychain,
+            KeySource::Passphrase(_) => KeyKind::Passphrase,
+        }
+    }
+    
+    fn derive(&self, salt: &[u8], create: bool) -> Result<ChaCha20Poly1305, Error> {
+        let mut key = [0u8; 32];
+        match self {
+            KeySource::Keychain => {
+                let secret = keychain_secret(create)?;
+                key = blake3::derive_key(KEYCHAIN_KEY_CONTEXT, &[secret.as_slice(), salt].concat());
+            }
+            KeySource::Passphrase(passphrase) => {
+                argon2::Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+                    .map_err(|e| DeskShareError::Internal(e.to_string()))?;
+            }
+        }
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+}
+
+fn keychain_secret(create: bool) -> Result<Vec<u8>, Error> {
+    let entry = keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT).map_err(locked)?;
+    match entry.get_password() {
+        Ok(secret) => Ok(hex::decode(secret).map_err(locked)?),
+        Err(keyring::Error::NoEntry) if create => {
+            let mut secret = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut secret);
+            entry.set_password(&hex::encode(secret)).map_err(locked)?;
+            Ok(secret.to_vec())
+        }
+        Err(e) => Err(locked(e)),
+    }
+}
+
+fn locked(e: impl std::fmt::Display) -> Error {
+    DeskShareError::ChatHistoryLocked(e.to_string()).into()
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Sealed {
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Encryption {
+    kind: KeyKind,
+    salt: String,
+    verifier: Sealed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Body {
+    Plain {
+        content: String,
+        attachment: Option<ChatAttachment>,
+    },
+    Sealed(Sealed),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct StoredMessage {
+    id: String,
+    from: String,
+    to: Option<String>,
+    timestamp: u64,
+    state: DeliveryState,
+    body: Body,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct StoreFile {
+    encryption: Option<Encryption>,
+    messages: Vec<StoredMessage>,
+}
+
+pub struct ChatStore {
+    path: PathBuf,
+    encryption: Option<(Encryption, ChaCha20Poly1305)>,
+}
+
+impl ChatStore {
+    /// Open the history at `path`. An encrypted store needs its key: a
+    /// keychain store finds it by itself, a passphrase store needs `key`.
+    pub fn open(path: &Path, key: Option<&KeySource>) -> Result<Self, Error> {
+        let file = Self::read(path)?;
+        let encryption = match file.encryption {
+            None => None,
+            Some(encryption) => {
+                let key = match (key, encryption.kind) {
+                    (Some(key), kind) if key.kind() == kind => key.clone(),
+                    (None, KeyKind::Keychain) => KeySource::Keychain,
+                    (_, KeyKind::Keychain) => return Err(locked("history is locked by the keychain, not a passphrase")),
+                    (_, KeyKind::Passphrase) => return Err(locked("a passphrase is needed")),
+                };
+                let cipher = key.derive(&hex::decode(&encryption.salt)?, false)?;
+                open_sealed(&cipher, &encryption.verifier, b"verifier")
+                    .map_err(|_| locked("wrong passphrase or keychain secret"))?;
+                Some((encryption, cipher))
+            }
+        };
+        
+        Ok(Self {
+            path: path.to_path_buf(),
+            encryption,
+        })
+    }
+    
+    /// Key kind the store at `path` is encrypted under, if any
+    pub fn key_kind(path: &Path) -> Result<Option<KeyKind>, Error> {
+        Ok(Self::read(path)?.encryption.map(|encryption| encryption.kind))
+    }
+    
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+    
+    pub fn load(&self) -> Result<Vec<ChatMessage>, Error> {
+        Self::read(&self.path)?
+            .messages
+            .into_iter()
+            .map(|stored| self.unseal(stored))
+            .collect()
+    }
+    
+    pub fn save(&self, messages: &[ChatMessage]) -> Result<(), Error> {
+        let file = StoreFile {
+            encryption: self.encryption.as_ref().map(|(encryption, _)| encryption.clone()),
+            messages: messages.iter().map(|message| self.seal(message)).collect::<Result<_, _>>()?,
+        };
+        Self::write(&self.path, &file)
+    }
+    
+    /// Encrypt the history under `key`, re-sealing every message already
+    /// stored; `progress` gets (done, total) as it goes. The file is only
+    /// replaced once everything is re-sealed.
+    pub fn encrypt(&mut self, key: &KeySource, mut progress: impl FnMut(usize, usize)) -> Result<(), Error> {
+        let messages = self.load()?;
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let cipher = key.derive(&salt, true)?;
+        let encryption = Encryption {
+            kind: key.kind(),
+            salt: hex::encode(salt),
+            verifier: seal(&cipher, VERIFIER, b"verifier")?,
+        };
+        let sealer = Self {
+            path: self.path.clone(),
+            encryption: Some((encryption.clone(), cipher)),
+        };
+        
+        let total = messages.len();
+        let mut sealed = Vec::with_capacity(total);
+        progress(0, total);
+        for (done, message) in messages.iter().enumerate() {
+            sealed.push(sealer.seal(message)?);
+            progress(done + 1, total);
+        }
+        
+        let staging = self.path.with_extension("tmp");
+        Self::write(&staging, &StoreFile { encryption: Some(encryption), messages: sealed })?;
+        std::fs::rename(&staging, &self.path)?;
+        *self = sealer;
+        tracing::info!("Chat history encrypted ({} messages)", total);
+        Ok(())
+    }
+    
+    /// Discard the history at `path` without reading it; the way out when the
+    /// key is lost
+    pub fn reset(path: &Path) -> Result<(), Error> {
+        match std::fs::remove_file(path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        tracing::warn!("Chat history at {} was reset", path.display());
+        Ok(())
+    }
+    
+    fn seal(&self, message: &ChatMessage) -> Result<StoredMessage, Error> {
+        let body = match &self.encryption {
+            None => Body::Plain {
+                content: message.content.clone(),
+                attachment: message.attachment.clone(),
+            },
+            Some((_, cipher)) => {
+                let plaintext = serde_json::to_vec(&(&message.content, &message.attachment))?;
+                Body::Sealed(seal(cipher, &plaintext, message.id.as_bytes())?)
+            }
+        };
+        Ok(StoredMessage {
+            id: message.id.clone(),
+            from: message.from.clone(),
+            to: message.to.clone(),
+            timestamp: message.timestamp,
+            state: message.state,
+            body,
+        })
+    }
+    
+    fn unseal(&self, stored: StoredMessage) -> Result<ChatMessage, Error> {
+        let (content, attachment) = match (stored.body, &self.encryption) {
+            (Body::Plain { content, attachment }, _) => (content, attachment),
+            (Body::Sealed(sealed), Some((_, cipher))) => {
+                serde_json::from_slice(&open_sealed(cipher, &sealed, stored.id.as_bytes())?)?
+            }
+            (Body::Sealed(_), None) => return Err(locked("message is sealed but the store has no key")),
+        };
+        Ok(ChatMessage {
+            id: stored.id,
+            from: stored.from,
+            to: stored.to,
+            content,
+            timestamp: stored.timestamp,
+            attachment,
+            state: stored.state,
+        })
+    }
+    
+    fn read(path: &Path) -> Result<StoreFile, Error> {
+        match std::fs::read(path) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(StoreFile::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+    
+    fn write(path: &Path, file: &StoreFile) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec(file)?)?;
+        Ok(())
+    }
+}
+
+/// Seal `plaintext` bound to `aad`, so a body can't be moved to another message
+fn seal(cipher: &ChaCha20Poly1305, plaintext: &[u8], aad: &[u8]) -> Result<Sealed, Error> {
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+        .map_err(|e| DeskShareError::Internal(e.to_string()))?;
+    Ok(Sealed {
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })
+}
+
+fn open_sealed(cipher: &ChaCha20Poly1305, sealed: &Sealed, aad: &[u8]) -> Result<Vec<u8>, Error> {
+    let nonce = hex::decode(&sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err(locked("malformed nonce"));
+    }
+    let ciphertext = hex::decode(&sealed.ciphertext)?;
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad })
+        .map_err(|_| locked("message failed to decrypt"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn message(id: &str, content: &str) -> ChatMessage {
+        ChatMessage {
+            id: id.to_string(),
+            from: "10.0.0.3".to_string(),
+            to: None,
+            content: content.to_string(),
+            timestamp: 1_700_000_000,
+            attachment: Some(ChatAttachment {
+                file_hash: "abc123".to_string(),
+                name: "quarterly-figures.xlsx".to_string(),
+                size: 2048,
+            }),
+            state: DeliveryState::Delivered,
+        }
+    }
+    
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle.as_bytes())
+    }
+    
+    fn locked_code(err: Error) -> &'static str {
+        err.downcast_ref::<DeskShareError>().unwrap().code()
+    }
+    
+    #[test]
+    fn test_encrypted_store_needs_its_key() {
+        let path = std::env::temp_dir().join(format!("desk-share-chat-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let messages = vec![message("m1", "the merger closes friday"), message("m2", "don't tell anyone")];
+        
+        // An existing plaintext history is migrated in place
+        let mut store = ChatStore::open(&path, None).unwrap();
+        store.save(&messages).unwrap();
+        assert!(contains(&std::fs::read(&path).unwrap(), "the merger closes friday"));
+        
+        let passphrase = KeySource::Passphrase("correct horse battery staple".to_string());
+        let mut reported = Vec::new();
+        store.encrypt(&passphrase, |done, total| reported.push((done, total))).unwrap();
+        assert_eq!(reported, [(0, 2), (1, 2), (2, 2)]);
+        store.save(&[messages.clone(), vec![message("m3", "board meets at nine")]].concat()).unwrap();
+        
+        let bytes = std::fs::read(&path).unwrap();
+        for secret in ["the merger closes friday", "don't tell anyone", "board meets at nine", "quarterly-figures"] {
+            assert!(!contains(&bytes, secret), "{:?} is in the file", secret);
+        }
+        assert_eq!(ChatStore::key_kind(&path).unwrap(), Some(KeyKind::Passphrase));
+        
+        let reopened = ChatStore::open(&path, Some(&passphrase)).unwrap();
+        let loaded = reopened.load().unwrap();
+        assert_eq!(loaded.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), [
+            "the merger closes friday",
+            "don't tell anyone",
+            "board meets at nine",
+        ]);
+        assert_eq!(loaded[0].attachment, messages[0].attachment);
+        
+        let wrong = KeySource::Passphrase("incorrect horse".to_string());
+        assert_eq!(locked_code(ChatStore::open(&path, Some(&wrong)).err().unwrap()), "chat_history_locked");
+        assert_eq!(locked_code(ChatStore::open(&path, None).err().unwrap()), "chat_history_locked");
+        
+        // A body moved onto another message doesn't open
+        let mut file: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        file["messages"][0]["body"] = file["messages"][1]["body"].clone();
+        std::fs::write(&path, serde_json::to_vec(&file).unwrap()).unwrap();
+        assert_eq!(locked_code(reopened.load().unwrap_err()), "chat_history_locked");
+        
+        // Reset is the way out without the key
+        ChatStore::reset(&path).unwrap();
+        let fresh = ChatStore::open(&path, None).unwrap();
+        assert!(!fresh.is_encrypted());
+        assert!(fresh.load().unwrap().is_empty());
+    }
+}
@@ -1,41 +1,488 @@
 // File transfer service
 // Simplified interface for file sharing
 
-use std::path::Path;
-use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use bytes::Bytes;
+use tokio::sync::broadcast;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct SharedFile {
-    pub hash: String,
-    pub name: String,
-    pub size: u64,
-    pub peer_id: String,
-}
+use crate::network::{
+    self, ChunkCacheUsage, ChunkOrder, ChunkTransport, ChunkWindowConfig, CollisionPolicy, DirEntryInfo, HashAlgorithm, IgnoreRules, LanChunkTransport, MerkleProof, MultiSendProgress, OfferEvent, PendingOffer, PeerTransferStats, PeerWindowState, ReceivedText, RemoteFile, ShareRegistry, SharedDirectory, SharedFileSummary,
+    SignedAnnouncement, SignedRetraction, StageTimings, TaggedChunk, TransferHistory, TransferHistoryEntry, TransferObserver, TransferProgress,
+    TransferRecord, TransferStatus, UploadProgress,
+};
+use crate::config::AutoAcceptConfig;
+use crate::p2p::LanStream;
+use crate::security::{DeviceIdentity, PeerIdentity, RateLimiter, SecureChannel, SecurityConfig, TrustStore};
 
+/// Clones share the same transfers
+#[derive(Clone)]
 pub struct FileTransfer {
-    // Internal implementation will be added later
+    inner: network::FileTransfer,
 }
 
 impl FileTransfer {
     pub async fn new() -> Self {
         tracing::info!("FileTransfer service initialized");
-        Self {}
+        Self {
+            inner: network::FileTransfer::new().await,
+        }
+    }
+    
+    pub fn with_trust_store(self, trust_store: Arc<TrustStore>) -> Self {
+        Self {
+            inner: self.inner.with_trust_store(trust_store),
+        }
+    }
+    
+    pub fn with_rate_limiter(self, rate_limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            inner: self.inner.with_rate_limiter(rate_limiter),
+        }
+    }
+    
+    pub fn with_identity(self, identity: Arc<DeviceIdentity>) -> Self {
+        Self {
+            inner: self.inner.with_identity(identity),
+        }
+    }
+    
+    pub fn with_security_config(self, config: SecurityConfig) -> Self {
+        Self {
+            inner: self.inner.with_security_config(config),
+        }
+    }
+    
+    pub fn with_auto_accept(self, config: AutoAcceptConfig) -> Self {
+        Self {
+            inner: self.inner.with_auto_accept(config),
+        }
+    }
+    
+    pub fn with_collision_policy(self, policy: CollisionPolicy) -> Self {
+        Self {
+            inner: self.inner.with_collision_policy(policy),
+        }
+    }
+    
+    pub fn with_chunk_order(self, order: ChunkOrder) -> Self {
+        Self {
+            inner: self.inner.with_chunk_order(order),
+        }
+    }
+    
+    pub fn with_share_registry(self, registry: Arc<ShareRegistry>) -> Self {
+        Self {
+            inner: self.inner.with_share_registry(registry),
+        }
+    }
+    
+    pub fn with_hash_algorithm(self, algorithm: HashAlgorithm) -> Self {
+        Self {
+            inner: self.inner.with_hash_algorithm(algorithm),
+        }
+    }
+    
+    pub fn with_chunk_cache_limit(self, bytes: u64) -> Self {
+        Self {
+            inner: self.inner.with_chunk_cache_limit(bytes),
+        }
+    }
+    
+    pub fn with_transfer_history(self, history: Arc<TransferHistory>) -> Self {
+        Self {
+            inner: self.inner.with_transfer_history(history),
+        }
+    }
+    
+    pub fn with_chunk_transport(self, transport: Arc<dyn ChunkTransport>) -> Self {
+        Self {
+            inner: self.inner.with_chunk_transport(transport),
+        }
+    }
+    
+    pub fn with_lan_transport(self, transport: Arc<LanChunkTransport>) -> Self {
+        Self {
+            inner: self.inner.with_lan_transport(transport),
+        }
+    }
+    
+    pub fn with_chunk_windows(self, config: ChunkWindowConfig) -> Self {
+        Self {
+            inner: self.inner.with_chunk_windows(config),
+        }
+    }
+    
+    pub fn with_chunk_size(self, chunk_size: usize) -> Self {
+        Self {
+            inner: self.inner.with_chunk_size(chunk_size),
+        }
+    }
+    
+    pub fn signed_announcement(&self, file_hash: &str) -> Result<SignedAnnouncement, anyhow::Error> {
+        self.inner.signed_announcement(file_hash)
+    }
+    
+    pub async fn handle_announcement(&self, from_peer: String, announcement: SignedAnnouncement) -> Result<(), anyhow::Error> {
+        self.inner.handle_announcement(from_peer, announcement).await
+    }
+    
+    pub async fn handle_retraction(&self, from_peer: String, retraction: SignedRetraction) -> Result<(), anyhow::Error> {
+        self.inner.handle_retraction(from_peer, retraction).await
+    }
+    
+    pub fn subscribe_retractions(&self) -> broadcast::Receiver<SignedRetraction> {
+        self.inner.subscribe_retractions()
+    }
+    
+    pub async fn handle_chunk_request(&self, chunk_hash: &str, from: String) -> Result<(), anyhow::Error> {
+        self.inner.handle_chunk_request(chunk_hash, from).await
+    }
+    
+    pub async fn handle_incoming_message(&self, peer_id: &str, message: impl Into<Bytes>) -> Result<Option<Bytes>, anyhow::Error> {
+        self.inner.handle_incoming_message(peer_id, message).await
+    }
+    
+    pub async fn receive_chunk(&self, file_hash: &str, chunk_index: usize, data: impl Into<Bytes>) -> Result<(), anyhow::Error> {
+        self.inner.receive_chunk(file_hash, chunk_index, data).await
+    }
+    
+    pub fn set_transfer_key<S>(&self, file_hash: &str, peer_id: &str, channel: &SecureChannel<S>) {
+        self.inner.set_transfer_key(file_hash, peer_id, channel)
+    }
+    
+    pub fn tag_chunk(&self, peer_id: &str, file_hash: &str, index: usize, data: impl Into<Bytes>) -> Result<TaggedChunk, anyhow::Error> {
+        self.inner.tag_chunk(peer_id, file_hash, index, data)
+    }
+    
+    pub async fn receive_tagged_chunk(&self, from_peer: &str, chunk: TaggedChunk) -> Result<(), anyhow::Error> {
+        self.inner.receive_tagged_chunk(from_peer, chunk).await
     }
     
     pub async fn share_file(&self, path: &Path) -> Result<String, anyhow::Error> {
         tracing::info!("Sharing file: {:?}", path);
-        // Implementation will be added
-        Ok("file_hash_placeholder".to_string())
+        // Use a default peer ID for now
+        let peer_id = "local".to_string();
+        self.inner.share_file(path, peer_id).await
+    }
+    
+    /// Share a file whose hashes are taken with `algorithm`
+    pub async fn share_file_with_hash_algorithm(&self, path: &Path, algorithm: HashAlgorithm) -> Result<String, anyhow::Error> {
+        tracing::info!("Sharing file: {:?} hashed with {:?}", path, algorithm);
+        let peer_id = "local".to_string();
+        self.inner.share_file_with_hash_algorithm(path, peer_id, algorithm).await
+    }
+    
+    /// Share a file until `ttl` has passed; without one it's shared for good
+    pub async fn share_file_with_ttl(&self, path: &Path, ttl: Option<Duration>) -> Result<String, anyhow::Error> {
+        tracing::info!("Sharing file: {:?} for {:?}", path, ttl);
+        let peer_id = "local".to_string();
+        self.inner.share_file_with_ttl(path, peer_id, ttl).await
+    }
+    
+    pub fn set_share_ttl(&self, file_hash: &str, ttl: Option<Duration>) -> Result<(), anyhow::Error> {
+        self.inner.set_share_ttl(file_hash, ttl)
+    }
+    
+    /// Unshare whatever has outlived its time to live
+    pub async fn reap_expired_shares(&self) -> Vec<String> {
+        self.inner.reap_expired_shares().await
+    }
+    
+    /// Share a folder with its layout; returns the hash it's known by
+    pub async fn share_directory(&self, path: &Path) -> Result<String, anyhow::Error> {
+        tracing::info!("Sharing folder: {:?}", path);
+        let peer_id = "local".to_string();
+        self.inner.share_directory(path, peer_id).await
+    }
+    
+    /// Share a folder without the entries `rules` match
+    pub async fn share_directory_with_rules(&self, path: &Path, rules: &IgnoreRules) -> Result<SharedDirectory, anyhow::Error> {
+        tracing::info!("Sharing folder: {:?}, ignoring {:?}", path, rules.patterns);
+        let peer_id = "local".to_string();
+        self.inner.share_directory_with_rules(path, peer_id, rules).await
+    }
+    
+    /// Share a snippet of text under `title`; returns its hash
+    pub async fn share_text(&self, content: String, title: String) -> Result<String, anyhow::Error> {
+        tracing::info!("Sharing text: {}", title);
+        let peer_id = "local".to_string();
+        self.inner.share_text(content, title, peer_id).await
     }
     
     pub async fn download_file(&self, file_hash: &str, output_path: &Path) -> Result<(), anyhow::Error> {
         tracing::info!("Downloading file {} to {:?}", file_hash, output_path);
-        // Implementation will be added
-        Ok(())
+        self.inner.download_file(file_hash, output_path).await
+    }
+    
+    pub async fn download_file_with_policy(&self, file_hash: &str, output_path: &Path, policy: CollisionPolicy) -> Result<(), anyhow::Error> {
+        self.inner.download_file_with_policy(file_hash, output_path, policy).await
+    }
+    
+    /// Offer a file to a device and stay connected until it has all of it
+    pub async fn send_file_to_device(&self, device_ip: &str, file_path: &str) -> Result<(), anyhow::Error> {
+        tracing::info!("Sending {} to {}", file_path, device_ip);
+        self.inner.send_file_to_device(device_ip, file_path).await
+    }
+    
+    /// Offer a file to several devices at once; one failing leaves the rest going
+    pub async fn send_file_to_devices(&self, device_ips: Vec<String>, file_path: &str) -> Result<MultiSendProgress, anyhow::Error> {
+        tracing::info!("Sending {} to {} devices", file_path, device_ips.len());
+        self.inner.send_file_to_devices(device_ips, file_path).await
+    }
+    
+    pub async fn send_share_to_devices(&self, device_ips: Vec<String>, file_hash: &str) -> Result<MultiSendProgress, anyhow::Error> {
+        self.inner.send_share_to_devices(device_ips, file_hash).await
+    }
+    
+    pub fn get_multi_send_progress(&self, file_hash: &str) -> Option<MultiSendProgress> {
+        self.inner.get_multi_send_progress(file_hash)
+    }
+    
+    pub async fn send_text_to_device(&self, device_ip: &str, content: String, title: String) -> Result<(), anyhow::Error> {
+        tracing::info!("Sending text {} to {}", title, device_ip);
+        self.inner.send_text_to_device(device_ip, content, title).await
+    }
+    
+    /// Offer a file to a device and return its hash once the offer has
+    /// arrived, serving the file in the background
+    pub async fn send_offer(&self, device_ip: &str, file_path: &str) -> Result<String, anyhow::Error> {
+        tracing::info!("Offering {} to {}", file_path, device_ip);
+        self.inner.send_offer(device_ip, file_path).await
+    }
+    
+    /// Carry on with downloads a previous run left unfinished
+    pub async fn resume_pending_downloads(&self) -> Result<Vec<String>, anyhow::Error> {
+        self.inner.resume_pending_downloads().await
+    }
+    
+    pub async fn restore_pending_downloads(&self) -> Result<Vec<String>, anyhow::Error> {
+        self.inner.restore_pending_downloads().await
+    }
+    
+    pub async fn restore_shares(&self) -> Vec<String> {
+        self.inner.restore_shares().await
+    }
+    
+    pub async fn accept_transfer(&self, stream: LanStream, peer_id: String) -> Result<String, anyhow::Error> {
+        self.inner.accept_transfer(stream, peer_id).await
+    }
+    
+    pub async fn get_transfer_progress(&self) -> Vec<TransferProgress> {
+        self.inner.get_transfer_progress().await
+    }
+    
+    pub async fn wait_for_transfer(&self, file_hash: &str) -> Option<TransferStatus> {
+        self.inner.wait_for_transfer(file_hash).await
+    }
+    
+    pub fn get_upload_progress(&self) -> Vec<UploadProgress> {
+        self.inner.get_upload_progress()
+    }
+    
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<TransferProgress> {
+        self.inner.subscribe_progress()
+    }
+    
+    pub fn add_observer(&self, observer: Box<dyn TransferObserver>) {
+        self.inner.add_observer(observer)
+    }
+    
+    pub async fn pause_transfer(&self, file_hash: &str) -> Result<TransferStatus, anyhow::Error> {
+        self.inner.pause_transfer(file_hash).await
+    }
+    
+    pub async fn resume_transfer(&self, file_hash: &str) -> Result<TransferStatus, anyhow::Error> {
+        self.inner.resume_transfer(file_hash).await
+    }
+    
+    pub async fn cancel_transfer(&self, file_hash: &str) -> Result<TransferStatus, anyhow::Error> {
+        self.inner.cancel_transfer(file_hash).await
+    }
+    
+    /// Clear a failed or cancelled transfer off the list
+    pub async fn dismiss_transfer(&self, file_hash: &str) -> Result<(), anyhow::Error> {
+        self.inner.dismiss_transfer(file_hash).await
+    }
+    
+    pub async fn receive_offer(
+        &self,
+        from_peer: String,
+        sender_name: String,
+        file: network::SharedFile,
+    ) -> String {
+        self.inner.receive_offer(from_peer, sender_name, file).await
+    }
+    
+    pub async fn receive_authenticated_offer(
+        &self,
+        from: PeerIdentity,
+        sender_name: String,
+        file: network::SharedFile,
+    ) -> String {
+        self.inner.receive_authenticated_offer(from, sender_name, file).await
+    }
+    
+    pub async fn accept_offer(&self, offer_id: &str, output_dir: &Path) -> Result<String, anyhow::Error> {
+        self.inner.accept_offer(offer_id, output_dir).await
+    }
+    
+    pub async fn reject_offer(&self, offer_id: &str, reason: &str) -> Result<(), anyhow::Error> {
+        self.inner.reject_offer(offer_id, reason).await
+    }
+    
+    pub fn get_pending_offers(&self) -> Vec<PendingOffer> {
+        self.inner.get_pending_offers()
+    }
+    
+    pub fn subscribe_offers(&self) -> broadcast::Receiver<OfferEvent> {
+        self.inner.subscribe_offers()
+    }
+    
+    pub fn received_texts(&self) -> Vec<ReceivedText> {
+        self.inner.received_texts()
+    }
+    
+    pub fn dismiss_text(&self, file_hash: &str) -> Result<(), anyhow::Error> {
+        self.inner.dismiss_text(file_hash)
+    }
+    
+    pub fn subscribe_texts(&self) -> broadcast::Receiver<ReceivedText> {
+        self.inner.subscribe_texts()
+    }
+    
+    pub fn set_offer_timeout(&self, timeout: Duration) {
+        self.inner.set_offer_timeout(timeout)
+    }
+    
+    pub fn downloads_dir(&self) -> PathBuf {
+        self.inner.downloads_dir()
+    }
+    
+    pub fn set_upload_limit(&self, bytes_per_second: u64) {
+        self.inner.set_upload_limit(bytes_per_second)
+    }
+    
+    pub fn set_download_limit(&self, bytes_per_second: u64) {
+        self.inner.set_download_limit(bytes_per_second)
+    }
+    
+    pub fn set_max_concurrent_transfers(&self, max: usize) {
+        self.inner.set_max_concurrent_transfers(max)
+    }
+    
+    pub fn set_chunk_cache_limit(&self, bytes: u64) {
+        self.inner.set_chunk_cache_limit(bytes)
+    }
+    
+    pub fn chunk_cache_usage(&self) -> ChunkCacheUsage {
+        self.inner.chunk_cache_usage()
+    }
+    
+    pub fn reorder_queue(&self, file_hash: &str, position: usize) -> Result<(), anyhow::Error> {
+        self.inner.reorder_queue(file_hash, position)
+    }
+    
+    pub fn queued_transfers(&self) -> Vec<String> {
+        self.inner.queued_transfers()
+    }
+    
+    pub fn set_downloads_dir(&self, dir: PathBuf) -> Result<(), anyhow::Error> {
+        self.inner.set_downloads_dir(dir)
+    }
+    
+    pub async fn transfer_history(&self) -> Vec<TransferHistoryEntry> {
+        self.inner.transfer_history().await
+    }
+    
+    pub fn get_transfer_history(&self, limit: usize, offset: usize) -> Result<Vec<TransferRecord>, anyhow::Error> {
+        self.inner.get_transfer_history(limit, offset)
+    }
+    
+    pub fn clear_history(&self) -> Result<(), anyhow::Error> {
+        self.inner.clear_history()
+    }
+    
+    pub fn hash_timings(&self) -> StageTimings {
+        self.inner.hash_timings()
+    }
+    
+    pub fn chunk_proof(&self, file_hash: &str, index: usize) -> Result<MerkleProof, anyhow::Error> {
+        self.inner.chunk_proof(file_hash, index)
+    }
+    
+    /// Outstanding chunk requests allowed per peer, for transfer diagnostics
+    pub fn chunk_windows(&self) -> Vec<PeerWindowState> {
+        self.inner.chunk_windows()
+    }
+    
+    /// Totals per device, to spot the slow one
+    pub fn get_peer_stats(&self) -> Vec<PeerTransferStats> {
+        self.inner.get_peer_stats()
+    }
+    
+    pub fn reset_peer_stats(&self) {
+        self.inner.reset_peer_stats()
+    }
+    
+    pub async fn chunk_attempts(&self, file_hash: &str) -> HashMap<usize, u32> {
+        self.inner.chunk_attempts(file_hash).await
+    }
+    
+    pub fn shareable_roots(&self) -> Vec<PathBuf> {
+        self.inner.shareable_roots()
+    }
+    
+    pub fn set_shareable_roots(&self, roots: Vec<PathBuf>) {
+        self.inner.set_shareable_roots(roots)
+    }
+    
+    pub fn add_shareable_root(&self, root: &Path) -> Result<bool, anyhow::Error> {
+        self.inner.add_shareable_root(root)
+    }
+    
+    pub fn check_shareable(&self, path: &Path) -> Result<PathBuf, anyhow::Error> {
+        self.inner.check_shareable(path)
+    }
+    
+    pub async fn list_files_in_directory(&self, path: &str, show_hidden: bool) -> Result<Vec<DirEntryInfo>, anyhow::Error> {
+        self.inner.list_files_in_directory(path, show_hidden).await
+    }
+    
+    pub async fn unshare_file(&self, file_hash: &str, force: bool) -> Result<(), anyhow::Error> {
+        tracing::info!("Unsharing file: {}", file_hash);
+        self.inner.unshare_file(file_hash, force).await
+    }
+    
+    pub async fn unshare_path(&self, path: &Path, force: bool) -> Result<(), anyhow::Error> {
+        tracing::info!("Unsharing {:?}", path);
+        self.inner.unshare_path(path, force).await
+    }
+    
+    pub fn get_shared_files(&self) -> Vec<SharedFileSummary> {
+        self.inner.list_shared_files()
+    }
+    
+    pub fn get_shared_file(&self, file_hash: &str) -> Option<network::SharedFile> {
+        self.inner.get_shared_file(file_hash)
+    }
+    
+    pub fn get_remote_files(&self, peer_id: &str) -> Vec<RemoteFile> {
+        self.inner.get_remote_files(peer_id)
+    }
+    
+    pub fn search_remote_files(&self, query: &str) -> Vec<RemoteFile> {
+        self.inner.search_remote_files(query)
+    }
+    
+    pub async fn forget_remote_peer(&self, peer_id: &str) {
+        self.inner.forget_remote_peer(peer_id).await
     }
     
-    pub fn get_shared_files(&self) -> Vec<SharedFile> {
-        // Implementation will be added
-        Vec::new()
+    /// Peers a file can be fetched from, for transfer diagnostics
+    pub async fn get_peers_for_file(&self, file_hash: &str) -> Vec<String> {
+        self.inner.get_peers_for_file(file_hash).await
     }
 }
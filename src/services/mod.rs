@@ -4,8 +4,10 @@
 pub mod file_share;
 pub mod screen_share;
 pub mod chat;
+pub mod chat_store;
 
 // Re-export service types
 pub use file_share::FileTransfer;
 pub use screen_share::ScreenShare;
-pub use chat::ChatService;
+pub use chat::{ChatAttachment, ChatEvent, ChatMessage, ChatPacket, ChatService, ChatTransport, DeliveryState, MessageFilter};
+pub use chat_store::{ChatStore, KeyKind, KeySource};
@@ -1,7 +1,19 @@
 // Screen sharing service
 // Simplified interface for screen capture and streaming
 
+use std::sync::Arc;
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
+use tokio::sync::broadcast;
+
+use crate::network::{
+    self, AccessMode, AdaptiveConfig, BufferUsage, CaptureConfig, ControlMessage, Frame, FrameBufferConfig, InputEvent, JoinRequest, JoinResponse,
+    LanAnnouncer, LanFrameLinks, LanSessionTransport, MonitorLost, Participant, ParticipantChange, PendingJoin, QualityPreset, RemoteSession, SessionAnnouncement, SessionBeacon, SessionEnded, SessionStats, SessionStatusChange, SessionSummary, SessionTransport, StageTimings, StreamOverrides, TokenGrant,
+};
+use crate::p2p::LanStream;
+use crate::platform::input::InputBackend;
+use crate::platform::{CaptureBackend, CaptureRegion, MonitorInfo};
+use crate::security::{PeerIdentity, RateLimiter, TrustStore};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SharingSession {
@@ -11,35 +23,328 @@ pub struct SharingSession {
     pub resolution: (u32, u32),
 }
 
+/// Clones share the same sessions
+#[derive(Clone)]
 pub struct ScreenShare {
-    // Internal implementation will be added later
+    inner: network::ScreenShare,
 }
 
 impl ScreenShare {
     pub async fn new() -> Self {
         tracing::info!("ScreenShare service initialized");
-        Self {}
+        Self {
+            inner: network::ScreenShare::new().await,
+        }
+    }
+    
+    pub fn with_capture_backend(capture: Arc<dyn CaptureBackend>) -> Self {
+        Self {
+            inner: network::ScreenShare::with_capture_backend(capture),
+        }
+    }
+    
+    pub fn with_transport(self, local_peer_id: String, transport: Arc<dyn SessionTransport>) -> Self {
+        Self {
+            inner: self.inner.with_transport(local_peer_id, transport),
+        }
+    }
+    
+    pub fn with_lan_transport(self, local_peer_id: String, transport: Arc<LanSessionTransport>) -> Self {
+        Self {
+            inner: self.inner.with_lan_transport(local_peer_id, transport),
+        }
+    }
+    
+    pub fn with_frame_links(self, links: Arc<LanFrameLinks>) -> Self {
+        Self {
+            inner: self.inner.with_frame_links(links),
+        }
+    }
+    
+    pub fn with_announcer(self, announcer: Arc<LanAnnouncer>) -> Self {
+        Self {
+            inner: self.inner.with_announcer(announcer),
+        }
+    }
+    
+    pub fn with_trust_store(self, trust_store: Arc<TrustStore>) -> Self {
+        Self {
+            inner: self.inner.with_trust_store(trust_store),
+        }
+    }
+    
+    pub fn with_rate_limiter(self, rate_limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            inner: self.inner.with_rate_limiter(rate_limiter),
+        }
+    }
+    
+    pub fn with_frame_buffer(self, config: FrameBufferConfig) -> Self {
+        Self {
+            inner: self.inner.with_frame_buffer(config),
+        }
+    }
+    
+    pub fn with_display_name(self, display_name: String) -> Self {
+        Self {
+            inner: self.inner.with_display_name(display_name),
+        }
+    }
+    
+    pub fn with_capture_config(self, config: CaptureConfig) -> Self {
+        Self {
+            inner: self.inner.with_capture_config(config),
+        }
+    }
+    
+    pub fn with_input_backend(self, input: Arc<dyn InputBackend>) -> Self {
+        Self {
+            inner: self.inner.with_input_backend(input),
+        }
+    }
+    
+    pub fn list_monitors(&self) -> Result<Vec<MonitorInfo>, anyhow::Error> {
+        self.inner.list_monitors()
     }
     
     pub async fn start_sharing(
         &self,
         frame_rate: u32,
         resolution: (u32, u32),
+        monitor_id: Option<u32>,
+        quality: Option<u8>,
+        max_participants: Option<usize>,
     ) -> Result<String, anyhow::Error> {
-        tracing::info!("Starting screen share at {}fps, {:?}", frame_rate, resolution);
-        // Implementation will be added
-        Ok("session_id_placeholder".to_string())
+        tracing::info!("Starting screen share at {}fps, {:?}, monitor {:?}", frame_rate, resolution, monitor_id);
+        let peer_id = self.inner.local_peer_id().to_string();
+        self.inner.start_sharing(peer_id, frame_rate, resolution, monitor_id, quality, max_participants).await
+    }
+    
+    pub async fn start_sharing_preset(
+        &self,
+        preset: QualityPreset,
+        overrides: StreamOverrides,
+        resolution: (u32, u32),
+        monitor_id: Option<u32>,
+        max_participants: Option<usize>,
+    ) -> Result<String, anyhow::Error> {
+        tracing::info!("Starting screen share at {:?} {:?}, {:?}, monitor {:?}", preset, overrides, resolution, monitor_id);
+        let peer_id = self.inner.local_peer_id().to_string();
+        self.inner.start_sharing_preset(peer_id, preset, overrides, resolution, monitor_id, max_participants).await
+    }
+    
+    pub async fn switch_monitor(&self, session_id: &str, monitor_id: u32) -> Result<(), anyhow::Error> {
+        tracing::info!("Switching screen share {} to monitor {}", session_id, monitor_id);
+        self.inner.switch_monitor(session_id, monitor_id).await
+    }
+    
+    pub async fn update_session_settings(
+        &self,
+        session_id: &str,
+        frame_rate: u32,
+        resolution: (u32, u32),
+    ) -> Result<(), anyhow::Error> {
+        tracing::info!("Screen share {} now at {}fps, {:?}", session_id, frame_rate, resolution);
+        self.inner.update_session_settings(session_id, frame_rate, resolution).await
+    }
+    
+    pub async fn set_quality_preset(
+        &self,
+        session_id: &str,
+        preset: QualityPreset,
+        overrides: StreamOverrides,
+    ) -> Result<(), anyhow::Error> {
+        tracing::info!("Screen share {} now at {:?} {:?}", session_id, preset, overrides);
+        self.inner.set_quality_preset(session_id, preset, overrides).await
+    }
+    
+    pub async fn set_capture_region(&self, session_id: &str, region: Option<CaptureRegion>) -> Result<(), anyhow::Error> {
+        self.inner.set_capture_region(session_id, region).await
+    }
+    
+    pub async fn set_show_cursor(&self, session_id: &str, show: bool) -> Result<(), anyhow::Error> {
+        self.inner.set_show_cursor(session_id, show).await
+    }
+    
+    /// Whether audio is going out; false where it can't be captured
+    pub async fn set_audio(&self, session_id: &str, enabled: bool) -> Result<bool, anyhow::Error> {
+        tracing::info!("Turning audio {} for screen share {}", if enabled { "on" } else { "off" }, session_id);
+        self.inner.set_audio(session_id, enabled).await
+    }
+    
+    pub async fn get_session(&self, session_id: &str) -> Option<network::SharingSession> {
+        self.inner.get_session(session_id).await
+    }
+    
+    pub async fn subscribe_frames(&self, session_id: &str) -> Result<broadcast::Receiver<Frame>, anyhow::Error> {
+        self.inner.subscribe_frames(session_id).await
+    }
+    
+    pub async fn get_latest_frame(&self, session_id: &str) -> Option<Frame> {
+        self.inner.get_latest_frame(session_id).await
+    }
+    
+    pub fn capture_timings(&self) -> StageTimings {
+        self.inner.capture_timings()
+    }
+    
+    pub async fn buffer_usage(&self) -> BufferUsage {
+        self.inner.buffer_usage().await
+    }
+    
+    pub async fn session_stats(&self, session_id: &str) -> Option<SessionStats> {
+        self.inner.session_stats(session_id).await
     }
     
     pub async fn stop_sharing(&self, session_id: &str) -> Result<(), anyhow::Error> {
         tracing::info!("Stopping screen share session: {}", session_id);
-        // Implementation will be added
-        Ok(())
+        let peer_id = self.inner.local_peer_id().to_string();
+        self.inner.stop_sharing(session_id, &peer_id).await
+    }
+    
+    /// End every session we host before the app exits
+    pub async fn shutdown(&self) {
+        self.inner.shutdown().await
     }
     
     pub async fn join_session(&self, session_id: &str) -> Result<(), anyhow::Error> {
         tracing::info!("Joining screen share session: {}", session_id);
-        // Implementation will be added
-        Ok(())
+        let peer_id = self.inner.local_peer_id().to_string();
+        self.inner.join_session(session_id, peer_id).await
     }
-}
\ No newline at end of file
+    
+    pub async fn set_access(
+        &self,
+        session_id: &str,
+        access_mode: AccessMode,
+        password: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        tracing::info!("Screen share {} now {:?}", session_id, access_mode);
+        self.inner.set_access(session_id, access_mode, password).await
+    }
+    
+    pub async fn get_available_sessions(&self) -> Vec<RemoteSession> {
+        self.inner.get_available_sessions().await
+    }
+    
+    pub async fn refresh_announcements(&self) {
+        self.inner.refresh_announcements().await
+    }
+    
+    pub async fn join_remote_session(
+        &self,
+        session_id: &str,
+        password: Option<String>,
+    ) -> Result<RemoteSession, anyhow::Error> {
+        tracing::info!("Joining remote screen share session: {}", session_id);
+        self.inner.join_remote_session(session_id, password).await
+    }
+    
+    pub async fn leave_remote_session(&self, session_id: &str) -> Result<(), anyhow::Error> {
+        tracing::info!("Leaving remote screen share session: {}", session_id);
+        self.inner.leave_remote_session(session_id).await
+    }
+    
+    pub fn subscribe_join_requests(&self) -> broadcast::Receiver<PendingJoin> {
+        self.inner.subscribe_join_requests()
+    }
+    
+    pub fn subscribe_monitor_lost(&self) -> broadcast::Receiver<MonitorLost> {
+        self.inner.subscribe_monitor_lost()
+    }
+    
+    pub fn subscribe_session_status(&self) -> broadcast::Receiver<SessionStatusChange> {
+        self.inner.subscribe_session_status()
+    }
+    
+    pub fn subscribe_participants(&self) -> broadcast::Receiver<ParticipantChange> {
+        self.inner.subscribe_participants()
+    }
+    
+    pub fn subscribe_session_summaries(&self) -> broadcast::Receiver<SessionSummary> {
+        self.inner.subscribe_session_summaries()
+    }
+    
+    pub fn respond_to_join(&self, request_id: &str, approve: bool) -> Result<(), anyhow::Error> {
+        self.inner.respond_to_join(request_id, approve)
+    }
+    
+    pub fn set_join_approval_timeout(&self, timeout: Duration) {
+        self.inner.set_join_approval_timeout(timeout)
+    }
+    
+    pub async fn handle_announcement(&self, announcement: SessionAnnouncement) {
+        self.inner.handle_announcement(announcement).await
+    }
+    
+    pub async fn handle_beacon(&self, beacon: SessionBeacon, sender: &str) {
+        self.inner.handle_beacon(beacon, sender).await
+    }
+    
+    pub async fn handle_join_request(&self, request: JoinRequest) -> JoinResponse {
+        self.inner.handle_join_request(request).await
+    }
+    
+    /// A join from a viewer whose connection proved who it is
+    pub async fn handle_authenticated_join(&self, request: JoinRequest, viewer: &PeerIdentity) -> JoinResponse {
+        self.inner.handle_authenticated_join(request, viewer).await
+    }
+    
+    /// Serve a connection a viewer opened to our screen port
+    pub async fn accept_connection(&self, stream: LanStream, peer_id: String) -> Result<(), anyhow::Error> {
+        self.inner.accept_connection(stream, peer_id).await
+    }
+    
+    /// A viewer subscribing to or leaving a session we host
+    pub async fn handle_control(&self, message: ControlMessage) -> Result<(), anyhow::Error> {
+        self.inner.handle_control(message).await
+    }
+    
+    pub async fn handle_token_grant(&self, grant: TokenGrant) -> Result<(), anyhow::Error> {
+        self.inner.handle_token_grant(grant).await
+    }
+    
+    pub async fn handle_session_ended(&self, ended: SessionEnded) {
+        self.inner.handle_session_ended(ended).await
+    }
+    
+    pub async fn get_participants(&self, session_id: &str) -> Result<Vec<Participant>, anyhow::Error> {
+        self.inner.get_participants(session_id).await
+    }
+    
+    pub async fn kick_participant(&self, session_id: &str, peer_id: &str) -> Result<(), anyhow::Error> {
+        tracing::info!("Removing {} from screen share {}", peer_id, session_id);
+        self.inner.kick_participant(session_id, peer_id).await
+    }
+    
+    pub async fn set_paused(&self, session_id: &str, paused: bool) -> Result<(), anyhow::Error> {
+        tracing::info!("Screen share {} {}", session_id, if paused { "paused" } else { "resumed" });
+        self.inner.set_paused(session_id, paused).await
+    }
+    
+    pub async fn set_adaptive(&self, session_id: &str, config: AdaptiveConfig) -> Result<(), anyhow::Error> {
+        self.inner.set_adaptive(session_id, config).await
+    }
+    
+    pub async fn set_remote_control(&self, session_id: &str, allowed: bool) -> Result<(), anyhow::Error> {
+        tracing::info!("Remote control of screen share {} {}", session_id, if allowed { "enabled" } else { "disabled" });
+        self.inner.set_remote_control(session_id, allowed).await
+    }
+    
+    pub async fn allow_control(&self, session_id: &str, peer_id: &str, allowed: bool) -> Result<(), anyhow::Error> {
+        tracing::info!("{} {} control of screen share {}", peer_id, if allowed { "given" } else { "denied" }, session_id);
+        self.inner.allow_control(session_id, peer_id, allowed).await
+    }
+    
+    pub async fn revoke_control(&self) {
+        self.inner.revoke_control().await
+    }
+    
+    pub async fn send_input(&self, session_id: &str, event: InputEvent) -> Result<(), anyhow::Error> {
+        self.inner.send_input(session_id, event).await
+    }
+    
+    pub async fn receive_frame(&self, frame: Frame) {
+        self.inner.receive_frame(frame).await
+    }
+}
@@ -42,6 +42,7 @@ async fn get_devices(
             port: info.port,
             is_online: true,
             last_seen: chrono::Utc::now().to_rfc3339(),
+            fingerprint: info.fingerprint,
         })
         .collect();
     
@@ -54,11 +55,9 @@ async fn start_file_transfer(
     device_ip: String,
     file_path: String,
 ) -> Result<(), String> {
-    let _transfer = state.file_transfer.lock().await;
-    let _user_name = state.user_name.lock().await.clone();
-    // TODO: FileTransfer doesn't have send_file_to_device method yet
-    // Placeholder implementation
-    Err("File transfer not implemented".to_string())
+    let transfer = state.file_transfer.lock().await;
+    transfer.send_file_to_device(&device_ip, &file_path).await
+        .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -68,7 +67,7 @@ async fn start_screen_share(
 ) -> Result<String, String> {
     let share = state.screen_share.lock().await;
     let _user_name = state.user_name.lock().await.clone();
-    share.start_sharing(frame_rate, (1920, 1080)).await
+    share.start_sharing(frame_rate, (1920, 1080), None, None, None).await
         .map_err(|e| e.to_string())
 }
 
@@ -97,11 +96,12 @@ async fn get_transfer_progress(
 async fn list_local_files(
     state: tauri::State<'_, AppState>,
     path: String,
+    show_hidden: Option<bool>,
 ) -> Result<serde_json::Value, String> {
-    let _transfer = state.file_transfer.lock().await;
-    // TODO: FileTransfer doesn't have list_files_in_directory method yet
-    let files: Vec<String> = vec![];
-    Ok(json!(files))
+    let transfer = state.file_transfer.lock().await;
+    let entries = transfer.list_files_in_directory(&path, show_hidden.unwrap_or(false)).await
+        .map_err(|e| e.to_string())?;
+    Ok(json!(entries))
 }
 
 #[tauri::command]